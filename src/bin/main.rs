@@ -1,15 +1,44 @@
-use anyhow::anyhow;
+use anyhow::Result;
+use mgit::branch::{branch, BranchAction, BranchListOptions};
 use mgit::cat_file;
-use mgit::clone;
+use mgit::date::{parse_date, DateFormat};
+use mgit::diff::{
+    diff, parse_similarity, DiffAlgorithm, DiffOptions, PatchOptions, TreeDiffOptions,
+    WhitespaceOptions, WordDiff,
+};
+use mgit::diff_tree::{diff_tree, DiffTreeOptions, DiffTreeOutput};
+use mgit::fetch::{fetch, FetchOptions};
+use mgit::fsck::{fsck, FsckOptions};
+use mgit::gc::{gc, GcOptions};
 use mgit::hash_object::hash_object;
 use mgit::init;
-use mgit::objects;
-use mgit::pack_protocol;
+use mgit::merge::{merge, MergeOptions};
+use mgit::merge_base::{merge_base, MergeBaseOptions};
+use mgit::pack_objects::{pack_objects, PackObjectsOptions};
+use mgit::push::{push, PushOptions};
+use mgit::rebase::{rebase, RebaseAction, RebaseOptions};
+use mgit::http_backend::http_backend;
+use mgit::receive_pack::{receive_pack, ReceivePackOptions};
+use mgit::repack::{repack, RepackOptions};
+use mgit::reset::{reset, ResetMode, ResetOptions};
+use mgit::restore::{restore, RestoreOptions};
+use mgit::rev_list::{rev_list, RevListOptions};
+use mgit::rev_parse::resolve_revision;
+use mgit::revwalk::CommitOrder;
+use mgit::sequencer::{cherry_pick, revert, ReplayAction, ReplayOptions};
+use mgit::stash::{stash, StashAction, StashPushOptions};
+use mgit::tag::{tag, TagAction, TagListOptions};
+use mgit::upload_pack::{upload_pack, Session, UploadPackOptions};
 
-use std::{path::PathBuf, process::exit};
+use std::{
+    io::{stderr, stdout, IsTerminal},
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 use clap::Parser;
-use log::error;
+use log::{error, LevelFilter};
+use regex::Regex;
 use simple_logger::{set_up_color_terminal, SimpleLogger};
 
 #[derive(Debug, Parser, Clone)]
@@ -28,26 +57,1399 @@ enum Cli {
         write: bool,
         file_path: String,
     },
+
+    /// Shows the commit logs
+    #[command()]
+    Log {
+        /// shorthand for --pretty=oneline --abbrev-commit
+        #[clap(long)]
+        oneline: bool,
+        /// pretty-print format: oneline, short, medium, full, fuller, raw, format:<string> or tformat:<string>
+        #[clap(long, visible_alias = "pretty", num_args = 0..=1, default_missing_value = "medium")]
+        format: Option<String>,
+        #[clap(long)]
+        abbrev_commit: bool,
+        /// date format: default, relative, local, iso, iso-strict, rfc, short, raw, unix or format:<strftime>
+        #[clap(long)]
+        date: Option<String>,
+        /// draw a text-based graph of the history
+        #[clap(long)]
+        graph: bool,
+        #[clap(short = 'n', long)]
+        max_count: Option<usize>,
+        #[clap(long, visible_alias = "after")]
+        since: Option<String>,
+        #[clap(long, visible_alias = "before")]
+        until: Option<String>,
+        #[clap(long)]
+        author: Option<String>,
+        #[clap(long)]
+        grep: Option<String>,
+        #[clap(long)]
+        first_parent: bool,
+        #[clap(long, conflicts_with = "date_order")]
+        topo_order: bool,
+        #[clap(long)]
+        date_order: bool,
+        revisions: Vec<String>,
+        /// only show commits that modify these paths
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Lists commit objects in reverse chronological order
+    #[command()]
+    RevList {
+        /// print the number of commits instead of listing them
+        #[clap(long)]
+        count: bool,
+        /// mark commits of symmetric ranges with < or > depending on the side they are on
+        #[clap(long)]
+        left_right: bool,
+        /// also list the trees and blobs used by the listed commits
+        #[clap(long)]
+        objects: bool,
+        #[clap(long)]
+        reverse: bool,
+        #[clap(short = 'n', long)]
+        max_count: Option<usize>,
+        #[clap(long)]
+        first_parent: bool,
+        #[clap(long, conflicts_with = "date_order")]
+        topo_order: bool,
+        #[clap(long)]
+        date_order: bool,
+        /// revisions (A, ^A, A..B, A...B), optionally mixed with --not, --all, --branches, --tags and --remotes.
+        /// the options above may also follow them
+        #[clap(allow_hyphen_values = true)]
+        revisions: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Finds the best common ancestors of commits
+    #[command()]
+    MergeBase {
+        /// output all merge bases instead of the first one
+        #[clap(short, long)]
+        all: bool,
+        /// compute the common ancestors of all commits, as needed by an n-way merge
+        #[clap(long, conflicts_with_all = ["is_ancestor", "fork_point"])]
+        octopus: bool,
+        /// exit with status 0 if the first commit is an ancestor of the second one, 1 otherwise
+        #[clap(long, conflicts_with = "fork_point")]
+        is_ancestor: bool,
+        /// find the point at which <commit> forked from the history of <ref>: mgit merge-base --fork-point <ref> [<commit>]
+        #[clap(long)]
+        fork_point: bool,
+        commits: Vec<String>,
+    },
+
+    /// Joins the history of a commit with the current branch
+    #[command()]
+    Merge {
+        /// create a merge commit even when the merge resolves as a fast-forward
+        #[clap(long, conflicts_with = "ff_only")]
+        no_ff: bool,
+        /// refuse to merge unless the merge resolves as a fast-forward
+        #[clap(long)]
+        ff_only: bool,
+        /// update the index and the working tree without committing
+        #[clap(long)]
+        squash: bool,
+        /// abort the merge in progress
+        #[clap(long, conflicts_with = "commit")]
+        abort: bool,
+        /// merge strategy option: ours or theirs
+        #[clap(short = 'X', long = "strategy-option")]
+        strategy_options: Vec<String>,
+        #[clap(short, long)]
+        message: Option<String>,
+        #[clap(required_unless_present = "abort")]
+        commit: Option<String>,
+    },
+
+    /// Reapplies commits on top of another base
+    #[command()]
+    Rebase {
+        /// edit the list of commits to rebase before starting
+        #[clap(short, long)]
+        interactive: bool,
+        /// the new base of the commits, instead of upstream
+        #[clap(long)]
+        onto: Option<String>,
+        /// move fixup! and squash! commits after the commits they refer to
+        #[clap(long)]
+        autosquash: bool,
+        /// recreate the merges instead of flattening the history
+        #[clap(short, long)]
+        rebase_merges: bool,
+        /// continue the rebase once conflicts are resolved
+        #[clap(long, conflicts_with_all = ["skip", "abort", "upstream"])]
+        r#continue: bool,
+        /// skip the commit the rebase stopped at
+        #[clap(long, conflicts_with_all = ["abort", "upstream"])]
+        skip: bool,
+        /// abort the rebase and go back to the original branch
+        #[clap(long, conflicts_with = "upstream")]
+        abort: bool,
+        upstream: Option<String>,
+        /// the branch to rebase, checked out first
+        branch: Option<String>,
+    },
+
+    /// Applies the changes introduced by existing commits
+    #[command()]
+    CherryPick {
+        /// append a line saying which commit was cherry-picked
+        #[clap(short = 'x')]
+        record_origin: bool,
+        /// the parent number, starting from 1, of merges to take their changes against
+        #[clap(short, long)]
+        mainline: Option<usize>,
+        /// update the index and the working tree without committing
+        #[clap(short, long)]
+        no_commit: bool,
+        /// edit the commit messages
+        #[clap(short, long)]
+        edit: bool,
+        /// continue the cherry-pick once conflicts are resolved
+        #[clap(long, conflicts_with_all = ["skip", "abort", "commits"])]
+        r#continue: bool,
+        /// skip the commit that stopped the cherry-pick
+        #[clap(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+        /// cancel the cherry-pick and go back to the commit it started from
+        #[clap(long, conflicts_with = "commits")]
+        abort: bool,
+        #[clap(required_unless_present_any = ["continue", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    /// Reverts the changes introduced by existing commits
+    #[command()]
+    Revert {
+        /// the parent number, starting from 1, of merges to revert their changes against
+        #[clap(short, long)]
+        mainline: Option<usize>,
+        /// update the index and the working tree without committing
+        #[clap(short, long)]
+        no_commit: bool,
+        /// edit the commit messages, the default when run from a terminal
+        #[clap(short, long, conflicts_with = "no_edit")]
+        edit: bool,
+        /// use the default commit messages
+        #[clap(long)]
+        no_edit: bool,
+        /// continue the revert once conflicts are resolved
+        #[clap(long, conflicts_with_all = ["skip", "abort", "commits"])]
+        r#continue: bool,
+        /// skip the commit that stopped the revert
+        #[clap(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+        /// cancel the revert and go back to the commit it started from
+        #[clap(long, conflicts_with = "commits")]
+        abort: bool,
+        #[clap(required_unless_present_any = ["continue", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    /// Resets the current branch to a commit, or the index entries of some paths
+    #[command()]
+    Reset {
+        /// only move the branch, keeping the index and the working tree
+        #[clap(long, group = "mode")]
+        soft: bool,
+        /// reset the index but not the working tree (the default)
+        #[clap(long, group = "mode")]
+        mixed: bool,
+        /// reset the index and the working tree, discarding local changes
+        #[clap(long, group = "mode")]
+        hard: bool,
+        /// reset the index and the files that change, keeping unstaged changes to the others
+        #[clap(long, group = "mode")]
+        merge: bool,
+        /// reset the index and the files that change, refusing if they have local changes
+        #[clap(long, group = "mode")]
+        keep: bool,
+        /// the commit to reset to, followed by paths
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Restores working tree files or index entries
+    #[command()]
+    Restore {
+        /// the tree-ish to restore from, instead of the index or HEAD
+        #[clap(short, long)]
+        source: Option<String>,
+        /// restore the index
+        #[clap(short = 'S', long)]
+        staged: bool,
+        /// restore the working tree, the default unless --staged is given
+        #[clap(short = 'W', long)]
+        worktree: bool,
+        paths: Vec<String>,
+    },
+
+    /// Lists, creates, renames, copies or deletes branches
+    #[command()]
+    Branch {
+        /// list the remote-tracking branches too
+        #[clap(short, long)]
+        all: bool,
+        /// list or delete the remote-tracking branches
+        #[clap(short, long)]
+        remotes: bool,
+        /// show the commit of each branch, and its upstream too when given twice
+        #[clap(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+        /// only list the branches merged into a commit, HEAD by default
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+        /// only list the branches not merged into a commit, HEAD by default
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+        /// only list the branches containing a commit, HEAD by default
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+        /// only list the branches not containing a commit, HEAD by default
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        no_contains: Option<String>,
+        /// sort by a key like refname or -committerdate, the last one given first
+        #[clap(long)]
+        sort: Vec<String>,
+        /// show the branches with a format like %(refname:short) %(objectname)
+        #[clap(long)]
+        format: Option<String>,
+        /// delete branches merged into their upstream or HEAD
+        #[clap(short, long, group = "action")]
+        delete: bool,
+        /// delete branches, merged or not
+        #[clap(short = 'D', group = "action")]
+        force_delete: bool,
+        /// rename a branch, the current one by default
+        #[clap(short = 'm', long = "move", group = "action")]
+        rename: bool,
+        /// rename a branch even if the new name exists
+        #[clap(short = 'M', group = "action")]
+        force_rename: bool,
+        /// copy a branch, the current one by default
+        #[clap(short, long, group = "action")]
+        copy: bool,
+        /// copy a branch even if the new name exists
+        #[clap(short = 'C', group = "action")]
+        force_copy: bool,
+        /// reset the branch to the starting point if it exists
+        #[clap(short, long)]
+        force: bool,
+        /// make the new branch track its starting point
+        #[clap(short, long, conflicts_with = "no_track")]
+        track: bool,
+        /// don't make the new branch track its starting point
+        #[clap(long)]
+        no_track: bool,
+        /// make a branch, the current one by default, track another
+        #[clap(short = 'u', long, group = "action")]
+        set_upstream_to: Option<String>,
+        /// stop a branch, the current one by default, from tracking another
+        #[clap(long, group = "action")]
+        unset_upstream: bool,
+        /// the branch to create and its starting point, or the branches to act on
+        args: Vec<String>,
+    },
+
+    /// Lists, creates or deletes tags
+    #[command()]
+    Tag {
+        /// list the tags, only those matching the patterns if any are given
+        #[clap(short, long)]
+        list: bool,
+        /// show the first lines of each tag's message, one by default
+        #[clap(short = 'n', num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<usize>,
+        /// only list the tags containing a commit, HEAD by default
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+        /// sort by a key like refname or version:refname, the last one given first
+        #[clap(long)]
+        sort: Vec<String>,
+        /// show the tags with a format like %(refname:short) %(objectname)
+        #[clap(long)]
+        format: Option<String>,
+        /// make an annotated tag
+        #[clap(short, long)]
+        annotate: bool,
+        /// the message of an annotated tag. several messages become separate paragraphs.
+        #[clap(short, long)]
+        message: Vec<String>,
+        /// read the message of an annotated tag from a file, or from stdin with "-"
+        #[clap(short = 'F', long)]
+        file: Option<String>,
+        /// replace an existing tag
+        #[clap(short, long)]
+        force: bool,
+        /// delete tags
+        #[clap(short, long)]
+        delete: bool,
+        /// the tag to create and the object to tag, the tags to delete, or the patterns to list
+        args: Vec<String>,
+    },
+
+    /// Downloads objects and refs from another repository
+    #[command()]
+    Fetch {
+        /// delete the remote-tracking refs that no longer exist on the remote
+        #[clap(short, long)]
+        prune: bool,
+        /// fetch all the tags of the remote
+        #[clap(short, long)]
+        tags: bool,
+        /// update local refs even when the updates aren't fast-forwards
+        #[clap(short, long)]
+        force: bool,
+        /// the command run on the remote side to serve the fetch
+        #[clap(long)]
+        upload_pack: Option<String>,
+        /// don't report ref updates, nor progress
+        #[clap(short, long)]
+        quiet: bool,
+        /// report progress even when stderr isn't a terminal
+        #[clap(long)]
+        progress: bool,
+        /// the remote to fetch from, origin by default
+        remote: Option<String>,
+        /// the refs to fetch, and where to store them, like +refs/heads/*:refs/remotes/origin/*
+        refspecs: Vec<String>,
+    },
+
+    /// Updates the refs of another repository, and sends the objects they need
+    #[command()]
+    Push {
+        /// update remote refs even when the updates aren't fast-forwards
+        #[clap(short, long)]
+        force: bool,
+        /// force updates only while the remote refs are still at their remote-tracking
+        /// values, or at the values given
+        #[clap(
+            long,
+            value_name = "REF[:EXPECT]",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        force_with_lease: Vec<String>,
+        /// delete the refs named on the remote
+        #[clap(short, long)]
+        delete: bool,
+        /// push all the tags along with the refspecs
+        #[clap(long)]
+        tags: bool,
+        /// update all the remote refs or none of them
+        #[clap(long)]
+        atomic: bool,
+        /// a string handed to the hooks of the remote
+        #[clap(short = 'o', long = "push-option")]
+        push_option: Vec<String>,
+        /// the command run on the remote side to receive the push
+        #[clap(long, alias = "exec")]
+        receive_pack: Option<String>,
+        /// only report errors
+        #[clap(short, long)]
+        quiet: bool,
+        /// the remote to push to, origin by default
+        remote: Option<String>,
+        /// the refs to push, and the remote refs to update, like main or +HEAD:refs/heads/x
+        refspecs: Vec<String>,
+    },
+
+    /// Creates a pack of the objects named on stdin, each optionally followed by its path
+    #[command()]
+    PackObjects {
+        /// write the pack to stdout rather than to files
+        #[clap(long)]
+        stdout: bool,
+        /// how many objects are tried as the delta base of each object
+        #[clap(long, default_value_t = 10)]
+        window: usize,
+        /// the longest chain of deltas an object may be stored as
+        #[clap(long, default_value_t = 50)]
+        depth: usize,
+        /// point to delta bases by offset rather than by object name
+        #[clap(long)]
+        delta_base_offset: bool,
+        /// compute all deltas rather than reusing those of existing packs
+        #[clap(long)]
+        no_reuse_delta: bool,
+        /// write the pack and its index as <base-name>-<checksum>.pack and .idx
+        #[clap(required_unless_present = "stdout", conflicts_with = "stdout")]
+        base_name: Option<String>,
+    },
+
+    /// Verifies the objects of the repository, and that those reachable are all there
+    #[command()]
+    Fsck {
+        /// check the packs and their objects too, the default
+        #[clap(long, overrides_with = "no_full")]
+        full: bool,
+        /// only check the loose objects
+        #[clap(long)]
+        no_full: bool,
+        /// only check that the reachable objects are there, not their content
+        #[clap(long)]
+        connectivity_only: bool,
+        /// report all unreachable objects, not only the dangling ones
+        #[clap(long)]
+        unreachable: bool,
+        /// report the dangling objects, the default
+        #[clap(long, overrides_with = "no_dangling")]
+        dangling: bool,
+        /// don't report the dangling objects
+        #[clap(long)]
+        no_dangling: bool,
+        /// write the dangling objects to .git/lost-found
+        #[clap(long)]
+        lost_found: bool,
+    },
+
+    /// Sends the objects a fetch or a clone asks for, over stdin and stdout
+    #[command()]
+    UploadPack {
+        /// serve a single request, without advertising the refs first
+        #[clap(long)]
+        stateless_rpc: bool,
+        /// only advertise the refs and capabilities
+        #[clap(long, alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+        /// the repository to serve
+        dir: String,
+    },
+
+    /// Receives the objects and ref updates of a push, over stdin and stdout
+    #[command()]
+    ReceivePack {
+        /// receive a single push, without advertising the refs first
+        #[clap(long)]
+        stateless_rpc: bool,
+        /// only advertise the refs and capabilities
+        #[clap(long, alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+        /// the repository pushed to
+        dir: String,
+    },
+
+    /// Serves repositories over smart and dumb HTTP, as a CGI program. the repositories
+    /// are found below GIT_PROJECT_ROOT.
+    #[command()]
+    HttpBackend,
+
+    /// Packs the loose objects of the repository, or all of its objects into a single pack
+    #[command()]
+    Repack {
+        /// pack all reachable objects into a single pack, rather than only the loose ones
+        #[clap(short = 'a')]
+        all: bool,
+        /// like -a, but the unreachable objects of the packs replaced are kept loose
+        #[clap(short = 'A')]
+        all_loosen_unreachable: bool,
+        /// remove the packs and the loose objects made redundant by the new pack
+        #[clap(short = 'd')]
+        delete: bool,
+        /// don't report that there was nothing to pack
+        #[clap(short, long)]
+        quiet: bool,
+        /// how many objects are tried as the delta base of each object
+        #[clap(long, default_value_t = 10)]
+        window: usize,
+        /// the longest chain of deltas an object may be stored as
+        #[clap(long, default_value_t = 50)]
+        depth: usize,
+    },
+
+    /// Packs refs and objects, and removes the unreachable ones
+    #[command()]
+    Gc {
+        /// only run when there are enough loose objects or packs
+        #[clap(long)]
+        auto: bool,
+        /// prune the unreachable loose objects older than this date, rather than 2 weeks
+        #[clap(long)]
+        prune: Option<String>,
+        /// don't report what is done
+        #[clap(short, long)]
+        quiet: bool,
+    },
+
+    /// Stashes the changes of the working tree away, or brings stashed changes back
+    #[command(args_conflicts_with_subcommands = true)]
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommand>,
+        #[command(flatten)]
+        push: StashPushArgs,
+    },
+
+    /// Compares the content and mode of blobs found via two tree objects
+    #[command()]
+    DiffTree {
+        /// recurse into subtrees
+        #[clap(short)]
+        recursive: bool,
+        /// show only the names of changed files
+        #[clap(long, conflicts_with = "name_status")]
+        name_only: bool,
+        /// show only the names and statuses of changed files
+        #[clap(long)]
+        name_status: bool,
+        /// detect renames, optionally with a minimum similarity (e.g. -M90%)
+        #[clap(short = 'M', long, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_renames: Option<String>,
+        /// detect copies as well as renames, optionally with a minimum similarity
+        #[clap(short = 'C', long, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_copies: Option<String>,
+        /// also consider unmodified files as copy sources
+        #[clap(long)]
+        find_copies_harder: bool,
+        /// show the changes of a root commit as additions
+        #[clap(long)]
+        root: bool,
+        /// a commit, or two tree-ish objects
+        #[clap(num_args = 1..=2)]
+        objects: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Shows changes between the working tree, the index, commits and blobs
+    #[command()]
+    Diff {
+        /// compare the index with a commit (HEAD by default)
+        #[clap(long, visible_alias = "staged")]
+        cached: bool,
+        /// number of context lines
+        #[clap(short = 'U', long)]
+        unified: Option<usize>,
+        /// show the patch along with --stat or --numstat
+        #[clap(short = 'p', long)]
+        patch: bool,
+        #[clap(long)]
+        stat: bool,
+        #[clap(long)]
+        numstat: bool,
+        /// show changed words instead of lines: plain or color
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "plain")]
+        word_diff: Option<String>,
+        /// when to color the output: always, never or auto
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+        color: Option<String>,
+        #[clap(long)]
+        no_color: bool,
+        /// ignore whitespace when comparing lines
+        #[clap(short = 'w', long)]
+        ignore_all_space: bool,
+        /// ignore changes in the amount of whitespace
+        #[clap(short = 'b', long)]
+        ignore_space_change: bool,
+        #[clap(long)]
+        ignore_space_at_eol: bool,
+        #[clap(long)]
+        ignore_cr_at_eol: bool,
+        /// ignore changes whose lines are all blank
+        #[clap(long)]
+        ignore_blank_lines: bool,
+        /// myers, minimal, patience or histogram
+        #[clap(long)]
+        diff_algorithm: Option<String>,
+        #[clap(long)]
+        patience: bool,
+        #[clap(long)]
+        histogram: bool,
+        #[clap(long)]
+        minimal: bool,
+        /// detect renames, optionally with a minimum similarity (e.g. -M90%)
+        #[clap(short = 'M', long, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_renames: Option<String>,
+        #[clap(long)]
+        no_renames: bool,
+        /// revisions to compare, or paths
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Debug, clap::Subcommand, Clone)]
+enum StashCommand {
+    /// Saves the local changes in a new stash and reverts them, the default
+    Push(StashPushArgs),
+    /// Applies a stash on top of the working tree
+    Apply {
+        /// restore the changes of the index as well
+        #[clap(long)]
+        index: bool,
+        stash: Option<String>,
+    },
+    /// Applies a stash and drops it if it applied cleanly
+    Pop {
+        /// restore the changes of the index as well
+        #[clap(long)]
+        index: bool,
+        stash: Option<String>,
+    },
+    /// Lists the stashes, the latest first
+    List,
+    /// Shows the changes recorded in a stash
+    Show {
+        /// show the changes as a patch
+        #[clap(short, long)]
+        patch: bool,
+        /// show a diffstat, the default without --patch
+        #[clap(long)]
+        stat: bool,
+        stash: Option<String>,
+    },
+    /// Removes a stash from the list
+    Drop { stash: Option<String> },
+    /// Removes all the stashes
+    Clear,
+    /// Creates a branch at the commit a stash was made on and applies the stash there
+    Branch {
+        branch: String,
+        stash: Option<String>,
+    },
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct StashPushArgs {
+    /// the description of the stash
+    #[clap(short, long)]
+    message: Option<String>,
+    /// stash the untracked files too, and remove them
+    #[clap(short = 'u', long)]
+    include_untracked: bool,
+    /// keep the changes added to the index
+    #[clap(short, long)]
+    keep_index: bool,
+    /// stash only the changes to these paths
+    paths: Vec<String>,
 }
 
 fn main() {
     set_up_color_terminal();
-    let logger = SimpleLogger::new().without_timestamps();
+    // dependencies like reqwest and hyper trace every request, only their warnings are shown
+    let logger = SimpleLogger::new()
+        .without_timestamps()
+        .with_level(LevelFilter::Warn)
+        .with_module_level("mgit", LevelFilter::Trace);
     let max_level = logger.max_level();
 
     log::set_max_level(max_level);
     log::set_boxed_logger(Box::new(logger)).unwrap();
 
-    let args = Cli::parse();
+    let args = Cli::parse_from(std::env::args().map(expand_similarity_arg));
+
+    if let Err(err) = run(args) {
+        error!("{}", err);
+        exit(1)
+    }
+}
+
+/// git accepts similarity thresholds attached to -M and -C (e.g. -M90%), while a value of
+/// an optional short flag can't be told apart from the next argument. the attached forms
+/// are rewritten to their long equivalents.
+fn expand_similarity_arg(arg: String) -> String {
+    let long = match arg.get(..2) {
+        Some("-M") => "--find-renames",
+        Some("-C") => "--find-copies",
+        _ => return arg,
+    };
+
+    match arg[2..].chars().next() {
+        Some(c) if c.is_ascii_digit() || c == '.' => format!("{}={}", long, &arg[2..]),
+        _ => arg,
+    }
+}
+
+/// the action given to cherry-pick or revert, if any
+fn replay_action(r#continue: bool, skip: bool, abort: bool) -> Option<ReplayAction> {
+    match (r#continue, skip, abort) {
+        (true, _, _) => Some(ReplayAction::Continue),
+        (_, true, _) => Some(ReplayAction::Skip),
+        (_, _, true) => Some(ReplayAction::Abort),
+        _ => None,
+    }
+}
+
+/// the part of a session upload-pack or receive-pack serves
+fn session(stateless_rpc: bool, advertise_refs: bool) -> Session {
+    match (stateless_rpc, advertise_refs) {
+        (_, true) => Session::AdvertiseRefs,
+        (true, false) => Session::StatelessRpc,
+        (false, false) => Session::Full,
+    }
+}
+
+/// the first argument of reset is the commit, unless it names a file rather than a revision
+fn split_reset_args(args: Vec<String>, mut paths: Vec<String>) -> (Option<String>, Vec<String>) {
+    let mut args = args.into_iter();
+    let commit = match args.next() {
+        Some(arg) if paths.is_empty() && is_path_arg(&arg) => {
+            paths.push(arg);
+            None
+        }
+        arg => arg,
+    };
+    paths.extend(args);
+    (commit, paths)
+}
+
+fn is_path_arg(arg: &str) -> bool {
+    resolve_revision(arg).is_err() && Path::new(arg).exists()
+}
 
-    let res = match args {
+fn run(args: Cli) -> Result<()> {
+    match args {
         Cli::Init => init::init(),
         Cli::CatFile { object: hash } => cat_file::cat_file(hash),
         Cli::HashObject { write, file_path } => hash_object(PathBuf::from(file_path), write),
-    };
+        Cli::Log {
+            oneline,
+            format,
+            abbrev_commit,
+            date,
+            graph,
+            max_count,
+            since,
+            until,
+            author,
+            grep,
+            first_parent,
+            topo_order,
+            date_order,
+            revisions,
+            paths,
+        } => {
+            let mut options = mgit::log::LogOptions {
+                revisions,
+                paths,
+                max_count,
+                since: since.as_deref().map(parse_date).transpose()?,
+                until: until.as_deref().map(parse_date).transpose()?,
+                author: author.as_deref().map(Regex::new).transpose()?,
+                grep: grep.as_deref().map(Regex::new).transpose()?,
+                first_parent,
+                graph,
+                abbrev_commit,
+                ..Default::default()
+            };
 
-    if let Err(err) = res {
-        error!("{}", err);
-        exit(1)
+            if oneline {
+                options.format = mgit::log::PrettyFormat::Oneline;
+                options.abbrev_commit = true;
+            }
+
+            if let Some(format) = format {
+                options.format = mgit::log::PrettyFormat::try_from(format.as_str())?;
+            }
+
+            if let Some(date) = date {
+                options.date_format = DateFormat::try_from(date.as_str())?;
+            }
+
+            if topo_order {
+                options.order = CommitOrder::Topo;
+            } else if date_order {
+                options.order = CommitOrder::Date;
+            }
+
+            mgit::log::log(options)
+        }
+        Cli::RevList {
+            count,
+            left_right,
+            objects,
+            reverse,
+            max_count,
+            first_parent,
+            topo_order,
+            date_order,
+            revisions,
+            paths,
+        } => {
+            let order = if topo_order {
+                CommitOrder::Topo
+            } else if date_order {
+                CommitOrder::Date
+            } else {
+                CommitOrder::Default
+            };
+
+            rev_list(RevListOptions {
+                args: revisions,
+                paths,
+                count,
+                left_right,
+                objects,
+                reverse,
+                max_count,
+                first_parent,
+                order,
+            })
+        }
+        Cli::MergeBase {
+            all,
+            octopus,
+            is_ancestor,
+            fork_point,
+            commits,
+        } => {
+            let found = merge_base(MergeBaseOptions {
+                commits,
+                all,
+                octopus,
+                is_ancestor,
+                fork_point,
+            })?;
+
+            if !found {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Merge {
+            no_ff,
+            ff_only,
+            squash,
+            abort,
+            strategy_options,
+            message,
+            commit,
+        } => {
+            let merged = merge(MergeOptions {
+                commit,
+                no_ff,
+                ff_only,
+                squash,
+                abort,
+                strategy_options,
+                message,
+            })?;
+
+            if !merged {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Rebase {
+            interactive,
+            onto,
+            autosquash,
+            rebase_merges,
+            r#continue,
+            skip,
+            abort,
+            upstream,
+            branch,
+        } => {
+            let action = match (r#continue, skip, abort) {
+                (true, _, _) => Some(RebaseAction::Continue),
+                (_, true, _) => Some(RebaseAction::Skip),
+                (_, _, true) => Some(RebaseAction::Abort),
+                _ => None,
+            };
+            let rebased = rebase(RebaseOptions {
+                upstream,
+                branch,
+                onto,
+                interactive,
+                autosquash,
+                rebase_merges,
+                action,
+            })?;
+
+            if !rebased {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::CherryPick {
+            record_origin,
+            mainline,
+            no_commit,
+            edit,
+            r#continue,
+            skip,
+            abort,
+            commits,
+        } => {
+            let picked = cherry_pick(ReplayOptions {
+                commits,
+                mainline,
+                record_origin,
+                no_commit,
+                edit: edit.then_some(true),
+                action: replay_action(r#continue, skip, abort),
+            })?;
+
+            if !picked {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Revert {
+            mainline,
+            no_commit,
+            edit,
+            no_edit,
+            r#continue,
+            skip,
+            abort,
+            commits,
+        } => {
+            let reverted = revert(ReplayOptions {
+                commits,
+                mainline,
+                no_commit,
+                edit: match (edit, no_edit) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                action: replay_action(r#continue, skip, abort),
+                ..Default::default()
+            })?;
+
+            if !reverted {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Reset {
+            soft,
+            mixed: _,
+            hard,
+            merge,
+            keep,
+            args,
+            paths,
+        } => {
+            let mode = match (soft, hard, merge, keep) {
+                (true, _, _, _) => ResetMode::Soft,
+                (_, true, _, _) => ResetMode::Hard,
+                (_, _, true, _) => ResetMode::Merge,
+                (_, _, _, true) => ResetMode::Keep,
+                _ => ResetMode::Mixed,
+            };
+            let (commit, paths) = split_reset_args(args, paths);
+            reset(ResetOptions {
+                mode,
+                commit,
+                paths,
+            })
+        }
+        Cli::Restore {
+            source,
+            staged,
+            worktree,
+            paths,
+        } => restore(RestoreOptions {
+            source,
+            staged,
+            worktree,
+            paths,
+        }),
+        Cli::Branch {
+            all,
+            remotes,
+            verbose,
+            merged,
+            no_merged,
+            contains,
+            no_contains,
+            sort,
+            format,
+            delete,
+            force_delete,
+            rename,
+            force_rename,
+            copy,
+            force_copy,
+            force,
+            track,
+            no_track,
+            set_upstream_to,
+            unset_upstream,
+            mut args,
+        } => {
+            let listing = merged.is_some()
+                || no_merged.is_some()
+                || contains.is_some()
+                || no_contains.is_some()
+                || format.is_some()
+                || !sort.is_empty()
+                || verbose > 0
+                || all
+                || (remotes && !delete && !force_delete);
+            let action = if delete || force_delete {
+                BranchAction::Delete {
+                    names: args,
+                    remotes,
+                    force: force || force_delete,
+                }
+            } else if rename || force_rename || copy || force_copy {
+                let new = args
+                    .pop()
+                    .ok_or(anyhow::anyhow!("branch name required"))?;
+                let old = args.pop();
+                let force = force || force_rename || force_copy;
+                match rename || force_rename {
+                    true => BranchAction::Rename { old, new, force },
+                    false => BranchAction::Copy { old, new, force },
+                }
+            } else if let Some(upstream) = set_upstream_to {
+                BranchAction::SetUpstream {
+                    upstream,
+                    branch: args.pop(),
+                }
+            } else if unset_upstream {
+                BranchAction::UnsetUpstream { branch: args.pop() }
+            } else if args.is_empty() || listing {
+                BranchAction::List(BranchListOptions {
+                    all,
+                    remotes,
+                    verbose,
+                    merged,
+                    no_merged,
+                    contains,
+                    no_contains,
+                    sort,
+                    format,
+                    color: stdout().is_terminal(),
+                })
+            } else {
+                let mut args = args.into_iter();
+                BranchAction::Create {
+                    name: args.next().expect("args is not empty"),
+                    start: args.next(),
+                    force,
+                    track: match (track, no_track) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    },
+                }
+            };
+
+            if !branch(action)? {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Tag {
+            list,
+            lines,
+            contains,
+            sort,
+            format,
+            annotate,
+            message,
+            file,
+            force,
+            delete,
+            args,
+        } => {
+            let listing = list
+                || lines.is_some()
+                || contains.is_some()
+                || !sort.is_empty()
+                || format.is_some();
+            let action = if delete {
+                TagAction::Delete { names: args }
+            } else if args.is_empty() || listing {
+                TagAction::List(TagListOptions {
+                    patterns: args,
+                    sort,
+                    contains,
+                    lines,
+                    format,
+                })
+            } else {
+                let mut args = args.into_iter();
+                TagAction::Create {
+                    name: args.next().expect("args is not empty"),
+                    object: args.next(),
+                    annotate,
+                    messages: message,
+                    file,
+                    force,
+                }
+            };
+
+            if !tag(action)? {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Fetch {
+            prune,
+            tags,
+            force,
+            upload_pack,
+            quiet,
+            progress,
+            remote,
+            refspecs,
+        } => {
+            let reflog_action = std::iter::once("fetch".to_string())
+                .chain(std::env::args().skip(2))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let options = FetchOptions {
+                remote,
+                refspecs,
+                prune,
+                tags,
+                force,
+                upload_pack,
+                reflog_action,
+                quiet,
+                progress: progress || (!quiet && stderr().is_terminal()),
+            };
+            if !fetch(options)? {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::Push {
+            force,
+            force_with_lease,
+            delete,
+            tags,
+            atomic,
+            push_option,
+            receive_pack,
+            quiet,
+            remote,
+            refspecs,
+        } => {
+            let options = PushOptions {
+                remote,
+                refspecs,
+                force,
+                force_with_lease,
+                delete,
+                tags,
+                atomic,
+                push_options: push_option,
+                receive_pack,
+                quiet,
+            };
+            if !push(options)? {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::PackObjects {
+            stdout: _,
+            window,
+            depth,
+            delta_base_offset,
+            no_reuse_delta,
+            base_name,
+        } => pack_objects(PackObjectsOptions {
+            base_name,
+            window,
+            depth,
+            ofs_delta: delta_base_offset,
+            reuse_deltas: !no_reuse_delta,
+        }),
+        Cli::Repack {
+            all,
+            all_loosen_unreachable,
+            delete,
+            quiet,
+            window,
+            depth,
+        } => repack(RepackOptions {
+            all: all || all_loosen_unreachable,
+            loosen_unreachable: all_loosen_unreachable,
+            delete,
+            quiet,
+            window,
+            depth,
+        }),
+        Cli::Gc { auto, prune, quiet } => gc(GcOptions { auto, prune, quiet }),
+        Cli::Fsck {
+            full: _,
+            no_full,
+            connectivity_only,
+            unreachable,
+            dangling: _,
+            no_dangling,
+            lost_found,
+        } => {
+            let checked = fsck(FsckOptions {
+                full: !no_full,
+                connectivity_only,
+                unreachable,
+                dangling: !no_dangling,
+                lost_found,
+            })?;
+
+            if !checked {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::UploadPack {
+            stateless_rpc,
+            advertise_refs,
+            dir,
+        } => upload_pack(UploadPackOptions {
+            dir,
+            session: session(stateless_rpc, advertise_refs),
+        }),
+        Cli::ReceivePack {
+            stateless_rpc,
+            advertise_refs,
+            dir,
+        } => receive_pack(ReceivePackOptions {
+            dir,
+            session: session(stateless_rpc, advertise_refs),
+        }),
+        Cli::HttpBackend => http_backend(),
+        Cli::Stash { command, push } => {
+            let action = match command.unwrap_or(StashCommand::Push(push)) {
+                StashCommand::Push(push) => StashAction::Push(StashPushOptions {
+                    message: push.message,
+                    include_untracked: push.include_untracked,
+                    keep_index: push.keep_index,
+                    paths: push.paths,
+                }),
+                StashCommand::Apply { index, stash } => StashAction::Apply { stash, index },
+                StashCommand::Pop { index, stash } => StashAction::Pop { stash, index },
+                StashCommand::List => StashAction::List,
+                StashCommand::Show { patch, stat, stash } => {
+                    StashAction::Show { stash, patch, stat }
+                }
+                StashCommand::Drop { stash } => StashAction::Drop { stash },
+                StashCommand::Clear => StashAction::Clear,
+                StashCommand::Branch { branch, stash } => StashAction::Branch { branch, stash },
+            };
+
+            if !stash(action)? {
+                exit(1)
+            }
+            Ok(())
+        }
+        Cli::DiffTree {
+            recursive,
+            name_only,
+            name_status,
+            find_renames,
+            find_copies,
+            find_copies_harder,
+            root,
+            objects,
+            paths,
+        } => {
+            let output = if name_only {
+                DiffTreeOutput::NameOnly
+            } else if name_status {
+                DiffTreeOutput::NameStatus
+            } else {
+                DiffTreeOutput::Raw
+            };
+
+            let threshold = find_copies.as_ref().or(find_renames.as_ref());
+            let diff = TreeDiffOptions {
+                recursive,
+                paths,
+                find_renames: find_renames.is_some(),
+                find_copies: find_copies.is_some(),
+                find_copies_harder,
+                rename_score: parse_similarity(threshold.map_or("", |t| t.as_str()))?,
+            };
+
+            diff_tree(DiffTreeOptions {
+                objects,
+                output,
+                root,
+                diff,
+            })
+        }
+        Cli::Diff {
+            cached,
+            unified,
+            patch,
+            stat,
+            numstat,
+            word_diff,
+            color,
+            no_color,
+            ignore_all_space,
+            ignore_space_change,
+            ignore_space_at_eol,
+            ignore_cr_at_eol,
+            ignore_blank_lines,
+            diff_algorithm,
+            patience,
+            histogram,
+            minimal,
+            find_renames,
+            no_renames,
+            args,
+            paths,
+        } => {
+            let mut format = PatchOptions::default();
+            if let Some(context) = unified {
+                format.context = context;
+            }
+            if let Some(word_diff) = word_diff {
+                format.word_diff = WordDiff::try_from(word_diff.as_str())?;
+            }
+            format.color = match color.as_deref() {
+                _ if no_color => false,
+                None | Some("auto") => stdout().is_terminal(),
+                Some("always") => true,
+                Some("never") => false,
+                Some(color) => anyhow::bail!("invalid color mode: {}", color),
+            };
+            // colored word diffs can't be shown without colors
+            if format.word_diff == WordDiff::Color {
+                format.color = true;
+            }
+
+            format.line.whitespace = WhitespaceOptions {
+                ignore_all_space,
+                ignore_space_change,
+                ignore_space_at_eol,
+                ignore_cr_at_eol,
+                ignore_blank_lines,
+            };
+            if let Some(algorithm) = diff_algorithm {
+                format.line.algorithm = DiffAlgorithm::try_from(algorithm.as_str())?;
+            }
+            if patience {
+                format.line.algorithm = DiffAlgorithm::Patience;
+            } else if histogram {
+                format.line.algorithm = DiffAlgorithm::Histogram;
+            } else if minimal {
+                format.line.algorithm = DiffAlgorithm::Minimal;
+            }
+
+            diff(DiffOptions {
+                args,
+                paths,
+                cached,
+                patch,
+                stat,
+                numstat,
+                find_renames: !no_renames,
+                rename_score: parse_similarity(find_renames.as_deref().unwrap_or(""))?,
+                format,
+            })
+        }
     }
 }
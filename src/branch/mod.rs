@@ -0,0 +1,772 @@
+use std::{
+    env,
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::{
+        copy_config_section, read_config, rename_config_section, set_config, unset_config, Config,
+    },
+    log::subject,
+    merge_base::CommitGraph,
+    objects::{hash::Hash, ObjectKind},
+    ref_filter::{short_ref_name, RefFormatter, RefItem},
+    refs::{
+        delete_ref, expand_ref, head_branch, is_valid_ref_name, list_refs, read_symbolic_ref,
+        reflog::{append_reflog, read_reflog, write_reflog},
+        resolve_ref, update_ref, write_ref, write_symbolic_ref,
+    },
+    rev_parse::{abbreviate, peel, resolve_commit},
+    sequencer::read_commit,
+    worktree::detached_head,
+};
+
+use self::tracking::{tracked_remote_ref, tracking, upstream};
+
+pub mod tracking;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const BLUE: &str = "\x1b[34m";
+const RESET: &str = "\x1b[m";
+
+#[derive(Debug, Clone, Default)]
+pub struct BranchListOptions {
+    /// list the remote-tracking branches too
+    pub all: bool,
+    /// list the remote-tracking branches only
+    pub remotes: bool,
+    /// show the commit of each branch, and its upstream too when given twice
+    pub verbose: u8,
+    /// only list the branches whose tips are reachable from this commit
+    pub merged: Option<String>,
+    /// only list the branches whose tips aren't reachable from this commit
+    pub no_merged: Option<String>,
+    /// only list the branches containing this commit
+    pub contains: Option<String>,
+    /// only list the branches not containing this commit
+    pub no_contains: Option<String>,
+    /// keys to sort by, like refname or -committerdate. the last one is the primary key.
+    pub sort: Vec<String>,
+    /// a for-each-ref format to show the branches with, e.g. %(refname:short)
+    pub format: Option<String>,
+    pub color: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum BranchAction {
+    List(BranchListOptions),
+    /// creates a branch at a commit, HEAD by default. with track set, or by default when
+    /// starting from a remote-tracking branch, the new branch tracks its starting point.
+    Create {
+        name: String,
+        start: Option<String>,
+        force: bool,
+        track: Option<bool>,
+    },
+    /// renames a branch, the current one by default, along with its reflog and config
+    Rename {
+        old: Option<String>,
+        new: String,
+        force: bool,
+    },
+    /// copies a branch, the current one by default, along with its reflog and config
+    Copy {
+        old: Option<String>,
+        new: String,
+        force: bool,
+    },
+    /// deletes branches, or remote-tracking branches. unless forced, local branches must be
+    /// merged into their upstream, or into HEAD if they have none.
+    Delete {
+        names: Vec<String>,
+        remotes: bool,
+        force: bool,
+    },
+    /// makes a branch, the current one by default, track another
+    SetUpstream {
+        upstream: String,
+        branch: Option<String>,
+    },
+    UnsetUpstream {
+        branch: Option<String>,
+    },
+}
+
+/// lists, creates, renames, copies and deletes branches, and sets their upstream.
+/// returns false if a branch couldn't be deleted.
+pub fn branch(action: BranchAction) -> Result<bool> {
+    match action {
+        BranchAction::List(options) => list(options, &mut io::stdout()).map(|_| true),
+        BranchAction::Create {
+            name,
+            start,
+            force,
+            track,
+        } => create(&name, start.as_deref().unwrap_or("HEAD"), force, track).map(|_| true),
+        BranchAction::Rename { old, new, force } => {
+            copy_or_rename(old, &new, force, false).map(|_| true)
+        }
+        BranchAction::Copy { old, new, force } => {
+            copy_or_rename(old, &new, force, true).map(|_| true)
+        }
+        BranchAction::Delete {
+            names,
+            remotes,
+            force,
+        } => delete(&names, remotes, force),
+        BranchAction::SetUpstream { upstream, branch } => {
+            let branch = match branch {
+                Some(branch) => branch,
+                None => current_branch().map_err(|_| {
+                    anyhow!(
+                        "could not set upstream of HEAD to {} when it does not point to any branch.",
+                        upstream
+                    )
+                })?,
+            };
+            if resolve_ref(&format!("refs/heads/{}", branch))?.is_none() {
+                bail!("branch '{}' does not exist", branch);
+            }
+            let full_name = expand_ref(&upstream)?
+                .filter(|name| name.starts_with("refs/heads/") || name.starts_with("refs/remotes/"))
+                .ok_or(anyhow!(
+                    "the requested upstream branch '{}' does not exist\n{}",
+                    upstream,
+                    UPSTREAM_HINT
+                ))?;
+            set_upstream(&branch, &full_name)?;
+            Ok(true)
+        }
+        BranchAction::UnsetUpstream { branch } => {
+            let branch = match branch {
+                Some(branch) => branch,
+                None => current_branch().map_err(|_| anyhow!("HEAD does not point to a branch"))?,
+            };
+            let config = read_config()?;
+            if config.get(&format!("branch.{}.merge", branch)).is_none() {
+                bail!("Branch '{}' has no upstream information", branch);
+            }
+            unset_config(&format!("branch.{}.remote", branch))?;
+            unset_config(&format!("branch.{}.merge", branch))?;
+            Ok(true)
+        }
+    }
+}
+
+const UPSTREAM_HINT: &str = "hint: \nhint: If you are planning on basing your work on an upstream\nhint: branch that already exists at the remote, you may need to\nhint: run \"mgit fetch\" to retrieve it.\nhint: \nhint: If you are planning to push out a new local branch that\nhint: will track its remote counterpart, you may want to use\nhint: \"mgit push -u\" to set the upstream config as you push.";
+
+fn list<W: Write>(options: BranchListOptions, out: &mut W) -> Result<()> {
+    let config = read_config()?;
+    let formatter = RefFormatter::new(options.color)?;
+    let mut items = Vec::new();
+    if !options.remotes || options.all {
+        items.extend(list_refs("refs/heads/")?);
+    }
+    if options.remotes || options.all {
+        items.extend(list_refs("refs/remotes/")?);
+    }
+    let mut items: Vec<RefItem> = items
+        .into_iter()
+        .map(|(name, hash)| RefItem { name, hash })
+        .collect();
+    formatter.sort(&mut items, &options.sort)?;
+
+    // a detached HEAD is listed first, under a description of where it is
+    let head = resolve_ref("HEAD")?;
+    if let (None, Some(head), false) = (head_branch()?, &head, options.remotes && !options.all) {
+        items.insert(
+            0,
+            RefItem {
+                name: format!("({})", detached_description(head)?),
+                hash: head.clone(),
+            },
+        );
+    }
+
+    let mut graph = CommitGraph::new();
+    let filters = [
+        (&options.merged, true, true),
+        (&options.no_merged, true, false),
+        (&options.contains, false, true),
+        (&options.no_contains, false, false),
+    ];
+    for (rev, merged, keep) in filters {
+        let commit = match rev {
+            Some(rev) => {
+                resolve_commit(rev).map_err(|_| anyhow!("malformed object name {}", rev))?
+            }
+            None => continue,
+        };
+        let mut kept = Vec::new();
+        for item in items {
+            let tip = peel(item.hash.clone(), Some(ObjectKind::Commit))?;
+            let reachable = match merged {
+                true => graph.is_ancestor(&tip, &commit)?,
+                false => graph.is_ancestor(&commit, &tip)?,
+            };
+            if reachable == keep {
+                kept.push(item);
+            }
+        }
+        items = kept;
+    }
+
+    if let Some(format) = &options.format {
+        for item in &items {
+            writeln!(out, "{}", formatter.format(format, item)?)?;
+        }
+        return Ok(());
+    }
+
+    let current = head_branch()?;
+    let display_name = |name: &str| match name.strip_prefix("refs/heads/") {
+        Some(branch) => branch.to_string(),
+        None if options.all => name.trim_start_matches("refs/").to_string(),
+        None => name.trim_start_matches("refs/remotes/").to_string(),
+    };
+    let width = items
+        .iter()
+        .map(|item| display_name(&item.name).chars().count())
+        .max()
+        .unwrap_or(0);
+    for item in &items {
+        let is_current = match &current {
+            Some(current) => *current == item.name,
+            None => item.name.starts_with('('),
+        };
+        let name = display_name(&item.name);
+        let color = match (is_current, item.name.starts_with("refs/remotes/")) {
+            _ if !options.color => "",
+            (true, _) => GREEN,
+            (_, true) => RED,
+            _ => "",
+        };
+        let reset = if color.is_empty() { "" } else { RESET };
+        let marker = if is_current { '*' } else { ' ' };
+
+        let symref = read_symbolic_ref(&item.name)?
+            .map(|target| short_ref_name(&target))
+            .transpose()?;
+        let line = match (options.verbose, symref) {
+            (0, None) => format!("{} {}{}{}", marker, color, name, reset),
+            (0, Some(target)) => format!("{} {}{}{} -> {}", marker, color, name, reset, target),
+            (_, Some(target)) => {
+                format!("{} {}{:width$}{} -> {}", marker, color, name, reset, target)
+            }
+            (verbose, None) => {
+                let commit = read_commit(&item.hash)?;
+                let track = match item.name.strip_prefix("refs/heads/") {
+                    Some(branch) => {
+                        describe_tracking(&config, branch, &item.hash, verbose, options.color)?
+                    }
+                    None => String::new(),
+                };
+                format!(
+                    "{} {}{:width$}{} {} {}{}",
+                    marker,
+                    color,
+                    name,
+                    reset,
+                    abbreviate(&item.hash)?,
+                    track,
+                    subject(&commit.message)
+                )
+            }
+        };
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// the tracking information shown by -v, "[ahead 1] ", and by -vv, "[origin/main: ahead 1] "
+fn describe_tracking(
+    config: &Config,
+    branch: &str,
+    head: &Hash,
+    verbose: u8,
+    color: bool,
+) -> Result<String> {
+    let tracking = match tracking(config, branch, head)? {
+        Some(tracking) => tracking,
+        None => return Ok(String::new()),
+    };
+    let described = tracking.describe();
+    if verbose < 2 {
+        return Ok(match described.is_empty() {
+            true => String::new(),
+            false => format!("[{}] ", described),
+        });
+    }
+
+    let mut upstream = short_ref_name(&tracking.upstream)?;
+    if color {
+        upstream = format!("{}{}{}", BLUE, upstream, RESET);
+    }
+    Ok(match described.is_empty() {
+        true => format!("[{}] ", upstream),
+        false => format!("[{}: {}] ", upstream, described),
+    })
+}
+
+/// describes a detached HEAD, or the branch being rebased
+fn detached_description(head: &Hash) -> Result<String> {
+    if let Ok(head_name) = std::fs::read_to_string(".git/rebase-merge/head-name") {
+        let branch = head_name.trim_end().trim_start_matches("refs/heads/");
+        return Ok(format!("no branch, rebasing {}", branch));
+    }
+    detached_head(head)
+}
+
+fn create(name: &str, start: &str, force: bool, track: Option<bool>) -> Result<()> {
+    let full_name = check_branch_name(name)?;
+    let hash =
+        resolve_commit(start).map_err(|_| anyhow!("not a valid object name: '{}'", start))?;
+    let existing = resolve_ref(&full_name)?;
+    if existing.is_some() {
+        if !force {
+            bail!("a branch named '{}' already exists", name);
+        }
+        if head_branch()?.as_deref() == Some(full_name.as_str()) {
+            bail!(
+                "cannot force update the branch '{}' checked out at '{}'",
+                name,
+                env::current_dir()?.display()
+            );
+        }
+    }
+
+    // the upstream is the starting point when it's a branch, remote-tracking ones by default
+    let config = read_config()?;
+    let start_ref = expand_ref(start)?.filter(|start_ref| {
+        start_ref.starts_with("refs/heads/") || start_ref.starts_with("refs/remotes/")
+    });
+    let auto_track = config.get_bool("branch.autoSetupMerge")?.unwrap_or(true);
+    let upstream = match (track, &start_ref) {
+        (Some(false), _) => None,
+        (Some(true), None) => bail!(
+            "cannot set up tracking information; starting point '{}' is not a branch",
+            start
+        ),
+        (Some(true), Some(start_ref)) => Some(start_ref.clone()),
+        (None, Some(start_ref)) if auto_track && start_ref.starts_with("refs/remotes/") => {
+            Some(start_ref.clone())
+        }
+        (None, _) => None,
+    };
+
+    match existing {
+        Some(existing) if existing == hash => {}
+        Some(_) => update_ref(&full_name, &hash, &format!("branch: Reset to {}", start))?,
+        None => update_ref(
+            &full_name,
+            &hash,
+            &format!("branch: Created from {}", start),
+        )?,
+    }
+    if let Some(upstream) = upstream {
+        set_upstream(name, &upstream)?;
+    }
+    Ok(())
+}
+
+/// records the upstream of a branch in branch.<name>.remote and branch.<name>.merge
+fn set_upstream(branch: &str, upstream: &str) -> Result<()> {
+    let config = read_config()?;
+    let (remote, merge) = match upstream.starts_with("refs/remotes/") {
+        true => tracked_remote_ref(&config, upstream).ok_or(anyhow!(
+            "the requested upstream branch '{}' does not exist\n{}",
+            short_ref_name(upstream)?,
+            UPSTREAM_HINT
+        ))?,
+        false => (String::from("."), upstream.to_string()),
+    };
+
+    set_config(&format!("branch.{}.remote", branch), &remote)?;
+    set_config(&format!("branch.{}.merge", branch), &merge)?;
+    println!(
+        "branch '{}' set up to track '{}'.",
+        branch,
+        short_ref_name(upstream)?
+    );
+    Ok(())
+}
+
+fn copy_or_rename(old: Option<String>, new: &str, force: bool, copy: bool) -> Result<()> {
+    let action = if copy { "copy" } else { "rename" };
+    let old = match old {
+        Some(old) => old,
+        None => current_branch()
+            .map_err(|_| anyhow!("cannot {} the current branch while not on any.", action))?,
+    };
+    let old_name = format!("refs/heads/{}", old);
+    let hash = resolve_ref(&old_name)?.ok_or(anyhow!("No branch named '{}'.", old))?;
+    let new_name = check_branch_name(new)?;
+    let current = head_branch()?;
+    if old_name != new_name && resolve_ref(&new_name)?.is_some() {
+        if !force {
+            bail!("a branch named '{}' already exists", new);
+        }
+        if current.as_deref() == Some(new_name.as_str()) {
+            bail!("cannot force update the current branch.");
+        }
+    }
+
+    let message = match copy {
+        true => format!("Branch: copied {} to {}", old_name, new_name),
+        false => format!("Branch: renamed {} to {}", old_name, new_name),
+    };
+    let entries = read_reflog(&old_name)?;
+    if !copy {
+        delete_ref(&old_name)?;
+    }
+    delete_ref(&new_name)?;
+    write_ref(&new_name, &hash)?;
+    if !entries.is_empty() {
+        write_reflog(&new_name, &entries)?;
+    }
+    append_reflog(&new_name, Some(&hash), &hash, &message)?;
+
+    if !copy && current.as_deref() == Some(old_name.as_str()) {
+        write_symbolic_ref("HEAD", &new_name)?;
+        append_reflog("HEAD", Some(&hash), &hash, &message)?;
+    }
+
+    let old_section = format!("branch.{}", old);
+    let new_section = format!("branch.{}", new);
+    match copy {
+        true => copy_config_section(&old_section, &new_section),
+        false => rename_config_section(&old_section, Some(&new_section)),
+    }
+}
+
+fn delete(names: &[String], remotes: bool, force: bool) -> Result<bool> {
+    let config = read_config()?;
+    let head = resolve_ref("HEAD")?;
+    let current = head_branch()?;
+    let mut graph = CommitGraph::new();
+    let mut deleted_all = true;
+    for name in names {
+        let full_name = match remotes {
+            true => format!("refs/remotes/{}", name),
+            false => format!("refs/heads/{}", name),
+        };
+        let hash = match resolve_ref(&full_name)? {
+            Some(hash) => hash,
+            None => {
+                match remotes {
+                    true => eprintln!("error: remote-tracking branch '{}' not found.", name),
+                    false => eprintln!("error: branch '{}' not found.", name),
+                }
+                deleted_all = false;
+                continue;
+            }
+        };
+
+        if !remotes {
+            if current.as_deref() == Some(full_name.as_str()) {
+                eprintln!(
+                    "error: Cannot delete branch '{}' checked out at '{}'",
+                    name,
+                    env::current_dir()?.display()
+                );
+                deleted_all = false;
+                continue;
+            }
+            if !force && !is_merged(&mut graph, &config, name, &hash, head.as_ref())? {
+                eprintln!("error: The branch '{}' is not fully merged.", name);
+                eprintln!(
+                    "If you are sure you want to delete it, run 'mgit branch -D {}'.",
+                    name
+                );
+                deleted_all = false;
+                continue;
+            }
+        }
+
+        delete_ref(&full_name)?;
+        match remotes {
+            true => println!(
+                "Deleted remote-tracking branch {} (was {}).",
+                name,
+                abbreviate(&hash)?
+            ),
+            false => {
+                rename_config_section(&format!("branch.{}", name), None)?;
+                println!("Deleted branch {} (was {}).", name, abbreviate(&hash)?);
+            }
+        }
+    }
+    Ok(deleted_all)
+}
+
+/// checks that a branch can be deleted safely: it must be merged into its upstream, or
+/// into HEAD if it has none. a warning is given when HEAD disagrees with the upstream.
+fn is_merged(
+    graph: &mut CommitGraph,
+    config: &Config,
+    branch: &str,
+    hash: &Hash,
+    head: Option<&Hash>,
+) -> Result<bool> {
+    let upstream = upstream(config, branch)
+        .map(|upstream| resolve_ref(&upstream).map(|hash| hash.map(|hash| (upstream, hash))))
+        .transpose()?
+        .flatten();
+    let merged_into = |graph: &mut CommitGraph, target: Option<&Hash>| match target {
+        Some(target) => graph.is_ancestor(hash, target),
+        None => Ok(false),
+    };
+
+    let (upstream_name, upstream_hash) = match upstream {
+        Some(upstream) => upstream,
+        None => return merged_into(graph, head),
+    };
+    let merged = merged_into(graph, Some(&upstream_hash))?;
+    if head.is_some() && merged_into(graph, head)? != merged {
+        match merged {
+            true => eprintln!(
+                "warning: deleting branch '{}' that has been merged to\n         '{}', but not yet merged to HEAD.",
+                branch, upstream_name
+            ),
+            false => eprintln!(
+                "warning: not deleting branch '{}' that is not yet merged to\n         '{}', even though it is merged to HEAD.",
+                branch, upstream_name
+            ),
+        }
+    }
+    Ok(merged)
+}
+
+/// the name of the branch HEAD points to, failing when it's detached
+fn current_branch() -> Result<String> {
+    match head_branch()? {
+        Some(branch) => Ok(branch.trim_start_matches("refs/heads/").to_string()),
+        None => bail!("HEAD is detached"),
+    }
+}
+
+/// checks that a branch name is valid, returning its full ref name
+fn check_branch_name(name: &str) -> Result<String> {
+    let full_name = format!("refs/heads/{}", name);
+    if name == "HEAD" || name.starts_with('-') || !is_valid_ref_name(&full_name) {
+        bail!("'{}' is not a valid branch name", name);
+    }
+    Ok(full_name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{read_config, set_config},
+        objects::hash::Hash,
+        refs::{list_refs, read_symbolic_ref, reflog::read_reflog, resolve_ref, update_ref},
+        rev_parse::abbreviate,
+        test_repo::TestRepo,
+    };
+
+    use super::{branch, list, BranchAction, BranchListOptions};
+
+    struct Commits {
+        first: Hash,
+        second: Hash,
+        topic: Hash,
+    }
+
+    /// main (checked out) has first and second. merged points at first, and topic at a
+    /// commit on top of second.
+    fn setup(repo: &TestRepo) -> Commits {
+        let first = repo.commit(&repo.tree(&[("a", "1")]), &[], 1000, "first");
+        let second = repo.commit(&repo.tree(&[("a", "2")]), &[&first], 2000, "second");
+        let topic = repo.commit(&repo.tree(&[("a", "3")]), &[&second], 3000, "topic");
+        repo.checkout(&second);
+        repo.set_ref("refs/heads/merged", &first);
+        update_ref("refs/heads/topic", &topic, "branch: Created from HEAD").unwrap();
+        Commits {
+            first,
+            second,
+            topic,
+        }
+    }
+
+    fn branches() -> Vec<String> {
+        list_refs("refs/heads/")
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name.trim_start_matches("refs/heads/").to_string())
+            .collect()
+    }
+
+    fn listed(options: BranchListOptions) -> String {
+        let mut out = Vec::new();
+        list(options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn delete(names: &[&str], force: bool) -> bool {
+        branch(BranchAction::Delete {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            remotes: false,
+            force,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rename_and_copy() {
+        let repo = TestRepo::new();
+        let commits = setup(&repo);
+        set_config("branch.topic.remote", ".").unwrap();
+        set_config("branch.topic.merge", "refs/heads/main").unwrap();
+
+        branch(BranchAction::Rename {
+            old: Some(String::from("topic")),
+            new: String::from("renamed"),
+            force: false,
+        })
+        .unwrap();
+        assert_eq!(branches(), vec!["main", "merged", "renamed"]);
+        assert_eq!(
+            resolve_ref("refs/heads/renamed").unwrap(),
+            Some(commits.topic.clone())
+        );
+        // the reflog and the config go along
+        assert_eq!(read_reflog("refs/heads/renamed").unwrap().len(), 2);
+        let config = read_config().unwrap();
+        assert_eq!(config.get("branch.renamed.merge"), Some("refs/heads/main"));
+        assert_eq!(config.get("branch.topic.merge"), None);
+
+        // renaming the current branch moves HEAD with it
+        branch(BranchAction::Rename {
+            old: None,
+            new: String::from("trunk"),
+            force: false,
+        })
+        .unwrap();
+        assert_eq!(
+            read_symbolic_ref("HEAD").unwrap().as_deref(),
+            Some("refs/heads/trunk")
+        );
+
+        branch(BranchAction::Copy {
+            old: Some(String::from("renamed")),
+            new: String::from("copied"),
+            force: false,
+        })
+        .unwrap();
+        assert_eq!(branches(), vec!["copied", "merged", "renamed", "trunk"]);
+        let config = read_config().unwrap();
+        assert_eq!(config.get("branch.copied.merge"), Some("refs/heads/main"));
+        assert_eq!(config.get("branch.renamed.merge"), Some("refs/heads/main"));
+
+        // existing branches are only replaced when forced
+        let copy = |force| {
+            branch(BranchAction::Copy {
+                old: Some(String::from("merged")),
+                new: String::from("copied"),
+                force,
+            })
+        };
+        assert!(copy(false).is_err());
+        copy(true).unwrap();
+        assert_eq!(
+            resolve_ref("refs/heads/copied").unwrap(),
+            Some(commits.first)
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let repo = TestRepo::new();
+        setup(&repo);
+
+        // unmerged branches and the current branch are kept
+        assert!(!delete(&["merged", "topic", "main"], false));
+        assert_eq!(branches(), vec!["main", "topic"]);
+
+        assert!(delete(&["topic"], true));
+        assert_eq!(branches(), vec!["main"]);
+        assert!(!delete(&["missing"], true));
+    }
+
+    #[test]
+    fn test_list_filters() {
+        let repo = TestRepo::new();
+        let commits = setup(&repo);
+        let names = |options: BranchListOptions| {
+            listed(BranchListOptions {
+                format: Some(String::from("%(refname:short)")),
+                ..options
+            })
+        };
+
+        let merged = names(BranchListOptions {
+            merged: Some(String::from("main")),
+            ..Default::default()
+        });
+        assert_eq!(merged, "main\nmerged\n");
+
+        let no_merged = names(BranchListOptions {
+            no_merged: Some(String::from("main")),
+            ..Default::default()
+        });
+        assert_eq!(no_merged, "topic\n");
+
+        let contains = names(BranchListOptions {
+            contains: Some(format!("{:x}", commits.second)),
+            ..Default::default()
+        });
+        assert_eq!(contains, "main\ntopic\n");
+
+        let no_contains = names(BranchListOptions {
+            no_contains: Some(String::from("main")),
+            ..Default::default()
+        });
+        assert_eq!(no_contains, "merged\n");
+    }
+
+    #[test]
+    fn test_list_tracking() {
+        let repo = TestRepo::new();
+        let commits = setup(&repo);
+        set_config("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*").unwrap();
+        repo.set_ref("refs/remotes/origin/main", &commits.first);
+        set_config("branch.main.remote", "origin").unwrap();
+        set_config("branch.main.merge", "refs/heads/main").unwrap();
+        set_config("branch.topic.remote", "origin").unwrap();
+        set_config("branch.topic.merge", "refs/heads/gone").unwrap();
+        set_config("branch.merged.remote", ".").unwrap();
+        set_config("branch.merged.merge", "refs/heads/main").unwrap();
+
+        let expected = |verbose: u8| {
+            let track = |upstream: &str, described: &str| match verbose {
+                1 => format!("[{}] ", described),
+                _ => format!("[{}: {}] ", upstream, described),
+            };
+            format!(
+                "* main   {} {}second\n  merged {} {}first\n  topic  {} {}topic\n",
+                abbreviate(&commits.second).unwrap(),
+                track("origin/main", "ahead 1"),
+                abbreviate(&commits.first).unwrap(),
+                track("main", "behind 1"),
+                abbreviate(&commits.topic).unwrap(),
+                track("origin/gone", "gone"),
+            )
+        };
+        for verbose in [1, 2] {
+            let output = listed(BranchListOptions {
+                verbose,
+                ..Default::default()
+            });
+            assert_eq!(output, expected(verbose));
+        }
+
+        let all = listed(BranchListOptions {
+            all: true,
+            ..Default::default()
+        });
+        assert_eq!(all, "* main\n  merged\n  topic\n  remotes/origin/main\n");
+    }
+}
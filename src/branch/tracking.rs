@@ -0,0 +1,105 @@
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    objects::hash::Hash,
+    refs::{refspec::Refspec, resolve_ref},
+    revwalk::RevWalk,
+};
+
+/// how a branch relates to the branch it tracks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracking {
+    /// the full name of the upstream ref
+    pub upstream: String,
+    /// the commits only the branch has and those only the upstream has, or None if the
+    /// upstream ref is gone
+    pub counts: Option<(usize, usize)>,
+}
+
+impl Tracking {
+    /// describes the counts like git branch -v and %(upstream:track): "ahead 1, behind 2"
+    /// or "gone". empty when the branch is up to date.
+    pub fn describe(&self) -> String {
+        match self.counts {
+            None => String::from("gone"),
+            Some((0, 0)) => String::new(),
+            Some((ahead, 0)) => format!("ahead {}", ahead),
+            Some((0, behind)) => format!("behind {}", behind),
+            Some((ahead, behind)) => format!("ahead {}, behind {}", ahead, behind),
+        }
+    }
+
+    /// the counts as %(upstream:trackshort): <, >, <> or =
+    pub fn describe_short(&self) -> &'static str {
+        match self.counts {
+            None => "",
+            Some((0, 0)) => "=",
+            Some((_, 0)) => ">",
+            Some((0, _)) => "<",
+            Some(_) => "<>",
+        }
+    }
+}
+
+/// the full name of the ref a branch tracks, following branch.<name>.remote and
+/// branch.<name>.merge through the fetch refspecs of the remote. the ref may not exist.
+pub fn upstream(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+
+    config
+        .get_all(&format!("remote.{}.fetch", remote))
+        .into_iter()
+        .find_map(|refspec| map_refspec(refspec, merge, false))
+}
+
+/// finds the remote whose fetch refspecs map a ref of theirs to a remote-tracking ref,
+/// returning the remote and their ref
+pub fn tracked_remote_ref(config: &Config, tracking_ref: &str) -> Option<(String, String)> {
+    config.subsections("remote").into_iter().find_map(|remote| {
+        config
+            .get_all(&format!("remote.{}.fetch", remote))
+            .into_iter()
+            .find_map(|refspec| map_refspec(refspec, tracking_ref, true))
+            .map(|their_ref| (remote.to_string(), their_ref))
+    })
+}
+
+/// maps a ref through a refspec like +refs/heads/*:refs/remotes/origin/*, from its source
+/// to its destination, or back
+fn map_refspec(refspec: &str, name: &str, reverse: bool) -> Option<String> {
+    let refspec = Refspec::parse(refspec).ok()?;
+    match reverse {
+        true => refspec.map_reverse(name),
+        false => refspec.map(name),
+    }
+}
+
+/// compares a branch with its upstream, if it has one
+pub fn tracking(config: &Config, branch: &str, head: &Hash) -> Result<Option<Tracking>> {
+    let upstream = match upstream(config, branch) {
+        Some(upstream) => upstream,
+        None => return Ok(None),
+    };
+    let counts = match resolve_ref(&upstream)? {
+        Some(base) => Some((count_only(head, &base)?, count_only(&base, head)?)),
+        None => None,
+    };
+    Ok(Some(Tracking { upstream, counts }))
+}
+
+/// counts the commits reachable from one commit but not from another
+fn count_only(commit: &Hash, hidden: &Hash) -> Result<usize> {
+    let mut walk = RevWalk::new();
+    walk.push(commit.clone())?;
+    walk.hide(hidden.clone())?;
+    let mut count = 0;
+    while walk.next_commit()?.is_some() {
+        count += 1;
+    }
+    Ok(count)
+}
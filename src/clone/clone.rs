@@ -1,5 +1,5 @@
 use crate::objects::hash::Hash;
-use crate::pack_protocol::pack_file::PackFile;
+use crate::objects::pack::store_pack;
 use anyhow::{anyhow, bail, Context, Result};
 use reqwest::blocking;
 use std::{collections::HashMap, io::Read, io::Write, path::PathBuf};
@@ -127,12 +127,7 @@ fn fetch_refs(url: &str, refs: &[&str]) -> Result<()> {
     let header = header.trim();
     anyhow::ensure!(header == "NAK", "only NAK response is supported");
 
-    let mut pack_file = PackFile::new(bytes.clone())?;
-    let pack_objects = pack_file.read_objects()?;
-    println!("pack objects: {:?}", pack_objects);
-    for pack_obj in pack_objects {
-        // let (hash, obj) = pack_obj.prepare()?;
-    }
+    store_pack(&bytes[..], false)?;
 
     Ok(())
 }
@@ -0,0 +1,502 @@
+use std::{env, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+const CONFIG_PATH: &str = ".git/config";
+
+/// a configuration variable, with its section and subsection lowercased and the
+/// subsection kept as written (e.g. branch.main.remote)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+    /// None for keys given without a value, which are true booleans
+    pub value: Option<String>,
+}
+
+impl ConfigEntry {
+    /// the full name of the variable, e.g. remote.origin.url
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+}
+
+/// the variables of the global and repository configuration files, in the order
+/// they are read. later values override earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// returns the last value of a variable, given as section.key or section.subsection.key
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let (section, subsection, key) = split_name(name).ok()?;
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| {
+                entry.section == section && entry.subsection == subsection && entry.key == key
+            })
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+    }
+
+    /// returns all the values of a multi-valued variable, e.g. remote.origin.fetch
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        let (section, subsection, key) = match split_name(name) {
+            Ok(parts) => parts,
+            Err(_) => return Vec::new(),
+        };
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.section == section && entry.subsection == subsection && entry.key == key
+            })
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+            .collect()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>> {
+        self.get(name).map(parse_bool).transpose()
+    }
+
+    pub fn get_size(&self, name: &str) -> Result<Option<u64>> {
+        self.get(name).map(parse_size).transpose()
+    }
+
+    /// returns the subsections of a section, e.g. the names of the remotes
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let mut subsections: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            if let Some(subsection) = entry.subsection.as_deref() {
+                if entry.section == section && !subsections.contains(&subsection) {
+                    subsections.push(subsection);
+                }
+            }
+        }
+        subsections
+    }
+}
+
+/// reads ~/.gitconfig (or $GIT_CONFIG_GLOBAL) and .git/config
+pub fn read_config() -> Result<Config> {
+    let mut config = Config::default();
+    let global = match env::var("GIT_CONFIG_GLOBAL") {
+        Ok(path) => Some(PathBuf::from(path)),
+        Err(_) => env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".gitconfig")),
+    };
+
+    for path in global.into_iter().chain([PathBuf::from(CONFIG_PATH)]) {
+        if path.is_file() {
+            config
+                .entries
+                .extend(parse_config(&fs::read_to_string(path)?)?);
+        }
+    }
+
+    Ok(config)
+}
+
+/// sets a variable of .git/config, replacing its last value or adding it to its section
+pub fn set_config(name: &str, value: &str) -> Result<()> {
+    let (section, subsection, key) = split_name(name)?;
+    let path = PathBuf::from(CONFIG_PATH);
+    let content = match path.is_file() {
+        true => fs::read_to_string(&path)?,
+        false => String::new(),
+    };
+
+    let mut lines: Vec<String> = content.lines().map(|line| line.to_string()).collect();
+    let line = format!("\t{} = {}", key, quote_value(value));
+    let mut current: Option<(String, Option<String>)> = None;
+    let mut key_line = None;
+    let mut section_end = None;
+    for (i, raw) in lines.iter().enumerate() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with('[') {
+            current = parse_section_header(trimmed).ok().map(|(header, _)| header);
+            continue;
+        }
+        if current.as_ref() != Some(&(section.clone(), subsection.clone())) {
+            continue;
+        }
+
+        section_end = Some(i + 1);
+        let name = trimmed
+            .split(['=', ' ', '\t', ';', '#'])
+            .next()
+            .unwrap_or("");
+        if name.to_ascii_lowercase() == key {
+            key_line = Some(i);
+        }
+    }
+
+    match (key_line, section_end) {
+        (Some(i), _) => lines[i] = line,
+        (None, Some(end)) => lines.insert(end, line),
+        (None, None) => {
+            // sections are located by their last variable, so a header alone gets the key right after it
+            let header = section_header(&section, subsection.as_deref());
+            match lines.iter().rposition(|raw| raw.trim() == header) {
+                Some(i) => lines.insert(i + 1, line),
+                None => {
+                    lines.push(header);
+                    lines.push(line);
+                }
+            }
+        }
+    }
+
+    fs::write(path, format!("{}\n", lines.join("\n")))?;
+    Ok(())
+}
+
+/// removes all the values of a variable from .git/config
+pub fn unset_config(name: &str) -> Result<()> {
+    let (section, subsection, key) = split_name(name)?;
+    let path = PathBuf::from(CONFIG_PATH);
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let target = Some((section, subsection));
+    let mut current: Option<(String, Option<String>)> = None;
+    let mut lines: Vec<String> = Vec::new();
+    // the header of the section the variable was removed from, dropped if nothing follows it
+    let mut header = 0;
+    let mut emptied: Option<usize> = None;
+    for raw in fs::read_to_string(&path)?.lines() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with('[') {
+            if emptied.is_some_and(|i| i + 1 == lines.len()) {
+                lines.pop();
+            }
+            current = parse_section_header(trimmed).ok().map(|(header, _)| header);
+            emptied = None;
+            header = lines.len();
+        } else if current == target {
+            let name = trimmed.split(['=', ' ', '\t']).next().unwrap_or("");
+            if name.to_ascii_lowercase() == key {
+                emptied = Some(header);
+                continue;
+            }
+        }
+        lines.push(raw.to_string());
+    }
+    if emptied.is_some_and(|i| i + 1 == lines.len()) {
+        lines.pop();
+    }
+
+    fs::write(path, format!("{}\n", lines.join("\n")))?;
+    Ok(())
+}
+
+/// renames a section of .git/config (e.g. branch.topic), or removes it and its variables
+/// when no new name is given
+pub fn rename_config_section(name: &str, new_name: Option<&str>) -> Result<()> {
+    let header = split_section(name);
+    let path = PathBuf::from(CONFIG_PATH);
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let mut in_section = false;
+    let mut lines = Vec::new();
+    for raw in fs::read_to_string(&path)?.lines() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with('[') {
+            in_section = parse_section_header(trimmed).is_ok_and(|(parsed, _)| parsed == header);
+            if in_section {
+                if let Some(new_name) = new_name {
+                    let (section, subsection) = split_section(new_name);
+                    lines.push(section_header(&section, subsection.as_deref()));
+                }
+                continue;
+            }
+        }
+        if !in_section || new_name.is_some() {
+            lines.push(raw.to_string());
+        }
+    }
+
+    fs::write(path, format!("{}\n", lines.join("\n")))?;
+    Ok(())
+}
+
+/// copies the variables of a section of .git/config to a new section right after it
+pub fn copy_config_section(name: &str, new_name: &str) -> Result<()> {
+    let header = split_section(name);
+    let path = PathBuf::from(CONFIG_PATH);
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let content = fs::read_to_string(&path)?;
+    let mut lines: Vec<&str> = content.lines().collect();
+    let mut copied = Vec::new();
+    let mut section_end = None;
+    let mut in_section = false;
+    for (i, raw) in lines.iter().enumerate() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with('[') {
+            in_section = parse_section_header(trimmed).is_ok_and(|(parsed, _)| parsed == header);
+        } else if in_section {
+            copied.push(*raw);
+            section_end = Some(i + 1);
+        }
+    }
+    let section_end = match section_end {
+        Some(end) => end,
+        None => return Ok(()),
+    };
+
+    let (section, subsection) = split_section(new_name);
+    let new_header = section_header(&section, subsection.as_deref());
+    copied.insert(0, &new_header);
+    lines.splice(section_end..section_end, copied);
+    fs::write(path, format!("{}\n", lines.join("\n")))?;
+    Ok(())
+}
+
+/// parses git's boolean values: true/yes/on/1 and false/no/off/0/empty
+pub fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => bail!("invalid boolean value: {}", value),
+    }
+}
+
+/// parses a size, with an optional k, m or g unit: 96m is 96 MiB
+pub fn parse_size(value: &str) -> Result<u64> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_lowercase()),
+        _ => (value, ' '),
+    };
+    let shift = match unit {
+        ' ' => 0,
+        'k' => 10,
+        'm' => 20,
+        'g' => 30,
+        _ => bail!("invalid unit in size: {}", value),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(1 << shift))
+        .ok_or(anyhow!("invalid size: {}", value))
+}
+
+pub fn parse_config(content: &str) -> Result<Vec<ConfigEntry>> {
+    let mut entries = Vec::new();
+    let mut section: Option<(String, Option<String>)> = None;
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let mut line = line.trim_start();
+        if line.starts_with('[') {
+            let (header, rest) = parse_section_header(line)?;
+            section = Some(header);
+            line = rest.trim_start();
+        }
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let (section, subsection) = section
+            .clone()
+            .ok_or(anyhow!("config variable outside of a section: {}", line))?;
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value)),
+            None => (line.split(['#', ';']).next().unwrap_or("").trim(), None),
+        };
+
+        let value = match value {
+            Some(value) => {
+                // a backslash at the end of a line continues the value on the next line
+                let mut value = value.to_string();
+                while value.trim_end().ends_with('\\') && !value.trim_end().ends_with("\\\\") {
+                    value.truncate(value.trim_end().len() - 1);
+                    value.push_str(lines.next().unwrap_or(""));
+                }
+                Some(parse_value(&value)?)
+            }
+            None => None,
+        };
+
+        entries.push(ConfigEntry {
+            section,
+            subsection,
+            key: key.to_ascii_lowercase(),
+            value,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// parses [section], [section "subsection"] and the legacy [section.subsection],
+/// returning the header and the rest of the line
+fn parse_section_header(line: &str) -> Result<((String, Option<String>), &str)> {
+    let invalid = || anyhow!("invalid config section header: {}", line);
+    let inner = line.strip_prefix('[').ok_or_else(invalid)?;
+
+    if let Some((name, rest)) = inner.split_once(" \"") {
+        let mut subsection = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => subsection.extend(chars.next().map(|(_, c)| c)),
+                '"' => {
+                    let rest = rest[i + 1..].strip_prefix(']').ok_or_else(invalid)?;
+                    return Ok(((name.trim().to_ascii_lowercase(), Some(subsection)), rest));
+                }
+                c => subsection.push(c),
+            }
+        }
+        return Err(invalid());
+    }
+
+    let (name, rest) = inner.split_once(']').ok_or_else(invalid)?;
+    match name.split_once('.') {
+        Some((section, subsection)) => Ok((
+            (
+                section.to_ascii_lowercase(),
+                Some(subsection.to_ascii_lowercase()),
+            ),
+            rest,
+        )),
+        None => Ok(((name.trim().to_ascii_lowercase(), None), rest)),
+    }
+}
+
+/// removes quotes and comments from a value and processes its escape sequences
+fn parse_value(raw: &str) -> Result<String> {
+    let mut value = String::new();
+    let mut quoted = false;
+    // whitespace is only kept between words, not at the end of the value
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                quoted = !quoted;
+            }
+            '#' | ';' if !quoted => break,
+            c if c.is_whitespace() && !quoted => pending_space.push(c),
+            '\\' => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(c) => bail!("invalid escape sequence in config value: \\{}", c),
+                    None => {}
+                }
+            }
+            c => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                value.push(c);
+            }
+        }
+    }
+
+    if quoted {
+        bail!("unterminated quote in config value: {}", raw);
+    }
+    Ok(value)
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    match needs_quotes {
+        true => format!("\"{}\"", escaped),
+        false => escaped,
+    }
+}
+
+fn section_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]", section),
+    }
+}
+
+/// splits a variable name into its lowercased section, its subsection and its lowercased key
+fn split_name(name: &str) -> Result<(String, Option<String>, String)> {
+    let (section, rest) = name
+        .split_once('.')
+        .ok_or(anyhow!("invalid config variable name: {}", name))?;
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection.to_string()), key),
+        None => (None, rest),
+    };
+
+    Ok((
+        section.to_ascii_lowercase(),
+        subsection,
+        key.to_ascii_lowercase(),
+    ))
+}
+
+/// splits a section name into its lowercased section and its subsection, e.g. branch.topic
+fn split_section(name: &str) -> (String, Option<String>) {
+    match name.split_once('.') {
+        Some((section, subsection)) => (section.to_ascii_lowercase(), Some(subsection.to_string())),
+        None => (name.to_ascii_lowercase(), None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_config, parse_size, Config};
+
+    #[test]
+    fn test_parse_config() {
+        let content = "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = https://example.com/r.git\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n[user]\n\tname = \"A U\" Thor # comment\n\tsigned\n";
+        let config = Config {
+            entries: parse_config(content).unwrap(),
+        };
+
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/r.git")
+        );
+        assert_eq!(
+            config.get("Remote.origin.URL"),
+            Some("https://example.com/r.git")
+        );
+        assert_eq!(config.get("user.name"), Some("A U Thor"));
+        assert_eq!(config.get_bool("user.signed").unwrap(), Some(true));
+        assert_eq!(config.subsections("remote"), vec!["origin"]);
+        assert_eq!(config.get("remote.other.url"), None);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("96m").unwrap(), 96 << 20);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert!(parse_size("12x").is_err());
+        assert!(parse_size("k").is_err());
+    }
+}
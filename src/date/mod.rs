@@ -0,0 +1,404 @@
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+};
+
+/// date styles accepted by --date, matching git's names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DateStyle {
+    Default,
+    Relative,
+    Iso,
+    IsoStrict,
+    Rfc,
+    Short,
+    Raw,
+    Unix,
+    Format(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateFormat {
+    pub style: DateStyle,
+    /// show dates in the local time zone instead of the recorded one
+    pub local: bool,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat {
+            style: DateStyle::Default,
+            local: false,
+        }
+    }
+}
+
+impl TryFrom<&str> for DateFormat {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
+        if let Some(format) = value.strip_prefix("format-local:") {
+            return Ok(DateFormat {
+                style: strftime_style(format)?,
+                local: true,
+            });
+        }
+
+        if let Some(format) = value.strip_prefix("format:") {
+            return Ok(DateFormat {
+                style: strftime_style(format)?,
+                local: false,
+            });
+        }
+
+        let (name, local) = match value.strip_suffix("-local") {
+            Some(name) => (name, true),
+            None => (value, false),
+        };
+
+        let style = match name {
+            "default" => DateStyle::Default,
+            "local" => {
+                return Ok(DateFormat {
+                    style: DateStyle::Default,
+                    local: true,
+                })
+            }
+            "relative" => DateStyle::Relative,
+            "iso" | "iso8601" => DateStyle::Iso,
+            "iso-strict" | "iso8601-strict" => DateStyle::IsoStrict,
+            "rfc" | "rfc2822" => DateStyle::Rfc,
+            "short" => DateStyle::Short,
+            "raw" => DateStyle::Raw,
+            "unix" => DateStyle::Unix,
+            _ => bail!("unknown date format {}", value),
+        };
+
+        Ok(DateFormat { style, local })
+    }
+}
+
+/// the style of a format: or format-local: date, rejecting specifiers chrono doesn't know
+fn strftime_style(format: &str) -> Result<DateStyle> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        bail!("invalid date format: {}", format);
+    }
+    Ok(DateStyle::Format(format.to_string()))
+}
+
+/// parses a git time zone offset such as +0200 or -0530
+pub fn parse_time_zone(time_zone: &str) -> Result<FixedOffset> {
+    let invalid = || anyhow!("invalid time zone {}", time_zone);
+    if time_zone.len() != 5 {
+        return Err(invalid());
+    }
+
+    let (sign, digits) = time_zone.split_at(1);
+    let hours = digits[..2].parse::<i32>().map_err(|_| invalid())?;
+    let minutes = digits[2..].parse::<i32>().map_err(|_| invalid())?;
+    let seconds = hours * 3600 + minutes * 60;
+
+    match sign {
+        "+" => FixedOffset::east_opt(seconds),
+        "-" => FixedOffset::west_opt(seconds),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+/// formats an offset the way git records it in objects (e.g. +0200)
+pub fn format_time_zone(offset: &FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// returns the current time as seconds since the epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// formats a timestamp recorded with the given time zone using the requested format
+pub fn format_date(time: u64, time_zone: &str, format: &DateFormat) -> Result<String> {
+    match &format.style {
+        DateStyle::Raw => return Ok(format!("{} {}", time, time_zone)),
+        DateStyle::Unix => return Ok(time.to_string()),
+        DateStyle::Relative => return Ok(format_relative(time, now())),
+        _ => {}
+    }
+
+    let offset = if format.local {
+        local_offset(time)?
+    } else {
+        parse_time_zone(time_zone)?
+    };
+
+    let date = offset
+        .timestamp_opt(i64::try_from(time)?, 0)
+        .single()
+        .ok_or(anyhow!("invalid timestamp {}", time))?;
+
+    let formatted = match &format.style {
+        DateStyle::Default if format.local => date.format("%a %b %-d %H:%M:%S %Y").to_string(),
+        DateStyle::Default => date.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+        DateStyle::Iso => date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+        DateStyle::IsoStrict if offset.local_minus_utc() == 0 => {
+            date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+        }
+        DateStyle::IsoStrict => date.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        DateStyle::Rfc => date.format("%a, %-d %b %Y %H:%M:%S %z").to_string(),
+        DateStyle::Short => date.format("%Y-%m-%d").to_string(),
+        DateStyle::Format(format) => {
+            let mut formatted = String::new();
+            write!(formatted, "{}", date.format(format))
+                .map_err(|_| anyhow!("invalid date format: {}", format))?;
+            formatted
+        }
+        DateStyle::Raw | DateStyle::Unix | DateStyle::Relative => unreachable!(),
+    };
+
+    Ok(formatted)
+}
+
+/// the local time zone comes from the TZ environment variable if it names a known zone,
+/// otherwise from the system settings
+fn local_offset(time: u64) -> Result<FixedOffset> {
+    let time = i64::try_from(time)?;
+    if let Some(tz) = std::env::var("TZ")
+        .ok()
+        .and_then(|tz| tz.parse::<chrono_tz::Tz>().ok())
+    {
+        let date = tz
+            .timestamp_opt(time, 0)
+            .single()
+            .ok_or(anyhow!("invalid timestamp {}", time))?;
+        return Ok(date.offset().fix());
+    }
+
+    let date = Local
+        .timestamp_opt(time, 0)
+        .single()
+        .ok_or(anyhow!("invalid timestamp {}", time))?;
+    Ok(*date.offset())
+}
+
+/// formats the distance between two timestamps like git's relative dates (e.g. "3 days ago")
+pub fn format_relative(time: u64, now: u64) -> String {
+    if time > now {
+        return String::from("in the future");
+    }
+
+    let plural = |n: u64, unit: &str| {
+        if n == 1 {
+            format!("{} {}", n, unit)
+        } else {
+            format!("{} {}s", n, unit)
+        }
+    };
+
+    let diff = now - time;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+
+    let minutes = (diff + 30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", plural(minutes, "minute"));
+    }
+
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", plural(hours, "hour"));
+    }
+
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", plural(days, "day"));
+    }
+
+    if days < 70 {
+        return format!("{} ago", plural((days + 3) / 7, "week"));
+    }
+
+    if days < 365 {
+        return format!("{} ago", plural((days + 15) / 30, "month"));
+    }
+
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months == 0 {
+            return format!("{} ago", plural(years, "year"));
+        }
+        return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+    }
+
+    format!("{} ago", plural((days + 183) / 365, "year"))
+}
+
+/// parses the dates accepted by --since and --until.
+///
+/// supported forms are unix timestamps (`@1700000000`), ISO 8601 and RFC 2822 dates,
+/// `YYYY-MM-DD[ HH:MM[:SS]]` in the local time zone (a missing time means midnight),
+/// `now`, `today`, `yesterday` and relative dates such as `2 weeks ago` or `3.days.ago`.
+pub fn parse_date(value: &str) -> Result<u64> {
+    parse_date_relative_to(value, now())
+}
+
+fn parse_date_relative_to(value: &str, now: u64) -> Result<u64> {
+    let value = value.trim();
+    let invalid = || anyhow!("invalid date {}", value);
+
+    if let Some(timestamp) = value.strip_prefix('@') {
+        return timestamp.parse::<u64>().map_err(|_| invalid());
+    }
+
+    match value {
+        "now" | "today" => return Ok(now),
+        "yesterday" => return Ok(now.saturating_sub(86400)),
+        _ => {}
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return to_timestamp(date.timestamp());
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return to_timestamp(date.timestamp());
+    }
+
+    if let Ok(date) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z") {
+        return to_timestamp(date.timestamp());
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return local_timestamp(date);
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return local_timestamp(date.and_time(NaiveTime::MIN));
+    }
+
+    // relative dates: "<n> <unit>[s] [ago]", words may also be separated by dots
+    let words = value
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>();
+    if let [count, unit, rest @ ..] = words.as_slice() {
+        if !rest.is_empty() && rest != ["ago"] {
+            return Err(invalid());
+        }
+
+        let count = count.parse::<i64>().map_err(|_| invalid())?;
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        let duration = match unit {
+            "second" | "sec" => Duration::seconds(count),
+            "minute" | "min" => Duration::minutes(count),
+            "hour" => Duration::hours(count),
+            "day" => Duration::days(count),
+            "week" => Duration::weeks(count),
+            "month" => Duration::days(count * 30),
+            "year" => Duration::days(count * 365),
+            _ => return Err(invalid()),
+        };
+
+        return to_timestamp(i64::try_from(now)? - duration.num_seconds());
+    }
+
+    if value.len() >= 9 && value.chars().all(|c| c.is_ascii_digit()) {
+        return value.parse::<u64>().map_err(|_| invalid());
+    }
+
+    Err(invalid())
+}
+
+fn local_timestamp(date: NaiveDateTime) -> Result<u64> {
+    let date = Local
+        .from_local_datetime(&date)
+        .earliest()
+        .ok_or(anyhow!("invalid local date {}", date))?;
+    to_timestamp(date.timestamp())
+}
+
+fn to_timestamp(timestamp: i64) -> Result<u64> {
+    u64::try_from(timestamp).map_err(|_| anyhow!("dates before 1970 are not supported"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_date, format_relative, parse_date_relative_to, DateFormat, DateStyle};
+
+    #[test]
+    fn test_format_date() {
+        let time = 1112911993;
+        let tz = "-0700";
+
+        let format = |style: &str| format_date(time, tz, &DateFormat::try_from(style).unwrap());
+
+        assert_eq!(format("default").unwrap(), "Thu Apr 7 15:13:13 2005 -0700");
+        assert_eq!(format("iso").unwrap(), "2005-04-07 15:13:13 -0700");
+        assert_eq!(format("iso-strict").unwrap(), "2005-04-07T15:13:13-07:00");
+        assert_eq!(format("rfc").unwrap(), "Thu, 7 Apr 2005 15:13:13 -0700");
+        assert_eq!(format("short").unwrap(), "2005-04-07");
+        assert_eq!(format("raw").unwrap(), "1112911993 -0700");
+        assert_eq!(format("unix").unwrap(), "1112911993");
+        assert_eq!(format("format:%d/%m/%Y").unwrap(), "07/04/2005");
+        assert!(DateFormat::try_from("format:%Y %Q").is_err());
+        assert_eq!(
+            DateFormat::try_from("iso-local").unwrap(),
+            DateFormat {
+                style: DateStyle::Iso,
+                local: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_format_relative() {
+        let now = 1_000_000_000;
+        assert_eq!(format_relative(now - 1, now), "1 second ago");
+        assert_eq!(format_relative(now - 60 * 10, now), "10 minutes ago");
+        assert_eq!(format_relative(now - 3600 * 5, now), "5 hours ago");
+        assert_eq!(format_relative(now - 86400 * 3, now), "3 days ago");
+        assert_eq!(format_relative(now - 86400 * 21, now), "3 weeks ago");
+        assert_eq!(
+            format_relative(now - 86400 * 400, now),
+            "1 year, 1 month ago"
+        );
+        assert_eq!(format_relative(now + 10, now), "in the future");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let now = 1_000_000_000;
+        assert_eq!(parse_date_relative_to("@1234", now).unwrap(), 1234);
+        assert_eq!(
+            parse_date_relative_to("2 weeks ago", now).unwrap(),
+            now - 14 * 86400
+        );
+        assert_eq!(
+            parse_date_relative_to("3.days.ago", now).unwrap(),
+            now - 3 * 86400
+        );
+        assert_eq!(
+            parse_date_relative_to("2005-04-07T22:13:13Z", now).unwrap(),
+            1112911993
+        );
+        assert_eq!(
+            parse_date_relative_to("2005-04-07 15:13:13 -0700", now).unwrap(),
+            1112911993
+        );
+        assert!(parse_date_relative_to("not a date", now).is_err());
+    }
+}
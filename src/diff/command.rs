@@ -0,0 +1,391 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    index::{index_mtime, read_index, Index, IndexEntry},
+    merge_base::CommitGraph,
+    objects::{hash::Hash, tree::EntryMode, Object, ObjectKind},
+    rev_parse::{peel, resolve_commit, resolve_revision},
+    worktree::read_worktree_file,
+};
+
+use super::{
+    diff_files, list_tree_files, make_patch, matches_paths, read_content, stat_width, write_numstat,
+    write_patch, write_stat, Change, ChangeStatus, ContentSource, DiffFile, FilePatch, FileStat,
+    PatchOptions, TreeDiffOptions,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// revisions to compare (none, one, two, A..B or A...B), possibly followed by paths
+    pub args: Vec<String>,
+    pub paths: Vec<String>,
+    /// compare the index with a commit (HEAD by default) instead of the working tree
+    pub cached: bool,
+    /// show the patch. implied when neither stat nor numstat is set.
+    pub patch: bool,
+    pub stat: bool,
+    pub numstat: bool,
+    pub find_renames: bool,
+    /// minimum similarity of renames, out of MAX_SCORE
+    pub rename_score: u32,
+    pub format: PatchOptions,
+}
+
+/// one side of a comparison
+enum Side {
+    /// the files of a tree, or no files
+    Tree(Option<Hash>),
+    Index,
+    Worktree,
+}
+
+/// shows changes between the working tree, the index, commits and blobs
+pub fn diff(options: DiffOptions) -> Result<()> {
+    let mut paths = options.paths.clone();
+    let mut revisions = Vec::new();
+    for (i, arg) in options.args.iter().enumerate() {
+        if resolve_arg(arg).is_err() && fs::symlink_metadata(arg).is_ok() {
+            paths.extend(options.args[i..].iter().cloned());
+            break;
+        }
+        revisions.push(arg.as_str());
+    }
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    let (old, new) = match revisions.as_slice() {
+        [] if options.cached => (Side::Tree(head_tree()?), Side::Index),
+        [] => (Side::Index, Side::Worktree),
+        [range] if range.contains("...") => {
+            let (left, right) = range.split_once("...").expect("range contains ...");
+            let left = resolve_commit(or_head(left))?;
+            let right = resolve_commit(or_head(right))?;
+            let base = CommitGraph::new()
+                .merge_bases(&left, std::slice::from_ref(&right))?
+                .into_iter()
+                .next()
+                .ok_or(anyhow!("{} has no merge base", range))?;
+            (
+                Side::Tree(Some(tree(base)?)),
+                Side::Tree(Some(tree(right)?)),
+            )
+        }
+        [range] if range.contains("..") => {
+            let (left, right) = range.split_once("..").expect("range contains ..");
+            let left = resolve_revision(or_head(left))?;
+            let right = resolve_revision(or_head(right))?;
+            (
+                Side::Tree(Some(tree(left)?)),
+                Side::Tree(Some(tree(right)?)),
+            )
+        }
+        [rev] if options.cached => (Side::Tree(Some(tree(resolve_arg(rev)?)?)), Side::Index),
+        [rev] => (Side::Tree(Some(tree(resolve_arg(rev)?)?)), Side::Worktree),
+        [old, new] => {
+            let old_hash = peel(resolve_arg(old)?, None)?;
+            let new_hash = peel(resolve_arg(new)?, None)?;
+            if is_blob(&old_hash)? && is_blob(&new_hash)? {
+                let change = Change {
+                    status: ChangeStatus::Modified,
+                    old: Some(blob_file(old, old_hash)),
+                    new: Some(blob_file(new, new_hash)),
+                    score: None,
+                };
+                let items = vec![Item::Change(change)];
+                return write_items(&mut stdout, items, ContentSource::Objects, &options);
+            }
+            (
+                Side::Tree(Some(tree(old_hash)?)),
+                Side::Tree(Some(tree(new_hash)?)),
+            )
+        }
+        _ => bail!("diff takes at most two revisions"),
+    };
+
+    let tree_options = TreeDiffOptions {
+        recursive: true,
+        paths,
+        find_renames: options.find_renames,
+        rename_score: options.rename_score,
+        ..Default::default()
+    };
+
+    let index = read_index()?;
+    let new_source = match new {
+        Side::Worktree => ContentSource::Worktree,
+        _ => ContentSource::Objects,
+    };
+
+    // paths with conflicts are reported rather than compared when one side is the index
+    // or the working tree. the working tree is compared with our side of the merge.
+    let mut unmerged = Vec::new();
+    if !matches!((&old, &new), (Side::Tree(_), Side::Tree(_))) {
+        unmerged = unmerged_entries(&index, &tree_options);
+    }
+    let merged = |file: &DiffFile| !unmerged.iter().any(|(path, _)| *path == file.path);
+    let old_files = list_side(&old, &index, &tree_options)?;
+    let new_files = list_side(&new, &index, &tree_options)?;
+    let old_files = old_files.into_iter().filter(merged).collect();
+    let new_files = new_files.into_iter().filter(merged).collect();
+
+    let mut items: Vec<Item> = diff_files(old_files, new_files, &tree_options, new_source)?
+        .into_iter()
+        .map(Item::Change)
+        .collect();
+    match (&old, &new) {
+        (Side::Index, Side::Worktree) => {
+            for (path, ours) in unmerged {
+                items.push(Item::Unmerged(path));
+                let ours = match ours {
+                    Some(ours) => ours,
+                    None => continue,
+                };
+                let file = match worktree_file(ours, None)? {
+                    Some(file) => file,
+                    None => continue,
+                };
+                let ours = DiffFile {
+                    path: ours.path.clone(),
+                    mode: EntryMode::try_from(ours.mode)?,
+                    hash: ours.hash.clone(),
+                };
+                if file != ours {
+                    items.push(Item::Change(Change {
+                        status: ChangeStatus::Modified,
+                        old: Some(ours),
+                        new: Some(file),
+                        score: None,
+                    }));
+                }
+            }
+        }
+        (_, Side::Index) => {
+            items.extend(unmerged.into_iter().map(|(path, _)| Item::Unmerged(path)))
+        }
+        _ => {}
+    }
+    items.sort_by(|a, b| a.path().cmp(b.path()));
+
+    write_items(&mut stdout, items, new_source, &options)
+}
+
+/// an entry of the output
+enum Item {
+    Change(Change),
+    /// a path with conflicting entries in the index
+    Unmerged(String),
+}
+
+impl Item {
+    fn path(&self) -> &str {
+        match self {
+            Item::Change(change) => change.path(),
+            Item::Unmerged(path) => path,
+        }
+    }
+}
+
+/// prints the requested outputs: the stat, the numstat and the patch
+fn write_items<W: Write>(
+    out: &mut W,
+    items: Vec<Item>,
+    new_source: ContentSource,
+    options: &DiffOptions,
+) -> Result<()> {
+    let mut patches = Vec::new();
+    let mut stats = Vec::new();
+    for item in items {
+        let change = match item {
+            Item::Change(change) => change,
+            Item::Unmerged(path) => {
+                stats.push(FileStat::unmerged(&path));
+                patches.push(Err(path));
+                continue;
+            }
+        };
+
+        let old = match &change.old {
+            Some(old) => read_content(old, ContentSource::Objects)?,
+            None => Vec::new(),
+        };
+        let new = match &change.new {
+            Some(new) => read_content(new, new_source)?,
+            None => Vec::new(),
+        };
+
+        let patch = make_patch(change, &old, &new, &options.format);
+        stats.push(FileStat::new(&patch, old.len(), new.len()));
+        patches.push(Ok((patch, old, new)));
+    }
+
+    if options.numstat {
+        write_numstat(out, &stats)?;
+    }
+    if options.stat {
+        write_stat(out, &stats, options.format.color, stat_width())?;
+    }
+    if (options.stat || options.numstat) && !options.patch {
+        return Ok(());
+    }
+    if (options.stat || options.numstat) && !patches.is_empty() {
+        writeln!(out)?;
+    }
+
+    for patch in patches {
+        let (patch, old, new) = match patch {
+            Ok(patch) => patch,
+            Err(path) => {
+                writeln!(out, "* Unmerged path {}", path)?;
+                continue;
+            }
+        };
+
+        // a file replaced by a symbolic link (or the reverse) is shown as a deletion and an addition
+        if patch.change.status != ChangeStatus::TypeChanged {
+            write_patch(out, &patch, &options.format)?;
+            continue;
+        }
+
+        let FilePatch { change, .. } = patch;
+        let deletion = Change {
+            status: ChangeStatus::Deleted,
+            new: None,
+            ..change.clone()
+        };
+        let addition = Change {
+            status: ChangeStatus::Added,
+            old: None,
+            ..change
+        };
+        write_patch(
+            out,
+            &make_patch(deletion, &old, &[], &options.format),
+            &options.format,
+        )?;
+        write_patch(
+            out,
+            &make_patch(addition, &[], &new, &options.format),
+            &options.format,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn list_side(side: &Side, index: &Index, options: &TreeDiffOptions) -> Result<Vec<DiffFile>> {
+    match side {
+        Side::Tree(tree) => list_tree_files(tree.as_ref(), options),
+        Side::Index => Ok(index_files(index)),
+        Side::Worktree => worktree_files(index),
+    }
+}
+
+/// the merged entries of the index. submodules and paths only marked to be added are skipped.
+fn index_files(index: &Index) -> Vec<DiffFile> {
+    index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() == 0 && !entry.intent_to_add())
+        .filter_map(|entry| {
+            let mode = EntryMode::try_from(entry.mode).ok()?;
+            Some(DiffFile {
+                path: entry.path.clone(),
+                mode,
+                hash: entry.hash.clone(),
+            })
+        })
+        .collect()
+}
+
+/// the files of the working tree tracked by the index
+fn worktree_files(index: &Index) -> Result<Vec<DiffFile>> {
+    let index_mtime = index_mtime()?;
+    let mut files = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        if let Some(file) = worktree_file(entry, index_mtime)? {
+            files.push(file);
+        }
+    }
+
+    Ok(files)
+}
+
+/// reads the working tree file of an index entry, if it exists
+fn worktree_file(entry: &IndexEntry, index_mtime: Option<(u32, u32)>) -> Result<Option<DiffFile>> {
+    if EntryMode::try_from(entry.mode).is_err() {
+        return Ok(None);
+    }
+
+    Ok(read_worktree_file(entry, index_mtime)?.map(|file| DiffFile {
+        path: entry.path.clone(),
+        mode: file.mode,
+        hash: file.hash,
+    }))
+}
+
+/// the paths with unmerged entries, along with our entry (stage 2) if there is one
+fn unmerged_entries<'a>(
+    index: &'a Index,
+    options: &TreeDiffOptions,
+) -> Vec<(String, Option<&'a IndexEntry>)> {
+    let mut unmerged: Vec<(String, Option<&IndexEntry>)> = Vec::new();
+    for entry in &index.entries {
+        if entry.stage() == 0 || !matches_paths(&entry.path, false, &options.paths) {
+            continue;
+        }
+        if unmerged.last().is_none_or(|(path, _)| *path != entry.path) {
+            unmerged.push((entry.path.clone(), None));
+        }
+        if entry.stage() == 2 {
+            unmerged.last_mut().expect("an entry was just pushed").1 = Some(entry);
+        }
+    }
+
+    unmerged
+}
+
+fn head_tree() -> Result<Option<Hash>> {
+    match resolve_revision("HEAD") {
+        Ok(head) => Ok(Some(tree(head)?)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn tree(hash: Hash) -> Result<Hash> {
+    peel(hash, Some(ObjectKind::Tree))
+}
+
+fn is_blob(hash: &Hash) -> Result<bool> {
+    Ok(Object::read_from_hash(hash.to_hex())?.kind == ObjectKind::Blob)
+}
+
+/// a blob named on the command line, labelled by its path when named as rev:path
+fn blob_file(name: &str, hash: Hash) -> DiffFile {
+    DiffFile {
+        path: name.rsplit(':').next().unwrap_or(name).to_string(),
+        mode: EntryMode::RegularFile,
+        hash,
+    }
+}
+
+fn resolve_arg(arg: &str) -> Result<Hash> {
+    match arg.split_once("..") {
+        Some((left, right)) => {
+            resolve_revision(or_head(left.trim_end_matches('.')))?;
+            resolve_revision(or_head(right.trim_start_matches('.')))
+        }
+        None => resolve_revision(arg),
+    }
+}
+
+fn or_head(rev: &str) -> &str {
+    match rev {
+        "" => "HEAD",
+        rev => rev,
+    }
+}
@@ -0,0 +1,342 @@
+use super::lines::is_space;
+
+/// indentation is capped at this many columns
+const MAX_INDENT: i32 = 200;
+/// blank lines are counted up to this many
+const MAX_BLANKS: i32 = 20;
+/// changes are slid at most this many lines by the indent heuristic
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+
+// penalties of the indent heuristic, tuned by git on a corpus of human-made diffs
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+fn changed(rchg: &[bool], line: usize) -> bool {
+    rchg.get(line).copied().unwrap_or(false)
+}
+
+/// a run of changed lines, possibly empty
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    /// first unchanged line after the group
+    end: usize,
+}
+
+impl Group {
+    fn first(rchg: &[bool]) -> Group {
+        let mut end = 0;
+        while changed(rchg, end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next(&mut self, rchg: &[bool]) -> bool {
+        if self.end == rchg.len() {
+            return false;
+        }
+
+        self.start = self.end + 1;
+        self.end = self.start;
+        while changed(rchg, self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, rchg: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed(rchg, self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, ids: &[usize], rchg: &mut [bool]) -> bool {
+        if self.end < ids.len() && ids[self.start] == ids[self.end] {
+            rchg[self.start] = false;
+            rchg[self.end] = true;
+            self.start += 1;
+            self.end += 1;
+            while changed(rchg, self.end) {
+                self.end += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    fn slide_up(&mut self, ids: &[usize], rchg: &mut [bool]) -> bool {
+        if self.start > 0 && ids[self.start - 1] == ids[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            rchg[self.start] = true;
+            rchg[self.end] = false;
+            while self.start > 0 && changed(rchg, self.start - 1) {
+                self.start -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+/// moves groups of changed lines that could be placed elsewhere (e.g. an added line
+/// repeating the line before it) to the most readable position: aligned with a change of
+/// the other file, or where the indentation suggests the change starts and ends.
+/// the groups of the other file are walked in sync.
+pub(super) fn change_compact(
+    ids: &[usize],
+    lines: &[&[u8]],
+    rchg: &mut [bool],
+    other_rchg: &[bool],
+    indent_heuristic: bool,
+) {
+    let mut g = Group::first(rchg);
+    let mut go = Group::first(other_rchg);
+
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let group_size = g.end - g.start;
+                end_matching_other = None;
+
+                // shift the group up as much as possible, merging it with the groups it meets
+                while g.slide_up(ids, rchg) {
+                    assert!(go.previous(other_rchg), "group sync broken sliding up");
+                }
+
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+
+                // then down as much as possible
+                while g.slide_down(ids, rchg) {
+                    assert!(go.next(other_rchg), "group sync broken sliding down");
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+
+                if group_size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // the group can't be shifted
+            } else if end_matching_other.is_some() {
+                // line up with the last group of the other file it can align with
+                while go.end == go.start {
+                    assert!(g.slide_up(ids, rchg), "match disappeared");
+                    assert!(
+                        go.previous(other_rchg),
+                        "group sync broken sliding to match"
+                    );
+                }
+            } else if indent_heuristic {
+                let group_size = g.end - g.start;
+                let mut shift = earliest_end;
+                if g.end > group_size + 1 && g.end - group_size - 1 > shift {
+                    shift = g.end - group_size - 1;
+                }
+                if g.end > INDENT_HEURISTIC_MAX_SLIDING
+                    && g.end - INDENT_HEURISTIC_MAX_SLIDING > shift
+                {
+                    shift = g.end - INDENT_HEURISTIC_MAX_SLIDING;
+                }
+
+                let mut best: Option<(usize, Score)> = None;
+                for shift in shift..=g.end {
+                    let mut score = Score::default();
+                    score.add_split(&measure_split(lines, shift));
+                    score.add_split(&measure_split(lines, shift - group_size));
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best_score)| score.cmp(best_score) <= 0)
+                    {
+                        best = Some((shift, score));
+                    }
+                }
+
+                let best_shift = best.map_or(g.end, |(shift, _)| shift);
+                while g.end > best_shift {
+                    assert!(g.slide_up(ids, rchg), "best shift unreached");
+                    assert!(
+                        go.previous(other_rchg),
+                        "group sync broken sliding to blank line"
+                    );
+                }
+            }
+        }
+
+        if !g.next(rchg) {
+            break;
+        }
+        assert!(
+            go.next(other_rchg),
+            "group sync broken moving to next group"
+        );
+    }
+}
+
+/// what surrounds a possible boundary of a change, right before the given line
+struct SplitMeasurement {
+    end_of_file: bool,
+    /// indentation of the line after the split, -1 for a blank line
+    indent: i32,
+    /// blank lines before the split
+    pre_blank: i32,
+    /// indentation of the closest non-blank line before the split
+    pre_indent: i32,
+    /// blank lines after the line after the split
+    post_blank: i32,
+    /// indentation of the closest non-blank line after the line after the split
+    post_indent: i32,
+}
+
+#[derive(Default)]
+struct Score {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl Score {
+    fn add_split(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 {
+            // nothing to compare with
+        } else if indent > m.pre_indent {
+            // the line is indented more than its predecessor
+            self.penalty += if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            };
+        } else if indent == m.pre_indent {
+            // same indentation level
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            // indented less than its predecessor and more than its successor:
+            // probably the start of a block
+            self.penalty += if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            };
+        } else {
+            // probably the end of a block
+            self.penalty += if any_blanks {
+                RELATIVE_DEDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_DEDENT_PENALTY
+            };
+        }
+    }
+
+    /// negative if this score is better than the other
+    fn cmp(&self, other: &Score) -> i32 {
+        let cmp_indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        INDENT_WEIGHT * cmp_indents + (self.penalty - other.penalty)
+    }
+}
+
+fn measure_split(lines: &[&[u8]], split: usize) -> SplitMeasurement {
+    let (end_of_file, indent) = match lines.get(split) {
+        Some(line) => (false, get_indent(line)),
+        None => (true, -1),
+    };
+
+    let (mut pre_blank, mut pre_indent) = (0, -1);
+    for line in lines[..split.min(lines.len())].iter().rev() {
+        pre_indent = get_indent(line);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+    }
+
+    let (mut post_blank, mut post_indent) = (0, -1);
+    for line in lines.iter().skip(split + 1) {
+        post_indent = get_indent(line);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+    }
+
+    SplitMeasurement {
+        end_of_file,
+        indent,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+/// the indentation of the line in columns, tabs stopping every 8 columns.
+/// -1 for lines with only whitespace.
+fn get_indent(line: &[u8]) -> i32 {
+    let mut indent = 0;
+    for &b in line {
+        if !is_space(b) {
+            return indent;
+        }
+        if b == b' ' {
+            indent += 1;
+        } else if b == b'\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+
+    -1
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use super::myers::myers;
+
+/// lines occurring more often than this in the first file aren't used to split the diff
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// the occurrences of a line in the first file
+struct Record {
+    /// first occurrence, counted from 1
+    ptr: usize,
+    count: usize,
+}
+
+/// the longest common region found, lines counted from 1. zero means no region.
+#[derive(Default)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+/// compares two sequences of line ids by splitting them at the longest common region of
+/// lines that are rare in the first file, like patience diff extended to lines that are not
+/// unique. returns the changed flags of both sequences.
+pub(super) fn histogram(ids1: &[usize], ids2: &[usize]) -> (Vec<bool>, Vec<bool>) {
+    let mut diff = Histogram {
+        ids1,
+        ids2,
+        rchg1: vec![false; ids1.len()],
+        rchg2: vec![false; ids2.len()],
+    };
+    diff.diff(1, ids1.len(), 1, ids2.len());
+    (diff.rchg1, diff.rchg2)
+}
+
+struct Histogram<'a> {
+    ids1: &'a [usize],
+    ids2: &'a [usize],
+    rchg1: Vec<bool>,
+    rchg2: Vec<bool>,
+}
+
+/// the occurrences of the lines of a range of the first file
+struct Index {
+    records: HashMap<usize, Record>,
+    /// next occurrence of the same line, by line (relative to the start of the range)
+    next_ptrs: Vec<usize>,
+    /// smallest occurrence count of the best region found so far
+    count: usize,
+    has_common: bool,
+}
+
+impl Histogram<'_> {
+    /// compares lines from line1 and line2 (counted from 1)
+    fn diff(&mut self, mut line1: usize, mut count1: usize, mut line2: usize, mut count2: usize) {
+        loop {
+            if count1 == 0 {
+                self.rchg2[line2 - 1..line2 - 1 + count2].fill(true);
+                return;
+            }
+            if count2 == 0 {
+                self.rchg1[line1 - 1..line1 - 1 + count1].fill(true);
+                return;
+            }
+
+            let lcs = match self.find_lcs(line1, count1, line2, count2) {
+                Some(lcs) => lcs,
+                None => {
+                    let (rchg1, rchg2) = myers(
+                        &self.ids1[line1 - 1..line1 - 1 + count1],
+                        &self.ids2[line2 - 1..line2 - 1 + count2],
+                        false,
+                    );
+                    self.rchg1[line1 - 1..line1 - 1 + count1].copy_from_slice(&rchg1);
+                    self.rchg2[line2 - 1..line2 - 1 + count2].copy_from_slice(&rchg2);
+                    return;
+                }
+            };
+
+            if lcs.begin1 == 0 && lcs.begin2 == 0 {
+                self.rchg1[line1 - 1..line1 - 1 + count1].fill(true);
+                self.rchg2[line2 - 1..line2 - 1 + count2].fill(true);
+                return;
+            }
+
+            self.diff(line1, lcs.begin1 - line1, line2, lcs.begin2 - line2);
+
+            let (last1, last2) = (line1 + count1 - 1, line2 + count2 - 1);
+            count1 = last1 - lcs.end1;
+            line1 = lcs.end1 + 1;
+            count2 = last2 - lcs.end2;
+            line2 = lcs.end2 + 1;
+        }
+    }
+
+    /// finds the longest common region of lines with the fewest occurrences in the first file.
+    /// returns None when all the common lines occur too often, an empty region when there is
+    /// no common line.
+    fn find_lcs(&self, line1: usize, count1: usize, line2: usize, count2: usize) -> Option<Region> {
+        let mut index = Index {
+            records: HashMap::new(),
+            next_ptrs: vec![0; count1],
+            count: MAX_CHAIN_LENGTH + 1,
+            has_common: false,
+        };
+
+        // scanning backwards leaves each record pointing at the first occurrence
+        for ptr in (line1..line1 + count1).rev() {
+            let id = self.ids1[ptr - 1];
+            match index.records.get_mut(&id) {
+                Some(record) => {
+                    index.next_ptrs[ptr - line1] = record.ptr;
+                    record.ptr = ptr;
+                    record.count += 1;
+                }
+                None => {
+                    index.records.insert(id, Record { ptr, count: 1 });
+                }
+            }
+        }
+
+        let mut lcs = Region::default();
+        let mut b_ptr = line2;
+        while b_ptr < line2 + count2 {
+            b_ptr = self.try_lcs(&mut index, &mut lcs, b_ptr, line1, count1, line2, count2);
+        }
+
+        if index.has_common && MAX_CHAIN_LENGTH < index.count {
+            return None;
+        }
+        Some(lcs)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn try_lcs(
+        &self,
+        index: &mut Index,
+        lcs: &mut Region,
+        b_ptr: usize,
+        line1: usize,
+        count1: usize,
+        line2: usize,
+        count2: usize,
+    ) -> usize {
+        let mut b_next = b_ptr + 1;
+        let (last1, last2) = (line1 + count1 - 1, line2 + count2 - 1);
+        let same = |a: usize, b: usize| self.ids1[a - 1] == self.ids2[b - 1];
+        let occurrences = |index: &Index, ptr: usize| index.records[&self.ids1[ptr - 1]].count;
+
+        let (first, count) = match index.records.get(&self.ids2[b_ptr - 1]) {
+            Some(record) => (record.ptr, record.count),
+            None => return b_next,
+        };
+        if count > index.count {
+            index.has_common = true;
+            return b_next;
+        }
+
+        index.has_common = true;
+        let mut a_ptr = first;
+        loop {
+            let mut np = index.next_ptrs[a_ptr - line1];
+            let (mut as_, mut bs, mut ae, mut be) = (a_ptr, b_ptr, a_ptr, b_ptr);
+            let mut rc = count;
+
+            while line1 < as_ && line2 < bs && same(as_ - 1, bs - 1) {
+                as_ -= 1;
+                bs -= 1;
+                if 1 < rc {
+                    rc = rc.min(occurrences(index, as_));
+                }
+            }
+            while ae < last1 && be < last2 && same(ae + 1, be + 1) {
+                ae += 1;
+                be += 1;
+                if 1 < rc {
+                    rc = rc.min(occurrences(index, ae));
+                }
+            }
+
+            if b_next <= be {
+                b_next = be + 1;
+            }
+            if lcs.end1 - lcs.begin1 < ae - as_ || rc < index.count {
+                *lcs = Region {
+                    begin1: as_,
+                    end1: ae,
+                    begin2: bs,
+                    end2: be,
+                };
+                index.count = rc;
+            }
+
+            if np == 0 {
+                break;
+            }
+            while np <= ae {
+                np = index.next_ptrs[np - line1];
+                if np == 0 {
+                    break;
+                }
+            }
+            if np == 0 {
+                break;
+            }
+
+            a_ptr = np;
+        }
+
+        b_next
+    }
+}
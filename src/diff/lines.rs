@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+
+use super::{compact::change_compact, histogram::histogram, myers::myers, patience::patience};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    /// myers without the heuristics that trade a minimal diff for speed
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+impl TryFrom<&str> for DiffAlgorithm {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
+        let algorithm = match value {
+            "myers" | "default" => DiffAlgorithm::Myers,
+            "minimal" => DiffAlgorithm::Minimal,
+            "patience" => DiffAlgorithm::Patience,
+            "histogram" => DiffAlgorithm::Histogram,
+            _ => bail!("invalid diff algorithm: {}", value),
+        };
+
+        Ok(algorithm)
+    }
+}
+
+/// whitespace differences ignored when comparing lines
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceOptions {
+    /// -w: ignore all whitespace
+    pub ignore_all_space: bool,
+    /// -b: ignore changes in the amount of whitespace
+    pub ignore_space_change: bool,
+    pub ignore_space_at_eol: bool,
+    pub ignore_cr_at_eol: bool,
+    /// hide changes whose lines are all blank
+    pub ignore_blank_lines: bool,
+}
+
+impl WhitespaceOptions {
+    fn changes_matching(&self) -> bool {
+        self.ignore_all_space
+            || self.ignore_space_change
+            || self.ignore_space_at_eol
+            || self.ignore_cr_at_eol
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LineDiffOptions {
+    pub algorithm: DiffAlgorithm,
+    pub whitespace: WhitespaceOptions,
+    /// slide changes to the boundaries that look best given the indentation around them
+    pub indent_heuristic: bool,
+}
+
+impl Default for LineDiffOptions {
+    fn default() -> Self {
+        LineDiffOptions {
+            algorithm: DiffAlgorithm::default(),
+            whitespace: WhitespaceOptions::default(),
+            indent_heuristic: true,
+        }
+    }
+}
+
+/// a run of changed lines. lines are counted from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    /// all the lines of the edit are blank, and --ignore-blank-lines is set
+    pub ignorable: bool,
+}
+
+/// the lines of both sides of a diff, each line being mapped to an id
+/// shared by all the lines it matches
+struct Files<'a> {
+    lines1: Vec<&'a [u8]>,
+    lines2: Vec<&'a [u8]>,
+    ids1: Vec<usize>,
+    ids2: Vec<usize>,
+}
+
+impl<'a> Files<'a> {
+    fn new(old: &'a [u8], new: &'a [u8], whitespace: &WhitespaceOptions) -> Files<'a> {
+        let lines1 = split_lines(old);
+        let lines2 = split_lines(new);
+
+        let mut classes: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut classify = |line: &[u8]| {
+            let next = classes.len();
+            *classes.entry(line_key(line, whitespace)).or_insert(next)
+        };
+        let ids1 = lines1.iter().map(|line| classify(line)).collect();
+        let ids2 = lines2.iter().map(|line| classify(line)).collect();
+
+        Files {
+            lines1,
+            lines2,
+            ids1,
+            ids2,
+        }
+    }
+}
+
+/// splits content into lines, keeping the line terminators
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// compares the lines of two files, returning the changed line runs in order
+pub fn diff_lines(old: &[u8], new: &[u8], options: &LineDiffOptions) -> Vec<Edit> {
+    let files = Files::new(old, new, &options.whitespace);
+
+    let (mut rchg1, mut rchg2) = match options.algorithm {
+        DiffAlgorithm::Myers => myers(&files.ids1, &files.ids2, false),
+        DiffAlgorithm::Minimal => myers(&files.ids1, &files.ids2, true),
+        DiffAlgorithm::Patience => patience(&files.ids1, &files.ids2),
+        DiffAlgorithm::Histogram => histogram(&files.ids1, &files.ids2),
+    };
+
+    change_compact(
+        &files.ids1,
+        &files.lines1,
+        &mut rchg1,
+        &rchg2,
+        options.indent_heuristic,
+    );
+    change_compact(
+        &files.ids2,
+        &files.lines2,
+        &mut rchg2,
+        &rchg1,
+        options.indent_heuristic,
+    );
+
+    let mut edits = build_script(&rchg1, &rchg2);
+    if options.whitespace.ignore_blank_lines {
+        let blank = |line: &&[u8]| is_blank(line, &options.whitespace);
+        for edit in &mut edits {
+            let old = &files.lines1[edit.old_start..edit.old_start + edit.old_count];
+            let new = &files.lines2[edit.new_start..edit.new_start + edit.new_count];
+            edit.ignorable = old.iter().all(blank) && new.iter().all(blank);
+        }
+    }
+
+    edits
+}
+
+/// collects the runs of changed lines. unchanged lines of both files pair up in order.
+fn build_script(rchg1: &[bool], rchg2: &[bool]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut i1, mut i2) = (0, 0);
+    while i1 < rchg1.len() || i2 < rchg2.len() {
+        let changed1 = rchg1.get(i1).copied().unwrap_or(false);
+        let changed2 = rchg2.get(i2).copied().unwrap_or(false);
+        if !changed1 && !changed2 {
+            i1 += 1;
+            i2 += 1;
+            continue;
+        }
+
+        let (start1, start2) = (i1, i2);
+        while rchg1.get(i1).copied().unwrap_or(false) {
+            i1 += 1;
+        }
+        while rchg2.get(i2).copied().unwrap_or(false) {
+            i2 += 1;
+        }
+
+        edits.push(Edit {
+            old_start: start1,
+            old_count: i1 - start1,
+            new_start: start2,
+            new_count: i2 - start2,
+            ignorable: false,
+        });
+    }
+
+    edits
+}
+
+/// the whitespace characters of git's ctype
+pub(super) fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+fn is_blank(line: &[u8], whitespace: &WhitespaceOptions) -> bool {
+    if !whitespace.changes_matching() {
+        return line.len() <= 1;
+    }
+
+    line.iter().all(|b| is_space(*b))
+}
+
+/// returns the part of the line that is compared, given the ignored whitespace
+fn line_key(line: &[u8], whitespace: &WhitespaceOptions) -> Vec<u8> {
+    if whitespace.ignore_all_space {
+        return line.iter().copied().filter(|b| !is_space(*b)).collect();
+    }
+
+    if whitespace.ignore_space_change {
+        let mut key = Vec::with_capacity(line.len());
+        let mut pending_space = false;
+        for &b in line {
+            if is_space(b) {
+                pending_space = true;
+                continue;
+            }
+            if pending_space {
+                key.push(b' ');
+                pending_space = false;
+            }
+            key.push(b);
+        }
+        return key;
+    }
+
+    if whitespace.ignore_space_at_eol {
+        let end = line
+            .iter()
+            .rposition(|b| !is_space(*b))
+            .map_or(0, |i| i + 1);
+        return line[..end].to_vec();
+    }
+
+    if whitespace.ignore_cr_at_eol {
+        let mut line = line.strip_suffix(b"\n").unwrap_or(line);
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+        return line.to_vec();
+    }
+
+    line.to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff_lines, DiffAlgorithm, Edit, LineDiffOptions, WhitespaceOptions};
+
+    fn edits(old: &str, new: &str, algorithm: DiffAlgorithm) -> Vec<(usize, usize, usize, usize)> {
+        let options = LineDiffOptions {
+            algorithm,
+            ..Default::default()
+        };
+        diff_lines(old.as_bytes(), new.as_bytes(), &options)
+            .into_iter()
+            .map(|edit: Edit| {
+                (
+                    edit.old_start,
+                    edit.old_count,
+                    edit.new_start,
+                    edit.new_count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        for algorithm in [
+            DiffAlgorithm::Myers,
+            DiffAlgorithm::Minimal,
+            DiffAlgorithm::Patience,
+            DiffAlgorithm::Histogram,
+        ] {
+            assert_eq!(edits("a\nb\nc\n", "a\nb\nc\n", algorithm), vec![]);
+            assert_eq!(
+                edits("a\nb\nc\n", "a\nx\nc\n", algorithm),
+                vec![(1, 1, 1, 1)]
+            );
+            assert_eq!(edits("a\nc\n", "a\nb\nc\n", algorithm), vec![(1, 0, 1, 1)]);
+            assert_eq!(edits("", "a\n", algorithm), vec![(0, 0, 0, 1)]);
+            assert_eq!(edits("a\n", "a", algorithm), vec![(0, 1, 0, 1)]);
+        }
+    }
+
+    #[test]
+    fn test_slide_to_indentation() {
+        // the added block is shown as a whole function rather than
+        // starting in the middle of the previous one
+        let old = "fn a() {\n    x\n}\n";
+        let new = "fn a() {\n    x\n}\n\nfn b() {\n    x\n}\n";
+        assert_eq!(edits(old, new, DiffAlgorithm::Myers), vec![(3, 0, 3, 4)]);
+    }
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let whitespace = WhitespaceOptions {
+            ignore_space_change: true,
+            ..Default::default()
+        };
+        let options = LineDiffOptions {
+            whitespace,
+            ..Default::default()
+        };
+        assert!(diff_lines(b"a  b\nc\n", b"a b \nc", &options).is_empty());
+        assert_eq!(diff_lines(b"ab\n", b"a b\n", &options).len(), 1);
+    }
+}
@@ -0,0 +1,373 @@
+use std::{collections::BTreeMap, fs, os::unix::ffi::OsStrExt};
+
+use anyhow::{bail, Result};
+
+use crate::objects::{
+    hash::Hash,
+    tree::{decode_tree, Entry, EntryMode},
+    Object, ObjectKind,
+};
+
+mod command;
+mod compact;
+mod histogram;
+mod lines;
+mod myers;
+mod patch;
+mod patience;
+mod rename;
+mod stat;
+
+pub use command::{diff, DiffOptions};
+pub use lines::{diff_lines, split_lines, DiffAlgorithm, Edit, LineDiffOptions, WhitespaceOptions};
+pub use patch::{
+    make_hunks, make_patch, write_patch, FilePatch, Hunk, HunkLine, LineOrigin, PatchOptions,
+    WordDiff,
+};
+pub use rename::{parse_similarity, MAX_SCORE};
+pub use stat::{
+    rename_name, stat_width, summary, write_numstat, write_stat, write_summary,
+    write_tree_shortstat, write_tree_stat, FileStat,
+};
+
+/// default minimum similarity of renames and copies (50%)
+pub const DEFAULT_RENAME_SCORE: u32 = MAX_SCORE / 2;
+
+/// number of bytes checked for a NUL byte to decide whether a file is binary
+const BINARY_CHECK_SIZE: usize = 8000;
+
+/// one side of a change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFile {
+    pub path: String,
+    pub mode: EntryMode,
+    pub hash: Hash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Added,
+    Deleted,
+    Modified,
+    /// the entry changed between a regular file and a symbolic link
+    TypeChanged,
+    Renamed,
+    Copied,
+}
+
+impl ChangeStatus {
+    /// the letter used by raw and --name-status output
+    pub fn letter(&self) -> char {
+        match self {
+            ChangeStatus::Added => 'A',
+            ChangeStatus::Deleted => 'D',
+            ChangeStatus::Modified => 'M',
+            ChangeStatus::TypeChanged => 'T',
+            ChangeStatus::Renamed => 'R',
+            ChangeStatus::Copied => 'C',
+        }
+    }
+}
+
+/// where the content of the files of a diff side is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentSource {
+    #[default]
+    Objects,
+    /// files of the working tree, whose content may not be in the object database
+    Worktree,
+}
+
+/// a difference between two trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub status: ChangeStatus,
+    /// missing for added entries
+    pub old: Option<DiffFile>,
+    /// missing for deleted entries
+    pub new: Option<DiffFile>,
+    /// similarity of renamed and copied files, out of MAX_SCORE
+    pub score: Option<u32>,
+}
+
+impl Change {
+    /// the path of the entry after the change, or before it for deleted entries
+    pub fn path(&self) -> &str {
+        match (&self.new, &self.old) {
+            (Some(new), _) => &new.path,
+            (None, Some(old)) => &old.path,
+            (None, None) => unreachable!("a change has at least one side"),
+        }
+    }
+
+    /// the similarity of renamed and copied files, as a percentage
+    pub fn similarity(&self) -> Option<u32> {
+        self.score.map(|score| score * 100 / MAX_SCORE)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TreeDiffOptions {
+    /// descend into subtrees instead of reporting changed subtrees as a single entry
+    pub recursive: bool,
+    /// only report changes to these paths
+    pub paths: Vec<String>,
+    pub find_renames: bool,
+    /// also detect files copied from modified files (implies find_renames)
+    pub find_copies: bool,
+    /// also consider unmodified files as copy sources
+    pub find_copies_harder: bool,
+    /// minimum similarity of renames and copies, out of MAX_SCORE
+    pub rename_score: u32,
+}
+
+/// compares two trees, a missing tree being treated as empty.
+/// changes are returned in the order their paths appear in the trees.
+pub fn diff_trees(
+    old: Option<&Hash>,
+    new: Option<&Hash>,
+    options: &TreeDiffOptions,
+) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    diff_entries(old, new, "", options, &mut changes)?;
+
+    if options.find_renames || options.find_copies || options.find_copies_harder {
+        let unmodified = match (old, options.find_copies_harder) {
+            (Some(old), true) => list_files(old, "", options)?,
+            _ => Vec::new(),
+        };
+        changes = rename::detect_renames(changes, unmodified, options, ContentSource::Objects)?;
+    }
+
+    Ok(changes)
+}
+
+/// compares two lists of files sorted by path, e.g. the files of a tree and the entries of the index.
+/// the content of the new files is read from the given source when detecting renames.
+pub fn diff_files(
+    old: Vec<DiffFile>,
+    new: Vec<DiffFile>,
+    options: &TreeDiffOptions,
+    new_source: ContentSource,
+) -> Result<Vec<Change>> {
+    let unmodified = match options.find_copies_harder {
+        true => old.clone(),
+        false => Vec::new(),
+    };
+
+    let mut files: BTreeMap<String, (Option<DiffFile>, Option<DiffFile>)> = BTreeMap::new();
+    for file in old {
+        let path = file.path.clone();
+        files.entry(path).or_default().0 = Some(file);
+    }
+    for file in new {
+        let path = file.path.clone();
+        files.entry(path).or_default().1 = Some(file);
+    }
+
+    let mut changes = Vec::new();
+    for (path, (old, new)) in files {
+        if !matches_paths(&path, false, &options.paths) {
+            continue;
+        }
+        if let (Some(old), Some(new)) = (&old, &new) {
+            if old.hash == new.hash && old.mode == new.mode {
+                continue;
+            }
+        }
+
+        changes.push(Change {
+            status: change_status(old.as_ref().map(|f| f.mode), new.as_ref().map(|f| f.mode)),
+            old,
+            new,
+            score: None,
+        });
+    }
+
+    if options.find_renames || options.find_copies || options.find_copies_harder {
+        changes = rename::detect_renames(changes, unmodified, options, new_source)?;
+    }
+
+    Ok(changes)
+}
+
+/// lists the files of a tree recursively, a missing tree being treated as empty
+pub fn list_tree_files(tree: Option<&Hash>, options: &TreeDiffOptions) -> Result<Vec<DiffFile>> {
+    match tree {
+        Some(tree) => list_files(tree, "", options),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// reads the content of a file. symbolic links of the working tree are read as their target.
+pub fn read_content(file: &DiffFile, source: ContentSource) -> Result<Vec<u8>> {
+    match source {
+        ContentSource::Objects => Ok(Object::read_from_hash(file.hash.to_hex())?.data),
+        ContentSource::Worktree if file.mode == EntryMode::SymbolicLink => {
+            Ok(fs::read_link(&file.path)?.as_os_str().as_bytes().to_vec())
+        }
+        ContentSource::Worktree => Ok(fs::read(&file.path)?),
+    }
+}
+
+/// returns true if the content looks binary, i.e. has a NUL byte close to its start
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_SIZE)].contains(&0)
+}
+
+/// returns true if the path is selected by the pathspecs, or is a directory containing selected paths
+pub fn matches_paths(path: &str, is_dir: bool, paths: &[String]) -> bool {
+    if paths.is_empty() {
+        return true;
+    }
+
+    paths.iter().any(|pathspec| {
+        let pathspec = pathspec.trim_matches('/');
+        pathspec.is_empty()
+            || pathspec == "."
+            || path == pathspec
+            || path.starts_with(&format!("{}/", pathspec))
+            || (is_dir && pathspec.starts_with(&format!("{}/", path)))
+    })
+}
+
+fn diff_entries(
+    old: Option<&Hash>,
+    new: Option<&Hash>,
+    prefix: &str,
+    options: &TreeDiffOptions,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    // directories sort as if their names ended with a slash, like they do in tree objects.
+    // an entry that changed between a file and a directory shows up as two different keys.
+    let mut entries: BTreeMap<String, (Option<Entry>, Option<Entry>)> = BTreeMap::new();
+    for entry in read_entries(old)? {
+        let key = sort_key(&entry);
+        entries.entry(key).or_default().0 = Some(entry);
+    }
+    for entry in read_entries(new)? {
+        let key = sort_key(&entry);
+        entries.entry(key).or_default().1 = Some(entry);
+    }
+
+    for (_, (old_entry, new_entry)) in entries {
+        let name = match (&old_entry, &new_entry) {
+            (Some(entry), _) | (None, Some(entry)) => &entry.name,
+            (None, None) => unreachable!(),
+        };
+        let path = format!("{}{}", prefix, name);
+        let is_dir = old_entry
+            .as_ref()
+            .or(new_entry.as_ref())
+            .is_some_and(|entry| entry.mode == EntryMode::Directory);
+        if !matches_paths(&path, is_dir, &options.paths) {
+            continue;
+        }
+
+        if let (Some(old_entry), Some(new_entry)) = (&old_entry, &new_entry) {
+            if old_entry.hash == new_entry.hash && old_entry.mode == new_entry.mode {
+                continue;
+            }
+        }
+
+        if is_dir && options.recursive {
+            let old_tree = old_entry.as_ref().map(|entry| &entry.hash);
+            let new_tree = new_entry.as_ref().map(|entry| &entry.hash);
+            diff_entries(old_tree, new_tree, &format!("{}/", path), options, changes)?;
+            continue;
+        }
+
+        let file = |entry: Entry| DiffFile {
+            path: path.clone(),
+            mode: entry.mode,
+            hash: entry.hash,
+        };
+        let status = change_status(
+            old_entry.as_ref().map(|entry| entry.mode),
+            new_entry.as_ref().map(|entry| entry.mode),
+        );
+
+        changes.push(Change {
+            status,
+            old: old_entry.map(file),
+            new: new_entry.map(file),
+            score: None,
+        });
+    }
+
+    Ok(())
+}
+
+fn change_status(old: Option<EntryMode>, new: Option<EntryMode>) -> ChangeStatus {
+    match (old, new) {
+        (None, _) => ChangeStatus::Added,
+        (_, None) => ChangeStatus::Deleted,
+        (Some(old), Some(new))
+            if (old == EntryMode::SymbolicLink) != (new == EntryMode::SymbolicLink) =>
+        {
+            ChangeStatus::TypeChanged
+        }
+        _ => ChangeStatus::Modified,
+    }
+}
+
+/// lists the files of a tree recursively
+fn list_files(tree: &Hash, prefix: &str, options: &TreeDiffOptions) -> Result<Vec<DiffFile>> {
+    let mut files = Vec::new();
+    for entry in read_entries(Some(tree))? {
+        let path = format!("{}{}", prefix, entry.name);
+        let is_dir = entry.mode == EntryMode::Directory;
+        if !matches_paths(&path, is_dir, &options.paths) {
+            continue;
+        }
+
+        if is_dir {
+            files.extend(list_files(&entry.hash, &format!("{}/", path), options)?);
+        } else {
+            files.push(DiffFile {
+                path,
+                mode: entry.mode,
+                hash: entry.hash,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+fn read_entries(tree: Option<&Hash>) -> Result<Vec<Entry>> {
+    let tree = match tree {
+        Some(tree) => tree,
+        None => return Ok(Vec::new()),
+    };
+
+    let object = Object::read_from_hash(tree.to_hex())?;
+    if object.kind != ObjectKind::Tree {
+        bail!("object {:x} is a {}, not a tree", tree, object.kind);
+    }
+
+    Ok(decode_tree(object.data)?.entries)
+}
+
+fn sort_key(entry: &Entry) -> String {
+    match entry.mode {
+        EntryMode::Directory => format!("{}/", entry.name),
+        _ => entry.name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::matches_paths;
+
+    #[test]
+    fn test_matches_paths() {
+        let paths = vec![String::from("src/objects")];
+        assert!(matches_paths("src", true, &paths));
+        assert!(matches_paths("src/objects", true, &paths));
+        assert!(matches_paths("src/objects/tree.rs", false, &paths));
+        assert!(!matches_paths("src/objects.rs", false, &paths));
+        assert!(!matches_paths("src", false, &paths));
+        assert!(matches_paths("anything", false, &[]));
+    }
+}
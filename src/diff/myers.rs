@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+
+/// lines matching more than this many lines of the other file may be discarded before diffing
+const MAX_EQLIMIT: usize = 1024;
+/// window scanned around a line with many matches when deciding whether to discard it
+const SIMSCAN_WINDOW: usize = 100;
+const KPDIS_RUN: usize = 4;
+/// minimum edit cost after which the search settles for the furthest reaching path
+const MAX_COST_MIN: i64 = 256;
+/// edit cost after which snakes are sampled for a good enough split
+const HEUR_MIN_COST: i64 = 256;
+/// minimum length of a snake considered interesting by the heuristic
+const SNAKE_CNT: i64 = 20;
+const K_HEUR: i64 = 4;
+const LINE_MAX: i64 = i64::MAX;
+
+/// how a line relates to the lines of the other file
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Discard {
+    /// the line doesn't appear in the other file
+    NoMatch,
+    Match,
+    /// the line appears many times in the other file
+    ManyMatches,
+}
+
+/// compares two sequences of line ids with the algorithm of "An O(ND) Difference Algorithm
+/// and Its Variations" by Eugene W. Myers, with the same heuristics as git's xdiff.
+/// returns the changed flags of both sequences.
+pub(super) fn myers(ids1: &[usize], ids2: &[usize], minimal: bool) -> (Vec<bool>, Vec<bool>) {
+    let mut rchg1 = vec![false; ids1.len()];
+    let mut rchg2 = vec![false; ids2.len()];
+
+    // lines common to the start and end of both files are left out
+    let lim = ids1.len().min(ids2.len());
+    let prefix = (0..lim).take_while(|&i| ids1[i] == ids2[i]).count();
+    let suffix = (0..lim - prefix)
+        .take_while(|&i| ids1[ids1.len() - 1 - i] == ids2[ids2.len() - 1 - i])
+        .count();
+    let range1 = prefix..ids1.len() - suffix;
+    let range2 = prefix..ids2.len() - suffix;
+
+    let mut counts1: HashMap<usize, usize> = HashMap::new();
+    let mut counts2: HashMap<usize, usize> = HashMap::new();
+    for id in ids1 {
+        *counts1.entry(*id).or_default() += 1;
+    }
+    for id in ids2 {
+        *counts2.entry(*id).or_default() += 1;
+    }
+
+    let (rindex1, ha1) = discard_lines(ids1, range1, &counts2, &mut rchg1, minimal);
+    let (rindex2, ha2) = discard_lines(ids2, range2, &counts1, &mut rchg2, minimal);
+
+    let ndiags = ha1.len() + ha2.len() + 3;
+    let mut diff = Myers {
+        ha1: &ha1,
+        ha2: &ha2,
+        rindex1: &rindex1,
+        rindex2: &rindex2,
+        rchg1: &mut rchg1,
+        rchg2: &mut rchg2,
+        kvdf: vec![0; ndiags],
+        kvdb: vec![0; ndiags],
+        offset: ha2.len() as i64 + 1,
+        mxcost: bogosqrt(ndiags).max(MAX_COST_MIN),
+    };
+    diff.recs_cmp(0, ha1.len() as i64, 0, ha2.len() as i64, minimal);
+
+    (rchg1, rchg2)
+}
+
+/// lines without a match in the other file are changed for sure and are left out of the search,
+/// and so are lines with many matches surrounded by such lines.
+/// returns the indices and ids of the lines kept.
+fn discard_lines(
+    ids: &[usize],
+    range: std::ops::Range<usize>,
+    other_counts: &HashMap<usize, usize>,
+    rchg: &mut [bool],
+    minimal: bool,
+) -> (Vec<usize>, Vec<usize>) {
+    let mlim = (bogosqrt(ids.len()) as usize).min(MAX_EQLIMIT);
+    let mut dis = vec![Discard::NoMatch; ids.len()];
+    for i in range.clone() {
+        let matches = other_counts.get(&ids[i]).copied().unwrap_or(0);
+        dis[i] = match matches {
+            0 => Discard::NoMatch,
+            n if n >= mlim && !minimal => Discard::ManyMatches,
+            _ => Discard::Match,
+        };
+    }
+
+    let mut rindex = Vec::new();
+    let mut ha = Vec::new();
+    if range.is_empty() {
+        return (rindex, ha);
+    }
+
+    let (start, end) = (range.start, range.end - 1);
+    for i in range {
+        if dis[i] == Discard::Match
+            || (dis[i] == Discard::ManyMatches && !clean_mmatch(&dis, i, start, end))
+        {
+            rindex.push(i);
+            ha.push(ids[i]);
+        } else {
+            rchg[i] = true;
+        }
+    }
+
+    (rindex, ha)
+}
+
+/// returns true if the line with many matches at i sits in a run of lines that are mostly
+/// without a match, in which case it is discarded too
+fn clean_mmatch(dis: &[Discard], i: usize, mut start: usize, mut end: usize) -> bool {
+    if i - start > SIMSCAN_WINDOW {
+        start = i - SIMSCAN_WINDOW;
+    }
+    if end - i > SIMSCAN_WINDOW {
+        end = i + SIMSCAN_WINDOW;
+    }
+
+    let (mut rdis0, mut rpdis0) = (0, 1);
+    for j in (start..i).rev() {
+        match dis[j] {
+            Discard::NoMatch => rdis0 += 1,
+            Discard::ManyMatches => rpdis0 += 1,
+            Discard::Match => break,
+        }
+    }
+    if rdis0 == 0 {
+        return false;
+    }
+
+    let (mut rdis1, mut rpdis1) = (0, 1);
+    for d in dis.iter().take(end + 1).skip(i + 1) {
+        match d {
+            Discard::NoMatch => rdis1 += 1,
+            Discard::ManyMatches => rpdis1 += 1,
+            Discard::Match => break,
+        }
+    }
+    if rdis1 == 0 {
+        return false;
+    }
+
+    rdis1 += rdis0;
+    rpdis1 += rpdis0;
+    rpdis1 * KPDIS_RUN < rpdis1 + rdis1
+}
+
+/// a rough integer square root
+fn bogosqrt(mut n: usize) -> i64 {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+struct Split {
+    i1: i64,
+    i2: i64,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+struct Myers<'a> {
+    ha1: &'a [usize],
+    ha2: &'a [usize],
+    rindex1: &'a [usize],
+    rindex2: &'a [usize],
+    rchg1: &'a mut [bool],
+    rchg2: &'a mut [bool],
+    /// furthest reaching paths of the forward and backward searches, by diagonal
+    kvdf: Vec<i64>,
+    kvdb: Vec<i64>,
+    /// index of diagonal 0 in kvdf and kvdb
+    offset: i64,
+    mxcost: i64,
+}
+
+impl Myers<'_> {
+    fn recs_cmp(
+        &mut self,
+        mut off1: i64,
+        mut lim1: i64,
+        mut off2: i64,
+        mut lim2: i64,
+        need_min: bool,
+    ) {
+        // shrink the box by walking through the diagonal snakes at both ends
+        while off1 < lim1 && off2 < lim2 && self.ha1[off1 as usize] == self.ha2[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1
+            && off2 < lim2
+            && self.ha1[lim1 as usize - 1] == self.ha2[lim2 as usize - 1]
+        {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                self.rchg2[self.rindex2[i as usize]] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                self.rchg1[self.rindex1[i as usize]] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.recs_cmp(off1, split.i1, off2, split.i2, split.min_lo);
+            self.recs_cmp(split.i1, lim1, split.i2, lim2, split.min_hi);
+        }
+    }
+
+    fn f(&self, d: i64) -> i64 {
+        self.kvdf[(d + self.offset) as usize]
+    }
+
+    fn b(&self, d: i64) -> i64 {
+        self.kvdb[(d + self.offset) as usize]
+    }
+
+    fn set_f(&mut self, d: i64, value: i64) {
+        self.kvdf[(d + self.offset) as usize] = value;
+    }
+
+    fn set_b(&mut self, d: i64, value: i64) {
+        self.kvdb[(d + self.offset) as usize] = value;
+    }
+
+    fn same(&self, i1: i64, i2: i64) -> bool {
+        self.ha1[i1 as usize] == self.ha2[i2 as usize]
+    }
+
+    /// finds the middle snake of the box, searching forward from its top left corner and
+    /// backward from its bottom right corner until the paths meet
+    fn split(&mut self, off1: i64, lim1: i64, off2: i64, lim2: i64, need_min: bool) -> Split {
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        self.set_f(fmid, off1);
+        self.set_b(bmid, lim1);
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+
+            // extend the forward diagonal domain by one, or shrink it at the box boundaries
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_f(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_f(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.f(d - 1) >= self.f(d + 1) {
+                    self.f(d - 1) + 1
+                } else {
+                    self.f(d + 1)
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_f(d, i1);
+                if odd && bmin <= d && d <= bmax && self.b(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_b(bmin - 1, LINE_MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_b(bmax + 1, LINE_MAX);
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.b(d - 1) < self.b(d + 1) {
+                    self.b(d - 1)
+                } else {
+                    self.b(d + 1) - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_b(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.f(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // past the heuristic trigger, a diagonal that got far from both the corner and
+            // the middle diagonal at the end of a long snake is good enough
+            if got_snake && ec > HEUR_MIN_COST {
+                if let Some(split) =
+                    self.forward_snake(off1, lim1, off2, lim2, fmin, fmax, fmid, ec)
+                {
+                    return split;
+                }
+                if let Some(split) =
+                    self.backward_snake(off1, lim1, off2, lim2, bmin, bmax, bmid, ec)
+                {
+                    return split;
+                }
+            }
+
+            // enough is enough: settle for the furthest reaching path
+            if ec >= self.mxcost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.f(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (LINE_MAX, LINE_MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = off1.max(self.b(d));
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+
+            ec += 1;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn forward_snake(
+        &self,
+        off1: i64,
+        lim1: i64,
+        off2: i64,
+        lim2: i64,
+        fmin: i64,
+        fmax: i64,
+        fmid: i64,
+        ec: i64,
+    ) -> Option<Split> {
+        let mut best = 0;
+        let mut split = None;
+        let mut d = fmax;
+        while d >= fmin {
+            let dd = (d - fmid).abs();
+            let i1 = self.f(d);
+            let i2 = i1 - d;
+            let v = (i1 - off1) + (i2 - off2) - dd;
+
+            if v > K_HEUR * ec
+                && v > best
+                && off1 + SNAKE_CNT <= i1
+                && i1 < lim1
+                && off2 + SNAKE_CNT <= i2
+                && i2 < lim2
+                && (1..=SNAKE_CNT).all(|k| self.same(i1 - k, i2 - k))
+            {
+                best = v;
+                split = Some(Split {
+                    i1,
+                    i2,
+                    min_lo: true,
+                    min_hi: false,
+                });
+            }
+            d -= 2;
+        }
+
+        split
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn backward_snake(
+        &self,
+        off1: i64,
+        lim1: i64,
+        off2: i64,
+        lim2: i64,
+        bmin: i64,
+        bmax: i64,
+        bmid: i64,
+        ec: i64,
+    ) -> Option<Split> {
+        let mut best = 0;
+        let mut split = None;
+        let mut d = bmax;
+        while d >= bmin {
+            let dd = (d - bmid).abs();
+            let i1 = self.b(d);
+            let i2 = i1 - d;
+            let v = (lim1 - i1) + (lim2 - i2) - dd;
+
+            if v > K_HEUR * ec
+                && v > best
+                && off1 < i1
+                && i1 <= lim1 - SNAKE_CNT
+                && off2 < i2
+                && i2 <= lim2 - SNAKE_CNT
+                && (0..SNAKE_CNT).all(|k| self.same(i1 + k, i2 + k))
+            {
+                best = v;
+                split = Some(Split {
+                    i1,
+                    i2,
+                    min_lo: false,
+                    min_hi: true,
+                });
+            }
+            d -= 2;
+        }
+
+        split
+    }
+}
@@ -0,0 +1,808 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::{objects::hash::Hash, rev_parse::abbreviate};
+
+use super::{
+    diff_lines, is_binary,
+    lines::{is_space, split_lines},
+    Change, ChangeStatus, DiffAlgorithm, LineDiffOptions, WhitespaceOptions,
+};
+
+/// hunk header function names are truncated to this many bytes
+const MAX_FUNCTION_LENGTH: usize = 80;
+
+const RESET: &str = "\x1b[m";
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RED_BACKGROUND: &str = "\x1b[41m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WordDiff {
+    /// changes are shown as removed and added lines
+    #[default]
+    None,
+    /// changed words are shown inline as [-removed-]{+added+}
+    Plain,
+    /// changed words are shown inline in color only
+    Color,
+}
+
+impl TryFrom<&str> for WordDiff {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
+        let mode = match value {
+            "plain" => WordDiff::Plain,
+            "color" => WordDiff::Color,
+            "none" => WordDiff::None,
+            _ => anyhow::bail!("invalid word diff mode: {}", value),
+        };
+
+        Ok(mode)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PatchOptions {
+    /// number of unchanged lines shown around changes
+    pub context: usize,
+    pub line: LineDiffOptions,
+    pub word_diff: WordDiff,
+    pub color: bool,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        PatchOptions {
+            context: 3,
+            line: LineDiffOptions::default(),
+            word_diff: WordDiff::default(),
+            color: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOrigin {
+    Context,
+    Removed,
+    Added,
+}
+
+impl LineOrigin {
+    /// the character starting the line in a unified diff
+    pub fn prefix(&self) -> u8 {
+        match self {
+            LineOrigin::Context => b' ',
+            LineOrigin::Removed => b'-',
+            LineOrigin::Added => b'+',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkLine {
+    pub origin: LineOrigin,
+    /// the line, with its newline unless it is the last line of a file that doesn't end with one
+    pub content: Vec<u8>,
+}
+
+/// a group of changes with the unchanged lines around them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// line numbers as shown in the hunk header: counted from 1, or the line
+    /// before the hunk when it has no lines on that side
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+    /// the closest line before the hunk that looks like the start of a function
+    pub function: Option<Vec<u8>>,
+    pub lines: Vec<HunkLine>,
+}
+
+impl Hunk {
+    /// the @@ -old +new @@ part of the hunk header
+    pub fn range(&self) -> String {
+        let side = |start: usize, count: usize| match count {
+            1 => start.to_string(),
+            count => format!("{},{}", start, count),
+        };
+        format!(
+            "@@ -{} +{} @@",
+            side(self.old_start, self.old_count),
+            side(self.new_start, self.new_count)
+        )
+    }
+}
+
+/// the differences of a file, in a form ready to be printed
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub change: Change,
+    /// one of the sides contains a NUL byte. binary files have no hunks.
+    pub binary: bool,
+    pub hunks: Vec<Hunk>,
+    /// lines of the new file made of whitespace only, starting from this one (counted from 1),
+    /// are added blank lines at the end of the file
+    blank_at_eof: Option<usize>,
+}
+
+impl FilePatch {
+    /// number of added and removed lines
+    pub fn line_counts(&self) -> (usize, usize) {
+        let lines = self.hunks.iter().flat_map(|hunk| &hunk.lines);
+        lines.fold((0, 0), |(added, removed), line| match line.origin {
+            LineOrigin::Added => (added + 1, removed),
+            LineOrigin::Removed => (added, removed + 1),
+            LineOrigin::Context => (added, removed),
+        })
+    }
+}
+
+/// compares the content of both sides of a change
+pub fn make_patch(change: Change, old: &[u8], new: &[u8], options: &PatchOptions) -> FilePatch {
+    if is_binary(old) || is_binary(new) {
+        return FilePatch {
+            change,
+            binary: true,
+            hunks: Vec::new(),
+            blank_at_eof: None,
+        };
+    }
+
+    FilePatch {
+        change,
+        binary: false,
+        hunks: make_hunks(old, new, &options.line, options.context),
+        blank_at_eof: blank_at_eof(old, new),
+    }
+}
+
+/// groups the changed lines of two files into hunks with the given number of context lines
+pub fn make_hunks(old: &[u8], new: &[u8], options: &LineDiffOptions, context: usize) -> Vec<Hunk> {
+    let lines1 = split_lines(old);
+    let lines2 = split_lines(new);
+    let edits = diff_lines(old, new, options);
+
+    let mut hunks = Vec::new();
+    let mut function: Option<Vec<u8>> = None;
+    let mut function_limit: Option<usize> = None;
+    let mut next = 0;
+    while let Some((first, last)) = next_hunk(&edits[next..], context) {
+        let (first, last) = (next + first, next + last);
+        next = last + 1;
+
+        let s1 = edits[first].old_start.saturating_sub(context);
+        let s2 = edits[first].new_start.saturating_sub(context);
+        let e1 = (edits[last].old_start + edits[last].old_count + context).min(lines1.len());
+        let e2 = (edits[last].new_start + edits[last].new_count + context).min(lines2.len());
+
+        // search backward for a function line, stopping where the previous search started.
+        // the previous function is kept when none is found.
+        let search_end = function_limit.map_or(0, |limit| limit + 1);
+        if let Some(found) = (search_end..s1)
+            .rev()
+            .find_map(|line| function_name(lines1[line]))
+        {
+            function = Some(found);
+        }
+        function_limit = s1.checked_sub(1);
+
+        let mut lines = Vec::new();
+        let line = |origin, content: &[u8]| HunkLine {
+            origin,
+            content: content.to_vec(),
+        };
+        let mut i2 = s2;
+        for edit in &edits[first..=last] {
+            for content in &lines2[i2..edit.new_start] {
+                lines.push(line(LineOrigin::Context, content));
+            }
+            for content in &lines1[edit.old_start..edit.old_start + edit.old_count] {
+                lines.push(line(LineOrigin::Removed, content));
+            }
+            for content in &lines2[edit.new_start..edit.new_start + edit.new_count] {
+                lines.push(line(LineOrigin::Added, content));
+            }
+            i2 = edit.new_start + edit.new_count;
+        }
+        for content in &lines2[i2..e2] {
+            lines.push(line(LineOrigin::Context, content));
+        }
+
+        let start = |start: usize, count: usize| if count > 0 { start + 1 } else { start };
+        hunks.push(Hunk {
+            old_start: start(s1, e1 - s1),
+            old_count: e1 - s1,
+            new_start: start(s2, e2 - s2),
+            new_count: e2 - s2,
+            function: function.clone(),
+            lines,
+        });
+    }
+
+    hunks
+}
+
+/// returns the first and last edits of the next hunk. changes closer than twice the context
+/// share a hunk, and ignorable changes are only shown when close to other changes.
+fn next_hunk(edits: &[super::Edit], context: usize) -> Option<(usize, usize)> {
+    let max_common = 2 * context;
+    let max_ignorable = context;
+    let end = |i: usize| edits[i].old_start + edits[i].old_count;
+
+    // drop ignorable changes that are too far before other changes
+    let mut first = 0;
+    let mut i = 0;
+    while i < edits.len() && edits[i].ignorable {
+        if i + 1 == edits.len() || edits[i + 1].old_start - end(i) >= max_ignorable {
+            first = i + 1;
+        }
+        i += 1;
+    }
+    if first == edits.len() {
+        return None;
+    }
+
+    let mut last = first;
+    let mut ignored = 0;
+    for (current, edit) in edits.iter().enumerate().skip(first + 1) {
+        let previous = current - 1;
+        let distance = edit.old_start - end(previous);
+        if distance > max_common {
+            break;
+        }
+
+        if distance < max_ignorable && (!edit.ignorable || last == previous) {
+            last = current;
+            ignored = 0;
+        } else if distance < max_ignorable && edit.ignorable {
+            ignored += edit.new_count;
+        } else if last != previous && edit.old_start + ignored - end(last) > max_common {
+            break;
+        } else if !edit.ignorable {
+            last = current;
+            ignored = 0;
+        } else {
+            ignored += edit.new_count;
+        }
+    }
+
+    Some((first, last))
+}
+
+/// git's default function line detection: lines starting with a letter, '_' or '$'
+fn function_name(line: &[u8]) -> Option<Vec<u8>> {
+    let first = *line.first()?;
+    if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+        return None;
+    }
+
+    let mut name = &line[..line.len().min(MAX_FUNCTION_LENGTH)];
+    while let [rest @ .., last] = name {
+        if !is_space(*last) {
+            break;
+        }
+        name = rest;
+    }
+    Some(name.to_vec())
+}
+
+/// finds the blank lines added at the end of the new file, which are highlighted as whitespace errors
+fn blank_at_eof(old: &[u8], new: &[u8]) -> Option<usize> {
+    let trailing_blank = |lines: &[&[u8]]| {
+        lines
+            .iter()
+            .rev()
+            .take_while(|line| line.iter().all(|b| is_space(*b)))
+            .count()
+    };
+    let lines1 = split_lines(old);
+    let lines2 = split_lines(new);
+    let (l1, l2) = (trailing_blank(&lines1), trailing_blank(&lines2));
+    if l2 <= l1 {
+        return None;
+    }
+
+    Some(lines2.len() - l2 + 1)
+}
+
+struct Colors {
+    meta: &'static str,
+    frag: &'static str,
+    old: &'static str,
+    new: &'static str,
+    whitespace: &'static str,
+    reset: &'static str,
+}
+
+impl Colors {
+    fn new(color: bool) -> Colors {
+        if !color {
+            return Colors {
+                meta: "",
+                frag: "",
+                old: "",
+                new: "",
+                whitespace: "",
+                reset: "",
+            };
+        }
+
+        Colors {
+            meta: BOLD,
+            frag: CYAN,
+            old: RED,
+            new: GREEN,
+            whitespace: RED_BACKGROUND,
+            reset: RESET,
+        }
+    }
+}
+
+/// prints a patch like git diff does: the diff --git header, the extended header lines and the hunks
+pub fn write_patch<W: Write>(out: &mut W, patch: &FilePatch, options: &PatchOptions) -> Result<()> {
+    let colors = Colors::new(options.color);
+    let change = &patch.change;
+    let old_label = match &change.old {
+        Some(old) => format!("a/{}", old.path),
+        None => String::from("/dev/null"),
+    };
+    let new_label = match &change.new {
+        Some(new) => format!("b/{}", new.path),
+        None => String::from("/dev/null"),
+    };
+
+    let mut header = Vec::new();
+    let old_path = change.old.as_ref().or(change.new.as_ref()).map(|f| &f.path);
+    let new_path = change.new.as_ref().or(change.old.as_ref()).map(|f| &f.path);
+    header.push(format!(
+        "diff --git a/{} b/{}",
+        old_path.expect("a change has at least one side"),
+        new_path.expect("a change has at least one side")
+    ));
+
+    let mut must_show_header = true;
+    match (&change.old, &change.new) {
+        (None, Some(new)) => header.push(format!("new file mode {:06o}", new.mode)),
+        (Some(old), None) => header.push(format!("deleted file mode {:06o}", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push(format!("old mode {:06o}", old.mode));
+            header.push(format!("new mode {:06o}", new.mode));
+        }
+        _ => must_show_header = false,
+    }
+
+    if let (Some(old), Some(new), Some(similarity)) =
+        (&change.old, &change.new, change.similarity())
+    {
+        let kind = match change.status {
+            ChangeStatus::Copied => "copy",
+            _ => "rename",
+        };
+        header.push(format!("similarity index {}%", similarity));
+        header.push(format!("{} from {}", kind, old.path));
+        header.push(format!("{} to {}", kind, new.path));
+        must_show_header = true;
+    }
+
+    let null = Hash(vec![0; 20]);
+    let old_hash = change.old.as_ref().map_or(&null, |old| &old.hash);
+    let new_hash = change.new.as_ref().map_or(&null, |new| &new.hash);
+    if old_hash != new_hash {
+        let mut line = format!(
+            "index {}..{}",
+            abbreviate_hash(old_hash)?,
+            abbreviate_hash(new_hash)?
+        );
+        if let (Some(old), Some(new)) = (&change.old, &change.new) {
+            if old.mode == new.mode {
+                line.push_str(&format!(" {:06o}", old.mode));
+            }
+        }
+        header.push(line);
+    }
+
+    let write_header = |out: &mut W| -> Result<()> {
+        for line in &header {
+            writeln!(out, "{}{}{}", colors.meta, line, colors.reset)?;
+        }
+        Ok(())
+    };
+
+    if patch.binary {
+        if old_hash == new_hash {
+            if must_show_header {
+                write_header(out)?;
+            }
+            return Ok(());
+        }
+
+        write_header(out)?;
+        writeln!(out, "Binary files {} and {} differ", old_label, new_label)?;
+        return Ok(());
+    }
+
+    if patch.hunks.is_empty() {
+        if must_show_header {
+            write_header(out)?;
+        }
+        return Ok(());
+    }
+
+    write_header(out)?;
+    let tab = |label: &str| if label.contains(' ') { "\t" } else { "" };
+    writeln!(
+        out,
+        "{}--- {}{}{}",
+        colors.meta,
+        old_label,
+        tab(&old_label),
+        colors.reset
+    )?;
+    writeln!(
+        out,
+        "{}+++ {}{}{}",
+        colors.meta,
+        new_label,
+        tab(&new_label),
+        colors.reset
+    )?;
+
+    for hunk in &patch.hunks {
+        write_hunk_header(out, hunk, &colors)?;
+        match options.word_diff {
+            WordDiff::None => write_lines(out, hunk, patch.blank_at_eof, &colors)?,
+            mode => write_words(out, hunk, mode, &colors)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn abbreviate_hash(hash: &Hash) -> Result<String> {
+    if hash.0.iter().all(|b| *b == 0) {
+        return Ok("0".repeat(7));
+    }
+    abbreviate(hash)
+}
+
+fn write_hunk_header<W: Write>(out: &mut W, hunk: &Hunk, colors: &Colors) -> Result<()> {
+    write!(out, "{}{}{}", colors.frag, hunk.range(), colors.reset)?;
+    if let Some(function) = hunk.function.as_ref().filter(|f| !f.is_empty()) {
+        write!(out, " {}", colors.reset)?;
+        out.write_all(function)?;
+        write!(out, "{}", colors.reset)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_lines<W: Write>(
+    out: &mut W,
+    hunk: &Hunk,
+    blank_at_eof: Option<usize>,
+    colors: &Colors,
+) -> Result<()> {
+    let mut new_line = hunk.new_start.max(1);
+    for line in &hunk.lines {
+        let (content, newline) = match line.content.strip_suffix(b"\n") {
+            Some(content) => (content, true),
+            None => (line.content.as_slice(), false),
+        };
+
+        match line.origin {
+            LineOrigin::Context => {
+                write!(out, " ")?;
+                out.write_all(content)?;
+                write!(out, "{}", colors.reset)?;
+            }
+            LineOrigin::Removed => {
+                write!(out, "{}-", colors.old)?;
+                out.write_all(content)?;
+                write!(out, "{}", colors.reset)?;
+            }
+            LineOrigin::Added => {
+                let at_eof = blank_at_eof.is_some_and(|start| new_line >= start)
+                    && content.iter().all(|b| is_space(*b));
+                if at_eof && !colors.whitespace.is_empty() {
+                    write!(out, "{}+", colors.whitespace)?;
+                    out.write_all(content)?;
+                    write!(out, "{}", colors.reset)?;
+                } else {
+                    write!(out, "{}+{}", colors.new, colors.reset)?;
+                    write_checked_whitespace(out, content, colors)?;
+                }
+            }
+        }
+        if line.origin != LineOrigin::Removed {
+            new_line += 1;
+        }
+
+        writeln!(out)?;
+        if !newline {
+            writeln!(out, "\\ No newline at end of file{}", colors.reset)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// writes an added line, highlighting trailing whitespace and spaces before tabs in the indentation
+fn write_checked_whitespace<W: Write>(out: &mut W, line: &[u8], colors: &Colors) -> Result<()> {
+    let trailing = line
+        .iter()
+        .rposition(|b| !is_space(*b))
+        .map_or(0, |i| i + 1);
+
+    let mut written = 0;
+    for (i, &b) in line[..trailing].iter().enumerate() {
+        if b == b' ' {
+            continue;
+        }
+        if b != b'\t' {
+            break;
+        }
+        if written < i {
+            write!(out, "{}", colors.whitespace)?;
+            out.write_all(&line[written..i])?;
+            write!(out, "{}", colors.reset)?;
+            out.write_all(&line[i..=i])?;
+        } else {
+            out.write_all(&line[written..=i])?;
+        }
+        written = i + 1;
+    }
+
+    if trailing > written {
+        write!(out, "{}", colors.new)?;
+        out.write_all(&line[written..trailing])?;
+        write!(out, "{}", colors.reset)?;
+    }
+    if trailing != line.len() {
+        write!(out, "{}", colors.whitespace)?;
+        out.write_all(&line[trailing..])?;
+        write!(out, "{}", colors.reset)?;
+    }
+
+    Ok(())
+}
+
+/// how one kind of text is shown by the word diff
+struct WordStyle {
+    color: &'static str,
+    prefix: &'static str,
+    suffix: &'static str,
+}
+
+/// writes the lines of a hunk with the changed words of consecutive removed and added lines inline
+fn write_words<W: Write>(out: &mut W, hunk: &Hunk, mode: WordDiff, colors: &Colors) -> Result<()> {
+    let brackets = mode == WordDiff::Plain;
+    let styles = [
+        WordStyle {
+            color: colors.old,
+            prefix: if brackets { "[-" } else { "" },
+            suffix: if brackets { "-]" } else { "" },
+        },
+        WordStyle {
+            color: colors.new,
+            prefix: if brackets { "{+" } else { "" },
+            suffix: if brackets { "+}" } else { "" },
+        },
+        WordStyle {
+            color: "",
+            prefix: "",
+            suffix: "",
+        },
+    ];
+
+    let mut minus = Vec::new();
+    let mut plus = Vec::new();
+    for line in &hunk.lines {
+        match line.origin {
+            LineOrigin::Removed => minus.extend_from_slice(&line.content),
+            LineOrigin::Added => plus.extend_from_slice(&line.content),
+            LineOrigin::Context => {
+                flush_words(out, &mut minus, &mut plus, &styles, colors)?;
+                match line.content.strip_suffix(b"\n") {
+                    Some([]) => {}
+                    Some(content) => {
+                        out.write_all(content)?;
+                        write!(out, "{}", colors.reset)?;
+                    }
+                    None => {
+                        out.write_all(&line.content)?;
+                        write!(out, "{}", colors.reset)?;
+                    }
+                }
+                writeln!(out)?;
+            }
+        }
+    }
+
+    flush_words(out, &mut minus, &mut plus, &styles, colors)
+}
+
+fn flush_words<W: Write>(
+    out: &mut W,
+    minus: &mut Vec<u8>,
+    plus: &mut Vec<u8>,
+    styles: &[WordStyle; 3],
+    colors: &Colors,
+) -> Result<()> {
+    let [old_style, new_style, context_style] = styles;
+    if minus.is_empty() && plus.is_empty() {
+        return Ok(());
+    }
+
+    if plus.is_empty() {
+        write_word_segment(out, old_style, minus, colors)?;
+        if !minus.ends_with(b"\n") {
+            // the last line had no newline at the end of the file
+            writeln!(out)?;
+        }
+        minus.clear();
+        return Ok(());
+    }
+
+    let (old_words, old_text) = split_words(minus);
+    let (new_words, new_text) = split_words(plus);
+    let options = LineDiffOptions {
+        algorithm: DiffAlgorithm::Myers,
+        whitespace: WhitespaceOptions::default(),
+        indent_heuristic: false,
+    };
+
+    // words are numbered from 1, word 0 standing for the start of the text
+    let word_end = |words: &[(usize, usize)], i: usize| if i == 0 { 0 } else { words[i - 1].1 };
+    let mut current_plus = 0;
+    for edit in diff_lines(&old_text, &new_text, &options) {
+        let (minus_begin, minus_end) = match edit.old_count {
+            0 => {
+                let end = word_end(&old_words, edit.old_start);
+                (end, end)
+            }
+            count => (
+                old_words[edit.old_start].0,
+                old_words[edit.old_start + count - 1].1,
+            ),
+        };
+        let (plus_begin, plus_end) = match edit.new_count {
+            0 => {
+                let end = word_end(&new_words, edit.new_start);
+                (end, end)
+            }
+            count => (
+                new_words[edit.new_start].0,
+                new_words[edit.new_start + count - 1].1,
+            ),
+        };
+
+        if current_plus != plus_begin {
+            write_word_segment(out, context_style, &plus[current_plus..plus_begin], colors)?;
+        }
+        if minus_begin != minus_end {
+            write_word_segment(out, old_style, &minus[minus_begin..minus_end], colors)?;
+        }
+        if plus_begin != plus_end {
+            write_word_segment(out, new_style, &plus[plus_begin..plus_end], colors)?;
+        }
+        current_plus = plus_end;
+    }
+
+    if current_plus != plus.len() {
+        write_word_segment(out, context_style, &plus[current_plus..], colors)?;
+    }
+    if !plus.ends_with(b"\n") {
+        writeln!(out)?;
+    }
+
+    minus.clear();
+    plus.clear();
+    Ok(())
+}
+
+/// splits text into words separated by whitespace. returns the word boundaries,
+/// and the words one per line for diffing.
+fn split_words(text: &[u8]) -> (Vec<(usize, usize)>, Vec<u8>) {
+    let mut words = Vec::new();
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        if is_space(text[i]) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < text.len() && !is_space(text[i]) {
+            i += 1;
+        }
+        words.push((start, i));
+        lines.extend_from_slice(&text[start..i]);
+        lines.push(b'\n');
+    }
+
+    (words, lines)
+}
+
+/// writes text in the given style, line by line
+fn write_word_segment<W: Write>(
+    out: &mut W,
+    style: &WordStyle,
+    text: &[u8],
+    colors: &Colors,
+) -> Result<()> {
+    for (i, segment) in text.split(|b| *b == b'\n').enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        if segment.is_empty() {
+            continue;
+        }
+
+        write!(out, "{}{}", style.color, style.prefix)?;
+        out.write_all(segment)?;
+        write!(out, "{}", style.suffix)?;
+        if !style.color.is_empty() {
+            write!(out, "{}", colors.reset)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{function_name, make_hunks, LineOrigin};
+    use crate::diff::LineDiffOptions;
+
+    #[test]
+    fn test_make_hunks() {
+        let old = "fn main() {\n    a();\n    b();\n    c();\n    d();\n    e();\n}\n";
+        let new = "fn main() {\n    a();\n    b();\n    c();\n    x();\n    e();\n}\n";
+        let hunks = make_hunks(
+            old.as_bytes(),
+            new.as_bytes(),
+            &LineDiffOptions::default(),
+            1,
+        );
+        assert_eq!(hunks.len(), 1);
+        let hunk = &hunks[0];
+        assert_eq!(hunk.range(), "@@ -4,3 +4,3 @@");
+        assert_eq!(hunk.function.as_deref(), Some("fn main() {".as_bytes()));
+        let origins: Vec<LineOrigin> = hunk.lines.iter().map(|line| line.origin).collect();
+        assert_eq!(
+            origins,
+            vec![
+                LineOrigin::Context,
+                LineOrigin::Removed,
+                LineOrigin::Added,
+                LineOrigin::Context
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hunk_range() {
+        let hunks = make_hunks(b"", b"a\n", &LineDiffOptions::default(), 3);
+        assert_eq!(hunks[0].range(), "@@ -0,0 +1 @@");
+        let hunks = make_hunks(b"a\n", b"", &LineDiffOptions::default(), 3);
+        assert_eq!(hunks[0].range(), "@@ -1 +0,0 @@");
+    }
+
+    #[test]
+    fn test_function_name() {
+        assert_eq!(
+            function_name(b"int main(void)  \n"),
+            Some(b"int main(void)".to_vec())
+        );
+        assert_eq!(function_name(b"_start:\n"), Some(b"_start:".to_vec()));
+        assert_eq!(function_name(b"    indented\n"), None);
+        assert_eq!(function_name(b"{\n"), None);
+    }
+}
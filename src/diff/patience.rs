@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use super::myers::myers;
+
+/// a line of the first file, with the line it uniquely matches in the second file
+struct Entry {
+    line1: usize,
+    line2: Option<usize>,
+    /// the line isn't unique in one of the files
+    non_unique: bool,
+    previous: Option<usize>,
+}
+
+/// compares two sequences of line ids by matching the lines that appear exactly once
+/// in both, recursing between them and falling back to myers where no such line exists.
+/// returns the changed flags of both sequences.
+pub(super) fn patience(ids1: &[usize], ids2: &[usize]) -> (Vec<bool>, Vec<bool>) {
+    let mut diff = Patience {
+        ids1,
+        ids2,
+        rchg1: vec![false; ids1.len()],
+        rchg2: vec![false; ids2.len()],
+    };
+    diff.diff(0, ids1.len(), 0, ids2.len());
+    (diff.rchg1, diff.rchg2)
+}
+
+struct Patience<'a> {
+    ids1: &'a [usize],
+    ids2: &'a [usize],
+    rchg1: Vec<bool>,
+    rchg2: Vec<bool>,
+}
+
+impl Patience<'_> {
+    fn diff(&mut self, line1: usize, count1: usize, line2: usize, count2: usize) {
+        if count1 == 0 {
+            self.rchg2[line2..line2 + count2].fill(true);
+            return;
+        }
+        if count2 == 0 {
+            self.rchg1[line1..line1 + count1].fill(true);
+            return;
+        }
+
+        // entries are kept in the order of their first line in the first file
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut by_id: HashMap<usize, usize> = HashMap::new();
+        for line in line1..line1 + count1 {
+            match by_id.get(&self.ids1[line]) {
+                Some(&index) => entries[index].non_unique = true,
+                None => {
+                    by_id.insert(self.ids1[line], entries.len());
+                    entries.push(Entry {
+                        line1: line,
+                        line2: None,
+                        non_unique: false,
+                        previous: None,
+                    });
+                }
+            }
+        }
+
+        let mut has_matches = false;
+        for line in line2..line2 + count2 {
+            if let Some(&index) = by_id.get(&self.ids2[line]) {
+                has_matches = true;
+                let entry = &mut entries[index];
+                if entry.line2.is_some() {
+                    entry.non_unique = true;
+                }
+                entry.line2 = Some(line);
+            }
+        }
+
+        if !has_matches {
+            self.rchg1[line1..line1 + count1].fill(true);
+            self.rchg2[line2..line2 + count2].fill(true);
+            return;
+        }
+
+        match longest_common_sequence(&mut entries) {
+            Some(sequence) => {
+                let matches: Vec<(usize, usize)> = sequence
+                    .iter()
+                    .map(|&index| {
+                        let entry = &entries[index];
+                        (
+                            entry.line1,
+                            entry.line2.expect("sequence entries are matched"),
+                        )
+                    })
+                    .collect();
+                self.walk_common_sequence(&matches, line1, count1, line2, count2);
+            }
+            None => {
+                let (rchg1, rchg2) = myers(
+                    &self.ids1[line1..line1 + count1],
+                    &self.ids2[line2..line2 + count2],
+                    false,
+                );
+                self.rchg1[line1..line1 + count1].copy_from_slice(&rchg1);
+                self.rchg2[line2..line2 + count2].copy_from_slice(&rchg2);
+            }
+        }
+    }
+
+    /// diffs the ranges between the matched unique lines, after growing the matches
+    /// with the identical lines around them
+    fn walk_common_sequence(
+        &mut self,
+        matches: &[(usize, usize)],
+        mut line1: usize,
+        count1: usize,
+        mut line2: usize,
+        count2: usize,
+    ) {
+        let (end1, end2) = (line1 + count1, line2 + count2);
+        let mut next = 0;
+        loop {
+            let (mut next1, mut next2) = match matches.get(next) {
+                Some(&(next1, next2)) => (next1, next2),
+                None => (end1, end2),
+            };
+            if next < matches.len() {
+                while next1 > line1 && next2 > line2 && self.ids1[next1 - 1] == self.ids2[next2 - 1]
+                {
+                    next1 -= 1;
+                    next2 -= 1;
+                }
+            }
+            while line1 < next1 && line2 < next2 && self.ids1[line1] == self.ids2[line2] {
+                line1 += 1;
+                line2 += 1;
+            }
+
+            if next1 > line1 || next2 > line2 {
+                self.diff(line1, next1 - line1, line2, next2 - line2);
+            }
+
+            if next == matches.len() {
+                return;
+            }
+
+            while next + 1 < matches.len()
+                && matches[next + 1].0 == matches[next].0 + 1
+                && matches[next + 1].1 == matches[next].1 + 1
+            {
+                next += 1;
+            }
+
+            line1 = matches[next].0 + 1;
+            line2 = matches[next].1 + 1;
+            next += 1;
+        }
+    }
+}
+
+/// patience sorting of the uniquely matched lines: returns the longest sequence of
+/// matches increasing in both files, as indices in entries
+fn longest_common_sequence(entries: &mut [Entry]) -> Option<Vec<usize>> {
+    // for each length, the sequence ending with the smallest line of the second file
+    let mut sequence: Vec<usize> = Vec::new();
+    for index in 0..entries.len() {
+        let line2 = match (&entries[index].line2, entries[index].non_unique) {
+            (Some(line2), false) => *line2,
+            _ => continue,
+        };
+
+        let position = sequence.partition_point(|&other| entries[other].line2 < Some(line2));
+        entries[index].previous = position.checked_sub(1).map(|i| sequence[i]);
+        if position == sequence.len() {
+            sequence.push(index);
+        } else {
+            sequence[position] = index;
+        }
+    }
+
+    let mut index = *sequence.last()?;
+    let mut result = vec![index];
+    while let Some(previous) = entries[index].previous {
+        result.push(previous);
+        index = previous;
+    }
+    result.reverse();
+    Some(result)
+}
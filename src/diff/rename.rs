@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use crate::objects::{hash::Hash, tree::EntryMode};
+
+use super::{
+    is_binary, read_content, Change, ChangeStatus, ContentSource, DiffFile, TreeDiffOptions,
+    DEFAULT_RENAME_SCORE,
+};
+
+/// similarity scores are fractions of this value, like in git's diffcore
+pub const MAX_SCORE: u32 = 60000;
+
+/// content is compared in chunks ending at a newline, or after this many bytes
+const MAX_CHUNK: usize = 64;
+
+/// parses a similarity threshold given to -M or -C. digits are read as a fraction
+/// (-M5 and -M50 both mean 50%) unless followed by a percent sign (-M5% means 5%).
+pub fn parse_similarity(value: &str) -> Result<u32> {
+    if value.is_empty() {
+        return Ok(DEFAULT_RENAME_SCORE);
+    }
+
+    let (mut num, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' if chars.peek().is_none() => {
+                scale = if dot { scale * 100 } else { 100 };
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + c.to_digit(10).expect("c is a digit") as u64;
+                }
+            }
+            _ => bail!("invalid similarity score: {}", value),
+        }
+    }
+
+    if num >= scale {
+        return Ok(MAX_SCORE);
+    }
+    Ok((MAX_SCORE as u64 * num / scale) as u32)
+}
+
+struct Source {
+    file: DiffFile,
+    /// index of the deletion this source comes from. other sources are copy sources.
+    deletion: Option<usize>,
+}
+
+/// pairs added files with deleted (and, for copies, existing) files of similar content.
+/// matched additions become renames or copies, and deletions used by renames are dropped.
+/// the content of added files is read from new_source.
+pub(super) fn detect_renames(
+    changes: Vec<Change>,
+    unmodified: Vec<DiffFile>,
+    options: &TreeDiffOptions,
+    new_source: ContentSource,
+) -> Result<Vec<Change>> {
+    let copies = options.find_copies || options.find_copies_harder;
+
+    let mut sources = Vec::new();
+    for (index, change) in changes.iter().enumerate() {
+        let old = match &change.old {
+            Some(old) if old.mode != EntryMode::Directory => old,
+            _ => continue,
+        };
+
+        match change.status {
+            ChangeStatus::Deleted => sources.push(Source {
+                file: old.clone(),
+                deletion: Some(index),
+            }),
+            ChangeStatus::Modified | ChangeStatus::TypeChanged if copies => sources.push(Source {
+                file: old.clone(),
+                deletion: None,
+            }),
+            _ => {}
+        }
+    }
+    sources.extend(unmodified.into_iter().map(|file| Source {
+        file,
+        deletion: None,
+    }));
+    sources.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+
+    let destinations: Vec<usize> = changes
+        .iter()
+        .enumerate()
+        .filter(|(_, change)| change.status == ChangeStatus::Added)
+        .filter(|(_, change)| {
+            change
+                .new
+                .as_ref()
+                .is_some_and(|new| new.mode != EntryMode::Directory)
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    if sources.is_empty() || destinations.is_empty() {
+        return Ok(changes);
+    }
+
+    let mut matches: HashMap<usize, (usize, u32)> = HashMap::new();
+    let mut used = vec![0usize; sources.len()];
+
+    // identical content is matched first, preferring sources with the same file name
+    for &dst in &destinations {
+        let new = changes[dst]
+            .new
+            .as_ref()
+            .expect("additions have a new side");
+        let candidates: Vec<usize> = (0..sources.len())
+            .filter(|&src| copies || used[src] == 0)
+            .filter(|&src| {
+                let old = &sources[src].file;
+                old.hash == new.hash
+                    && (old.mode == EntryMode::SymbolicLink)
+                        == (new.mode == EntryMode::SymbolicLink)
+            })
+            .collect();
+
+        let best = candidates
+            .iter()
+            .find(|&&src| file_name(&sources[src].file.path) == file_name(&new.path))
+            .or(candidates.first());
+        if let Some(&src) = best {
+            used[src] += 1;
+            matches.insert(dst, (src, MAX_SCORE));
+        }
+    }
+
+    let mut chunks: HashMap<Hash, (usize, HashMap<Vec<u8>, usize>)> = HashMap::new();
+    let mut candidates = Vec::new();
+    for &dst in &destinations {
+        if matches.contains_key(&dst) {
+            continue;
+        }
+
+        let new = changes[dst]
+            .new
+            .as_ref()
+            .expect("additions have a new side");
+        if !is_regular(new.mode) {
+            continue;
+        }
+
+        for (src, source) in sources.iter().enumerate() {
+            if !is_regular(source.file.mode) {
+                continue;
+            }
+
+            for (file, source) in [(&source.file, ContentSource::Objects), (new, new_source)] {
+                if !chunks.contains_key(&file.hash) {
+                    let data = read_content(file, source)?;
+                    chunks.insert(file.hash.clone(), (data.len(), count_chunks(&data)));
+                }
+            }
+
+            let score = similarity(
+                &chunks[&source.file.hash],
+                &chunks[&new.hash],
+                options.rename_score,
+            );
+            if score >= options.rename_score {
+                candidates.push((score, dst, src));
+            }
+        }
+    }
+
+    // the best scores are assigned first. a source is only used once for renames,
+    // copies may reuse it afterwards.
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    for reuse in [false, true] {
+        if reuse && !copies {
+            break;
+        }
+
+        for &(score, dst, src) in &candidates {
+            if matches.contains_key(&dst) || (!reuse && used[src] > 0) {
+                continue;
+            }
+
+            used[src] += 1;
+            matches.insert(dst, (src, score));
+        }
+    }
+
+    let renamed: HashSet<usize> = sources
+        .iter()
+        .zip(&used)
+        .filter(|(_, used)| **used > 0)
+        .filter_map(|(source, _)| source.deletion)
+        .collect();
+
+    // when a deleted file has several destinations, the last one is the rename and the others copies
+    let mut remaining = used;
+    let mut result = Vec::new();
+    for (index, change) in changes.into_iter().enumerate() {
+        if renamed.contains(&index) {
+            continue;
+        }
+
+        let (src, score) = match matches.get(&index) {
+            Some(&matched) => matched,
+            None => {
+                result.push(change);
+                continue;
+            }
+        };
+
+        remaining[src] -= 1;
+        let source = &sources[src];
+        let status = if source.deletion.is_some() && remaining[src] == 0 {
+            ChangeStatus::Renamed
+        } else {
+            ChangeStatus::Copied
+        };
+
+        result.push(Change {
+            status,
+            old: Some(source.file.clone()),
+            new: change.new,
+            score: Some(score),
+        });
+    }
+
+    Ok(result)
+}
+
+/// estimates how much of the destination was copied from the source, out of MAX_SCORE
+fn similarity(
+    (src_size, src_chunks): &(usize, HashMap<Vec<u8>, usize>),
+    (dst_size, dst_chunks): &(usize, HashMap<Vec<u8>, usize>),
+    minimum_score: u32,
+) -> u32 {
+    let max_size = *src_size.max(dst_size) as u64;
+    let delta_size = max_size - *src_size.min(dst_size) as u64;
+    if max_size == 0 {
+        return 0;
+    }
+
+    // files that differ too much in size can't reach the minimum score
+    if max_size * ((MAX_SCORE - minimum_score) as u64) < delta_size * MAX_SCORE as u64 {
+        return 0;
+    }
+
+    let copied: usize = src_chunks
+        .iter()
+        .map(|(chunk, count)| (*count).min(dst_chunks.get(chunk).copied().unwrap_or(0)))
+        .sum();
+
+    (copied as u64 * MAX_SCORE as u64 / max_size) as u32
+}
+
+/// splits content into chunks and counts the bytes of each distinct chunk.
+/// carriage returns before newlines are ignored in text files.
+fn count_chunks(data: &[u8]) -> HashMap<Vec<u8>, usize> {
+    let is_text = !is_binary(data);
+
+    let mut counts: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut chunk = Vec::new();
+    for (i, &byte) in data.iter().enumerate() {
+        if is_text && byte == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+
+        chunk.push(byte);
+        if byte == b'\n' || chunk.len() == MAX_CHUNK {
+            let len = chunk.len();
+            *counts.entry(std::mem::take(&mut chunk)).or_default() += len;
+        }
+    }
+
+    if !chunk.is_empty() {
+        let len = chunk.len();
+        *counts.entry(chunk).or_default() += len;
+    }
+
+    counts
+}
+
+fn is_regular(mode: EntryMode) -> bool {
+    matches!(mode, EntryMode::RegularFile | EntryMode::ExecutableFile)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_chunks, parse_similarity, similarity, MAX_SCORE};
+
+    #[test]
+    fn test_parse_similarity() {
+        assert_eq!(parse_similarity("").unwrap(), MAX_SCORE / 2);
+        assert_eq!(parse_similarity("5").unwrap(), MAX_SCORE / 2);
+        assert_eq!(parse_similarity("50").unwrap(), MAX_SCORE / 2);
+        assert_eq!(parse_similarity("90%").unwrap(), MAX_SCORE * 9 / 10);
+        assert_eq!(parse_similarity("5%").unwrap(), MAX_SCORE / 20);
+        assert_eq!(parse_similarity("100%").unwrap(), MAX_SCORE);
+        assert!(parse_similarity("x").is_err());
+    }
+
+    #[test]
+    fn test_similarity() {
+        let src = b"line 1\nline 2\nline 3\nline 4\n";
+        let dst = b"line 1\nline 2\nline 3\nline 5\n";
+        let chunks = |data: &[u8]| (data.len(), count_chunks(data));
+
+        assert_eq!(similarity(&chunks(src), &chunks(src), 0), MAX_SCORE);
+        // 21 of the 28 bytes are kept
+        assert_eq!(
+            similarity(&chunks(src), &chunks(dst), 0),
+            21 * MAX_SCORE / 28
+        );
+        assert_eq!(
+            similarity(&chunks(src), &chunks(b"other\n"), MAX_SCORE / 2),
+            0
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::objects::hash::Hash;
+
+use super::{
+    diff_trees, make_patch, read_content, Change, ChangeStatus, ContentSource, FilePatch,
+    PatchOptions, TreeDiffOptions, DEFAULT_RENAME_SCORE,
+};
+
+/// width of --stat output when COLUMNS isn't set
+const DEFAULT_STAT_WIDTH: usize = 80;
+
+/// the changes of a file, as counted by --stat and --numstat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// the path, or both paths of a rename as "dir/{old => new}"
+    pub name: String,
+    /// added and removed lines, or the new and old sizes of binary files
+    pub added: usize,
+    pub removed: usize,
+    pub binary: bool,
+    /// the path has conflicting entries in the index, and no counts
+    pub unmerged: bool,
+}
+
+impl FileStat {
+    /// counts the changes of a patch made with no context lines. the sizes of
+    /// binary files are counted instead of their lines.
+    pub fn new(patch: &FilePatch, old_size: usize, new_size: usize) -> FileStat {
+        let (added, removed) = match patch.binary {
+            true => (new_size, old_size),
+            false => patch.line_counts(),
+        };
+
+        FileStat {
+            name: stat_name(&patch.change),
+            added,
+            removed,
+            binary: patch.binary,
+            unmerged: false,
+        }
+    }
+
+    pub fn unmerged(path: &str) -> FileStat {
+        FileStat {
+            name: path.to_string(),
+            added: 0,
+            removed: 0,
+            binary: false,
+            unmerged: true,
+        }
+    }
+}
+
+fn stat_name(change: &Change) -> String {
+    match (&change.old, &change.new, change.score) {
+        (Some(old), Some(new), Some(_)) if old.path != new.path => {
+            rename_name(&old.path, &new.path)
+        }
+        _ => change.path().to_string(),
+    }
+}
+
+/// shows a rename with the common leading and trailing directories factored out,
+/// e.g. "src/{a => b}/lib.rs"
+pub fn rename_name(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+
+    // the common prefix, up to and including its last slash
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // the common suffix, starting with a slash, that doesn't overlap the prefix
+    let mut suffix = 0;
+    let min_start = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > min_start && j > min_start && a[i - 1] == b[j - 1] {
+        i -= 1;
+        j -= 1;
+        if a[i] == b'/' {
+            suffix = a.len() - i;
+        }
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let middle = format!(
+        "{} => {}",
+        &old[prefix..prefix + a_mid],
+        &new[prefix..prefix + b_mid]
+    );
+    if prefix + suffix == 0 {
+        return middle;
+    }
+
+    format!(
+        "{}{{{}}}{}",
+        &old[..prefix],
+        middle,
+        &old[old.len() - suffix..]
+    )
+}
+
+/// prints a histogram of the changes like git diff --stat, fitting in the given width
+pub fn write_stat<W: Write>(
+    out: &mut W,
+    stats: &[FileStat],
+    color: bool,
+    width: usize,
+) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    let (add_color, del_color, reset) = match color {
+        true => ("\x1b[32m", "\x1b[31m", "\x1b[m"),
+        false => ("", "", ""),
+    };
+
+    let mut max_len = 0;
+    let mut max_change = 0;
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for stat in stats {
+        max_len = max_len.max(stat.name.chars().count());
+        if stat.unmerged {
+            continue;
+        }
+        if stat.binary {
+            // "Bin XXX -> YYY bytes"
+            bin_width = bin_width.max(14 + decimal_width(stat.added) + decimal_width(stat.removed));
+            number_width = 3;
+            continue;
+        }
+        max_change = max_change.max(stat.added + stat.removed);
+    }
+    number_width = number_width.max(decimal_width(max_change));
+
+    // the name gets what it needs, the graph at most 3/8 of the width when both don't fit
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let mut total_files = 0;
+    let mut total_added = 0;
+    let mut total_removed = 0;
+    for stat in stats {
+        let mut name: &str = &stat.name;
+        let mut prefix = "";
+        let mut len = name_width;
+        if name_width < name.chars().count() {
+            // keep the end of long names, from a directory boundary if possible
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            let start = name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i);
+            name = &name[start..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+
+        write!(
+            out,
+            " {}{}{:padding$} | ",
+            prefix,
+            name,
+            "",
+            padding = padding
+        )?;
+        if stat.unmerged {
+            writeln!(out, "Unmerged")?;
+            continue;
+        }
+
+        total_files += 1;
+        if stat.binary {
+            write!(out, "{:>width$}", "Bin", width = number_width)?;
+            if stat.added == 0 && stat.removed == 0 {
+                writeln!(out)?;
+                continue;
+            }
+            writeln!(
+                out,
+                " {}{}{} -> {}{}{} bytes",
+                del_color, stat.removed, reset, add_color, stat.added, reset
+            )?;
+            continue;
+        }
+
+        total_added += stat.added;
+        total_removed += stat.removed;
+        let total = stat.added + stat.removed;
+        let (mut add, mut del) = (stat.added, stat.removed);
+        if graph_width <= max_change {
+            let mut scaled = scale_linear(total, graph_width, max_change);
+            if scaled < 2 && add > 0 && del > 0 {
+                scaled = 2;
+            }
+            if add < del {
+                add = scale_linear(add, graph_width, max_change);
+                del = scaled - add;
+            } else {
+                del = scale_linear(del, graph_width, max_change);
+                add = scaled - del;
+            }
+        }
+
+        write!(
+            out,
+            "{:>width$}{}",
+            total,
+            if total > 0 { " " } else { "" },
+            width = number_width
+        )?;
+        if add > 0 {
+            write!(out, "{}{}{}", add_color, "+".repeat(add), reset)?;
+        }
+        if del > 0 {
+            write!(out, "{}{}{}", del_color, "-".repeat(del), reset)?;
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(out, "{}", summary(total_files, total_added, total_removed))?;
+    Ok(())
+}
+
+/// the last line of --stat: " 2 files changed, 3 insertions(+), 1 deletion(-)"
+pub fn summary(files: usize, insertions: usize, deletions: usize) -> String {
+    if files == 0 {
+        return String::from(" 0 files changed");
+    }
+
+    let plural = |n: usize, one: &str, many: &str| match n {
+        1 => format!("{} {}", n, one),
+        n => format!("{} {}", n, many),
+    };
+    let mut line = format!(" {} changed", plural(files, "file", "files"));
+    if insertions > 0 || deletions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        ));
+    }
+    line
+}
+
+/// the width --stat output fits in: $COLUMNS, or 80 columns
+pub fn stat_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_STAT_WIDTH)
+}
+
+/// prints the created, deleted and renamed files and the mode changes like git diff --summary
+pub fn write_summary<W: Write>(out: &mut W, changes: &[Change]) -> Result<()> {
+    for change in changes {
+        match (&change.old, &change.new) {
+            (None, Some(new)) => writeln!(out, " create mode {:06o} {}", new.mode, new.path)?,
+            (Some(old), None) => writeln!(out, " delete mode {:06o} {}", old.mode, old.path)?,
+            (Some(old), Some(new)) => {
+                let verb = match change.status {
+                    ChangeStatus::Renamed => "rename",
+                    ChangeStatus::Copied => "copy",
+                    _ => {
+                        if old.mode != new.mode {
+                            writeln!(
+                                out,
+                                " mode change {:06o} => {:06o} {}",
+                                old.mode, new.mode, new.path
+                            )?;
+                        }
+                        continue;
+                    }
+                };
+                let similarity = change.similarity().unwrap_or(100);
+                writeln!(
+                    out,
+                    " {} {} ({}%)",
+                    verb,
+                    rename_name(&old.path, &new.path),
+                    similarity
+                )?;
+                if old.mode != new.mode {
+                    writeln!(out, " mode change {:06o} => {:06o}", old.mode, new.mode)?;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// prints the stat and the summary of the changes between two trees, as shown after a merge
+pub fn write_tree_stat<W: Write>(out: &mut W, old: Option<&Hash>, new: &Hash) -> Result<()> {
+    let (changes, stats) = tree_stats(old, new)?;
+    write_stat(out, &stats, false, stat_width())?;
+    write_summary(out, &changes)
+}
+
+/// prints the totals and the summary of the changes between two trees, as shown after a commit
+pub fn write_tree_shortstat<W: Write>(out: &mut W, old: Option<&Hash>, new: &Hash) -> Result<()> {
+    let (changes, stats) = tree_stats(old, new)?;
+    let counted = stats.iter().filter(|stat| !stat.binary);
+    let insertions = counted.clone().map(|stat| stat.added).sum();
+    let deletions = counted.map(|stat| stat.removed).sum();
+    writeln!(out, "{}", summary(stats.len(), insertions, deletions))?;
+    write_summary(out, &changes)
+}
+
+/// the changes between two trees, with renames, and their line counts
+fn tree_stats(old: Option<&Hash>, new: &Hash) -> Result<(Vec<Change>, Vec<FileStat>)> {
+    let options = TreeDiffOptions {
+        recursive: true,
+        find_renames: true,
+        rename_score: DEFAULT_RENAME_SCORE,
+        ..Default::default()
+    };
+    let changes = diff_trees(old, Some(new), &options)?;
+
+    let mut stats = Vec::new();
+    for change in &changes {
+        let old = match &change.old {
+            Some(old) => read_content(old, ContentSource::Objects)?,
+            None => Vec::new(),
+        };
+        let new = match &change.new {
+            Some(new) => read_content(new, ContentSource::Objects)?,
+            None => Vec::new(),
+        };
+        let patch = make_patch(change.clone(), &old, &new, &PatchOptions::default());
+        stats.push(FileStat::new(&patch, old.len(), new.len()));
+    }
+
+    Ok((changes, stats))
+}
+
+/// prints the changes like git diff --numstat: added, removed and name separated by tabs
+pub fn write_numstat<W: Write>(out: &mut W, stats: &[FileStat]) -> Result<()> {
+    for stat in stats {
+        match stat.binary {
+            true => writeln!(out, "-\t-\t{}", stat.name)?,
+            false => writeln!(out, "{}\t{}\t{}", stat.added, stat.removed, stat.name)?,
+        }
+    }
+    Ok(())
+}
+
+/// scales a count to the graph width, keeping at least one column for non-zero counts
+fn scale_linear(n: usize, width: usize, max_change: usize) -> usize {
+    if n == 0 {
+        return 0;
+    }
+    1 + n * (width - 1) / max_change
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{rename_name, summary};
+
+    #[test]
+    fn test_rename_name() {
+        assert_eq!(rename_name("a.txt", "b.txt"), "a.txt => b.txt");
+        assert_eq!(rename_name("src/a.rs", "src/b.rs"), "src/{a.rs => b.rs}");
+        assert_eq!(rename_name("a/x/f", "b/x/f"), "{a => b}/x/f");
+        assert_eq!(rename_name("d/f", "d/e/f"), "d/{ => e}/f");
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(summary(1, 1, 0), " 1 file changed, 1 insertion(+)");
+        assert_eq!(summary(2, 0, 3), " 2 files changed, 3 deletions(-)");
+        assert_eq!(
+            summary(1, 0, 0),
+            " 1 file changed, 0 insertions(+), 0 deletions(-)"
+        );
+    }
+}
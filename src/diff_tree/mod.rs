@@ -0,0 +1,98 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    diff::{diff_trees, Change, TreeDiffOptions},
+    objects::{commit::decode_commit, hash::Hash, Object, ObjectKind},
+    rev_parse::{peel, resolve_revision},
+};
+
+/// how changes are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffTreeOutput {
+    /// :<old mode> <new mode> <old hash> <new hash> <status>\t<path>
+    #[default]
+    Raw,
+    NameOnly,
+    NameStatus,
+}
+
+pub struct DiffTreeOptions {
+    /// a commit, compared with its first parent, or two tree-ish objects
+    pub objects: Vec<String>,
+    pub output: DiffTreeOutput,
+    /// show the changes of a root commit as additions
+    pub root: bool,
+    pub diff: TreeDiffOptions,
+}
+
+/// compares the content and mode of the blobs found in two tree objects
+pub fn diff_tree(options: DiffTreeOptions) -> Result<()> {
+    let (header, old, new) = match options.objects.as_slice() {
+        [commit] => {
+            let hash = peel(resolve_revision(commit)?, Some(ObjectKind::Commit))?;
+            let commit = decode_commit(Object::read_from_hash(hash.to_hex())?.data)?;
+            match commit.parents.first() {
+                Some(parent) => {
+                    let parent = peel(parent.clone(), Some(ObjectKind::Tree))?;
+                    (Some(hash), Some(parent), commit.tree)
+                }
+                None if options.root => (Some(hash), None, commit.tree),
+                None => return Ok(()),
+            }
+        }
+        [old, new] => {
+            let old = peel(resolve_revision(old)?, Some(ObjectKind::Tree))?;
+            let new = peel(resolve_revision(new)?, Some(ObjectKind::Tree))?;
+            (None, Some(old), new)
+        }
+        _ => bail!("diff-tree takes a commit or two tree-ish objects"),
+    };
+
+    let changes = diff_trees(old.as_ref(), Some(&new), &options.diff)?;
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(header) = header {
+        println!("{:x}", header);
+    }
+
+    for change in &changes {
+        println!("{}", format_change(change, options.output));
+    }
+
+    Ok(())
+}
+
+/// formats a change like git's raw, --name-only and --name-status outputs
+pub fn format_change(change: &Change, output: DiffTreeOutput) -> String {
+    let status = match change.similarity() {
+        Some(similarity) => format!("{}{:03}", change.status.letter(), similarity),
+        None => change.status.letter().to_string(),
+    };
+    let paths = match (&change.old, &change.new, change.score) {
+        (Some(old), Some(new), Some(_)) => format!("{}\t{}", old.path, new.path),
+        _ => change.path().to_string(),
+    };
+
+    match output {
+        DiffTreeOutput::NameOnly => change.path().to_string(),
+        DiffTreeOutput::NameStatus => format!("{}\t{}", status, paths),
+        DiffTreeOutput::Raw => {
+            let null = Hash(vec![0; 20]);
+            let (old_mode, old_hash) = match &change.old {
+                Some(old) => (old.mode as u32, &old.hash),
+                None => (0, &null),
+            };
+            let (new_mode, new_hash) = match &change.new {
+                Some(new) => (new.mode as u32, &new.hash),
+                None => (0, &null),
+            };
+
+            format!(
+                ":{:06o} {:06o} {:x} {:x} {}\t{}",
+                old_mode, new_mode, old_hash, new_hash, status, paths
+            )
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{env, path::Path, process::Command};
+
+use anyhow::{bail, Result};
+
+use crate::config::read_config;
+
+/// editor used when none is configured
+const DEFAULT_EDITOR: &str = "vi";
+
+/// returns the editor of commit messages: $GIT_EDITOR, core.editor, $VISUAL, $EDITOR, then vi
+pub fn editor() -> Result<String> {
+    if let Ok(editor) = env::var("GIT_EDITOR") {
+        return Ok(editor);
+    }
+    if let Some(editor) = read_config()?.get("core.editor") {
+        return Ok(editor.to_string());
+    }
+
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| env::var(name).ok());
+    Ok(editor.unwrap_or(String::from(DEFAULT_EDITOR)))
+}
+
+/// returns the editor of todo lists: $GIT_SEQUENCE_EDITOR, sequence.editor, then the editor
+/// of commit messages
+pub fn sequence_editor() -> Result<String> {
+    if let Ok(editor) = env::var("GIT_SEQUENCE_EDITOR") {
+        return Ok(editor);
+    }
+    match read_config()?.get("sequence.editor") {
+        Some(editor) => Ok(editor.to_string()),
+        None => editor(),
+    }
+}
+
+/// lets the user edit a file. the editor is a shell command, given the path as argument.
+pub fn launch_editor(editor: &str, path: &Path) -> Result<()> {
+    // ":" is the way to say that nothing should be edited
+    if editor == ":" {
+        return Ok(());
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        bail!("there was a problem with the editor '{}'", editor);
+    }
+    Ok(())
+}
+
+/// cleans up a message the way commit messages are: trailing whitespace is removed, runs of
+/// empty lines are collapsed, and leading and trailing empty lines are dropped. comment lines,
+/// starting with '#', are removed too if strip_comments is set.
+pub fn cleanup_message(message: &str, strip_comments: bool) -> String {
+    let mut cleaned = String::new();
+    let mut blank = false;
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !cleaned.is_empty() {
+            cleaned.push('\n');
+        }
+        blank = false;
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+#[cfg(test)]
+mod test {
+    use super::cleanup_message;
+
+    #[test]
+    fn test_cleanup_message() {
+        let message = "\n\nsubject  \n\n\n\nbody\n# comment\n\n";
+        assert_eq!(cleanup_message(message, true), "subject\n\nbody\n");
+        assert_eq!(
+            cleanup_message(message, false),
+            "subject\n\nbody\n# comment\n"
+        );
+        assert_eq!(cleanup_message("# only comments\n", true), "");
+    }
+}
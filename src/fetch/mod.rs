@@ -0,0 +1,655 @@
+use std::{collections::HashSet, env, fs};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::{read_config, Config},
+    gc::auto_gc,
+    merge_base::CommitGraph,
+    objects::{hash::Hash, Object, ObjectKind},
+    pack_protocol::{
+        fetch_pack::fetch_pack,
+        protocol_v2::ls_refs,
+        sideband::{ProgressSink, StderrProgress},
+        transport::{protocol_version, Advertisement, Service, Transport},
+    },
+    refs::{
+        delete_ref, head_branch, list_refs, read_symbolic_ref,
+        refspec::{local_ref_name, Refspec},
+        resolve_ref, update_ref,
+    },
+    remote::{default_remote, remote, Remote},
+    rev_parse::{abbreviate, peel_kind},
+};
+
+const FETCH_HEAD: &str = ".git/FETCH_HEAD";
+
+/// width of the summary column, enough for "abbrev...abbrev"
+const SUMMARY_WIDTH: usize = 17;
+
+/// minimum width of the column of remote ref names
+const REF_COLUMN_WIDTH: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// the remote to fetch from, a configured name or a url. the remote of the current
+    /// branch, or origin, by default.
+    pub remote: Option<String>,
+    /// the refspecs to fetch instead of the configured ones
+    pub refspecs: Vec<String>,
+    /// delete the remote-tracking refs whose refs are gone from the remote
+    pub prune: bool,
+    /// fetch all the tags of the remote, as with refs/tags/*:refs/tags/*
+    pub tags: bool,
+    /// update refs even when the update isn't a fast-forward
+    pub force: bool,
+    /// the command run on the remote side instead of git-upload-pack
+    pub upload_pack: Option<String>,
+    /// the message reflogs record updates under, like "fetch origin"
+    pub reflog_action: String,
+    /// don't report ref updates
+    pub quiet: bool,
+    /// show the progress the remote reports on stderr
+    pub progress: bool,
+}
+
+/// a remote ref to fetch, and where to store it
+#[derive(Debug, Clone)]
+struct FetchRef {
+    /// the name of the ref on the remote
+    name: String,
+    hash: Hash,
+    /// the local ref to update, if any
+    dst: Option<String>,
+    force: bool,
+    /// whether the ref is for a following merge, or only recorded in FETCH_HEAD
+    for_merge: bool,
+    /// whether the ref is recorded in FETCH_HEAD at all
+    in_fetch_head: bool,
+}
+
+/// fetches refs and the objects they need from a remote, and updates the local refs the
+/// refspecs map them to. returns false if a ref couldn't be updated.
+pub fn fetch(options: FetchOptions) -> Result<bool> {
+    let config = read_config()?;
+    let branch = head_branch()?.map(|name| name.trim_start_matches("refs/heads/").to_string());
+    let remote_name = options
+        .remote
+        .clone()
+        .unwrap_or_else(|| default_remote(&config, branch.as_deref()));
+    let remote = remote(&config, &remote_name)?;
+    let upload_pack = options
+        .upload_pack
+        .clone()
+        .unwrap_or_else(|| remote.upload_pack.clone());
+
+    let auto_follow = !options.tags && remote.configured;
+    let prefixes = ref_prefixes(&options, &config, &remote, branch.as_deref(), auto_follow)?;
+    let version = protocol_version(&config)?;
+
+    let (mut transport, mut advertisement) =
+        Transport::connect(&remote.url, Service::UploadPack, &upload_pack, version)?;
+    if advertisement.version == 2 {
+        ls_refs(&mut transport, &mut advertisement, &prefixes)?;
+    }
+
+    let planned = fetch_map(
+        &options,
+        &config,
+        &remote,
+        branch.as_deref(),
+        &advertisement,
+    )
+    .and_then(|(refspecs, mut fetch_refs)| {
+        if auto_follow {
+            let tags = followed_tags(&advertisement, &fetch_refs)?;
+            add_followed_tags(&mut fetch_refs, tags);
+        }
+        check_not_current_branch(&fetch_refs)?;
+        Ok((refspecs, fetch_refs))
+    });
+    let (refspecs, mut fetch_refs) = match planned {
+        Ok(planned) => planned,
+        Err(err) => {
+            transport.abort()?;
+            return Err(err);
+        }
+    };
+
+    let wants: Vec<Hash> = fetch_refs.iter().map(|r| r.hash.clone()).collect();
+    let mut progress = StderrProgress::new();
+    fetch_pack(
+        &mut transport,
+        &advertisement,
+        &wants,
+        &local_tips()?,
+        auto_follow,
+        options
+            .progress
+            .then_some(&mut progress as &mut dyn ProgressSink),
+    )?;
+    transport.close()?;
+
+    // tags pointing into the history that was just fetched can only be followed now.
+    // include-tag brought their objects along, unless the remote doesn't support it.
+    if auto_follow {
+        let tags = followed_tags(&advertisement, &fetch_refs)?;
+        let mut missing = Vec::new();
+        for tag in &tags {
+            if !Object::exists(&tag.hash)? {
+                missing.push(tag.hash.clone());
+            }
+        }
+        if !missing.is_empty() {
+            let (mut transport, advertisement) =
+                Transport::connect(&remote.url, Service::UploadPack, &upload_pack, version)?;
+            fetch_pack(
+                &mut transport,
+                &advertisement,
+                &missing,
+                &local_tips()?,
+                false,
+                None,
+            )?;
+            transport.close()?;
+        }
+        add_followed_tags(&mut fetch_refs, tags);
+    }
+
+    let url = display_url(&remote.url);
+    let width = fetch_refs
+        .iter()
+        .filter(|r| r.dst.is_some() && r.name != "HEAD")
+        .map(|r| pretty_ref_name(&r.name).len())
+        .fold(REF_COLUMN_WIDTH, usize::max);
+    let mut output = Output {
+        url: url.clone(),
+        header_shown: false,
+        width,
+        quiet: options.quiet,
+    };
+
+    if options.prune {
+        prune(&refspecs, &advertisement, &mut output)?;
+    }
+
+    write_fetch_head(&fetch_refs, &url)?;
+
+    let mut graph = CommitGraph::new();
+    let mut updated_all = true;
+    for fetch_ref in &fetch_refs {
+        updated_all &= update_local_ref(fetch_ref, &options, &mut graph, &mut output)?;
+    }
+
+    auto_gc(options.quiet)?;
+    Ok(updated_all)
+}
+
+/// the prefixes of the refs the refspecs can fetch, so that a version 2 remote only lists
+/// those
+fn ref_prefixes(
+    options: &FetchOptions,
+    config: &Config,
+    remote: &Remote,
+    branch: Option<&str>,
+    auto_follow: bool,
+) -> Result<Vec<String>> {
+    let mut prefixes = Vec::new();
+    if !options.refspecs.is_empty() {
+        for spec in &options.refspecs {
+            prefixes.extend(Refspec::parse(spec)?.ref_prefixes());
+        }
+    } else if !remote.fetch.is_empty() {
+        for refspec in &remote.fetch {
+            prefixes.extend(refspec.ref_prefixes());
+        }
+        if let Some(branch) = branch {
+            if config.get(&format!("branch.{}.remote", branch)) == Some(&remote.name) {
+                prefixes.extend(
+                    config
+                        .get_all(&format!("branch.{}.merge", branch))
+                        .into_iter()
+                        .map(|merge| merge.to_string()),
+                );
+            }
+        }
+    } else {
+        prefixes.push("HEAD".to_string());
+    }
+
+    if options.tags || auto_follow {
+        prefixes.push("refs/tags/".to_string());
+    }
+    Ok(prefixes)
+}
+
+/// maps the refs of the remote through the refspecs, returning the refspecs that were
+/// used, for pruning, and the refs to fetch
+fn fetch_map(
+    options: &FetchOptions,
+    config: &Config,
+    remote: &Remote,
+    branch: Option<&str>,
+    advertisement: &Advertisement,
+) -> Result<(Vec<Refspec>, Vec<FetchRef>)> {
+    let remote_refs: Vec<String> = advertisement
+        .refs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    let hash_of = |name: &str| {
+        advertisement
+            .refs
+            .iter()
+            .find(|(ref_name, _)| ref_name == name)
+            .map(|(_, hash)| hash.clone())
+            .expect("the ref is advertised")
+    };
+    let mut fetch_refs = Vec::new();
+
+    let mut refspecs = Vec::new();
+    if !options.refspecs.is_empty() {
+        // refs named on the command line are all for merge. configured refspecs still
+        // update the remote-tracking refs of the refs fetched.
+        for spec in &options.refspecs {
+            let refspec = Refspec::parse(spec)?;
+            if refspec.is_pattern() {
+                fetch_refs.extend(map_pattern(&refspec, &remote_refs, &hash_of, true));
+            } else {
+                let name = match refspec.find_src(&remote_refs) {
+                    Some(name) => name.clone(),
+                    None => bail!("couldn't find remote ref {}", refspec.src),
+                };
+                fetch_refs.push(FetchRef {
+                    hash: hash_of(&name),
+                    dst: refspec.dst.as_deref().map(local_ref_name),
+                    force: refspec.force,
+                    for_merge: true,
+                    in_fetch_head: true,
+                    name,
+                });
+            }
+            refspecs.push(refspec);
+        }
+
+        let fetched: Vec<String> = fetch_refs.iter().map(|r| r.name.clone()).collect();
+        for refspec in &remote.fetch {
+            for name in &fetched {
+                if let Some(dst) = refspec.map(name) {
+                    if !fetch_refs.iter().any(|r| r.dst.as_deref() == Some(&dst)) {
+                        fetch_refs.push(FetchRef {
+                            name: name.clone(),
+                            hash: hash_of(name),
+                            dst: Some(dst),
+                            force: refspec.force,
+                            for_merge: false,
+                            in_fetch_head: false,
+                        });
+                    }
+                }
+            }
+        }
+    } else if !remote.fetch.is_empty() {
+        // the merge config of the current branch picks the refs for merge, or else the
+        // first refspec when it names a single ref
+        let merge_refs: Vec<&str> = match branch {
+            Some(branch)
+                if config.get(&format!("branch.{}.remote", branch)) == Some(&remote.name) =>
+            {
+                config.get_all(&format!("branch.{}.merge", branch))
+            }
+            _ => Vec::new(),
+        };
+        for (i, refspec) in remote.fetch.iter().enumerate() {
+            if refspec.is_pattern() {
+                for mut fetch_ref in map_pattern(refspec, &remote_refs, &hash_of, false) {
+                    fetch_ref.for_merge = merge_refs.contains(&fetch_ref.name.as_str());
+                    fetch_refs.push(fetch_ref);
+                }
+            } else if let Some(name) = refspec.find_src(&remote_refs) {
+                fetch_refs.push(FetchRef {
+                    hash: hash_of(name),
+                    dst: refspec.dst.as_deref().map(local_ref_name),
+                    force: refspec.force,
+                    for_merge: merge_refs.contains(&name.as_str())
+                        || (merge_refs.is_empty() && i == 0),
+                    in_fetch_head: true,
+                    name: name.clone(),
+                });
+            }
+        }
+        refspecs.extend(remote.fetch.iter().cloned());
+    } else if remote_refs.iter().any(|name| name == "HEAD") {
+        // without refspecs, only the remote HEAD is fetched, for merge
+        fetch_refs.push(FetchRef {
+            name: "HEAD".to_string(),
+            hash: hash_of("HEAD"),
+            dst: None,
+            force: false,
+            for_merge: true,
+            in_fetch_head: true,
+        });
+    }
+
+    if options.tags {
+        let refspec = Refspec::parse("refs/tags/*:refs/tags/*")?;
+        for fetch_ref in map_pattern(&refspec, &remote_refs, &hash_of, false) {
+            if !fetch_refs.iter().any(|r| r.dst == fetch_ref.dst) {
+                fetch_refs.push(fetch_ref);
+            }
+        }
+        refspecs.push(refspec);
+    }
+
+    Ok((refspecs, fetch_refs))
+}
+
+fn map_pattern(
+    refspec: &Refspec,
+    remote_refs: &[String],
+    hash_of: &impl Fn(&str) -> Hash,
+    for_merge: bool,
+) -> Vec<FetchRef> {
+    remote_refs
+        .iter()
+        .filter_map(|name| {
+            let dst = refspec.map(name)?;
+            Some(FetchRef {
+                name: name.clone(),
+                hash: hash_of(name),
+                dst: Some(dst),
+                force: refspec.force,
+                for_merge,
+                in_fetch_head: true,
+            })
+        })
+        .collect()
+}
+
+/// the refs and HEAD of the repository, which a remote is told about during negotiation
+fn local_tips() -> Result<Vec<Hash>> {
+    let mut tips: Vec<Hash> = list_refs("refs/")?.into_iter().map(|(_, h)| h).collect();
+    tips.extend(resolve_ref("HEAD")?);
+    Ok(tips)
+}
+
+/// followed tags come before the refs only updated opportunistically
+fn add_followed_tags(fetch_refs: &mut Vec<FetchRef>, tags: Vec<FetchRef>) {
+    let at = fetch_refs
+        .iter()
+        .position(|r| !r.in_fetch_head)
+        .unwrap_or(fetch_refs.len());
+    fetch_refs.splice(at..at, tags);
+}
+
+/// the tags of the remote pointing at objects that are fetched or already here, which
+/// are fetched along with them when they are missing locally
+fn followed_tags(advertisement: &Advertisement, fetch_refs: &[FetchRef]) -> Result<Vec<FetchRef>> {
+    let fetched: HashSet<&Hash> = fetch_refs.iter().map(|r| &r.hash).collect();
+    let mut tags = Vec::new();
+    for (name, hash) in &advertisement.refs {
+        if !name.starts_with("refs/tags/")
+            || fetch_refs
+                .iter()
+                .any(|r| r.dst.as_deref() == Some(name.as_str()))
+            || resolve_ref(name)?.is_some()
+        {
+            continue;
+        }
+
+        let target = advertisement.peeled.get(name).unwrap_or(hash);
+        if fetched.contains(target) || Object::exists(target)? {
+            tags.push(FetchRef {
+                name: name.clone(),
+                hash: hash.clone(),
+                dst: Some(name.clone()),
+                force: false,
+                for_merge: false,
+                in_fetch_head: true,
+            });
+        }
+    }
+    Ok(tags)
+}
+
+/// deletes the local refs the refspecs map from refs the remote no longer has
+fn prune(refspecs: &[Refspec], advertisement: &Advertisement, output: &mut Output) -> Result<()> {
+    let mut pruned = Vec::new();
+    for refspec in refspecs {
+        let dst = match &refspec.dst {
+            Some(dst) if refspec.is_pattern() => dst,
+            _ => continue,
+        };
+        let prefix = dst
+            .split_once('*')
+            .map_or(dst.as_str(), |(prefix, _)| prefix);
+        for (name, _) in list_refs(prefix)? {
+            let src = match refspec.map_reverse(&name) {
+                Some(src) => src,
+                None => continue,
+            };
+            if read_symbolic_ref(&name)?.is_some()
+                || pruned.contains(&name)
+                || advertisement.refs.iter().any(|(remote, _)| *remote == src)
+            {
+                continue;
+            }
+            delete_ref(&name)?;
+            output.line('-', "[deleted]", "(none)", &name, None);
+            pruned.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// updates the local ref a fetched ref maps to, reporting the update. returns false if
+/// the update was rejected.
+fn update_local_ref(
+    fetch_ref: &FetchRef,
+    options: &FetchOptions,
+    graph: &mut CommitGraph,
+    output: &mut Output,
+) -> Result<bool> {
+    let dst = match &fetch_ref.dst {
+        Some(dst) => dst,
+        None => {
+            let (kind, name) = ref_kind(&fetch_ref.name);
+            let kind = match kind {
+                "" => "branch",
+                kind => kind,
+            };
+            output.line('*', kind, name, "FETCH_HEAD", None);
+            return Ok(true);
+        }
+    };
+
+    let new = &fetch_ref.hash;
+    let old = match resolve_ref(dst)? {
+        Some(old) if old == *new => return Ok(true),
+        old => old,
+    };
+    let action = &options.reflog_action;
+    let force = fetch_ref.force || options.force;
+
+    let old = match old {
+        Some(old) => old,
+        None => {
+            let (message, summary) = if fetch_ref.name.starts_with("refs/tags/") {
+                ("storing tag", "[new tag]")
+            } else if fetch_ref.name.starts_with("refs/heads/") {
+                ("storing head", "[new branch]")
+            } else {
+                ("storing ref", "[new ref]")
+            };
+            update_ref(dst, new, &format!("{}: {}", action, message))?;
+            output.line('*', summary, &fetch_ref.name, dst, None);
+            return Ok(true);
+        }
+    };
+
+    if dst.starts_with("refs/tags/") {
+        if !force {
+            output.line(
+                '!',
+                "[rejected]",
+                &fetch_ref.name,
+                dst,
+                Some("would clobber existing tag"),
+            );
+            return Ok(false);
+        }
+        update_ref(dst, new, &format!("{}: updating tag", action))?;
+        output.line('t', "[tag update]", &fetch_ref.name, dst, None);
+        return Ok(true);
+    }
+
+    let (old_target, old_kind) = peel_kind(old.clone(), None)?;
+    let (new_target, new_kind) = peel_kind(new.clone(), None)?;
+    let fast_forward = old_kind == ObjectKind::Commit
+        && new_kind == ObjectKind::Commit
+        && graph.is_ancestor(&old_target, &new_target)?;
+    if fast_forward {
+        update_ref(dst, new, &format!("{}: fast-forward", action))?;
+        let summary = format!("{}..{}", abbreviate(&old)?, abbreviate(new)?);
+        output.line(' ', &summary, &fetch_ref.name, dst, None);
+        Ok(true)
+    } else if force {
+        update_ref(dst, new, &format!("{}: forced-update", action))?;
+        let summary = format!("{}...{}", abbreviate(&old)?, abbreviate(new)?);
+        output.line('+', &summary, &fetch_ref.name, dst, Some("forced update"));
+        Ok(true)
+    } else {
+        output.line(
+            '!',
+            "[rejected]",
+            &fetch_ref.name,
+            dst,
+            Some("non-fast-forward"),
+        );
+        Ok(false)
+    }
+}
+
+/// refuses to update the branch checked out, whose index and working tree would no
+/// longer match it
+fn check_not_current_branch(fetch_refs: &[FetchRef]) -> Result<()> {
+    let branch = match head_branch()? {
+        Some(branch) => branch,
+        None => return Ok(()),
+    };
+    if fetch_refs.iter().any(|r| r.dst.as_ref() == Some(&branch)) && !is_bare()? {
+        bail!(
+            "refusing to fetch into branch '{}' checked out at '{}'",
+            branch,
+            env::current_dir()?.display()
+        );
+    }
+    Ok(())
+}
+
+fn is_bare() -> Result<bool> {
+    Ok(read_config()?.get_bool("core.bare")?.unwrap_or(false))
+}
+
+/// records the fetched refs in FETCH_HEAD, those for merge first
+fn write_fetch_head(fetch_refs: &[FetchRef], url: &str) -> Result<()> {
+    let mut content = String::new();
+    for for_merge in [true, false] {
+        for fetch_ref in fetch_refs
+            .iter()
+            .filter(|r| r.in_fetch_head && r.for_merge == for_merge)
+        {
+            let note = match ref_kind(&fetch_ref.name) {
+                ("", "HEAD") => url.to_string(),
+                ("", name) => format!("'{}' of {}", name, url),
+                (kind, name) => format!("{} '{}' of {}", kind, name, url),
+            };
+            content.push_str(&format!(
+                "{}\t{}\t{}\n",
+                fetch_ref.hash.to_hex(),
+                if for_merge { "" } else { "not-for-merge" },
+                note
+            ));
+        }
+    }
+    fs::write(FETCH_HEAD, content)?;
+    Ok(())
+}
+
+/// the kind of a remote ref, as FETCH_HEAD and the output name it, and its short name
+fn ref_kind(name: &str) -> (&'static str, &str) {
+    if name == "HEAD" {
+        ("", "HEAD")
+    } else if let Some(short) = name.strip_prefix("refs/heads/") {
+        ("branch", short)
+    } else if let Some(short) = name.strip_prefix("refs/tags/") {
+        ("tag", short)
+    } else if let Some(short) = name.strip_prefix("refs/remotes/") {
+        ("remote-tracking branch", short)
+    } else {
+        ("", name)
+    }
+}
+
+/// shortens a ref name for display, dropping refs/heads/, refs/tags/ or refs/remotes/
+fn pretty_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// the url as shown in the output and FETCH_HEAD, without trailing slashes or .git
+fn display_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_string()
+}
+
+/// reports ref updates on stderr, below a "From <url>" line shown before the first one
+struct Output {
+    url: String,
+    header_shown: bool,
+    width: usize,
+    quiet: bool,
+}
+
+impl Output {
+    fn line(&mut self, flag: char, summary: &str, remote: &str, local: &str, reason: Option<&str>) {
+        if self.quiet {
+            return;
+        }
+        if !self.header_shown {
+            eprintln!("From {}", self.url);
+            self.header_shown = true;
+        }
+        let remote = pretty_ref_name(remote);
+        let local = pretty_ref_name(local);
+        let reason = reason.map(|r| format!("  ({})", r)).unwrap_or_default();
+        eprintln!(
+            " {} {:summary_width$} {:width$} -> {}{}",
+            flag,
+            summary,
+            remote,
+            local,
+            reason,
+            summary_width = SUMMARY_WIDTH,
+            width = self.width
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{display_url, ref_kind};
+
+    #[test]
+    fn test_display_url() {
+        assert_eq!(
+            display_url("https://example.com/repo.git/"),
+            "https://example.com/repo"
+        );
+        assert_eq!(display_url("../up"), "../up");
+        assert_eq!(ref_kind("refs/heads/main"), ("branch", "main"));
+        assert_eq!(ref_kind("refs/pull/1/head"), ("", "refs/pull/1/head"));
+    }
+}
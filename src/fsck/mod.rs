@@ -0,0 +1,579 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    index::read_index,
+    objects::{
+        commit::decode_commit,
+        hash::Hash,
+        pack::{packs, verify_pack},
+        tag::decode_tag,
+        tree::EntryMode,
+        Object, ObjectKind,
+    },
+    refs::{
+        list_refs, read_symbolic_ref,
+        reflog::{list_reflogs, read_reflog},
+        resolve_ref,
+    },
+};
+
+const LOST_FOUND: &str = ".git/lost-found";
+
+/// the mode of submodule entries, which point to commits of another repository
+const GITLINK_MODE: &str = "160000";
+const GITLINK: u32 = 0o160000;
+
+pub struct FsckOptions {
+    /// check the objects of the packs too, and the packs themselves
+    pub full: bool,
+    /// only check that the objects reachable from the refs are there, without checking
+    /// the content of any object
+    pub connectivity_only: bool,
+    /// report all unreachable objects, rather than only the dangling ones
+    pub unreachable: bool,
+    /// report the unreachable objects no other object refers to
+    pub dangling: bool,
+    /// write the dangling objects to .git/lost-found
+    pub lost_found: bool,
+}
+
+/// how bad a problem found with an object is: errors make fsck fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// a problem found in a tree, by the id git's fsck reports it with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TreeProblem {
+    EmptyName,
+    FullPathname,
+    HasDot,
+    HasDotdot,
+    HasDotgit,
+    BadFilemode,
+    ZeroPaddedFilemode,
+    DuplicateEntries,
+    TreeNotSorted,
+    BadTree,
+}
+
+impl TreeProblem {
+    fn severity(&self) -> Severity {
+        match self {
+            TreeProblem::DuplicateEntries | TreeProblem::TreeNotSorted | TreeProblem::BadTree => {
+                Severity::Error
+            }
+            _ => Severity::Warning,
+        }
+    }
+
+    fn id(&self) -> &'static str {
+        match self {
+            TreeProblem::EmptyName => "emptyName",
+            TreeProblem::FullPathname => "fullPathname",
+            TreeProblem::HasDot => "hasDot",
+            TreeProblem::HasDotdot => "hasDotdot",
+            TreeProblem::HasDotgit => "hasDotgit",
+            TreeProblem::BadFilemode => "badFilemode",
+            TreeProblem::ZeroPaddedFilemode => "zeroPaddedFilemode",
+            TreeProblem::DuplicateEntries => "duplicateEntries",
+            TreeProblem::TreeNotSorted => "treeNotSorted",
+            TreeProblem::BadTree => "badTree",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            TreeProblem::EmptyName => "contains empty pathname",
+            TreeProblem::FullPathname => "contains full pathnames",
+            TreeProblem::HasDot => "contains '.'",
+            TreeProblem::HasDotdot => "contains '..'",
+            TreeProblem::HasDotgit => "contains '.git'",
+            TreeProblem::BadFilemode => "contains bad file modes",
+            TreeProblem::ZeroPaddedFilemode => "contains zero-padded file modes",
+            TreeProblem::DuplicateEntries => "contains duplicate file entries",
+            TreeProblem::TreeNotSorted => "not properly sorted",
+            TreeProblem::BadTree => "cannot be parsed as a tree",
+        }
+    }
+}
+
+/// an object another one refers to, and the kind it is expected to be
+type Link = (Hash, ObjectKind);
+
+/// an object found in the repository, with the objects it refers to
+struct Node {
+    kind: ObjectKind,
+    links: Vec<Link>,
+}
+
+struct Fsck {
+    options: FsckOptions,
+    nodes: HashMap<Hash, Node>,
+    errors: bool,
+}
+
+/// verifies the objects of the repository: that they hash to their names, that they
+/// parse, and that the objects reachable from the refs, the reflogs and the index are all
+/// there. returns false if errors were found.
+pub fn fsck(options: FsckOptions) -> Result<bool> {
+    let mut fsck = Fsck {
+        options,
+        nodes: HashMap::new(),
+        errors: false,
+    };
+
+    if !fsck.options.connectivity_only {
+        for (hash, path) in Object::list_loose()? {
+            fsck.check_loose(&hash, &path);
+        }
+        if fsck.options.full {
+            for (pack_path, _) in packs()? {
+                fsck.check_pack(&pack_path)?;
+            }
+        }
+    }
+
+    let reachable = fsck.check_connectivity()?;
+    fsck.check_unreachable(&reachable)?;
+    Ok(!fsck.errors)
+}
+
+impl Fsck {
+    fn error(&mut self, message: &str) {
+        eprintln!("error: {}", message);
+        self.errors = true;
+    }
+
+    /// reports a problem with the content of an object
+    fn report(&mut self, severity: Severity, kind: ObjectKind, hash: &Hash, message: &str) {
+        match severity {
+            Severity::Error => {
+                eprintln!("error in {} {:x}: {}", kind, hash, message);
+                self.errors = true;
+            }
+            Severity::Warning => eprintln!("warning in {} {:x}: {}", kind, hash, message),
+        }
+    }
+
+    /// whether all objects were checked up front, rather than read as they are reached
+    fn checked_all(&self) -> bool {
+        self.options.full && !self.options.connectivity_only
+    }
+
+    fn check_loose(&mut self, hash: &Hash, path: &Path) {
+        let object = fs::File::open(path)
+            .map_err(anyhow::Error::from)
+            .and_then(Object::read);
+        let object = match object {
+            Ok(object) => object,
+            Err(err) => {
+                self.error(&format!("{}: {}", path.display(), err));
+                self.error(&format!(
+                    "{:x}: object corrupt or missing: {}",
+                    hash,
+                    path.display()
+                ));
+                return;
+            }
+        };
+        match object.hash() {
+            Ok(found) if found == *hash => {}
+            Ok(found) => {
+                self.error(&format!(
+                    "{:x}: hash-path mismatch, found at: {}",
+                    found,
+                    path.display()
+                ));
+                return;
+            }
+            Err(err) => {
+                self.error(&format!("{}: {}", path.display(), err));
+                return;
+            }
+        }
+        self.check_object(hash, object);
+    }
+
+    fn check_pack(&mut self, pack_path: &Path) -> Result<()> {
+        let mut corrupt = Vec::new();
+        let problems = verify_pack(pack_path, &mut |hash, offset, object| {
+            let object = object.and_then(|object| match object.hash()? == *hash {
+                true => Ok(object),
+                false => Err(anyhow!("hash mismatch for {:x}", hash)),
+            });
+            match object {
+                Ok(object) => self.check_object(hash, object),
+                Err(err) => corrupt.push((hash.clone(), offset, err)),
+            }
+        })?;
+        for problem in problems {
+            self.error(&problem);
+        }
+        for (hash, offset, err) in corrupt {
+            self.error(&err.to_string());
+            self.error(&format!(
+                "cannot unpack {:x} from {} at offset {}",
+                hash,
+                pack_path.display(),
+                offset
+            ));
+        }
+        Ok(())
+    }
+
+    /// parses an object, checking its content, and records what it refers to
+    fn check_object(&mut self, hash: &Hash, object: Object) {
+        let kind = object.kind;
+        let links = match self.parse(hash, object) {
+            Ok(links) => links,
+            Err(err) => {
+                self.report(Severity::Error, kind, hash, &err.to_string());
+                Vec::new()
+            }
+        };
+        self.nodes.insert(hash.clone(), Node { kind, links });
+    }
+
+    fn parse(&mut self, hash: &Hash, object: Object) -> Result<Vec<Link>> {
+        let links = match object.kind {
+            ObjectKind::Blob => Vec::new(),
+            ObjectKind::Commit => {
+                let commit = decode_commit(object.data)?;
+                std::iter::once((commit.tree, ObjectKind::Tree))
+                    .chain(commit.parents.into_iter().map(|p| (p, ObjectKind::Commit)))
+                    .collect()
+            }
+            ObjectKind::Tag => {
+                let tag = decode_tag(object.data)?;
+                vec![(tag.object, tag.object_type)]
+            }
+            ObjectKind::Tree => {
+                let (links, problems) = check_tree(&object.data);
+                if !self.options.connectivity_only {
+                    for problem in problems {
+                        let message = format!("{}: {}", problem.id(), problem.message());
+                        self.report(problem.severity(), ObjectKind::Tree, hash, &message);
+                    }
+                }
+                links
+            }
+        };
+        Ok(links)
+    }
+
+    /// reads an object not checked yet, when not all of them were. blobs are only looked
+    /// for.
+    fn load(&mut self, hash: &Hash, kind: ObjectKind) -> Result<bool> {
+        if self.nodes.contains_key(hash) {
+            return Ok(true);
+        }
+        if self.checked_all() || !Object::exists(hash)? {
+            return Ok(false);
+        }
+        if kind == ObjectKind::Blob {
+            let links = Vec::new();
+            self.nodes.insert(hash.clone(), Node { kind, links });
+            return Ok(true);
+        }
+        match Object::read_from_hash(hash.to_hex()) {
+            Ok(object) => self.check_object(hash, object),
+            Err(err) => {
+                self.error(&format!("{:x}: {}", hash, err));
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// walks the objects reachable from HEAD, the refs, their reflogs and the index, and
+    /// reports those missing
+    fn check_connectivity(&mut self) -> Result<HashSet<Hash>> {
+        let mut tips: Vec<Link> = Vec::new();
+        let refs = list_refs("refs/")?;
+        if refs.is_empty() {
+            eprintln!("notice: No default references");
+        }
+        match resolve_ref("HEAD")? {
+            Some(head) => tips.push((head, ObjectKind::Commit)),
+            None => {
+                if let Some(branch) = read_symbolic_ref("HEAD")? {
+                    let branch = branch.trim_start_matches("refs/heads/");
+                    eprintln!("notice: HEAD points to an unborn branch ({})", branch);
+                }
+            }
+        }
+        for (name, hash) in refs {
+            if !self.load(&hash, ObjectKind::Commit)? {
+                self.error(&format!("{}: invalid sha1 pointer {:x}", name, hash));
+                continue;
+            }
+            let kind = self.nodes[&hash].kind;
+            tips.push((hash, kind));
+        }
+        let null = Hash(vec![0; 20]);
+        for name in list_reflogs()? {
+            for entry in read_reflog(&name)? {
+                for hash in [entry.old, entry.new] {
+                    if hash == null {
+                        continue;
+                    }
+                    match self.load(&hash, ObjectKind::Commit)? {
+                        true => tips.push((hash, ObjectKind::Commit)),
+                        false => self.error(&format!("{}: invalid reflog entry {:x}", name, hash)),
+                    }
+                }
+            }
+        }
+        for entry in read_index()?.entries {
+            if entry.mode != GITLINK {
+                tips.push((entry.hash, ObjectKind::Blob));
+            }
+        }
+
+        let mut reachable = HashSet::new();
+        // objects are reached along with the object linking to them, if any
+        let mut stack: Vec<(Link, Option<Link>)> =
+            tips.into_iter().rev().map(|tip| (tip, None)).collect();
+        while let Some(((hash, kind), parent)) = stack.pop() {
+            if reachable.contains(&hash) {
+                continue;
+            }
+            if !self.load(&hash, kind)? {
+                if let Some((parent, parent_kind)) = parent {
+                    println!(
+                        "broken link from {:>7} {:x}",
+                        parent_kind.to_string(),
+                        parent
+                    );
+                    println!("              to {:>7} {:x}", kind.to_string(), hash);
+                }
+                println!("missing {} {:x}", kind, hash);
+                self.errors = true;
+                reachable.insert(hash);
+                continue;
+            }
+            reachable.insert(hash.clone());
+            let node = &self.nodes[&hash];
+            let parent = (hash.clone(), node.kind);
+            for link in node.links.iter().rev() {
+                stack.push((link.clone(), Some(parent.clone())));
+            }
+        }
+        Ok(reachable)
+    }
+
+    /// reports the objects nothing reachable refers to, and writes them to lost-found
+    fn check_unreachable(&mut self, reachable: &HashSet<Hash>) -> Result<()> {
+        if !self.options.unreachable && !self.options.dangling && !self.options.lost_found {
+            return Ok(());
+        }
+
+        // without checking all objects first, the unreachable ones are still to be read
+        if !self.checked_all() {
+            let mut hashes: Vec<Hash> = Object::list_loose()?
+                .into_iter()
+                .map(|(hash, _)| hash)
+                .collect();
+            for (_, index) in packs()? {
+                hashes.extend(index.entries().iter().map(|entry| entry.hash.clone()));
+            }
+            for hash in hashes {
+                if !reachable.contains(&hash) && !self.nodes.contains_key(&hash) {
+                    if let Ok(object) = Object::read_from_hash(hash.to_hex()) {
+                        self.check_object(&hash, object);
+                    }
+                }
+            }
+        }
+
+        let used: HashSet<&Hash> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.links.iter().map(|(hash, _)| hash))
+            .collect();
+        let mut unreachable: Vec<(&Hash, &Node)> = self
+            .nodes
+            .iter()
+            .filter(|(hash, _)| !reachable.contains(*hash))
+            .collect();
+        unreachable.sort_by_key(|(hash, _)| *hash);
+
+        for (hash, node) in unreachable {
+            if self.options.unreachable {
+                println!("unreachable {} {:x}", node.kind, hash);
+                continue;
+            }
+            if used.contains(hash) {
+                continue;
+            }
+            if self.options.dangling {
+                println!("dangling {} {:x}", node.kind, hash);
+            }
+            if self.options.lost_found {
+                write_lost_found(hash, node.kind)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// checks the entries of a tree, and returns the objects they point to
+fn check_tree(data: &[u8]) -> (Vec<Link>, Vec<TreeProblem>) {
+    let mut links = Vec::new();
+    let mut problems = Vec::new();
+    let mut report = |problem: TreeProblem| {
+        if !problems.contains(&problem) {
+            problems.push(problem);
+        }
+    };
+
+    let mut rest = data;
+    let mut previous: Option<(Vec<u8>, bool)> = None;
+    while !rest.is_empty() {
+        let entry = rest
+            .iter()
+            .position(|b| *b == 0)
+            .filter(|end| rest.len() >= end + 21)
+            .and_then(|end| {
+                let (mode, name) =
+                    rest[..end].split_at(rest[..end].iter().position(|b| *b == b' ')?);
+                let hash = Hash(rest[end + 1..end + 21].to_vec());
+                Some((mode, &name[1..], hash, end + 21))
+            });
+        let (mode, name, hash, length) = match entry {
+            Some(entry) => entry,
+            None => {
+                report(TreeProblem::BadTree);
+                break;
+            }
+        };
+        rest = &rest[length..];
+
+        let mode = String::from_utf8_lossy(mode);
+        let is_dir = mode == "40000";
+        if mode.starts_with('0') {
+            report(TreeProblem::ZeroPaddedFilemode);
+        } else if mode != GITLINK_MODE && EntryMode::try_from(mode.as_ref()).is_err() {
+            report(TreeProblem::BadFilemode);
+        }
+        match name {
+            b"" => report(TreeProblem::EmptyName),
+            b"." => report(TreeProblem::HasDot),
+            b".." => report(TreeProblem::HasDotdot),
+            name if name.eq_ignore_ascii_case(b".git") => report(TreeProblem::HasDotgit),
+            name if name.contains(&b'/') => report(TreeProblem::FullPathname),
+            _ => {}
+        }
+
+        // entries are sorted as if directory names ended with a slash
+        let key = |name: &[u8], is_dir: bool| {
+            let mut key = name.to_vec();
+            if is_dir {
+                key.push(b'/');
+            }
+            key
+        };
+        if let Some((previous_name, previous_is_dir)) = &previous {
+            if previous_name == name {
+                report(TreeProblem::DuplicateEntries);
+            } else if key(previous_name, *previous_is_dir) > key(name, is_dir) {
+                report(TreeProblem::TreeNotSorted);
+            }
+        }
+        previous = Some((name.to_vec(), is_dir));
+
+        let kind = match mode.as_ref() {
+            GITLINK_MODE => continue,
+            "40000" => ObjectKind::Tree,
+            _ => ObjectKind::Blob,
+        };
+        links.push((hash, kind));
+    }
+
+    (links, problems)
+}
+
+/// writes a dangling object to .git/lost-found: commits under commit/, and other objects
+/// under other/. blobs are written with their content, other objects with their name.
+fn write_lost_found(hash: &Hash, kind: ObjectKind) -> Result<()> {
+    let dir = match kind {
+        ObjectKind::Commit => "commit",
+        _ => "other",
+    };
+    let dir = PathBuf::from(LOST_FOUND).join(dir);
+    fs::create_dir_all(&dir)?;
+    let content = match kind {
+        ObjectKind::Blob => Object::read_from_hash(hash.to_hex())?.data,
+        _ => format!("{:x}\n", hash).into_bytes(),
+    };
+    fs::write(dir.join(hash.to_hex()), content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::objects::{
+        hash::Hash,
+        tree::{encode_tree, new_tree, Entry, EntryMode},
+        ObjectKind,
+    };
+
+    use super::{check_tree, TreeProblem};
+
+    fn raw_tree(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (mode, name) in entries {
+            data.extend(format!("{} {}\0", mode, name).into_bytes());
+            data.extend([0xaa; 20]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let hash = Hash(vec![0xaa; 20]);
+        let entry = |mode, name: &str| Entry {
+            mode,
+            name: name.to_string(),
+            hash: hash.clone(),
+        };
+        let tree = new_tree(vec![
+            entry(EntryMode::RegularFile, "a.txt"),
+            entry(EntryMode::Directory, "a"),
+            entry(EntryMode::ExecutableFile, "a-b"),
+        ]);
+        let (links, problems) = check_tree(&encode_tree(tree));
+        assert!(problems.is_empty());
+        let kinds: Vec<ObjectKind> = links.into_iter().map(|(_, kind)| kind).collect();
+        assert_eq!(
+            kinds,
+            [ObjectKind::Blob, ObjectKind::Blob, ObjectKind::Tree]
+        );
+
+        let check = |entries: &[(&str, &str)]| check_tree(&raw_tree(entries)).1;
+        assert_eq!(
+            check(&[("100644", "b"), ("100644", "a")]),
+            [TreeProblem::TreeNotSorted]
+        );
+        assert_eq!(
+            check(&[("100644", "a"), ("40000", "a")]),
+            [TreeProblem::DuplicateEntries]
+        );
+        assert_eq!(check(&[("100644", ".GIT")]), [TreeProblem::HasDotgit]);
+        assert_eq!(check(&[("40000", "..")]), [TreeProblem::HasDotdot]);
+        assert_eq!(check(&[("100644", "a/b")]), [TreeProblem::FullPathname]);
+        assert_eq!(check(&[("100664", "a")]), [TreeProblem::BadFilemode]);
+        assert_eq!(check(&[("040000", "a")]), [TreeProblem::ZeroPaddedFilemode]);
+        assert!(check(&[("160000", "sub")]).is_empty());
+        assert_eq!(check_tree(b"100644 a\0short").1, [TreeProblem::BadTree]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{collections::HashSet, fs, time::UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::{
+    config::{read_config, Config},
+    date::parse_date,
+    merge_base::CommitGraph,
+    objects::{hash::Hash, pack::packs, Object},
+    refs::{
+        pack_refs,
+        reflog::{list_reflogs, read_reflog, write_reflog},
+        resolve_ref,
+    },
+    repack::{reachable_objects, repack, RepackOptions},
+};
+
+/// about this many loose objects make gc --auto pack them, like git's gc.auto
+const DEFAULT_AUTO_THRESHOLD: u64 = 6700;
+
+/// more packs than this make gc --auto consolidate them, like git's gc.autoPackLimit
+const DEFAULT_AUTO_PACK_LIMIT: u64 = 50;
+
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+const DEFAULT_REFLOG_EXPIRE: &str = "90.days.ago";
+const DEFAULT_REFLOG_EXPIRE_UNREACHABLE: &str = "30.days.ago";
+
+/// the fan-out directory the loose objects are counted in, to estimate their number
+const SAMPLE_DIR: &str = ".git/objects/17";
+
+pub struct GcOptions {
+    /// only run when there are enough loose objects or packs, as set by gc.auto and
+    /// gc.autoPackLimit
+    pub auto: bool,
+    /// prune the unreachable loose objects older than this date rather than gc.pruneExpire
+    pub prune: Option<String>,
+    pub quiet: bool,
+}
+
+/// packs the refs, expires old reflog entries, packs all reachable objects into a single
+/// pack and prunes the unreachable loose objects old enough
+pub fn gc(options: GcOptions) -> Result<()> {
+    let config = read_config()?;
+    if options.auto {
+        if !needs_gc(&config)? {
+            return Ok(());
+        }
+        if !options.quiet {
+            eprintln!("Auto packing the repository for optimum performance.");
+        }
+    }
+
+    if config.get_bool("gc.packRefs")?.unwrap_or(true) {
+        pack_refs()?;
+    }
+    expire_reflogs(&config)?;
+    repack(RepackOptions {
+        all: true,
+        loosen_unreachable: true,
+        delete: true,
+        quiet: options.quiet,
+        window: config.get_size("pack.window")?.unwrap_or(10) as usize,
+        depth: config.get_size("pack.depth")?.unwrap_or(50) as usize,
+    })?;
+
+    let prune = options.prune.as_deref().or(config.get("gc.pruneExpire"));
+    if let Some(expire) = expire_date(prune.unwrap_or(DEFAULT_PRUNE_EXPIRE))? {
+        prune_loose(expire)?;
+    }
+    Ok(())
+}
+
+/// runs gc --auto, as commands creating objects do once done
+pub fn auto_gc(quiet: bool) -> Result<()> {
+    gc(GcOptions {
+        auto: true,
+        prune: None,
+        quiet,
+    })
+}
+
+/// whether there are too many loose objects, or too many packs
+fn needs_gc(config: &Config) -> Result<bool> {
+    let threshold = config
+        .get_size("gc.auto")?
+        .unwrap_or(DEFAULT_AUTO_THRESHOLD);
+    if threshold == 0 {
+        return Ok(false);
+    }
+
+    // objects are spread evenly over the 256 fan-out directories, so one is enough to
+    // estimate how many there are
+    let mut loose = 0;
+    if let Ok(entries) = fs::read_dir(SAMPLE_DIR) {
+        for entry in entries {
+            let name = entry?.file_name();
+            if name.len() == 38
+                && name
+                    .to_string_lossy()
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit())
+            {
+                loose += 1;
+            }
+        }
+    }
+    if loose > threshold.div_ceil(256) {
+        return Ok(true);
+    }
+
+    let pack_limit = config
+        .get_size("gc.autoPackLimit")?
+        .unwrap_or(DEFAULT_AUTO_PACK_LIMIT);
+    let packs = packs()?
+        .iter()
+        .filter(|(path, _)| !path.with_extension("keep").is_file())
+        .count();
+    Ok(pack_limit > 0 && packs as u64 > pack_limit)
+}
+
+/// removes reflog entries older than gc.reflogExpire, and those older than
+/// gc.reflogExpireUnreachable that the ref no longer reaches
+fn expire_reflogs(config: &Config) -> Result<()> {
+    let expire = config.get("gc.reflogExpire");
+    let expire = expire_date(expire.unwrap_or(DEFAULT_REFLOG_EXPIRE))?;
+    let unreachable = config.get("gc.reflogExpireUnreachable");
+    let unreachable = expire_date(unreachable.unwrap_or(DEFAULT_REFLOG_EXPIRE_UNREACHABLE))?;
+
+    let mut graph = CommitGraph::new();
+    for name in list_reflogs()? {
+        let tip = resolve_ref(&name)?;
+        let entries = read_reflog(&name)?;
+        let mut kept = Vec::new();
+        for entry in &entries {
+            let time = entry.committer.time;
+            if expire.is_some_and(|expire| time <= expire) {
+                continue;
+            }
+            if unreachable.is_some_and(|unreachable| time <= unreachable) {
+                let reachable = match &tip {
+                    Some(tip) => {
+                        Object::exists(&entry.new)? && graph.is_ancestor(&entry.new, tip)?
+                    }
+                    None => false,
+                };
+                if !reachable {
+                    continue;
+                }
+            }
+            kept.push(entry.clone());
+        }
+        if kept.len() != entries.len() {
+            write_reflog(&name, &kept)?;
+        }
+    }
+    Ok(())
+}
+
+/// removes the loose objects that are unreachable and were last written before expire
+fn prune_loose(expire: u64) -> Result<()> {
+    let mut reachable: Option<HashSet<Hash>> = None;
+    for (hash, path) in Object::list_loose()? {
+        let mtime = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|mtime| mtime.as_secs())
+            .unwrap_or(0);
+        if mtime > expire {
+            continue;
+        }
+
+        // the walk is only done once an object is old enough to be pruned
+        if reachable.is_none() {
+            let objects = reachable_objects()?;
+            reachable = Some(objects.into_iter().map(|object| object.hash).collect());
+        }
+        if reachable
+            .as_ref()
+            .is_some_and(|reachable| reachable.contains(&hash))
+        {
+            continue;
+        }
+
+        fs::remove_file(&path)?;
+        if let Some(dir) = path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+    Ok(())
+}
+
+/// parses an expiry date. "never" and "false" disable expiry, and give None.
+fn expire_date(value: &str) -> Result<Option<u64>> {
+    match value {
+        "never" | "false" => Ok(None),
+        "all" => Ok(Some(u64::MAX)),
+        value => Ok(Some(parse_date(value)?)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::expire_date;
+
+    #[test]
+    fn test_expire_date() {
+        assert_eq!(expire_date("never").unwrap(), None);
+        assert_eq!(expire_date("false").unwrap(), None);
+        assert_eq!(expire_date("all").unwrap(), Some(u64::MAX));
+        assert_eq!(expire_date("@1700000000").unwrap(), Some(1700000000));
+        assert!(expire_date("2.weeks.ago").unwrap().is_some());
+        assert!(expire_date("soon").is_err());
+    }
+}
@@ -11,19 +11,18 @@ pub fn hash_object(path: PathBuf, write: bool) -> Result<()> {
 
     let mut file_contents = fs::File::open(path)?;
     let mut data = Vec::new();
-    file_contents.read(&mut data)?;
+    file_contents.read_to_end(&mut data)?;
 
     let object = Object {
         data,
         kind: ObjectKind::Blob,
     };
 
-    let hash: Hash;
-    if write {
-        hash = object.write()?;
+    let hash: Hash = if write {
+        object.write()?
     } else {
-        hash = object.hash()?;
-    }
+        object.hash()?
+    };
 
     println!("{:x}", hash);
 
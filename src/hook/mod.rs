@@ -0,0 +1,90 @@
+use std::{
+    fs,
+    io::{pipe, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{config::Config, objects::pack::QUARANTINE_ENV};
+
+/// where hooks are looked for when core.hooksPath isn't set
+const DEFAULT_HOOKS_DIR: &str = ".git/hooks";
+
+/// a program run on an event, found in the hooks directory under the name of the event
+pub struct Hook {
+    name: String,
+    path: PathBuf,
+}
+
+impl Hook {
+    /// finds the hook of an event. returns None if there is none, or if it isn't executable.
+    pub fn find(config: &Config, name: &str) -> Option<Hook> {
+        let dir = config.get("core.hooksPath").unwrap_or(DEFAULT_HOOKS_DIR);
+        let path = PathBuf::from(dir).join(name);
+        let executable = fs::metadata(&path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0);
+        executable.then(|| Hook {
+            name: name.to_string(),
+            path,
+        })
+    }
+
+    /// runs the hook with arguments and extra environment variables, writing input to its
+    /// stdin. what it prints on stdout and stderr goes to output, in the order it was
+    /// printed. returns whether the hook succeeded.
+    ///
+    /// objects not accepted yet are in the quarantine, a directory of the objects directory
+    /// that the hook reads objects from before those of the repository.
+    pub fn run(
+        &self,
+        args: &[&str],
+        env: &[(String, String)],
+        quarantine: Option<&Path>,
+        input: &[u8],
+        output: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<bool> {
+        let (mut reader, writer) = pipe()?;
+        let quarantine_env = match quarantine {
+            Some(quarantine) => vec![
+                (QUARANTINE_ENV, quarantine),
+                ("GIT_OBJECT_DIRECTORY", quarantine),
+                (
+                    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+                    quarantine.parent().unwrap_or(quarantine),
+                ),
+            ],
+            None => Vec::new(),
+        };
+        let mut child = Command::new(&self.path)
+            .args(args)
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .envs(quarantine_env)
+            .stdin(Stdio::piped())
+            .stdout(writer.try_clone()?)
+            .stderr(writer)
+            .spawn()
+            .with_context(|| format!("cannot run hook '{}'", self.name))?;
+
+        // the hook may print before it reads all of its input
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.to_vec();
+        let feeder = thread::spawn(move || {
+            // a hook doesn't have to read its input
+            let _ = stdin.write_all(&input);
+        });
+
+        let mut buf = [0; 4096];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => break,
+                n => output(&buf[..n])?,
+            }
+        }
+        let _ = feeder.join();
+        Ok(child.wait()?.success())
+    }
+}
@@ -0,0 +1,570 @@
+use std::{
+    env, fs,
+    io::{self, stdin, stdout, BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, Result};
+use flate2::read::MultiGzDecoder;
+
+use crate::{
+    config::read_config,
+    objects::{pack::packs, Object, ObjectKind},
+    pack_protocol::{
+        pkt_line::{write_flush, write_line},
+        transport::Service,
+    },
+    receive_pack::serve_receive_pack,
+    refs::list_refs,
+    rev_parse::peel,
+    upload_pack::{enter_repository, requested_version, serve_upload_pack, Session},
+};
+
+/// the file whose presence lets a repository be served when not all of them are
+const EXPORT_OK_FILE: &str = ".git/git-daemon-export-ok";
+
+/// held while a request is served from inside its repository, as the working directory is
+/// shared by the whole process
+static WORKING_DIR: Mutex<()> = Mutex::new(());
+
+/// moves back to a working directory when dropped, even while a panic unwinds
+struct RestoreDir(PathBuf);
+
+impl Drop for RestoreDir {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.0);
+    }
+}
+
+/// what can be asked of a repository, as the end of the path of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    /// the refs, advertised by a service or listed for dumb clients
+    InfoRefs,
+    /// a file served as is, like HEAD
+    Text,
+    /// the packs, listed for dumb clients
+    InfoPacks,
+    LooseObject,
+    Pack,
+    PackIndex,
+    /// a request of a smart client to a service
+    Rpc(Service),
+}
+
+/// a request, as a CGI program or an embedding server is handed it
+pub struct HttpRequest<R: Read> {
+    pub method: String,
+    /// the path below the project root, like /repo.git/info/refs
+    pub path: String,
+    pub query: String,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    /// the Git-Protocol header, asking for a protocol version
+    pub git_protocol: Option<String>,
+    /// the user the server authenticated, if any
+    pub remote_user: Option<String>,
+    pub body: R,
+}
+
+/// the status and headers of a response, sent before its body
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpResponse {
+    fn new(status: u16, content_type: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        }
+    }
+
+    /// a response whose content changes with the repository
+    fn no_cache(mut self) -> HttpResponse {
+        self.headers.extend([
+            (
+                "Expires".to_string(),
+                "Fri, 01 Jan 1980 00:00:00 GMT".to_string(),
+            ),
+            ("Pragma".to_string(), "no-cache".to_string()),
+            (
+                "Cache-Control".to_string(),
+                "no-cache, max-age=0, must-revalidate".to_string(),
+            ),
+        ]);
+        self
+    }
+
+    /// a response whose content is named after its hash, and never changes
+    fn cache_forever(mut self) -> HttpResponse {
+        self.headers.push((
+            "Cache-Control".to_string(),
+            "public, max-age=31536000".to_string(),
+        ));
+        self
+    }
+}
+
+/// where a response is written: its head is sent once, before any of its body
+pub trait ResponseWriter: Write {
+    fn send_head(&mut self, response: &HttpResponse) -> io::Result<()>;
+}
+
+/// writes a response the way a CGI program does, its head as a Status line and headers
+struct CgiResponse<W: Write>(W);
+
+impl<W: Write> Write for CgiResponse<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> ResponseWriter for CgiResponse<W> {
+    fn send_head(&mut self, response: &HttpResponse) -> io::Result<()> {
+        write!(
+            self.0,
+            "Status: {} {}\r\n",
+            response.status,
+            reason(response.status)
+        )?;
+        for (name, value) in &response.headers {
+            write!(self.0, "{}: {}\r\n", name, value)?;
+        }
+        write!(self.0, "\r\n")
+    }
+}
+
+/// the response of a service. its head, and the start of its body, are only sent once the
+/// service writes the rest, so that a service failing before that is answered with an error.
+struct ServiceResponse<'a, W: ResponseWriter> {
+    out: &'a mut W,
+    pending: Option<(HttpResponse, Vec<u8>)>,
+}
+
+impl<'a, W: ResponseWriter> ServiceResponse<'a, W> {
+    fn new(out: &'a mut W, head: HttpResponse, start: Vec<u8>) -> ServiceResponse<'a, W> {
+        ServiceResponse {
+            out,
+            pending: Some((head, start)),
+        }
+    }
+
+    fn send_pending(&mut self) -> io::Result<()> {
+        if let Some((head, start)) = self.pending.take() {
+            self.out.send_head(&head)?;
+            self.out.write_all(&start)?;
+        }
+        Ok(())
+    }
+
+    /// sends what is still pending once the service is done, or an error in its place if
+    /// the service failed. errors the client can read were sent to it, the rest is left to
+    /// the server log.
+    fn finish(mut self, served: Result<()>) -> Result<()> {
+        match served {
+            Ok(()) => self.send_pending()?,
+            Err(err) => {
+                log::error!("{}", err);
+                if self.pending.is_some() {
+                    send_error(self.out, 500, &err.to_string())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: ResponseWriter> Write for ServiceResponse<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_pending()?;
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.pending {
+            Some(_) => Ok(()),
+            None => self.out.flush(),
+        }
+    }
+}
+
+pub struct HttpBackendOptions {
+    /// the directory the paths of requests are relative to
+    pub project_root: PathBuf,
+    /// serve every repository, not only those with a git-daemon-export-ok file
+    pub export_all: bool,
+}
+
+/// serves a request to the repositories below the project root, like the CGI program
+/// does, writing the response as it goes. requests are served one at a time, from inside
+/// their repository: nothing else in the process should rely on the working directory
+/// meanwhile. it is restored afterwards.
+pub fn handle_request<R: Read, W: ResponseWriter>(
+    options: &HttpBackendOptions,
+    request: HttpRequest<R>,
+    out: &mut W,
+) -> Result<()> {
+    // a request that panicked doesn't keep the others from being served
+    let _lock = WORKING_DIR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // dropped before the lock, so the next request starts from here too
+    let current_dir = RestoreDir(env::current_dir()?);
+    let project_root = current_dir.0.join(&options.project_root);
+    serve_request(&project_root, options.export_all, request, out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// serves the request described by the CGI environment, reading its body from stdin
+/// and writing the response to stdout
+pub fn http_backend() -> Result<()> {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+    let project_root = match var("GIT_PROJECT_ROOT") {
+        Some(root) => PathBuf::from(root),
+        None => bail!("no GIT_PROJECT_ROOT from server"),
+    };
+    let options = HttpBackendOptions {
+        project_root,
+        export_all: var("GIT_HTTP_EXPORT_ALL").is_some(),
+    };
+
+    let stdin = stdin().lock();
+    let body: Box<dyn Read> = match var("CONTENT_LENGTH").map(|length| length.parse::<u64>()) {
+        Some(Ok(length)) => Box::new(stdin.take(length)),
+        Some(Err(_)) => bail!("invalid CONTENT_LENGTH"),
+        None => Box::new(stdin),
+    };
+    let request = HttpRequest {
+        method: var("REQUEST_METHOD").unwrap_or_else(|| "GET".to_string()),
+        path: var("PATH_INFO").unwrap_or_default(),
+        query: var("QUERY_STRING").unwrap_or_default(),
+        content_type: var("CONTENT_TYPE"),
+        content_encoding: var("HTTP_CONTENT_ENCODING"),
+        git_protocol: var("HTTP_GIT_PROTOCOL"),
+        remote_user: var("REMOTE_USER"),
+        body,
+    };
+    handle_request(&options, request, &mut CgiResponse(stdout().lock()))
+}
+
+/// sends a whole response
+fn respond<W: ResponseWriter>(out: &mut W, response: HttpResponse, body: &[u8]) -> Result<()> {
+    out.send_head(&response)?;
+    out.write_all(body)?;
+    Ok(())
+}
+
+fn send_error<W: ResponseWriter>(out: &mut W, status: u16, message: &str) -> Result<()> {
+    let body = format!("{}\n", message);
+    respond(
+        out,
+        HttpResponse::new(status, "text/plain"),
+        body.as_bytes(),
+    )
+}
+
+fn serve_request<R: Read, W: ResponseWriter>(
+    project_root: &Path,
+    export_all: bool,
+    request: HttpRequest<R>,
+    out: &mut W,
+) -> Result<()> {
+    let (repository, route) = match parse_path(&request.path) {
+        Some((repository, route)) => (repository.to_string(), route),
+        None => return send_error(out, 404, "Not Found"),
+    };
+    let method_allowed = match route {
+        Route::Rpc(_) => request.method == "POST",
+        _ => request.method == "GET" || request.method == "HEAD",
+    };
+    if !method_allowed {
+        let allowed = match route {
+            Route::Rpc(_) => "POST",
+            _ => "GET, HEAD",
+        };
+        let mut response = HttpResponse::new(405, "text/plain");
+        response
+            .headers
+            .push(("Allow".to_string(), allowed.to_string()));
+        return respond(out, response, b"Method Not Allowed\n");
+    }
+
+    // the file asked for, in the .git directory of the repository
+    let file = request.path[repository.len()..]
+        .trim_start_matches('/')
+        .to_string();
+    let dir = project_root.join(repository.trim_start_matches('/'));
+    if enter_repository(&dir.to_string_lossy()).is_err() {
+        return send_error(out, 404, "Not Found");
+    }
+    if !export_all && !Path::new(EXPORT_OK_FILE).is_file() {
+        return send_error(out, 404, "Repository not exported");
+    }
+
+    let service = match route {
+        Route::Rpc(service) => Some(service),
+        Route::InfoRefs => {
+            let service = request
+                .query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("service="));
+            match service {
+                Some(name) if name == Service::UploadPack.name() => Some(Service::UploadPack),
+                Some(name) if name == Service::ReceivePack.name() => Some(Service::ReceivePack),
+                Some(_) => return send_error(out, 403, "Unsupported service"),
+                None => None,
+            }
+        }
+        _ => None,
+    };
+    if let Some(service) = service {
+        if !service_enabled(service, request.remote_user.is_some())? {
+            return send_error(out, 403, "Service not enabled");
+        }
+    }
+    let version = request
+        .git_protocol
+        .as_deref()
+        .map(requested_version)
+        .unwrap_or(0);
+
+    match (route, service) {
+        (Route::InfoRefs, Some(service)) => advertise(service, version, out),
+        (Route::InfoRefs, None) => {
+            let response = HttpResponse::new(200, "text/plain").no_cache();
+            respond(out, response, &info_refs()?)
+        }
+        (Route::Rpc(service), _) => rpc(service, version, request, out),
+        (Route::Text, _) => match fs::read(Path::new(".git").join(file)) {
+            Ok(content) => {
+                let response = HttpResponse::new(200, "text/plain").no_cache();
+                respond(out, response, &content)
+            }
+            Err(_) => send_error(out, 404, "Not Found"),
+        },
+        (Route::InfoPacks, _) => {
+            let response = HttpResponse::new(200, "text/plain; charset=utf-8").no_cache();
+            respond(out, response, &info_packs()?)
+        }
+        (Route::LooseObject | Route::Pack | Route::PackIndex, _) => {
+            let content_type = match route {
+                Route::LooseObject => "application/x-git-loose-object",
+                Route::Pack => "application/x-git-packed-objects",
+                _ => "application/x-git-packed-objects-toc",
+            };
+            match fs::File::open(Path::new(".git").join(file)) {
+                Ok(mut content) => {
+                    out.send_head(&HttpResponse::new(200, content_type).cache_forever())?;
+                    io::copy(&mut content, out)?;
+                    Ok(())
+                }
+                Err(_) => send_error(out, 404, "Not Found"),
+            }
+        }
+    }
+}
+
+/// splits the path of a request into the path of the repository and what is asked of it.
+/// paths going up a directory are refused.
+fn parse_path(path: &str) -> Option<(&str, Route)> {
+    if Path::new(path)
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        return None;
+    }
+
+    let is_hex = |name: &str, len: usize| {
+        name.len() == len && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let mut parts = path.rsplitn(4, '/');
+    let last = parts.next()?;
+    let parent = parts.next();
+    let grandparent = parts.next();
+    // how many parts of the path the route takes
+    let (route_parts, route) = match (grandparent, parent, last) {
+        (_, _, "git-upload-pack") => (1, Route::Rpc(Service::UploadPack)),
+        (_, _, "git-receive-pack") => (1, Route::Rpc(Service::ReceivePack)),
+        (_, _, "HEAD") => (1, Route::Text),
+        (_, Some("info"), "refs") => (2, Route::InfoRefs),
+        (Some("objects"), Some("info"), "packs") => (3, Route::InfoPacks),
+        (Some("objects"), Some("info"), "alternates" | "http-alternates") => (3, Route::Text),
+        (Some("objects"), Some("pack"), name) => {
+            let route = match name.strip_prefix("pack-") {
+                Some(name) if name.ends_with(".pack") && is_hex(&name[..name.len() - 5], 40) => {
+                    Route::Pack
+                }
+                Some(name) if name.ends_with(".idx") && is_hex(&name[..name.len() - 4], 40) => {
+                    Route::PackIndex
+                }
+                _ => return None,
+            };
+            (3, route)
+        }
+        (Some("objects"), Some(dir), name) if is_hex(dir, 2) && is_hex(name, 38) => {
+            (3, Route::LooseObject)
+        }
+        _ => return None,
+    };
+
+    let mut end = path.len();
+    for _ in 0..route_parts {
+        end = path[..end].rfind('/')?;
+    }
+    Some((&path[..end], route))
+}
+
+/// whether a service may be used. pushing is only enabled by default to users the server
+/// authenticated.
+fn service_enabled(service: Service, authenticated: bool) -> Result<bool> {
+    let config = read_config()?;
+    let (key, default) = match service {
+        Service::UploadPack => ("http.uploadpack", true),
+        Service::ReceivePack => ("http.receivepack", authenticated),
+    };
+    Ok(config.get_bool(key)?.unwrap_or(default))
+}
+
+/// the advertisement of a service, preceded by the service line smart clients expect. a
+/// version 2 advertisement comes alone.
+fn advertise<W: ResponseWriter>(service: Service, version: u8, out: &mut W) -> Result<()> {
+    let mut start = Vec::new();
+    if version != 2 || service == Service::ReceivePack {
+        write_line(&mut start, &format!("# service={}", service.name()))?;
+        write_flush(&mut start)?;
+    }
+
+    let content_type = format!("application/x-{}-advertisement", service.name());
+    let head = HttpResponse::new(200, &content_type).no_cache();
+    let mut response = ServiceResponse::new(out, head, start);
+    let served = run_service(
+        service,
+        &[][..],
+        &mut response,
+        version,
+        Session::AdvertiseRefs,
+    );
+    response.finish(served)
+}
+
+/// serves a request of a smart client, whose body may be gzipped
+fn rpc<R: Read, W: ResponseWriter>(
+    service: Service,
+    version: u8,
+    request: HttpRequest<R>,
+    out: &mut W,
+) -> Result<()> {
+    let expected = format!("application/x-{}-request", service.name());
+    if request.content_type.as_deref() != Some(expected.as_str()) {
+        return send_error(out, 415, "Unsupported Media Type");
+    }
+    let body: Box<dyn Read> = match request.content_encoding.as_deref() {
+        None | Some("identity") => Box::new(request.body),
+        Some("gzip" | "x-gzip") => Box::new(MultiGzDecoder::new(request.body)),
+        Some(encoding) => {
+            let message = format!("unsupported content encoding '{}'", encoding);
+            return send_error(out, 415, &message);
+        }
+    };
+
+    let content_type = format!("application/x-{}-result", service.name());
+    let head = HttpResponse::new(200, &content_type).no_cache();
+    let mut response = ServiceResponse::new(out, head, Vec::new());
+    let served = run_service(
+        service,
+        BufReader::new(body),
+        &mut response,
+        version,
+        Session::StatelessRpc,
+    );
+    response.finish(served)
+}
+
+fn run_service<R: BufRead, W: Write>(
+    service: Service,
+    reader: R,
+    writer: W,
+    version: u8,
+    session: Session,
+) -> Result<()> {
+    match service {
+        Service::UploadPack => serve_upload_pack(reader, writer, version, session),
+        Service::ReceivePack => serve_receive_pack(reader, writer, version, session),
+    }
+}
+
+/// the refs, with the objects annotated tags point to, for dumb clients
+fn info_refs() -> Result<Vec<u8>> {
+    let mut info = String::new();
+    for (name, hash) in list_refs("refs/")? {
+        info.push_str(&format!("{}\t{}\n", hash.to_hex(), name));
+        if Object::read_from_hash(hash.to_hex())?.kind == ObjectKind::Tag {
+            let peeled = peel(hash, None)?;
+            info.push_str(&format!("{}\t{}^{{}}\n", peeled.to_hex(), name));
+        }
+    }
+    Ok(info.into_bytes())
+}
+
+/// the packs, for dumb clients
+fn info_packs() -> Result<Vec<u8>> {
+    let mut info = String::new();
+    for (path, _) in packs()? {
+        if let Some(name) = path.file_name() {
+            info.push_str(&format!("P {}\n", name.to_string_lossy()));
+        }
+    }
+    info.push('\n');
+    Ok(info.into_bytes())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pack_protocol::transport::Service;
+
+    use super::{parse_path, Route};
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("/repo.git/info/refs"),
+            Some(("/repo.git", Route::InfoRefs))
+        );
+        assert_eq!(
+            parse_path("/a/b/git-upload-pack"),
+            Some(("/a/b", Route::Rpc(Service::UploadPack)))
+        );
+        assert_eq!(parse_path("/repo/HEAD"), Some(("/repo", Route::Text)));
+        let loose = format!("/repo/objects/8a/{}", "b".repeat(38));
+        assert_eq!(parse_path(&loose), Some(("/repo", Route::LooseObject)));
+        let pack = format!("/objects/pack/pack-{}.idx", "c".repeat(40));
+        assert_eq!(parse_path(&pack), Some(("", Route::PackIndex)));
+        assert_eq!(
+            parse_path("/repo/objects/info/packs"),
+            Some(("/repo", Route::InfoPacks))
+        );
+        assert_eq!(parse_path("/repo/objects/8a/xyz"), None);
+        assert_eq!(parse_path("/../etc/info/refs"), None);
+    }
+}
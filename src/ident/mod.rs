@@ -0,0 +1,150 @@
+use std::{env, fs};
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, Offset, TimeZone};
+
+use crate::{
+    config::read_config,
+    date::{format_time_zone, now, parse_date, parse_time_zone},
+    objects::commit::Author,
+};
+
+/// who an identity is for. each role has its own environment variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+}
+
+/// returns the identity used for new commits, tags and reflog entries, taken from
+/// GIT_{AUTHOR,COMMITTER}_{NAME,EMAIL,DATE}, then from user.name and user.email
+pub fn identity(role: Role) -> Result<Author> {
+    let prefix = role.env_prefix();
+    let config = read_config()?;
+
+    let name = match env::var(format!("{}_NAME", prefix)) {
+        Ok(name) => name,
+        Err(_) => config
+            .get("user.name")
+            .map(|name| name.to_string())
+            .ok_or(anyhow!(
+                "author identity unknown: set user.name and user.email, or {}_NAME and {}_EMAIL",
+                prefix,
+                prefix
+            ))?,
+    };
+    let email = match env::var(format!("{}_EMAIL", prefix)) {
+        Ok(email) => email,
+        Err(_) => config
+            .get("user.email")
+            .map(|email| email.to_string())
+            .ok_or(anyhow!(
+                "author identity unknown: set user.email or {}_EMAIL",
+                prefix
+            ))?,
+    };
+
+    let (time, time_zone) = match env::var(format!("{}_DATE", prefix)) {
+        Ok(date) => parse_ident_date(&date)?,
+        Err(_) => (now(), local_time_zone(now())),
+    };
+
+    Ok(Author {
+        name,
+        email,
+        time,
+        time_zone,
+    })
+}
+
+/// returns the committer identity for reflog entries. when none is configured, falls back
+/// to the login name and the host name instead of failing, as reflogs are also written by
+/// commands that don't otherwise need an identity, like fetch or a push being received.
+pub fn reflog_identity() -> Result<Author> {
+    if let Ok(committer) = identity(Role::Committer) {
+        return Ok(committer);
+    }
+
+    let config = read_config()?;
+    let login = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or(String::from("unknown"));
+    let name = env::var("GIT_COMMITTER_NAME")
+        .ok()
+        .or(config.get("user.name").map(|name| name.to_string()))
+        .unwrap_or(login.clone());
+    let email = env::var("GIT_COMMITTER_EMAIL")
+        .ok()
+        .or(config.get("user.email").map(|email| email.to_string()))
+        .unwrap_or(format!("{}@{}", login, host_name()));
+    let (time, time_zone) = match env::var("GIT_COMMITTER_DATE") {
+        Ok(date) => parse_ident_date(&date)?,
+        Err(_) => (now(), local_time_zone(now())),
+    };
+
+    Ok(Author {
+        name,
+        email,
+        time,
+        time_zone,
+    })
+}
+
+fn host_name() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .or(env::var("HOSTNAME").ok())
+        .unwrap_or(String::from("localhost"))
+}
+
+/// parses dates given in the environment: git's internal "<timestamp> <zone>" format,
+/// optionally with a leading '@', or any date accepted by parse_date
+fn parse_ident_date(date: &str) -> Result<(u64, String)> {
+    let date = date.trim();
+    if let Some((time, zone)) = date.trim_start_matches('@').split_once(' ') {
+        if let (Ok(time), Ok(_)) = (time.parse::<u64>(), parse_time_zone(zone)) {
+            return Ok((time, zone.to_string()));
+        }
+    }
+
+    let time = parse_date(date)?;
+    Ok((time, local_time_zone(time)))
+}
+
+fn local_time_zone(time: u64) -> String {
+    let offset = Local
+        .timestamp_opt(time as i64, 0)
+        .single()
+        .map(|date| date.offset().fix())
+        .unwrap_or(chrono::FixedOffset::east_opt(0).expect("zero is a valid offset"));
+    format_time_zone(&offset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_ident_date;
+
+    #[test]
+    fn test_parse_ident_date() {
+        assert_eq!(
+            parse_ident_date("1112911993 -0700").unwrap(),
+            (1112911993, String::from("-0700"))
+        );
+        assert_eq!(
+            parse_ident_date("@1112911993 +0200").unwrap(),
+            (1112911993, String::from("+0200"))
+        );
+    }
+}
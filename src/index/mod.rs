@@ -0,0 +1,563 @@
+use std::{
+    fs::{self, Metadata},
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    objects::{
+        hash::{hash, Hash},
+        tree::{decode_tree, encode_tree, new_tree, Entry, EntryMode},
+        Object, ObjectKind,
+    },
+    refs::write_locked,
+};
+
+const INDEX_PATH: &str = ".git/index";
+const SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// size of the fixed part of an entry: ten 32-bit stat fields, the hash and the flags
+const ENTRY_HEADER_SIZE: usize = 62;
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const EXTENDED_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/// the staging area, as stored in .git/index
+#[derive(Debug, Default)]
+pub struct Index {
+    pub version: u32,
+    /// entries sorted by path, then by stage
+    pub entries: Vec<IndexEntry>,
+    /// extensions (cached trees, resolve-undo, ...) kept as raw signature and data
+    pub extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+/*
+    ctime seconds, ctime nanoseconds, mtime seconds, mtime nanoseconds,
+    dev, ino, mode, uid, gid, size (32 bits each)
+    hash (20 bytes)
+    flags (16 bits): assume-valid, extended, stage (2 bits), name length (12 bits)
+    extended flags (16 bits, version 3 and later, only if the extended flag is set)
+    path, NUL padded to a multiple of 8 bytes (versions 2 and 3),
+    or prefix compressed against the previous path (version 4)
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime_sec: u32,
+    pub ctime_nsec: u32,
+    pub mtime_sec: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: Hash,
+    pub flags: u16,
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// creates an entry with no stat information, as if the file was never checked out
+    pub fn new(path: &str, mode: EntryMode, hash: Hash, stage: u8) -> IndexEntry {
+        let name_len = path.len().min(FLAG_NAME_MASK as usize) as u16;
+        IndexEntry {
+            ctime_sec: 0,
+            ctime_nsec: 0,
+            mtime_sec: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode: mode as u32,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            flags: ((stage as u16) << FLAG_STAGE_SHIFT) | name_len,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// records the stat information of the checked out file, so that it is known to be clean
+    pub fn refresh(&mut self, metadata: &Metadata) {
+        self.ctime_sec = metadata.ctime() as u32;
+        self.ctime_nsec = metadata.ctime_nsec() as u32;
+        self.mtime_sec = metadata.mtime() as u32;
+        self.mtime_nsec = metadata.mtime_nsec() as u32;
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    /// 0 for merged entries. unmerged entries use 1 (base), 2 (ours) and 3 (theirs).
+    pub fn stage(&self) -> u8 {
+        ((self.flags & FLAG_STAGE_MASK) >> FLAG_STAGE_SHIFT) as u8
+    }
+
+    /// returns true for paths added with git add -N, whose content isn't staged yet
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & EXTENDED_FLAG_INTENT_TO_ADD != 0
+    }
+
+    /// returns true if the file looks unchanged since the entry was written.
+    /// entries written in the same second as the index, or later, can't be trusted
+    /// since the file may have changed right after.
+    pub fn matches_stat(&self, metadata: &Metadata, index_mtime: Option<(u32, u32)>) -> bool {
+        let racy = index_mtime.is_none_or(|mtime| mtime <= (self.mtime_sec, self.mtime_nsec));
+        !racy
+            && self.mtime_sec == metadata.mtime() as u32
+            && self.mtime_nsec == metadata.mtime_nsec() as u32
+            && self.size == metadata.size() as u32
+            && self.ino == metadata.ino() as u32
+    }
+}
+
+impl Index {
+    /// returns the merged entry at the given path
+    pub fn entry(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path && entry.stage() == 0)
+    }
+
+    /// returns true if some paths have unmerged entries
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage() != 0)
+    }
+
+    /// adds an entry, replacing the entries of the path it conflicts with: all of them
+    /// for a merged entry, the merged one and the one of the same stage otherwise
+    pub fn add(&mut self, entry: IndexEntry) {
+        let stage = entry.stage();
+        self.entries.retain(|existing| {
+            existing.path != entry.path
+                || (stage != 0 && existing.stage() != 0 && existing.stage() != stage)
+        });
+        let position = self
+            .entries
+            .partition_point(|existing| entry_key(existing) < entry_key(&entry));
+        self.entries.insert(position, entry);
+    }
+
+    /// removes all the entries of a path
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|entry| entry.path != path);
+    }
+
+    /// the entries of a tree, at stage 0
+    pub fn from_tree(tree: &Hash) -> Result<Index> {
+        let mut entries = Vec::new();
+        collect_tree_entries(tree, "", &mut entries)?;
+        entries.sort_by(|a, b| entry_key(a).cmp(&entry_key(b)));
+
+        Ok(Index {
+            version: 2,
+            entries,
+            extensions: Vec::new(),
+        })
+    }
+
+    /// writes the tree objects of the merged entries, returning the hash of the root tree
+    pub fn write_tree(&self) -> Result<Hash> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage() != 0) {
+            bail!("{}: unmerged entries can't be written to a tree", entry.path);
+        }
+
+        let entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        write_tree_level(&entries, "")
+    }
+}
+
+/// entries are sorted by path bytes, then by stage
+fn entry_key(entry: &IndexEntry) -> (&[u8], u8) {
+    (entry.path.as_bytes(), entry.stage())
+}
+
+fn collect_tree_entries(tree: &Hash, prefix: &str, entries: &mut Vec<IndexEntry>) -> Result<()> {
+    let object = Object::read_from_hash(tree.to_hex())?;
+    if object.kind != ObjectKind::Tree {
+        bail!("object {:x} is a {}, not a tree", tree, object.kind);
+    }
+
+    for entry in decode_tree(object.data)?.entries {
+        let path = format!("{}{}", prefix, entry.name);
+        match entry.mode {
+            EntryMode::Directory => {
+                collect_tree_entries(&entry.hash, &format!("{}/", path), entries)?
+            }
+            mode => entries.push(IndexEntry::new(&path, mode, entry.hash, 0)),
+        }
+    }
+
+    Ok(())
+}
+
+/// writes the tree of the entries below a directory, given sorted entries whose paths
+/// all start with the prefix
+fn write_tree_level(entries: &[&IndexEntry], prefix: &str) -> Result<Hash> {
+    let mut tree_entries = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let name = &entries[i].path[prefix.len()..];
+        match name.split_once('/') {
+            Some((dir, _)) => {
+                let dir_prefix = format!("{}{}/", prefix, dir);
+                let end = i + entries[i..]
+                    .iter()
+                    .position(|entry| !entry.path.starts_with(&dir_prefix))
+                    .unwrap_or(entries.len() - i);
+                tree_entries.push(Entry {
+                    mode: EntryMode::Directory,
+                    name: dir.to_string(),
+                    hash: write_tree_level(&entries[i..end], &dir_prefix)?,
+                });
+                i = end;
+            }
+            None => {
+                tree_entries.push(Entry {
+                    mode: EntryMode::try_from(entries[i].mode)?,
+                    name: name.to_string(),
+                    hash: entries[i].hash.clone(),
+                });
+                i += 1;
+            }
+        }
+    }
+
+    let object = Object {
+        data: encode_tree(new_tree(tree_entries)),
+        kind: ObjectKind::Tree,
+    };
+    object.write()
+}
+
+/// reads .git/index. a missing index is read as an empty one.
+pub fn read_index() -> Result<Index> {
+    let path = PathBuf::from(INDEX_PATH);
+    if !path.is_file() {
+        return Ok(Index {
+            version: 2,
+            ..Default::default()
+        });
+    }
+
+    decode_index(&fs::read(path)?)
+}
+
+/// writes .git/index. extensions are dropped since the cached data they hold may no
+/// longer match the entries.
+pub fn write_index(index: &Index) -> Result<()> {
+    write_locked(&PathBuf::from(INDEX_PATH), &encode_index(index))
+}
+
+/// returns the modification time of .git/index, used to detect racily clean entries
+pub fn index_mtime() -> Result<Option<(u32, u32)>> {
+    let path = PathBuf::from(INDEX_PATH);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let modified = fs::metadata(path)?
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)?;
+    Ok(Some((modified.as_secs() as u32, modified.subsec_nanos())))
+}
+
+pub fn decode_index(data: &[u8]) -> Result<Index> {
+    if data.len() < 12 + 20 || &data[..4] != SIGNATURE {
+        bail!("invalid index file signature");
+    }
+
+    let (content, checksum) = data.split_at(data.len() - 20);
+    if hash(content).0 != checksum {
+        bail!("index file checksum mismatch");
+    }
+
+    let version = read_u32(content, 4)?;
+    if !(2..=4).contains(&version) {
+        bail!("unsupported index version {}", version);
+    }
+
+    let count = read_u32(content, 8)? as usize;
+    let mut offset = 12;
+    let mut entries = Vec::with_capacity(count);
+    let mut previous_path: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = offset;
+        let field = |i: usize| read_u32(content, start + i * 4);
+        let hash_start = start + 40;
+        let hash = Hash::try_from(
+            content
+                .get(hash_start..hash_start + 20)
+                .ok_or(anyhow!("truncated index entry"))?,
+        )?;
+        let flags = read_u16(content, start + 60)?;
+        offset += ENTRY_HEADER_SIZE;
+
+        let mut extended_flags = 0;
+        if flags & FLAG_EXTENDED != 0 {
+            if version < 3 {
+                bail!("extended index entry in a version {} index", version);
+            }
+            extended_flags = read_u16(content, offset)?;
+            offset += 2;
+        }
+
+        let path = if version == 4 {
+            let (strip, varint_len) = read_varint(&content[offset..])?;
+            offset += varint_len;
+            let suffix_len = content[offset..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or(anyhow!("unterminated index entry path"))?;
+            let keep = previous_path
+                .len()
+                .checked_sub(strip)
+                .ok_or(anyhow!("invalid index path compression"))?;
+            let mut path = previous_path[..keep].to_vec();
+            path.extend_from_slice(&content[offset..offset + suffix_len]);
+            offset += suffix_len + 1;
+            path
+        } else {
+            let path_len = content[offset..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or(anyhow!("unterminated index entry path"))?;
+            let path = content[offset..offset + path_len].to_vec();
+            // entries are padded with 1 to 8 NUL bytes to a multiple of 8 bytes
+            let entry_len = offset + path_len - start;
+            offset = start + (entry_len + 8) / 8 * 8;
+            path
+        };
+
+        entries.push(IndexEntry {
+            ctime_sec: field(0)?,
+            ctime_nsec: field(1)?,
+            mtime_sec: field(2)?,
+            mtime_nsec: field(3)?,
+            dev: field(4)?,
+            ino: field(5)?,
+            mode: field(6)?,
+            uid: field(7)?,
+            gid: field(8)?,
+            size: field(9)?,
+            hash,
+            flags,
+            extended_flags,
+            path: String::from_utf8(path.clone())?,
+        });
+        previous_path = path;
+    }
+
+    let mut extensions = Vec::new();
+    while offset + 8 <= content.len() {
+        let signature: [u8; 4] = content[offset..offset + 4].try_into()?;
+        let size = read_u32(content, offset + 4)? as usize;
+        let data = content
+            .get(offset + 8..offset + 8 + size)
+            .ok_or(anyhow!("truncated index extension"))?;
+        extensions.push((signature, data.to_vec()));
+        offset += 8 + size;
+    }
+
+    Ok(Index {
+        version,
+        entries,
+        extensions,
+    })
+}
+
+pub fn encode_index(index: &Index) -> Vec<u8> {
+    let extended = index.entries.iter().any(|entry| entry.extended_flags != 0);
+    let version: u32 = match index.version {
+        4 => 4,
+        _ if extended => 3,
+        _ => 2,
+    };
+
+    let mut data = Vec::new();
+    data.extend_from_slice(SIGNATURE);
+    data.extend_from_slice(&version.to_be_bytes());
+    data.extend_from_slice(&(index.entries.len() as u32).to_be_bytes());
+
+    let mut previous_path: &[u8] = &[];
+    for entry in &index.entries {
+        let start = data.len();
+        for field in [
+            entry.ctime_sec,
+            entry.ctime_nsec,
+            entry.mtime_sec,
+            entry.mtime_nsec,
+            entry.dev,
+            entry.ino,
+            entry.mode,
+            entry.uid,
+            entry.gid,
+            entry.size,
+        ] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.extend_from_slice(&entry.hash.0);
+
+        let name_len = entry.path.len().min(FLAG_NAME_MASK as usize) as u16;
+        let mut flags = (entry.flags & !(FLAG_NAME_MASK | FLAG_EXTENDED)) | name_len;
+        if entry.extended_flags != 0 {
+            flags |= FLAG_EXTENDED;
+        }
+        data.extend_from_slice(&flags.to_be_bytes());
+        if entry.extended_flags != 0 {
+            data.extend_from_slice(&entry.extended_flags.to_be_bytes());
+        }
+
+        let path = entry.path.as_bytes();
+        if version == 4 {
+            let common = previous_path
+                .iter()
+                .zip(path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            write_varint(&mut data, previous_path.len() - common);
+            data.extend_from_slice(&path[common..]);
+            data.push(0);
+        } else {
+            data.extend_from_slice(path);
+            let entry_len = data.len() - start;
+            let padded_len = (entry_len + 8) / 8 * 8;
+            data.resize(start + padded_len, 0);
+        }
+        previous_path = path;
+    }
+
+    let checksum = hash(&data);
+    data.extend_from_slice(&checksum.0);
+    data
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >> 7 != 0 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    bytes.reverse();
+    data.extend_from_slice(&bytes);
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or(anyhow!("truncated index file"))?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or(anyhow!("truncated index file"))?;
+    Ok(u16::from_be_bytes(bytes.try_into()?))
+}
+
+/// reads the offset encoding used by index v4 path compression, returning the value
+/// and the number of bytes read
+fn read_varint(data: &[u8]) -> Result<(usize, usize)> {
+    let mut bytes = data.iter();
+    let mut byte = *bytes.next().ok_or(anyhow!("truncated varint"))?;
+    let mut value = (byte & 0x7f) as usize;
+    let mut len = 1;
+    while byte & 0x80 != 0 {
+        byte = *bytes.next().ok_or(anyhow!("truncated varint"))?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        len += 1;
+    }
+
+    Ok((value, len))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::objects::hash::{hash, Hash};
+
+    use super::{decode_index, encode_index, read_varint, write_varint, Index, IndexEntry};
+    use crate::objects::tree::EntryMode;
+
+    #[test]
+    fn test_decode_index() {
+        let blob = Hash::try_from("a".repeat(40).as_bytes()).unwrap();
+        let mut data = Vec::new();
+        data.extend_from_slice(b"DIRC");
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        for field in [1, 0, 2, 0, 0, 0, 0o100644, 0, 0, 5u32] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.extend_from_slice(&blob.0);
+        data.extend_from_slice(&(0x2000u16 | 5).to_be_bytes());
+        data.extend_from_slice(b"a.txt");
+        // 62 + 5 bytes, padded to 72
+        data.extend_from_slice(&[0; 5]);
+        data.extend_from_slice(&hash(&data).0);
+
+        let index = decode_index(&data).unwrap();
+        assert_eq!(index.entries.len(), 1);
+        let entry = &index.entries[0];
+        assert_eq!(entry.path, "a.txt");
+        assert_eq!(entry.mode, 0o100644);
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.hash, blob);
+        assert_eq!(entry.stage(), 2);
+    }
+
+    #[test]
+    fn test_encode_index() {
+        let blob = Hash::try_from("b".repeat(40).as_bytes()).unwrap();
+        for version in [2, 4] {
+            let mut index = Index {
+                version,
+                ..Default::default()
+            };
+            index.add(IndexEntry::new("src/lib.rs", EntryMode::RegularFile, blob.clone(), 0));
+            index.add(IndexEntry::new("src/a.rs", EntryMode::RegularFile, blob.clone(), 3));
+            index.add(IndexEntry::new("src/a.rs", EntryMode::RegularFile, blob.clone(), 1));
+            index.add(IndexEntry::new("run.sh", EntryMode::ExecutableFile, blob.clone(), 0));
+
+            let decoded = decode_index(&encode_index(&index)).unwrap();
+            assert_eq!(decoded.entries, index.entries);
+            let paths: Vec<(&str, u8)> = decoded
+                .entries
+                .iter()
+                .map(|entry| (entry.path.as_str(), entry.stage()))
+                .collect();
+            assert_eq!(
+                paths,
+                vec![("run.sh", 0), ("src/a.rs", 1), ("src/a.rs", 3), ("src/lib.rs", 0)]
+            );
+        }
+
+        // a merged entry replaces the conflicting ones
+        let mut index = Index::default();
+        index.add(IndexEntry::new("a", EntryMode::RegularFile, blob.clone(), 2));
+        index.add(IndexEntry::new("a", EntryMode::RegularFile, blob.clone(), 3));
+        index.add(IndexEntry::new("a", EntryMode::RegularFile, blob, 0));
+        assert_eq!(index.entries.len(), 1);
+        assert!(!index.has_conflicts());
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            assert_eq!(read_varint(&data).unwrap(), (value, data.len()));
+        }
+    }
+}
@@ -1,6 +1,43 @@
+pub mod branch;
 pub mod cat_file;
 pub mod clone;
+pub mod config;
+pub mod date;
+pub mod diff;
+pub mod diff_tree;
+pub mod editor;
+pub mod fetch;
+pub mod fsck;
+pub mod gc;
 pub mod hash_object;
+pub mod hook;
+pub mod http_backend;
+pub mod ident;
+pub mod index;
 pub mod init;
+pub mod log;
+pub mod merge;
+pub mod merge_base;
 pub mod objects;
+pub mod pack_objects;
 pub mod pack_protocol;
+pub mod progress;
+pub mod push;
+pub mod rebase;
+pub mod receive_pack;
+pub mod ref_filter;
+pub mod refs;
+pub mod remote;
+pub mod repack;
+pub mod reset;
+pub mod restore;
+pub mod rev_list;
+pub mod rev_parse;
+pub mod revwalk;
+pub mod sequencer;
+pub mod stash;
+pub mod tag;
+#[cfg(test)]
+mod test_repo;
+pub mod upload_pack;
+pub mod worktree;
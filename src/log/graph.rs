@@ -0,0 +1,188 @@
+use crate::objects::hash::Hash;
+
+/// draws the history graph next to the log output.
+///
+/// every column is a line of history waiting for the commit stored in it.
+/// commits are drawn as `*` in their column, and the lines following a commit show
+/// columns forking (`\`) for merges and joining (`/`) when two columns wait for the same commit.
+pub struct Graph {
+    columns: Vec<Hash>,
+    /// width of the prefixes of the last commit, kept for its remaining lines
+    width: usize,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            columns: Vec::new(),
+            width: 0,
+        }
+    }
+
+    /// adds a commit to the graph and returns the prefixes of the lines it takes:
+    /// the first prefix belongs to the commit line, the rest are connector lines
+    pub fn next_commit(&mut self, hash: &Hash, parents: &[Hash]) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        // columns waiting for this commit join before it is drawn
+        lines.extend(self.collapse());
+
+        let position = match self.columns.iter().position(|c| c == hash) {
+            Some(position) => position,
+            None => {
+                self.columns.push(hash.clone());
+                self.columns.len() - 1
+            }
+        };
+
+        let commit_line = (0..self.columns.len())
+            .map(|i| if i == position { "*" } else { "|" })
+            .collect::<Vec<&str>>()
+            .join(" ");
+        lines.push(commit_line);
+
+        let old_len = self.columns.len();
+        self.columns
+            .splice(position..position + 1, parents.iter().cloned());
+
+        if parents.len() > 1 {
+            // the extra parents fork new columns, pushing the following columns to the right
+            let mut line = String::new();
+            for i in 0..=position {
+                line.push_str(if i == 0 { "|" } else { " |" });
+            }
+            for _ in position + 1..self.columns.len() {
+                line.push_str("\\ ");
+            }
+            lines.push(line.trim_end().to_string());
+        } else if parents.is_empty() && position + 1 < old_len {
+            // the column ends here, pulling the following columns to the left
+            let mut line = String::new();
+            for i in 0..position {
+                line.push_str(if i == 0 { "|" } else { " |" });
+            }
+            line.push_str(if position == 0 { " " } else { "  " });
+            for _ in position + 1..old_len {
+                line.push_str("/ ");
+            }
+            lines.push(line.trim_end().to_string());
+        }
+
+        let new_len = self.columns.len();
+        lines.extend(self.collapse());
+
+        let width = lines
+            .iter()
+            .map(|line| line.len())
+            .chain([2 * old_len.max(new_len) - 1])
+            .max()
+            .unwrap_or(0);
+        self.width = width;
+
+        lines
+            .into_iter()
+            .map(|line| format!("{:width$} ", line, width = width))
+            .collect()
+    }
+
+    /// prefix for lines that don't belong to a commit or connector
+    pub fn padding(&self) -> String {
+        if self.width == 0 {
+            return String::new();
+        }
+
+        // the last commit keeps its width even when it ended the last column
+        let line = (0..self.columns.len())
+            .map(|_| "|")
+            .collect::<Vec<&str>>()
+            .join(" ");
+        format!("{:width$} ", line, width = self.width)
+    }
+
+    /// prefix for the blank line separating the previous commit from the next one. like
+    /// every line of the next commit, it is padded to the width that commit takes.
+    pub fn separator(&self, hash: &Hash, parents: &[Hash]) -> String {
+        if self.columns.is_empty() {
+            return String::new();
+        }
+
+        // the commit replaces its column, if it has one, with a column per parent
+        let columns =
+            self.columns.len() + parents.len().max(1) - usize::from(self.columns.contains(hash));
+        let line = "| ".repeat(self.columns.len());
+        format!("{:width$}", line, width = 2 * columns)
+    }
+
+    /// joins columns waiting for the same commit, returning the connector lines drawn
+    fn collapse(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let duplicate =
+                (1..self.columns.len()).find(|&i| self.columns[..i].contains(&self.columns[i]));
+            let duplicate = match duplicate {
+                Some(duplicate) => duplicate,
+                None => break,
+            };
+
+            let mut line = String::new();
+            for i in 0..duplicate {
+                line.push_str(if i == 0 { "|" } else { " |" });
+            }
+            for _ in duplicate..self.columns.len() {
+                line.push_str("/ ");
+            }
+            lines.push(line.trim_end().to_string());
+
+            self.columns.remove(duplicate);
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::objects::hash::Hash;
+
+    use super::Graph;
+
+    fn hash(c: char) -> Hash {
+        Hash::try_from((0..40).map(|_| c).collect::<String>().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_graph_merge() {
+        let (merge, a, b, base) = (hash('1'), hash('2'), hash('3'), hash('4'));
+        let mut graph = Graph::new();
+
+        assert_eq!(
+            graph.next_commit(&merge, &[a.clone(), b.clone()]),
+            vec!["*   ", "|\\  "]
+        );
+        assert_eq!(
+            graph.next_commit(&b, std::slice::from_ref(&base)),
+            vec!["| * "]
+        );
+        assert_eq!(
+            graph.next_commit(&a, std::slice::from_ref(&base)),
+            vec!["* | ", "|/  "]
+        );
+        assert_eq!(graph.next_commit(&base, &[]), vec!["* "]);
+        assert_eq!(graph.padding(), "  ");
+    }
+
+    #[test]
+    fn test_graph_separator() {
+        let (merge, a, b, base) = (hash('1'), hash('2'), hash('3'), hash('4'));
+        let mut graph = Graph::new();
+
+        graph.next_commit(&merge, &[a.clone(), b.clone()]);
+        assert_eq!(graph.separator(&b, std::slice::from_ref(&base)), "| | ");
+        graph.next_commit(&b, std::slice::from_ref(&base));
+        // a root commit ends its column
+        assert_eq!(graph.separator(&a, &[]), "| | ");
+        graph.next_commit(&a, &[]);
+        // a merge makes room for its second parent
+        assert_eq!(graph.separator(&base, &[a.clone(), b.clone()]), "|   ");
+    }
+}
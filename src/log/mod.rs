@@ -0,0 +1,145 @@
+mod graph;
+mod pretty;
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::{
+    date::DateFormat,
+    rev_parse::resolve_commit,
+    revwalk::{CommitOrder, RevWalk},
+};
+
+use self::graph::Graph;
+pub use self::pretty::{body, format_commit, subject, PrettyFormat};
+
+pub struct LogOptions {
+    /// revisions to start from (including ranges such as A..B and ^A), HEAD if empty
+    pub revisions: Vec<String>,
+    /// only show commits that modify these paths
+    pub paths: Vec<String>,
+    pub max_count: Option<usize>,
+    /// only show commits with a committer date after this timestamp
+    pub since: Option<u64>,
+    /// only show commits with a committer date before this timestamp
+    pub until: Option<u64>,
+    /// only show commits whose author ("name <email>") matches
+    pub author: Option<Regex>,
+    /// only show commits whose message matches
+    pub grep: Option<Regex>,
+    pub first_parent: bool,
+    pub order: CommitOrder,
+    pub graph: bool,
+    pub format: PrettyFormat,
+    pub date_format: DateFormat,
+    pub abbrev_commit: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            revisions: Vec::new(),
+            paths: Vec::new(),
+            max_count: None,
+            since: None,
+            until: None,
+            author: None,
+            grep: None,
+            first_parent: false,
+            order: CommitOrder::Default,
+            graph: false,
+            format: PrettyFormat::Medium,
+            date_format: DateFormat::default(),
+            abbrev_commit: false,
+        }
+    }
+}
+
+/// prints the commit history reachable from the requested revisions
+pub fn log(options: LogOptions) -> Result<()> {
+    let mut walk = RevWalk::new();
+    if options.revisions.is_empty() {
+        walk.push(resolve_commit("HEAD")?)?;
+    }
+
+    for revision in &options.revisions {
+        walk.push_arg(revision, false)?;
+    }
+
+    let mut order = options.order;
+    if options.graph && order == CommitOrder::Default {
+        order = CommitOrder::Topo;
+    }
+
+    walk.order(order);
+    walk.first_parent(options.first_parent);
+    walk.paths(options.paths.clone());
+    walk.max_count(options.max_count);
+    walk.rewrite_parents(options.graph);
+
+    let (since, until) = (options.since, options.until);
+    let (author, grep) = (options.author.clone(), options.grep.clone());
+    walk.filter(move |commit| {
+        if since.is_some_and(|since| commit.committer.time < since) {
+            return false;
+        }
+
+        if until.is_some_and(|until| commit.committer.time > until) {
+            return false;
+        }
+
+        if let Some(author) = &author {
+            let ident = format!("{} <{}>", commit.author.name, commit.author.email);
+            if !author.is_match(&ident) {
+                return false;
+            }
+        }
+
+        if let Some(grep) = &grep {
+            if !grep.is_match(&commit.message) {
+                return false;
+            }
+        }
+
+        true
+    });
+
+    let mut graph = Graph::new();
+    let mut first = true;
+    while let Some(entry) = walk.next_commit()? {
+        let lines = pretty::format_commit(&entry.hash, &entry.commit, &options)?;
+
+        if !first && options.format.is_multi_line() {
+            let separator = if options.graph {
+                graph.separator(&entry.hash, &entry.parents)
+            } else {
+                String::new()
+            };
+            println!("{}", separator);
+        }
+
+        if !options.graph {
+            let output = lines.join("\n");
+            match options.format {
+                PrettyFormat::Format(_) if first => print!("{}", output),
+                PrettyFormat::Format(_) => print!("\n{}", output),
+                _ => println!("{}", output),
+            }
+            first = false;
+            continue;
+        }
+
+        let mut prefixes = graph.next_commit(&entry.hash, &entry.parents).into_iter();
+        for line in lines {
+            let prefix = prefixes.next().unwrap_or_else(|| graph.padding());
+            println!("{}{}", prefix, line);
+        }
+
+        for prefix in prefixes {
+            println!("{}", prefix);
+        }
+        first = false;
+    }
+
+    Ok(())
+}
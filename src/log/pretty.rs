@@ -0,0 +1,364 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    date::{format_date, DateFormat, DateStyle},
+    objects::{
+        commit::{Author, Commit},
+        hash::Hash,
+    },
+    rev_parse::abbreviate,
+};
+
+use super::LogOptions;
+
+/// commit formats accepted by --pretty and --format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrettyFormat {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    /// placeholders with separator semantics (format:<string>)
+    Format(String),
+    /// placeholders with terminator semantics (tformat:<string>, or a bare string with placeholders)
+    TFormat(String),
+}
+
+impl TryFrom<&str> for PrettyFormat {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
+        if let Some(format) = value.strip_prefix("format:") {
+            return Ok(PrettyFormat::Format(format.to_string()));
+        }
+
+        if let Some(format) = value.strip_prefix("tformat:") {
+            return Ok(PrettyFormat::TFormat(format.to_string()));
+        }
+
+        let format = match value {
+            "oneline" => PrettyFormat::Oneline,
+            "short" => PrettyFormat::Short,
+            "medium" => PrettyFormat::Medium,
+            "full" => PrettyFormat::Full,
+            "fuller" => PrettyFormat::Fuller,
+            "raw" => PrettyFormat::Raw,
+            format if format.contains('%') => PrettyFormat::TFormat(format.to_string()),
+            _ => bail!("invalid pretty format: {}", value),
+        };
+
+        Ok(format)
+    }
+}
+
+impl PrettyFormat {
+    /// built-in multi-line formats separate commits with an empty line
+    pub fn is_multi_line(&self) -> bool {
+        !matches!(
+            self,
+            PrettyFormat::Oneline | PrettyFormat::Format(_) | PrettyFormat::TFormat(_)
+        )
+    }
+}
+
+/// formats a commit into output lines
+pub fn format_commit(hash: &Hash, commit: &Commit, options: &LogOptions) -> Result<Vec<String>> {
+    let commit_id = if options.abbrev_commit {
+        abbreviate(hash)?
+    } else {
+        hash.to_hex()
+    };
+
+    let mut lines = Vec::new();
+    match &options.format {
+        PrettyFormat::Format(format) | PrettyFormat::TFormat(format) => {
+            let expanded = expand_placeholders(format, hash, commit, &options.date_format)?;
+            lines.extend(expanded.split('\n').map(|line| line.to_string()));
+        }
+        PrettyFormat::Oneline => lines.push(format!("{} {}", commit_id, subject(&commit.message))),
+        PrettyFormat::Raw => {
+            lines.push(format!("commit {}", commit_id));
+            lines.push(format!("tree {:x}", commit.tree));
+            for parent in &commit.parents {
+                lines.push(format!("parent {:x}", parent));
+            }
+            lines.push(format!("author {}", commit.author));
+            lines.push(format!("committer {}", commit.committer));
+            lines.push(String::new());
+            lines.extend(indent_message(&commit.message));
+        }
+        format => {
+            lines.push(format!("commit {}", commit_id));
+            if commit.parents.len() > 1 {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(abbreviate)
+                    .collect::<Result<Vec<String>>>()?;
+                lines.push(format!("Merge: {}", parents.join(" ")));
+            }
+
+            let date =
+                |author: &Author| format_date(author.time, &author.time_zone, &options.date_format);
+            match format {
+                PrettyFormat::Short => lines.push(format!("Author: {}", ident(&commit.author))),
+                PrettyFormat::Medium => {
+                    lines.push(format!("Author: {}", ident(&commit.author)));
+                    lines.push(format!("Date:   {}", date(&commit.author)?));
+                }
+                PrettyFormat::Full => {
+                    lines.push(format!("Author: {}", ident(&commit.author)));
+                    lines.push(format!("Commit: {}", ident(&commit.committer)));
+                }
+                _ => {
+                    lines.push(format!("Author:     {}", ident(&commit.author)));
+                    lines.push(format!("AuthorDate: {}", date(&commit.author)?));
+                    lines.push(format!("Commit:     {}", ident(&commit.committer)));
+                    lines.push(format!("CommitDate: {}", date(&commit.committer)?));
+                }
+            }
+
+            lines.push(String::new());
+            if *format == PrettyFormat::Short {
+                lines.extend(indent_message(&subject(&commit.message)));
+            } else {
+                lines.extend(indent_message(&commit.message));
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+fn ident(author: &Author) -> String {
+    format!("{} <{}>", author.name, author.email)
+}
+
+fn indent_message(message: &str) -> Vec<String> {
+    message
+        .trim_start_matches('\n')
+        .trim_end()
+        .lines()
+        .map(|line| format!("    {}", line))
+        .collect()
+}
+
+/// the subject is the first paragraph of the message, joined into a single line
+pub fn subject(message: &str) -> String {
+    message
+        .trim_start_matches('\n')
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .map(|line| line.trim())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// the body is everything after the first paragraph of the message
+pub fn body(message: &str) -> String {
+    let mut lines = message
+        .trim_start_matches('\n')
+        .lines()
+        .skip_while(|line| !line.trim().is_empty())
+        .skip_while(|line| line.trim().is_empty())
+        .peekable();
+
+    let mut body = String::new();
+    while let Some(line) = lines.next() {
+        body.push_str(line);
+        body.push('\n');
+        if lines.peek().is_none() {
+            break;
+        }
+    }
+
+    body
+}
+
+/// expands the placeholders supported by --format
+pub fn expand_placeholders(
+    format: &str,
+    hash: &Hash,
+    commit: &Commit,
+    date_format: &DateFormat,
+) -> Result<String> {
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let placeholder = match chars.next() {
+            Some(placeholder) => placeholder,
+            None => {
+                output.push('%');
+                break;
+            }
+        };
+
+        match placeholder {
+            '%' => output.push('%'),
+            'n' => output.push('\n'),
+            'H' => output.push_str(&hash.to_hex()),
+            'h' => output.push_str(&abbreviate(hash)?),
+            'T' => output.push_str(&commit.tree.to_hex()),
+            't' => output.push_str(&abbreviate(&commit.tree)?),
+            'P' => {
+                let parents: Vec<String> = commit.parents.iter().map(|p| p.to_hex()).collect();
+                output.push_str(&parents.join(" "));
+            }
+            'p' => {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(abbreviate)
+                    .collect::<Result<Vec<String>>>()?;
+                output.push_str(&parents.join(" "));
+            }
+            's' => output.push_str(&subject(&commit.message)),
+            'b' => output.push_str(&body(&commit.message)),
+            'B' => {
+                output.push_str(&commit.message);
+                output.push('\n');
+            }
+            'a' | 'c' => {
+                let person = if placeholder == 'a' {
+                    &commit.author
+                } else {
+                    &commit.committer
+                };
+
+                let field = match chars.next() {
+                    Some(field) => field,
+                    None => {
+                        output.push('%');
+                        output.push(placeholder);
+                        break;
+                    }
+                };
+
+                let date_style = |style: DateStyle| DateFormat {
+                    style,
+                    local: false,
+                };
+                let expanded = match field {
+                    'n' => person.name.clone(),
+                    'e' => person.email.clone(),
+                    'd' => format_date(person.time, &person.time_zone, date_format)?,
+                    'D' => {
+                        format_date(person.time, &person.time_zone, &date_style(DateStyle::Rfc))?
+                    }
+                    'r' => format_date(
+                        person.time,
+                        &person.time_zone,
+                        &date_style(DateStyle::Relative),
+                    )?,
+                    't' => person.time.to_string(),
+                    'i' => {
+                        format_date(person.time, &person.time_zone, &date_style(DateStyle::Iso))?
+                    }
+                    'I' => format_date(
+                        person.time,
+                        &person.time_zone,
+                        &date_style(DateStyle::IsoStrict),
+                    )?,
+                    's' => format_date(
+                        person.time,
+                        &person.time_zone,
+                        &date_style(DateStyle::Short),
+                    )?,
+                    _ => format!("%{}{}", placeholder, field),
+                };
+                output.push_str(&expanded);
+            }
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) => output.push(byte as char),
+                    Err(_) => output.push_str(&format!("%x{}", digits)),
+                }
+            }
+            _ => {
+                output.push('%');
+                output.push(placeholder);
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        date::DateFormat,
+        objects::{
+            commit::{Author, Commit},
+            hash::Hash,
+        },
+    };
+
+    use super::{body, expand_placeholders, subject, PrettyFormat};
+
+    #[test]
+    fn test_subject_and_body() {
+        let message = "first line\ncontinued\n\nbody line 1\nbody line 2";
+        assert_eq!(subject(message), "first line continued");
+        assert_eq!(body(message), "body line 1\nbody line 2\n");
+        assert_eq!(body("only subject"), "");
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        let hash = Hash::try_from((0..40).map(|_| 'a').collect::<String>().as_bytes()).unwrap();
+        let author = Author {
+            name: String::from("A U Thor"),
+            email: String::from("author@example.com"),
+            time: 1112911993,
+            time_zone: String::from("-0700"),
+        };
+        let commit = Commit {
+            tree: hash.clone(),
+            author: author.clone(),
+            committer: author,
+            parents: Vec::new(),
+            message: String::from("subject\n\nbody"),
+            additional_data: None,
+        };
+
+        let expanded = expand_placeholders(
+            "%H%n%an <%ae> %ad%n%s|%b%%",
+            &hash,
+            &commit,
+            &DateFormat::try_from("short").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            format!(
+                "{}\nA U Thor <author@example.com> 2005-04-07\nsubject|body\n%",
+                hash.to_hex()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_pretty_format() {
+        assert_eq!(
+            PrettyFormat::try_from("oneline").unwrap(),
+            PrettyFormat::Oneline
+        );
+        assert_eq!(
+            PrettyFormat::try_from("%h %s").unwrap(),
+            PrettyFormat::TFormat(String::from("%h %s"))
+        );
+        assert_eq!(
+            PrettyFormat::try_from("format:%H").unwrap(),
+            PrettyFormat::Format(String::from("%H"))
+        );
+        assert!(PrettyFormat::try_from("unknown").is_err());
+    }
+}
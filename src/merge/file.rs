@@ -0,0 +1,636 @@
+use anyhow::bail;
+
+use crate::diff::{diff_lines, split_lines, DiffAlgorithm, Edit, LineDiffOptions};
+
+/// default length of conflict markers
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// how conflicts are shown in merged files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictStyle {
+    /// our lines and their lines
+    #[default]
+    Merge,
+    /// our lines, the base lines and their lines
+    Diff3,
+    /// like diff3, with the lines common to both sides at the edges moved out of the conflict
+    ZealousDiff3,
+}
+
+impl TryFrom<&str> for ConflictStyle {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
+        let style = match value {
+            "merge" => ConflictStyle::Merge,
+            "diff3" => ConflictStyle::Diff3,
+            "zdiff3" => ConflictStyle::ZealousDiff3,
+            _ => bail!("unknown conflict style '{}'", value),
+        };
+
+        Ok(style)
+    }
+}
+
+/// the side conflicts are resolved to, if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Favor {
+    #[default]
+    None,
+    Ours,
+    Theirs,
+    /// keep the lines of both sides
+    Union,
+}
+
+impl Favor {
+    /// the mode conflicting regions are given, as in xdiff
+    fn mode(&self) -> u8 {
+        match self {
+            Favor::None => 0,
+            Favor::Ours => 1,
+            Favor::Theirs => 2,
+            Favor::Union => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeFileOptions {
+    pub style: ConflictStyle,
+    pub favor: Favor,
+    pub marker_size: usize,
+    /// the names shown after the conflict markers
+    pub ours_label: Option<String>,
+    pub base_label: Option<String>,
+    pub theirs_label: Option<String>,
+    pub algorithm: DiffAlgorithm,
+}
+
+impl Default for MergeFileOptions {
+    fn default() -> Self {
+        MergeFileOptions {
+            style: ConflictStyle::default(),
+            favor: Favor::default(),
+            marker_size: DEFAULT_MARKER_SIZE,
+            ours_label: None,
+            base_label: None,
+            theirs_label: None,
+            algorithm: DiffAlgorithm::default(),
+        }
+    }
+}
+
+/// the result of a three-way merge of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedFile {
+    pub content: Vec<u8>,
+    /// the number of conflicts written to the content
+    pub conflicts: usize,
+}
+
+/// how far conflicts are reduced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    /// identical changes are still conflicts
+    Minimal,
+    /// identical changes on both sides are merged
+    Eager,
+    /// conflicts are narrowed down to the lines that differ between the sides
+    Zealous,
+}
+
+/// a region of the merge. lines of the base are counted with i0,
+/// lines of our side with i1 and lines of their side with i2.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    /// 0 for conflicts, 1 for our changes, 2 for theirs, 3 for both,
+    /// and 4 for identical changes found when refining a conflict
+    mode: u8,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+struct Sides<'a> {
+    base: Vec<&'a [u8]>,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+}
+
+/// merges the changes made to base by both sides, writing conflict markers around the
+/// changes that overlap
+pub fn merge_file(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    options: &MergeFileOptions,
+) -> MergedFile {
+    let line_options = LineDiffOptions {
+        algorithm: options.algorithm,
+        indent_heuristic: false,
+        ..Default::default()
+    };
+    let edits1 = diff_lines(base, ours, &line_options);
+    let edits2 = diff_lines(base, theirs, &line_options);
+
+    if edits1.is_empty() {
+        return MergedFile {
+            content: theirs.to_vec(),
+            conflicts: 0,
+        };
+    }
+    if edits2.is_empty() {
+        return MergedFile {
+            content: ours.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let sides = Sides {
+        base: split_lines(base),
+        ours: split_lines(ours),
+        theirs: split_lines(theirs),
+    };
+
+    // diff3 output does not make sense when refining conflicts, as the base is shown
+    let level = match options.style {
+        ConflictStyle::Merge => Level::Zealous,
+        ConflictStyle::Diff3 | ConflictStyle::ZealousDiff3 => Level::Eager,
+    };
+
+    let mut regions = collect_regions(&sides, &edits1, &edits2, level);
+    match options.style {
+        ConflictStyle::ZealousDiff3 => refine_zdiff3_conflicts(&sides, &mut regions),
+        _ if level >= Level::Zealous => {
+            regions = refine_conflicts(&sides, regions, &line_options);
+            simplify_non_conflicts(&mut regions);
+        }
+        _ => {}
+    }
+
+    fill_merge_buffer(&sides, &mut regions, options)
+}
+
+/// walks both edit scripts together, turning them into regions changed by one side,
+/// and conflicts where both changed the same lines of the base
+fn collect_regions(sides: &Sides, edits1: &[Edit], edits2: &[Edit], level: Level) -> Vec<Region> {
+    let edit = |edit: &Edit| {
+        (
+            edit.old_start as isize,
+            edit.old_count as isize,
+            edit.new_start as isize,
+            edit.new_count as isize,
+        )
+    };
+
+    let mut regions: Vec<Region> = Vec::new();
+    let (mut k1, mut k2) = (0, 0);
+    while k1 < edits1.len() && k2 < edits2.len() {
+        let (a_i1, a_chg1, a_i2, a_chg2) = edit(&edits1[k1]);
+        let (b_i1, b_chg1, b_i2, b_chg2) = edit(&edits2[k2]);
+
+        if a_i1 + a_chg1 < b_i1 {
+            let i2 = b_i2 - b_i1 + a_i1;
+            append_region(&mut regions, 1, a_i1, a_chg1, a_i2, a_chg2, i2, a_chg1);
+            k1 += 1;
+            continue;
+        }
+        if b_i1 + b_chg1 < a_i1 {
+            let i1 = a_i2 - a_i1 + b_i1;
+            append_region(&mut regions, 2, b_i1, b_chg1, i1, b_chg1, b_i2, b_chg2);
+            k2 += 1;
+            continue;
+        }
+
+        let identical = a_i1 == b_i1
+            && a_chg1 == b_chg1
+            && a_chg2 == b_chg2
+            && (0..a_chg2 as usize)
+                .all(|k| sides.ours[a_i2 as usize + k] == sides.theirs[b_i2 as usize + k]);
+        if level == Level::Minimal || !identical {
+            let off = a_i1 - b_i1;
+            let ffo = off + a_chg1 - b_chg1;
+
+            let (mut i0, mut i1, mut i2) = (a_i1, a_i2, b_i2);
+            if off > 0 {
+                i0 -= off;
+                i1 -= off;
+            } else {
+                i2 += off;
+            }
+            let mut chg0 = a_i1 + a_chg1 - i0;
+            let mut chg1 = a_i2 + a_chg2 - i1;
+            let mut chg2 = b_i2 + b_chg2 - i2;
+            if ffo < 0 {
+                chg0 -= ffo;
+                chg1 -= ffo;
+            } else {
+                chg2 += ffo;
+            }
+            append_region(&mut regions, 0, i0, chg0, i1, chg1, i2, chg2);
+        }
+
+        let end1 = a_i1 + a_chg1;
+        let end2 = b_i1 + b_chg1;
+        if end1 >= end2 {
+            k2 += 1;
+        }
+        if end2 >= end1 {
+            k1 += 1;
+        }
+    }
+
+    let nrec0 = sides.base.len() as isize;
+    for edit1 in &edits1[k1..] {
+        let (i1, chg1, i2, chg2) = edit(edit1);
+        let theirs_i = i1 + sides.theirs.len() as isize - nrec0;
+        append_region(&mut regions, 1, i1, chg1, i2, chg2, theirs_i, chg1);
+    }
+    for edit2 in &edits2[k2..] {
+        let (i1, chg1, i2, chg2) = edit(edit2);
+        let ours_i = i1 + sides.ours.len() as isize - nrec0;
+        append_region(&mut regions, 2, i1, chg1, ours_i, chg1, i2, chg2);
+    }
+
+    regions
+}
+
+/// adds a region, coalescing it with the previous one when they touch.
+/// coalesced regions changed by different sides become conflicts.
+#[allow(clippy::too_many_arguments)]
+fn append_region(
+    regions: &mut Vec<Region>,
+    mode: u8,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+) {
+    if let Some(last) = regions.last_mut() {
+        if i1 <= last.i1 + last.chg1 || i2 <= last.i2 + last.chg2 {
+            if mode != last.mode {
+                last.mode = 0;
+            }
+            last.chg0 = i0 + chg0 - last.i0;
+            last.chg1 = i1 + chg1 - last.i1;
+            last.chg2 = i2 + chg2 - last.i2;
+            return;
+        }
+    }
+
+    regions.push(Region {
+        mode,
+        i0,
+        chg0,
+        i1,
+        chg1,
+        i2,
+        chg2,
+    });
+}
+
+/// diffs the two sides of each conflict, keeping only the lines that differ in conflict.
+/// a conflict can be split in several, and conflicts between identical changes are dropped.
+fn refine_conflicts(sides: &Sides, regions: Vec<Region>, options: &LineDiffOptions) -> Vec<Region> {
+    let mut refined = Vec::with_capacity(regions.len());
+    for region in regions {
+        // no sense refining a conflict when one side is empty
+        if region.mode != 0 || region.chg1 == 0 || region.chg2 == 0 {
+            refined.push(region);
+            continue;
+        }
+
+        let ours = concat(&sides.ours, region.i1, region.chg1);
+        let theirs = concat(&sides.theirs, region.i2, region.chg2);
+        let edits = diff_lines(&ours, &theirs, options);
+        if edits.is_empty() {
+            refined.push(Region { mode: 4, ..region });
+            continue;
+        }
+
+        for edit in edits {
+            refined.push(Region {
+                mode: 0,
+                i1: region.i1 + edit.old_start as isize,
+                chg1: edit.old_count as isize,
+                i2: region.i2 + edit.new_start as isize,
+                chg2: edit.new_count as isize,
+                ..region
+            });
+        }
+    }
+
+    refined
+}
+
+/// moves the lines both sides agree on at the start and the end of each conflict out of it
+fn refine_zdiff3_conflicts(sides: &Sides, regions: &mut [Region]) {
+    for region in regions.iter_mut().filter(|region| region.mode == 0) {
+        while region.chg1 > 0
+            && region.chg2 > 0
+            && sides.ours[region.i1 as usize] == sides.theirs[region.i2 as usize]
+        {
+            region.chg1 -= 1;
+            region.chg2 -= 1;
+            region.i1 += 1;
+            region.i2 += 1;
+        }
+        while region.chg1 > 0
+            && region.chg2 > 0
+            && sides.ours[(region.i1 + region.chg1 - 1) as usize]
+                == sides.theirs[(region.i2 + region.chg2 - 1) as usize]
+        {
+            region.chg1 -= 1;
+            region.chg2 -= 1;
+        }
+    }
+}
+
+/// merges conflicts separated by three lines or less, as the result takes up
+/// less (or as many) lines with the lines between them moved into a single conflict
+fn simplify_non_conflicts(regions: &mut Vec<Region>) {
+    let mut k = 0;
+    while k + 1 < regions.len() {
+        let (current, next) = (regions[k], regions[k + 1]);
+        let begin = current.i1 + current.chg1;
+        let end = next.i1;
+        if current.mode != 0 || next.mode != 0 || end - begin > 3 {
+            k += 1;
+            continue;
+        }
+
+        let merged = &mut regions[k];
+        merged.chg0 = next.i0 + next.chg0 - merged.i0;
+        merged.chg1 = next.i1 + next.chg1 - merged.i1;
+        merged.chg2 = next.i2 + next.chg2 - merged.i2;
+        regions.remove(k + 1);
+    }
+}
+
+fn concat(lines: &[&[u8]], start: isize, count: isize) -> Vec<u8> {
+    lines[start as usize..(start + count) as usize].concat()
+}
+
+/// writes the merged file: our lines, with the changes of each region applied
+fn fill_merge_buffer(
+    sides: &Sides,
+    regions: &mut [Region],
+    options: &MergeFileOptions,
+) -> MergedFile {
+    let mut content = Vec::new();
+    let mut conflicts = 0;
+    let mut i = 0;
+    for region in regions.iter_mut() {
+        if region.mode == 0 {
+            region.mode = options.favor.mode();
+        }
+
+        if region.mode == 0 {
+            conflicts += 1;
+            fill_conflict_hunk(sides, &mut content, i, region, options);
+        } else if region.mode & 3 != 0 {
+            copy_lines(&mut content, &sides.ours, i, region.i1 - i, false, false);
+            if region.mode & 1 != 0 {
+                let needs_cr = is_cr_needed(sides, region);
+                let add_nl = region.mode & 2 != 0;
+                copy_lines(
+                    &mut content,
+                    &sides.ours,
+                    region.i1,
+                    region.chg1,
+                    needs_cr,
+                    add_nl,
+                );
+            }
+            if region.mode & 2 != 0 {
+                copy_lines(
+                    &mut content,
+                    &sides.theirs,
+                    region.i2,
+                    region.chg2,
+                    false,
+                    false,
+                );
+            }
+        } else {
+            continue;
+        }
+        i = region.i1 + region.chg1;
+    }
+    let rest = sides.ours.len() as isize - i;
+    copy_lines(&mut content, &sides.ours, i, rest, false, false);
+
+    MergedFile { content, conflicts }
+}
+
+fn fill_conflict_hunk(
+    sides: &Sides,
+    content: &mut Vec<u8>,
+    i: isize,
+    region: &Region,
+    options: &MergeFileOptions,
+) {
+    let needs_cr = is_cr_needed(sides, region);
+    let marker = |content: &mut Vec<u8>, c: u8, label: Option<&str>| {
+        content.extend(std::iter::repeat_n(c, options.marker_size));
+        if let Some(label) = label {
+            content.push(b' ');
+            content.extend_from_slice(label.as_bytes());
+        }
+        if needs_cr {
+            content.push(b'\r');
+        }
+        content.push(b'\n');
+    };
+
+    copy_lines(content, &sides.ours, i, region.i1 - i, false, false);
+
+    marker(content, b'<', options.ours_label.as_deref());
+    copy_lines(content, &sides.ours, region.i1, region.chg1, needs_cr, true);
+
+    if options.style != ConflictStyle::Merge {
+        marker(content, b'|', options.base_label.as_deref());
+        copy_lines(content, &sides.base, region.i0, region.chg0, needs_cr, true);
+    }
+
+    marker(content, b'=', None);
+    copy_lines(
+        content,
+        &sides.theirs,
+        region.i2,
+        region.chg2,
+        needs_cr,
+        true,
+    );
+    marker(content, b'>', options.theirs_label.as_deref());
+}
+
+/// copies count lines from start, ending them with a newline if add_nl is set and
+/// the last one has none
+fn copy_lines(
+    content: &mut Vec<u8>,
+    lines: &[&[u8]],
+    start: isize,
+    count: isize,
+    needs_cr: bool,
+    add_nl: bool,
+) {
+    if count < 1 {
+        return;
+    }
+
+    let lines = &lines[start as usize..(start + count) as usize];
+    for line in lines {
+        content.extend_from_slice(line);
+    }
+    if add_nl && !lines.last().is_some_and(|line| line.ends_with(b"\n")) {
+        if needs_cr {
+            content.push(b'\r');
+        }
+        content.push(b'\n');
+    }
+}
+
+/// whether the line at i ends with CRLF. None if it can't be told from the file.
+fn is_eol_crlf(lines: &[&[u8]], i: usize) -> Option<bool> {
+    let crlf = |line: &[u8]| line.len() > 1 && line[line.len() - 2] == b'\r';
+    if i + 1 < lines.len() {
+        // all lines before the last end with LF
+        return Some(crlf(lines[i]));
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    if lines[i].ends_with(b"\n") {
+        return Some(crlf(lines[i]));
+    }
+    if i == 0 {
+        // the only line has no end of line
+        return None;
+    }
+    // the end of line of the last line is the one of the line before it
+    Some(crlf(lines[i - 1]))
+}
+
+/// whether the lines added around a conflict should end with CRLF: they do when the lines
+/// before the conflict on both sides do, and when the first line of the base does
+fn is_cr_needed(sides: &Sides, region: &Region) -> bool {
+    let before = |i: isize| (i - 1).max(0) as usize;
+    let mut needs_cr = is_eol_crlf(&sides.ours, before(region.i1));
+    if needs_cr != Some(false) {
+        needs_cr = is_eol_crlf(&sides.theirs, before(region.i2));
+    }
+    if needs_cr != Some(false) {
+        needs_cr = is_eol_crlf(&sides.base, 0);
+    }
+    needs_cr.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{merge_file, ConflictStyle, Favor, MergeFileOptions};
+
+    fn options(style: ConflictStyle, favor: Favor) -> MergeFileOptions {
+        MergeFileOptions {
+            style,
+            favor,
+            ours_label: Some(String::from("ours")),
+            base_label: Some(String::from("base")),
+            theirs_label: Some(String::from("theirs")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_file_clean() {
+        let base = b"a\nb\nc\nd\ne\nf\ng\n";
+        let ours = b"A\nb\nc\nd\ne\nf\ng\n";
+        let theirs = b"a\nb\nc\nd\ne\nf\nG\n";
+        let merged = merge_file(base, ours, theirs, &MergeFileOptions::default());
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.content, b"A\nb\nc\nd\ne\nf\nG\n");
+
+        // identical changes on both sides are not conflicts
+        let merged = merge_file(base, ours, ours, &MergeFileOptions::default());
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.content, ours);
+    }
+
+    #[test]
+    fn test_merge_file_conflict_styles() {
+        let base = b"1\n2\n3\n";
+        let ours = b"1\nx\ny\n3\n";
+        let theirs = b"1\nx\nz\n3\n";
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::Merge, Favor::None),
+        );
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "1\nx\n<<<<<<< ours\ny\n=======\nz\n>>>>>>> theirs\n3\n"
+        );
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::Diff3, Favor::None),
+        );
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "1\n<<<<<<< ours\nx\ny\n||||||| base\n2\n=======\nx\nz\n>>>>>>> theirs\n3\n"
+        );
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::ZealousDiff3, Favor::None),
+        );
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "1\nx\n<<<<<<< ours\ny\n||||||| base\n2\n=======\nz\n>>>>>>> theirs\n3\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_file_favor() {
+        let base = b"1\n2\n3\n";
+        let ours = b"1\nours\n3\n";
+        let theirs = b"1\ntheirs\n3\n";
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::Merge, Favor::Ours),
+        );
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.content, ours);
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::Merge, Favor::Theirs),
+        );
+        assert_eq!(merged.content, theirs);
+
+        let merged = merge_file(
+            base,
+            ours,
+            theirs,
+            &options(ConflictStyle::Merge, Favor::Union),
+        );
+        assert_eq!(merged.content, b"1\nours\ntheirs\n3\n");
+    }
+}
@@ -0,0 +1,352 @@
+pub mod file;
+pub mod tree;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::read_config,
+    diff::write_tree_stat,
+    gc::auto_gc,
+    ident::{identity, Role},
+    index::{read_index, write_index, Index},
+    log::{format_commit, LogOptions},
+    merge_base::CommitGraph,
+    objects::{
+        commit::{decode_commit, new_commit},
+        hash::Hash,
+        Object, ObjectKind,
+    },
+    refs::{head_branch, resolve_ref, update_ref},
+    rev_parse::{abbreviate, peel, resolve_commit},
+    revwalk::RevWalk,
+    worktree::{
+        check_overwrite, checkout_file, remove_file, staged_paths, switch_index, update_worktree,
+    },
+};
+
+use self::{
+    file::{ConflictStyle, Favor},
+    tree::{merge_commits, TreeMergeOptions, TreeMergeResult},
+};
+
+const MERGE_HEAD: &str = ".git/MERGE_HEAD";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+const MERGE_MODE: &str = ".git/MERGE_MODE";
+const SQUASH_MSG: &str = ".git/SQUASH_MSG";
+const ORIG_HEAD: &str = ".git/ORIG_HEAD";
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// the commit to merge into the current branch
+    pub commit: Option<String>,
+    /// create a merge commit even when the merge is a fast-forward
+    pub no_ff: bool,
+    /// refuse to merge unless the merge is a fast-forward
+    pub ff_only: bool,
+    /// update the index and the working tree without committing or recording the merge
+    pub squash: bool,
+    /// abort the merge in progress
+    pub abort: bool,
+    /// options of the merge strategy (-X): ours or theirs
+    pub strategy_options: Vec<String>,
+    pub message: Option<String>,
+}
+
+/// joins the history of a commit with the current branch. returns false if the merge
+/// stopped on conflicts.
+pub fn merge(options: MergeOptions) -> Result<bool> {
+    if options.abort {
+        abort()?;
+        return Ok(true);
+    }
+
+    if Path::new(MERGE_HEAD).is_file() {
+        bail!("You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge.");
+    }
+    let index = read_index()?;
+    if index.has_conflicts() {
+        bail!("Merging is not possible because you have unmerged files.\nhint: Fix them up in the work tree, and then use 'git add/rm <file>'\nhint: as appropriate to mark resolution and make a commit.");
+    }
+
+    let name = options
+        .commit
+        .as_deref()
+        .ok_or(anyhow!("No commit specified"))?;
+    let theirs = resolve_commit(name)?;
+    let favor = parse_strategy_options(&options.strategy_options)?;
+    let message = match &options.message {
+        Some(message) => message.clone(),
+        None => merge_message(name)?,
+    };
+
+    let head = match resolve_ref("HEAD")? {
+        Some(head) => head,
+        // merging into an unborn branch checks out the commit
+        None => return fast_forward(&index, None, &theirs, name, &options),
+    };
+    fs::write(ORIG_HEAD, format!("{:x}\n", head))?;
+
+    let mut graph = CommitGraph::new();
+    if graph.is_ancestor(&theirs, &head)? {
+        println!("Already up to date.");
+        return Ok(true);
+    }
+    if graph.is_ancestor(&head, &theirs)? && !options.no_ff {
+        return fast_forward(&index, Some(&head), &theirs, name, &options);
+    }
+    if options.ff_only {
+        bail!("Not possible to fast-forward, aborting.");
+    }
+
+    // the index is used to record the merge, so it must not have changes of its own
+    let head_tree = commit_tree(&head)?;
+    let head_index = Index::from_tree(&head_tree)?;
+    let staged = staged_paths(&index, &head_index);
+    if !staged.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by merge:\n  {}",
+            staged.join(" ")
+        );
+    }
+
+    let tree_options = TreeMergeOptions {
+        theirs_label: name.to_string(),
+        style: conflict_style()?,
+        favor,
+        ..Default::default()
+    };
+    let result = merge_commits(&head, &theirs, &tree_options)?;
+
+    let merged_index = checkout_merge(&index, &result, "merge")?;
+
+    for message in &result.messages {
+        println!("{}", message);
+    }
+
+    let mut conflicts = String::from("\n# Conflicts:\n");
+    for path in result.conflicted_paths() {
+        conflicts.push_str(&format!("#\t{}\n", path));
+    }
+
+    if options.squash {
+        fs::write(SQUASH_MSG, squash_message(&head, &theirs)?)?;
+        if result.is_clean() {
+            println!("Automatic merge went well; stopped before committing as requested");
+            println!("Squash commit -- not updating HEAD");
+            return Ok(true);
+        }
+        fs::write(MERGE_MSG, conflicts)?;
+        println!("Squash commit -- not updating HEAD");
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return Ok(false);
+    }
+
+    if !result.is_clean() {
+        let merge_msg = format!("{}\n{}", message, conflicts);
+        fs::write(MERGE_HEAD, format!("{:x}\n", theirs))?;
+        fs::write(MERGE_MSG, merge_msg)?;
+        fs::write(MERGE_MODE, if options.no_ff { "no-ff" } else { "" })?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return Ok(false);
+    }
+
+    let tree = merged_index.write_tree()?;
+    let commit = new_commit(
+        tree.clone(),
+        vec![head, theirs],
+        identity(Role::Author)?,
+        Some(identity(Role::Committer)?),
+        None,
+        Some(message),
+    )?;
+    let commit = commit.write()?;
+    update_ref(
+        "HEAD",
+        &commit,
+        &format!("merge {}: Merge made by the 'ort' strategy.", name),
+    )?;
+
+    println!("Merge made by the 'ort' strategy.");
+    write_tree_stat(&mut io::stdout().lock(), Some(&head_tree), &tree)?;
+    auto_gc(false)?;
+    Ok(true)
+}
+
+/// updates the index and the working tree to the result of a tree merge. conflicting paths
+/// are recorded in the index at stages 1 to 3, and written with conflict markers.
+pub fn checkout_merge(index: &Index, result: &TreeMergeResult, operation: &str) -> Result<Index> {
+    let mut merged_index = result.index();
+    check_overwrite(index, &merged_index, operation)?;
+    update_worktree(index, &mut merged_index)?;
+    for path in result.paths.iter().filter(|path| path.conflict.is_some()) {
+        match &path.file {
+            Some(file) => {
+                checkout_file(&file.path, file.mode, &file.hash, 0)?;
+            }
+            None => remove_file(&path.path)?,
+        }
+    }
+    write_index(&merged_index)?;
+    Ok(merged_index)
+}
+
+/// moves HEAD forward to a descendant, checking out its tree. with --squash only the
+/// index and the working tree are updated.
+fn fast_forward(
+    index: &Index,
+    head: Option<&Hash>,
+    theirs: &Hash,
+    name: &str,
+    options: &MergeOptions,
+) -> Result<bool> {
+    let head_tree = head.map(commit_tree).transpose()?;
+    let from = match &head_tree {
+        Some(tree) => Index::from_tree(tree)?,
+        None => Index::default(),
+    };
+    let theirs_tree = commit_tree(theirs)?;
+
+    let mut switched = switch_index(index, &from, &Index::from_tree(&theirs_tree)?, "merge")?;
+    check_overwrite(index, &switched, "merge")?;
+
+    if let Some(head) = head {
+        println!("Updating {}..{}", abbreviate(head)?, abbreviate(theirs)?);
+        println!("Fast-forward");
+    }
+    update_worktree(index, &mut switched)?;
+    write_index(&switched)?;
+
+    let mut stdout = io::stdout().lock();
+    match options.squash {
+        true => {
+            if let Some(head) = head {
+                fs::write(SQUASH_MSG, squash_message(head, theirs)?)?;
+            }
+            writeln!(stdout, "Squash commit -- not updating HEAD")?;
+        }
+        false => {
+            update_ref("HEAD", theirs, &format!("merge {}: Fast-forward", name))?;
+        }
+    }
+
+    write_tree_stat(&mut stdout, head_tree.as_ref(), &theirs_tree)?;
+    if !options.squash {
+        auto_gc(false)?;
+    }
+    Ok(true)
+}
+
+/// resets the index and the working tree to HEAD, and forgets the merge in progress.
+/// changes to paths the merge didn't touch are kept.
+fn abort() -> Result<()> {
+    if !Path::new(MERGE_HEAD).is_file() {
+        bail!("There is no merge to abort (MERGE_HEAD missing).");
+    }
+
+    let head = resolve_commit("HEAD")?;
+    let index = read_index()?;
+    let head_index = Index::from_tree(&commit_tree(&head)?)?;
+
+    // entries that differ from HEAD are the merge's, and are reset along with their files
+    let mut reset = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    let merge_paths = staged_paths(&index, &head_index);
+    for entry in &index.entries {
+        if !merge_paths.contains(&entry.path.as_str()) {
+            reset.entries.push(entry.clone());
+        }
+    }
+    for entry in &head_index.entries {
+        if merge_paths.contains(&entry.path.as_str()) {
+            let mode = entry.mode.try_into()?;
+            let checked_out = checkout_file(&entry.path, mode, &entry.hash, 0)?;
+            reset.add(checked_out);
+        }
+    }
+    for path in &merge_paths {
+        if head_index.entry(path).is_none() {
+            remove_file(path)?;
+        }
+    }
+    write_index(&reset)?;
+
+    for file in [MERGE_HEAD, MERGE_MSG, MERGE_MODE] {
+        if Path::new(file).is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// the style of conflict markers set by merge.conflictStyle
+pub fn conflict_style() -> Result<ConflictStyle> {
+    match read_config()?.get("merge.conflictstyle") {
+        Some(style) => ConflictStyle::try_from(style),
+        None => Ok(ConflictStyle::default()),
+    }
+}
+
+fn parse_strategy_options(strategy_options: &[String]) -> Result<Favor> {
+    let mut favor = Favor::None;
+    for option in strategy_options {
+        favor = match option.as_str() {
+            "ours" => Favor::Ours,
+            "theirs" => Favor::Theirs,
+            option => bail!("unknown strategy option: -X{}", option),
+        };
+    }
+    Ok(favor)
+}
+
+/// the default message of a merge commit, naming what was merged and, unless it is
+/// main or master, the branch merged into
+fn merge_message(name: &str) -> Result<String> {
+    let mut message = if resolve_ref(&format!("refs/heads/{}", name))?.is_some() {
+        format!("Merge branch '{}'", name)
+    } else if resolve_ref(&format!("refs/remotes/{}", name))?.is_some() {
+        format!("Merge remote-tracking branch '{}'", name)
+    } else if resolve_ref(&format!("refs/tags/{}", name))?.is_some() {
+        format!("Merge tag '{}'", name)
+    } else {
+        format!("Merge commit '{}'", name)
+    };
+
+    if let Some(branch) = head_branch()? {
+        let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+        if branch != "main" && branch != "master" {
+            message.push_str(&format!(" into {}", branch));
+        }
+    }
+    Ok(message)
+}
+
+/// the message suggested for the commit of a squashed merge: the log of the merged commits
+fn squash_message(head: &Hash, theirs: &Hash) -> Result<String> {
+    let mut walk = RevWalk::new();
+    walk.push(theirs.clone())?;
+    walk.hide(head.clone())?;
+
+    let mut message = String::from("Squashed commit of the following:\n");
+    while let Some(entry) = walk.next_commit()? {
+        let object = Object::read_from_hash(entry.hash.to_hex())?;
+        let commit = decode_commit(object.data)?;
+        message.push('\n');
+        for line in format_commit(&entry.hash, &commit, &LogOptions::default())? {
+            message.push_str(&line);
+            message.push('\n');
+        }
+    }
+    Ok(message)
+}
+
+fn commit_tree(commit: &Hash) -> Result<Hash> {
+    peel(commit.clone(), Some(ObjectKind::Tree))
+}
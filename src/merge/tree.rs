@@ -0,0 +1,723 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::{
+    diff::{
+        diff_trees, is_binary, list_tree_files, ChangeStatus, DiffFile, TreeDiffOptions,
+        DEFAULT_RENAME_SCORE,
+    },
+    index::{Index, IndexEntry},
+    merge_base::CommitGraph,
+    objects::{hash::Hash, tree::EntryMode, Object, ObjectKind},
+    rev_parse::{abbreviate, peel},
+};
+
+use super::file::{merge_file, ConflictStyle, Favor, MergeFileOptions, DEFAULT_MARKER_SIZE};
+
+#[derive(Debug, Clone)]
+pub struct TreeMergeOptions {
+    /// the names of both sides, shown in conflict markers and messages
+    pub ours_label: String,
+    pub theirs_label: String,
+    /// the name of the merge base in diff3 conflicts. merge_commits sets it from the merge bases.
+    pub ancestor_label: String,
+    pub style: ConflictStyle,
+    /// the side conflicting changes are resolved to (-X ours/theirs)
+    pub favor: Favor,
+    pub find_renames: bool,
+    /// minimum similarity of renames, out of MAX_SCORE
+    pub rename_score: u32,
+}
+
+impl Default for TreeMergeOptions {
+    fn default() -> Self {
+        TreeMergeOptions {
+            ours_label: String::from("HEAD"),
+            theirs_label: String::new(),
+            ancestor_label: String::new(),
+            style: ConflictStyle::default(),
+            favor: Favor::default(),
+            find_renames: true,
+            rename_score: DEFAULT_RENAME_SCORE,
+        }
+    }
+}
+
+/// a path of the merged tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedPath {
+    pub path: String,
+    /// the version written to the working tree, and to the index if there is no conflict.
+    /// a path left with conflicts may have none.
+    pub file: Option<DiffFile>,
+    /// the versions of the merge base, ours and theirs (index stages 1 to 3) of a path
+    /// left with conflicts
+    pub conflict: Option<[Option<DiffFile>; 3]>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TreeMergeResult {
+    /// the merged paths, sorted
+    pub paths: Vec<MergedPath>,
+    /// what happened to the paths that needed merging, e.g. Auto-merging f or
+    /// CONFLICT (content): Merge conflict in f
+    pub messages: Vec<String>,
+}
+
+impl TreeMergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.paths.iter().all(|path| path.conflict.is_none())
+    }
+
+    pub fn conflicted_paths(&self) -> Vec<&str> {
+        self.paths
+            .iter()
+            .filter(|path| path.conflict.is_some())
+            .map(|path| path.path.as_str())
+            .collect()
+    }
+
+    /// the index recording the merge: merged paths at stage 0, and the versions of
+    /// conflicting paths at stages 1 to 3
+    pub fn index(&self) -> Index {
+        let mut index = Index {
+            version: 2,
+            ..Default::default()
+        };
+        for path in &self.paths {
+            match (&path.conflict, &path.file) {
+                (None, Some(file)) => {
+                    index.add(IndexEntry::new(&path.path, file.mode, file.hash.clone(), 0))
+                }
+                (None, None) => {}
+                (Some(stages), _) => {
+                    for (stage, version) in stages.iter().enumerate() {
+                        if let Some(version) = version {
+                            let hash = version.hash.clone();
+                            let entry =
+                                IndexEntry::new(&path.path, version.mode, hash, stage as u8 + 1);
+                            index.add(entry);
+                        }
+                    }
+                }
+            }
+        }
+
+        index
+    }
+
+    /// writes the tree of the working tree versions, conflict markers included
+    pub fn write_tree(&self) -> Result<Hash> {
+        let mut index = Index::default();
+        for file in self.paths.iter().filter_map(|path| path.file.as_ref()) {
+            index.add(IndexEntry::new(&file.path, file.mode, file.hash.clone(), 0));
+        }
+        index.write_tree()
+    }
+}
+
+/// merges two commits, using the merge of their merge bases as the base when there are several
+pub fn merge_commits(
+    ours: &Hash,
+    theirs: &Hash,
+    options: &TreeMergeOptions,
+) -> Result<TreeMergeResult> {
+    let mut graph = CommitGraph::new();
+    let (base, ancestor_label) = merge_base_tree(&mut graph, ours, theirs, options, 0)?;
+    let options = TreeMergeOptions {
+        ancestor_label,
+        ..options.clone()
+    };
+
+    merge_trees_at(
+        base.as_ref(),
+        &commit_tree(ours)?,
+        &commit_tree(theirs)?,
+        &options,
+        0,
+    )
+}
+
+/// merges the changes made to the base tree by our tree and their tree
+pub fn merge_trees(
+    base: Option<&Hash>,
+    ours: &Hash,
+    theirs: &Hash,
+    options: &TreeMergeOptions,
+) -> Result<TreeMergeResult> {
+    merge_trees_at(base, ours, theirs, options, 0)
+}
+
+fn commit_tree(commit: &Hash) -> Result<Hash> {
+    peel(commit.clone(), Some(ObjectKind::Tree))
+}
+
+/// returns the tree to use as the base of a merge, and its label. several merge bases are
+/// merged together, oldest first, into a virtual one.
+fn merge_base_tree(
+    graph: &mut CommitGraph,
+    ours: &Hash,
+    theirs: &Hash,
+    options: &TreeMergeOptions,
+    depth: usize,
+) -> Result<(Option<Hash>, String)> {
+    let mut bases = graph.merge_bases(ours, std::slice::from_ref(theirs))?;
+    bases.reverse();
+
+    let (first, rest) = match bases.split_first() {
+        Some(split) => split,
+        None => return Ok((None, String::from("empty tree"))),
+    };
+    if rest.is_empty() {
+        return Ok((Some(commit_tree(first)?), abbreviate(first)?));
+    }
+
+    let mut tree = commit_tree(first)?;
+    let mut previous = first;
+    for base in rest {
+        let (inner_base, _) = merge_base_tree(graph, previous, base, options, depth + 1)?;
+        let inner_options = TreeMergeOptions {
+            ours_label: String::from("Temporary merge branch 1"),
+            theirs_label: String::from("Temporary merge branch 2"),
+            ancestor_label: String::from("merged common ancestors"),
+            favor: Favor::None,
+            ..options.clone()
+        };
+        let merged = merge_trees_at(
+            inner_base.as_ref(),
+            &tree,
+            &commit_tree(base)?,
+            &inner_options,
+            depth + 1,
+        )?;
+        tree = merged.write_tree()?;
+        previous = base;
+    }
+
+    Ok((Some(tree), String::from("merged common ancestors")))
+}
+
+/// the versions of a file in the three trees, a file renamed on a side being matched
+/// with its base version
+struct Unit {
+    base: Option<DiffFile>,
+    ours: Option<DiffFile>,
+    theirs: Option<DiffFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Ours,
+    Theirs,
+}
+
+struct Merger<'a> {
+    options: &'a TreeMergeOptions,
+    /// how deep in the merging of merge bases this merge is. conflicts found while building
+    /// a virtual merge base are recorded in its tree.
+    depth: usize,
+    ours_files: BTreeMap<String, DiffFile>,
+    paths: Vec<MergedPath>,
+    messages: Vec<(String, String)>,
+}
+
+fn merge_trees_at(
+    base: Option<&Hash>,
+    ours: &Hash,
+    theirs: &Hash,
+    options: &TreeMergeOptions,
+    depth: usize,
+) -> Result<TreeMergeResult> {
+    let base_files = list_files(base)?;
+    let ours_files = list_files(Some(ours))?;
+    let theirs_files = list_files(Some(theirs))?;
+    let ours_renames = renames(base, ours, options)?;
+    let theirs_renames = renames(base, theirs, options)?;
+
+    let mut claimed_ours = HashSet::new();
+    let mut claimed_theirs = HashSet::new();
+    let mut units = Vec::new();
+    for (path, file) in &base_files {
+        units.push(Unit {
+            base: Some(file.clone()),
+            ours: side_version(path, &ours_files, &ours_renames, &mut claimed_ours),
+            theirs: side_version(path, &theirs_files, &theirs_renames, &mut claimed_theirs),
+        });
+    }
+    for (path, file) in &ours_files {
+        if claimed_ours.contains(path) {
+            continue;
+        }
+        let mut theirs = None;
+        if !claimed_theirs.contains(path) {
+            theirs = theirs_files.get(path).cloned();
+            claimed_theirs.insert(path.clone());
+        }
+        units.push(Unit {
+            base: None,
+            ours: Some(file.clone()),
+            theirs,
+        });
+    }
+    for (path, file) in &theirs_files {
+        if !claimed_theirs.contains(path) {
+            units.push(Unit {
+                base: None,
+                ours: None,
+                theirs: Some(file.clone()),
+            });
+        }
+    }
+
+    let mut merger = Merger {
+        options,
+        depth,
+        ours_files,
+        paths: Vec::new(),
+        messages: Vec::new(),
+    };
+    for unit in units {
+        merger.merge_unit(unit)?;
+    }
+    merger.finish()
+}
+
+fn list_files(tree: Option<&Hash>) -> Result<BTreeMap<String, DiffFile>> {
+    let options = TreeDiffOptions {
+        recursive: true,
+        ..Default::default()
+    };
+    Ok(list_tree_files(tree, &options)?
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect())
+}
+
+/// the files of the base renamed on a side, mapped to their new paths
+fn renames(
+    base: Option<&Hash>,
+    side: &Hash,
+    options: &TreeMergeOptions,
+) -> Result<HashMap<String, String>> {
+    if !options.find_renames || base.is_none() {
+        return Ok(HashMap::new());
+    }
+
+    let diff_options = TreeDiffOptions {
+        recursive: true,
+        find_renames: true,
+        rename_score: options.rename_score,
+        ..Default::default()
+    };
+    Ok(diff_trees(base, Some(side), &diff_options)?
+        .into_iter()
+        .filter(|change| change.status == ChangeStatus::Renamed)
+        .filter_map(|change| Some((change.old?.path, change.new?.path)))
+        .collect())
+}
+
+/// the version of a base file on a side: the file it was renamed to, or the file at the
+/// same path unless that one is the target of another rename
+fn side_version(
+    path: &str,
+    files: &BTreeMap<String, DiffFile>,
+    renames: &HashMap<String, String>,
+    claimed: &mut HashSet<String>,
+) -> Option<DiffFile> {
+    let path = match renames.get(path) {
+        Some(new_path) => new_path.as_str(),
+        None if renames.values().any(|target| target == path) => return None,
+        None => path,
+    };
+    let file = files.get(path).cloned();
+    if file.is_some() {
+        claimed.insert(path.to_string());
+    }
+    file
+}
+
+/// two versions of a file have the same content and mode
+fn same(a: &DiffFile, b: &DiffFile) -> bool {
+    a.hash == b.hash && a.mode == b.mode
+}
+
+fn at(file: &DiffFile, path: &str) -> DiffFile {
+    DiffFile {
+        path: path.to_string(),
+        ..file.clone()
+    }
+}
+
+impl Merger<'_> {
+    fn merge_unit(&mut self, unit: Unit) -> Result<()> {
+        match (&unit.base, &unit.ours, &unit.theirs) {
+            (_, None, None) => {}
+            (None, Some(file), None) | (None, None, Some(file)) => self.clean(file.clone()),
+            (None, Some(ours), Some(theirs)) => match same(ours, theirs) {
+                true => self.clean(ours.clone()),
+                false => self.merge_content(None, ours, theirs, &ours.path)?,
+            },
+            (Some(base), Some(kept), None) => self.deleted_on_one_side(base, kept, Side::Ours),
+            (Some(base), None, Some(kept)) => self.deleted_on_one_side(base, kept, Side::Theirs),
+            (Some(base), Some(ours), Some(theirs)) => {
+                if ours.path != base.path && theirs.path != base.path && ours.path != theirs.path {
+                    self.renamed_twice(base, ours, theirs);
+                    return Ok(());
+                }
+
+                let path = match ours.path != base.path {
+                    true => ours.path.clone(),
+                    false => theirs.path.clone(),
+                };
+                if same(ours, theirs) || same(base, theirs) {
+                    self.clean(at(ours, &path));
+                } else if same(base, ours) {
+                    self.clean(at(theirs, &path));
+                } else {
+                    self.merge_content(Some(base), ours, theirs, &path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn label(&self, side: Side) -> &str {
+        match side {
+            Side::Ours => &self.options.ours_label,
+            Side::Theirs => &self.options.theirs_label,
+        }
+    }
+
+    fn message(&mut self, path: &str, message: String) {
+        self.messages.push((path.to_string(), message));
+    }
+
+    fn clean(&mut self, file: DiffFile) {
+        self.paths.push(MergedPath {
+            path: file.path.clone(),
+            file: Some(file),
+            conflict: None,
+        });
+    }
+
+    /// records a conflicting path, with the version left in the working tree if any
+    fn conflict(&mut self, path: &str, file: Option<DiffFile>, stages: [Option<&DiffFile>; 3]) {
+        let stages = stages.map(|stage| stage.map(|stage| at(stage, path)));
+        self.paths.push(MergedPath {
+            path: path.to_string(),
+            file,
+            conflict: Some(stages),
+        });
+    }
+
+    /// a file deleted on a side, and modified or renamed on the other
+    fn deleted_on_one_side(&mut self, base: &DiffFile, kept: &DiffFile, side: Side) {
+        if kept.path == base.path && same(base, kept) {
+            return;
+        }
+
+        let other = match side {
+            Side::Ours => Side::Theirs,
+            Side::Theirs => Side::Ours,
+        };
+        let message = match kept.path == base.path {
+            true => format!(
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                kept.path,
+                self.label(other),
+                self.label(side),
+                self.label(side),
+                kept.path
+            ),
+            false => format!(
+                "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}.",
+                base.path,
+                kept.path,
+                self.label(side),
+                self.label(other)
+            ),
+        };
+        self.message(&kept.path, message);
+
+        // a virtual merge base keeps the version of the base
+        let file = match self.depth {
+            0 => kept.clone(),
+            _ => at(base, &kept.path),
+        };
+        match side {
+            Side::Ours => self.conflict(&kept.path, Some(file), [Some(base), Some(kept), None]),
+            Side::Theirs => self.conflict(&kept.path, Some(file), [Some(base), None, Some(kept)]),
+        }
+    }
+
+    /// a file renamed to different paths on both sides. each side's version is kept at its
+    /// path, and the base version is left at the original path in the index only.
+    fn renamed_twice(&mut self, base: &DiffFile, ours: &DiffFile, theirs: &DiffFile) {
+        let message = format!(
+            "CONFLICT (rename/rename): {} renamed to {} in {} and to {} in {}.",
+            base.path, ours.path, self.options.ours_label, theirs.path, self.options.theirs_label
+        );
+        self.message(&base.path, message);
+        self.conflict(&base.path, None, [Some(base), None, None]);
+        self.conflict(&ours.path, Some(ours.clone()), [None, Some(ours), None]);
+        self.conflict(
+            &theirs.path,
+            Some(theirs.clone()),
+            [None, None, Some(theirs)],
+        );
+    }
+
+    /// merges two versions of a file changed on both sides, or added on both sides
+    /// when there is no base
+    fn merge_content(
+        &mut self,
+        base: Option<&DiffFile>,
+        ours: &DiffFile,
+        theirs: &DiffFile,
+        path: &str,
+    ) -> Result<()> {
+        let (mode, mode_conflict) = match base {
+            _ if ours.mode == theirs.mode => (ours.mode, false),
+            Some(base) if base.mode == ours.mode => (theirs.mode, false),
+            Some(base) if base.mode == theirs.mode => (ours.mode, false),
+            _ => (ours.mode, true),
+        };
+        let stages = [base, Some(ours), Some(theirs)];
+
+        if ours.hash == theirs.hash {
+            let file = DiffFile {
+                path: path.to_string(),
+                mode,
+                hash: ours.hash.clone(),
+            };
+            match mode_conflict {
+                true => {
+                    self.message(
+                        path,
+                        format!("CONFLICT (content): Merge conflict in {}", path),
+                    );
+                    self.conflict(path, Some(file), stages);
+                }
+                false => self.clean(file),
+            }
+            return Ok(());
+        }
+
+        // symbolic links can't be merged line by line
+        let links = ours.mode == EntryMode::SymbolicLink || theirs.mode == EntryMode::SymbolicLink;
+        let contents = [base, Some(ours), Some(theirs)].map(|file| match file {
+            Some(file) => read_blob(&file.hash),
+            None => Ok(Vec::new()),
+        });
+        let [base_content, ours_content, theirs_content] = contents;
+        let (base_content, ours_content, theirs_content) =
+            (base_content?, ours_content?, theirs_content?);
+
+        let binary = [&base_content, &ours_content, &theirs_content]
+            .iter()
+            .any(|content| is_binary(content));
+        if binary && !links && self.options.favor == Favor::None {
+            let message = format!(
+                "warning: Cannot merge binary files: {} ({} vs. {})",
+                path, self.options.ours_label, self.options.theirs_label
+            );
+            self.message(path, message);
+        }
+        if !links {
+            self.message(path, format!("Auto-merging {}", path));
+        }
+        if links || binary {
+            match self.options.favor {
+                Favor::Theirs => self.clean(at(theirs, path)),
+                Favor::Ours => self.clean(at(ours, path)),
+                _ => {
+                    self.message(
+                        path,
+                        format!("CONFLICT (content): Merge conflict in {}", path),
+                    );
+                    self.conflict(path, Some(at(ours, path)), stages);
+                }
+            }
+            return Ok(());
+        }
+
+        let same_paths = base.is_none_or(|base| base.path == path) && ours.path == theirs.path;
+        let label = |label: &str, file: Option<&DiffFile>| match (same_paths, file) {
+            (false, Some(file)) => format!("{}:{}", label, file.path),
+            _ => label.to_string(),
+        };
+        let file_options = MergeFileOptions {
+            style: self.options.style,
+            favor: self.options.favor,
+            // markers of inner merges are longer, so that they stand out from the outer ones
+            marker_size: DEFAULT_MARKER_SIZE + 2 * self.depth,
+            ours_label: Some(label(&self.options.ours_label, Some(ours))),
+            base_label: Some(label(&self.options.ancestor_label, base)),
+            theirs_label: Some(label(&self.options.theirs_label, Some(theirs))),
+            ..Default::default()
+        };
+        let merged = merge_file(&base_content, &ours_content, &theirs_content, &file_options);
+
+        let object = Object {
+            data: merged.content,
+            kind: ObjectKind::Blob,
+        };
+        let file = DiffFile {
+            path: path.to_string(),
+            mode,
+            hash: object.write()?,
+        };
+        if merged.conflicts == 0 && !mode_conflict {
+            self.clean(file);
+            return Ok(());
+        }
+
+        let kind = match base {
+            Some(_) => "content",
+            None => "add/add",
+        };
+        self.message(
+            path,
+            format!("CONFLICT ({}): Merge conflict in {}", kind, path),
+        );
+        self.conflict(path, Some(file), stages);
+        Ok(())
+    }
+
+    /// resolves the paths claimed twice and the files in the way of directories,
+    /// and sorts the paths and messages
+    fn finish(mut self) -> Result<TreeMergeResult> {
+        self.paths.sort_by(|a, b| a.path.cmp(&b.path));
+
+        // a path taken by two files, e.g. a file renamed on a side to a path added on the other
+        let mut paths: Vec<MergedPath> = Vec::with_capacity(self.paths.len());
+        for path in std::mem::take(&mut self.paths) {
+            match paths.last_mut() {
+                Some(last)
+                    if last.path == path.path && last.file.is_some() && path.file.is_some() =>
+                {
+                    let name = path.path.clone();
+                    let stages = [None, last.file.as_ref(), path.file.as_ref()]
+                        .map(|stage| stage.map(|stage| at(stage, &name)));
+                    last.conflict = Some(stages);
+                    self.messages.push((
+                        name.clone(),
+                        format!("CONFLICT (add/add): Merge conflict in {}", name),
+                    ));
+                }
+                _ => paths.push(path),
+            }
+        }
+
+        // a file where the other side has a directory is moved aside
+        let dirs: HashSet<&str> = paths
+            .iter()
+            .flat_map(|path| {
+                let path = path.path.as_str();
+                path.match_indices('/').map(move |(i, _)| &path[..i])
+            })
+            .collect();
+        let in_the_way: Vec<usize> = (0..paths.len())
+            .filter(|&i| paths[i].file.is_some() && dirs.contains(paths[i].path.as_str()))
+            .collect();
+        for i in in_the_way {
+            let path = paths[i].path.clone();
+            let current = paths[i]
+                .file
+                .clone()
+                .expect("only paths with a file are moved");
+            let side = match self.ours_files.get(&path) {
+                Some(file) if same(file, &current) => Side::Ours,
+                _ => Side::Theirs,
+            };
+            let label = self.label(side).replace('/', "_");
+            let new_path = format!("{}~{}", path, label);
+            let message = format!(
+                "CONFLICT (file/directory): directory in the way of {} from {}; moving it to {} instead.",
+                path,
+                self.label(side),
+                new_path
+            );
+            self.messages.push((path.clone(), message));
+
+            let file = at(&current, &new_path);
+            // the stages of a conflict move with the file, keeping the merge base at stage 1
+            let stages = match paths[i].conflict.take() {
+                Some(stages) => stages.map(|stage| stage.map(|stage| at(&stage, &new_path))),
+                None => match side {
+                    Side::Ours => [None, Some(file.clone()), None],
+                    Side::Theirs => [None, None, Some(file.clone())],
+                },
+            };
+            paths[i].conflict = Some(stages);
+            paths[i].path = new_path;
+            paths[i].file = Some(file);
+        }
+        paths.sort_by(|a, b| a.path.cmp(&b.path));
+
+        self.messages.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(TreeMergeResult {
+            paths,
+            messages: self
+                .messages
+                .into_iter()
+                .map(|(_, message)| message)
+                .collect(),
+        })
+    }
+}
+
+fn read_blob(hash: &Hash) -> Result<Vec<u8>> {
+    Ok(Object::read_from_hash(hash.to_hex())?.data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        objects::tree::{Entry, EntryMode},
+        test_repo::TestRepo,
+    };
+
+    use super::{merge_trees, TreeMergeOptions};
+
+    #[test]
+    fn test_file_moved_aside_keeps_stages() {
+        let repo = TestRepo::new();
+        let base = repo.tree(&[("a", "base"), ("k", "k")]);
+        let ours = repo.tree(&[("a", "ours"), ("k", "k")]);
+        let theirs = repo.tree_from_entries(vec![
+            Entry {
+                mode: EntryMode::Directory,
+                name: String::from("a"),
+                hash: repo.tree(&[("b", "theirs")]),
+            },
+            Entry {
+                mode: EntryMode::RegularFile,
+                name: String::from("k"),
+                hash: repo.blob("k"),
+            },
+        ]);
+
+        // a is modified on our side, and replaced by a directory on theirs
+        let result =
+            merge_trees(Some(&base), &ours, &theirs, &TreeMergeOptions::default()).unwrap();
+        assert_eq!(result.conflicted_paths(), vec!["a~HEAD"]);
+
+        let moved = result
+            .paths
+            .iter()
+            .find(|path| path.path == "a~HEAD")
+            .unwrap();
+        let stages = moved.conflict.as_ref().unwrap();
+        let stage = |i: usize| {
+            stages[i]
+                .as_ref()
+                .map(|file| (file.path.as_str(), file.hash.clone()))
+        };
+        assert_eq!(stage(0), Some(("a~HEAD", repo.blob("base"))));
+        assert_eq!(stage(1), Some(("a~HEAD", repo.blob("ours"))));
+        assert_eq!(stage(2), None);
+    }
+}
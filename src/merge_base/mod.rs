@@ -0,0 +1,416 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    objects::{commit::decode_commit, hash::Hash, Object, ObjectKind},
+    refs::{self, reflog::read_reflog},
+    rev_parse::resolve_commit,
+    revwalk::QueueItem,
+};
+
+/// the commit is reachable from the first commit
+const PARENT1: u8 = 1 << 0;
+/// the commit is reachable from one of the other commits
+const PARENT2: u8 = 1 << 1;
+/// the commit is an ancestor of a common ancestor, so it can't be a merge base
+const STALE: u8 = 1 << 2;
+/// the commit was added to the result
+const RESULT: u8 = 1 << 3;
+
+pub struct MergeBaseOptions {
+    pub commits: Vec<String>,
+    /// output all merge bases instead of the first one
+    pub all: bool,
+    /// compute the common ancestors of all commits, as needed by an n-way merge
+    pub octopus: bool,
+    /// check whether the first commit is an ancestor of the second one
+    pub is_ancestor: bool,
+    /// find the point at which a branch forked from the ref given as the first argument,
+    /// using the reflog of the ref
+    pub fork_point: bool,
+}
+
+struct CommitInfo {
+    time: u64,
+    parents: Vec<Hash>,
+}
+
+/// computes merge bases over the commit graph.
+///
+/// the parents and committer times of visited commits are cached, so a single graph should be
+/// reused when running many queries against the same history.
+#[derive(Default)]
+pub struct CommitGraph {
+    commits: HashMap<Hash, CommitInfo>,
+}
+
+impl CommitGraph {
+    pub fn new() -> CommitGraph {
+        CommitGraph::default()
+    }
+
+    /// returns the best common ancestors of `one` and a hypothetical merge of `twos`,
+    /// newest first. none of the returned commits is an ancestor of another one.
+    pub fn merge_bases(&mut self, one: &Hash, twos: &[Hash]) -> Result<Vec<Hash>> {
+        if twos.contains(one) {
+            return Ok(vec![one.clone()]);
+        }
+
+        let (bases, _) = self.paint_down_to_common(one, twos)?;
+        if bases.len() <= 1 {
+            return Ok(bases);
+        }
+
+        let mut bases = self.remove_redundant(bases)?;
+        self.sort_by_date(&mut bases)?;
+        Ok(bases)
+    }
+
+    /// returns the common ancestors of all the given commits, newest first
+    pub fn octopus_merge_bases(&mut self, commits: &[Hash]) -> Result<Vec<Hash>> {
+        let (first, rest) = match commits.split_first() {
+            Some(split) => split,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut bases = vec![first.clone()];
+        for commit in rest {
+            let mut next = Vec::new();
+            for base in &bases {
+                for found in self.merge_bases(commit, std::slice::from_ref(base))? {
+                    if !next.contains(&found) {
+                        next.push(found);
+                    }
+                }
+            }
+            bases = next;
+        }
+
+        let mut bases = self.remove_redundant(bases)?;
+        self.sort_by_date(&mut bases)?;
+        Ok(bases)
+    }
+
+    /// returns true if `ancestor` is reachable from `descendant`. a commit is its own ancestor.
+    pub fn is_ancestor(&mut self, ancestor: &Hash, descendant: &Hash) -> Result<bool> {
+        if ancestor == descendant {
+            return Ok(true);
+        }
+
+        let (_, flags) = self.paint_down_to_common(ancestor, std::slice::from_ref(descendant))?;
+        Ok(flags
+            .get(ancestor)
+            .is_some_and(|flags| flags & PARENT2 != 0))
+    }
+
+    /// returns the commit at which `commit` forked from the history of the ref, as recorded
+    /// in the ref's reflog. returns None if no such commit can be determined.
+    pub fn fork_point(&mut self, ref_name: &str, commit: &Hash) -> Result<Option<Hash>> {
+        let mut revs = Vec::new();
+        let entries = read_reflog(ref_name)?;
+        let current = refs::resolve_ref(ref_name)?;
+        for hash in entries.into_iter().map(|entry| entry.new).chain(current) {
+            if revs.contains(&hash) || hash.0.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+
+            // entries may point to objects that were pruned, or that are not commits
+            if self.info(&hash).is_ok() {
+                revs.push(hash);
+            }
+        }
+
+        if revs.is_empty() {
+            return Ok(None);
+        }
+
+        let bases = self.merge_bases(commit, &revs)?;
+        match bases.as_slice() {
+            [base] if revs.contains(base) => Ok(Some(base.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    /// paints the ancestors of `one` with PARENT1 and the ancestors of `twos` with PARENT2,
+    /// newest commits first. commits reached by both are common ancestors, and their own
+    /// ancestors are marked STALE. the walk stops once only stale commits are left.
+    fn paint_down_to_common(
+        &mut self,
+        one: &Hash,
+        twos: &[Hash],
+    ) -> Result<(Vec<Hash>, HashMap<Hash, u8>)> {
+        let mut flags: HashMap<Hash, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut seq = 0;
+
+        for (hash, flag) in
+            std::iter::once((one, PARENT1)).chain(twos.iter().map(|two| (two, PARENT2)))
+        {
+            *flags.entry(hash.clone()).or_default() |= flag;
+            queue.push(QueueItem {
+                time: self.info(hash)?.time,
+                seq,
+                hash: hash.clone(),
+            });
+            seq += 1;
+        }
+
+        let mut result = Vec::new();
+        while queue.iter().any(|item| {
+            flags
+                .get(&item.hash)
+                .is_some_and(|flags| flags & STALE == 0)
+        }) {
+            let item = queue.pop().expect("queue has non-stale commits");
+            let commit_flags = flags
+                .get_mut(&item.hash)
+                .expect("queued commits are flagged");
+            let mut paint = *commit_flags & (PARENT1 | PARENT2 | STALE);
+            if paint == PARENT1 | PARENT2 {
+                if *commit_flags & RESULT == 0 {
+                    *commit_flags |= RESULT;
+                    result.push(item.hash.clone());
+                }
+                paint |= STALE;
+            }
+
+            let parents = self.info(&item.hash)?.parents.clone();
+            for parent in parents {
+                let parent_flags = flags.entry(parent.clone()).or_default();
+                if *parent_flags & paint == paint {
+                    continue;
+                }
+
+                *parent_flags |= paint;
+                queue.push(QueueItem {
+                    time: self.info(&parent)?.time,
+                    seq,
+                    hash: parent,
+                });
+                seq += 1;
+            }
+        }
+
+        // common ancestors found early may turn out to be ancestors of other common ancestors
+        result.retain(|hash| flags[hash] & STALE == 0);
+        Ok((result, flags))
+    }
+
+    /// removes the commits that are ancestors of other commits in the list
+    fn remove_redundant(&mut self, mut commits: Vec<Hash>) -> Result<Vec<Hash>> {
+        let mut unique = Vec::with_capacity(commits.len());
+        for commit in commits.drain(..) {
+            if !unique.contains(&commit) {
+                unique.push(commit);
+            }
+        }
+
+        let mut redundant = vec![false; unique.len()];
+        for i in 0..unique.len() {
+            for j in 0..unique.len() {
+                if i == j || redundant[j] {
+                    continue;
+                }
+
+                if self.is_ancestor(&unique[i], &unique[j])? {
+                    redundant[i] = true;
+                    break;
+                }
+            }
+        }
+
+        Ok(unique
+            .into_iter()
+            .zip(redundant)
+            .filter(|(_, redundant)| !redundant)
+            .map(|(commit, _)| commit)
+            .collect())
+    }
+
+    fn sort_by_date(&mut self, commits: &mut [Hash]) -> Result<()> {
+        let mut times = HashMap::new();
+        for commit in commits.iter() {
+            times.insert(commit.clone(), self.info(commit)?.time);
+        }
+
+        commits.sort_by_key(|commit| Reverse(times[commit]));
+        Ok(())
+    }
+
+    /// the parents of a commit
+    pub fn parents(&mut self, hash: &Hash) -> Result<Vec<Hash>> {
+        Ok(self.info(hash)?.parents.clone())
+    }
+
+    /// the committer time of a commit
+    pub fn time(&mut self, hash: &Hash) -> Result<u64> {
+        Ok(self.info(hash)?.time)
+    }
+
+    fn info(&mut self, hash: &Hash) -> Result<&CommitInfo> {
+        if !self.commits.contains_key(hash) {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            if object.kind != ObjectKind::Commit {
+                bail!("object {:x} is a {}, not a commit", hash, object.kind);
+            }
+
+            let commit = decode_commit(object.data)?;
+            self.commits.insert(
+                hash.clone(),
+                CommitInfo {
+                    time: commit.committer.time,
+                    parents: commit.parents,
+                },
+            );
+        }
+
+        Ok(&self.commits[hash])
+    }
+}
+
+/// prints the merge bases of the given commits. returns false if none was found,
+/// or if the --is-ancestor check failed.
+pub fn merge_base(options: MergeBaseOptions) -> Result<bool> {
+    let mut graph = CommitGraph::new();
+
+    if options.fork_point {
+        let (ref_name, commit) = match options.commits.as_slice() {
+            [ref_name] => (ref_name, "HEAD"),
+            [ref_name, commit] => (ref_name, commit.as_str()),
+            _ => bail!("--fork-point takes a ref and an optional commit"),
+        };
+
+        let full_name = refs::expand_ref(ref_name)?.ok_or(anyhow!("unknown ref: {}", ref_name))?;
+        let commit = resolve_commit(commit)?;
+        return match graph.fork_point(&full_name, &commit)? {
+            Some(base) => {
+                println!("{:x}", base);
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+
+    let commits = options
+        .commits
+        .iter()
+        .map(|commit| resolve_commit(commit))
+        .collect::<Result<Vec<Hash>>>()?;
+
+    if options.is_ancestor {
+        return match commits.as_slice() {
+            [ancestor, descendant] => graph.is_ancestor(ancestor, descendant),
+            _ => bail!("--is-ancestor takes exactly two commits"),
+        };
+    }
+
+    let bases = if options.octopus {
+        graph.octopus_merge_bases(&commits)?
+    } else {
+        match commits.split_first() {
+            Some((one, twos)) if !twos.is_empty() => graph.merge_bases(one, twos)?,
+            _ => bail!("merge-base takes at least two commits"),
+        }
+    };
+
+    let shown = if options.all { bases.len() } else { 1 };
+    for base in bases.iter().take(shown) {
+        println!("{:x}", base);
+    }
+
+    Ok(!bases.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::objects::hash::Hash;
+
+    use super::{CommitGraph, CommitInfo};
+
+    fn hash(c: char) -> Hash {
+        Hash::try_from(c.to_string().repeat(40).as_bytes()).unwrap()
+    }
+
+    /// builds a graph from (commit, time, parents) triples
+    fn graph(commits: &[(char, u64, &str)]) -> CommitGraph {
+        let mut graph = CommitGraph::new();
+        for (commit, time, parents) in commits {
+            graph.commits.insert(
+                hash(*commit),
+                CommitInfo {
+                    time: *time,
+                    parents: parents.chars().map(hash).collect(),
+                },
+            );
+        }
+        graph
+    }
+
+    #[test]
+    fn test_merge_bases() {
+        // a - b - c - e
+        //      \     /
+        //       - d -
+        let mut g = graph(&[
+            ('a', 1, ""),
+            ('b', 2, "a"),
+            ('c', 3, "b"),
+            ('d', 4, "b"),
+            ('e', 5, "cd"),
+        ]);
+
+        assert_eq!(
+            g.merge_bases(&hash('c'), &[hash('d')]).unwrap(),
+            vec![hash('b')]
+        );
+        assert_eq!(
+            g.merge_bases(&hash('e'), &[hash('d')]).unwrap(),
+            vec![hash('d')]
+        );
+        assert!(g.is_ancestor(&hash('a'), &hash('e')).unwrap());
+        assert!(g.is_ancestor(&hash('e'), &hash('e')).unwrap());
+        assert!(!g.is_ancestor(&hash('c'), &hash('d')).unwrap());
+    }
+
+    #[test]
+    fn test_criss_cross_merge_bases() {
+        // b and c are merged into each other in d and e, so both are best common ancestors
+        let mut g = graph(&[
+            ('a', 1, ""),
+            ('b', 2, "a"),
+            ('c', 3, "a"),
+            ('d', 4, "bc"),
+            ('e', 5, "cb"),
+        ]);
+
+        assert_eq!(
+            g.merge_bases(&hash('d'), &[hash('e')]).unwrap(),
+            vec![hash('c'), hash('b')]
+        );
+    }
+
+    #[test]
+    fn test_octopus_merge_bases() {
+        let mut g = graph(&[
+            ('a', 1, ""),
+            ('b', 2, "a"),
+            ('c', 3, "b"),
+            ('d', 4, "b"),
+            ('e', 5, "a"),
+        ]);
+
+        assert_eq!(
+            g.merge_bases(&hash('c'), &[hash('d'), hash('e')]).unwrap(),
+            vec![hash('b')]
+        );
+        assert_eq!(
+            g.octopus_merge_bases(&[hash('c'), hash('d'), hash('e')])
+                .unwrap(),
+            vec![hash('a')]
+        );
+    }
+}
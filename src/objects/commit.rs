@@ -1,16 +1,16 @@
-use super::Object;
+use super::{Object, ObjectKind};
 use crate::objects::Hash;
 use anyhow::{anyhow, Result};
-use std::{fmt::Display, io::BufRead};
+use std::fmt::Display;
 
 #[derive(Debug)]
 pub struct Commit {
-    tree: Hash,
-    author: Author,
-    committer: Author,
-    parents: Vec<Hash>,
-    message: String,
-    additional_data: Option<String>,
+    pub tree: Hash,
+    pub author: Author,
+    pub committer: Author,
+    pub parents: Vec<Hash>,
+    pub message: String,
+    pub additional_data: Option<String>,
 }
 
 impl Display for Commit {
@@ -59,13 +59,14 @@ impl Display for Author {
 impl TryFrom<&str> for Author {
     type Error = anyhow::Error;
     fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
-        let mut words = value.split_whitespace();
-
-        let name = words.next().ok_or(anyhow!("invalid author data"))?;
-        let email = words
-            .next()
-            .ok_or(anyhow!("invalid author data"))?
-            .trim_matches(|c| c == '<' || c == '>');
+        // names may contain spaces, so the email delimiters are used to split the fields
+        let (name, rest) = value
+            .split_once('<')
+            .ok_or(anyhow!("invalid author data"))?;
+        let (email, rest) = rest.split_once('>').ok_or(anyhow!("invalid author data"))?;
+        let name = name.trim_end();
+
+        let mut words = rest.split_whitespace();
         let time = u64::from_str_radix(words.next().ok_or(anyhow!("invalid author data"))?, 10)?;
         let time_zone = words.next().ok_or(anyhow!("invalid author data"))?;
 
@@ -78,6 +79,7 @@ impl TryFrom<&str> for Author {
     }
 }
 
+/// builds a commit object. the author is used as committer when none is given.
 pub fn new_commit(
     tree: Hash,
     parents: Vec<Hash>,
@@ -85,8 +87,23 @@ pub fn new_commit(
     committer: Option<Author>,
     signature: Option<String>,
     message: Option<String>,
-) -> Object {
-    todo!()
+) -> Result<Object> {
+    // multi-line headers continue on lines starting with a space
+    let additional_data =
+        signature.map(|signature| format!("gpgsig {}", signature.replace('\n', "\n ")));
+    let commit = Commit {
+        tree,
+        committer: committer.unwrap_or(author.clone()),
+        author,
+        parents,
+        message: message.unwrap_or_default(),
+        additional_data,
+    };
+
+    Ok(Object {
+        data: encode_commit(commit)?,
+        kind: ObjectKind::Commit,
+    })
 }
 
 pub fn decode_commit(data: Vec<u8>) -> Result<Commit> {
@@ -95,26 +112,39 @@ pub fn decode_commit(data: Vec<u8>) -> Result<Commit> {
     let mut author: Option<Author> = None;
     let mut committer: Option<Author> = None;
     let mut additional_data: Option<String> = None;
-    let mut message = String::new();
-    let lines: Vec<String> = data.lines().collect::<Result<_, _>>()?;
-    for (i, line) in lines.iter().enumerate() {
-        if line.len() == 0 {
-            // next is commit message
-            if i != lines.len() - 2 {
-                return Err(anyhow!("invalid commit data"));
-            }
 
-            message = lines[i + 1].clone();
-            break;
+    let content = String::from_utf8(data)?;
+    let (headers, message) = match content.split_once("\n\n") {
+        Some((headers, message)) => (headers, message),
+        None => (content.strip_suffix('\n').unwrap_or(&content), ""),
+    };
+
+    for line in headers.lines() {
+        if line.starts_with(' ') {
+            // continuation of a multi-line header (e.g. gpgsig)
+            match additional_data.as_mut() {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(line);
+                }
+                None => return Err(anyhow!("invalid commit data")),
+            }
+            continue;
         }
 
-        let (first_word, words) = line.split_once(' ').ok_or(anyhow!("invalid tag data"))?;
+        let (first_word, words) = line.split_once(' ').ok_or(anyhow!("invalid commit data"))?;
         match first_word {
             "tree" => tree = Some(Hash::try_from(words.as_bytes())?),
             "parent" => parents.push(Hash::try_from(words.as_bytes())?),
             "author" => author = Some(Author::try_from(words)?),
             "committer" => committer = Some(Author::try_from(words)?),
-            _ => additional_data = Some(line.to_string()),
+            _ => match additional_data.as_mut() {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(line);
+                }
+                None => additional_data = Some(line.to_string()),
+            },
         }
     }
 
@@ -123,7 +153,7 @@ pub fn decode_commit(data: Vec<u8>) -> Result<Commit> {
         author: author.ok_or(anyhow!("commit missing author information"))?,
         committer: committer.ok_or(anyhow!("commit missing committer information"))?,
         parents,
-        message,
+        message: message.strip_suffix('\n').unwrap_or(message).to_string(),
         additional_data,
     })
 }
@@ -160,8 +190,11 @@ pub fn encode_commit(commit: Commit) -> Result<Vec<u8>> {
     }
 
     content.append(&mut "\n".as_bytes().to_vec());
-    content.append(&mut commit.message.into_bytes());
-    content.append(&mut "\n".as_bytes().to_vec());
+    // an empty message has no line to end
+    if !commit.message.is_empty() {
+        content.append(&mut commit.message.into_bytes());
+        content.append(&mut "\n".as_bytes().to_vec());
+    }
 
     Ok(content)
 }
@@ -213,6 +246,25 @@ mod test {
         assert_eq!(commit.message, String::from("commit message"));
     }
 
+    #[test]
+    fn test_decode_commit_multi_line() {
+        let hash = (0..40).map(|_| 'a').collect::<String>();
+        let data = format!("tree {}\nauthor A U Thor <a@x.com> 1 +0000\ncommitter C O Mitter <c@x.com> 2 -0100\ngpgsig -----BEGIN-----\n line\n -----END-----\n\nsubject\n\nbody line\n", hash);
+        let commit = decode_commit(data.into_bytes()).unwrap();
+
+        assert_eq!(commit.author.name, "A U Thor");
+        assert_eq!(commit.author.email, "a@x.com");
+        assert_eq!(commit.committer.name, "C O Mitter");
+        assert_eq!(commit.committer.time_zone, "-0100");
+        assert_eq!(
+            commit.additional_data,
+            Some(String::from(
+                "gpgsig -----BEGIN-----\n line\n -----END-----"
+            ))
+        );
+        assert_eq!(commit.message, "subject\n\nbody line");
+    }
+
     #[test]
     fn test_encode_commit() {
         let hash1 = (0..40).map(|_| 'a').collect::<String>();
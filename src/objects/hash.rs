@@ -5,7 +5,7 @@ use hex;
 use sha1::{Digest, Sha1};
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Clone, Hash)]
-pub(crate) struct Hash(pub Vec<u8>);
+pub struct Hash(pub Vec<u8>);
 
 /// hash data using sha1
 pub fn hash(data: &[u8]) -> Hash {
@@ -0,0 +1,395 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{BufReader, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    pack_protocol::pack_reader::{read_entry, EntryKind, PackEntry},
+    progress::Progress,
+};
+
+use super::{hash::Hash, pack::apply_pack_delta, Object};
+
+/// the memory each thread may keep inflated delta bases in when core.deltaBaseCacheLimit
+/// isn't set
+pub const DEFAULT_DELTA_BASE_CACHE_LIMIT: u64 = 96 << 20;
+
+/// what is known about an entry of a pack being stored
+pub struct StoredEntry {
+    pub offset: u64,
+    pub crc32: u32,
+    /// the hash of the object, set once the delta it may be is resolved
+    pub hash: OnceLock<Hash>,
+    pub base: Option<DeltaBase>,
+}
+
+pub enum DeltaBase {
+    Offset(u64),
+    Ref(Hash),
+}
+
+/// an object deltas apply to: an entry of the pack, or an object of the repository
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Base {
+    Entry(usize),
+    External(Hash),
+}
+
+/// finds the objects the deltas of a stored pack make. the deltas form trees, rooted at
+/// the objects stored whole, and the trees are resolved in parallel: each thread applies
+/// the deltas against an object to it, then the deltas against those, and so on. deltas
+/// against objects outside of the pack come last, and the objects of the repository they
+/// apply to are returned. at most cache_limit bytes of inflated bases are kept by each
+/// thread, and evicted ones are inflated again when needed.
+pub fn resolve_deltas(
+    path: &Path,
+    entries: &[StoredEntry],
+    cache_limit: u64,
+    progress: bool,
+) -> Result<Vec<Hash>> {
+    let total = entries.iter().filter(|entry| entry.base.is_some()).count() as u64;
+    let mut by_offset: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut by_hash: HashMap<Hash, Vec<usize>> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        match &entry.base {
+            Some(DeltaBase::Offset(offset)) => by_offset.entry(*offset).or_default().push(i),
+            Some(DeltaBase::Ref(hash)) => by_hash.entry(hash.clone()).or_default().push(i),
+            None => {}
+        }
+    }
+
+    let resolver = DeltaResolver {
+        path,
+        entries,
+        by_offset,
+        by_hash: Mutex::new(by_hash),
+        cache_limit,
+        resolved: AtomicU64::new(0),
+        progress: (progress && total > 0)
+            .then(|| Mutex::new(Progress::new("Resolving deltas", total))),
+    };
+
+    let mut roots = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(hash) = entry.hash.get() {
+            let deltas = resolver.take_deltas(i, hash);
+            if !deltas.is_empty() {
+                roots.push((Base::Entry(i), deltas));
+            }
+        }
+    }
+    resolver.run(roots)?;
+
+    // what is left are deltas against objects outside of the pack, as in thin packs.
+    // those the repository has are used, in the order their first delta comes in.
+    let mut external: Vec<(Hash, Vec<usize>)> = resolver.by_hash.lock().unwrap().drain().collect();
+    external.sort_by_key(|(_, deltas)| deltas.iter().min().copied());
+    let mut roots = Vec::new();
+    let mut bases = Vec::new();
+    for (hash, deltas) in external {
+        if Object::exists(&hash)? {
+            bases.push(hash.clone());
+            roots.push((Base::External(hash), deltas));
+        }
+    }
+    resolver.run(roots)?;
+
+    let resolved = resolver.resolved.load(Ordering::Relaxed);
+    if let Some(progress) = resolver.progress {
+        let message = match bases.len() {
+            0 => "done".to_string(),
+            1 => "completed with 1 local object".to_string(),
+            n => format!("completed with {} local objects", n),
+        };
+        progress.into_inner().unwrap().finish(resolved, &message);
+    }
+    if resolved < total {
+        return Err(anyhow!("pack has {} unresolved deltas", total - resolved));
+    }
+    Ok(bases)
+}
+
+/// the deltas of a stored pack, by the base they apply to, shared by the threads
+/// resolving them
+struct DeltaResolver<'a> {
+    path: &'a Path,
+    entries: &'a [StoredEntry],
+    by_offset: HashMap<u64, Vec<usize>>,
+    /// deltas against objects named by their hash, taken once their base is known
+    by_hash: Mutex<HashMap<Hash, Vec<usize>>>,
+    cache_limit: u64,
+    resolved: AtomicU64,
+    progress: Option<Mutex<Progress>>,
+}
+
+/// what a thread keeps while resolving trees of deltas
+struct Worker {
+    pack: BufReader<fs::File>,
+    cache: BaseCache,
+    /// the base each resolved delta was applied to, to inflate it again
+    parents: HashMap<usize, Base>,
+    /// the deltas not applied yet against each cached base
+    remaining: HashMap<Base, usize>,
+}
+
+impl DeltaResolver<'_> {
+    /// the deltas against an entry whose object is now known
+    fn take_deltas(&self, i: usize, hash: &Hash) -> Vec<usize> {
+        let mut deltas = self
+            .by_offset
+            .get(&self.entries[i].offset)
+            .cloned()
+            .unwrap_or_default();
+        deltas.extend(
+            self.by_hash
+                .lock()
+                .unwrap()
+                .remove(hash)
+                .unwrap_or_default(),
+        );
+        deltas
+    }
+
+    /// resolves trees of deltas, each given as its root and the deltas against it, with
+    /// as many threads as there are processors
+    fn run(&self, roots: Vec<(Base, Vec<usize>)>) -> Result<()> {
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(roots.len());
+        let roots = Mutex::new(roots);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| scope.spawn(|| self.work(&roots)))
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("resolving deltas panicked"))
+        })
+    }
+
+    /// resolves trees until none are left
+    fn work(&self, roots: &Mutex<Vec<(Base, Vec<usize>)>>) -> Result<()> {
+        let mut worker = Worker {
+            pack: BufReader::new(fs::File::open(self.path)?),
+            cache: BaseCache::new(self.cache_limit),
+            parents: HashMap::new(),
+            remaining: HashMap::new(),
+        };
+        loop {
+            let root = roots.lock().unwrap().pop();
+            match root {
+                Some((base, deltas)) => self.resolve_tree(&mut worker, base, deltas)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// applies deltas to their base, then the deltas against the objects they make. the
+    /// tree is walked depth first, so that the base needed next is usually cached.
+    fn resolve_tree(&self, worker: &mut Worker, root: Base, deltas: Vec<usize>) -> Result<()> {
+        worker.remaining.insert(root.clone(), deltas.len());
+        let mut pending: Vec<(usize, Base)> =
+            deltas.into_iter().map(|i| (i, root.clone())).collect();
+        while let Some((i, base)) = pending.pop() {
+            let base_object = self.object(worker, &base)?;
+            let entry = read(&mut worker.pack, self.entries[i].offset)?;
+            let object = apply_pack_delta(&base_object, &entry.data)?;
+            drop(base_object);
+            worker.release(&base);
+
+            let hash = object.hash()?;
+            let deltas = self.take_deltas(i, &hash);
+            self.entries[i]
+                .hash
+                .set(hash)
+                .expect("each delta is applied once");
+            worker.parents.insert(i, base);
+            if !deltas.is_empty() {
+                let base = Base::Entry(i);
+                worker.remaining.insert(base.clone(), deltas.len());
+                worker.cache.insert(base.clone(), Arc::new(object));
+                pending.extend(deltas.into_iter().map(|delta| (delta, base.clone())));
+            }
+
+            let resolved = self.resolved.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(progress) = &self.progress {
+                progress.lock().unwrap().update(resolved);
+            }
+        }
+        Ok(())
+    }
+
+    /// the object of a base, from the cache, or inflated again from the nearest cached
+    /// object it derives from
+    fn object(&self, worker: &mut Worker, base: &Base) -> Result<Arc<Object>> {
+        let mut chain = Vec::new();
+        let mut current = base.clone();
+        let mut object = loop {
+            if let Some(object) = worker.cache.get(&current) {
+                break object;
+            }
+            let object = match &current {
+                Base::External(hash) => Object::read_from_hash(hash.to_hex())?,
+                Base::Entry(i) => match worker.parents.get(i) {
+                    Some(parent) => {
+                        chain.push(*i);
+                        current = parent.clone();
+                        continue;
+                    }
+                    None => match read(&mut worker.pack, self.entries[*i].offset)? {
+                        PackEntry {
+                            kind: EntryKind::Object(kind),
+                            data,
+                            ..
+                        } => Object { data, kind },
+                        _ => bail!(
+                            "pack entry at {} is not a whole object",
+                            self.entries[*i].offset
+                        ),
+                    },
+                },
+            };
+            let object = Arc::new(object);
+            worker.keep(current, object.clone());
+            break object;
+        };
+
+        for i in chain.into_iter().rev() {
+            let entry = read(&mut worker.pack, self.entries[i].offset)?;
+            object = Arc::new(apply_pack_delta(&object, &entry.data)?);
+            worker.keep(Base::Entry(i), object.clone());
+        }
+        Ok(object)
+    }
+}
+
+impl Worker {
+    /// caches an object inflated again, if deltas against it are still to be applied
+    fn keep(&mut self, base: Base, object: Arc<Object>) {
+        if self.remaining.contains_key(&base) {
+            self.cache.insert(base, object);
+        }
+    }
+
+    /// counts a delta applied against a base, which leaves the cache with the last one
+    fn release(&mut self, base: &Base) {
+        if let Some(remaining) = self.remaining.get_mut(base) {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.remaining.remove(base);
+                self.cache.remove(base);
+            }
+        }
+    }
+}
+
+fn read(pack: &mut BufReader<fs::File>, offset: u64) -> Result<PackEntry> {
+    pack.seek(SeekFrom::Start(offset))?;
+    read_entry(pack, offset)
+}
+
+/// inflated delta bases, up to a number of bytes. the least recently used ones are
+/// evicted first.
+struct BaseCache {
+    limit: u64,
+    size: u64,
+    tick: u64,
+    objects: HashMap<Base, (Arc<Object>, u64)>,
+    /// the cached bases, by when they were last used
+    by_use: BTreeMap<u64, Base>,
+}
+
+impl BaseCache {
+    fn new(limit: u64) -> BaseCache {
+        BaseCache {
+            limit,
+            size: 0,
+            tick: 0,
+            objects: HashMap::new(),
+            by_use: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, base: &Base) -> Option<Arc<Object>> {
+        let (object, used) = self.objects.get_mut(base)?;
+        self.by_use.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.by_use.insert(self.tick, base.clone());
+        Some(object.clone())
+    }
+
+    /// caches an object, evicting others until it fits. an object larger than the whole
+    /// cache isn't kept.
+    fn insert(&mut self, base: Base, object: Arc<Object>) {
+        let size = object.data.len() as u64;
+        if size > self.limit {
+            return;
+        }
+        self.remove(&base);
+        while self.size + size > self.limit {
+            match self.by_use.pop_first() {
+                Some((_, evicted)) => {
+                    let (object, _) = self.objects.remove(&evicted).expect("cached");
+                    self.size -= object.data.len() as u64;
+                }
+                None => break,
+            }
+        }
+        self.tick += 1;
+        self.size += size;
+        self.by_use.insert(self.tick, base.clone());
+        self.objects.insert(base, (object, self.tick));
+    }
+
+    fn remove(&mut self, base: &Base) {
+        if let Some((object, used)) = self.objects.remove(base) {
+            self.by_use.remove(&used);
+            self.size -= object.data.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::objects::{Object, ObjectKind};
+
+    use super::{Base, BaseCache};
+
+    fn blob(size: usize) -> Arc<Object> {
+        Arc::new(Object {
+            data: vec![0; size],
+            kind: ObjectKind::Blob,
+        })
+    }
+
+    #[test]
+    fn test_base_cache() {
+        let mut cache = BaseCache::new(10);
+        cache.insert(Base::Entry(0), blob(4));
+        cache.insert(Base::Entry(1), blob(4));
+        assert!(cache.get(&Base::Entry(0)).is_some());
+
+        // the least recently used base makes room
+        cache.insert(Base::Entry(2), blob(4));
+        assert!(cache.get(&Base::Entry(1)).is_none());
+        assert!(cache.get(&Base::Entry(0)).is_some());
+        assert_eq!(cache.size, 8);
+
+        cache.insert(Base::Entry(3), blob(11));
+        assert!(cache.get(&Base::Entry(3)).is_none());
+        cache.remove(&Base::Entry(0));
+        assert_eq!(cache.size, 4);
+    }
+}
@@ -2,6 +2,8 @@ pub mod blob;
 pub mod commit;
 mod compress;
 pub mod hash;
+mod index_pack;
+pub mod pack;
 pub mod tag;
 pub mod tree;
 
@@ -12,7 +14,7 @@ use self::{
     hash::{hash, Hash},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 
 const OBJECTS_DIR: &str = ".git/objects";
 
@@ -45,7 +47,7 @@ pub struct Object {
     pub kind: ObjectKind,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ObjectKind {
     Blob,
     Commit,
@@ -84,10 +86,82 @@ impl Object {
         let hash = Hash::try_from(hash_hex.as_bytes())?;
         let (dir, file_name) = hash.get_object_path();
         let path = PathBuf::from(OBJECTS_DIR).join(dir).join(file_name);
-        let file = fs::File::open(path)?;
+        if !path.is_file() {
+            if let Some(object) = pack::read_packed(&hash)? {
+                return Ok(object);
+            }
+        }
+        let file =
+            fs::File::open(path).with_context(|| format!("object {} not found", hash_hex))?;
         Self::read(file)
     }
 
+    /// checks whether an object is stored, loose or packed
+    pub fn exists(hash: &Hash) -> Result<bool> {
+        let (dir, file_name) = hash.get_object_path();
+        if PathBuf::from(OBJECTS_DIR)
+            .join(dir)
+            .join(file_name)
+            .is_file()
+        {
+            return Ok(true);
+        }
+        pack::has_packed(hash)
+    }
+
+    /// returns the hashes of all stored objects whose hex representation starts with the given prefix
+    pub fn find_by_prefix(prefix: &str) -> Result<Vec<Hash>> {
+        if prefix.len() < 2 {
+            return Err(anyhow!("object prefix must be at least 2 characters"));
+        }
+
+        let (dir, rest) = prefix.split_at(2);
+        let dir_path = PathBuf::from(OBJECTS_DIR).join(dir);
+
+        let mut hashes = Vec::new();
+        if dir_path.is_dir() {
+            for entry in fs::read_dir(dir_path)? {
+                let file_name = entry?.file_name();
+                let file_name = file_name.to_string_lossy();
+                if file_name.len() == 38 && file_name.starts_with(rest) {
+                    hashes.push(Hash::try_from(format!("{}{}", dir, file_name).as_bytes())?);
+                }
+            }
+        }
+
+        for hash in pack::find_packed_by_prefix(prefix)? {
+            if !hashes.contains(&hash) {
+                hashes.push(hash);
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// lists the loose objects, with the paths of their files
+    pub fn list_loose() -> Result<Vec<(Hash, PathBuf)>> {
+        let mut objects = Vec::new();
+        for entry in fs::read_dir(OBJECTS_DIR)? {
+            let entry = entry?;
+            let dir = entry.file_name().to_string_lossy().to_string();
+            if dir.len() != 2 || !entry.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(entry.path())? {
+                let entry = entry?;
+                let name = format!("{}{}", dir, entry.file_name().to_string_lossy());
+                if name.len() != 40 {
+                    continue;
+                }
+                if let Ok(hash) = Hash::try_from(name.as_bytes()) {
+                    objects.push((hash, entry.path()));
+                }
+            }
+        }
+        objects.sort();
+        Ok(objects)
+    }
+
     pub fn read<R: Read>(data: R) -> Result<Object> {
         // decompress content
         let data = decompress(data)?;
@@ -106,7 +180,7 @@ impl Object {
             .ok_or(anyhow!("invalid object header"))?;
 
         let kind = ObjectKind::try_from(kind_str)?;
-        let length = usize::from_str_radix(length_str, 10)?;
+        let length = length_str.parse::<usize>()?;
 
         if length != data.len() {
             return Err(anyhow!("object size does not match"));
@@ -121,18 +195,21 @@ impl Object {
     pub fn write(&self) -> Result<Hash> {
         let object_data = self.encode();
 
-        // compress content
-        let compressed_content = compress::compress(&object_data)?;
-
-        // hash
-        let hash = hash(&compressed_content);
+        // objects are named after the hash of their uncompressed content
+        let hash = hash(&object_data);
 
         let (dir_name, file_name) = hash.get_object_path();
+        let dir = PathBuf::from(OBJECTS_DIR).join(dir_name);
+        let path = dir.join(file_name);
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        // compress content
+        let compressed_content = compress::compress(&object_data)?;
 
-        fs::write(
-            PathBuf::from(OBJECTS_DIR).join(dir_name).join(file_name),
-            compressed_content,
-        )?;
+        fs::create_dir_all(dir)?;
+        fs::write(path, compressed_content)?;
 
         // return hash
         Ok(hash)
@@ -147,12 +224,7 @@ impl Object {
     }
 
     pub fn hash(&self) -> Result<Hash> {
-        let blob_content = self.encode();
-
-        // compress content
-        let compressed_content = compress::compress(&blob_content)?;
-
-        Ok(hash(&compressed_content))
+        Ok(hash(&self.encode()))
     }
 }
 
@@ -162,6 +234,20 @@ mod test {
 
     use super::Object;
 
+    #[test]
+    fn test_hash_blob() {
+        let object = Object {
+            data: "hello world".as_bytes().to_vec(),
+            kind: ObjectKind::Blob,
+        };
+
+        // same as `git hash-object` on a file containing "hello world"
+        assert_eq!(
+            object.hash().unwrap().to_hex(),
+            "95d09f2b10159347eece71399a7e2e907ea3df4f"
+        );
+    }
+
     #[test]
     fn test_read_blob() {
         let blob_data = String::from("hello world");
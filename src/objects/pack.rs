@@ -0,0 +1,462 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use flate2::Crc;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    config::read_config,
+    pack_protocol::{
+        pack_file::PackFileError,
+        pack_index::{PackIndex, PackIndexEntry},
+        pack_object::{apply_delta, entry_header, PackObject},
+        pack_reader::{read_entry, EntryKind, PackReader},
+    },
+};
+
+use super::{
+    compress::compress,
+    hash::Hash,
+    index_pack::{resolve_deltas, DeltaBase, StoredEntry, DEFAULT_DELTA_BASE_CACHE_LIMIT},
+    Object, OBJECTS_DIR,
+};
+
+/// maximum number of deltas followed to rebuild a packed object
+const MAX_DELTA_DEPTH: usize = 4096;
+
+/// indexes of the packs read so far, by path. packs are named after their content, so
+/// an index never changes once written.
+static INDEXES: Mutex<Option<HashMap<PathBuf, Arc<PackIndex>>>> = Mutex::new(None);
+
+/// names the directory the objects of a push are kept in until they are accepted, for the
+/// hooks that run meanwhile. its packs are read along with those of the repository.
+pub const QUARANTINE_ENV: &str = "GIT_QUARANTINE_PATH";
+
+fn pack_dir() -> PathBuf {
+    PathBuf::from(OBJECTS_DIR).join("pack")
+}
+
+/// the packs of the repository, as pairs of pack paths and their indexes
+pub fn packs() -> Result<Vec<(PathBuf, Arc<PackIndex>)>> {
+    let mut dirs = vec![pack_dir()];
+    if let Some(quarantine) = env::var_os(QUARANTINE_ENV) {
+        dirs.push(PathBuf::from(quarantine).join("pack"));
+    }
+    packs_in(&dirs)
+}
+
+fn packs_in(dirs: &[PathBuf]) -> Result<Vec<(PathBuf, Arc<PackIndex>)>> {
+    let mut idx_paths = Vec::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        let mut dir_paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "idx")
+                && path.with_extension("pack").is_file()
+            {
+                dir_paths.push(path);
+            }
+        }
+        dir_paths.sort();
+        idx_paths.extend(dir_paths);
+    }
+
+    let mut packs = Vec::new();
+    for idx_path in idx_paths {
+        let index = load_index(&idx_path)?;
+        packs.push((idx_path.with_extension("pack"), index));
+    }
+    Ok(packs)
+}
+
+fn load_index(idx_path: &Path) -> Result<Arc<PackIndex>> {
+    let key = fs::canonicalize(idx_path)?;
+    let mut indexes = INDEXES
+        .lock()
+        .map_err(|_| anyhow!("pack index cache is poisoned"))?;
+    let indexes = indexes.get_or_insert_with(HashMap::new);
+    if let Some(index) = indexes.get(&key) {
+        return Ok(index.clone());
+    }
+
+    let index = PackIndex::decode(&fs::read(idx_path)?)
+        .map_err(|err| anyhow!("{}: {}", idx_path.display(), err))?;
+    let index = Arc::new(index);
+    indexes.insert(key, index.clone());
+    Ok(index)
+}
+
+/// checks whether a pack has an object
+pub fn has_packed(hash: &Hash) -> Result<bool> {
+    Ok(packs()?.iter().any(|(_, index)| index.find(hash).is_some()))
+}
+
+/// reads an object from the packs, or returns None if none of them has it
+pub fn read_packed(hash: &Hash) -> Result<Option<Object>> {
+    read_from(&packs()?, hash)
+}
+
+fn read_from(packs: &[(PathBuf, Arc<PackIndex>)], hash: &Hash) -> Result<Option<Object>> {
+    for (pack_path, index) in packs {
+        if let Some(offset) = index.find(hash) {
+            let mut pack = BufReader::new(fs::File::open(pack_path)?);
+            return read_at(&mut pack, offset, 0, &|hash| index.find(hash)).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// the packs of a directory kept apart from the repository, as the quarantine of a push
+pub struct PackDir {
+    packs: Vec<(PathBuf, Arc<PackIndex>)>,
+}
+
+impl PackDir {
+    /// finds the packs of an objects directory. there are none if it doesn't exist.
+    pub fn open(objects_dir: &Path) -> Result<PackDir> {
+        Ok(PackDir {
+            packs: packs_in(&[objects_dir.join("pack")])?,
+        })
+    }
+
+    /// reads an object from the packs of the directory, or returns None if none of them has it
+    pub fn read(&self, hash: &Hash) -> Result<Option<Object>> {
+        read_from(&self.packs, hash)
+    }
+}
+
+/// reads how a packed object is stored when it is a delta: the hash of its base, and the
+/// delta, which can be copied to another pack as it is
+pub fn read_packed_delta(hash: &Hash) -> Result<Option<(Hash, Vec<u8>)>> {
+    for (pack_path, index) in packs()? {
+        if let Some(offset) = index.find(hash) {
+            let mut pack = BufReader::new(fs::File::open(&pack_path)?);
+            pack.seek(SeekFrom::Start(offset))?;
+            let entry = read_entry(&mut pack, offset)?;
+            let base = match entry.kind {
+                EntryKind::Object(_) => return Ok(None),
+                EntryKind::OfsDelta(base_offset) => match index.find_offset(base_offset) {
+                    Some(base) => base.clone(),
+                    None => return Ok(None),
+                },
+                EntryKind::RefDelta(base) => base,
+            };
+            return Ok(Some((base, entry.data)));
+        }
+    }
+    Ok(None)
+}
+
+/// returns the hashes of the packed objects whose hex representation starts with the
+/// given prefix
+pub fn find_packed_by_prefix(prefix: &str) -> Result<Vec<Hash>> {
+    let mut hashes = Vec::new();
+    for (_, index) in packs()? {
+        hashes.extend(
+            index
+                .entries()
+                .iter()
+                .filter(|entry| entry.hash.to_hex().starts_with(prefix))
+                .map(|entry| entry.hash.clone()),
+        );
+    }
+    Ok(hashes)
+}
+
+/// reads the object stored at an offset of a pack, resolving the deltas it is made of.
+/// find gives the offsets of the objects of the same pack, for deltas against objects
+/// named by their hash.
+fn read_at<R: Read + Seek>(
+    pack: &mut R,
+    offset: u64,
+    depth: usize,
+    find: &dyn Fn(&Hash) -> Option<u64>,
+) -> Result<Object> {
+    if depth > MAX_DELTA_DEPTH {
+        return Err(
+            PackFileError::ErrOffsetDeltaBaseObject("delta chain is too deep".into()).into(),
+        );
+    }
+
+    pack.seek(SeekFrom::Start(offset))?;
+    let entry = read_entry(&mut BufReader::new(&mut *pack), offset)?;
+    let base = match entry.kind {
+        EntryKind::Object(kind) => {
+            return Ok(Object {
+                data: entry.data,
+                kind,
+            })
+        }
+        EntryKind::OfsDelta(base_offset) => read_at(pack, base_offset, depth + 1, find)?,
+        EntryKind::RefDelta(base) => match find(&base) {
+            Some(base_offset) => read_at(pack, base_offset, depth + 1, find)?,
+            None => Object::read_from_hash(base.to_hex())?,
+        },
+    };
+    apply_pack_delta(&base, &entry.data)
+}
+
+/// checks a pack against its index: the checksums of both files, and the CRC32 of each
+/// entry. every object is read, and passed to visit along with the hash and the offset
+/// the index gives it. the problems found with the pack itself are returned.
+pub fn verify_pack(
+    pack_path: &Path,
+    visit: &mut dyn FnMut(&Hash, u64, Result<Object>),
+) -> Result<Vec<String>> {
+    let idx_path = pack_path.with_extension("idx");
+    let index = match PackIndex::decode(&fs::read(&idx_path)?) {
+        Ok(index) => index,
+        Err(err) => return Ok(vec![format!("{}: {}", idx_path.display(), err)]),
+    };
+
+    let mut problems = Vec::new();
+    let mut pack = BufReader::new(fs::File::open(pack_path)?);
+    let size = pack.get_ref().metadata()?.len();
+    if size < 12 + 20 {
+        return Ok(vec![format!("{} is too small", pack_path.display())]);
+    }
+    let mut sha = Sha1::new();
+    io::copy(&mut (&mut pack).take(size - 20), &mut sha)?;
+    let mut checksum = vec![0; 20];
+    pack.read_exact(&mut checksum)?;
+    if sha.finalize()[..] != checksum[..] {
+        problems.push(format!("{} pack checksum mismatch", pack_path.display()));
+    }
+    if checksum != index.pack_checksum.0 {
+        problems.push(format!(
+            "packfile {} does not match index",
+            pack_path.display()
+        ));
+    }
+
+    // an entry ends where the next one starts, or at the trailer for the last one
+    let mut entries: Vec<&PackIndexEntry> = index.entries().iter().collect();
+    entries.sort_by_key(|entry| entry.offset);
+    for (i, entry) in entries.iter().enumerate() {
+        let end = entries
+            .get(i + 1)
+            .map_or(size - 20, |next| next.offset.min(size - 20));
+        let mut data = vec![0; end.saturating_sub(entry.offset) as usize];
+        pack.seek(SeekFrom::Start(entry.offset))?;
+        pack.read_exact(&mut data)?;
+        let mut crc = Crc::new();
+        crc.update(&data);
+        if crc.sum() != entry.crc32 {
+            problems.push(format!(
+                "index CRC mismatch for object {:x} from {} at offset {}",
+                entry.hash,
+                pack_path.display(),
+                entry.offset
+            ));
+        }
+    }
+
+    for entry in entries {
+        let object = read_at(&mut pack, entry.offset, 0, &|hash| index.find(hash));
+        visit(&entry.hash, entry.offset, object);
+    }
+    Ok(problems)
+}
+
+/// applies the delta of a pack entry to its base object
+pub(super) fn apply_pack_delta(base: &Object, delta: &[u8]) -> Result<Object> {
+    let (base_size, result_size, instructions) =
+        PackObject::parse_delta(Bytes::copy_from_slice(delta))?;
+    if base_size != base.data.len() {
+        return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
+    }
+    let data = apply_delta(&base.data, &instructions)?;
+    if result_size != data.len() {
+        return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
+    }
+    Ok(Object {
+        data,
+        kind: base.kind,
+    })
+}
+
+/// stores a pack read from a stream in the objects directory, along with its index. the
+/// pack goes to a temporary file as it is read, and is named after its trailing checksum,
+/// which is returned, once all of its objects are known. the progress of resolving its
+/// deltas is shown on stderr when asked for.
+pub fn store_pack<R: Read>(reader: R, progress: bool) -> Result<Hash> {
+    store_pack_in(reader, &pack_dir(), progress)
+}
+
+/// stores a pack like [`store_pack`], in another directory than the one of the repository
+pub fn store_pack_in<R: Read>(reader: R, dir: &Path, progress: bool) -> Result<Hash> {
+    fs::create_dir_all(dir)?;
+    let tmp_path = dir.join(format!("tmp_pack_{}", process::id()));
+    let stored = write_pack(reader, dir, &tmp_path, progress);
+    if stored.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    stored
+}
+
+fn write_pack<R: Read>(reader: R, dir: &Path, tmp_path: &Path, progress: bool) -> Result<Hash> {
+    let file = BufWriter::new(fs::File::create(tmp_path)?);
+    let mut pack = PackReader::with_copy(reader, file)?;
+    let mut entries = Vec::with_capacity(pack.object_count() as usize);
+    while let Some((entry, crc32)) = pack.next_entry()? {
+        let (hash, base) = match entry.kind {
+            EntryKind::Object(kind) => (
+                Some(
+                    Object {
+                        data: entry.data,
+                        kind,
+                    }
+                    .hash()?,
+                ),
+                None,
+            ),
+            EntryKind::OfsDelta(offset) => (None, Some(DeltaBase::Offset(offset))),
+            EntryKind::RefDelta(hash) => (None, Some(DeltaBase::Ref(hash))),
+        };
+        entries.push(StoredEntry {
+            offset: entry.offset,
+            crc32,
+            hash: hash.map(OnceLock::from).unwrap_or_default(),
+            base,
+        });
+    }
+    let mut checksum = pack
+        .checksum()
+        .expect("the pack was read to its end")
+        .clone();
+    drop(pack);
+
+    let cache_limit = read_config()?
+        .get_size("core.deltaBaseCacheLimit")?
+        .unwrap_or(DEFAULT_DELTA_BASE_CACHE_LIMIT);
+    let bases = resolve_deltas(tmp_path, &entries, cache_limit, progress)?;
+    if !bases.is_empty() {
+        checksum = fix_thin_pack(tmp_path, &mut entries, &bases)?;
+    }
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| PackIndexEntry {
+            hash: entry.hash.into_inner().expect("all deltas are resolved"),
+            crc32: entry.crc32,
+            offset: entry.offset,
+        })
+        .collect();
+    let index = PackIndex::new(entries, checksum.clone());
+
+    // the index is written last, so that the pack is only seen once complete
+    let name = format!("pack-{}", checksum.to_hex());
+    fs::rename(tmp_path, dir.join(format!("{}.pack", name)))?;
+    fs::write(dir.join(format!("{}.idx", name)), index.encode())?;
+
+    Ok(checksum)
+}
+
+/// moves the packs stored in another directory into the repository, indexes last
+pub fn migrate_packs(dir: &Path) -> Result<()> {
+    let dst = pack_dir();
+    fs::create_dir_all(&dst)?;
+    for ext in ["pack", "idx"] {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == ext) {
+                let name = path.file_name().expect("the path is a file");
+                fs::rename(&path, dst.join(name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// completes a thin pack, whose deltas apply to objects it doesn't have, by appending
+/// those objects from the repository. the object count of the header and the trailing
+/// checksum are rewritten, and the new checksum is returned.
+fn fix_thin_pack(path: &Path, entries: &mut Vec<StoredEntry>, bases: &[Hash]) -> Result<Hash> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let mut offset = file.seek(SeekFrom::End(-20))?;
+    file.set_len(offset)?;
+
+    let mut writer = BufWriter::new(&mut file);
+    for hash in bases {
+        let object = Object::read_from_hash(hash.to_hex())?;
+        let mut entry = entry_header(object.kind.into(), object.data.len() as u64);
+        entry.extend(compress(&object.data)?);
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        writer.write_all(&entry)?;
+
+        entries.push(StoredEntry {
+            offset,
+            crc32: crc.sum(),
+            hash: OnceLock::from(hash.clone()),
+            base: None,
+        });
+        offset += entry.len() as u64;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    let count = u32::try_from(entries.len())
+        .map_err(|_| anyhow!("pack has too many objects: {}", entries.len()))?;
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&count.to_be_bytes())?;
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut sha = Sha1::new();
+    io::copy(&mut file, &mut sha)?;
+    let checksum = Hash(sha.finalize().to_vec());
+    file.write_all(&checksum.0)?;
+    Ok(checksum)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::objects::{compress::compress, ObjectKind};
+
+    use super::read_at;
+
+    #[test]
+    fn test_read_ofs_delta() {
+        // a blob "hello world" at offset 12, and a delta at offset 12 + n building
+        // "hello there!" from it
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        pack.push(0b0011_1011);
+        pack.extend(compress(&b"hello world".to_vec()).unwrap());
+        let delta_offset = pack.len() as u64;
+        let relative = delta_offset - 12;
+        assert!(relative < 128);
+        pack.push(0b0110_1011);
+        pack.push(relative as u8);
+        pack.extend(
+            compress(&vec![
+                11,
+                12,
+                0b1001_0000,
+                6,
+                6,
+                b't',
+                b'h',
+                b'e',
+                b'r',
+                b'e',
+                b'!',
+            ])
+            .unwrap(),
+        );
+
+        let object = read_at(&mut Cursor::new(pack), delta_offset, 0, &|_| None).unwrap();
+
+        assert_eq!(object.kind, ObjectKind::Blob);
+        assert_eq!(object.data, b"hello there!");
+    }
+}
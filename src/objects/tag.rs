@@ -1,16 +1,16 @@
-use std::{fmt::Display, io::BufRead};
+use std::fmt::Display;
 
 use super::{commit::Author, hash::Hash, Object, ObjectKind};
 use anyhow::{anyhow, Result};
 
 #[derive(Debug)]
 pub struct Tag {
-    object: Hash,
-    object_type: ObjectKind,
-    tag_name: String,
-    tagger: Author,
-    commit_message: String,
-    additional_data: Option<String>,
+    pub object: Hash,
+    pub object_type: ObjectKind,
+    pub tag_name: String,
+    pub tagger: Author,
+    pub commit_message: String,
+    pub additional_data: Option<String>,
 }
 
 impl Display for Tag {
@@ -23,6 +23,7 @@ impl Display for Tag {
     }
 }
 
+/// builds a tag object pointing to an object of any kind
 pub fn new_tag(
     object: Hash,
     object_type: ObjectKind,
@@ -31,7 +32,19 @@ pub fn new_tag(
     commit_message: Option<String>,
     additional_data: Option<String>,
 ) -> Object {
-    todo!()
+    let tag = Tag {
+        object,
+        object_type,
+        tag_name,
+        tagger,
+        commit_message: commit_message.unwrap_or_default(),
+        additional_data,
+    };
+
+    Object {
+        data: encode_tag(tag),
+        kind: ObjectKind::Tag,
+    }
 }
 
 /*
@@ -53,20 +66,14 @@ pub fn decode_tag(data: Vec<u8>) -> Result<Tag> {
     let mut tag_name: Option<String> = None;
     let mut tagger: Option<Author> = None;
     let mut additional_data: Option<String> = None;
-    let mut commit_message: String = String::new();
-
-    let lines: Vec<String> = data.lines().collect::<Result<_, _>>()?;
-    for (i, line) in lines.iter().enumerate() {
-        if line.len() == 0 {
-            // next is commit message
-            if i != lines.len() - 2 {
-                return Err(anyhow!("invalid tag data"));
-            }
-
-            commit_message = lines[i + 1].clone();
-            break;
-        }
 
+    let content = String::from_utf8(data)?;
+    let (headers, message) = match content.split_once("\n\n") {
+        Some((headers, message)) => (headers, message),
+        None => (content.strip_suffix('\n').unwrap_or(&content), ""),
+    };
+
+    for line in headers.lines() {
         let (first_word, words) = line.split_once(' ').ok_or(anyhow!("invalid tag data"))?;
         match first_word {
             "object" => object = Some(Hash::try_from(words.as_bytes())?),
@@ -76,6 +83,7 @@ pub fn decode_tag(data: Vec<u8>) -> Result<Tag> {
             _ => additional_data = Some(line.to_string()),
         }
     }
+    let commit_message = message.strip_suffix('\n').unwrap_or(message).to_string();
 
     Ok(Tag {
         object: object.ok_or(anyhow!("tag missing object information"))?,
@@ -109,7 +117,10 @@ pub fn encode_tag(tag: Tag) -> Vec<u8> {
     }
 
     content.append(&mut "\n".as_bytes().to_vec());
-    content.append(&mut format!("{}\n", tag.commit_message).into_bytes());
+    // an empty message has no line to end
+    if !tag.commit_message.is_empty() {
+        content.append(&mut format!("{}\n", tag.commit_message).into_bytes());
+    }
 
     content
 }
@@ -163,6 +174,21 @@ mod test {
         assert_eq!(tag.tag_name, tag_name);
     }
 
+    #[test]
+    fn test_decode_tag_multiline_message() {
+        let hash = (0..40).map(|_| 'b').collect::<String>();
+        let data = format!(
+            "object {}\ntype tree\ntag t\ntagger n <m@m.com> 1 +0000\n\nsubject\n\nbody\nmore\n",
+            hash
+        );
+
+        let tag = decode_tag(data.clone().into_bytes()).unwrap();
+
+        assert_eq!(tag.commit_message, "subject\n\nbody\nmore");
+        assert_eq!(tag.object_type, ObjectKind::Tree);
+        assert_eq!(encode_tag(tag), data.into_bytes());
+    }
+
     #[test]
     fn test_encode_tag() {
         let hash_hex = (0..40).map(|_| 'a').collect::<String>();
@@ -1,7 +1,7 @@
 use super::{hash::Hash, Object, ObjectError};
 use anyhow::{anyhow, bail, Ok, Result};
 use std::{
-    fmt::Display,
+    fmt::{Display, Octal},
     fs::{self, DirEntry},
     os::unix::fs::PermissionsExt,
     str::FromStr,
@@ -9,7 +9,7 @@ use std::{
 
 #[derive(Debug)]
 pub struct Tree {
-    entries: Vec<Entry>,
+    pub entries: Vec<Entry>,
 }
 
 impl Display for Tree {
@@ -23,13 +23,15 @@ impl Display for Tree {
     }
 }
 
-pub fn new_tree(entries: Vec<Entry>) -> Tree {
-    todo!()
+/// builds a tree from its entries, sorted the way git expects them
+pub fn new_tree(mut entries: Vec<Entry>) -> Tree {
+    entries.sort_by_key(Entry::sort_key);
+    Tree { entries }
 }
 
 pub fn decode_tree(mut data: Vec<u8>) -> Result<Tree> {
     let mut entries = Vec::<Entry>::new();
-    while data.len() > 0 {
+    while !data.is_empty() {
         let null_byte_index = data
             .iter()
             .position(|c| *c == b'\0')
@@ -56,18 +58,44 @@ pub fn decode_tree(mut data: Vec<u8>) -> Result<Tree> {
 pub fn encode_tree(tree: Tree) -> Vec<u8> {
     let mut data = Vec::new();
     for entry in tree.entries {
-        data.append(&mut format!("{} {}\0", entry.mode, entry.name).into_bytes());
+        data.append(&mut format!("{:o} {}\0", entry.mode, entry.name).into_bytes());
         data.append(&mut entry.hash.into())
     }
 
     data
 }
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+/// looks up the entry at the given slash separated path, starting from the tree with the given hash
+pub fn lookup_path(tree: &Hash, path: &str) -> Result<Option<Entry>> {
+    let mut current = tree.clone();
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+    while let Some(component) = components.next() {
+        let object = Object::read_from_hash(current.to_hex())?;
+        let tree = decode_tree(object.data)?;
+        let entry = match tree.entries.into_iter().find(|e| e.name == component) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+
+        if entry.mode != EntryMode::Directory {
+            return Ok(None);
+        }
+
+        current = entry.hash;
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Clone)]
 pub struct Entry {
-    mode: EntryMode,
-    name: String,
-    hash: Hash,
+    pub mode: EntryMode,
+    pub name: String,
+    pub hash: Hash,
 }
 
 impl Display for Entry {
@@ -77,16 +105,25 @@ impl Display for Entry {
 }
 
 impl Entry {
+    /// trees are sorted by name, directory names comparing as if they ended with a slash
+    pub fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.as_bytes().to_vec();
+        if self.mode == EntryMode::Directory {
+            key.push(b'/');
+        }
+        key
+    }
+
     pub fn from_dir_entry(dir_entry: DirEntry) -> Result<Entry> {
         let mut mode = EntryMode::RegularFile;
         if dir_entry.file_type()?.is_dir() {
             mode = EntryMode::Directory;
         } else if dir_entry.file_type()?.is_symlink() {
             mode = EntryMode::SymbolicLink;
-        } else if dir_entry.file_type()?.is_file() {
-            if dir_entry.metadata()?.permissions().mode() & 0111 != 0 {
-                mode = EntryMode::ExecutableFile;
-            }
+        } else if dir_entry.file_type()?.is_file()
+            && dir_entry.metadata()?.permissions().mode() & 0o111 != 0
+        {
+            mode = EntryMode::ExecutableFile;
         }
 
         let os_name = dir_entry.file_name();
@@ -96,25 +133,27 @@ impl Entry {
 
         let name = os_name
             .into_string()
-            .map_err(|name| ObjectError::ErrInvalidFileName(name))?;
+            .map_err(ObjectError::ErrInvalidFileName)?;
 
         Ok(Entry { hash, mode, name })
     }
 }
 
-impl Into<Vec<u8>> for Entry {
-    fn into(self) -> Vec<u8> {
-        let mut v = format!("{} {}\0", self.mode, self.name).as_bytes().to_vec();
+impl From<Entry> for Vec<u8> {
+    fn from(entry: Entry) -> Vec<u8> {
+        let mut v = format!("{:o} {}\0", entry.mode, entry.name)
+            .as_bytes()
+            .to_vec();
 
-        let mut hash: Vec<u8> = self.hash.into();
+        let mut hash: Vec<u8> = entry.hash.into();
         v.append(&mut hash);
 
         v
     }
 }
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
-enum EntryMode {
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Clone, Copy)]
+pub enum EntryMode {
     RegularFile = 0o100644,
     ExecutableFile = 0o100755,
     SymbolicLink = 0o120000,
@@ -132,10 +171,24 @@ impl Display for EntryMode {
     }
 }
 
+/// formats the numeric mode, as stored in tree objects
+impl Octal for EntryMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Octal::fmt(&(*self as u32), f)
+    }
+}
+
 impl TryFrom<&str> for EntryMode {
     type Error = anyhow::Error;
     fn try_from(value: &str) -> std::prelude::v1::Result<Self, Self::Error> {
-        let mode = usize::from_str_radix(value, 8)?;
+        EntryMode::try_from(u32::from_str_radix(value, 8)?)
+    }
+}
+
+impl TryFrom<u32> for EntryMode {
+    type Error = anyhow::Error;
+    fn try_from(value: u32) -> std::prelude::v1::Result<Self, Self::Error> {
+        let mode = value as usize;
         let entry_mode = match mode {
             0o40000 => EntryMode::Directory,
             0o120000 => EntryMode::SymbolicLink,
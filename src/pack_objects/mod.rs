@@ -0,0 +1,75 @@
+use std::{
+    fs,
+    io::{stdin, stdout, BufRead, BufWriter},
+    process,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    objects::{hash::Hash, Object},
+    pack_protocol::pack_writer::{PackWriter, PackWriterOptions},
+};
+
+pub struct PackObjectsOptions {
+    /// the pack and its index are written as <base_name>-<checksum>.pack and .idx, or the
+    /// pack alone to stdout when there is none
+    pub base_name: Option<String>,
+    pub window: usize,
+    pub depth: usize,
+    pub ofs_delta: bool,
+    pub reuse_deltas: bool,
+}
+
+/// packs the objects named on stdin, one per line. a name may be followed by the path the
+/// object was found at, which helps finding deltas.
+pub fn pack_objects(options: PackObjectsOptions) -> Result<()> {
+    let mut writer = PackWriter::new(PackWriterOptions {
+        window: options.window,
+        depth: options.depth,
+        ofs_delta: options.ofs_delta,
+        reuse_deltas: options.reuse_deltas,
+    });
+    for line in stdin().lock().lines() {
+        let line = line?;
+        let (name, path) = match line.split_once(' ') {
+            Some((name, path)) => (name, Some(path)),
+            None => (line.as_str(), None),
+        };
+        let hash = Hash::try_from(name.as_bytes())
+            .map_err(|_| anyhow!("expected object ID, got garbage:\n {}", line))?;
+        writer.add(Object::read_from_hash(hash.to_hex())?, path)?;
+    }
+
+    match options.base_name {
+        Some(base_name) => {
+            let checksum = write_pack_files(writer, &base_name)?;
+            println!("{}", checksum.to_hex());
+        }
+        None => {
+            writer.write(BufWriter::new(stdout().lock()))?;
+        }
+    }
+    Ok(())
+}
+
+/// writes a pack and its index as <base_name>-<checksum>.pack and .idx, and returns the
+/// checksum
+pub fn write_pack_files(writer: PackWriter, base_name: &str) -> Result<Hash> {
+    // the pack is named after its checksum, which is only known once it is written
+    let tmp_path = format!("{}-tmp-{}.pack", base_name, process::id());
+    let written = fs::File::create(&tmp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| writer.write(BufWriter::new(file)));
+    let index = match written {
+        Ok(index) => index,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+    let name = format!("{}-{}", base_name, index.pack_checksum.to_hex());
+    fs::rename(&tmp_path, format!("{}.pack", name))?;
+    fs::write(format!("{}.idx", name), index.encode())?;
+    Ok(index.pack_checksum)
+}
@@ -0,0 +1,465 @@
+use bytes::Bytes;
+
+use super::pack_object::DeltaInstruction;
+
+/*
+    deltas are found as git's diff-delta.c finds them. the base is cut in blocks of
+    RABIN_WINDOW bytes, and the Rabin fingerprint of each block is indexed. a fingerprint
+    of the last RABIN_WINDOW bytes of the target is rolled along it, and where it is found
+    in the index, the bytes from there on are compared with the base to find the longest
+    match, which is copied. the bytes without a match are inserted.
+*/
+
+/// the bytes a fingerprint covers, and the size of the blocks of the base
+const RABIN_WINDOW: usize = 16;
+
+/// shifting a fingerprint by this leaves the byte that overflows when a byte is added
+const RABIN_SHIFT: u32 = 23;
+
+/// the polynomial fingerprints are reduced by, as in git
+const RABIN_POLYNOMIAL: u32 = 0xab59b4d1;
+
+/// folds the byte shifted out of a fingerprint back into it
+const T: [u32; 256] = fingerprint_table(31, true);
+
+/// the part of a fingerprint the byte leaving the window accounts for
+const U: [u32; 256] = fingerprint_table(8 * (RABIN_WINDOW as u32 - 1), false);
+
+/// the most blocks kept with the same bucket of the index, so that repetitive bases
+/// don't make finding matches quadratic
+const HASH_LIMIT: usize = 64;
+
+/// a match this long is good enough to stop looking for a longer one
+const GOOD_MATCH: usize = 4096;
+
+/// matches shorter than this are inserted, since a copy would be as long
+const MIN_MATCH: usize = 4;
+
+/// the most bytes a copy instruction can copy
+const MAX_COPY_SIZE: usize = 0x10000;
+
+/// the most bytes an insert instruction can carry
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+/// the fingerprints of each byte followed by a number of zero bits, modulo the polynomial.
+/// with top_bit, the bit a byte sets past the fingerprint is set too, to be cancelled.
+const fn fingerprint_table(shift: u32, top_bit: bool) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut value = 0;
+        let mut bit = 0;
+        while bit < 8 {
+            if (byte >> bit) & 1 == 1 {
+                value ^= shifted(shift + bit);
+            }
+            bit += 1;
+        }
+        if top_bit {
+            value ^= ((byte & 1) as u32) << 31;
+        }
+        table[byte] = value;
+        byte += 1;
+    }
+    table
+}
+
+/// the fingerprint of a 1 followed by a number of zero bits
+const fn shifted(bits: u32) -> u32 {
+    let mut value: u32 = 1;
+    let mut i = 0;
+    while i < bits {
+        value <<= 1;
+        if value & (1 << 31) != 0 {
+            value ^= RABIN_POLYNOMIAL;
+        }
+        i += 1;
+    }
+    value
+}
+
+/// adds a byte to a fingerprint
+fn roll(fingerprint: u32, byte: u8) -> u32 {
+    ((fingerprint << 8) | byte as u32) ^ T[(fingerprint >> RABIN_SHIFT) as usize]
+}
+
+/// the fingerprints of the blocks of a base, to find deltas of targets against it. the
+/// base isn't kept, so that indexes can be kept along with the objects they index.
+pub struct DeltaIndex {
+    base_size: usize,
+    mask: u32,
+    /// where the blocks of each bucket start in blocks
+    buckets: Vec<usize>,
+    /// the fingerprint of each block, and the position of its last byte
+    blocks: Vec<(u32, usize)>,
+}
+
+impl DeltaIndex {
+    pub fn new(base: &[u8]) -> DeltaIndex {
+        // copies can't reach past 4 GiB
+        let indexed = base.len().min(u32::MAX as usize);
+        let count = indexed.saturating_sub(1) / RABIN_WINDOW;
+        let mut size = 16;
+        while size < count / 4 {
+            size <<= 1;
+        }
+        let mask = (size - 1) as u32;
+
+        // blocks are fingerprinted from the end, so that of identical blocks in a row,
+        // the first one is kept
+        let mut buckets: Vec<Vec<(u32, usize)>> = vec![Vec::new(); size];
+        let mut previous = None;
+        for block in (0..count).rev() {
+            let start = block * RABIN_WINDOW;
+            let fingerprint = base[start + 1..=start + RABIN_WINDOW]
+                .iter()
+                .fold(0, |fingerprint, byte| roll(fingerprint, *byte));
+            let bucket = &mut buckets[(fingerprint & mask) as usize];
+            let position = start + RABIN_WINDOW;
+            match previous == Some(fingerprint) {
+                true => bucket.last_mut().expect("the previous block is there").1 = position,
+                false => bucket.push((fingerprint, position)),
+            }
+            previous = Some(fingerprint);
+        }
+
+        let mut index = DeltaIndex {
+            base_size: base.len(),
+            mask,
+            buckets: Vec::with_capacity(size + 1),
+            blocks: Vec::new(),
+        };
+        for mut bucket in buckets {
+            bucket.reverse();
+            index.buckets.push(index.blocks.len());
+            match bucket.len() > HASH_LIMIT {
+                // the blocks kept are spread over the base
+                true => index
+                    .blocks
+                    .extend((0..HASH_LIMIT).map(|i| bucket[i * bucket.len() / HASH_LIMIT])),
+                false => index.blocks.extend(bucket),
+            }
+        }
+        index.buckets.push(index.blocks.len());
+        index
+    }
+
+    /// finds instructions building target from base, the buffer the index was made of.
+    /// returns None if the encoded delta would be larger than max_size.
+    pub fn create_delta(
+        &self,
+        base: &[u8],
+        target: &[u8],
+        max_size: Option<usize>,
+    ) -> Option<Vec<DeltaInstruction>> {
+        assert_eq!(base.len(), self.base_size, "the base is the one indexed");
+        let mut delta = DeltaBuilder {
+            instructions: Vec::new(),
+            inserted: Vec::new(),
+            size: size_length(base.len()) + size_length(target.len()),
+        };
+
+        // the first bytes can't be matched until the fingerprint covers a whole block
+        let mut i = RABIN_WINDOW.min(target.len());
+        delta.inserted.extend_from_slice(&target[..i]);
+        let mut fingerprint = target[..i]
+            .iter()
+            .fold(0, |fingerprint, byte| roll(fingerprint, *byte));
+
+        let mut match_offset = 0;
+        let mut match_size = 0;
+        while i < target.len() {
+            if match_size < GOOD_MATCH {
+                fingerprint ^= U[target[i - RABIN_WINDOW] as usize];
+                fingerprint = roll(fingerprint, target[i]);
+                let bucket = (fingerprint & self.mask) as usize;
+                let blocks = &self.blocks[self.buckets[bucket]..self.buckets[bucket + 1]];
+                for &(block, position) in blocks {
+                    if block != fingerprint {
+                        continue;
+                    }
+                    let most = (base.len() - position).min(target.len() - i);
+                    if most <= match_size {
+                        break;
+                    }
+                    let size = base[position..position + most]
+                        .iter()
+                        .zip(&target[i..i + most])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if size > match_size {
+                        match_size = size;
+                        match_offset = position;
+                        if match_size >= GOOD_MATCH {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if match_size < MIN_MATCH {
+                delta.inserted.push(target[i]);
+                i += 1;
+                match_size = 0;
+            } else {
+                // the match may start earlier, in what would otherwise be inserted
+                while match_offset > 0 && delta.inserted.last() == Some(&base[match_offset - 1]) {
+                    delta.inserted.pop();
+                    match_offset -= 1;
+                    match_size += 1;
+                    i -= 1;
+                }
+
+                let size = match_size.min(MAX_COPY_SIZE);
+                delta.copy(match_offset, size);
+                i += size;
+                match_offset += size;
+                match_size -= size;
+                if match_offset > u32::MAX as usize {
+                    match_size = 0;
+                }
+                if match_size < GOOD_MATCH {
+                    fingerprint = target[i - RABIN_WINDOW..i]
+                        .iter()
+                        .fold(0, |fingerprint, byte| roll(fingerprint, *byte));
+                }
+            }
+
+            if max_size.is_some_and(|max_size| delta.pending_size() > max_size) {
+                return None;
+            }
+        }
+
+        delta.flush_insert();
+        Some(delta.instructions)
+    }
+}
+
+/// finds instructions building target from base
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<DeltaInstruction> {
+    DeltaIndex::new(base)
+        .create_delta(base, target, None)
+        .expect("deltas of any size are kept")
+}
+
+/// collects instructions, and the size of their encoding
+struct DeltaBuilder {
+    instructions: Vec<DeltaInstruction>,
+    /// the bytes of the insert instruction being built
+    inserted: Vec<u8>,
+    size: usize,
+}
+
+impl DeltaBuilder {
+    fn copy(&mut self, offset: usize, size: usize) {
+        self.flush_insert();
+        self.size += copy_length(offset as u64, size as u64);
+        self.instructions.push(DeltaInstruction::Copy {
+            offset: offset as u64,
+            size: size as u64,
+        });
+    }
+
+    fn flush_insert(&mut self) {
+        if !self.inserted.is_empty() {
+            self.size += insert_length(self.inserted.len());
+            let data = Bytes::from(std::mem::take(&mut self.inserted));
+            self.instructions.push(DeltaInstruction::Insert { data });
+        }
+    }
+
+    /// the size of the encoding, with the insert being built
+    fn pending_size(&self) -> usize {
+        self.size + insert_length(self.inserted.len())
+    }
+}
+
+/// encodes delta instructions as packs store them, after the sizes of the base and of the
+/// result. copies and inserts too large for one instruction are split.
+pub fn encode_delta(
+    base_size: usize,
+    result_size: usize,
+    instructions: &[DeltaInstruction],
+) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, base_size as u64);
+    write_size(&mut delta, result_size as u64);
+    for instruction in instructions {
+        match instruction {
+            DeltaInstruction::Copy { offset, size } => {
+                let mut offset = *offset;
+                let mut remaining = *size;
+                while remaining > 0 {
+                    let size = remaining.min(MAX_COPY_SIZE as u64);
+                    write_copy(&mut delta, offset, size);
+                    offset += size;
+                    remaining -= size;
+                }
+            }
+            DeltaInstruction::Insert { data } => {
+                for chunk in data.chunks(MAX_INSERT_SIZE) {
+                    delta.push(chunk.len() as u8);
+                    delta.extend_from_slice(chunk);
+                }
+            }
+        }
+    }
+    delta
+}
+
+/// writes a size: 7 bits per byte, least significant group first
+fn write_size(delta: &mut Vec<u8>, mut size: u64) {
+    loop {
+        let b = (size & 0b0111_1111) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(b);
+            return;
+        }
+        delta.push(b | (1 << 7));
+    }
+}
+
+/// writes a copy instruction: a byte saying which bytes of the offset and of the size
+/// follow, then the non-zero ones. a size of 0x10000 is written as 0.
+fn write_copy(delta: &mut Vec<u8>, offset: u64, size: u64) {
+    let mut instruction = vec![1 << 7];
+    for i in 0..4 {
+        let b = (offset >> (8 * i)) as u8;
+        if b != 0 {
+            instruction[0] |= 1 << i;
+            instruction.push(b);
+        }
+    }
+    for i in 0..3 {
+        let b = ((size % MAX_COPY_SIZE as u64) >> (8 * i)) as u8;
+        if b != 0 {
+            instruction[0] |= 1 << (i + 4);
+            instruction.push(b);
+        }
+    }
+    delta.extend(instruction);
+}
+
+fn size_length(size: usize) -> usize {
+    let mut delta = Vec::new();
+    write_size(&mut delta, size as u64);
+    delta.len()
+}
+
+fn copy_length(offset: u64, size: u64) -> usize {
+    let mut delta = Vec::new();
+    write_copy(&mut delta, offset, size);
+    delta.len()
+}
+
+fn insert_length(size: usize) -> usize {
+    size + size.div_ceil(MAX_INSERT_SIZE)
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::pack_protocol::pack_object::{apply_delta, DeltaInstruction, PackObject};
+
+    use super::{create_delta, encode_delta, DeltaIndex, T, U};
+
+    /// a xorshift generator, so that the tests see the same data on every run
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, size: usize, alphabet: u8) -> Vec<u8> {
+            (0..size)
+                .map(|_| b'a' + self.below(alphabet as usize) as u8)
+                .collect()
+        }
+    }
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<DeltaInstruction> {
+        let instructions = create_delta(base, target);
+        let delta = encode_delta(base.len(), target.len(), &instructions);
+        let (base_size, result_size, decoded) =
+            PackObject::parse_delta(Bytes::from(delta)).unwrap();
+        assert_eq!((base_size, result_size), (base.len(), target.len()));
+        assert_eq!(apply_delta(base, &decoded).unwrap(), target);
+        instructions
+    }
+
+    #[test]
+    fn test_fingerprint_tables() {
+        // the first entries of the tables of git's diff-delta.c
+        assert_eq!(T[..3], [0x00000000, 0xab59b4d1, 0x56b369a2]);
+        assert_eq!(U[..4], [0x00000000, 0x7eb5200d, 0x5633f4cb, 0x2886d4c6]);
+    }
+
+    #[test]
+    fn test_create_delta() {
+        let base = b"the quick brown fox jumps over the lazy dog, then rests a while".repeat(4);
+        let mut target = base.clone();
+        target.splice(100..110, b"0123".iter().copied());
+        target.extend_from_slice(b"and a tail");
+        let instructions = round_trip(&base, &target);
+        let copied: u64 = instructions
+            .iter()
+            .map(|instruction| match instruction {
+                DeltaInstruction::Copy { size, .. } => *size,
+                DeltaInstruction::Insert { .. } => 0,
+            })
+            .sum();
+        assert!(copied >= base.len() as u64 - 20);
+
+        // empty and tiny buffers on either side
+        round_trip(b"", b"");
+        round_trip(b"", b"target");
+        round_trip(b"base", b"");
+        round_trip(b"short", b"shorter");
+
+        // copies longer than one instruction can carry are split
+        let base = Random(7).bytes(200_000, 26);
+        round_trip(&base, &base);
+
+        // a delta larger than the limit is given up
+        let unrelated = Random(8).bytes(1000, 26);
+        assert!(DeltaIndex::new(&base)
+            .create_delta(&base, &unrelated, Some(100))
+            .is_none());
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut random = Random(42);
+        for _ in 0..200 {
+            // few distinct bytes make repetitive data, and spurious matches
+            let alphabet = [2, 4, 26][random.below(3)];
+            let size = random.below(3000);
+            let base = random.bytes(size, alphabet);
+            let mut target = Vec::new();
+            for _ in 0..random.below(20) {
+                match random.below(3) {
+                    0 if !base.is_empty() => {
+                        let start = random.below(base.len());
+                        let end = start + random.below(base.len() - start + 1);
+                        target.extend_from_slice(&base[start..end]);
+                    }
+                    _ => {
+                        let size = random.below(100);
+                        target.extend(random.bytes(size, alphabet))
+                    }
+                }
+            }
+            round_trip(&base, &target);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::io::{self, Read};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::objects::{hash::Hash, pack::store_pack, Object};
+
+use super::{
+    negotiator::Negotiator,
+    pkt_line::{write_flush, write_line, Capability},
+    protocol_v2::{command_request, read_section},
+    sideband::{NoProgress, ProgressSink, SidebandReader},
+    transport::{Advertisement, Transport},
+    upload_pack_request::upload_pack_request,
+};
+
+/// haves sent in the first round. each round sends twice as many, up to MAX_FLUSH.
+const INITIAL_FLUSH: usize = 16;
+const MAX_FLUSH: usize = 1024;
+
+/// haves the remote may ignore after acknowledging one before negotiation gives up
+const MAX_IN_VAIN: usize = 256;
+
+/// what the remote said about the haves of a round
+#[derive(Debug, PartialEq, Eq)]
+enum Ack {
+    /// the remote has the commit
+    Common(Hash),
+    /// the remote has enough commits to build a small pack
+    Ready(Hash),
+    /// the final answer to "done": the last common commit
+    Final(Hash),
+    Nak,
+}
+
+/// negotiates the objects to fetch with the remote and stores the pack it sends. the
+/// remote only reports its progress when there is a sink for it. returns the name of the
+/// stored pack, or None if all wanted objects were already there and nothing was fetched.
+pub fn fetch_pack(
+    transport: &mut Transport,
+    advertisement: &Advertisement,
+    wants: &[Hash],
+    local_tips: &[Hash],
+    include_tags: bool,
+    progress: Option<&mut dyn ProgressSink>,
+) -> Result<Option<Hash>> {
+    let mut missing = Vec::new();
+    for want in wants {
+        if !missing.contains(want) && !Object::exists(want)? {
+            missing.push(want.clone());
+        }
+    }
+    if missing.is_empty() {
+        // a flush-pkt tells the remote that nothing is wanted, or ends a version 2 session
+        let mut request = Vec::new();
+        write_flush(&mut request)?;
+        transport.send(&request)?;
+        return Ok(None);
+    }
+
+    let show_progress = progress.is_some();
+    let sideband = match advertisement.version {
+        2 => negotiate_v2(
+            transport,
+            advertisement,
+            &missing,
+            local_tips,
+            include_tags,
+            show_progress,
+        )?,
+        _ => negotiate_v0(
+            transport,
+            advertisement,
+            &missing,
+            local_tips,
+            include_tags,
+            show_progress,
+        )?,
+    };
+
+    // the pack is stored as it arrives
+    let name = match sideband {
+        true => {
+            let mut no_progress = NoProgress;
+            let progress = progress.unwrap_or(&mut no_progress);
+            read_pack(
+                SidebandReader::new(transport.reader(), progress),
+                show_progress,
+            )?
+        }
+        false => read_pack(transport.reader().get_mut(), show_progress)?,
+    };
+    Ok(Some(name))
+}
+
+/// stores the pack, then reads the stream to its end, so that the remote isn't cut off
+/// while it finishes sending
+fn read_pack<R: Read>(mut stream: R, progress: bool) -> Result<Hash> {
+    let name = store_pack(&mut stream, progress)?;
+    if io::copy(&mut stream, &mut io::sink())? > 0 {
+        bail!("pack has junk at the end");
+    }
+    Ok(name)
+}
+
+/// starts the negotiation from the local refs, and the advertised refs that are already
+/// here, which the remote has too
+fn negotiator(advertisement: &Advertisement, local_tips: &[Hash]) -> Result<Negotiator> {
+    let mut negotiator = Negotiator::new();
+    for (_, hash) in &advertisement.refs {
+        if Object::exists(hash)? {
+            negotiator.known_common(hash)?;
+        }
+    }
+    for tip in local_tips {
+        negotiator.add_tip(tip)?;
+    }
+    Ok(negotiator)
+}
+
+/// negotiates with protocol versions 0 and 1, up to the start of the pack. returns
+/// whether the pack is multiplexed with progress.
+fn negotiate_v0(
+    transport: &mut Transport,
+    advertisement: &Advertisement,
+    missing: &[Hash],
+    local_tips: &[Hash],
+    include_tags: bool,
+    progress: bool,
+) -> Result<bool> {
+    let capabilities = capabilities(advertisement, include_tags, progress);
+    let multi_ack = capabilities.iter().any(|cap| cap == "multi_ack_detailed");
+    let sideband = capabilities.iter().any(|cap| cap.starts_with("side-band"));
+    let wants_request = upload_pack_request(
+        missing.iter().map(|want| want.to_hex()).collect(),
+        capabilities,
+    )?;
+
+    let mut negotiator = match multi_ack {
+        true => negotiator(advertisement, local_tips)?,
+        false => Negotiator::new(),
+    };
+
+    let stateless = transport.is_stateless();
+    if !stateless {
+        transport.send(&wants_request)?;
+    }
+
+    let mut flush_at = INITIAL_FLUSH;
+    let mut in_vain = 0;
+    let mut got_common = false;
+    let mut ready = false;
+    // without multi_ack_detailed, nothing is negotiated and the whole history is fetched
+    while multi_ack && !ready {
+        let mut haves = Vec::new();
+        while haves.len() < flush_at {
+            match negotiator.next_have()? {
+                Some(have) => haves.push(have),
+                None => break,
+            }
+        }
+        if haves.is_empty() {
+            break;
+        }
+
+        let mut request = Vec::new();
+        if stateless {
+            request.extend_from_slice(&wants_request);
+            for common in negotiator.acked() {
+                write_line(&mut request, &format!("have {}", common.to_hex()))?;
+            }
+        }
+        for have in &haves {
+            write_line(&mut request, &format!("have {}", have.to_hex()))?;
+        }
+        write_flush(&mut request)?;
+        transport.send(&request)?;
+
+        in_vain += haves.len();
+        loop {
+            match read_ack(transport)? {
+                Ack::Common(hash) => {
+                    negotiator.ack(&hash);
+                    got_common = true;
+                    in_vain = 0;
+                }
+                Ack::Ready(hash) => {
+                    negotiator.ack(&hash);
+                    ready = true;
+                }
+                Ack::Nak => break,
+                Ack::Final(hash) => bail!("unexpected ACK {} during negotiation", hash.to_hex()),
+            }
+        }
+
+        if got_common && in_vain > MAX_IN_VAIN {
+            break;
+        }
+        flush_at = (flush_at * 2).min(MAX_FLUSH);
+    }
+
+    let mut request = Vec::new();
+    if stateless {
+        request.extend_from_slice(&wants_request);
+        for common in negotiator.acked() {
+            write_line(&mut request, &format!("have {}", common.to_hex()))?;
+        }
+    }
+    write_line(&mut request, "done")?;
+    transport.send(&request)?;
+    transport.finish_requests();
+
+    // a stateless remote acknowledges the repeated haves again before its final answer
+    loop {
+        match read_ack(transport)? {
+            Ack::Final(_) | Ack::Nak => break,
+            Ack::Common(_) | Ack::Ready(_) => {}
+        }
+    }
+
+    Ok(sideband)
+}
+
+/// negotiates with protocol version 2, up to the start of the packfile section. every
+/// round is a fetch command that repeats the wants and the commits known to be common,
+/// since the remote keeps no state. the pack is always multiplexed.
+fn negotiate_v2(
+    transport: &mut Transport,
+    advertisement: &Advertisement,
+    missing: &[Hash],
+    local_tips: &[Hash],
+    include_tags: bool,
+    progress: bool,
+) -> Result<bool> {
+    // deltas may apply to objects that are already here, which are added to the pack
+    let mut args = vec!["thin-pack".to_string()];
+    if !progress {
+        args.push("no-progress".to_string());
+    }
+    if include_tags {
+        args.push("include-tag".to_string());
+    }
+    args.push("ofs-delta".to_string());
+    args.extend(missing.iter().map(|want| format!("want {}", want.to_hex())));
+
+    let mut negotiator = negotiator(advertisement, local_tips)?;
+    let mut flush_at = INITIAL_FLUSH;
+    let mut in_vain = 0;
+    let mut got_common = false;
+    loop {
+        let mut haves = Vec::new();
+        if !(got_common && in_vain > MAX_IN_VAIN) {
+            while haves.len() < flush_at {
+                match negotiator.next_have()? {
+                    Some(have) => haves.push(have),
+                    None => break,
+                }
+            }
+        }
+        // once there is nothing left to offer, "done" asks for the pack right away
+        let done = haves.is_empty();
+
+        let mut round = args.clone();
+        round.extend(
+            negotiator
+                .acked()
+                .iter()
+                .chain(&haves)
+                .map(|have| format!("have {}", have.to_hex())),
+        );
+        if done {
+            round.push("done".to_string());
+        }
+        transport.send(&command_request(advertisement, "fetch", &round)?)?;
+        if done {
+            break;
+        }
+
+        let mut lines = Vec::new();
+        let more = read_section(transport.reader(), &mut lines)?;
+        if lines.first().map(|line| line.as_str()) != Some("acknowledgments") {
+            bail!(
+                "protocol error: expected acknowledgments, got {:?}",
+                lines.first()
+            );
+        }
+        in_vain += haves.len();
+        let mut ready = false;
+        for line in &lines[1..] {
+            match line.split_once(' ') {
+                Some(("ACK", hash)) => {
+                    negotiator.ack(&Hash::try_from(hash.as_bytes())?);
+                    got_common = true;
+                    in_vain = 0;
+                }
+                _ if line == "NAK" => {}
+                _ if line == "ready" => ready = true,
+                _ => bail!("protocol error: unexpected acknowledgment '{}'", line),
+            }
+        }
+        // a ready remote goes on with the pack in the same response
+        if ready != more {
+            bail!("protocol error: expected the pack only after 'ready'");
+        }
+        if ready {
+            break;
+        }
+        flush_at = (flush_at * 2).min(MAX_FLUSH);
+    }
+    transport.finish_requests();
+
+    loop {
+        let mut lines = Vec::new();
+        match transport.reader().read_line()? {
+            Some(section) if section == "packfile" => break,
+            // shallow commits and wanted refs are never asked for, so they are skipped
+            Some(section) if section == "shallow-info" || section == "wanted-refs" => {
+                if !read_section(transport.reader(), &mut lines)? {
+                    bail!("protocol error: the response ended before the pack");
+                }
+            }
+            Some(section) if section == "packfile-uris" => {
+                bail!("packfile URIs are not supported")
+            }
+            section => bail!("protocol error: unexpected section {:?}", section),
+        }
+    }
+    Ok(true)
+}
+
+/// the capabilities asked for, among those the remote advertises
+fn capabilities(
+    advertisement: &Advertisement,
+    include_tags: bool,
+    progress: bool,
+) -> Vec<Capability> {
+    let mut wanted = vec!["multi_ack_detailed"];
+    let sideband = ["side-band-64k", "side-band"]
+        .into_iter()
+        .find(|cap| advertisement.has_capability(cap));
+    wanted.extend(sideband);
+    wanted.push("thin-pack");
+    // without side-band, progress has nowhere to go but the remote's stderr
+    if !progress || sideband.is_none() {
+        wanted.push("no-progress");
+    }
+    if include_tags {
+        wanted.push("include-tag");
+    }
+    wanted.push("ofs-delta");
+
+    let mut capabilities: Vec<Capability> = wanted
+        .into_iter()
+        .filter(|cap| advertisement.has_capability(cap))
+        .map(|cap| cap.to_string())
+        .collect();
+    capabilities.push(format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")));
+    capabilities
+}
+
+fn read_ack(transport: &mut Transport) -> Result<Ack> {
+    let line = transport.reader().read_line()?.ok_or(anyhow!(
+        "protocol error: expected ACK/NAK, got a flush packet"
+    ))?;
+    if line == "NAK" {
+        return Ok(Ack::Nak);
+    }
+
+    let mut words = line.split(' ');
+    let hash = match (words.next(), words.next()) {
+        (Some("ACK"), Some(hash)) => Hash::try_from(hash.as_bytes())?,
+        _ => bail!("protocol error: expected ACK/NAK, got '{}'", line),
+    };
+    match words.next() {
+        None => Ok(Ack::Final(hash)),
+        Some("common") | Some("continue") => Ok(Ack::Common(hash)),
+        Some("ready") => Ok(Ack::Ready(hash)),
+        Some(status) => bail!("protocol error: unknown ACK status '{}'", status),
+    }
+}
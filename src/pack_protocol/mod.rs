@@ -1,4 +1,14 @@
+pub mod delta;
+pub mod fetch_pack;
+pub mod negotiator;
 pub mod pack_file;
+pub mod pack_index;
 pub mod pack_object;
+pub mod pack_reader;
+pub mod pack_writer;
 pub mod pkt_line;
+pub mod protocol_v2;
+pub mod receive_pack_request;
+pub mod sideband;
+pub mod transport;
 pub mod upload_pack_request;
@@ -0,0 +1,123 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::{
+    objects::{commit::decode_commit, hash::Hash, Object, ObjectKind},
+    rev_parse::peel_kind,
+};
+
+/// picks the local commits to offer as haves, newest first. once the remote acknowledges a
+/// commit as common, its ancestors are no longer offered, since the remote has them too.
+#[derive(Default)]
+pub struct Negotiator {
+    /// commits waiting to be offered, by committer time
+    queue: BinaryHeap<(u64, Hash)>,
+    /// queued commits not known to be common. once none are left, everything worth
+    /// offering was offered.
+    queued: HashSet<Hash>,
+    non_common: usize,
+    /// commits that were queued, and their parents
+    seen: HashSet<Hash>,
+    parents: HashMap<Hash, Vec<Hash>>,
+    common: HashSet<Hash>,
+    /// the commits the remote acknowledged, in order
+    acked: Vec<Hash>,
+}
+
+impl Negotiator {
+    pub fn new() -> Negotiator {
+        Negotiator::default()
+    }
+
+    /// starts offering the history of a local ref. tags are peeled, and refs that don't
+    /// lead to a commit are ignored.
+    pub fn add_tip(&mut self, hash: &Hash) -> Result<()> {
+        let (hash, kind) = peel_kind(hash.clone(), None)?;
+        if kind == ObjectKind::Commit {
+            self.push(hash)?;
+        }
+        Ok(())
+    }
+
+    /// records a commit the remote advertised and that exists locally. the remote has its
+    /// ancestors, so only the commit itself is offered.
+    pub fn known_common(&mut self, hash: &Hash) -> Result<()> {
+        let (hash, kind) = peel_kind(hash.clone(), None)?;
+        if kind != ObjectKind::Commit || self.seen.contains(&hash) {
+            return Ok(());
+        }
+        self.push(hash.clone())?;
+        for parent in self.parents.get(&hash).cloned().unwrap_or_default() {
+            self.mark_common(&parent);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, hash: Hash) -> Result<()> {
+        if self.seen.contains(&hash) || !Object::exists(&hash)? {
+            return Ok(());
+        }
+        let commit = decode_commit(Object::read_from_hash(hash.to_hex())?.data)?;
+        self.seen.insert(hash.clone());
+        self.parents.insert(hash.clone(), commit.parents);
+        if !self.common.contains(&hash) {
+            self.queued.insert(hash.clone());
+            self.non_common += 1;
+        }
+        self.queue.push((commit.committer.time, hash));
+        Ok(())
+    }
+
+    /// the next commit to offer, or None once only common commits are left
+    pub fn next_have(&mut self) -> Result<Option<Hash>> {
+        while self.non_common > 0 {
+            let (_, hash) = self.queue.pop().expect("non-common commits are queued");
+            if self.queued.remove(&hash) {
+                self.non_common -= 1;
+            }
+            let parents = self.parents.get(&hash).cloned().unwrap_or_default();
+            let common = self.common.contains(&hash);
+            for parent in parents {
+                if common {
+                    self.mark_common(&parent);
+                }
+                self.push(parent)?;
+            }
+            if !common {
+                return Ok(Some(hash));
+            }
+        }
+        Ok(None)
+    }
+
+    /// records that the remote has a commit, and so all of its ancestors. only ancestors
+    /// already read are marked, the others are marked once they are reached.
+    pub fn ack(&mut self, hash: &Hash) {
+        if !self.acked.contains(hash) {
+            self.acked.push(hash.clone());
+        }
+        self.mark_common(hash);
+    }
+
+    fn mark_common(&mut self, hash: &Hash) {
+        let mut stack = vec![hash.clone()];
+        while let Some(hash) = stack.pop() {
+            if !self.common.insert(hash.clone()) {
+                continue;
+            }
+            if self.queued.remove(&hash) {
+                self.non_common -= 1;
+            }
+            if let Some(parents) = self.parents.get(&hash) {
+                stack.extend(parents.iter().cloned());
+            }
+        }
+    }
+
+    /// the commits the remote acknowledged, which a stateless remote is reminded of in
+    /// every request
+    pub fn acked(&self) -> &[Hash] {
+        &self.acked
+    }
+}
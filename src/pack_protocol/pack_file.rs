@@ -1,11 +1,14 @@
 use crate::{
     objects::{hash::Hash, Object},
-    pack_protocol::pack_object::{PackObject, PackObjectType},
+    pack_protocol::pack_object::{next_byte, PackObject, PackObjectType},
 };
 use anyhow::Result;
 use bytes::{Buf, Bytes};
 use std::{collections::HashMap, error::Error, fmt::Display};
 
+/// size of the pack header: the signature, the version and the number of objects
+pub const PACK_HEADER_SIZE: usize = 12;
+
 pub struct PackFile {
     data: Bytes,
     // objects_read: u32,
@@ -30,6 +33,10 @@ pub enum PackFileError {
     ErrOffsetDeltaBaseObject(String),
     /// indicates an error while referring to base object for ref delta object
     ErrRefDeltaBaseObject(String),
+    /// indicates a pack whose trailing checksum doesn't match its content
+    ErrChecksumMismatch,
+    /// indicates a pack that ends in the middle of an object or before its checksum
+    ErrTruncated,
 }
 
 impl Error for PackFileError {}
@@ -57,6 +64,8 @@ impl Display for PackFileError {
             Self::ErrRefDeltaBaseObject(err) => {
                 write!(f, "failed to get ref delta base object: {}", err)
             }
+            Self::ErrChecksumMismatch => write!(f, "pack is corrupted: checksum mismatch"),
+            Self::ErrTruncated => write!(f, "pack is truncated"),
         }
     }
 }
@@ -73,77 +82,63 @@ impl PackFile {
     }
 
     fn read_header(data: &mut Bytes) -> Result<u32> {
+        if data.len() < PACK_HEADER_SIZE {
+            return Err(PackFileError::ErrInvalidSignature.into());
+        }
         let signature = data.split_to(4);
         if *signature != *b"PACK" {
             return Err(PackFileError::ErrInvalidSignature.into());
         }
 
-        let version = data.split_to(4);
-        let version = u32::from_be_bytes([version[0], version[1], version[2], version[3]]);
+        let version = data.get_u32();
         if version != 2 {
             return Err(PackFileError::ErrVersionNotSupported.into());
         }
 
-        let items_expexted_bytes = data.split_to(4);
-        let items_expected = u32::from_be_bytes([
-            items_expexted_bytes[0],
-            items_expexted_bytes[1],
-            items_expexted_bytes[2],
-            items_expexted_bytes[3],
-        ]);
+        let items_expected = data.get_u32();
 
         Ok(items_expected)
     }
 
+    /// parses the objects of the pack. their offsets are counted from the start of the
+    /// pack, header included, as pack indexes expect.
     pub fn read_objects(&mut self) -> Result<Vec<PackObject>> {
         let original_data_size = self.data.len();
         let mut pack_objects = Vec::new();
 
         for _ in 0..self.items_expected {
-            let cur_data_size = self.data.len();
+            let offset = original_data_size - self.data.len() + PACK_HEADER_SIZE;
             let mut object_header_bytes = Vec::new();
             loop {
-                let b = self.data.get_u8();
+                let b =
+                    next_byte(&mut self.data).ok_or(PackFileError::ErrInvalidPackObjectLength)?;
 
                 object_header_bytes.push(b);
 
                 if b & (1 << 7) == 0 {
                     break;
                 }
+                if object_header_bytes.len() > 8 {
+                    return Err(PackFileError::ErrInvalidPackObjectLength.into());
+                }
             }
 
             let object_type: PackObjectType =
                 ((object_header_bytes[0] & 0b0111_0000) >> 4).try_into()?;
 
-            if object_header_bytes.len() > 8 {
-                return Err(PackFileError::ErrInvalidPackObjectLength.into());
-            }
-
             let mut object_size = (object_header_bytes[0] & 0b0000_1111) as u64;
 
             for (i, b) in object_header_bytes[1..].iter().enumerate() {
                 object_size |= ((b & 0b0111_1111) as u64) << (7 * i + 4);
             }
+            let object_size = usize::try_from(object_size)?;
 
-            let obj = match object_type {
-                PackObjectType::Blob
-                | PackObjectType::Commit
-                | PackObjectType::Tag
-                | PackObjectType::Tree => PackObject::new_simple(
-                    &mut self.data,
-                    object_size.try_into()?,
-                    original_data_size - cur_data_size,
-                )?,
-                PackObjectType::OfsDelta => PackObject::new_ofs_delta(
-                    &mut self.data,
-                    object_size.try_into()?,
-                    original_data_size - cur_data_size,
-                )?,
-                PackObjectType::RefDelta => PackObject::new_ref_delte(
-                    &mut self.data,
-                    object_size.try_into()?,
-                    original_data_size - cur_data_size,
-                )?,
+            let obj = match object_type.object_kind() {
+                Some(kind) => PackObject::new_simple(&mut self.data, kind, object_size, offset)?,
+                None if object_type == PackObjectType::OfsDelta => {
+                    PackObject::new_ofs_delta(&mut self.data, object_size, offset)?
+                }
+                None => PackObject::new_ref_delte(&mut self.data, object_size, offset)?,
             };
 
             pack_objects.push(obj);
@@ -156,7 +151,7 @@ impl PackFile {
         let mut offset_index = HashMap::new();
         let mut hash_index = HashMap::new();
         let mut objs = Vec::new();
-        for (_, pack_obj) in pack_objs.iter_mut().enumerate() {
+        for pack_obj in pack_objs.iter_mut() {
             match pack_obj {
                 PackObject::OfsDelta {
                     offset,
@@ -208,8 +203,11 @@ impl PackFile {
                     offset_index.insert(offset, objs.len() - 1);
                     hash_index.insert(hash.to_hex(), objs.len() - 1);
                 }
-                PackObject::Simple { data, offset } => {
-                    let object = Object::read(data.reader())?;
+                PackObject::Simple { kind, data, offset } => {
+                    let object = Object {
+                        data: data.to_vec(),
+                        kind: *kind,
+                    };
                     let hash = object.hash()?;
                     objs.push(object);
                     offset_index.insert(offset, objs.len() - 1);
@@ -0,0 +1,240 @@
+use std::{error::Error, fmt::Display, sync::OnceLock};
+
+use anyhow::Result;
+
+use crate::objects::hash::{hash, Hash};
+
+/// signature of version 2 pack indexes
+const INDEX_SIGNATURE: [u8; 4] = [0xff, b't', b'O', b'c'];
+
+/// flag of 4-byte offsets that point into the table of 8-byte offsets instead
+const LARGE_OFFSET_FLAG: u32 = 1 << 31;
+
+#[derive(Debug)]
+pub enum PackIndexError {
+    /// indicates an index that isn't a version 2 pack index
+    ErrInvalidSignature,
+    /// indicates an index shorter than its tables
+    ErrTruncated,
+    /// indicates an index whose trailing checksum doesn't match its content
+    ErrChecksumMismatch,
+}
+
+impl Error for PackIndexError {}
+
+impl Display for PackIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ErrInvalidSignature => write!(f, "only version 2 pack indexes are supported"),
+            Self::ErrTruncated => write!(f, "pack index is truncated"),
+            Self::ErrChecksumMismatch => write!(f, "pack index checksum mismatch"),
+        }
+    }
+}
+
+/// an object stored in a pack, as listed in the pack's index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndexEntry {
+    pub hash: Hash,
+    /// the CRC32 of the object's bytes in the pack, header included
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+/*
+    version 2 index format:
+        signature "\377tOc", version 2
+        fanout table: 256 counts of objects whose first hash byte is at most i
+        sorted object names
+        CRC32 of each object
+        4-byte offsets, with the high bit pointing into the 8-byte offsets
+        8-byte offsets
+        pack checksum
+        index checksum
+*/
+
+/// the index of a pack, mapping object names to their offsets in the pack
+#[derive(Debug, Clone)]
+pub struct PackIndex {
+    fanout: Vec<u32>,
+    entries: Vec<PackIndexEntry>,
+    pub pack_checksum: Hash,
+    /// the positions of the entries, sorted by offset, built when first needed
+    by_offset: OnceLock<Vec<usize>>,
+}
+
+impl PackIndex {
+    /// builds the index of a pack from its objects, in any order
+    pub fn new(mut entries: Vec<PackIndexEntry>, pack_checksum: Hash) -> PackIndex {
+        entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+        let mut fanout = vec![0; 256];
+        for entry in &entries {
+            fanout[entry.hash.0[0] as usize] += 1;
+        }
+        for i in 1..256 {
+            fanout[i] += fanout[i - 1];
+        }
+
+        PackIndex {
+            fanout,
+            entries,
+            pack_checksum,
+            by_offset: OnceLock::new(),
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<PackIndex> {
+        if data.len() < 8 + 256 * 4 + 40 {
+            return Err(PackIndexError::ErrTruncated.into());
+        }
+        if data[..4] != INDEX_SIGNATURE || read_u32(&data[4..]) != 2 {
+            return Err(PackIndexError::ErrInvalidSignature.into());
+        }
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if hash(content).0 != checksum {
+            return Err(PackIndexError::ErrChecksumMismatch.into());
+        }
+
+        let fanout: Vec<u32> = (0..256).map(|i| read_u32(&data[8 + i * 4..])).collect();
+        let count = fanout[255] as usize;
+        let hashes_start = 8 + 256 * 4;
+        let crcs_start = hashes_start + count * 20;
+        let offsets_start = crcs_start + count * 4;
+        let large_offsets_start = offsets_start + count * 4;
+        if content.len() < large_offsets_start + 20 {
+            return Err(PackIndexError::ErrTruncated.into());
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let hash = Hash(data[hashes_start + i * 20..hashes_start + (i + 1) * 20].to_vec());
+            let crc32 = read_u32(&data[crcs_start + i * 4..]);
+            let offset = read_u32(&data[offsets_start + i * 4..]);
+            let offset = match offset & LARGE_OFFSET_FLAG {
+                0 => offset as u64,
+                _ => {
+                    let at = large_offsets_start + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+                    let bytes = content
+                        .get(at..at + 8)
+                        .ok_or(PackIndexError::ErrTruncated)?;
+                    u64::from_be_bytes(bytes.try_into().expect("slice has 8 bytes"))
+                }
+            };
+            entries.push(PackIndexEntry {
+                hash,
+                crc32,
+                offset,
+            });
+        }
+
+        let pack_checksum = Hash(content[content.len() - 20..].to_vec());
+        Ok(PackIndex {
+            fanout,
+            entries,
+            pack_checksum,
+            by_offset: OnceLock::new(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&INDEX_SIGNATURE);
+        data.extend_from_slice(&2u32.to_be_bytes());
+        for count in &self.fanout {
+            data.extend_from_slice(&count.to_be_bytes());
+        }
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.hash.0);
+        }
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for entry in &self.entries {
+            let offset = match u32::try_from(entry.offset) {
+                Ok(offset) if offset & LARGE_OFFSET_FLAG == 0 => offset,
+                _ => {
+                    large_offsets.push(entry.offset);
+                    (large_offsets.len() as u32 - 1) | LARGE_OFFSET_FLAG
+                }
+            };
+            data.extend_from_slice(&offset.to_be_bytes());
+        }
+        for offset in large_offsets {
+            data.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        data.extend_from_slice(&self.pack_checksum.0);
+        let checksum = hash(&data);
+        data.extend_from_slice(&checksum.0);
+        data
+    }
+
+    /// the offset of an object in the pack, if the pack has it
+    pub fn find(&self, hash: &Hash) -> Option<u64> {
+        let first = *hash.0.first()? as usize;
+        let start = match first {
+            0 => 0,
+            _ => self.fanout[first - 1] as usize,
+        };
+        let end = self.fanout[first] as usize;
+        self.entries[start..end]
+            .binary_search_by(|entry| entry.hash.cmp(hash))
+            .ok()
+            .map(|i| self.entries[start + i].offset)
+    }
+
+    /// the object stored at an offset of the pack, as the base of an ofs-delta is
+    pub fn find_offset(&self, offset: u64) -> Option<&Hash> {
+        let by_offset = self.by_offset.get_or_init(|| {
+            let mut by_offset: Vec<usize> = (0..self.entries.len()).collect();
+            by_offset.sort_by_key(|i| self.entries[*i].offset);
+            by_offset
+        });
+        by_offset
+            .binary_search_by_key(&offset, |i| self.entries[*i].offset)
+            .ok()
+            .map(|i| &self.entries[by_offset[i]].hash)
+    }
+
+    /// the objects of the pack, sorted by name
+    pub fn entries(&self) -> &[PackIndexEntry] {
+        &self.entries
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::objects::hash::Hash;
+
+    use super::{PackIndex, PackIndexEntry};
+
+    #[test]
+    fn test_encode_decode_index() {
+        let entry = |byte: u8, offset: u64| PackIndexEntry {
+            hash: Hash(vec![byte; 20]),
+            crc32: byte as u32,
+            offset,
+        };
+        let index = PackIndex::new(
+            vec![entry(0xab, 12), entry(0x01, 300), entry(0xab - 1, 1 << 33)],
+            Hash(vec![7; 20]),
+        );
+
+        let decoded = PackIndex::decode(&index.encode()).unwrap();
+
+        assert_eq!(decoded.entries(), index.entries());
+        assert_eq!(decoded.entries()[0].hash, Hash(vec![0x01; 20]));
+        assert_eq!(decoded.find(&Hash(vec![0xab; 20])), Some(12));
+        assert_eq!(decoded.find(&Hash(vec![0xaa; 20])), Some(1 << 33));
+        assert_eq!(decoded.find(&Hash(vec![0x02; 20])), None);
+        assert_eq!(decoded.pack_checksum, Hash(vec![7; 20]));
+        assert_eq!(decoded.find_offset(300), Some(&Hash(vec![0x01; 20])));
+        assert_eq!(decoded.find_offset(13), None);
+    }
+}
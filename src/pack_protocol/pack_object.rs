@@ -1,15 +1,18 @@
-use crate::{objects::Object, pack_protocol::pack_file::PackFileError};
+use crate::{
+    objects::{Object, ObjectKind},
+    pack_protocol::pack_file::PackFileError,
+};
 use std::io::Read;
 
 use bytes::{Buf, Bytes};
 
 use anyhow::Result;
-use flate2::read::ZlibDecoder;
+use flate2::bufread::ZlibDecoder;
 
 #[derive(Debug, Clone)]
 pub enum PackObject {
     Simple {
-        // kind: ObjectKind,
+        kind: ObjectKind,
         data: bytes::Bytes,
         offset: usize,
     },
@@ -30,16 +33,17 @@ pub enum PackObject {
 }
 
 impl PackObject {
-    pub fn new_simple(data: &mut Bytes, size: usize, obj_offset: usize) -> Result<PackObject> {
+    pub fn new_simple(
+        data: &mut Bytes,
+        kind: ObjectKind,
+        size: usize,
+        obj_offset: usize,
+    ) -> Result<PackObject> {
         // data is the compressed object data
-        let mut decompressed = Vec::new();
-        let read = ZlibDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
-        if decompressed.len() != size {
-            return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
-        }
-        data.advance(read);
+        let decompressed = Self::inflate(data, size)?;
 
         Ok(PackObject::Simple {
+            kind,
             data: Bytes::from(decompressed),
             offset: obj_offset,
         })
@@ -51,52 +55,42 @@ impl PackObject {
                 negative relative offset from the delta object's position in the pack
                 compressed delta data
         */
-        let offset = Self::read_variable_length(data);
+        let offset = usize::try_from(Self::read_offset(data)?)?;
+        let base_offset =
+            obj_offset
+                .checked_sub(offset)
+                .ok_or(PackFileError::ErrOffsetDeltaBaseObject(format!(
+                    "offset {} points before the start of the pack",
+                    offset
+                )))?;
 
-        let mut decompressed = Vec::new();
-        let read = ZlibDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
-        if decompressed.len() != size {
-            return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
-        }
-        data.advance(read);
-
-        let mut decompressed_bytes = Bytes::from(decompressed);
-        let base_size = usize::try_from(Self::read_variable_length(&mut decompressed_bytes))?;
-        let reconstructed_size =
-            usize::try_from(Self::read_variable_length(&mut decompressed_bytes))?;
-
-        let instructions = Self::parse_delta_instructions(decompressed_bytes)?;
+        let decompressed = Self::inflate(data, size)?;
+        let (base_size, reconstructed_size, instructions) =
+            Self::parse_delta(Bytes::from(decompressed))?;
 
         Ok(PackObject::OfsDelta {
             offset: obj_offset,
-            base_offset: obj_offset - usize::try_from(offset)?,
+            base_offset,
             instructions,
             base_size,
             reconstructed_size,
         })
     }
 
-    pub fn new_ref_delte(data: &mut Bytes, size: u64, obj_offset: usize) -> Result<PackObject> {
+    pub fn new_ref_delte(data: &mut Bytes, size: usize, obj_offset: usize) -> Result<PackObject> {
         /*
            data:
                base object name
                compressed delta data
         */
-        let base_obj_name = data.split_to(20);
-
-        let mut decompressed = Vec::new();
-        let read = ZlibDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
-        if read as u64 != size {
-            return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
+        if data.len() < 20 {
+            return Err(PackFileError::ErrRefDeltaBaseObject("truncated base name".into()).into());
         }
-        data.advance(read);
-
-        let mut decompressed_bytes = Bytes::from(decompressed);
-        let base_size = usize::try_from(Self::read_variable_length(&mut decompressed_bytes))?;
-        let reconstructed_size =
-            usize::try_from(Self::read_variable_length(&mut decompressed_bytes))?;
+        let base_obj_name = data.split_to(20);
 
-        let instructions = Self::parse_delta_instructions(decompressed_bytes)?;
+        let decompressed = Self::inflate(data, size)?;
+        let (base_size, reconstructed_size, instructions) =
+            Self::parse_delta(Bytes::from(decompressed))?;
 
         Ok(PackObject::RefDelta {
             offset: obj_offset,
@@ -107,55 +101,108 @@ impl PackObject {
         })
     }
 
-    fn read_variable_length(data: &mut Bytes) -> u64 {
+    /// decompresses the zlib stream at the start of data, and advances data past the
+    /// compressed bytes it was made of
+    fn inflate(data: &mut Bytes, size: usize) -> Result<Vec<u8>> {
+        let mut decoder = ZlibDecoder::new(&data[..]);
+        let mut decompressed = Vec::with_capacity(size);
+        decoder.read_to_end(&mut decompressed)?;
+        if decompressed.len() != size {
+            return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
+        }
+
+        let consumed = usize::try_from(decoder.total_in())?;
+        data.advance(consumed);
+        Ok(decompressed)
+    }
+
+    /// reads the size varints of a delta: 7 bits per byte, least significant group first
+    fn read_variable_length(data: &mut Bytes) -> Result<u64> {
         let mut size: u64 = 0;
-        for i in 0.. {
+        for i in 0..10 {
+            if !data.has_remaining() {
+                break;
+            }
             let b = data.get_u8();
             size |= ((b & 0b0111_1111) as u64) << (7 * i);
 
             if b & (1 << 7) == 0 {
+                return Ok(size);
+            }
+        }
+
+        Err(PackFileError::ErrInvalidDeltaInstruction("invalid size".into()).into())
+    }
+
+    /// reads the base offset of an ofs-delta: 7 bits per byte, most significant group
+    /// first, with each continuation adding one so that encodings are unique
+    pub fn read_offset(data: &mut Bytes) -> Result<u64> {
+        let mut offset: u64 = 0;
+        for i in 0..10 {
+            if !data.has_remaining() {
                 break;
             }
+            let b = data.get_u8();
+            if i > 0 {
+                offset += 1;
+            }
+            offset = (offset << 7) | (b & 0b0111_1111) as u64;
+
+            if b & (1 << 7) == 0 {
+                return Ok(offset);
+            }
         }
 
-        size
+        Err(PackFileError::ErrOffsetDeltaBaseObject("invalid offset".into()).into())
+    }
+
+    /// parses delta data into the size of the base, the size of the result and the
+    /// instructions building the result
+    pub fn parse_delta(mut data: Bytes) -> Result<(usize, usize, Vec<DeltaInstruction>)> {
+        let base_size = usize::try_from(Self::read_variable_length(&mut data)?)?;
+        let reconstructed_size = usize::try_from(Self::read_variable_length(&mut data)?)?;
+        let instructions = Self::parse_delta_instructions(data)?;
+        Ok((base_size, reconstructed_size, instructions))
     }
 
     fn parse_delta_instructions(mut data: Bytes) -> Result<Vec<DeltaInstruction>> {
+        let truncated =
+            || PackFileError::ErrInvalidDeltaInstruction("truncated instruction".into());
         let mut instructions = Vec::new();
         while !data.is_empty() {
             let b = data.get_u8();
 
             if b == 0 {
-                return Err(PackFileError::ErrInvalidDeltaInstruction(format!(
-                    "the 0 instruction is reserved for future expansion"
-                ))
+                return Err(PackFileError::ErrInvalidDeltaInstruction(
+                    "the 0 instruction is reserved for future expansion".into(),
+                )
                 .into());
             }
 
             if b & (1 << 7) == 0 {
                 // add instruction
-                let size = b;
-                let add = data.split_to(size.into());
+                let size = usize::from(b);
+                if data.len() < size {
+                    return Err(truncated().into());
+                }
+                let add = data.split_to(size);
 
-                instructions.push(DeltaInstruction::Insert {
-                    data: Bytes::from(add),
-                })
+                instructions.push(DeltaInstruction::Insert { data: add })
             } else {
-                // copy instruction
+                // copy instruction: the low bits say which offset and size bytes follow
                 let mut offset: u64 = 0;
                 let mut size: u64 = 0;
                 for i in 0..4 {
-                    if b & (1 << i) == 1 {
-                        let next = data.get_u8();
-                        offset |= (next << (8 * i)) as u64
+                    if b & (1 << i) != 0 {
+                        let next = next_byte(&mut data).ok_or_else(truncated)?;
+                        offset |= (next as u64) << (8 * i);
                     }
                 }
 
                 for i in 0..3 {
-                    if b & (1 << (i + 4)) == 1 {
-                        let next = data.get_u8();
-                        size |= (next << (8 * i)) as u64
+                    if b & (1 << (i + 4)) != 0 {
+                        let next = next_byte(&mut data).ok_or_else(truncated)?;
+                        size |= (next as u64) << (8 * i);
                     }
                 }
 
@@ -170,12 +217,49 @@ impl PackObject {
         Ok(instructions)
     }
 
+    /// rebuilds an object from its base and delta instructions. the object is of the same
+    /// kind as its base.
     pub fn apply_delta_instructions(
         base_obj: &Object,
-        instructions: &Vec<DeltaInstruction>,
+        instructions: &[DeltaInstruction],
     ) -> Result<Object> {
-        todo!()
+        Ok(Object {
+            data: apply_delta(&base_obj.data, instructions)?,
+            kind: base_obj.kind,
+        })
+    }
+}
+
+/// takes the next byte of data, if there is one
+pub fn next_byte(data: &mut Bytes) -> Option<u8> {
+    match data.has_remaining() {
+        true => Some(data.get_u8()),
+        false => None,
+    }
+}
+
+/// runs delta instructions against a base, copying ranges of it or inserting new data
+pub fn apply_delta(base: &[u8], instructions: &[DeltaInstruction]) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            DeltaInstruction::Copy { offset, size } => {
+                let start = usize::try_from(*offset)?;
+                let end = start + usize::try_from(*size)?;
+                let copied =
+                    base.get(start..end)
+                        .ok_or(PackFileError::ErrInvalidDeltaInstruction(format!(
+                            "copy of {}..{} is outside of a base of size {}",
+                            start,
+                            end,
+                            base.len()
+                        )))?;
+                result.extend_from_slice(copied);
+            }
+            DeltaInstruction::Insert { data } => result.extend_from_slice(data),
+        }
     }
+    Ok(result)
 }
 
 #[derive(Debug, Clone)]
@@ -194,6 +278,55 @@ pub enum PackObjectType {
     RefDelta,
 }
 
+impl PackObjectType {
+    /// the kind of the objects stored whole, or None for deltas
+    pub fn object_kind(&self) -> Option<ObjectKind> {
+        match self {
+            Self::Commit => Some(ObjectKind::Commit),
+            Self::Tree => Some(ObjectKind::Tree),
+            Self::Blob => Some(ObjectKind::Blob),
+            Self::Tag => Some(ObjectKind::Tag),
+            Self::OfsDelta | Self::RefDelta => None,
+        }
+    }
+
+    /// the type number entry headers store
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Commit => 1,
+            Self::Tree => 2,
+            Self::Blob => 3,
+            Self::Tag => 4,
+            Self::OfsDelta => 6,
+            Self::RefDelta => 7,
+        }
+    }
+}
+
+impl From<ObjectKind> for PackObjectType {
+    fn from(kind: ObjectKind) -> Self {
+        match kind {
+            ObjectKind::Commit => Self::Commit,
+            ObjectKind::Tree => Self::Tree,
+            ObjectKind::Blob => Self::Blob,
+            ObjectKind::Tag => Self::Tag,
+        }
+    }
+}
+
+/// encodes the header of a pack entry: its type, and the size of its inflated data in
+/// groups of 7 bits, 4 in the first byte
+pub fn entry_header(object_type: PackObjectType, size: u64) -> Vec<u8> {
+    let mut header = vec![(object_type.code() << 4) | (size & 0b1111) as u8];
+    let mut size = size >> 4;
+    while size > 0 {
+        *header.last_mut().expect("not empty") |= 1 << 7;
+        header.push((size & 0b0111_1111) as u8);
+        size >>= 7;
+    }
+    header
+}
+
 impl TryFrom<u8> for PackObjectType {
     type Error = PackFileError;
     fn try_from(value: u8) -> std::prelude::v1::Result<Self, Self::Error> {
@@ -208,3 +341,57 @@ impl TryFrom<u8> for PackObjectType {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{apply_delta, entry_header, PackObject, PackObjectType};
+
+    #[test]
+    fn test_entry_header() {
+        assert_eq!(entry_header(PackObjectType::Blob, 11), vec![0b0011_1011]);
+        // 300 is 0b1_0010_1100: 4 bits in the first byte, then 7
+        assert_eq!(
+            entry_header(PackObjectType::Commit, 300),
+            vec![0b1001_1100, 0b0001_0010]
+        );
+    }
+
+    #[test]
+    fn test_read_offset() {
+        // 0x80 0x00 encodes (0 + 1) << 7 | 0
+        assert_eq!(
+            PackObject::read_offset(&mut Bytes::from_static(&[0x80, 0x00])).unwrap(),
+            128
+        );
+        assert_eq!(
+            PackObject::read_offset(&mut Bytes::from_static(&[0x05])).unwrap(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_parse_and_apply_delta() {
+        let base = b"hello world";
+        // base size 11, result size 12, copy 6 bytes from offset 0, insert "there!"
+        let delta = Bytes::from_static(&[
+            11,
+            12,
+            0b1001_0000,
+            6,
+            6,
+            b't',
+            b'h',
+            b'e',
+            b'r',
+            b'e',
+            b'!',
+        ]);
+
+        let (base_size, result_size, instructions) = PackObject::parse_delta(delta).unwrap();
+
+        assert_eq!((base_size, result_size), (11, 12));
+        assert_eq!(apply_delta(base, &instructions).unwrap(), b"hello there!");
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{self, BufRead, Read, Sink, Write};
+
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::{bufread::ZlibDecoder, Crc};
+use sha1::{Digest, Sha1};
+
+use crate::objects::{hash::Hash, ObjectKind};
+
+use super::{
+    pack_file::{PackFileError, PACK_HEADER_SIZE},
+    pack_object::{PackObject, PackObjectType},
+};
+
+/// size of the buffer the pack is read through
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// an entry of a pack: an object, or a delta to apply to a base object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackEntry {
+    /// where the entry starts, counted from the start of the pack
+    pub offset: u64,
+    pub kind: EntryKind,
+    /// the inflated object, or delta
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    Object(ObjectKind),
+    /// a delta against the entry at an earlier offset of the pack
+    OfsDelta(u64),
+    /// a delta against an object named by its hash
+    RefDelta(Hash),
+}
+
+/// reads the entries of a pack one at a time from a stream, so that the pack never needs
+/// to be held in memory. the checksum of the pack and the CRC32 of each entry are computed
+/// from the bytes the entries are made of, and every byte read can be copied to a writer,
+/// as when the pack is stored.
+pub struct PackReader<R: Read, W: Write = Sink> {
+    reader: CountingReader<R, W>,
+    object_count: u32,
+    entries_read: u32,
+    checksum: Option<Hash>,
+}
+
+impl<R: Read> PackReader<R> {
+    pub fn new(reader: R) -> Result<PackReader<R>> {
+        Self::with_copy(reader, io::sink())
+    }
+}
+
+impl<R: Read, W: Write> PackReader<R, W> {
+    /// reads a pack, writing every byte of it to copy as it goes
+    pub fn with_copy(reader: R, copy: W) -> Result<PackReader<R, W>> {
+        let mut reader = CountingReader {
+            inner: reader,
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            position: 0,
+            filled: 0,
+            offset: 0,
+            sha: Sha1::new(),
+            crc: Crc::new(),
+            copy,
+            copy_error: None,
+        };
+
+        let mut header = [0; PACK_HEADER_SIZE];
+        read_exact(&mut reader, &mut header)?;
+        if header[..4] != *b"PACK" {
+            return Err(PackFileError::ErrInvalidSignature.into());
+        }
+        if u32::from_be_bytes(header[4..8].try_into()?) != 2 {
+            return Err(PackFileError::ErrVersionNotSupported.into());
+        }
+        let object_count = u32::from_be_bytes(header[8..12].try_into()?);
+
+        Ok(PackReader {
+            reader,
+            object_count,
+            entries_read: 0,
+            checksum: None,
+        })
+    }
+
+    /// the number of objects the pack header announces
+    pub fn object_count(&self) -> u32 {
+        self.object_count
+    }
+
+    /// reads the next entry, along with the CRC32 of its bytes as pack indexes record
+    /// it. once all entries are read, the trailing checksum is verified and None is
+    /// returned.
+    pub fn next_entry(&mut self) -> Result<Option<(PackEntry, u32)>> {
+        if self.entries_read == self.object_count {
+            if self.checksum.is_none() {
+                self.read_trailer()?;
+            }
+            return Ok(None);
+        }
+
+        self.reader.crc.reset();
+        let offset = self.reader.offset;
+        let entry = read_entry(&mut self.reader, offset)?;
+        self.entries_read += 1;
+        Ok(Some((entry, self.reader.crc.sum())))
+    }
+
+    fn read_trailer(&mut self) -> Result<()> {
+        let checksum = Hash(self.reader.sha.clone().finalize().to_vec());
+        let mut trailer = [0; 20];
+        read_exact(&mut self.reader, &mut trailer)?;
+        if checksum.0 != trailer {
+            return Err(PackFileError::ErrChecksumMismatch.into());
+        }
+        if let Some(err) = self.reader.copy_error.take() {
+            return Err(err.into());
+        }
+        self.reader.copy.flush()?;
+        self.checksum = Some(checksum);
+        Ok(())
+    }
+
+    /// the checksum the pack ends with, once it was read and verified
+    pub fn checksum(&self) -> Option<&Hash> {
+        self.checksum.as_ref()
+    }
+
+    /// the number of bytes read so far
+    pub fn offset(&self) -> u64 {
+        self.reader.offset
+    }
+}
+
+/// reads the entry starting at offset: its header, the base of a delta, then its
+/// zlib-compressed data. only the bytes of the entry are consumed from the reader.
+pub fn read_entry<R: BufRead>(reader: &mut R, offset: u64) -> Result<PackEntry> {
+    let first = read_byte(reader)?;
+    let object_type = PackObjectType::try_from((first & 0b0111_0000) >> 4)?;
+    let mut size = (first & 0b0000_1111) as u64;
+    let mut b = first;
+    let mut shift = 4;
+    while b & (1 << 7) != 0 {
+        if shift > 60 {
+            return Err(PackFileError::ErrInvalidPackObjectLength.into());
+        }
+        b = read_byte(reader)?;
+        size |= ((b & 0b0111_1111) as u64) << shift;
+        shift += 7;
+    }
+    let size = usize::try_from(size)?;
+
+    let kind = match object_type.object_kind() {
+        Some(kind) => EntryKind::Object(kind),
+        None if object_type == PackObjectType::OfsDelta => {
+            let mut offset_bytes = Vec::new();
+            loop {
+                let b = read_byte(reader)?;
+                offset_bytes.push(b);
+                if b & (1 << 7) == 0 || offset_bytes.len() > 10 {
+                    break;
+                }
+            }
+            let relative = PackObject::read_offset(&mut Bytes::from(offset_bytes))?;
+            let base_offset =
+                offset
+                    .checked_sub(relative)
+                    .ok_or(PackFileError::ErrOffsetDeltaBaseObject(format!(
+                        "offset {} points before the start of the pack",
+                        relative
+                    )))?;
+            EntryKind::OfsDelta(base_offset)
+        }
+        None => {
+            let mut base = [0; 20];
+            read_exact(reader, &mut base)?;
+            EntryKind::RefDelta(Hash(base.to_vec()))
+        }
+    };
+
+    let mut data = Vec::with_capacity(size);
+    let mut decoder = ZlibDecoder::new(reader);
+    decoder.read_to_end(&mut data).map_err(truncated)?;
+    if data.len() != size {
+        return Err(PackFileError::ErrPackObjectLengthMistmatch.into());
+    }
+
+    Ok(PackEntry { offset, kind, data })
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0];
+    read_exact(reader, &mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(truncated)?;
+    Ok(())
+}
+
+/// turns the end of the stream in the middle of an entry into a truncated pack error
+fn truncated(err: io::Error) -> anyhow::Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => PackFileError::ErrTruncated.into(),
+        _ => err.into(),
+    }
+}
+
+/// a buffered reader that accounts for bytes when they are consumed rather than when
+/// they are read from the stream, so that the pack parser, and zlib, don't count the
+/// bytes of the next entry in the current one
+struct CountingReader<R: Read, W: Write> {
+    inner: R,
+    buffer: Box<[u8]>,
+    position: usize,
+    filled: usize,
+    /// bytes consumed so far
+    offset: u64,
+    sha: Sha1,
+    crc: Crc,
+    copy: W,
+    /// the first error writing the copy, reported once the pack is read
+    copy_error: Option<io::Error>,
+}
+
+impl<R: Read, W: Write> Read for CountingReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read, W: Write> BufRead for CountingReader<R, W> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.filled {
+            self.filled = self.inner.read(&mut self.buffer)?;
+            self.position = 0;
+        }
+        Ok(&self.buffer[self.position..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        let consumed = &self.buffer[self.position..self.position + amount];
+        self.sha.update(consumed);
+        self.crc.update(consumed);
+        if self.copy_error.is_none() {
+            if let Err(err) = self.copy.write_all(consumed) {
+                self.copy_error = Some(err);
+            }
+        }
+        self.offset += amount as u64;
+        self.position += amount;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::{
+        objects::{hash::hash, ObjectKind},
+        pack_protocol::pack_file::PackFileError,
+    };
+
+    use super::{EntryKind, PackReader};
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_pack_reader() {
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        pack.push(0b0011_1011);
+        pack.extend(compress(b"hello world"));
+        let delta_offset = pack.len() as u64;
+        pack.push(0b0110_0100);
+        pack.push((delta_offset - 12) as u8);
+        pack.extend(compress(&[11, 3, 0b1001_0000, 3]));
+        let checksum = hash(&pack);
+        pack.extend(&checksum.0);
+
+        let mut copy = Vec::new();
+        let mut reader = PackReader::with_copy(&pack[..], &mut copy).unwrap();
+        assert_eq!(reader.object_count(), 2);
+
+        let (blob, _) = reader.next_entry().unwrap().unwrap();
+        assert_eq!(blob.offset, 12);
+        assert_eq!(blob.kind, EntryKind::Object(ObjectKind::Blob));
+        assert_eq!(blob.data, b"hello world");
+
+        // the compressed data is followed by the next entry, which must not be consumed
+        let (delta, crc) = reader.next_entry().unwrap().unwrap();
+        assert_eq!(delta.offset, delta_offset);
+        assert_eq!(delta.kind, EntryKind::OfsDelta(12));
+        let mut expected = flate2::Crc::new();
+        expected.update(&pack[delta_offset as usize..pack.len() - 20]);
+        assert_eq!(crc, expected.sum());
+
+        assert!(reader.next_entry().unwrap().is_none());
+        assert_eq!(reader.checksum(), Some(&checksum));
+        drop(reader);
+        assert_eq!(copy, pack);
+
+        let last = pack.len() - 1;
+        pack[last] ^= 1;
+        let mut reader = PackReader::new(&pack[..]).unwrap();
+        reader.next_entry().unwrap();
+        reader.next_entry().unwrap();
+        let err = reader.next_entry().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PackFileError>(),
+            Some(PackFileError::ErrChecksumMismatch)
+        ));
+    }
+}
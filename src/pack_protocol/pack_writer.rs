@@ -0,0 +1,462 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, Result};
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use sha1::{Digest, Sha1};
+
+use crate::objects::{hash::Hash, pack::read_packed_delta, Object};
+
+use super::{
+    delta::{encode_delta, DeltaIndex},
+    pack_index::{PackIndex, PackIndexEntry},
+    pack_object::{entry_header, PackObjectType},
+};
+
+/// objects smaller than this are always stored whole
+const MIN_DELTA_SIZE: usize = 50;
+
+/// objects larger than this are always stored whole, as git's core.bigFileThreshold
+const BIG_FILE_THRESHOLD: usize = 512 << 20;
+
+#[derive(Debug, Clone)]
+pub struct PackWriterOptions {
+    /// how many objects are tried as the delta base of each object, the object included
+    pub window: usize,
+    /// the most deltas that may be applied to rebuild an object
+    pub depth: usize,
+    /// whether deltas point to their bases by offset rather than by hash
+    pub ofs_delta: bool,
+    /// whether objects stored as deltas in the packs of the repository are copied as they
+    /// are, when their bases are written too
+    pub reuse_deltas: bool,
+}
+
+impl Default for PackWriterOptions {
+    fn default() -> Self {
+        PackWriterOptions {
+            window: 10,
+            depth: 50,
+            ofs_delta: true,
+            reuse_deltas: true,
+        }
+    }
+}
+
+/// an object to write
+struct ToPack {
+    hash: Hash,
+    object: Object,
+    /// a hash of the path the object was found at
+    name_hash: u32,
+    /// the object the delta applies to, and the delta
+    delta: Option<(usize, Vec<u8>)>,
+    /// the number of deltas to apply to rebuild the object
+    depth: usize,
+    /// where the object was written, once it was
+    offset: Option<u64>,
+}
+
+/// writes version 2 packs. objects are stored as deltas against similar objects when that
+/// makes them smaller: as git does, objects are sorted by kind, path and size, and each
+/// one is tried against the objects just before it.
+pub struct PackWriter {
+    options: PackWriterOptions,
+    objects: Vec<ToPack>,
+    by_hash: HashMap<Hash, usize>,
+}
+
+impl PackWriter {
+    pub fn new(options: PackWriterOptions) -> PackWriter {
+        PackWriter {
+            options,
+            objects: Vec::new(),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    /// adds an object, with the path it was found at if any, since objects at the same
+    /// path tend to make good deltas of one another. objects are written in the order
+    /// they are added, except that delta bases come first.
+    pub fn add(&mut self, object: Object, name: Option<&str>) -> Result<()> {
+        let hash = object.hash()?;
+        if self.by_hash.contains_key(&hash) {
+            return Ok(());
+        }
+        self.by_hash.insert(hash.clone(), self.objects.len());
+        self.objects.push(ToPack {
+            hash,
+            object,
+            name_hash: name.map(name_hash).unwrap_or(0),
+            delta: None,
+            depth: 0,
+            offset: None,
+        });
+        Ok(())
+    }
+
+    /// the number of objects added
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// writes the pack, and returns its index
+    pub fn write<W: Write>(mut self, writer: W) -> Result<PackIndex> {
+        if self.options.reuse_deltas {
+            self.reuse_deltas()?;
+        }
+        self.find_deltas();
+        self.break_delta_chains();
+
+        let count = u32::try_from(self.objects.len())
+            .map_err(|_| anyhow!("too many objects for a pack: {}", self.objects.len()))?;
+        let mut out = PackOutput {
+            inner: writer,
+            sha: Sha1::new(),
+            crc: Crc::new(),
+            offset: 0,
+        };
+        out.write_all(b"PACK")?;
+        out.write_all(&2u32.to_be_bytes())?;
+        out.write_all(&count.to_be_bytes())?;
+
+        let mut entries = Vec::with_capacity(self.objects.len());
+        for i in 0..self.objects.len() {
+            // offsets only point back, so the bases of a delta are written before it
+            let mut chain = Vec::new();
+            let mut current = Some(i);
+            while let Some(j) = current.filter(|j| self.objects[*j].offset.is_none()) {
+                chain.push(j);
+                current = self.objects[j].delta.as_ref().map(|(base, _)| *base);
+            }
+            for j in chain.into_iter().rev() {
+                entries.push(self.write_entry(&mut out, j)?);
+            }
+        }
+
+        let checksum = Hash(out.sha.clone().finalize().to_vec());
+        out.inner.write_all(&checksum.0)?;
+        out.inner.flush()?;
+        Ok(PackIndex::new(entries, checksum))
+    }
+
+    /// takes the deltas the packs of the repository store objects as, when their bases
+    /// are written too
+    fn reuse_deltas(&mut self) -> Result<()> {
+        for i in 0..self.objects.len() {
+            if let Some((base, delta)) = read_packed_delta(&self.objects[i].hash)? {
+                if let Some(&base) = self.by_hash.get(&base) {
+                    self.objects[i].delta = Some((base, delta));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// finds deltas for the objects that have none. each object is tried against the
+    /// objects before it in the window, larger ones of the same kind and path first, and
+    /// the smallest delta is kept.
+    fn find_deltas(&mut self) {
+        let mut order: Vec<usize> = (0..self.objects.len())
+            .filter(|i| {
+                let size = self.objects[*i].object.data.len();
+                self.objects[*i].delta.is_none()
+                    && (MIN_DELTA_SIZE..=BIG_FILE_THRESHOLD).contains(&size)
+            })
+            .collect();
+        order.sort_by_key(|i| {
+            let entry = &self.objects[*i];
+            (
+                Reverse(PackObjectType::from(entry.object.kind).code()),
+                Reverse(entry.name_hash),
+                Reverse(entry.object.data.len()),
+                *i,
+            )
+        });
+
+        // the objects of the window are indexed once, when they are targets, and tried as
+        // bases of the objects after them
+        let candidates = self.options.window.saturating_sub(1);
+        let mut window: VecDeque<(usize, DeltaIndex)> = VecDeque::with_capacity(candidates);
+        for &target in &order {
+            for (base, index) in window.iter().rev() {
+                if self.objects[*base].object.kind != self.objects[target].object.kind {
+                    break;
+                }
+                if let Some(delta) = self.try_delta(target, *base, index) {
+                    self.objects[target].depth = self.objects[*base].depth + 1;
+                    self.objects[target].delta = Some((*base, delta));
+                }
+            }
+            if candidates > 0 {
+                if window.len() == candidates {
+                    window.pop_front();
+                }
+                window.push_back((target, DeltaIndex::new(&self.objects[target].object.data)));
+            }
+        }
+    }
+
+    /// computes the delta of target against base, if it is worth keeping: smaller than
+    /// half the object, and than the delta target already has. the deeper the base, the
+    /// smaller the delta must be.
+    fn try_delta(&self, target: usize, base: usize, index: &DeltaIndex) -> Option<Vec<u8>> {
+        let (target, base) = (&self.objects[target], &self.objects[base]);
+        let max_depth = self.options.depth;
+        if base.depth >= max_depth {
+            return None;
+        }
+
+        let target_size = target.object.data.len();
+        let base_size = base.object.data.len();
+        let (max_size, target_depth) = match &target.delta {
+            Some((_, delta)) => (delta.len(), target.depth),
+            None => ((target_size / 2).saturating_sub(20), 1),
+        };
+        let max_size = max_size * (max_depth - base.depth) / (max_depth - target_depth + 1);
+        if max_size == 0 || target_size.saturating_sub(base_size) >= max_size {
+            return None;
+        }
+        if target_size < base_size / 32 {
+            return None;
+        }
+
+        let instructions =
+            index.create_delta(&base.object.data, &target.object.data, Some(max_size))?;
+        let delta = encode_delta(base_size, target_size, &instructions);
+        if let Some((_, current)) = &target.delta {
+            if delta.len() == current.len() && base.depth + 1 >= target.depth {
+                return None;
+            }
+        }
+        Some(delta)
+    }
+
+    /// stores whole the objects whose chains of deltas are too long, or loop, as deltas
+    /// reused from different packs may
+    fn break_delta_chains(&mut self) {
+        let mut depths: Vec<Option<usize>> = vec![None; self.objects.len()];
+        let mut on_chain = vec![false; self.objects.len()];
+        for i in 0..self.objects.len() {
+            // follows the bases up to one whose depth is known, or to an object stored whole
+            let mut chain = Vec::new();
+            let mut current = i;
+            while depths[current].is_none() {
+                chain.push(current);
+                on_chain[current] = true;
+                match self.objects[current].delta.as_ref().map(|(base, _)| *base) {
+                    Some(base) if on_chain[base] => {
+                        self.objects[current].delta = None;
+                        break;
+                    }
+                    Some(base) => current = base,
+                    None => break,
+                }
+            }
+
+            for j in chain.into_iter().rev() {
+                on_chain[j] = false;
+                let depth = match &self.objects[j].delta {
+                    Some((base, _)) => depths[*base].expect("bases come first") + 1,
+                    None => 0,
+                };
+                if depth > self.options.depth {
+                    self.objects[j].delta = None;
+                    depths[j] = Some(0);
+                } else {
+                    depths[j] = Some(depth);
+                }
+                self.objects[j].depth = depths[j].expect("just set");
+            }
+        }
+    }
+
+    fn write_entry<W: Write>(
+        &mut self,
+        out: &mut PackOutput<W>,
+        i: usize,
+    ) -> Result<PackIndexEntry> {
+        let offset = out.offset;
+        out.crc.reset();
+
+        let entry = &self.objects[i];
+        let data = match &entry.delta {
+            Some((base, delta)) => {
+                let base = &self.objects[*base];
+                if self.options.ofs_delta {
+                    let base_offset = base.offset.expect("bases are written first");
+                    out.write_all(&entry_header(PackObjectType::OfsDelta, delta.len() as u64))?;
+                    out.write_all(&encode_offset(offset - base_offset))?;
+                } else {
+                    out.write_all(&entry_header(PackObjectType::RefDelta, delta.len() as u64))?;
+                    out.write_all(&base.hash.0)?;
+                }
+                delta
+            }
+            None => {
+                let data = &entry.object.data;
+                out.write_all(&entry_header(entry.object.kind.into(), data.len() as u64))?;
+                data
+            }
+        };
+        let mut encoder = ZlibEncoder::new(&mut *out, Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()?;
+
+        self.objects[i].offset = Some(offset);
+        Ok(PackIndexEntry {
+            hash: self.objects[i].hash.clone(),
+            crc32: out.crc.sum(),
+            offset,
+        })
+    }
+}
+
+/// hashes the path an object was found at, so that objects at the same path, or at paths
+/// ending alike, sort together. the last characters count the most.
+fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .fold(0, |hash: u32, c| (hash >> 2).wrapping_add((c as u32) << 24))
+}
+
+/// encodes the distance back to the base of an ofs-delta: 7 bits per byte, most
+/// significant group first, with each continuation adding one
+fn encode_offset(mut offset: u64) -> Vec<u8> {
+    let mut bytes = vec![(offset & 0b0111_1111) as u8];
+    offset >>= 7;
+    while offset > 0 {
+        offset -= 1;
+        bytes.push((1 << 7) | (offset & 0b0111_1111) as u8);
+        offset >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// the stream a pack is written to, hashing it as it goes
+struct PackOutput<W: Write> {
+    inner: W,
+    sha: Sha1,
+    /// the CRC32 of the entry being written
+    crc: Crc,
+    offset: u64,
+}
+
+impl<W: Write> Write for PackOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sha.update(&buf[..n]);
+        self.crc.update(&buf[..n]);
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::{
+        objects::{Object, ObjectKind},
+        pack_protocol::{
+            pack_object::{apply_delta, PackObject},
+            pack_reader::{EntryKind, PackReader},
+        },
+    };
+
+    use super::{encode_offset, PackWriter, PackWriterOptions};
+
+    fn blob(data: String) -> Object {
+        Object {
+            data: data.into_bytes(),
+            kind: ObjectKind::Blob,
+        }
+    }
+
+    #[test]
+    fn test_encode_offset() {
+        for offset in [0, 127, 128, 300, 16511, 16512, 1 << 40] {
+            let encoded = encode_offset(offset);
+            assert_eq!(
+                PackObject::read_offset(&mut Bytes::from(encoded)).unwrap(),
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn test_pack_writer() {
+        let lines: Vec<String> = (0..40).map(|i| format!("line {}\n", i)).collect();
+        let first = lines.concat();
+        let second = lines[..30].concat() + "changed\n" + &lines[30..].concat();
+        let third = lines[5..].concat();
+        let small = "small".to_string();
+
+        let options = PackWriterOptions {
+            depth: 1,
+            reuse_deltas: false,
+            ..Default::default()
+        };
+        let mut writer = PackWriter::new(options);
+        for object in [&small, &third, &second, &first, &second] {
+            writer.add(blob(object.clone()), Some("file")).unwrap();
+        }
+        assert_eq!(writer.object_count(), 4);
+        let mut pack = Vec::new();
+        let index = writer.write(&mut pack).unwrap();
+
+        let mut reader = PackReader::new(&pack[..]).unwrap();
+        assert_eq!(reader.object_count(), 4);
+        let mut written = Vec::new();
+        let mut deltas = 0;
+        while let Some((entry, crc32)) = reader.next_entry().unwrap() {
+            let object = match entry.kind {
+                EntryKind::Object(kind) => Object {
+                    data: entry.data,
+                    kind,
+                },
+                EntryKind::OfsDelta(base_offset) => {
+                    deltas += 1;
+                    let base: &Object = written
+                        .iter()
+                        .find(|(offset, _)| *offset == base_offset)
+                        .map(|(_, object)| object)
+                        .unwrap();
+                    let (_, _, instructions) =
+                        PackObject::parse_delta(Bytes::from(entry.data)).unwrap();
+                    Object {
+                        data: apply_delta(&base.data, &instructions).unwrap(),
+                        kind: base.kind,
+                    }
+                }
+                EntryKind::RefDelta(_) => panic!("deltas use offsets"),
+            };
+            let hash = object.hash().unwrap();
+            assert_eq!(index.find(&hash), Some(entry.offset));
+            let indexed = index.entries().iter().find(|e| e.hash == hash).unwrap();
+            assert_eq!(indexed.crc32, crc32);
+            written.push((entry.offset, object));
+        }
+        assert_eq!(reader.checksum(), Some(&index.pack_checksum));
+
+        // the largest blob is the base of the others, which can't be bases with depth 1
+        assert_eq!(deltas, 2);
+        let hashes: Vec<_> = written
+            .iter()
+            .map(|(_, object)| object.hash().unwrap())
+            .collect();
+        let expected: Vec<_> = [&small, &second, &third, &first]
+            .iter()
+            .map(|object| blob(object.to_string()).hash().unwrap())
+            .collect();
+        assert_eq!(hashes, expected);
+    }
+}
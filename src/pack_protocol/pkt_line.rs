@@ -1,6 +1,13 @@
-use std::{error::Error, fmt::Display};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
+
+/// largest pkt-line, length bytes included
+pub const MAX_PKT_LINE_LENGTH: usize = 65520;
 
 pub struct PktLine(String);
 
@@ -25,6 +32,163 @@ impl PktLine {
     }
 }
 
+/// a packet of the protocol: data, or one of the special packets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// "0000", ends a message
+    Flush,
+    /// "0001", separates the sections of a message
+    Delim,
+    /// "0002", ends a response of a stateless connection
+    ResponseEnd,
+    Data(Vec<u8>),
+}
+
+/// writes data as one pkt-line
+pub fn write_packet<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    if data.len() + 4 > MAX_PKT_LINE_LENGTH {
+        return Err(PktLineError::ErrLineLengthBytes(format!(
+            "line length must not exceed {}, {} was found",
+            MAX_PKT_LINE_LENGTH,
+            data.len() + 4
+        ))
+        .into());
+    }
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// writes a text line as one pkt-line, adding the LF
+pub fn write_line<W: Write>(writer: &mut W, line: &str) -> Result<()> {
+    write_packet(writer, format!("{}\n", line).as_bytes())
+}
+
+pub fn write_flush<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(b"0000")?;
+    Ok(())
+}
+
+/// reads pkt-lines from a stream
+pub struct PktLineReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(reader: R) -> PktLineReader<R> {
+        PktLineReader { reader }
+    }
+
+    /// reads the next packet. a data packet starting with "ERR " is turned into an error.
+    pub fn read_packet(&mut self) -> Result<Packet> {
+        let mut length_bytes = [0; 4];
+        self.reader.read_exact(&mut length_bytes).map_err(|err| {
+            PktLineError::ErrLineLengthBytes(format!("failed to read length bytes: {}", err))
+        })?;
+        let length_str = std::str::from_utf8(&length_bytes)
+            .map_err(|_| PktLineError::ErrLineLengthBytes("length is not hex".to_string()))?;
+        let length = usize::from_str_radix(length_str, 16).map_err(|_| {
+            PktLineError::ErrLineLengthBytes(format!("'{}' is not hex", length_str))
+        })?;
+
+        match length {
+            0 => return Ok(Packet::Flush),
+            1 => return Ok(Packet::Delim),
+            2 => return Ok(Packet::ResponseEnd),
+            3 => {
+                return Err(PktLineError::ErrLineLengthBytes("invalid length 3".to_string()).into())
+            }
+            length if length > MAX_PKT_LINE_LENGTH => {
+                return Err(PktLineError::ErrLineLengthBytes(format!(
+                    "line length must not exceed {}, {} was found",
+                    MAX_PKT_LINE_LENGTH, length
+                ))
+                .into())
+            }
+            _ => {}
+        }
+
+        let mut data = vec![0; length - 4];
+        self.reader.read_exact(&mut data).map_err(|err| {
+            PktLineError::ErrLineLengthBytes(format!(
+                "line length {} was expected: {}",
+                length, err
+            ))
+        })?;
+        if let Some(message) = data.strip_prefix(b"ERR ") {
+            return Err(anyhow!(
+                "remote error: {}",
+                String::from_utf8_lossy(message).trim_end()
+            ));
+        }
+        Ok(Packet::Data(data))
+    }
+
+    /// reads the next packet as a line of text without its LF, or None for a flush-pkt
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        match self.read_packet()? {
+            Packet::Data(data) => {
+                let line = String::from_utf8(data)
+                    .map_err(|_| anyhow!("protocol error: line is not UTF-8"))?;
+                Ok(Some(line.strip_suffix('\n').unwrap_or(&line).to_string()))
+            }
+            Packet::Flush => Ok(None),
+            packet => Err(anyhow!("protocol error: unexpected {:?}", packet)),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// writes a version 0 or 1 ref advertisement, as parsed by [`AdvertisedRefsParser`]: the
+/// capabilities follow the first ref, or a no-refs line when there are no refs
+pub fn write_advertised_refs<W: Write>(
+    writer: &mut W,
+    version: u8,
+    refs: &[Ref],
+    capabilities: &[Capability],
+) -> Result<()> {
+    if version == 1 {
+        write_line(writer, "version 1")?;
+    }
+
+    let zero_id = "0".repeat(40);
+    let mut first = true;
+    let tips = refs.iter().filter(|r| !matches!(r, Ref::Shallow { .. }));
+    for r in tips {
+        let line = match r {
+            Ref::Tip { name, object_id } => format!("{} {}", object_id, name),
+            Ref::Peeled { name, object_id } => format!("{} {}^{{}}", object_id, name),
+            Ref::Shallow { .. } => unreachable!("shallows are written last"),
+        };
+        match first {
+            true => write_line(writer, &format!("{}\0{}", line, capabilities.join(" ")))?,
+            false => write_line(writer, &line)?,
+        }
+        first = false;
+    }
+    if first {
+        write_line(
+            writer,
+            &format!("{} capabilities^{{}}\0{}", zero_id, capabilities.join(" ")),
+        )?;
+    }
+
+    for r in refs {
+        if let Ref::Shallow { object_id } = r {
+            write_line(writer, &format!("shallow {}", object_id))?;
+        }
+    }
+    write_flush(writer)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Ref {
     Tip { name: String, object_id: String },
     Peeled { name: String, object_id: String },
@@ -69,53 +233,87 @@ impl Display for PktLineError {
     }
 }
 
-pub struct AdvertisedRefsParser {
-    data: String,
+pub struct AdvertisedRefsParser<'a, R: Read> {
+    reader: &'a mut PktLineReader<R>,
+    peeked: Option<Packet>,
     version: u8,
 }
 
-impl AdvertisedRefsParser {
-    fn peeker<T>(&mut self, f: impl Fn(&str) -> Result<T>) -> Result<T> {
-        let line = self.peek_pkt_line()?;
-        let line_length = line.len() + 4; // including 4 length bytes
+impl<'a, R: Read> AdvertisedRefsParser<'a, R> {
+    pub fn new(reader: &'a mut PktLineReader<R>) -> AdvertisedRefsParser<'a, R> {
+        AdvertisedRefsParser {
+            reader,
+            peeked: None,
+            version: 1,
+        }
+    }
 
-        let res = f(line.trim())?;
-        self.data = self.data.split_off(line_length);
+    /// parses the next line with f, and only consumes it if f succeeds
+    fn peeker<T>(&mut self, f: impl Fn(&str) -> Result<T>) -> Result<T> {
+        let res = match self.peek_packet()? {
+            Packet::Data(data) => {
+                let line = std::str::from_utf8(data)
+                    .map_err(|_| PktLineError::ErrInvalidRef("line is not UTF-8".to_string()))?;
+                f(line.strip_suffix('\n').unwrap_or(line))?
+            }
+            packet => {
+                return Err(PktLineError::ErrInvalidRef(format!("unexpected {:?}", packet)).into())
+            }
+        };
+        self.peeked = None;
 
         Ok(res)
     }
 
-    /// returns next line without modifying the actual buffer
-    fn peek_pkt_line(&mut self) -> Result<&str> {
-        let (length_str, rest) = self.data.split_at(4);
-        if length_str.len() != 4 {
-            bail!(anyhow!(PktLineError::ErrLineLengthBytes(format!(
-                "length bytes should be 4 but {} were found",
-                length_str.len()
-            ))))
+    /// returns the next packet without consuming it
+    fn peek_packet(&mut self) -> Result<&Packet> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.reader.read_packet()?);
         }
+        Ok(self.peeked.as_ref().expect("a packet was peeked"))
+    }
+
+    fn peek_is_flush(&mut self) -> Result<bool> {
+        Ok(*self.peek_packet()? == Packet::Flush)
+    }
 
-        let length = usize::from_str_radix(length_str, 16)?;
-        if length > 65520 {
-            bail!(anyhow!(PktLineError::ErrLineLengthBytes(format!(
-                "line length must not exceed 65520, {} was found",
-                length
-            ))))
+    /// reads the version line a remote starts with. version 0 has none, so nothing is
+    /// consumed and 0 is returned.
+    pub fn parse_version(&mut self) -> Result<u8> {
+        let version = match self.peeker(Self::parse_version_line) {
+            Ok(version) => version,
+            Err(_) => return Ok(0),
+        };
+        if !(1..=2).contains(&version) {
+            return Err(
+                PktLineError::ErrVersion(format!("unsupported version {}", version)).into(),
+            );
         }
+        Ok(version)
+    }
 
-        let (line, _) = rest.split_at(length - 4);
-        if line.len() != length - 4 {
-            bail!(anyhow!(PktLineError::ErrLineLengthBytes(format!(
-                "line length {} was expected, but {} was found",
-                length,
-                line.len() + 4
-            ))))
+    /// parses the capabilities a version 2 remote advertises after its version line, one
+    /// per line up to a flush-pkt. values, as in "fetch=shallow wait-for-done", may hold
+    /// spaces.
+    pub fn parse_capability_advertisement(&mut self) -> Result<Vec<Capability>> {
+        let mut capabilities = Vec::new();
+        while !self.peek_is_flush()? {
+            let capability = self.peeker(|line| Ok(line.to_string()))?;
+            Self::validate_capabilities(std::slice::from_ref(&capability))?;
+            capabilities.push(capability);
         }
+        self.peeked = None;
 
-        Ok(line)
+        if capabilities.is_empty() {
+            return Err(PktLineError::ErrInvalidCapability(
+                "version 2 advertisement without capabilities".to_string(),
+            )
+            .into());
+        }
+        Ok(capabilities)
     }
 
-    pub fn parse_advertised_refs(&mut self) -> Result<(Vec<Ref>, Vec<Capability>, Option<Ref>)> {
+    pub fn parse_advertised_refs(&mut self) -> Result<(Vec<Ref>, Vec<Capability>, Vec<Ref>)> {
         /*
             *1("version 1")
             (no-refs / list-of-refs)
@@ -123,18 +321,26 @@ impl AdvertisedRefsParser {
             flush-pkt
         */
         let mut refs = Vec::new();
-        let capabilities: Vec<Capability>;
-        let mut shallow: Option<Ref> = None;
-        // let (mut refs, mut capabilities, mut shallow) = (Vec::new(), Vec::new(), None);
-        // parse version
-        let version = self.peeker(Self::parse_version_line)?;
-        if version != self.version {
-            return Err(PktLineError::ErrVersion(format!(
-                "only supported version is {}",
-                self.version
-            ))
-            .into());
+        let mut capabilities = Vec::new();
+        let mut shallows = Vec::new();
+
+        // version 0 has no version line
+        if let Ok(version) = self.peeker(Self::parse_version_line) {
+            if version != self.version {
+                return Err(PktLineError::ErrVersion(format!(
+                    "only supported version is {}",
+                    self.version
+                ))
+                .into());
+            }
         }
+
+        // an empty repository may advertise nothing at all
+        if self.peek_is_flush()? {
+            self.peeked = None;
+            return Ok((refs, capabilities, shallows));
+        }
+
         // try parse no refs. if failed, try parse refs
         if let Ok(caps) = self.peeker(Self::parse_no_refs_line) {
             capabilities = caps
@@ -142,14 +348,13 @@ impl AdvertisedRefsParser {
             (refs, capabilities) = self.parse_list_of_refs()?;
         }
 
-        // try parse shallow
-        if let Ok(shall) = self.peeker(Self::parse_shallow_line) {
-            shallow = Some(shall)
+        while let Ok(shallow) = self.peeker(Self::parse_shallow_line) {
+            shallows.push(shallow);
         }
 
-        self.peeker(Self::validate_flush_pkt)?;
+        self.validate_flush_pkt()?;
 
-        Ok((refs, capabilities, shallow))
+        Ok((refs, capabilities, shallows))
     }
 
     fn parse_version_line(line: &str) -> Result<u8> {
@@ -161,7 +366,8 @@ impl AdvertisedRefsParser {
             return Err(PktLineError::ErrVersion("missing version string".to_string()).into());
         }
 
-        let version = u8::from_str_radix(version_number, 10)
+        let version = version_number
+            .parse::<u8>()
             .map_err(|err| PktLineError::ErrVersion(format!("invalid number: {}", err)))?;
 
         Ok(version)
@@ -218,10 +424,14 @@ impl AdvertisedRefsParser {
         let (first_ref, capabilities) = self.peeker(Self::parse_first_ref)?;
         let mut other_refs = Vec::new();
         loop {
-            let line = self.peek_pkt_line()?;
-            if line.starts_with("0000") || line.starts_with("shallow") {
+            if self.peek_is_flush()? {
                 break;
             }
+            if let Packet::Data(line) = self.peek_packet()? {
+                if line.starts_with(b"shallow ") {
+                    break;
+                }
+            }
 
             let other_ref = self.peeker(Self::parse_other_ref)?;
             other_refs.push(other_ref);
@@ -286,18 +496,16 @@ impl AdvertisedRefsParser {
             .into());
         }
 
-        let ret: Ref;
-        if refname.ends_with("^{}") {
-            ret = Ref::Peeled {
-                name: refname.trim_end_matches("^{}").to_string(),
+        let ret = match refname.strip_suffix("^{}") {
+            Some(name) => Ref::Peeled {
+                name: name.to_string(),
                 object_id: object_id.to_string(),
-            }
-        } else {
-            ret = Ref::Tip {
+            },
+            None => Ref::Tip {
                 name: refname.to_string(),
                 object_id: object_id.to_string(),
-            }
-        }
+            },
+        };
 
         Ok(ret)
     }
@@ -326,43 +534,137 @@ impl AdvertisedRefsParser {
             .map(|s| s.to_string())
             .collect::<Vec<Capability>>();
 
-        Self::validate_capabilities(&capabilities);
+        Self::validate_capabilities(&capabilities)?;
 
         Ok(capabilities)
     }
 
-    fn validate_capabilities(caps: &Vec<Capability>) -> Result<()> {
-        caps.iter().map(|cap| -> Result<()> {
-            if cap.len() == 0 {
+    /// checks the names of capabilities, the part before any '=' value
+    fn validate_capabilities(caps: &[Capability]) -> Result<()> {
+        for cap in caps {
+            let name = cap.split_once('=').map_or(cap.as_str(), |(name, _)| name);
+            if name.is_empty() {
                 return Err(PktLineError::ErrInvalidCapability(
                     "invalid empty capability".to_string(),
                 )
                 .into());
             }
 
-            cap.as_bytes().iter().map(|c| -> Result<()> {
-                if c.is_ascii_lowercase() || c.is_ascii_digit() || *c == b'-' || *c == b'_' {
-                    return Ok(());
-                }
-
+            if let Some(c) = name
+                .chars()
+                .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-' || *c == '_'))
+            {
                 return Err(PktLineError::ErrInvalidCapability(format!(
                     "invalid capability character: {}",
                     c
                 ))
                 .into());
-            });
-
-            Ok(())
-        });
+            }
+        }
 
         Ok(())
     }
 
-    fn validate_flush_pkt(line: &str) -> Result<()> {
-        if line != "0000" {
+    fn validate_flush_pkt(&mut self) -> Result<()> {
+        if !self.peek_is_flush()? {
             return Err(PktLineError::ErrInvalidFlushPkt.into());
         }
+        self.peeked = None;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{write_advertised_refs, AdvertisedRefsParser, PktLineReader, Ref};
+
+    #[test]
+    fn test_parse_advertised_refs() {
+        let id = "1".repeat(40);
+        let mut data = Vec::new();
+        let mut line = |content: String| {
+            data.extend(format!("{:04x}{}", content.len() + 4, content).into_bytes())
+        };
+        line(format!(
+            "{} HEAD\0multi_ack_detailed agent=git/2.39.5\n",
+            id
+        ));
+        line(format!("{} refs/tags/v1\n", id));
+        line(format!("{} refs/tags/v1^{{}}\n", id));
+        data.extend(b"0000PACK");
+
+        let mut reader = PktLineReader::new(&data[..]);
+        let (refs, capabilities, shallows) = AdvertisedRefsParser::new(&mut reader)
+            .parse_advertised_refs()
+            .unwrap();
+
+        assert_eq!(capabilities, vec!["multi_ack_detailed", "agent=git/2.39.5"]);
+        assert_eq!(refs.len(), 3);
+        assert_eq!(
+            refs[2],
+            Ref::Peeled {
+                name: "refs/tags/v1".to_string(),
+                object_id: id
+            }
+        );
+        assert!(shallows.is_empty());
+        assert_eq!(reader.into_inner(), b"PACK");
+    }
+
+    #[test]
+    fn test_parse_capability_advertisement() {
+        let data = b"000eversion 2\n0013ls-refs=unborn\n0020fetch=shallow wait-for-done\n0000";
+        let mut reader = PktLineReader::new(&data[..]);
+        let mut parser = AdvertisedRefsParser::new(&mut reader);
+        assert_eq!(parser.parse_version().unwrap(), 2);
+        assert_eq!(
+            parser.parse_capability_advertisement().unwrap(),
+            vec!["ls-refs=unborn", "fetch=shallow wait-for-done"]
+        );
+
+        let data = b"000eversion 3\n0000";
+        let mut reader = PktLineReader::new(&data[..]);
+        assert!(AdvertisedRefsParser::new(&mut reader)
+            .parse_version()
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_advertised_refs() {
+        let id = "1".repeat(40);
+        let refs = vec![
+            Ref::Tip {
+                name: "HEAD".to_string(),
+                object_id: id.clone(),
+            },
+            Ref::Tip {
+                name: "refs/tags/v1".to_string(),
+                object_id: id.clone(),
+            },
+            Ref::Peeled {
+                name: "refs/tags/v1".to_string(),
+                object_id: id.clone(),
+            },
+        ];
+        let capabilities = vec!["ofs-delta".to_string(), "agent=mgit/0.1".to_string()];
+
+        let mut data = Vec::new();
+        write_advertised_refs(&mut data, 1, &refs, &capabilities).unwrap();
+        let mut reader = PktLineReader::new(&data[..]);
+        let mut parser = AdvertisedRefsParser::new(&mut reader);
+        assert_eq!(parser.parse_version().unwrap(), 1);
+        let parsed = parser.parse_advertised_refs().unwrap();
+        assert_eq!(parsed, (refs, capabilities.clone(), Vec::new()));
+
+        let mut data = Vec::new();
+        write_advertised_refs(&mut data, 0, &[], &capabilities).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            format!(
+                "0056{} capabilities^{{}}\0ofs-delta agent=mgit/0.1\n0000",
+                "0".repeat(40)
+            )
+        );
+    }
+}
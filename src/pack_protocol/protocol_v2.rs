@@ -0,0 +1,139 @@
+use std::io::Read;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::objects::hash::Hash;
+
+use super::{
+    pkt_line::{write_flush, write_line, Packet, PktLineReader},
+    transport::{Advertisement, Transport},
+};
+
+/// builds a version 2 command request: the command and its capabilities, a delim-pkt,
+/// then the arguments of the command and a flush-pkt
+pub fn command_request(
+    advertisement: &Advertisement,
+    command: &str,
+    args: &[String],
+) -> Result<Vec<u8>> {
+    if !advertisement.has_capability(command) {
+        bail!("the remote does not support the '{}' command", command);
+    }
+
+    let mut request = Vec::new();
+    write_line(&mut request, &format!("command={}", command))?;
+    write_line(
+        &mut request,
+        &format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")),
+    )?;
+    if advertisement.has_capability("object-format") {
+        write_line(&mut request, "object-format=sha1")?;
+    }
+    request.extend_from_slice(b"0001");
+    for arg in args {
+        write_line(&mut request, arg)?;
+    }
+    write_flush(&mut request)?;
+    Ok(request)
+}
+
+/// lists the refs of a version 2 remote into its advertisement, with the targets of
+/// symbolic refs and the objects tags point to. only refs starting with one of the
+/// prefixes are listed, or all of them when there are none.
+pub fn ls_refs(
+    transport: &mut Transport,
+    advertisement: &mut Advertisement,
+    prefixes: &[String],
+) -> Result<()> {
+    let mut args = vec!["symrefs".to_string(), "peel".to_string()];
+    args.extend(
+        prefixes
+            .iter()
+            .map(|prefix| format!("ref-prefix {}", prefix)),
+    );
+    let request = command_request(advertisement, "ls-refs", &args)?;
+    transport.send(&request)?;
+
+    while let Some(line) = read_response_line(transport.reader())? {
+        let mut words = line.split(' ');
+        let (hash, name) = match (words.next(), words.next()) {
+            (Some(hash), Some(name)) => (Hash::try_from(hash.as_bytes())?, name.to_string()),
+            _ => bail!("protocol error: invalid ls-refs line '{}'", line),
+        };
+        for attribute in words {
+            if let Some(target) = attribute.strip_prefix("symref-target:") {
+                advertisement
+                    .symrefs
+                    .insert(name.clone(), target.to_string());
+            } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                advertisement
+                    .peeled
+                    .insert(name.clone(), Hash::try_from(peeled.as_bytes())?);
+            }
+        }
+        advertisement.refs.push((name, hash));
+    }
+    Ok(())
+}
+
+/// reads a line of a response, or None at its end: a flush-pkt, or the response-end-pkt
+/// a stateless connection may end it with
+pub fn read_response_line<R: Read>(reader: &mut PktLineReader<R>) -> Result<Option<String>> {
+    match reader.read_packet()? {
+        Packet::Data(data) => {
+            let line = String::from_utf8(data)
+                .map_err(|_| anyhow!("protocol error: line is not UTF-8"))?;
+            Ok(Some(line.strip_suffix('\n').unwrap_or(&line).to_string()))
+        }
+        Packet::Flush | Packet::ResponseEnd => Ok(None),
+        Packet::Delim => Err(anyhow!("protocol error: unexpected delim-pkt")),
+    }
+}
+
+/// reads the lines of a section of a fetch response, up to the delim-pkt that separates
+/// it from the next section. returns whether more sections follow.
+pub fn read_section<R: Read>(
+    reader: &mut PktLineReader<R>,
+    lines: &mut Vec<String>,
+) -> Result<bool> {
+    loop {
+        match reader.read_packet()? {
+            Packet::Data(data) => {
+                let line = String::from_utf8(data)
+                    .map_err(|_| anyhow!("protocol error: line is not UTF-8"))?;
+                lines.push(line.strip_suffix('\n').unwrap_or(&line).to_string());
+            }
+            Packet::Delim => return Ok(true),
+            Packet::Flush | Packet::ResponseEnd => return Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_request, read_section};
+    use crate::pack_protocol::{pkt_line::PktLineReader, transport::Advertisement};
+
+    #[test]
+    fn test_command_request() {
+        let advertisement = Advertisement {
+            version: 2,
+            capabilities: vec![
+                "ls-refs=unborn".to_string(),
+                "object-format=sha1".to_string(),
+            ],
+            ..Default::default()
+        };
+        let request = command_request(&advertisement, "ls-refs", &["peel".to_string()]).unwrap();
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("0014command=ls-refs\n"));
+        assert!(request.ends_with("0017object-format=sha1\n00010009peel\n0000"));
+        assert!(command_request(&advertisement, "fetch", &[]).is_err());
+
+        let data = b"0014acknowledgments\n0008NAK\n0001000dpackfile\n".to_vec();
+        let mut reader = PktLineReader::new(&data[..]);
+        let mut lines = Vec::new();
+        assert!(read_section(&mut reader, &mut lines).unwrap());
+        assert_eq!(lines, vec!["acknowledgments", "NAK"]);
+    }
+}
@@ -0,0 +1,175 @@
+use std::io::Read;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::objects::hash::Hash;
+
+use super::pkt_line::{write_flush, write_packet, Capability, PktLineReader};
+
+/// the object id standing for a ref that doesn't exist, on either side of an update
+const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+/// a ref update a push asks for, sent as "<old> <new> <ref>". the old value is what the
+/// pusher believes the ref points to, and None stands for a ref that doesn't exist: a
+/// missing old value creates the ref, and a missing new value deletes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub old: Option<Hash>,
+    pub new: Option<Hash>,
+    pub name: String,
+}
+
+impl RefUpdate {
+    pub fn parse(line: &str) -> Result<RefUpdate> {
+        let mut parts = line.splitn(3, ' ');
+        let (old, new, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(old), Some(new), Some(name)) => (old, new, name),
+            _ => bail!("protocol error: expected old/new/ref, got '{}'", line),
+        };
+        let id = |hex: &str| match hex {
+            ZERO_ID => Ok(None),
+            hex if hex.len() == 40 => Hash::try_from(hex.as_bytes()).map(Some),
+            _ => Err(anyhow!(
+                "protocol error: expected old/new/ref, got '{}'",
+                line
+            )),
+        };
+        Ok(RefUpdate {
+            old: id(old)?,
+            new: id(new)?,
+            name: name.to_string(),
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let id = |hash: &Option<Hash>| hash.as_ref().map_or(ZERO_ID.to_string(), Hash::to_hex);
+        format!("{} {} {}", id(&self.old), id(&self.new), self.name)
+    }
+
+    pub fn is_delete(&self) -> bool {
+        self.new.is_none()
+    }
+}
+
+/// writes the ref updates of a push, with the capabilities on the first one, followed by
+/// the push options if there are any. the pack, if any, comes after. like the lines of
+/// git, they don't end with LF.
+pub fn receive_pack_request(
+    updates: &[RefUpdate],
+    caps: &[Capability],
+    push_options: &[String],
+) -> Result<Vec<u8>> {
+    let mut request = Vec::new();
+    for (i, update) in updates.iter().enumerate() {
+        let line = match i {
+            0 => format!("{}\0{}", update.encode(), caps.join(" ")),
+            _ => update.encode(),
+        };
+        write_packet(&mut request, line.as_bytes())?;
+    }
+    write_flush(&mut request)?;
+
+    if !push_options.is_empty() {
+        for option in push_options {
+            write_packet(&mut request, option.as_bytes())?;
+        }
+        write_flush(&mut request)?;
+    }
+    Ok(request)
+}
+
+/// what receive-pack reports about a push
+#[derive(Debug, PartialEq, Eq)]
+pub struct PushReport {
+    /// why the pack couldn't be stored, if it couldn't
+    pub unpack_error: Option<String>,
+    /// the refs in the order they were reported, with the reasons they weren't updated
+    pub refs: Vec<(String, Option<String>)>,
+}
+
+impl PushReport {
+    /// the reason a ref wasn't updated, if it was reported as such
+    pub fn error(&self, name: &str) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(ref_name, _)| ref_name == name)
+            .and_then(|(_, error)| error.as_deref())
+    }
+}
+
+/// reads a report-status or report-status-v2 report, up to its flush-pkt. the options
+/// report-status-v2 adds after a ref are skipped.
+pub fn read_push_report<R: Read>(reader: &mut PktLineReader<R>) -> Result<PushReport> {
+    let unpack_error = match reader.read_line()? {
+        Some(line) => match line.strip_prefix("unpack ") {
+            Some("ok") => None,
+            Some(error) => Some(error.to_string()),
+            None => bail!("unpack status line expected, got '{}'", line),
+        },
+        None => bail!("unpack status line expected, got a flush-pkt"),
+    };
+
+    let mut refs = Vec::new();
+    while let Some(line) = reader.read_line()? {
+        if let Some(name) = line.strip_prefix("ok ") {
+            refs.push((name.to_string(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, error) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            refs.push((name.to_string(), Some(error.to_string())));
+        } else if !line.starts_with("option ") || refs.is_empty() {
+            bail!("invalid ref status from remote: {}", line);
+        }
+    }
+    Ok(PushReport { unpack_error, refs })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        objects::hash::Hash,
+        pack_protocol::pkt_line::{write_flush, write_line, PktLineReader},
+    };
+
+    use super::{read_push_report, receive_pack_request, RefUpdate};
+
+    #[test]
+    fn test_ref_update() {
+        let hex = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let update =
+            RefUpdate::parse(&format!("{} {} refs/heads/main", "0".repeat(40), hex)).unwrap();
+        assert_eq!(update.old, None);
+        assert_eq!(update.new, Some(Hash::try_from(hex.as_bytes()).unwrap()));
+        assert_eq!(update.name, "refs/heads/main");
+        assert_eq!(
+            RefUpdate::parse(&update.encode()).unwrap(),
+            update,
+            "an update round-trips"
+        );
+        assert!(RefUpdate::parse("abc refs/heads/main").is_err());
+
+        let request = receive_pack_request(&[update], &["atomic".to_string()], &[]).unwrap();
+        assert!(request.ends_with(b" refs/heads/main\0atomic0000"));
+    }
+
+    #[test]
+    fn test_read_push_report() {
+        let mut report = Vec::new();
+        for line in [
+            "unpack ok",
+            "ok refs/heads/main",
+            "option forced-update",
+            "ng refs/heads/next pre-receive hook declined",
+        ] {
+            write_line(&mut report, line).unwrap();
+        }
+        write_flush(&mut report).unwrap();
+
+        let report = read_push_report(&mut PktLineReader::new(report.as_slice())).unwrap();
+        assert_eq!(report.unpack_error, None);
+        assert_eq!(report.error("refs/heads/main"), None);
+        assert_eq!(
+            report.error("refs/heads/next"),
+            Some("pre-receive hook declined")
+        );
+    }
+}
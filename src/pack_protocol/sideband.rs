@@ -0,0 +1,319 @@
+use std::{
+    env,
+    io::{self, stderr, IsTerminal, Read, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::pkt_line::{write_flush, write_packet, Packet, PktLineReader, MAX_PKT_LINE_LENGTH};
+
+/// the band carrying the pack
+const PACK_BAND: u8 = 1;
+/// the band carrying progress messages
+const PROGRESS_BAND: u8 = 2;
+/// the band carrying a fatal error, after which nothing more is sent
+const ERROR_BAND: u8 = 3;
+
+/// the largest packet of side-band, as opposed to side-band-64k which uses pkt-lines of
+/// any length
+pub const SIDEBAND_MAX_PACKET: usize = 1000;
+
+/// receives the progress messages of a remote, line by line
+pub trait ProgressSink {
+    /// a line without its terminator. a line ending with CR is redrawn in place by the
+    /// next one, as percentages are, and one ending with LF stays.
+    fn line(&mut self, line: &str, terminator: char);
+}
+
+/// prints progress on stderr as "remote: Counting objects:  42% (21/50)"
+pub struct StderrProgress {
+    /// written after each line to clear what a longer previous line left
+    suffix: &'static str,
+}
+
+impl StderrProgress {
+    pub fn new() -> StderrProgress {
+        let smart = stderr().is_terminal() && env::var("TERM").is_ok_and(|term| term != "dumb");
+        StderrProgress {
+            suffix: if smart { "\x1b[K" } else { "        " },
+        }
+    }
+}
+
+impl Default for StderrProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressSink for StderrProgress {
+    fn line(&mut self, line: &str, terminator: char) {
+        // an empty line only ends the line a series of CRs kept redrawing
+        let suffix = if line.is_empty() { "" } else { self.suffix };
+        eprint!("remote: {}{}{}", line, suffix, terminator);
+    }
+}
+
+/// drops progress, for remotes that send some although none was asked for
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn line(&mut self, _: &str, _: char) {}
+}
+
+/// demultiplexes a side-band stream: each packet starts with its band. the pack is read
+/// from it, progress goes to a sink, and an error from the remote fails the read. the
+/// stream ends with a flush-pkt.
+pub struct SidebandReader<'a, R: Read> {
+    reader: &'a mut PktLineReader<R>,
+    progress: &'a mut dyn ProgressSink,
+    /// pack data of the last packet not read yet
+    data: Vec<u8>,
+    position: usize,
+    /// progress text still waiting for its terminator
+    partial: String,
+    done: bool,
+}
+
+impl<'a, R: Read> SidebandReader<'a, R> {
+    pub fn new(
+        reader: &'a mut PktLineReader<R>,
+        progress: &'a mut dyn ProgressSink,
+    ) -> SidebandReader<'a, R> {
+        SidebandReader {
+            reader,
+            progress,
+            data: Vec::new(),
+            position: 0,
+            partial: String::new(),
+            done: false,
+        }
+    }
+
+    /// reads packets until one carries pack data, or the stream ends
+    fn next_data(&mut self) -> Result<()> {
+        loop {
+            let mut data = match self.reader.read_packet()? {
+                Packet::Data(data) => data,
+                Packet::Flush | Packet::ResponseEnd => {
+                    self.flush_progress();
+                    self.done = true;
+                    return Ok(());
+                }
+                Packet::Delim => bail!("protocol error: unexpected delim-pkt in side-band"),
+            };
+            if data.is_empty() {
+                bail!("protocol error: empty side-band packet");
+            }
+
+            match data.remove(0) {
+                PACK_BAND => {
+                    if data.is_empty() {
+                        continue;
+                    }
+                    self.data = data;
+                    self.position = 0;
+                    return Ok(());
+                }
+                PROGRESS_BAND => self.progress(&String::from_utf8_lossy(&data)),
+                ERROR_BAND => {
+                    self.flush_progress();
+                    return Err(anyhow!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(&data).trim_end()
+                    ));
+                }
+                band => bail!("protocol error: bad band #{}", band),
+            }
+        }
+    }
+
+    /// hands the complete lines of progress to the sink. a line may span packets.
+    fn progress(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\r' | '\n' => {
+                    self.progress.line(&self.partial, c);
+                    self.partial.clear();
+                }
+                c => self.partial.push(c),
+            }
+        }
+    }
+
+    fn flush_progress(&mut self) {
+        if !self.partial.is_empty() {
+            self.progress.line(&self.partial, '\n');
+            self.partial.clear();
+        }
+    }
+}
+
+impl<R: Read> Read for SidebandReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.data.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_data().map_err(io::Error::other)?;
+        }
+        let n = buf.len().min(self.data.len() - self.position);
+        buf[..n].copy_from_slice(&self.data[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// multiplexes a pack with progress messages, the other end of a [`SidebandReader`]. pack
+/// data is buffered up to the largest packet the client accepts.
+pub struct SidebandWriter<W: Write> {
+    writer: W,
+    /// the most pack data a packet carries, without its length and band
+    max_data: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> SidebandWriter<W> {
+    /// max_packet is SIDEBAND_MAX_PACKET for side-band, or MAX_PKT_LINE_LENGTH for
+    /// side-band-64k
+    pub fn new(writer: W, max_packet: usize) -> SidebandWriter<W> {
+        let max_packet = max_packet.min(MAX_PKT_LINE_LENGTH);
+        SidebandWriter {
+            writer,
+            max_data: max_packet - 5,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// sends a progress message, after the pack data before it
+    pub fn progress(&mut self, text: &str) -> Result<()> {
+        self.send_data()?;
+        self.send(PROGRESS_BAND, text.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// sends a fatal error, after which nothing more is sent
+    pub fn error(mut self, message: &str) -> Result<()> {
+        self.send_data()?;
+        self.send(ERROR_BAND, message.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// sends the rest of the pack and the flush-pkt ending the stream
+    pub fn finish(mut self) -> Result<W> {
+        self.send_data()?;
+        write_flush(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn send_data(&mut self) -> Result<()> {
+        let data = std::mem::take(&mut self.buffer);
+        for chunk in data.chunks(self.max_data) {
+            self.send(PACK_BAND, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, band: u8, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(self.max_data) {
+            let mut packet = Vec::with_capacity(chunk.len() + 1);
+            packet.push(band);
+            packet.extend_from_slice(chunk);
+            write_packet(&mut self.writer, &packet)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SidebandWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.max_data {
+            let full = self.buffer.len() - self.buffer.len() % self.max_data;
+            let rest = self.buffer.split_off(full);
+            self.send_data().map_err(io::Error::other)?;
+            self.buffer = rest;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_data().map_err(io::Error::other)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::{ProgressSink, SidebandReader, SidebandWriter, SIDEBAND_MAX_PACKET};
+    use crate::pack_protocol::pkt_line::{write_flush, write_packet, PktLineReader};
+
+    #[derive(Default)]
+    struct Lines(Vec<(String, char)>);
+
+    impl ProgressSink for Lines {
+        fn line(&mut self, line: &str, terminator: char) {
+            self.0.push((line.to_string(), terminator));
+        }
+    }
+
+    #[test]
+    fn test_sideband_reader() {
+        let mut stream = Vec::new();
+        write_packet(&mut stream, b"\x01PA").unwrap();
+        write_packet(&mut stream, b"\x02Counting objects:  50% (1/2)\rCounting").unwrap();
+        write_packet(&mut stream, b"\x02 objects: 100% (2/2), done.\n").unwrap();
+        write_packet(&mut stream, b"\x01CK").unwrap();
+        write_flush(&mut stream).unwrap();
+
+        let mut reader = PktLineReader::new(&stream[..]);
+        let mut lines = Lines::default();
+        let mut pack = Vec::new();
+        SidebandReader::new(&mut reader, &mut lines)
+            .read_to_end(&mut pack)
+            .unwrap();
+        assert_eq!(pack, b"PACK");
+        assert_eq!(
+            lines.0,
+            vec![
+                ("Counting objects:  50% (1/2)".to_string(), '\r'),
+                ("Counting objects: 100% (2/2), done.".to_string(), '\n'),
+            ]
+        );
+
+        let mut stream = Vec::new();
+        write_packet(&mut stream, b"\x03upload-pack: not our ref").unwrap();
+        let mut reader = PktLineReader::new(&stream[..]);
+        let err = SidebandReader::new(&mut reader, &mut lines)
+            .read_to_end(&mut pack)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "remote error: upload-pack: not our ref");
+    }
+
+    #[test]
+    fn test_sideband_writer() {
+        let pack: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let mut writer = SidebandWriter::new(Vec::new(), SIDEBAND_MAX_PACKET);
+        writer.write_all(&pack[..3000]).unwrap();
+        writer.progress("Enumerating objects: 2, done.\n").unwrap();
+        writer.write_all(&pack[3000..]).unwrap();
+        let stream = writer.finish().unwrap();
+
+        let mut reader = PktLineReader::new(&stream[..]);
+        let mut lines = Lines::default();
+        let mut read = Vec::new();
+        SidebandReader::new(&mut reader, &mut lines)
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, pack);
+        assert_eq!(
+            lines.0,
+            vec![("Enumerating objects: 2, done.".to_string(), '\n')]
+        );
+    }
+}
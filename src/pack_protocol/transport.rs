@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use url::Url;
+
+use crate::{config::Config, objects::hash::Hash};
+
+use super::pkt_line::{write_flush, AdvertisedRefsParser, Capability, PktLineReader, Ref};
+
+/// the protocol version asked for when protocol.version isn't set
+const DEFAULT_PROTOCOL_VERSION: u8 = 2;
+
+/// the header smart HTTP clients ask for a protocol version with
+const GIT_PROTOCOL_HEADER: &str = "Git-Protocol";
+
+/// the refs and capabilities a remote advertises. a version 2 remote only advertises
+/// capabilities, and its refs are listed on request.
+#[derive(Debug, Clone, Default)]
+pub struct Advertisement {
+    /// the protocol version the remote speaks
+    pub version: u8,
+    /// refs in the order they were advertised, HEAD included
+    pub refs: Vec<(String, Hash)>,
+    /// the objects annotated tags point to, by tag name
+    pub peeled: HashMap<String, Hash>,
+    /// the targets of symbolic refs, like HEAD
+    pub symrefs: HashMap<String, String>,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Advertisement {
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|cap| cap == name || cap.split_once('=').is_some_and(|(cap, _)| cap == name))
+    }
+
+    fn from_refs(
+        version: u8,
+        refs: Vec<Ref>,
+        capabilities: Vec<Capability>,
+    ) -> Result<Advertisement> {
+        let mut advertisement = Advertisement {
+            version,
+            capabilities,
+            ..Default::default()
+        };
+        for cap in &advertisement.capabilities {
+            if let Some((name, target)) = cap
+                .strip_prefix("symref=")
+                .and_then(|symref| symref.split_once(':'))
+            {
+                advertisement
+                    .symrefs
+                    .insert(name.to_string(), target.to_string());
+            }
+        }
+        for r in refs {
+            match r {
+                Ref::Tip { name, object_id } => advertisement
+                    .refs
+                    .push((name, Hash::try_from(object_id.as_bytes())?)),
+                Ref::Peeled { name, object_id } => {
+                    advertisement
+                        .peeled
+                        .insert(name, Hash::try_from(object_id.as_bytes())?);
+                }
+                Ref::Shallow { .. } => {}
+            }
+        }
+        Ok(advertisement)
+    }
+}
+
+/// the programs a remote runs for a transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// sends the objects of fetches and clones
+    UploadPack,
+    /// receives the objects and ref updates of pushes
+    ReceivePack,
+}
+
+impl Service {
+    /// the name of the service, as smart HTTP urls and content types use it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Service::UploadPack => "git-upload-pack",
+            Service::ReceivePack => "git-receive-pack",
+        }
+    }
+}
+
+/// the protocol version to ask remotes for, from protocol.version
+pub fn protocol_version(config: &Config) -> Result<u8> {
+    match config.get("protocol.version") {
+        Some(version) => match version.parse::<u8>() {
+            Ok(version) if version <= 2 => Ok(version),
+            _ => bail!("unknown value for config 'protocol.version': {}", version),
+        },
+        None => Ok(DEFAULT_PROTOCOL_VERSION),
+    }
+}
+
+enum Connection {
+    /// upload-pack runs as a child process, locally or over ssh, for the whole fetch
+    Process {
+        child: Child,
+        stdin: Option<ChildStdin>,
+    },
+    /// every request is a POST to the smart HTTP endpoint, so the server keeps no state
+    /// between requests
+    Http {
+        client: reqwest::blocking::Client,
+        url: Url,
+        /// the Git-Protocol header sent with each request
+        protocol: Option<String>,
+    },
+}
+
+/// a connection to a service of a remote
+pub struct Transport {
+    service: Service,
+    connection: Connection,
+    reader: PktLineReader<Box<dyn Read>>,
+}
+
+impl Transport {
+    /// connects to a service of a remote and reads what it advertises. command is the
+    /// program run on the remote side by local and ssh transports. a version above 0 is
+    /// asked for, and remotes that don't know it answer with an older one.
+    pub fn connect(
+        url: &str,
+        service: Service,
+        command: &str,
+        version: u8,
+    ) -> Result<(Transport, Advertisement)> {
+        // version 2 only knows about fetches
+        let version = match service {
+            Service::ReceivePack if version == 2 => 0,
+            _ => version,
+        };
+        let protocol = (version > 0).then(|| format!("version={}", version));
+        let protocol = protocol.as_deref();
+        let mut transport = match url.split_once("://") {
+            Some(("http" | "https", _)) => Self::connect_http(url, service, protocol)?,
+            Some(("file", path)) => Self::spawn(None, path, service, command, protocol)?,
+            Some(("ssh", rest)) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                let path = format!("/{}", path);
+                Self::spawn(Some(host), &path, service, command, protocol)?
+            }
+            Some((scheme, _)) => bail!("Unable to find remote helper for '{}'", scheme),
+            None => match scp_like(url) {
+                Some((host, path)) => Self::spawn(Some(host), path, service, command, protocol)?,
+                None => Self::spawn(None, url, service, command, protocol)?,
+            },
+        };
+
+        let mut parser = AdvertisedRefsParser::new(&mut transport.reader);
+        let advertisement = match parser.parse_version() {
+            Ok(2) => parser
+                .parse_capability_advertisement()
+                .map(|capabilities| Advertisement {
+                    version: 2,
+                    capabilities,
+                    ..Default::default()
+                }),
+            Ok(version) => parser
+                .parse_advertised_refs()
+                .and_then(|(refs, capabilities, _)| {
+                    Advertisement::from_refs(version, refs, capabilities)
+                }),
+            Err(err) => Err(err),
+        }
+        .with_context(|| format!("could not read from remote repository '{}'", url))?;
+        Ok((transport, advertisement))
+    }
+
+    fn spawn(
+        host: Option<&str>,
+        path: &str,
+        service: Service,
+        program: &str,
+        protocol: Option<&str>,
+    ) -> Result<Transport> {
+        let remote_command = format!("{} {}", program, quote(path));
+        let mut command = match host {
+            Some(host) => ssh_command(host, path, &remote_command, protocol.is_some())?,
+            None => {
+                let mut command = Command::new("sh");
+                command.args(["-c", &remote_command]);
+                command
+            }
+        };
+
+        if let Some(protocol) = protocol {
+            command.env("GIT_PROTOCOL", protocol);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {}", program))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        Ok(Transport {
+            service,
+            connection: Connection::Process { child, stdin },
+            reader: PktLineReader::new(Box::new(stdout)),
+        })
+    }
+
+    fn connect_http(url: &str, service: Service, protocol: Option<&str>) -> Result<Transport> {
+        let client = reqwest::blocking::Client::new();
+        let base_url = Url::parse(&format!("{}/", url.trim_end_matches('/')))?;
+        let mut refs_url = base_url.join("info/refs")?;
+        refs_url
+            .query_pairs_mut()
+            .append_pair("service", service.name());
+
+        let mut request = client.get(refs_url);
+        if let Some(protocol) = protocol {
+            request = request.header(GIT_PROTOCOL_HEADER, protocol);
+        }
+        let response = request.send()?;
+        if !response.status().is_success() {
+            bail!("unable to access '{}': {}", url, response.status());
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if content_type != format!("application/x-{}-advertisement", service.name()) {
+            bail!("{} is not a smart HTTP remote", url);
+        }
+
+        // smart HTTP advertisements start with a service line and a flush-pkt, except
+        // for version 2 ones, which start right away with their version line
+        let mut response = BufReader::new(response);
+        if response
+            .fill_buf()?
+            .get(4..)
+            .is_some_and(|data| data.starts_with(b"# service="))
+        {
+            let mut reader = PktLineReader::new(&mut response);
+            let line = reader.read_line()?;
+            if line.as_deref() != Some(&format!("# service={}", service.name())) {
+                bail!("invalid smart HTTP service line: {:?}", line);
+            }
+            if reader.read_line()?.is_some() {
+                bail!("expected flush after the smart HTTP service line");
+            }
+        }
+
+        Ok(Transport {
+            service,
+            connection: Connection::Http {
+                client,
+                url: base_url.join(service.name())?,
+                protocol: protocol.map(|protocol| protocol.to_string()),
+            },
+            reader: PktLineReader::new(Box::new(response)),
+        })
+    }
+
+    /// whether the remote forgets everything between requests, so that each request must
+    /// repeat the wants and the commits known to be common
+    pub fn is_stateless(&self) -> bool {
+        matches!(self.connection, Connection::Http { .. })
+    }
+
+    /// sends a request. the response of a stateless remote is read from the next reads.
+    pub fn send(&mut self, request: &[u8]) -> Result<()> {
+        match &mut self.connection {
+            Connection::Process { stdin, .. } => {
+                let stdin = stdin.as_mut().ok_or(anyhow!("connection is closed"))?;
+                stdin.write_all(request)?;
+                stdin.flush()?;
+            }
+            Connection::Http {
+                client,
+                url,
+                protocol,
+            } => {
+                let mut post = client.post(url.clone());
+                if let Some(protocol) = protocol {
+                    post = post.header(GIT_PROTOCOL_HEADER, protocol.as_str());
+                }
+                let response = post
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        format!("application/x-{}-request", self.service.name()),
+                    )
+                    .header(
+                        reqwest::header::ACCEPT,
+                        format!("application/x-{}-result", self.service.name()),
+                    )
+                    .body(request.to_vec())
+                    .send()?;
+                if !response.status().is_success() {
+                    bail!("RPC failed; HTTP {}", response.status());
+                }
+                self.reader = PktLineReader::new(Box::new(response));
+            }
+        }
+        Ok(())
+    }
+
+    /// closes the sending side of a process once the last request was sent, so that
+    /// the remote sees the end of its input
+    pub fn finish_requests(&mut self) {
+        if let Connection::Process { stdin, .. } = &mut self.connection {
+            stdin.take();
+        }
+    }
+
+    pub fn reader(&mut self) -> &mut PktLineReader<Box<dyn Read>> {
+        &mut self.reader
+    }
+
+    /// hangs up before asking for anything, as when the refs to fetch can't be mapped
+    pub fn abort(mut self) -> Result<()> {
+        if !self.is_stateless() {
+            let mut request = Vec::new();
+            write_flush(&mut request)?;
+            self.send(&request)?;
+        }
+        self.close()
+    }
+
+    /// closes the connection, waiting for a child process to exit
+    pub fn close(self) -> Result<()> {
+        if let Connection::Process { mut child, stdin } = self.connection {
+            drop(stdin);
+            drop(self.reader);
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("{} exited with {}", self.service.name(), status);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the ssh command running a service on a host. hosts and paths that ssh or the service
+/// would take for options are refused, as a url could otherwise run commands locally
+/// (e.g. -oProxyCommand=...).
+fn ssh_command(
+    host: &str,
+    path: &str,
+    remote_command: &str,
+    send_protocol: bool,
+) -> Result<Command> {
+    let (host, port) = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => (host, Some(port)),
+        _ => (host, None),
+    };
+    if host.starts_with('-') {
+        bail!("strange hostname '{}' blocked", host);
+    }
+    if path.starts_with('-') {
+        bail!("strange pathname '{}' blocked", path);
+    }
+
+    let mut command = Command::new("ssh");
+    if send_protocol {
+        command.args(["-o", "SendEnv=GIT_PROTOCOL"]);
+    }
+    if let Some(port) = port {
+        command.args(["-p", port]);
+    }
+    command.args(["--", host, remote_command]);
+    Ok(command)
+}
+
+/// splits an scp-like url such as host:path or user@host:path. a colon after a slash
+/// belongs to a local path.
+fn scp_like(url: &str) -> Option<(&str, &str)> {
+    let (host, path) = url.split_once(':')?;
+    match host.contains('/') || host.is_empty() {
+        true => None,
+        false => Some((host, path)),
+    }
+}
+
+/// quotes an argument for the shell, which runs the command of the service
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{quote, scp_like, ssh_command};
+
+    #[test]
+    fn test_scp_like() {
+        assert_eq!(
+            scp_like("git@example.com:repo.git"),
+            Some(("git@example.com", "repo.git"))
+        );
+        assert_eq!(scp_like("../up"), None);
+        assert_eq!(scp_like("./a:b"), None);
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        let args = |host, path| {
+            ssh_command(host, path, "git-upload-pack 'repo'", true).map(|command| {
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            args("git@example.com:2222", "/repo").unwrap(),
+            [
+                "-o",
+                "SendEnv=GIT_PROTOCOL",
+                "-p",
+                "2222",
+                "--",
+                "git@example.com",
+                "git-upload-pack 'repo'"
+            ]
+        );
+        let (host, path) = scp_like("-oProxyCommand=touch${IFS}x:repo").unwrap();
+        assert!(args(host, path).is_err());
+        assert!(args("example.com", "-repo").is_err());
+    }
+}
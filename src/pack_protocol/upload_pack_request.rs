@@ -1,7 +1,5 @@
 use super::pkt_line::Capability;
 use super::pkt_line::PktLine;
-use super::pkt_line::Ref;
-use super::pkt_line::Shallow;
 use anyhow::anyhow;
 use anyhow::Result;
 use std::io::Write;
@@ -19,28 +17,21 @@ pub fn upload_pack_request(
     // shallow_commits: Vec<String>,
     // depth_requests: Vec<DepthRequest>,
     // filter_requests: Vec<String>,
-    // caps: Vec<Capability>,
+    caps: Vec<Capability>,
 ) -> Result<Vec<u8>> {
     let mut request = Vec::new();
 
-    if want_list.len() < 0 {
+    if want_list.is_empty() {
         return Err(anyhow!("want list must have at least 1 want"));
     }
 
-    // if caps.len() < 0 {
-    //     return Err(anyhow!("capabilities list must have at least 1 capability"));
-    // }
-
-    // let first_want = want_list[0];
-    // let cap_list = caps.join(" ");
-    // request.push_str(PktLine::new(format!("want {} {}", first_want, cap_list)).as_str());
-
-    for want in want_list {
-        write!(
-            request,
-            "{}",
-            PktLine::new(format!("want {}", want)).as_str()
-        );
+    // capabilities are sent on the first want
+    for (i, want) in want_list.iter().enumerate() {
+        let line = match i {
+            0 if !caps.is_empty() => format!("want {} {}", want, caps.join(" ")),
+            _ => format!("want {}", want),
+        };
+        write!(request, "{}", PktLine::new(line).as_str())?;
     }
 
     // for shallow in shallow_commits {
@@ -65,8 +56,8 @@ pub fn upload_pack_request(
     //     request.push_str(PktLine::new(format!("filter {}", r)).as_str())
     // }
 
-    write!(request, "{}", PktLine::new_flush().as_str());
-    write!(request, "{}", PktLine::new_end().as_str());
+    // the wants end with a flush-pkt. "done" is sent once negotiation is over.
+    write!(request, "{}", PktLine::new_flush().as_str())?;
 
     Ok(request)
 }
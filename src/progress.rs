@@ -0,0 +1,52 @@
+/// shows the progress of a long operation on stderr, as "Resolving deltas:  42% (21/50)".
+/// the line is redrawn in place each time the percentage changes.
+pub struct Progress {
+    title: String,
+    total: u64,
+    percent: Option<u64>,
+}
+
+impl Progress {
+    /// starts showing progress, at 0%
+    pub fn new(title: &str, total: u64) -> Progress {
+        let mut progress = Progress {
+            title: title.to_string(),
+            total,
+            percent: None,
+        };
+        progress.update(0);
+        progress
+    }
+
+    pub fn update(&mut self, done: u64) {
+        let percent = self.percent_of(done);
+        if self.percent != Some(percent) {
+            self.percent = Some(percent);
+            eprint!("{}: {:3}% ({}/{})\r", self.title, percent, done, self.total);
+        }
+    }
+
+    /// ends the line, as "Resolving deltas: 100% (50/50), done."
+    pub fn done(self, done: u64) {
+        self.finish(done, "done");
+    }
+
+    /// ends the line with a message in place of "done"
+    pub fn finish(self, done: u64, message: &str) {
+        eprintln!(
+            "{}: {:3}% ({}/{}), {}.",
+            self.title,
+            self.percent_of(done),
+            done,
+            self.total,
+            message
+        );
+    }
+
+    fn percent_of(&self, done: u64) -> u64 {
+        match self.total {
+            0 => 100,
+            total => done * 100 / total,
+        }
+    }
+}
@@ -0,0 +1,767 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::{read_config, Config},
+    merge_base::CommitGraph,
+    objects::{hash::Hash, tag::decode_tag, Object, ObjectKind},
+    pack_protocol::{
+        pack_writer::{PackWriter, PackWriterOptions},
+        pkt_line::{write_flush, PktLineReader},
+        receive_pack_request::{read_push_report, receive_pack_request, PushReport, RefUpdate},
+        sideband::{SidebandReader, StderrProgress},
+        transport::{protocol_version, Advertisement, Service, Transport},
+    },
+    refs::{
+        delete_ref, expand_ref, head_branch, list_refs, refspec::Refspec, resolve_ref, update_ref,
+    },
+    remote::{default_push_remote, remote, Remote},
+    rev_parse::{abbreviate, peel_kind, resolve_revision},
+    revwalk::{collect_tree_objects, RevWalk, WalkedObject},
+};
+
+/// width of the summary column, enough for "abbrev...abbrev"
+const SUMMARY_WIDTH: usize = 17;
+
+/// the message reflogs record the updates of remote-tracking refs under
+const TRACKING_REFLOG_MESSAGE: &str = "update by push";
+
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    /// the remote to push to, a configured name or a url. the push remote of the current
+    /// branch, or the remote fetched from, by default.
+    pub remote: Option<String>,
+    /// the refspecs to push instead of the configured ones, like main or +HEAD:refs/heads/x
+    pub refspecs: Vec<String>,
+    /// update remote refs even when the updates aren't fast-forwards
+    pub force: bool,
+    /// the values of --force-with-lease: empty for all the refs pushed, <ref> to expect the
+    /// ref at its remote-tracking value, or <ref>:<expect> to expect it at a revision
+    pub force_with_lease: Vec<String>,
+    /// delete the refs named by the refspecs
+    pub delete: bool,
+    /// push all the tags too
+    pub tags: bool,
+    /// update all the remote refs or none of them
+    pub atomic: bool,
+    /// strings handed to the hooks of the remote
+    pub push_options: Vec<String>,
+    /// the command run on the remote side instead of git-receive-pack
+    pub receive_pack: Option<String>,
+    /// only report errors
+    pub quiet: bool,
+}
+
+/// what the remote ref has to be for a push with a lease to go through
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Lease {
+    /// the value of its remote-tracking ref
+    Tracking,
+    /// a given value, or None for a ref that must not exist
+    Expect(Option<Hash>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Status {
+    /// to be sent to the remote
+    Pending,
+    UpToDate,
+    /// refused before it was sent
+    Rejected(&'static str),
+    /// refused by the remote
+    RemoteRejected(String),
+    Ok,
+}
+
+/// a remote ref to update
+#[derive(Debug, Clone)]
+struct PushRef {
+    /// what was pushed, as shown: a local ref, a revision, or None for a delete
+    src: Option<String>,
+    /// the ref updated on the remote
+    dst: String,
+    /// the value pushed, or None to delete the ref
+    new: Option<Hash>,
+    /// the value the remote advertised
+    old: Option<Hash>,
+    force: bool,
+    lease: Option<Lease>,
+    /// whether the update was forced over history the new value doesn't have
+    forced_update: bool,
+    status: Status,
+}
+
+/// pushes local refs and the objects they need to a remote, and updates the
+/// remote-tracking refs of those the remote took. returns false if a ref couldn't be
+/// updated.
+pub fn push(options: PushOptions) -> Result<bool> {
+    let config = read_config()?;
+    let branch = head_branch()?.map(|name| name.trim_start_matches("refs/heads/").to_string());
+    let remote_name = options
+        .remote
+        .clone()
+        .unwrap_or_else(|| default_push_remote(&config, branch.as_deref()));
+    let remote = remote(&config, &remote_name)?;
+    let url = remote
+        .push_url
+        .clone()
+        .unwrap_or_else(|| remote.url.clone());
+    let receive_pack = options
+        .receive_pack
+        .clone()
+        .unwrap_or_else(|| remote.receive_pack.clone());
+
+    let specs = push_refspecs(&options, &config, &remote, branch.as_deref())?;
+    let leases = parse_leases(&options.force_with_lease)?;
+    if options.delete && options.refspecs.is_empty() {
+        bail!("--delete doesn't make sense without any refs");
+    }
+
+    let (mut transport, advertisement) = Transport::connect(
+        &url,
+        Service::ReceivePack,
+        &receive_pack,
+        protocol_version(&config)?,
+    )?;
+    let planned = match_push_refs(&specs, &options, &advertisement).and_then(|mut refs| {
+        set_statuses(&mut refs, &options, &leases, &remote, &advertisement)?;
+        if options.atomic && !advertisement.has_capability("atomic") {
+            bail!("the receiving end does not support --atomic push");
+        }
+        if !options.push_options.is_empty() && !advertisement.has_capability("push-options") {
+            bail!("the receiving end does not support push options");
+        }
+        Ok(refs)
+    });
+    let mut refs = match planned {
+        Ok(refs) => refs,
+        Err(err) => {
+            transport.abort()?;
+            return Err(err);
+        }
+    };
+
+    if refs.iter().any(|r| r.status == Status::Pending) {
+        let report = send_pack(&mut transport, &advertisement, &refs, &options, &config)?;
+        for push_ref in refs.iter_mut().filter(|r| r.status == Status::Pending) {
+            push_ref.status = match &report {
+                Some(report) => match report.refs.iter().find(|(name, _)| *name == push_ref.dst) {
+                    Some((_, None)) => Status::Ok,
+                    Some((_, Some(error))) => Status::RemoteRejected(error.clone()),
+                    None => Status::RemoteRejected("remote failed to report status".to_string()),
+                },
+                None => Status::Ok,
+            };
+        }
+        if let Some(error) = report
+            .as_ref()
+            .and_then(|report| report.unpack_error.as_ref())
+        {
+            eprintln!("error: remote unpack failed: {}", error);
+        }
+    } else {
+        let mut request = Vec::new();
+        write_flush(&mut request)?;
+        transport.send(&request)?;
+        transport.finish_requests();
+    }
+    transport.close()?;
+
+    if remote.configured {
+        update_tracking_refs(&remote, &refs)?;
+    }
+
+    let failed = refs
+        .iter()
+        .any(|r| matches!(r.status, Status::Rejected(_) | Status::RemoteRejected(_)));
+    if !options.quiet || failed {
+        print_statuses(&url, &refs)?;
+    }
+    if failed {
+        eprintln!("error: failed to push some refs to '{}'", url);
+    } else if !options.quiet && refs.iter().all(|r| r.status == Status::UpToDate) {
+        eprintln!("Everything up-to-date");
+    }
+    Ok(!failed)
+}
+
+/// the refspecs to push: those given, with --tags adding all the tags, or else the
+/// configured ones, or else the ones push.default picks
+fn push_refspecs(
+    options: &PushOptions,
+    config: &Config,
+    remote: &Remote,
+    branch: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut specs: Vec<String> = options
+        .refspecs
+        .iter()
+        .map(|spec| match options.delete {
+            true if spec.contains(':') => bail!("--delete only accepts plain target ref names"),
+            true => Ok(format!(":{}", spec)),
+            false => Ok(spec.clone()),
+        })
+        .collect::<Result<_>>()?;
+    if options.tags {
+        specs.push("refs/tags/*:refs/tags/*".to_string());
+    }
+    if !specs.is_empty() {
+        return Ok(specs);
+    }
+    if !remote.push.is_empty() {
+        return Ok(remote.push.clone());
+    }
+
+    let mode = config.get("push.default").unwrap_or("simple");
+    if mode == "nothing" {
+        bail!("You didn't specify any refspecs to push, and push.default is \"nothing\".");
+    }
+    if mode == "matching" {
+        return Ok(vec![":".to_string()]);
+    }
+    let branch = match branch {
+        Some(branch) => branch,
+        None => bail!("You are not currently on a branch."),
+    };
+
+    // pushing to another remote than the one fetched from pushes the branch to the
+    // branch of the same name
+    let upstream_remote = config.get(&format!("branch.{}.remote", branch));
+    let triangular = upstream_remote.unwrap_or("origin") != remote.name;
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    match mode {
+        "current" => Ok(vec![format!("refs/heads/{0}:refs/heads/{0}", branch)]),
+        "simple" if triangular => Ok(vec![format!("refs/heads/{0}:refs/heads/{0}", branch)]),
+        "simple" | "upstream" | "tracking" => {
+            let merge = match merge {
+                Some(merge) if upstream_remote.is_some() => merge,
+                _ => bail!(
+                    "The current branch {0} has no upstream branch.\nTo push the current branch \
+                     and set the remote as upstream, use\n\n    git push --set-upstream {1} {0}\n",
+                    branch,
+                    remote.name
+                ),
+            };
+            if triangular {
+                bail!(
+                    "You are pushing to remote '{}', which is not the upstream of\nyour current \
+                     branch '{}', without telling me what to push\nto update which remote branch.",
+                    remote.name,
+                    branch
+                );
+            }
+            if mode == "simple" && merge != format!("refs/heads/{}", branch) {
+                bail!(
+                    "The upstream branch of your current branch does not match\nthe name of your \
+                     current branch."
+                );
+            }
+            Ok(vec![format!("refs/heads/{}:{}", branch, merge)])
+        }
+        mode => bail!("unknown value for config 'push.default': {}", mode),
+    }
+}
+
+fn parse_leases(values: &[String]) -> Result<Vec<(Option<String>, Lease)>> {
+    let mut leases = Vec::new();
+    for value in values {
+        let lease = match value.split_once(':') {
+            _ if value.is_empty() => (None, Lease::Tracking),
+            None => (Some(value.clone()), Lease::Tracking),
+            Some((name, "")) => (Some(name.to_string()), Lease::Expect(None)),
+            Some((name, expect)) => match resolve_revision(expect) {
+                Ok(hash) => (Some(name.to_string()), Lease::Expect(Some(hash))),
+                Err(_) => bail!("cannot parse expected object name '{}'", expect),
+            },
+        };
+        leases.push(lease);
+    }
+    Ok(leases)
+}
+
+/// maps the refspecs to the remote refs they update
+fn match_push_refs(
+    specs: &[String],
+    options: &PushOptions,
+    advertisement: &Advertisement,
+) -> Result<Vec<PushRef>> {
+    let remote_names: Vec<String> = advertisement
+        .refs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut refs: Vec<PushRef> = Vec::new();
+    let mut add = |src: Option<String>, dst: String, new: Option<Hash>, force: bool| {
+        if refs.iter().any(|r| r.dst == dst) {
+            bail!("multiple updates for ref '{}' not allowed", dst);
+        }
+        refs.push(PushRef {
+            src,
+            dst,
+            new,
+            old: None,
+            force: force || options.force,
+            lease: None,
+            forced_update: false,
+            status: Status::Pending,
+        });
+        Ok(())
+    };
+
+    for spec in specs {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec.as_str()),
+        };
+
+        // ":" pushes the branches the remote has too
+        if spec == ":" {
+            for (name, hash) in list_refs("refs/heads/")? {
+                if remote_names.contains(&name) {
+                    add(Some(name.clone()), name, Some(hash), force)?;
+                }
+            }
+            continue;
+        }
+
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst)),
+            None => (spec, None),
+        };
+        if src.is_empty() {
+            let dst = dst.unwrap_or_default();
+            let name = match Refspec::parse(dst)?.find_src(&remote_names) {
+                Some(name) => name.clone(),
+                None => bail!("unable to delete '{}': remote ref does not exist", dst),
+            };
+            add(None, name, None, force)?;
+            continue;
+        }
+
+        if src.contains('*') {
+            let refspec = Refspec::parse(spec)?;
+            let prefix = src.split_once('*').map_or(src, |(prefix, _)| prefix);
+            for (name, hash) in list_refs(prefix)? {
+                if let Some(dst) = refspec.map(&name) {
+                    add(Some(name), dst, Some(hash), force)?;
+                }
+            }
+            continue;
+        }
+
+        // a ref is pushed to the ref of the same name, and a revision to a ref named in full
+        let src_ref = match expand_ref(src)? {
+            Some(name) if name == "HEAD" => head_branch()?.or(Some(name)),
+            name => name,
+        };
+        let new = match &src_ref {
+            Some(name) => resolve_ref(name)?,
+            None => resolve_revision(src).ok(),
+        };
+        let new = match new {
+            Some(new) => new,
+            None => bail!("src refspec {} does not match any", src),
+        };
+        let dst = match (dst, &src_ref) {
+            (Some(dst), _) if dst.starts_with("refs/") => dst.to_string(),
+            (Some(dst), src_ref) => match Refspec::parse(dst)?.find_src(&remote_names) {
+                Some(name) => name.clone(),
+                None => match src_ref.as_deref().and_then(|name| {
+                    ["refs/heads/", "refs/tags/"]
+                        .into_iter()
+                        .find(|prefix| name.starts_with(prefix))
+                }) {
+                    Some(prefix) => format!("{}{}", prefix, dst),
+                    None => bail!(
+                        "The destination you provided is not a full refname (i.e.,\nstarting \
+                             with \"refs/\"): {}",
+                        dst
+                    ),
+                },
+            },
+            (None, Some(name)) if name.starts_with("refs/") => name.clone(),
+            (None, _) => bail!(
+                "The destination you provided is not a full refname (i.e.,\nstarting with \
+                 \"refs/\"): {}",
+                src
+            ),
+        };
+        add(
+            Some(src_ref.unwrap_or(src.to_string())),
+            dst,
+            Some(new),
+            force,
+        )?;
+    }
+    Ok(refs)
+}
+
+/// decides which updates are sent, rejecting those that would lose history on the
+/// remote without being forced, and those whose lease isn't met
+fn set_statuses(
+    refs: &mut [PushRef],
+    options: &PushOptions,
+    leases: &[(Option<String>, Lease)],
+    remote: &Remote,
+    advertisement: &Advertisement,
+) -> Result<()> {
+    let advertised: HashMap<&String, &Hash> = advertisement
+        .refs
+        .iter()
+        .map(|(name, hash)| (name, hash))
+        .collect();
+    let mut graph = CommitGraph::new();
+    for push_ref in refs.iter_mut() {
+        push_ref.old = advertised.get(&push_ref.dst).map(|hash| (*hash).clone());
+        push_ref.lease = leases.iter().rev().find_map(|(name, lease)| match name {
+            None => Some(lease.clone()),
+            Some(name) => {
+                let full_name = Refspec::parse(name)
+                    .ok()
+                    .and_then(|spec| spec.find_src(std::slice::from_ref(&push_ref.dst)).cloned());
+                (full_name.as_ref() == Some(&push_ref.dst)).then(|| lease.clone())
+            }
+        });
+        push_ref.status = status(push_ref, remote, advertisement, &mut graph)?;
+    }
+
+    if options.atomic && refs.iter().any(|r| matches!(r.status, Status::Rejected(_))) {
+        for push_ref in refs.iter_mut().filter(|r| r.status == Status::Pending) {
+            push_ref.status = Status::Rejected("atomic push failed");
+        }
+    }
+    Ok(())
+}
+
+fn status(
+    push_ref: &mut PushRef,
+    remote: &Remote,
+    advertisement: &Advertisement,
+    graph: &mut CommitGraph,
+) -> Result<Status> {
+    if push_ref.new.is_none() && !advertisement.has_capability("delete-refs") {
+        return Ok(Status::Rejected("remote does not support deleting refs"));
+    }
+    if push_ref.new.is_some() && push_ref.new == push_ref.old {
+        return Ok(Status::UpToDate);
+    }
+
+    let mut force = push_ref.force;
+    if let Some(lease) = &push_ref.lease {
+        let expected = match lease {
+            Lease::Expect(expected) => Some(expected.clone()),
+            Lease::Tracking => match tracking_ref(remote, &push_ref.dst) {
+                Some(tracking) => Some(resolve_ref(&tracking)?),
+                None => None,
+            },
+        };
+        if expected != Some(push_ref.old.clone()) {
+            return Ok(Status::Rejected("stale info"));
+        }
+        force = true;
+    }
+
+    let (old, new) = match (&push_ref.old, &push_ref.new) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Ok(Status::Pending),
+    };
+    let fast_forward = !push_ref.dst.starts_with("refs/tags/")
+        && Object::exists(old)?
+        && peel_kind(old.clone(), None)?.1 == ObjectKind::Commit
+        && peel_kind(new.clone(), None)?.1 == ObjectKind::Commit
+        && graph.is_ancestor(old, new)?;
+    if fast_forward {
+        return Ok(Status::Pending);
+    }
+    if force {
+        push_ref.forced_update = true;
+        return Ok(Status::Pending);
+    }
+
+    if push_ref.dst.starts_with("refs/tags/") {
+        Ok(Status::Rejected("already exists"))
+    } else if !Object::exists(old)? {
+        Ok(Status::Rejected("fetch first"))
+    } else {
+        Ok(Status::Rejected("non-fast-forward"))
+    }
+}
+
+/// the remote-tracking ref of a remote ref, as the fetch refspecs of the remote map it
+fn tracking_ref(remote: &Remote, name: &str) -> Option<String> {
+    remote.fetch.iter().find_map(|refspec| refspec.map(name))
+}
+
+/// sends the ref updates and the objects they need, and reads what the remote did with
+/// them. returns None if the remote doesn't report it.
+fn send_pack(
+    transport: &mut Transport,
+    advertisement: &Advertisement,
+    refs: &[PushRef],
+    options: &PushOptions,
+    config: &Config,
+) -> Result<Option<PushReport>> {
+    let pending: Vec<&PushRef> = refs
+        .iter()
+        .filter(|r| r.status == Status::Pending)
+        .collect();
+    let updates: Vec<RefUpdate> = pending
+        .iter()
+        .map(|r| RefUpdate {
+            old: r.old.clone(),
+            new: r.new.clone(),
+            name: r.dst.clone(),
+        })
+        .collect();
+
+    let mut caps = Vec::new();
+    let report_status = if advertisement.has_capability("report-status-v2") {
+        caps.push("report-status-v2".to_string());
+        true
+    } else if advertisement.has_capability("report-status") {
+        caps.push("report-status".to_string());
+        true
+    } else {
+        false
+    };
+    let sideband = advertisement.has_capability("side-band-64k");
+    if sideband {
+        caps.push("side-band-64k".to_string());
+    }
+    if options.quiet && advertisement.has_capability("quiet") {
+        caps.push("quiet".to_string());
+    }
+    if options.atomic {
+        caps.push("atomic".to_string());
+    }
+    if !options.push_options.is_empty() {
+        caps.push("push-options".to_string());
+    }
+    let ofs_delta = advertisement.has_capability("ofs-delta");
+    if ofs_delta {
+        caps.push("ofs-delta".to_string());
+    }
+    if advertisement.has_capability("object-format") {
+        caps.push("object-format=sha1".to_string());
+    }
+    caps.push(format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")));
+
+    let mut request = receive_pack_request(&updates, &caps, &options.push_options)?;
+    if updates.iter().any(|update| !update.is_delete()) {
+        let mut writer = PackWriter::new(PackWriterOptions {
+            window: config.get_size("pack.window")?.unwrap_or(10) as usize,
+            depth: config.get_size("pack.depth")?.unwrap_or(50) as usize,
+            ofs_delta,
+            reuse_deltas: true,
+        });
+        let tips: Vec<Hash> = updates.iter().filter_map(|u| u.new.clone()).collect();
+        add_objects(&mut writer, &tips, advertisement)?;
+        writer.write(&mut request)?;
+    }
+    transport.send(&request)?;
+    transport.finish_requests();
+
+    if !report_status {
+        return Ok(None);
+    }
+    let report = match sideband {
+        true => {
+            let mut progress = StderrProgress::new();
+            let mut stream = SidebandReader::new(transport.reader(), &mut progress);
+            let report = read_push_report(&mut PktLineReader::new(&mut stream))?;
+            io::copy(&mut stream, &mut io::sink())?;
+            report
+        }
+        false => read_push_report(transport.reader())?,
+    };
+    Ok(Some(report))
+}
+
+/// adds the objects reachable from the pushed values, but for those reachable from the
+/// refs the remote has
+fn add_objects(
+    writer: &mut PackWriter,
+    tips: &[Hash],
+    advertisement: &Advertisement,
+) -> Result<()> {
+    let mut walk = RevWalk::new();
+    let mut seen = HashSet::new();
+    let mut others = Vec::new();
+    for tip in tips {
+        let (target, kind) = peel_kind(tip.clone(), None)?;
+        if kind == ObjectKind::Commit {
+            walk.push(tip.clone())?;
+            continue;
+        }
+
+        let mut hash = tip.clone();
+        while hash != target {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            others.push(WalkedObject {
+                hash: hash.clone(),
+                kind: ObjectKind::Tag,
+                path: String::new(),
+            });
+            hash = decode_tag(object.data)?.object;
+        }
+        match kind {
+            ObjectKind::Tree => {
+                collect_tree_objects(&target, String::new(), &mut seen, &mut others)?
+            }
+            kind => others.push(WalkedObject {
+                hash: target,
+                kind,
+                path: String::new(),
+            }),
+        }
+    }
+    for (_, hash) in &advertisement.refs {
+        if Object::exists(hash)? && peel_kind(hash.clone(), None)?.1 == ObjectKind::Commit {
+            walk.hide(hash.clone())?;
+        }
+    }
+
+    let mut commits = Vec::new();
+    while let Some(commit) = walk.next_commit()? {
+        commits.push(commit.hash);
+    }
+    let mut objects: Vec<WalkedObject> = commits
+        .iter()
+        .map(|hash| WalkedObject {
+            hash: hash.clone(),
+            kind: ObjectKind::Commit,
+            path: String::new(),
+        })
+        .collect();
+    objects.extend(walk.objects(&commits)?);
+    objects.extend(others);
+
+    let mut added = HashSet::new();
+    for object in objects {
+        if added.insert(object.hash.clone()) {
+            let data = Object::read_from_hash(object.hash.to_hex())?;
+            let name = (object.kind != ObjectKind::Commit).then_some(object.path.as_str());
+            writer.add(data, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// points the remote-tracking refs of the refs the remote took at their new values
+fn update_tracking_refs(remote: &Remote, refs: &[PushRef]) -> Result<()> {
+    for push_ref in refs {
+        if !matches!(push_ref.status, Status::Ok | Status::UpToDate) {
+            continue;
+        }
+        let tracking = match tracking_ref(remote, &push_ref.dst) {
+            Some(tracking) => tracking,
+            None => continue,
+        };
+        match &push_ref.new {
+            Some(new) if resolve_ref(&tracking)?.as_ref() != Some(new) => {
+                update_ref(&tracking, new, TRACKING_REFLOG_MESSAGE)?
+            }
+            Some(_) => {}
+            None => delete_ref(&tracking)?,
+        }
+    }
+    Ok(())
+}
+
+/// reports the updates on stderr below a "To <url>" line, those that went through first
+fn print_statuses(url: &str, refs: &[PushRef]) -> Result<()> {
+    let mut header_shown = false;
+    let updated = refs.iter().filter(|r| r.status == Status::Ok);
+    let failed = refs
+        .iter()
+        .filter(|r| matches!(r.status, Status::Rejected(_) | Status::RemoteRejected(_)));
+    for push_ref in updated.chain(failed) {
+        if !header_shown {
+            eprintln!("To {}", url);
+            header_shown = true;
+        }
+
+        let (flag, summary, reason) = match &push_ref.status {
+            Status::Rejected(reason) => ('!', "[rejected]".to_string(), Some(reason.to_string())),
+            Status::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_string(), Some(reason.clone()))
+            }
+            _ => match (&push_ref.old, &push_ref.new) {
+                (_, None) => ('-', "[deleted]".to_string(), None),
+                (None, Some(_)) => {
+                    let summary = if push_ref.dst.starts_with("refs/tags/") {
+                        "[new tag]"
+                    } else if push_ref.dst.starts_with("refs/heads/") {
+                        "[new branch]"
+                    } else {
+                        "[new reference]"
+                    };
+                    ('*', summary.to_string(), None)
+                }
+                (Some(old), Some(new)) if push_ref.forced_update => (
+                    '+',
+                    format!("{}...{}", abbreviate(old)?, abbreviate(new)?),
+                    Some("forced update".to_string()),
+                ),
+                (Some(old), Some(new)) => (
+                    ' ',
+                    format!("{}..{}", abbreviate(old)?, abbreviate(new)?),
+                    None,
+                ),
+            },
+        };
+        let refs = match &push_ref.src {
+            Some(src) => format!(
+                "{} -> {}",
+                pretty_ref_name(src),
+                pretty_ref_name(&push_ref.dst)
+            ),
+            None => pretty_ref_name(&push_ref.dst).to_string(),
+        };
+        let reason = reason.map(|r| format!(" ({})", r)).unwrap_or_default();
+        eprintln!(
+            " {} {:width$} {}{}",
+            flag,
+            summary,
+            refs,
+            reason,
+            width = SUMMARY_WIDTH
+        );
+    }
+    Ok(())
+}
+
+/// shortens a ref name for display, dropping refs/heads/, refs/tags/ or refs/remotes/
+fn pretty_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_leases, Lease};
+
+    #[test]
+    fn test_parse_leases() {
+        let leases = parse_leases(&[
+            String::new(),
+            "main".to_string(),
+            "refs/heads/next:".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            leases,
+            vec![
+                (None, Lease::Tracking),
+                (Some("main".to_string()), Lease::Tracking),
+                (Some("refs/heads/next".to_string()), Lease::Expect(None)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1139 @@
+mod script;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::read_config,
+    diff::{diff_trees, make_patch, write_patch, PatchOptions, TreeDiffOptions},
+    editor::{cleanup_message, launch_editor, sequence_editor},
+    gc::auto_gc,
+    ident::{identity, Role},
+    index::{read_index, write_index, Index},
+    log::subject,
+    merge::{
+        checkout_merge, conflict_style,
+        tree::{merge_commits, TreeMergeOptions},
+    },
+    merge_base::CommitGraph,
+    objects::{commit::Commit, hash::Hash, Object},
+    refs::{
+        delete_ref, detach_head, head_branch, list_refs, reflog::append_reflog, resolve_ref,
+        update_ref, write_symbolic_ref,
+    },
+    rev_parse::{abbreviate, resolve_commit},
+    sequencer::{
+        apply_changes, edit_message, pick_options, read_author_script, read_commit,
+        todo::parse_todo, todo::parse_todo_line, todo::TodoCommand, todo::TodoItem,
+        write_author_script, write_commit, write_commit_summary,
+    },
+    worktree::{reset_hard, staged_paths, switch_trees, unstaged_paths},
+};
+
+use self::script::{autosquash, make_script, make_script_with_merges};
+
+/// the state of a rebase in progress, as kept by git
+const REBASE_DIR: &str = ".git/rebase-merge";
+const REBASE_HEAD: &str = ".git/REBASE_HEAD";
+const MERGE_HEAD: &str = ".git/MERGE_HEAD";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+const ORIG_HEAD: &str = ".git/ORIG_HEAD";
+
+/// name of the ref namespace of the labels of a todo list
+const REWRITTEN_REFS: &str = "refs/rewritten/";
+
+/// the name of the head being rebased when it isn't a branch
+const DETACHED_HEAD: &str = "detached HEAD";
+
+/// the files describing why the rebase stopped, removed when it goes on
+const STOP_FILES: [&str; 5] = ["amend", "author-script", "message", "patch", "stopped-sha"];
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup [-C | -c] <commit> = like \"squash\" but keep only the previous
+#                    commit's log message, unless -C is used, in which case
+#                    keep only this commit's message; -c is same as -C but
+#                    opens the editor
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'mgit rebase --continue')
+# d, drop <commit> = remove commit
+# l, label <label> = label current HEAD with a name
+# t, reset <label> = reset HEAD to a label
+# m, merge [-C <commit> | -c <commit>] <label> [# <oneline>]
+#         create a merge commit using the original merge commit's
+#         message (or the oneline, if no original merge commit was
+#         specified); use -c <commit> to reword the commit message
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+/// what to do with a rebase in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseAction {
+    /// go on after the rebase stopped, committing the resolved changes
+    Continue,
+    /// go on without the commit the rebase stopped at
+    Skip,
+    /// stop rebasing, and go back to the branch as it was before
+    Abort,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RebaseOptions {
+    /// the commits of the branch that aren't in upstream are rebased. defaults to the
+    /// upstream of the branch
+    pub upstream: Option<String>,
+    /// the branch to rebase, instead of the current one
+    pub branch: Option<String>,
+    /// where to put the commits, upstream by default. A...B stands for their merge base.
+    pub onto: Option<String>,
+    /// edit the todo list before starting
+    pub interactive: bool,
+    /// move the fixup!/squash!/amend! commits after the commits they refer to
+    pub autosquash: bool,
+    /// recreate the merges instead of flattening the history
+    pub rebase_merges: bool,
+    pub action: Option<RebaseAction>,
+}
+
+/// how the todo list goes on after a command
+enum Step {
+    Next,
+    /// the rebase stopped, successfully or not
+    Stop(bool),
+}
+
+/// what is being rebased, read from and written to REBASE_DIR
+struct RebaseState {
+    /// the branch rebased, or DETACHED_HEAD
+    head_name: String,
+    onto: Hash,
+    orig_head: Hash,
+}
+
+impl RebaseState {
+    fn read() -> Result<RebaseState> {
+        if !Path::new(REBASE_DIR).is_dir() {
+            bail!("No rebase in progress?");
+        }
+        let read = |name: &str| -> Result<String> {
+            Ok(fs::read_to_string(state_path(name))?.trim_end().to_string())
+        };
+        let hash = |name: &str| -> Result<Hash> { Hash::try_from(read(name)?.as_bytes()) };
+
+        Ok(RebaseState {
+            head_name: read("head-name")?,
+            onto: hash("onto")?,
+            orig_head: hash("orig-head")?,
+        })
+    }
+
+    fn write(&self, interactive: bool) -> Result<()> {
+        fs::create_dir_all(REBASE_DIR)?;
+        fs::write(state_path("head-name"), format!("{}\n", self.head_name))?;
+        fs::write(state_path("onto"), format!("{:x}\n", self.onto))?;
+        fs::write(state_path("orig-head"), format!("{:x}\n", self.orig_head))?;
+        fs::write(state_path("interactive"), "")?;
+        fs::write(state_path("no-reschedule-failed-exec"), "")?;
+        if !interactive {
+            fs::write(state_path("drop_redundant_commits"), "")?;
+        }
+        Ok(())
+    }
+}
+
+fn state_path(name: &str) -> PathBuf {
+    Path::new(REBASE_DIR).join(name)
+}
+
+/// reapplies the commits of a branch on top of another base. returns false if the rebase
+/// stopped on conflicts or on a failed command.
+pub fn rebase(options: RebaseOptions) -> Result<bool> {
+    match options.action {
+        Some(RebaseAction::Continue) => continue_rebase(),
+        Some(RebaseAction::Skip) => skip(),
+        Some(RebaseAction::Abort) => abort(),
+        None => start(&options),
+    }
+}
+
+fn start(options: &RebaseOptions) -> Result<bool> {
+    if Path::new(REBASE_DIR).exists() {
+        bail!(
+            "It seems that there is already a rebase-merge directory, and\n\
+            I wonder if you are in the middle of another rebase.  If that is the\n\
+            case, please try\n\tmgit rebase (--continue | --abort | --skip)\n\
+            If that is not the case, please\n\trm -fr \"{}\"\n\
+            and run me again.  I am stopping in case you still have something\n\
+            valuable there.",
+            REBASE_DIR
+        );
+    }
+
+    let (head_name, orig_head) = match &options.branch {
+        Some(branch) => match resolve_ref(&format!("refs/heads/{}", branch))? {
+            Some(hash) => (format!("refs/heads/{}", branch), hash),
+            None => (String::from(DETACHED_HEAD), resolve_commit(branch)?),
+        },
+        None => {
+            let head = resolve_commit("HEAD")?;
+            (head_branch()?.unwrap_or(String::from(DETACHED_HEAD)), head)
+        }
+    };
+    require_clean_worktree()?;
+
+    let upstream_name = match &options.upstream {
+        Some(upstream) => upstream.clone(),
+        None => configured_upstream(&head_name)?.ok_or(anyhow!(
+            "There is no tracking information for the current branch.\n\
+            Please specify which branch you want to rebase against."
+        ))?,
+    };
+    let upstream = resolve_commit(&upstream_name)?;
+    let onto_name = options.onto.clone().unwrap_or(upstream_name.clone());
+    let onto = resolve_onto(&onto_name)?;
+
+    let mut graph = CommitGraph::new();
+    if !options.interactive && !options.rebase_merges {
+        let merge_base = graph.merge_bases(&onto, std::slice::from_ref(&orig_head))?;
+        let up_to_date = merge_base.first() == Some(&onto)
+            && (upstream == onto
+                || graph
+                    .merge_bases(&upstream, std::slice::from_ref(&orig_head))?
+                    .first()
+                    == Some(&onto))
+            && is_linear(&orig_head, &onto)?;
+        if up_to_date {
+            if options.branch.is_some() {
+                checkout_branch(&head_name, &orig_head)?;
+            }
+            let name = head_name.strip_prefix("refs/heads/").unwrap_or("HEAD");
+            println!("Current branch {} is up to date.", name);
+            return Ok(true);
+        }
+    }
+
+    let mut lines = match options.rebase_merges {
+        true => make_script_with_merges(&upstream, &orig_head)?,
+        false => make_script(&upstream, &onto, &orig_head)?,
+    };
+    let config = read_config()?;
+    let autosquash_configured = config.get_bool("rebase.autosquash")?.unwrap_or(false);
+    if options.autosquash || (options.interactive && autosquash_configured) {
+        let items = autosquash(parse_todo(&lines.join("\n"))?)?;
+        lines = items
+            .iter()
+            .map(|item| item.format(false))
+            .collect::<Result<_>>()?;
+    }
+    if lines.is_empty() {
+        lines.push(String::from("noop"));
+    }
+
+    let state = RebaseState {
+        head_name,
+        onto: onto.clone(),
+        orig_head: orig_head.clone(),
+    };
+    state.write(options.interactive)?;
+
+    let commands = parse_todo(&lines.join("\n"))?.len();
+    let help = format!(
+        "\n# Rebase {}..{} onto {} ({} command{})\n#{}",
+        abbreviate(&upstream)?,
+        abbreviate(&orig_head)?,
+        abbreviate(&onto)?,
+        commands,
+        if commands == 1 { "" } else { "s" },
+        TODO_HELP
+    );
+    let todo = format!("{}\n{}", lines.join("\n"), help);
+    fs::write(state_path("git-rebase-todo.backup"), &todo)?;
+
+    let items = match options.interactive {
+        true => {
+            let items = edit_todo(&lines, &help)?;
+            if items.is_empty() {
+                fs::remove_dir_all(REBASE_DIR)?;
+                bail!("nothing to do");
+            }
+            items
+        }
+        false => parse_todo(&lines.join("\n"))?,
+    };
+    let mut todo = items
+        .iter()
+        .map(|item| item.format(false))
+        .collect::<Result<Vec<_>>>()?;
+    fs::write(ORIG_HEAD, format!("{:x}\n", orig_head))?;
+
+    // the first picks of commits already on top of onto don't need to be redone
+    let mut start = onto;
+    let mut done = 0;
+    for item in &items {
+        match (&item.command, &item.commit) {
+            (TodoCommand::Pick, Some(commit))
+                if read_commit(commit)?.parents == [start.clone()] =>
+            {
+                start = commit.clone();
+                done += 1;
+            }
+            _ => break,
+        }
+    }
+    let skipped: Vec<String> = todo.drain(..done).collect();
+    write_todo(&todo)?;
+    if !skipped.is_empty() {
+        fs::write(state_path("done"), format!("{}\n", skipped.join("\n")))?;
+    }
+    fs::write(state_path("msgnum"), format!("{}\n", done))?;
+
+    let index = read_index()?;
+    let head_tree = read_commit(&resolve_commit("HEAD")?)?.tree;
+    let switched = switch_trees(
+        &index,
+        Some(&head_tree),
+        &read_commit(&start)?.tree,
+        "checkout",
+    )?;
+    write_index(&switched)?;
+    detach_head(&start, &format!("rebase (start): checkout {}", onto_name))?;
+
+    run_todo(&state)
+}
+
+/// lets the user edit the todo list, shown with abbreviated hashes, and parses it back
+fn edit_todo(lines: &[String], help: &str) -> Result<Vec<TodoItem>> {
+    let mut shown = Vec::new();
+    for line in lines {
+        match line.is_empty() || line.starts_with('#') {
+            true => shown.push(line.clone()),
+            false => shown.push(parse_todo_line(line)?.format(true)?),
+        }
+    }
+    let path = state_path("git-rebase-todo");
+    fs::write(&path, format!("{}\n{}", shown.join("\n"), help))?;
+    launch_editor(&sequence_editor()?, &path)?;
+
+    match parse_todo(&fs::read_to_string(&path)?) {
+        Ok(items) => Ok(items),
+        Err(err) => {
+            fs::remove_dir_all(REBASE_DIR)?;
+            Err(err)
+        }
+    }
+}
+
+/// runs the commands of the todo list until it is empty or a command stops the rebase
+fn run_todo(state: &RebaseState) -> Result<bool> {
+    loop {
+        let todo = fs::read_to_string(state_path("git-rebase-todo"))?;
+        let mut lines = todo.lines().filter(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        });
+        let line = match lines.next() {
+            Some(line) => line,
+            None => return finish(state),
+        };
+        let remaining: Vec<String> = lines.map(|line| line.to_string()).collect();
+        let item = parse_todo_line(line.trim())
+            .map_err(|err| anyhow!("invalid line: {}\n{}", line, err))?;
+
+        write_todo(&remaining)?;
+        append_done(&item.format(false)?)?;
+        let msgnum = read_number("msgnum")? + 1;
+        fs::write(state_path("msgnum"), format!("{}\n", msgnum))?;
+        fs::write(state_path("end"), format!("{}\n", msgnum + remaining.len()))?;
+        eprint!("Rebasing ({}/{})\r", msgnum, msgnum + remaining.len());
+
+        let step = run_command(&item)?;
+        if let (true, Some(commit)) = (item.command.picks(), &item.commit) {
+            if item.command != TodoCommand::Drop && !matches!(step, Step::Stop(false)) {
+                record_rewritten(commit)?;
+            }
+        }
+        if let Step::Stop(succeeded) = step {
+            return Ok(succeeded);
+        }
+    }
+}
+
+/// clears the "Rebasing (n/m)" line before a message takes its place. terminals that can't
+/// clear a line get it overwritten with spaces.
+fn clear_progress() {
+    match env::var("TERM") {
+        Ok(term) if term != "dumb" => eprint!("\r\x1b[K"),
+        _ => eprint!("\r{:80}\r", ""),
+    }
+}
+
+fn run_command(item: &TodoItem) -> Result<Step> {
+    match item.command {
+        TodoCommand::Noop | TodoCommand::Drop => Ok(Step::Next),
+        TodoCommand::Exec => exec(&item.arg),
+        TodoCommand::Break => {
+            let head = resolve_commit("HEAD")?;
+            let commit = read_commit(&head)?;
+            clear_progress();
+            println!(
+                "Stopped at {} ({})",
+                abbreviate(&head)?,
+                subject(&commit.message)
+            );
+            Ok(Step::Stop(true))
+        }
+        TodoCommand::Label => label(&item.arg),
+        TodoCommand::Reset => reset(&item.arg),
+        TodoCommand::Merge => merge(item),
+        _ => pick(item),
+    }
+}
+
+/// applies a commit onto HEAD for pick, reword, edit, squash and fixup
+fn pick(item: &TodoItem) -> Result<Step> {
+    let hash = item.commit.clone().expect("picks have a commit");
+    let commit = read_commit(&hash)?;
+    let head = resolve_commit("HEAD")?;
+    let head_commit = read_commit(&head)?;
+    if commit.parents.len() > 1 {
+        bail!("commit {:x} is a merge but no -m option was given.", hash);
+    }
+    let parent = commit.parents.first();
+
+    // a commit already on top of HEAD is kept as is
+    if !item.command.is_fixup() && parent == Some(&head) {
+        let index = read_index()?;
+        let switched = switch_trees(&index, Some(&head_commit.tree), &commit.tree, "merge")?;
+        write_index(&switched)?;
+        update_ref("HEAD", &hash, "rebase: fast-forward")?;
+        return match item.command {
+            TodoCommand::Reword => reword_head(),
+            TodoCommand::Edit => stop_for_edit(&hash, &commit),
+            _ => Ok(Step::Next),
+        };
+    }
+
+    let base = match parent {
+        Some(parent) => Some(read_commit(parent)?.tree),
+        None => None,
+    };
+    let options = pick_options(&hash, &commit)?;
+    let index = read_index()?;
+    let result = apply_changes(
+        &index,
+        base.as_ref(),
+        &head_commit.tree,
+        &commit.tree,
+        &options,
+        "merge",
+    )?;
+    if !result.is_clean() {
+        return stop_on_conflict(&hash, &commit, &result.conflicted_paths());
+    }
+    let tree = result.index().write_tree()?;
+
+    if item.command.is_fixup() {
+        commit_fixup(item, &commit, tree)?;
+        return Ok(Step::Next);
+    }
+    // commits whose changes are already there are dropped
+    if tree == head_commit.tree && base.as_ref() != Some(&commit.tree) {
+        return Ok(Step::Next);
+    }
+
+    let reflog = format!(
+        "rebase ({}): {}",
+        item.command.name(),
+        subject(&commit.message)
+    );
+    write_commit(
+        tree,
+        vec![head],
+        commit.author.clone(),
+        &commit.message,
+        &reflog,
+    )?;
+    match item.command {
+        TodoCommand::Reword => reword_head(),
+        TodoCommand::Edit => stop_for_edit(&hash, &commit),
+        _ => Ok(Step::Next),
+    }
+}
+
+/// lets the user edit the message of the commit just picked, amending it
+fn reword_head() -> Result<Step> {
+    let head = read_commit(&resolve_commit("HEAD")?)?;
+    let message = edit_message(&head.message)?;
+    let reflog = format!("rebase (reword): {}", subject(&message));
+    let new = write_commit(head.tree, head.parents, head.author, &message, &reflog)?;
+    write_commit_summary(&new, true)?;
+    Ok(Step::Next)
+}
+
+/// melds a squash or a fixup into HEAD, with the tree given. the messages of the chain of
+/// squashes and fixups are kept in message-squash, and edited at the end of the chain if
+/// one of them is a squash.
+fn commit_fixup(item: &TodoItem, commit: &Commit, tree: Hash) -> Result<()> {
+    let head = resolve_commit("HEAD")?;
+    let head_commit = read_commit(&head)?;
+    let hash = item.commit.as_ref().expect("fixups have a commit");
+
+    let fixups_path = state_path("current-fixups");
+    let mut fixups = match fixups_path.is_file() {
+        true => fs::read_to_string(&fixups_path)?,
+        false => String::new(),
+    };
+    let count = fixups.lines().count() + 2;
+    let mut message = match fixups.is_empty() {
+        true => format!(
+            "# This is a combination of 2 commits.\n# This is the 1st commit message:\n\n{}\n",
+            head_commit.message
+        ),
+        false => {
+            let message = fs::read_to_string(state_path("message-squash"))?;
+            let (_, rest) = message.split_once('\n').unwrap_or(("", &message));
+            format!("# This is a combination of {} commits.\n{}", count, rest)
+        }
+    };
+
+    fixups.push_str(&format!("{} {:x}\n", item.command.name(), hash));
+    let squashing = fixups.lines().any(|line| line.starts_with("squash "));
+    let edit = squashing || item.flag == Some('c');
+
+    if item.flag.is_some() {
+        // the message of the commit replaces the previous ones
+        message = message
+            .lines()
+            .map(|line| match line.is_empty() || line.starts_with('#') {
+                true => format!("{}\n", line),
+                false => format!("# {}\n", line),
+            })
+            .collect();
+    }
+    match item.command == TodoCommand::Squash || item.flag.is_some() {
+        true => {
+            // the subjects naming the commit to meld into are left out
+            let body = match commit.message.split_once('\n') {
+                Some((first, rest)) if is_fixup_subject(first, squashing) => {
+                    format!("# {}\n{}", first, rest)
+                }
+                None if is_fixup_subject(&commit.message, squashing) => {
+                    format!("# {}", commit.message)
+                }
+                _ => commit.message.clone(),
+            };
+            message.push_str(&format!(
+                "\n# This is the commit message #{}:\n\n{}\n",
+                count, body
+            ));
+        }
+        false => {
+            message.push_str(&format!(
+                "\n# The commit message #{} will be skipped:\n\n",
+                count
+            ));
+            for line in commit.message.lines() {
+                match line.is_empty() {
+                    true => message.push_str("#\n"),
+                    false => message.push_str(&format!("# {}\n", line)),
+                }
+            }
+        }
+    }
+
+    fs::write(&fixups_path, &fixups)?;
+    fs::write(state_path("message-squash"), &message)?;
+
+    let last = !next_command()?.is_some_and(|command| command.is_fixup());
+    let final_message = match last && edit {
+        true => edit_message(&message)?,
+        false => cleanup_message(&message, true),
+    };
+    let reflog = format!(
+        "rebase ({}): {}",
+        item.command.name(),
+        subject(&final_message)
+    );
+    let parents = head_commit.parents.clone();
+    let new = write_commit(tree, parents, head_commit.author, &final_message, &reflog)?;
+
+    if last {
+        fs::remove_file(&fixups_path)?;
+        fs::remove_file(state_path("message-squash"))?;
+        if edit {
+            write_commit_summary(&new, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// returns true for the subjects made by commit --fixup, which don't belong in the message
+/// of the commit melded into
+fn is_fixup_subject(subject: &str, squashing: bool) -> bool {
+    subject.starts_with("amend!")
+        || (squashing && (subject.starts_with("squash!") || subject.starts_with("fixup!")))
+}
+
+/// the command following the current one, if any
+fn next_command() -> Result<Option<TodoCommand>> {
+    let todo = fs::read_to_string(state_path("git-rebase-todo"))?;
+    let line = todo
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'));
+    Ok(line
+        .and_then(|line| line.split_whitespace().next())
+        .and_then(|command| TodoCommand::try_from(command).ok()))
+}
+
+fn exec(command: &str) -> Result<Step> {
+    clear_progress();
+    eprintln!("Executing: {}", command);
+    let status = Command::new("sh").arg("-c").arg(command).status()?;
+    if !status.success() {
+        eprintln!(
+            "warning: execution failed: {}\nYou can fix the problem, and then run\n\n  mgit rebase --continue\n",
+            command
+        );
+        return Ok(Step::Stop(false));
+    }
+
+    if require_clean_worktree().is_err() {
+        eprintln!(
+            "warning: execution succeeded: {}\nbut left changes to the index and/or the working tree\n\
+            Commit or stash your changes, and then run\n\n  mgit rebase --continue\n",
+            command
+        );
+        return Ok(Step::Stop(false));
+    }
+    Ok(Step::Next)
+}
+
+/// names HEAD, so that later commands can refer to it
+fn label(name: &str) -> Result<Step> {
+    if name.contains(char::is_whitespace) || name.starts_with('#') || name.contains("..") {
+        bail!("'{}' is not a valid label", name);
+    }
+    let head = resolve_commit("HEAD")?;
+    update_ref(&format!("{}{}", REWRITTEN_REFS, name), &head, "")?;
+    Ok(Step::Next)
+}
+
+/// moves HEAD, the index and the working tree to a label or a commit
+fn reset(arg: &str) -> Result<Step> {
+    let name = arg.split_whitespace().next().unwrap_or_default();
+    if name == "[new" {
+        bail!("resetting to a new root is not supported");
+    }
+    let target = resolve_label(name)?;
+    let head = resolve_commit("HEAD")?;
+    if target == head {
+        return Ok(Step::Next);
+    }
+
+    let index = read_index()?;
+    let from = read_commit(&head)?.tree;
+    let switched = switch_trees(&index, Some(&from), &read_commit(&target)?.tree, "merge")?;
+    write_index(&switched)?;
+    detach_head(&target, &format!("rebase (reset): '{}'", name))?;
+    Ok(Step::Next)
+}
+
+/// merges a label into HEAD, reusing the message of the original merge given with -C or -c
+fn merge(item: &TodoItem) -> Result<Step> {
+    let (labels, oneline) = match item.arg.split_once('#') {
+        Some((labels, oneline)) => (labels.trim(), Some(oneline.trim())),
+        None => (item.arg.trim(), None),
+    };
+    let labels: Vec<&str> = labels.split_whitespace().collect();
+    let name = match labels.as_slice() {
+        [name] => *name,
+        _ => bail!("octopus merges are not supported"),
+    };
+    let target = resolve_label(name)?;
+    let head = resolve_commit("HEAD")?;
+    let original = item.commit.as_ref().map(read_commit).transpose()?;
+
+    // the original merge is kept if it already merges the same commits
+    if let (Some(hash), Some(original)) = (&item.commit, &original) {
+        if item.flag == Some('C') && original.parents == [head.clone(), target.clone()] {
+            let index = read_index()?;
+            let from = read_commit(&head)?.tree;
+            write_index(&switch_trees(&index, Some(&from), &original.tree, "merge")?)?;
+            update_ref("HEAD", hash, "rebase: fast-forward")?;
+            return Ok(Step::Next);
+        }
+    }
+
+    let (author, message) = match &original {
+        Some(original) => (original.author.clone(), original.message.clone()),
+        None => (
+            identity(Role::Author)?,
+            match oneline {
+                Some(oneline) if !oneline.is_empty() => oneline.to_string(),
+                _ => format!("Merge branch '{}'", name),
+            },
+        ),
+    };
+
+    let options = TreeMergeOptions {
+        theirs_label: name.to_string(),
+        style: conflict_style()?,
+        ..Default::default()
+    };
+    let result = merge_commits(&head, &target, &options)?;
+    checkout_merge(&read_index()?, &result, "merge")?;
+    for message in &result.messages {
+        println!("{}", message);
+    }
+
+    if !result.is_clean() {
+        fs::write(MERGE_HEAD, format!("{:x}\n", target))?;
+        fs::write(MERGE_MSG, format!("{}\n", message))?;
+        write_author_script(&state_path("author-script"), &author)?;
+        fs::write(state_path("message"), format!("{}\n", message))?;
+        match &item.commit {
+            Some(hash) => {
+                fs::write(REBASE_HEAD, format!("{:x}\n", hash))?;
+                eprintln!("Could not apply {}... {}", abbreviate(hash)?, item.arg);
+            }
+            None => eprintln!("Could not merge {}", item.arg),
+        }
+        return Ok(Step::Stop(false));
+    }
+
+    let message = match item.flag {
+        Some('c') => edit_message(&message)?,
+        _ => message,
+    };
+    let tree = result.index().write_tree()?;
+    // like git, merges are logged as picks
+    let reflog = format!("rebase (pick): {}", subject(&message));
+    write_commit(tree, vec![head, target], author, &message, &reflog)?;
+    Ok(Step::Next)
+}
+
+/// the commit of a label, or the commit given instead
+fn resolve_label(name: &str) -> Result<Hash> {
+    match resolve_ref(&format!("{}{}", REWRITTEN_REFS, name))? {
+        Some(hash) => Ok(hash),
+        None => resolve_commit(name).map_err(|_| anyhow!("could not resolve '{}'", name)),
+    }
+}
+
+/// records the commit the rebase stopped at, so that it can be committed once resolved
+fn write_stop_state(hash: &Hash, commit: &Commit) -> Result<()> {
+    fs::write(state_path("stopped-sha"), format!("{:x}\n", hash))?;
+    fs::write(state_path("message"), format!("{}\n", commit.message))?;
+    write_author_script(&state_path("author-script"), &commit.author)?;
+    write_commit_patch(commit, &state_path("patch"))?;
+    fs::write(REBASE_HEAD, format!("{:x}\n", hash))?;
+    Ok(())
+}
+
+fn stop_on_conflict(hash: &Hash, commit: &Commit, conflicts: &[&str]) -> Result<Step> {
+    write_stop_state(hash, commit)?;
+    let mut merge_msg = format!("{}\n\n# Conflicts:\n", commit.message);
+    for path in conflicts {
+        merge_msg.push_str(&format!("#\t{}\n", path));
+    }
+    fs::write(MERGE_MSG, merge_msg)?;
+
+    let description = format!("{}... {}", abbreviate(hash)?, subject(&commit.message));
+    eprintln!("error: could not apply {}", description);
+    print_conflict_hints();
+    eprintln!("Could not apply {}", description);
+    Ok(Step::Stop(false))
+}
+
+fn print_conflict_hints() {
+    eprintln!(
+        "hint: Resolve all conflicts manually, mark them as resolved with\n\
+        hint: \"git add/rm <conflicted_files>\", then run \"mgit rebase --continue\".\n\
+        hint: You can instead skip this commit: run \"mgit rebase --skip\".\n\
+        hint: To abort and get back to the state before \"mgit rebase\", run \"mgit rebase --abort\"."
+    );
+}
+
+fn stop_for_edit(hash: &Hash, commit: &Commit) -> Result<Step> {
+    write_stop_state(hash, commit)?;
+    fs::write(
+        state_path("amend"),
+        format!("{:x}\n", resolve_commit("HEAD")?),
+    )?;
+    clear_progress();
+    println!(
+        "Stopped at {}...  {}\n\
+        You can amend the commit now. Once you are satisfied with your changes, stage them and run\n\n  \
+        mgit rebase --continue",
+        abbreviate(hash)?,
+        subject(&commit.message)
+    );
+    Ok(Step::Stop(true))
+}
+
+/// writes the changes of a commit as a patch, for the user to look at when the rebase stops
+fn write_commit_patch(commit: &Commit, path: &Path) -> Result<()> {
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(read_commit(parent)?.tree),
+        None => None,
+    };
+    let options = TreeDiffOptions {
+        recursive: true,
+        ..Default::default()
+    };
+
+    let mut patch = Vec::new();
+    for change in diff_trees(parent_tree.as_ref(), Some(&commit.tree), &options)? {
+        let read = |hash: Option<&Hash>| -> Result<Vec<u8>> {
+            match hash {
+                Some(hash) => Ok(Object::read_from_hash(hash.to_hex())?.data),
+                None => Ok(Vec::new()),
+            }
+        };
+        let old = read(change.old.as_ref().map(|file| &file.hash))?;
+        let new = read(change.new.as_ref().map(|file| &file.hash))?;
+        let file_patch = make_patch(change, &old, &new, &PatchOptions::default());
+        write_patch(&mut patch, &file_patch, &PatchOptions::default())?;
+    }
+    fs::write(path, patch)?;
+    Ok(())
+}
+
+fn continue_rebase() -> Result<bool> {
+    let state = RebaseState::read()?;
+    let index = read_index()?;
+    if index.has_conflicts() {
+        bail!(
+            "Committing is not possible because you have unmerged files.\n\
+            hint: Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+            hint: as appropriate to mark resolution and make a commit."
+        );
+    }
+    if !unstaged_paths(&index)?.is_empty() {
+        bail!("cannot rebase: You have unstaged changes.\nPlease commit or stash them.");
+    }
+
+    let head = resolve_commit("HEAD")?;
+    let head_commit = read_commit(&head)?;
+    let tree = index.write_tree()?;
+    if tree != head_commit.tree || Path::new(MERGE_HEAD).is_file() {
+        commit_resolution(&head, head_commit, tree)?;
+    }
+
+    let done = fs::read_to_string(state_path("done"))?;
+    if let Some(line) = done.lines().last() {
+        let last = parse_todo_line(line)?;
+        if let (true, Some(commit)) = (last.command.picks(), &last.commit) {
+            if !state_path("amend").is_file() {
+                record_rewritten(commit)?;
+            }
+        }
+    }
+
+    clear_stop_state()?;
+    run_todo(&state)
+}
+
+/// commits the changes staged while the rebase was stopped: the amended commit of an edit,
+/// or the resolved conflicts of the last command
+fn commit_resolution(head: &Hash, head_commit: Commit, tree: Hash) -> Result<()> {
+    let amend = state_path("amend");
+    if amend.is_file() {
+        if Hash::try_from(fs::read_to_string(&amend)?.trim().as_bytes())? != *head {
+            bail!(
+                "You have uncommitted changes in your working tree. Please, commit them\n\
+                first and then run 'mgit rebase --continue' again."
+            );
+        }
+        let message = edit_message(&head_commit.message)?;
+        let reflog = format!("rebase (continue): {}", subject(&message));
+        let new = write_commit(
+            tree,
+            head_commit.parents,
+            head_commit.author,
+            &message,
+            &reflog,
+        )?;
+        return write_commit_summary(&new, true);
+    }
+
+    let done = fs::read_to_string(state_path("done"))?;
+    let last = done
+        .lines()
+        .last()
+        .map(parse_todo_line)
+        .transpose()?
+        .ok_or(anyhow!("no command was done"))?;
+    if last.command.is_fixup() {
+        let commit = read_commit(last.commit.as_ref().expect("fixups have a commit"))?;
+        return commit_fixup(&last, &commit, tree);
+    }
+
+    let author_script = state_path("author-script");
+    let author = match author_script.is_file() {
+        true => read_author_script(&author_script)?,
+        false => identity(Role::Author)?,
+    };
+    let message = match Path::new(MERGE_MSG).is_file() {
+        true => fs::read_to_string(MERGE_MSG)?,
+        false => fs::read_to_string(state_path("message"))?,
+    };
+    let message = edit_message(&message)?;
+
+    let mut parents = vec![head.clone()];
+    if Path::new(MERGE_HEAD).is_file() {
+        parents.push(Hash::try_from(
+            fs::read_to_string(MERGE_HEAD)?.trim().as_bytes(),
+        )?);
+    }
+    let reflog = format!("rebase (continue): {}", subject(&message));
+    let new = write_commit(tree, parents, author, &message, &reflog)?;
+    write_commit_summary(&new, false)
+}
+
+fn skip() -> Result<bool> {
+    let state = RebaseState::read()?;
+    let index = read_index()?;
+    let head_tree = read_commit(&resolve_commit("HEAD")?)?.tree;
+    write_index(&reset_hard(&index, &head_tree)?)?;
+
+    clear_stop_state()?;
+    run_todo(&state)
+}
+
+fn abort() -> Result<bool> {
+    let state = RebaseState::read()?;
+    let index = read_index()?;
+    let orig_tree = read_commit(&state.orig_head)?.tree;
+    write_index(&reset_hard(&index, &orig_tree)?)?;
+
+    let message = format!("rebase (abort): returning to {}", state.head_name);
+    match state.head_name.starts_with("refs/") {
+        true => {
+            let head = resolve_commit("HEAD")?;
+            write_symbolic_ref("HEAD", &state.head_name)?;
+            append_reflog("HEAD", Some(&head), &state.orig_head, &message)?;
+        }
+        false => detach_head(&state.orig_head, &message)?,
+    }
+
+    clear_stop_state()?;
+    remove_state()?;
+    Ok(true)
+}
+
+/// points the rebased branch at the new commits, and goes back to it
+fn finish(state: &RebaseState) -> Result<bool> {
+    let head = resolve_commit("HEAD")?;
+    if state.head_name.starts_with("refs/") {
+        let message = format!("rebase (finish): {} onto {:x}", state.head_name, state.onto);
+        update_ref(&state.head_name, &head, &message)?;
+        write_symbolic_ref("HEAD", &state.head_name)?;
+        let message = format!("rebase (finish): returning to {}", state.head_name);
+        append_reflog("HEAD", Some(&head), &head, &message)?;
+    }
+
+    remove_state()?;
+    clear_progress();
+    eprintln!("Successfully rebased and updated {}.", state.head_name);
+    auto_gc(false)?;
+    Ok(true)
+}
+
+/// records that a commit was rewritten. the commits of a chain of fixups are all rewritten
+/// into the commit at its end.
+fn record_rewritten(commit: &Hash) -> Result<()> {
+    let pending_path = state_path("rewritten-pending");
+    let mut pending = match pending_path.is_file() {
+        true => fs::read_to_string(&pending_path)?,
+        false => String::new(),
+    };
+    pending.push_str(&format!("{:x}\n", commit));
+    if next_command()?.is_some_and(|command| command.is_fixup()) {
+        fs::write(pending_path, pending)?;
+        return Ok(());
+    }
+
+    let head = resolve_commit("HEAD")?;
+    let list_path = state_path("rewritten-list");
+    let mut list = match list_path.is_file() {
+        true => fs::read_to_string(&list_path)?,
+        false => String::new(),
+    };
+    for old in pending.lines() {
+        list.push_str(&format!("{} {:x}\n", old, head));
+    }
+    fs::write(list_path, list)?;
+    if pending_path.is_file() {
+        fs::remove_file(pending_path)?;
+    }
+    Ok(())
+}
+
+fn clear_stop_state() -> Result<()> {
+    for file in [REBASE_HEAD, MERGE_HEAD, MERGE_MSG] {
+        if Path::new(file).is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    for name in STOP_FILES {
+        let path = state_path(name);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// removes the state directory and the labels
+fn remove_state() -> Result<()> {
+    for (name, _) in list_refs(REWRITTEN_REFS.trim_end_matches('/'))? {
+        delete_ref(&name)?;
+    }
+    fs::remove_dir_all(REBASE_DIR)?;
+    Ok(())
+}
+
+fn write_todo(lines: &[String]) -> Result<()> {
+    let content = lines
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    fs::write(state_path("git-rebase-todo"), content)?;
+    Ok(())
+}
+
+fn append_done(line: &str) -> Result<()> {
+    let path = state_path("done");
+    let mut done = match path.is_file() {
+        true => fs::read_to_string(&path)?,
+        false => String::new(),
+    };
+    done.push_str(line);
+    done.push('\n');
+    fs::write(path, done)?;
+    Ok(())
+}
+
+fn read_number(name: &str) -> Result<usize> {
+    let path = state_path(name);
+    match path.is_file() {
+        true => Ok(fs::read_to_string(path)?.trim().parse()?),
+        false => Ok(0),
+    }
+}
+
+/// rebasing needs the index and the working tree to match HEAD
+fn require_clean_worktree() -> Result<()> {
+    let index = read_index()?;
+    let head_tree = read_commit(&resolve_commit("HEAD")?)?.tree;
+    let staged = !staged_paths(&index, &Index::from_tree(&head_tree)?).is_empty();
+    let unstaged = !unstaged_paths(&index)?.is_empty();
+
+    match (unstaged, staged) {
+        (true, true) => bail!(
+            "cannot rebase: You have unstaged changes.\n\
+            additionally, your index contains uncommitted changes.\n\
+            Please commit or stash them."
+        ),
+        (true, false) => {
+            bail!("cannot rebase: You have unstaged changes.\nPlease commit or stash them.")
+        }
+        (false, true) => bail!(
+            "cannot rebase: Your index contains uncommitted changes.\nPlease commit or stash them."
+        ),
+        (false, false) => Ok(()),
+    }
+}
+
+/// the upstream configured for a branch with branch.<name>.remote and branch.<name>.merge
+fn configured_upstream(head_name: &str) -> Result<Option<String>> {
+    let branch = match head_name.strip_prefix("refs/heads/") {
+        Some(branch) => branch,
+        None => return Ok(None),
+    };
+    let config = read_config()?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+
+    Ok(match (remote, merge) {
+        (Some("."), Some(merge)) => Some(merge.to_string()),
+        (Some(remote), Some(merge)) => {
+            let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+            Some(format!("refs/remotes/{}/{}", remote, merge))
+        }
+        _ => None,
+    })
+}
+
+/// resolves the new base, where A...B stands for the merge base of A and B
+fn resolve_onto(name: &str) -> Result<Hash> {
+    let (left, right) = match name.split_once("...") {
+        Some(sides) => sides,
+        None => return resolve_commit(name),
+    };
+    let or_head = |side: &str| match side.is_empty() {
+        true => resolve_commit("HEAD"),
+        false => resolve_commit(side),
+    };
+    let bases = CommitGraph::new().merge_bases(&or_head(left)?, &[or_head(right)?])?;
+    match bases.as_slice() {
+        [base] => Ok(base.clone()),
+        [] => bail!("{}: there is no merge base", name),
+        _ => bail!("{}: there are more than one merge bases", name),
+    }
+}
+
+/// returns true if there is no merge between the base and the head
+fn is_linear(head: &Hash, base: &Hash) -> Result<bool> {
+    let mut current = head.clone();
+    while current != *base {
+        let commit = read_commit(&current)?;
+        match commit.parents.as_slice() {
+            [parent] => current = parent.clone(),
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// checks out the branch given to rebase when there is nothing to rebase
+fn checkout_branch(head_name: &str, commit: &Hash) -> Result<()> {
+    let index = read_index()?;
+    let head = resolve_commit("HEAD")?;
+    let from = read_commit(&head)?.tree;
+    write_index(&switch_trees(
+        &index,
+        Some(&from),
+        &read_commit(commit)?.tree,
+        "checkout",
+    )?)?;
+
+    let message = format!(
+        "rebase: checkout {}",
+        head_name.trim_start_matches("refs/heads/")
+    );
+    match head_name.starts_with("refs/") {
+        true => {
+            write_symbolic_ref("HEAD", head_name)?;
+            append_reflog("HEAD", Some(&head), commit, &message)
+        }
+        false => detach_head(commit, &message),
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::{
+    diff::{
+        diff_trees, is_binary, make_hunks, DiffFile, LineDiffOptions, LineOrigin, TreeDiffOptions,
+    },
+    log::subject,
+    merge_base::CommitGraph,
+    objects::{
+        commit::Commit,
+        hash::{hash, Hash},
+        Object,
+    },
+    rev_parse::abbreviate,
+    revwalk::{CommitOrder, RevWalk},
+    sequencer::{
+        read_commit,
+        todo::{TodoCommand, TodoItem},
+    },
+};
+
+/// the commits of a range, oldest first and parents before children
+fn list_commits(include: &Hash, exclude: &Hash) -> Result<Vec<(Hash, Commit)>> {
+    let mut walk = RevWalk::new();
+    walk.order(CommitOrder::Topo);
+    walk.reverse(true);
+    walk.push(include.clone())?;
+    walk.hide(exclude.clone())?;
+
+    let mut commits = Vec::new();
+    while let Some(entry) = walk.next_commit()? {
+        commits.push((entry.hash, entry.commit));
+    }
+    Ok(commits)
+}
+
+/// builds the todo list picking the commits of upstream..head that aren't merges, leaving
+/// out the ones whose changes onto already has
+pub fn make_script(upstream: &Hash, onto: &Hash, head: &Hash) -> Result<Vec<String>> {
+    let commits = list_commits(head, upstream)?;
+
+    // the changes of the commits only found on the side of onto
+    let mut applied = HashSet::new();
+    for (_, commit) in list_commits(onto, head)? {
+        if commit.parents.len() <= 1 {
+            applied.insert(patch_id(&commit)?);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut skipped = false;
+    for (hash, commit) in commits {
+        if commit.parents.len() > 1 {
+            continue;
+        }
+        if !applied.is_empty() && applied.contains(&patch_id(&commit)?) {
+            eprintln!(
+                "warning: skipped previously applied commit {}",
+                abbreviate(&hash)?
+            );
+            skipped = true;
+            continue;
+        }
+        let item = TodoItem::new(TodoCommand::Pick, Some(hash), &subject(&commit.message));
+        lines.push(item.format(false)?);
+    }
+    if skipped {
+        eprintln!("hint: use --reapply-cherry-picks to include skipped commits");
+    }
+    Ok(lines)
+}
+
+/// builds the todo list recreating the history of upstream..head, merges included. each
+/// branch merged is picked on its own, then labeled so that the merge can refer to it.
+pub fn make_script_with_merges(upstream: &Hash, head: &Hash) -> Result<Vec<String>> {
+    let commits = list_commits(head, upstream)?;
+    let interesting: HashSet<&Hash> = commits.iter().map(|(hash, _)| hash).collect();
+    let by_hash: HashMap<&Hash, &Commit> = commits
+        .iter()
+        .map(|(hash, commit)| (hash, commit))
+        .collect();
+
+    // the commits the branches start from are the ones the first picks are moved onto
+    let mut labels = Labels::default();
+    for base in CommitGraph::new().merge_bases(upstream, std::slice::from_ref(head))? {
+        labels.by_commit.insert(base, String::from("onto"));
+    }
+    labels.names.insert(String::from("onto"));
+
+    let mut todo: HashMap<&Hash, String> = HashMap::new();
+    let mut tips: Vec<&Hash> = Vec::new();
+    for (hash, commit) in &commits {
+        let oneline = subject(&commit.message);
+        let others = match commit.parents.as_slice() {
+            [_, others @ ..] if !others.is_empty() => others,
+            _ => {
+                let item = TodoItem::new(TodoCommand::Pick, Some(hash.clone()), &oneline);
+                todo.insert(hash, item.format(false)?);
+                continue;
+            }
+        };
+
+        let label = merge_label(&oneline);
+        let mut merged = Vec::new();
+        for parent in others {
+            match interesting.get(parent) {
+                Some(parent) => {
+                    tips.push(parent);
+                    merged.push(labels.label(parent, Some(&label))?);
+                }
+                None => merged.push(labels.label(parent, None)?),
+            }
+        }
+        let mut item = TodoItem::new(
+            TodoCommand::Merge,
+            Some(hash.clone()),
+            &format!("{} # {}", merged.join(" "), oneline),
+        );
+        item.flag = Some('C');
+        todo.insert(hash, item.format(false)?);
+    }
+
+    // commits with several children are where branches start
+    let mut child_seen = HashSet::new();
+    for (_, commit) in &commits {
+        for parent in &commit.parents {
+            if interesting.contains(parent) && !child_seen.insert(parent) {
+                labels.label(parent, Some("branch-point"))?;
+            }
+        }
+    }
+    if let Some((hash, _)) = commits.last() {
+        tips.push(hash);
+    }
+
+    // each branch is picked from where it starts, walking back from its tip
+    let mut lines = vec![String::from("label onto")];
+    let mut shown: HashSet<&Hash> = HashSet::new();
+    for tip in tips {
+        if shown.contains(tip) {
+            continue;
+        }
+        lines.push(String::new());
+        if let Some(label) = labels.by_commit.get(tip) {
+            lines.push(format!("# Branch {}", label));
+        }
+
+        let mut branch = Vec::new();
+        let mut current = Some(tip);
+        while let Some(hash) =
+            current.filter(|hash| interesting.contains(hash) && !shown.contains(hash))
+        {
+            branch.push(hash);
+            current = by_hash[hash].parents.first();
+        }
+        branch.reverse();
+
+        match current {
+            None => lines.push(String::from("reset onto")),
+            Some(base) => match labels.label(base, None)?.as_str() {
+                "onto" => lines.push(String::from("reset onto")),
+                label => {
+                    let oneline = subject(&read_commit(base)?.message);
+                    lines.push(format!("reset {} # {}", label, oneline));
+                }
+            },
+        }
+
+        for hash in branch {
+            lines.push(todo[hash].clone());
+            if let Some(label) = labels.by_commit.get(hash) {
+                lines.push(format!("label {}", label));
+            }
+            shown.insert(hash);
+        }
+    }
+    Ok(lines)
+}
+
+/// the labels of a todo list made with --rebase-merges
+#[derive(Default)]
+struct Labels {
+    by_commit: HashMap<Hash, String>,
+    names: HashSet<String>,
+}
+
+impl Labels {
+    /// returns the label of a commit, naming it if needed: after the given name, made unique,
+    /// or after its abbreviated hash
+    fn label(&mut self, commit: &Hash, name: Option<&str>) -> Result<String> {
+        if let Some(label) = self.by_commit.get(commit) {
+            return Ok(label.clone());
+        }
+
+        let base = match name {
+            Some(name) => sanitize_label(name),
+            None => abbreviate(commit)?,
+        };
+        let base = match base.is_empty() {
+            true => format!("rev-{}", abbreviate(commit)?),
+            false => base,
+        };
+        let mut label = base.clone();
+        let mut suffix = 2;
+        while self.names.contains(&label.to_lowercase()) {
+            label = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+
+        self.names.insert(label.to_lowercase());
+        self.by_commit.insert(commit.clone(), label.clone());
+        Ok(label)
+    }
+}
+
+/// the label of the branch merged by a merge commit, taken from its subject
+/// e.g. Merge branch 'topic' into main
+fn merge_label(oneline: &str) -> String {
+    if let Some(rest) = oneline.strip_prefix("Merge ") {
+        if let Some((_, quoted)) = rest.split_once('\'') {
+            if let Some((label, _)) = quoted.split_once('\'') {
+                return label.to_string();
+            }
+        }
+        if let Some(rest) = rest.strip_prefix("pull request ") {
+            if let Some((_, from)) = rest.split_once(" from ") {
+                return from.to_string();
+            }
+        }
+    }
+    oneline.to_string()
+}
+
+/// labels are ref names: characters other than letters and digits are replaced by dashes
+fn sanitize_label(name: &str) -> String {
+    let mut label = String::new();
+    for c in name.chars() {
+        if c.is_alphanumeric() || !c.is_ascii() {
+            label.push(c);
+        } else if !label.is_empty() && !label.ends_with('-') {
+            label.push('-');
+        }
+    }
+    label
+}
+
+/// moves the "fixup! <subject>", "squash! <subject>" and "amend! <subject>" commits right
+/// after the commit they refer to, turning their picks into fixups and squashes
+pub fn autosquash(items: Vec<TodoItem>) -> Result<Vec<TodoItem>> {
+    let subjects: Vec<String> = items.iter().map(|item| item.arg.clone()).collect();
+    let mut fixups: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut moved = vec![None; items.len()];
+
+    for (i, item) in items.iter().enumerate() {
+        if item.command != TodoCommand::Pick {
+            continue;
+        }
+        let (command, flag, mut target) = match &subjects[i] {
+            s if s.starts_with("fixup! ") => (TodoCommand::Fixup, None, &s[7..]),
+            s if s.starts_with("amend! ") => (TodoCommand::Fixup, Some('C'), &s[7..]),
+            s if s.starts_with("squash! ") => (TodoCommand::Squash, None, &s[8..]),
+            _ => continue,
+        };
+        while let Some(rest) = ["fixup! ", "amend! ", "squash! "]
+            .iter()
+            .find_map(|prefix| target.strip_prefix(prefix))
+        {
+            target = rest;
+        }
+
+        let by_subject = (0..i).find(|&j| moved[j].is_none() && subjects[j] == target);
+        let by_hash = || {
+            (0..i).find(|&j| {
+                moved[j].is_none()
+                    && target.len() >= 4
+                    && items[j]
+                        .commit
+                        .as_ref()
+                        .is_some_and(|commit| commit.to_hex().starts_with(target))
+            })
+        };
+        let by_prefix = || (0..i).find(|&j| moved[j].is_none() && subjects[j].starts_with(target));
+        if let Some(j) = by_subject.or_else(by_hash).or_else(by_prefix) {
+            moved[i] = Some((command, flag));
+            fixups.entry(j).or_default().push(i);
+        }
+    }
+
+    let mut rearranged = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        if moved[i].is_some() {
+            continue;
+        }
+        rearranged.push(item.clone());
+        for &fixup in fixups.get(&i).into_iter().flatten() {
+            let (command, flag) = moved[fixup].expect("fixups are moved");
+            let mut item = items[fixup].clone();
+            item.command = command;
+            item.flag = flag;
+            rearranged.push(item);
+        }
+    }
+    Ok(rearranged)
+}
+
+/// identifies the changes of a commit, whatever the lines and the whitespace around them,
+/// to find the commits applying the same changes
+fn patch_id(commit: &Commit) -> Result<Hash> {
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(read_commit(parent)?.tree),
+        None => None,
+    };
+    let options = TreeDiffOptions {
+        recursive: true,
+        ..Default::default()
+    };
+
+    let mut data = Vec::new();
+    for change in diff_trees(parent_tree.as_ref(), Some(&commit.tree), &options)? {
+        let (old, new) = (
+            read_blob(change.old.as_ref())?,
+            read_blob(change.new.as_ref())?,
+        );
+        data.extend(change.path().as_bytes());
+        data.push(0);
+        if is_binary(&old) || is_binary(&new) {
+            for file in [&change.old, &change.new].into_iter().flatten() {
+                data.extend(&file.hash.0);
+            }
+            continue;
+        }
+
+        for hunk in make_hunks(&old, &new, &LineDiffOptions::default(), 3) {
+            for line in hunk
+                .lines
+                .iter()
+                .filter(|line| line.origin != LineOrigin::Context)
+            {
+                data.push(line.origin.prefix());
+                data.extend(
+                    line.content
+                        .iter()
+                        .filter(|byte| !byte.is_ascii_whitespace()),
+                );
+            }
+        }
+    }
+    Ok(hash(&data))
+}
+
+fn read_blob(file: Option<&DiffFile>) -> Result<Vec<u8>> {
+    match file {
+        Some(file) => Ok(Object::read_from_hash(file.hash.to_hex())?.data),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{autosquash, merge_label, sanitize_label};
+    use crate::sequencer::todo::{TodoCommand, TodoItem};
+
+    #[test]
+    fn test_autosquash() {
+        let subjects = [
+            "add a",
+            "add b",
+            "fixup! add a",
+            "squash! add b",
+            "amend! add a",
+            "add c",
+        ];
+        let items = subjects
+            .iter()
+            .map(|subject| TodoItem::new(TodoCommand::Pick, None, subject))
+            .collect();
+
+        let rearranged = autosquash(items).unwrap();
+        let lines: Vec<String> = rearranged
+            .iter()
+            .map(|item| item.format(false).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "pick add a",
+                "fixup fixup! add a",
+                "fixup -C amend! add a",
+                "pick add b",
+                "squash squash! add b",
+                "pick add c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_label() {
+        assert_eq!(merge_label("Merge branch 'side' into topic"), "side");
+        assert_eq!(merge_label("Merge pull request #1 from a/b"), "a/b");
+        assert_eq!(sanitize_label("fix the a/b thing!"), "fix-the-a-b-thing-");
+    }
+}
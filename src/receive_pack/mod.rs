@@ -0,0 +1,720 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{stderr, stdin, stdout, BufRead, BufWriter, IsTerminal, Read, Write},
+    path::Path,
+    process,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::{read_config, Config},
+    gc::auto_gc,
+    hook::Hook,
+    merge_base::CommitGraph,
+    objects::{
+        commit::decode_commit,
+        hash::Hash,
+        pack::{migrate_packs, store_pack_in, PackDir},
+        tag::decode_tag,
+        tree::decode_tree,
+        Object, ObjectKind,
+    },
+    pack_protocol::{
+        pkt_line::{
+            write_advertised_refs, write_flush, write_line, Packet, PktLineReader, Ref,
+            MAX_PKT_LINE_LENGTH,
+        },
+        receive_pack_request::RefUpdate,
+        sideband::SidebandWriter,
+    },
+    refs::{head_branch, is_valid_ref_name, list_refs, resolve_ref, RefTransaction},
+    rev_parse::peel_kind,
+    upload_pack::{enter_repository, requested_version, Session},
+};
+
+/// the capabilities always advertised, in the order git advertises them
+const CAPABILITIES: [&str; 5] = [
+    "report-status",
+    "report-status-v2",
+    "delete-refs",
+    "side-band-64k",
+    "quiet",
+];
+
+/// the message reflogs record the updates of a push under
+const REFLOG_MESSAGE: &str = "push";
+
+pub struct ReceivePackOptions {
+    /// the repository pushed to: its work tree, or the .git directory in it. bare
+    /// repositories aren't supported.
+    pub dir: String,
+    pub session: Session,
+}
+
+/// receives a push over stdin and stdout: the objects it sends, and the refs it updates
+pub fn receive_pack(options: ReceivePackOptions) -> Result<()> {
+    enter_repository(&options.dir)?;
+    let version = env::var("GIT_PROTOCOL")
+        .map(|protocol| requested_version(&protocol))
+        .unwrap_or(0);
+    serve_receive_pack(
+        stdin().lock(),
+        BufWriter::new(stdout().lock()),
+        version,
+        options.session,
+    )
+}
+
+/// receives a push into the repository of the working directory
+pub(crate) fn serve_receive_pack<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    version: u8,
+    session: Session,
+) -> Result<()> {
+    let mut server = ReceivePack::new(reader, writer)?;
+    server.serve(version, session)
+}
+
+/// what receive.denyCurrentBranch and receive.denyDeleteCurrent say about updating or
+/// deleting the branch checked out, whose index and working tree would no longer match it
+#[derive(Debug, PartialEq, Eq)]
+enum Deny {
+    Refuse,
+    Warn,
+    Ignore,
+}
+
+impl Deny {
+    fn from_config(config: &Config, key: &str) -> Result<Deny> {
+        match config.get(key) {
+            None | Some("refuse") => Ok(Deny::Refuse),
+            Some("warn") => Ok(Deny::Warn),
+            Some("ignore") => Ok(Deny::Ignore),
+            Some(_) => match config.get_bool(key)? {
+                Some(true) => Ok(Deny::Refuse),
+                _ => Ok(Deny::Ignore),
+            },
+        }
+    }
+}
+
+/// a ref update of the push, and why it failed if it did
+struct Command {
+    update: RefUpdate,
+    error: Option<String>,
+}
+
+/// what the pusher asked for besides the ref updates
+#[derive(Default)]
+struct PushRequest {
+    report_status: bool,
+    sideband: bool,
+    quiet: bool,
+    atomic: bool,
+    /// whether the pusher sends push options, even if none
+    push_options: Option<Vec<String>>,
+}
+
+impl PushRequest {
+    fn capabilities(&mut self, capabilities: &str) {
+        for capability in capabilities.split(' ') {
+            match capability {
+                "report-status" | "report-status-v2" => self.report_status = true,
+                "side-band-64k" => self.sideband = true,
+                "quiet" => self.quiet = true,
+                "atomic" => self.atomic = true,
+                "push-options" => self.push_options = Some(Vec::new()),
+                _ => {}
+            }
+        }
+    }
+
+    /// the environment the push options are handed to hooks in. the count is set even
+    /// when there are none.
+    fn hook_env(&self) -> Vec<(String, String)> {
+        let options = self.push_options.as_deref().unwrap_or_default();
+        let mut env = vec![(
+            "GIT_PUSH_OPTION_COUNT".to_string(),
+            options.len().to_string(),
+        )];
+        for (i, option) in options.iter().enumerate() {
+            env.push((format!("GIT_PUSH_OPTION_{}", i), option.clone()));
+        }
+        env
+    }
+}
+
+/// where the messages of hooks and warnings go: the progress band when the report is
+/// multiplexed, or else stderr
+enum Output<W: Write> {
+    Sideband(SidebandWriter<W>),
+    Plain(W),
+}
+
+impl<W: Write> Output<W> {
+    fn message(&mut self, text: &str) -> Result<()> {
+        match self {
+            Output::Sideband(sideband) => sideband.progress(text),
+            Output::Plain(_) => {
+                eprint!("{}", text);
+                Ok(())
+            }
+        }
+    }
+
+    /// sends the report, which ends the exchange
+    fn finish(self, report: &[u8]) -> Result<()> {
+        match self {
+            Output::Sideband(mut sideband) => {
+                sideband.write_all(report)?;
+                sideband.finish()?;
+            }
+            Output::Plain(mut writer) => {
+                writer.write_all(report)?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the server side of a push
+struct ReceivePack<R: BufRead, W: Write> {
+    reader: PktLineReader<R>,
+    writer: W,
+    config: Config,
+}
+
+impl<R: BufRead, W: Write> ReceivePack<R, W> {
+    fn new(reader: R, writer: W) -> Result<ReceivePack<R, W>> {
+        Ok(ReceivePack {
+            reader: PktLineReader::new(reader),
+            writer,
+            config: read_config()?,
+        })
+    }
+
+    fn serve(&mut self, version: u8, session: Session) -> Result<()> {
+        if session != Session::StatelessRpc {
+            self.advertise(version)?;
+        }
+        if session == Session::AdvertiseRefs {
+            return Ok(());
+        }
+        let (mut commands, mut request) = match self.read_commands()? {
+            Some(read) => read,
+            None => return Ok(()),
+        };
+        if let Some(options) = request.push_options.as_mut() {
+            while let Some(option) = self.reader.read_line()? {
+                options.push(option);
+            }
+        }
+
+        // the objects are kept apart until the hooks accepted them
+        let quarantine = env::current_dir()?
+            .join(".git/objects")
+            .join(format!("incoming-{}", process::id()));
+        let unpacked = match commands.iter().all(|command| command.update.is_delete()) {
+            true => Ok(()),
+            false => self.read_pack(&quarantine, request.quiet),
+        };
+
+        let writer = &mut self.writer;
+        let mut output = match request.sideband {
+            true => Output::Sideband(SidebandWriter::new(writer, MAX_PKT_LINE_LENGTH)),
+            false => Output::Plain(writer),
+        };
+        let result = match &unpacked {
+            Ok(()) => execute(
+                &self.config,
+                &mut commands,
+                &request,
+                &quarantine,
+                &mut output,
+            ),
+            Err(_) => {
+                for command in &mut commands {
+                    command.error = Some("unpacker error".to_string());
+                }
+                Ok(())
+            }
+        };
+        if quarantine.exists() {
+            fs::remove_dir_all(&quarantine)?;
+        }
+        result?;
+
+        let mut report = Vec::new();
+        if request.report_status {
+            match &unpacked {
+                Ok(()) => write_line(&mut report, "unpack ok")?,
+                Err(err) => write_line(&mut report, &format!("unpack {}", err))?,
+            }
+            for command in &commands {
+                match &command.error {
+                    None => write_line(&mut report, &format!("ok {}", command.update.name))?,
+                    Some(error) => write_line(
+                        &mut report,
+                        &format!("ng {} {}", command.update.name, error),
+                    )?,
+                }
+            }
+            write_flush(&mut report)?;
+        }
+        output.finish(&report)?;
+
+        let updated = commands.iter().any(|command| command.error.is_none());
+        if updated && self.config.get_bool("receive.autogc")?.unwrap_or(true) {
+            auto_gc(true)?;
+        }
+        Ok(())
+    }
+
+    /// advertises the refs and capabilities. a repository without refs advertises its
+    /// capabilities alone.
+    fn advertise(&mut self, version: u8) -> Result<()> {
+        let refs: Vec<Ref> = list_refs("refs/")?
+            .into_iter()
+            .map(|(name, hash)| Ref::Tip {
+                name,
+                object_id: hash.to_hex(),
+            })
+            .collect();
+
+        let mut capabilities: Vec<String> =
+            CAPABILITIES.iter().map(|cap| cap.to_string()).collect();
+        if self
+            .config
+            .get_bool("receive.advertiseAtomic")?
+            .unwrap_or(true)
+        {
+            capabilities.push("atomic".to_string());
+        }
+        capabilities.push("ofs-delta".to_string());
+        if self
+            .config
+            .get_bool("receive.advertisePushOptions")?
+            .unwrap_or(false)
+        {
+            capabilities.push("push-options".to_string());
+        }
+        capabilities.push("object-format=sha1".to_string());
+        capabilities.push(format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")));
+        write_advertised_refs(&mut self.writer, version, &refs, &capabilities)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// reads the ref updates, with the capabilities on the first one. returns None if the
+    /// pusher has nothing to update.
+    fn read_commands(&mut self) -> Result<Option<(Vec<Command>, PushRequest)>> {
+        let mut commands = Vec::new();
+        let mut request = PushRequest::default();
+        loop {
+            // a pusher with nothing to push may hang up right away
+            if commands.is_empty() && self.reader.get_mut().fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+            let line = match self.reader.read_packet()? {
+                Packet::Data(data) => String::from_utf8(data)
+                    .map_err(|_| anyhow!("protocol error: line is not UTF-8"))?,
+                Packet::Flush => break,
+                packet => bail!("protocol error: unexpected {:?}", packet),
+            };
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = match line.split_once('\0') {
+                Some((line, capabilities)) if commands.is_empty() => {
+                    request.capabilities(capabilities);
+                    line
+                }
+                _ => line,
+            };
+            commands.push(Command {
+                update: RefUpdate::parse(line)?,
+                error: None,
+            });
+        }
+
+        match commands.is_empty() {
+            true => Ok(None),
+            false => Ok(Some((commands, request))),
+        }
+    }
+
+    /// stores the pack into the quarantine. an empty pack, as sent when all the objects
+    /// are here already, is read but not stored.
+    fn read_pack(&mut self, quarantine: &Path, quiet: bool) -> Result<()> {
+        let reader = self.reader.get_mut();
+        let mut header = [0; 12];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| anyhow!("eof before pack header was fully read"))?;
+        if header.starts_with(b"PACK") && header[8..] == [0, 0, 0, 0] {
+            let mut checksum = [0; 20];
+            reader.read_exact(&mut checksum)?;
+            return Ok(());
+        }
+
+        let progress = !quiet && stderr().is_terminal();
+        store_pack_in(
+            (&header[..]).chain(reader),
+            &quarantine.join("pack"),
+            progress,
+        )?;
+        Ok(())
+    }
+}
+
+/// checks the updates and runs the hooks, then moves the objects out of the quarantine
+/// and updates the refs that can be
+fn execute<W: Write>(
+    config: &Config,
+    commands: &mut [Command],
+    request: &PushRequest,
+    quarantine: &Path,
+    output: &mut Output<W>,
+) -> Result<()> {
+    let received = PackDir::open(quarantine)?;
+    for command in commands.iter_mut() {
+        if let Some(new) = &command.update.new {
+            if !is_connected(new, &received) {
+                command.error = Some("missing necessary objects".to_string());
+            }
+        }
+    }
+    if request.atomic {
+        fail_atomic(commands);
+    }
+
+    let env = request.hook_env();
+    let hook_quarantine = Some(quarantine).filter(|dir| dir.is_dir());
+    if !run_receive_hook(
+        config,
+        "pre-receive",
+        commands,
+        &env,
+        hook_quarantine,
+        output,
+    )? {
+        for command in commands
+            .iter_mut()
+            .filter(|command| command.error.is_none())
+        {
+            command.error = Some("pre-receive hook declined".to_string());
+        }
+        return Ok(());
+    }
+    let pack_dir = quarantine.join("pack");
+    if pack_dir.is_dir() {
+        migrate_packs(&pack_dir)?;
+    }
+
+    let mut graph = CommitGraph::new();
+    let current_branch = head_branch()?;
+    for command in commands
+        .iter_mut()
+        .filter(|command| command.error.is_none())
+    {
+        command.error = check_update(
+            config,
+            &command.update,
+            &mut graph,
+            current_branch.as_deref(),
+            &env,
+            output,
+        )?;
+        if command.error.is_some() && request.atomic {
+            break;
+        }
+        if command.error.is_none() && !request.atomic {
+            let mut transaction = RefTransaction::new();
+            command.error = lock_update(&mut transaction, &command.update, output)?;
+            if command.error.is_none() {
+                if let Err(err) = transaction.commit() {
+                    output.message(&format!("error: {}\n", err))?;
+                    command.error = Some("failed to update ref".to_string());
+                }
+            }
+        }
+    }
+
+    if request.atomic {
+        fail_atomic(commands);
+        // all the refs are locked and checked before any is written, and none is if one fails
+        let mut transaction = RefTransaction::new();
+        for command in commands
+            .iter_mut()
+            .filter(|command| command.error.is_none())
+        {
+            command.error = lock_update(&mut transaction, &command.update, output)?;
+            if command.error.is_some() {
+                break;
+            }
+        }
+        fail_atomic(commands);
+        if commands.iter().all(|command| command.error.is_none()) {
+            if let Err(err) = transaction.commit() {
+                output.message(&format!("error: {}\n", err))?;
+                for command in commands.iter_mut() {
+                    command.error = Some("failed to update ref".to_string());
+                }
+            }
+        }
+    }
+
+    run_receive_hook(config, "post-receive", commands, &env, None, output)?;
+    Ok(())
+}
+
+/// fails every update once one failed, so that an atomic push updates all of its refs or
+/// none of them
+fn fail_atomic(commands: &mut [Command]) {
+    if commands.iter().any(|command| command.error.is_some()) {
+        for command in commands
+            .iter_mut()
+            .filter(|command| command.error.is_none())
+        {
+            command.error = Some("atomic push failure".to_string());
+        }
+    }
+}
+
+/// whether all the objects reachable from a new ref value are here. the walk goes
+/// through the objects received, and stops at those the repository already has, whose
+/// history is complete.
+fn is_connected(hash: &Hash, received: &PackDir) -> bool {
+    let walked = || -> Result<()> {
+        let mut pending = vec![hash.clone()];
+        let mut seen = HashSet::new();
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash.clone()) || Object::exists(&hash)? {
+                continue;
+            }
+            let object = received
+                .read(&hash)?
+                .ok_or_else(|| anyhow!("missing {}", hash.to_hex()))?;
+            match object.kind {
+                ObjectKind::Commit => {
+                    let commit = decode_commit(object.data)?;
+                    pending.push(commit.tree);
+                    pending.extend(commit.parents);
+                }
+                ObjectKind::Tree => {
+                    let tree = decode_tree(object.data)?;
+                    pending.extend(tree.entries.into_iter().map(|entry| entry.hash));
+                }
+                ObjectKind::Tag => pending.push(decode_tag(object.data)?.object),
+                ObjectKind::Blob => {}
+            }
+        }
+        Ok(())
+    };
+    walked().is_ok()
+}
+
+/// checks an update against the config and the update hook. returns why it is refused,
+/// if it is.
+fn check_update<W: Write>(
+    config: &Config,
+    update: &RefUpdate,
+    graph: &mut CommitGraph,
+    current_branch: Option<&str>,
+    env: &[(String, String)],
+    output: &mut Output<W>,
+) -> Result<Option<String>> {
+    let name = &update.name;
+    if !name.starts_with("refs/") || !is_valid_ref_name(name) {
+        output.message(&format!(
+            "error: refusing to create funny ref '{}' remotely\n",
+            name
+        ))?;
+        return Ok(Some("funny refname".to_string()));
+    }
+
+    let is_current = current_branch == Some(name.as_str());
+    if is_current && !update.is_delete() {
+        match Deny::from_config(config, "receive.denyCurrentBranch")? {
+            Deny::Refuse => {
+                output.message(&format!(
+                    "error: refusing to update checked out branch: {}\n",
+                    name
+                ))?;
+                return Ok(Some("branch is currently checked out".to_string()));
+            }
+            Deny::Warn => {
+                output.message(&format!("warning: updating the current branch {}\n", name))?
+            }
+            Deny::Ignore => {}
+        }
+    }
+
+    let current = resolve_ref(name)?;
+    match (&update.old, &update.new) {
+        (_, None) => {
+            if config.get_bool("receive.denyDeletes")?.unwrap_or(false)
+                && name.starts_with("refs/heads/")
+            {
+                output.message(&format!("error: denying ref deletion for {}\n", name))?;
+                return Ok(Some("deletion prohibited".to_string()));
+            }
+            if is_current {
+                match Deny::from_config(config, "receive.denyDeleteCurrent")? {
+                    Deny::Refuse => {
+                        output.message(&format!(
+                            "error: refusing to delete the current branch: {}\n",
+                            name
+                        ))?;
+                        return Ok(Some(
+                            "deletion of the current branch prohibited".to_string(),
+                        ));
+                    }
+                    Deny::Warn => output.message("warning: deleting the current branch\n")?,
+                    Deny::Ignore => {}
+                }
+            }
+            if current.is_none() {
+                output.message("warning: deleting a non-existent ref\n")?;
+            }
+        }
+        (Some(old), Some(new))
+            if name.starts_with("refs/heads/")
+                && config
+                    .get_bool("receive.denyNonFastForwards")?
+                    .unwrap_or(false) =>
+        {
+            let fast_forward = peel_kind(old.clone(), None)?.1 == ObjectKind::Commit
+                && peel_kind(new.clone(), None)?.1 == ObjectKind::Commit
+                && graph.is_ancestor(old, new)?;
+            if !fast_forward {
+                output.message(&format!(
+                    "error: denying non-fast-forward {} (you should pull first)\n",
+                    name
+                ))?;
+                return Ok(Some("non-fast-forward".to_string()));
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(hook) = Hook::find(config, "update") {
+        let id = |hash: &Option<Hash>| hash.as_ref().map_or("0".repeat(40), |hash| hash.to_hex());
+        let (old, new) = (id(&update.old), id(&update.new));
+        let succeeded = hook.run(&[name, &old, &new], env, None, &[], &mut |text| {
+            output.message(&String::from_utf8_lossy(text))
+        })?;
+        if !succeeded {
+            output.message(&format!("error: hook declined to update {}\n", name))?;
+            return Ok(Some("hook declined".to_string()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// locks the ref of an update in a transaction, and checks that it still is what the pusher
+/// saw. returns why the update is refused, if it is.
+fn lock_update<W: Write>(
+    transaction: &mut RefTransaction,
+    update: &RefUpdate,
+    output: &mut Output<W>,
+) -> Result<Option<String>> {
+    let name = &update.name;
+    let failure = match update.is_delete() {
+        true => "failed to delete",
+        false => "failed to update ref",
+    };
+    let current = match transaction.lock(name, update.new.as_ref(), REFLOG_MESSAGE) {
+        Ok(current) => current,
+        Err(err) => {
+            output.message(&format!("error: {}\n", err))?;
+            return Ok(Some(failure.to_string()));
+        }
+    };
+
+    // deleting a ref that is already gone isn't a change
+    let changed = match update.new {
+        Some(_) => current != update.old,
+        None => current.is_some() && current != update.old,
+    };
+    if changed {
+        output.message(&format!(
+            "error: cannot lock ref '{}': reference has changed since it was advertised\n",
+            name
+        ))?;
+        return Ok(Some(failure.to_string()));
+    }
+    Ok(None)
+}
+
+/// runs pre-receive or post-receive, feeding it the updates that didn't fail as
+/// "<old> <new> <ref>" lines. returns false if the hook failed.
+fn run_receive_hook<W: Write>(
+    config: &Config,
+    name: &str,
+    commands: &[Command],
+    env: &[(String, String)],
+    quarantine: Option<&Path>,
+    output: &mut Output<W>,
+) -> Result<bool> {
+    let hook = match Hook::find(config, name) {
+        Some(hook) => hook,
+        None => return Ok(true),
+    };
+    let mut input = String::new();
+    for command in commands.iter().filter(|command| command.error.is_none()) {
+        input.push_str(&command.update.encode());
+        input.push('\n');
+    }
+    if input.is_empty() {
+        return Ok(true);
+    }
+    hook.run(&[], env, quarantine, input.as_bytes(), &mut |text| {
+        output.message(&String::from_utf8_lossy(text))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fail_atomic, Command, PushRequest};
+    use crate::pack_protocol::receive_pack_request::RefUpdate;
+
+    #[test]
+    fn test_push_request() {
+        let mut request = PushRequest::default();
+        request.capabilities("report-status-v2 side-band-64k atomic push-options agent=git/2");
+        assert!(request.report_status && request.sideband && request.atomic);
+        assert!(!request.quiet);
+        assert_eq!(
+            request.hook_env(),
+            vec![("GIT_PUSH_OPTION_COUNT".to_string(), "0".to_string())]
+        );
+        request.push_options = Some(vec!["ci.skip".to_string()]);
+        assert_eq!(
+            request.hook_env(),
+            vec![
+                ("GIT_PUSH_OPTION_COUNT".to_string(), "1".to_string()),
+                ("GIT_PUSH_OPTION_0".to_string(), "ci.skip".to_string()),
+            ]
+        );
+
+        let command = |name: &str, error: Option<&str>| Command {
+            update: RefUpdate {
+                old: None,
+                new: None,
+                name: name.to_string(),
+            },
+            error: error.map(|error| error.to_string()),
+        };
+        let mut commands = vec![
+            command("refs/heads/a", None),
+            command("refs/heads/b", Some("hook declined")),
+        ];
+        fail_atomic(&mut commands);
+        assert_eq!(commands[0].error.as_deref(), Some("atomic push failure"));
+        assert_eq!(commands[1].error.as_deref(), Some("hook declined"));
+    }
+}
@@ -0,0 +1,566 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch::tracking::tracking,
+    config::{read_config, Config},
+    date::{format_date, DateFormat},
+    log::{body, subject},
+    objects::{
+        commit::{decode_commit, Author, Commit},
+        hash::Hash,
+        tag::{decode_tag, Tag},
+        Object, ObjectKind,
+    },
+    refs::{expand_ref, head_branch, read_symbolic_ref},
+    rev_parse::abbreviate,
+};
+
+/// prefixes stripped from ref names by %(refname:short), most specific first
+const SHORT_PREFIXES: [&str; 4] = ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"];
+
+/// a ref to show, with the object it points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefItem {
+    pub name: String,
+    pub hash: Hash,
+}
+
+/// the value of an atom for a ref. dates also have a number, used when sorting.
+struct AtomValue {
+    text: String,
+    number: Option<u64>,
+}
+
+impl AtomValue {
+    fn text(text: String) -> AtomValue {
+        AtomValue { text, number: None }
+    }
+}
+
+/// the object a ref points to, read when an atom needs it
+enum RefObject {
+    Commit(Box<Commit>),
+    Tag(Box<Tag>),
+    Other,
+}
+
+/// expands for-each-ref style formats like "%(refname:short) %(objectname)" for refs, and
+/// sorts refs by such atoms
+pub struct RefFormatter {
+    config: Config,
+    head: Option<String>,
+    color: bool,
+}
+
+impl RefFormatter {
+    /// %(color:...) atoms only produce escape sequences when color is set
+    pub fn new(color: bool) -> Result<RefFormatter> {
+        Ok(RefFormatter {
+            config: read_config()?,
+            head: head_branch()?,
+            color,
+        })
+    }
+
+    /// expands the atoms of a format for a ref. %(align:<width>[,<position>]) pads what
+    /// comes up to the matching %(end).
+    pub fn format(&self, format: &str, item: &RefItem) -> Result<String> {
+        let mut object = None;
+        // the text of the enclosing %(align) blocks, with their width and position
+        let mut blocks: Vec<(String, usize, String)> = vec![(String::new(), 0, String::new())];
+        let mut rest = format;
+        while let Some(start) = rest.find('%') {
+            let output = &mut blocks.last_mut().expect("there is an outer block").0;
+            output.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                output.push('%');
+                rest = after;
+                continue;
+            }
+            if let Some(byte) = rest
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                output.push(byte as char);
+                rest = &rest[2..];
+                continue;
+            }
+            let atom = match rest
+                .strip_prefix('(')
+                .and_then(|after| after.split_once(')'))
+            {
+                Some((atom, after)) => {
+                    rest = after;
+                    atom
+                }
+                None => {
+                    output.push('%');
+                    continue;
+                }
+            };
+
+            if let Some(align) = atom.strip_prefix("align:") {
+                let (width, position) = align.split_once(',').unwrap_or((align, "left"));
+                let width = width
+                    .trim_start_matches("width=")
+                    .parse()
+                    .map_err(|_| anyhow!("unrecognized width: {}", width))?;
+                let position = position.trim_start_matches("position=").to_string();
+                blocks.push((String::new(), width, position));
+            } else if atom == "end" {
+                if blocks.len() == 1 {
+                    bail!("format: %(end) atom used without corresponding atom");
+                }
+                let (text, width, position) = blocks.pop().expect("there is an inner block");
+                let padding = width.saturating_sub(text.chars().count());
+                let aligned = match position.as_str() {
+                    "right" => format!("{}{}", " ".repeat(padding), text),
+                    "middle" => format!(
+                        "{}{}{}",
+                        " ".repeat(padding / 2),
+                        text,
+                        " ".repeat(padding - padding / 2)
+                    ),
+                    _ => format!("{}{}", text, " ".repeat(padding)),
+                };
+                blocks
+                    .last_mut()
+                    .expect("there is an outer block")
+                    .0
+                    .push_str(&aligned);
+            } else if let Some(color) = atom.strip_prefix("color:") {
+                if self.color {
+                    output.push_str(&color_code(color)?);
+                }
+            } else {
+                let value = self.atom(atom, item, &mut object)?;
+                blocks
+                    .last_mut()
+                    .expect("there is an outer block")
+                    .0
+                    .push_str(&value.text);
+            }
+        }
+
+        if blocks.len() > 1 {
+            bail!("format: %(end) atom missing");
+        }
+        let mut output = blocks.pop().expect("there is an outer block").0;
+        output.push_str(rest);
+        Ok(output)
+    }
+
+    /// sorts refs by keys like refname, -committerdate or version:refname. the last key is
+    /// the primary one, and refs are sorted by name when they are otherwise equal.
+    pub fn sort(&self, items: &mut Vec<RefItem>, keys: &[String]) -> Result<()> {
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+        for key in keys {
+            let (descending, key) = match key.strip_prefix('-') {
+                Some(key) => (true, key),
+                None => (false, key.as_str()),
+            };
+            let (version, atom) = match key
+                .strip_prefix("version:")
+                .or_else(|| key.strip_prefix("v:"))
+            {
+                Some(atom) => (true, atom),
+                None => (false, key),
+            };
+
+            let mut keyed = Vec::new();
+            for item in items.drain(..) {
+                let value = self.atom(atom, &item, &mut None)?;
+                keyed.push((value, item));
+            }
+            keyed.sort_by(|(a, _), (b, _)| {
+                let ordering = match (a.number, b.number) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    _ if version => version_cmp(&a.text, &b.text),
+                    _ => a.text.cmp(&b.text),
+                };
+                match descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            });
+            items.extend(keyed.into_iter().map(|(_, item)| item));
+        }
+        Ok(())
+    }
+
+    fn atom(
+        &self,
+        atom: &str,
+        item: &RefItem,
+        object: &mut Option<RefObject>,
+    ) -> Result<AtomValue> {
+        let (name, modifier) = atom.split_once(':').unwrap_or((atom, ""));
+        let value = match name {
+            "refname" => AtomValue::text(format_ref_name(&item.name, modifier)?),
+            "objectname" => AtomValue::text(match modifier {
+                "" => item.hash.to_hex(),
+                "short" => abbreviate(&item.hash)?,
+                _ => match modifier.strip_prefix("short=") {
+                    Some(length) => {
+                        let length: usize = length
+                            .parse()
+                            .map_err(|_| anyhow!("unrecognized length: {}", length))?;
+                        item.hash.to_hex()[..length.clamp(4, 40)].to_string()
+                    }
+                    None => bail!("unrecognized %(objectname) argument: {}", modifier),
+                },
+            }),
+            "objecttype" | "objectsize" => {
+                let stored = Object::read_from_hash(item.hash.to_hex())?;
+                match name {
+                    "objecttype" => AtomValue::text(stored.kind.to_string()),
+                    _ => AtomValue {
+                        text: stored.data.len().to_string(),
+                        number: Some(stored.data.len() as u64),
+                    },
+                }
+            }
+            "HEAD" => AtomValue::text(match self.head.as_deref() == Some(item.name.as_str()) {
+                true => String::from("*"),
+                false => String::from(" "),
+            }),
+            "symref" => AtomValue::text(match read_symbolic_ref(&item.name)? {
+                Some(target) => format_ref_name(&target, modifier)?,
+                None => String::new(),
+            }),
+            "upstream" => AtomValue::text(self.upstream(item, modifier)?),
+            _ => {
+                if object.is_none() {
+                    *object = Some(read_ref_object(&item.hash)?);
+                }
+                let object = object.as_ref().expect("the object was just read");
+                // %(*atom) reads the atom from the object a tag points to
+                if let Some(atom) = atom.strip_prefix('*') {
+                    return match object {
+                        RefObject::Tag(tag) => {
+                            let target = RefItem {
+                                name: item.name.clone(),
+                                hash: tag.object.clone(),
+                            };
+                            self.atom(atom, &target, &mut None)
+                        }
+                        _ => Ok(AtomValue::text(String::new())),
+                    };
+                }
+                object_atom(name, modifier, object)?
+            }
+        };
+        Ok(value)
+    }
+
+    /// %(upstream): the ref a branch tracks, or how they compare with :track and :trackshort
+    fn upstream(&self, item: &RefItem, modifier: &str) -> Result<String> {
+        let branch = match item.name.strip_prefix("refs/heads/") {
+            Some(branch) => branch,
+            None => return Ok(String::new()),
+        };
+        let tracking = match tracking(&self.config, branch, &item.hash)? {
+            Some(tracking) => tracking,
+            None => return Ok(String::new()),
+        };
+
+        let (modifier, nobracket) = match modifier.strip_suffix(",nobracket") {
+            Some(modifier) => (modifier, true),
+            None => (modifier, false),
+        };
+        Ok(match modifier {
+            "track" => match tracking.describe() {
+                described if described.is_empty() || nobracket => described,
+                described => format!("[{}]", described),
+            },
+            "trackshort" => tracking.describe_short().to_string(),
+            "remotename" => self
+                .config
+                .get(&format!("branch.{}.remote", branch))
+                .unwrap_or("")
+                .to_string(),
+            "remoteref" => self
+                .config
+                .get(&format!("branch.{}.merge", branch))
+                .unwrap_or("")
+                .to_string(),
+            modifier => format_ref_name(&tracking.upstream, modifier)?,
+        })
+    }
+}
+
+/// the atoms read from the commit or tag a ref points to. they are empty for other objects.
+fn object_atom(name: &str, modifier: &str, object: &RefObject) -> Result<AtomValue> {
+    if !OBJECT_ATOMS.contains(&name) {
+        bail!("unknown field name: {}", name);
+    }
+
+    let value = match object {
+        RefObject::Commit(commit) => commit_atom(name, modifier, commit)?,
+        RefObject::Tag(tag) => tag_atom(name, modifier, tag)?,
+        RefObject::Other => None,
+    };
+    Ok(value.unwrap_or(AtomValue::text(String::new())))
+}
+
+fn commit_atom(name: &str, modifier: &str, commit: &Commit) -> Result<Option<AtomValue>> {
+    let person = |role: &str| match role {
+        "author" => &commit.author,
+        _ => &commit.committer,
+    };
+    let value = match name {
+        "tree" => AtomValue::text(commit.tree.to_hex()),
+        "parent" => AtomValue::text(
+            commit
+                .parents
+                .iter()
+                .map(|parent| parent.to_hex())
+                .collect::<Vec<String>>()
+                .join(" "),
+        ),
+        "numparent" => AtomValue {
+            text: commit.parents.len().to_string(),
+            number: Some(commit.parents.len() as u64),
+        },
+        "authorname" | "committername" => {
+            AtomValue::text(person(&name[..name.len() - 4]).name.clone())
+        }
+        "authoremail" | "committeremail" => person_email(person(&name[..name.len() - 5]), modifier),
+        "author" | "committer" => AtomValue::text(person(name).to_string()),
+        "authordate" | "committerdate" | "creatordate" => {
+            let role = match name {
+                "authordate" => "author",
+                _ => "committer",
+            };
+            format_person_date(person(role), modifier)?
+        }
+        "creator" => AtomValue::text(commit.committer.to_string()),
+        _ => return Ok(message_atom(name, modifier, &commit.message)),
+    };
+    Ok(Some(value))
+}
+
+fn tag_atom(name: &str, modifier: &str, tag: &Tag) -> Result<Option<AtomValue>> {
+    let value = match name {
+        "tag" => AtomValue::text(tag.tag_name.clone()),
+        "type" => AtomValue::text(tag.object_type.to_string()),
+        "object" => AtomValue::text(tag.object.to_hex()),
+        "taggername" => AtomValue::text(tag.tagger.name.clone()),
+        "taggeremail" => person_email(&tag.tagger, modifier),
+        "tagger" | "creator" => AtomValue::text(tag.tagger.to_string()),
+        "taggerdate" | "creatordate" => format_person_date(&tag.tagger, modifier)?,
+        _ => return Ok(message_atom(name, modifier, &tag.commit_message)),
+    };
+    Ok(Some(value))
+}
+
+/// %(subject), %(body) and %(contents), read from the message of a commit or tag
+fn message_atom(name: &str, modifier: &str, message: &str) -> Option<AtomValue> {
+    let text = match (name, modifier) {
+        ("subject", _) | ("contents", "subject") => subject(message),
+        ("body", _) | ("contents", "body") => body(message),
+        ("contents", _) => format!("{}\n", message),
+        _ => return None,
+    };
+    Some(AtomValue::text(text))
+}
+
+/// the atoms read from the object a ref points to
+const OBJECT_ATOMS: [&str; 23] = [
+    "tree",
+    "parent",
+    "numparent",
+    "subject",
+    "body",
+    "contents",
+    "author",
+    "authorname",
+    "authoremail",
+    "authordate",
+    "committer",
+    "committername",
+    "committeremail",
+    "committerdate",
+    "tag",
+    "type",
+    "object",
+    "tagger",
+    "taggername",
+    "taggeremail",
+    "taggerdate",
+    "creator",
+    "creatordate",
+];
+
+fn person_email(person: &Author, modifier: &str) -> AtomValue {
+    AtomValue::text(match modifier {
+        "trim" => person.email.clone(),
+        "localpart" => person.email.split('@').next().unwrap_or("").to_string(),
+        _ => format!("<{}>", person.email),
+    })
+}
+
+fn format_person_date(person: &Author, modifier: &str) -> Result<AtomValue> {
+    let format = match modifier {
+        "" => DateFormat::default(),
+        modifier => DateFormat::try_from(modifier)?,
+    };
+    Ok(AtomValue {
+        text: format_date(person.time, &person.time_zone, &format)?,
+        number: Some(person.time),
+    })
+}
+
+fn read_ref_object(hash: &Hash) -> Result<RefObject> {
+    let object = Object::read_from_hash(hash.to_hex())?;
+    Ok(match object.kind {
+        ObjectKind::Commit => RefObject::Commit(Box::new(decode_commit(object.data)?)),
+        ObjectKind::Tag => RefObject::Tag(Box::new(decode_tag(object.data)?)),
+        _ => RefObject::Other,
+    })
+}
+
+/// formats a ref name for %(refname), with :short, :lstrip=<n> or :rstrip=<n>. negative
+/// counts keep that many components instead.
+pub fn format_ref_name(name: &str, modifier: &str) -> Result<String> {
+    if modifier.is_empty() {
+        return Ok(name.to_string());
+    }
+    if modifier == "short" {
+        return short_ref_name(name);
+    }
+
+    let (strip_left, count) = match (
+        modifier
+            .strip_prefix("lstrip=")
+            .or_else(|| modifier.strip_prefix("strip=")),
+        modifier.strip_prefix("rstrip="),
+    ) {
+        (Some(count), _) => (true, count),
+        (_, Some(count)) => (false, count),
+        _ => bail!("unrecognized %(refname) argument: {}", modifier),
+    };
+    let count: i64 = count
+        .parse()
+        .map_err(|_| anyhow!("unrecognized %(refname) argument: {}", modifier))?;
+    let parts: Vec<&str> = name.split('/').collect();
+    let stripped = match count < 0 {
+        true => parts.len().saturating_sub(count.unsigned_abs() as usize),
+        false => (count as usize).min(parts.len()),
+    };
+    let kept = match strip_left {
+        true => &parts[stripped..],
+        false => &parts[..parts.len() - stripped],
+    };
+    Ok(kept.join("/"))
+}
+
+/// shortens a full ref name as long as the short name doesn't refer to another ref,
+/// e.g. refs/heads/main to main and refs/remotes/origin/main to origin/main
+pub fn short_ref_name(name: &str) -> Result<String> {
+    for prefix in SHORT_PREFIXES {
+        if let Some(short) = name.strip_prefix(prefix) {
+            let expanded = expand_ref(short)?;
+            if prefix == "refs/" || expanded.is_none() || expanded.as_deref() == Some(name) {
+                return Ok(short.to_string());
+            }
+        }
+    }
+    Ok(name.to_string())
+}
+
+/// compares names so that the numbers in them are ordered by value, e.g. v1.9 before v1.10
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let split = |s: &str| {
+            let digits = s.starts_with(|c: char| c.is_ascii_digit());
+            let end = s
+                .find(|c: char| c.is_ascii_digit() != digits)
+                .unwrap_or(s.len());
+            (digits, end)
+        };
+        if a.is_empty() || b.is_empty() {
+            return a.len().cmp(&b.len());
+        }
+
+        let (a_digits, a_end) = split(a);
+        let (b_digits, b_end) = split(b);
+        let ordering = match (a_digits, b_digits) {
+            (true, true) => {
+                let (a_number, b_number) = (
+                    a[..a_end].trim_start_matches('0'),
+                    b[..b_end].trim_start_matches('0'),
+                );
+                a_number
+                    .len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(b_number))
+            }
+            _ => a[..a_end].cmp(&b[..b_end]),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = &a[a_end..];
+        b = &b[b_end..];
+    }
+}
+
+/// the escape sequence of a color given to %(color:...), e.g. red, bold green or reset
+fn color_code(color: &str) -> Result<String> {
+    let mut codes = Vec::new();
+    for word in color.split_whitespace() {
+        let code = match word {
+            "reset" | "normal" => "",
+            "bold" => "1",
+            "dim" => "2",
+            "ul" => "4",
+            "reverse" => "7",
+            "black" => "30",
+            "red" => "31",
+            "green" => "32",
+            "yellow" => "33",
+            "blue" => "34",
+            "magenta" => "35",
+            "cyan" => "36",
+            "white" => "37",
+            _ => bail!("invalid color value: {}", color),
+        };
+        codes.push(code);
+    }
+    Ok(format!("\x1b[{}m", codes.join(";")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use super::{format_ref_name, version_cmp};
+
+    #[test]
+    fn test_version_cmp() {
+        assert_eq!(version_cmp("v1.9", "v1.10"), Ordering::Less);
+        assert_eq!(version_cmp("v1.10", "v1.10"), Ordering::Equal);
+        assert_eq!(version_cmp("v2.0", "v1.10"), Ordering::Greater);
+        assert_eq!(version_cmp("v1.2", "v1.2.1"), Ordering::Less);
+        assert_eq!(version_cmp("a", "b"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_format_ref_name() {
+        let name = "refs/remotes/origin/main";
+        assert_eq!(format_ref_name(name, "").unwrap(), name);
+        assert_eq!(format_ref_name(name, "lstrip=2").unwrap(), "origin/main");
+        assert_eq!(
+            format_ref_name(name, "rstrip=1").unwrap(),
+            "refs/remotes/origin"
+        );
+        assert_eq!(format_ref_name(name, "lstrip=-1").unwrap(), "main");
+        assert!(format_ref_name(name, "nope").is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{objects::hash::Hash, rev_parse::peel};
+
+use self::reflog::append_reflog;
+
+pub mod reflog;
+pub mod refspec;
+
+const GIT_DIR: &str = ".git";
+const PACKED_REFS: &str = ".git/packed-refs";
+
+/// maximum number of symbolic refs followed before giving up
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// rules used to expand a short ref name, in order of precedence
+const DWIM_RULES: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// returns the target of a symbolic ref, or None if the ref does not exist or is not symbolic
+pub fn read_symbolic_ref(name: &str) -> Result<Option<String>> {
+    let path = PathBuf::from(GIT_DIR).join(name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .trim_end()
+        .strip_prefix("ref: ")
+        .map(|target| target.to_string()))
+}
+
+/// resolves a full ref name (e.g. HEAD, refs/heads/main) to the object it points to.
+/// symbolic refs are followed. returns None if the ref does not exist.
+pub fn resolve_ref(name: &str) -> Result<Option<Hash>> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let path = PathBuf::from(GIT_DIR).join(&name);
+        if path.is_file() {
+            let content = fs::read_to_string(&path)?;
+            let content = content.trim_end();
+            match content.strip_prefix("ref: ") {
+                Some(target) => {
+                    name = target.to_string();
+                    continue;
+                }
+                None => return Ok(Some(Hash::try_from(content.as_bytes())?)),
+            }
+        }
+
+        return Ok(read_packed_refs()?
+            .into_iter()
+            .find(|(ref_name, _)| *ref_name == name)
+            .map(|(_, hash)| hash));
+    }
+
+    Err(anyhow!("symbolic ref {} is nested too deeply", name))
+}
+
+/// expands a short ref name (e.g. main, v1.0, origin/main) into the full name of an existing ref
+pub fn expand_ref(name: &str) -> Result<Option<String>> {
+    for rule in DWIM_RULES {
+        let full_name = rule.replace("{}", name);
+        if resolve_ref(&full_name)?.is_some() {
+            return Ok(Some(full_name));
+        }
+    }
+
+    Ok(None)
+}
+
+/// checks a ref name against git's rules: no component may start with a dot or end
+/// with .lock, and "..", "@{", "//", control characters, spaces and ~^:?*[\ are forbidden
+pub fn is_valid_ref_name(name: &str) -> bool {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    !name.is_empty()
+        && name != "@"
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(forbidden)
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+/// lists all refs whose names start with the given prefix, sorted by name
+pub fn list_refs(prefix: &str) -> Result<Vec<(String, Hash)>> {
+    let mut refs = read_packed_refs()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .collect::<Vec<_>>();
+
+    let mut loose = Vec::new();
+    collect_loose_refs(&PathBuf::from(GIT_DIR).join("refs"), "refs", &mut loose)?;
+    for name in loose {
+        if !name.starts_with(prefix) {
+            continue;
+        }
+
+        if let Some(hash) = resolve_ref(&name)? {
+            // loose refs take precedence over packed ones
+            refs.retain(|(packed_name, _)| *packed_name != name);
+            refs.push((name, hash));
+        }
+    }
+
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(refs)
+}
+
+fn collect_loose_refs(dir: &PathBuf, prefix: &str, refs: &mut Vec<String>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_refs(&entry.path(), &name, refs)?;
+        } else if !name.ends_with(".lock") {
+            // refs being updated are locked by <ref>.lock files, which aren't refs
+            refs.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// points a ref (e.g. HEAD, refs/heads/main) at an object, and records the update in its reflog.
+/// a symbolic ref is updated through the ref it points to, and both reflogs record the update.
+pub fn update_ref(name: &str, new: &Hash, message: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new();
+    transaction.lock(name, Some(new), message)?;
+    transaction.commit()
+}
+
+/// ref updates applied together. each ref is locked when it is added, so that its value can
+/// be checked before anything is written. dropping the transaction releases the locks and
+/// leaves the refs as they were.
+#[derive(Default)]
+pub struct RefTransaction {
+    locks: Vec<RefLock>,
+}
+
+struct RefLock {
+    /// the ref as named, then the refs it points to, whose reflogs all record the update
+    names: Vec<String>,
+    path: PathBuf,
+    lock: PathBuf,
+    held: bool,
+    old: Option<Hash>,
+    new: Option<Hash>,
+    message: String,
+}
+
+impl RefTransaction {
+    pub fn new() -> RefTransaction {
+        RefTransaction::default()
+    }
+
+    /// locks a ref to point it at an object, or to delete it if new is None, and returns its
+    /// value under the lock. symbolic refs are updated through the ref they point to, but are
+    /// deleted themselves.
+    pub fn lock(&mut self, name: &str, new: Option<&Hash>, message: &str) -> Result<Option<Hash>> {
+        let mut names = vec![name.to_string()];
+        while let Some(target) = read_symbolic_ref(names.last().expect("names is not empty"))? {
+            if new.is_none() {
+                break;
+            }
+            if names.len() > MAX_SYMREF_DEPTH {
+                bail!("symbolic ref {} is nested too deeply", name);
+            }
+            names.push(target);
+        }
+
+        let target = names.last().expect("names is not empty").clone();
+        let path = PathBuf::from(GIT_DIR).join(&target);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = lock_path(&path);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+            .map_err(|err| {
+                anyhow!(
+                    "cannot lock ref '{}': unable to create {}: {}",
+                    target,
+                    lock.display(),
+                    err
+                )
+            })?;
+        self.locks.push(RefLock {
+            names,
+            path,
+            lock,
+            held: true,
+            old: None,
+            new: new.cloned(),
+            message: message.to_string(),
+        });
+
+        let old = resolve_ref(&target)?;
+        self.locks.last_mut().expect("a lock was just added").old = old.clone();
+        Ok(old)
+    }
+
+    /// applies the updates. all the reflogs are written before any ref is, so that failing
+    /// to write one leaves the refs as they were.
+    pub fn commit(self) -> Result<()> {
+        for lock in &self.locks {
+            if let Some(new) = &lock.new {
+                fs::write(&lock.lock, format!("{:x}\n", new))?;
+                for name in &lock.names {
+                    append_reflog(name, lock.old.as_ref(), new, &lock.message)?;
+                }
+            }
+        }
+
+        for mut lock in self.locks {
+            match lock.new {
+                Some(_) => {
+                    fs::rename(&lock.lock, &lock.path)?;
+                    lock.held = false;
+                }
+                None => delete_ref(&lock.names[0])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if self.held {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+/// points a ref at an object without recording the update in its reflog
+pub fn write_ref(name: &str, new: &Hash) -> Result<()> {
+    write_ref_file(name, &format!("{:x}\n", new))
+}
+
+/// points HEAD directly at a commit, detaching it from the branch it was on
+pub fn detach_head(new: &Hash, message: &str) -> Result<()> {
+    let old = resolve_ref("HEAD")?;
+    write_ref_file("HEAD", &format!("{:x}\n", new))?;
+    append_reflog("HEAD", old.as_ref(), new, message)
+}
+
+/// makes a ref (usually HEAD) point to another ref
+pub fn write_symbolic_ref(name: &str, target: &str) -> Result<()> {
+    write_ref_file(name, &format!("ref: {}\n", target))
+}
+
+/// deletes a ref, whether loose or packed, along with its reflog
+pub fn delete_ref(name: &str) -> Result<()> {
+    let path = PathBuf::from(GIT_DIR).join(name);
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+
+    let packed_path = PathBuf::from(PACKED_REFS);
+    if packed_path.is_file() {
+        let content = fs::read_to_string(&packed_path)?;
+        let mut lines = Vec::new();
+        let mut deleted = false;
+        for line in content.lines() {
+            // peeled lines belong to the ref before them
+            if deleted && line.starts_with('^') {
+                continue;
+            }
+            deleted = line
+                .split_once(' ')
+                .is_some_and(|(_, ref_name)| ref_name == name);
+            if !deleted {
+                lines.push(line);
+            }
+        }
+        if lines.len() != content.lines().count() {
+            write_locked(&packed_path, format!("{}\n", lines.join("\n")).as_bytes())?;
+        }
+    }
+
+    let log = PathBuf::from(GIT_DIR).join("logs").join(name);
+    if log.is_file() {
+        fs::remove_file(log)?;
+    }
+    Ok(())
+}
+
+/// returns the full name of the branch HEAD points to, or None if HEAD is detached
+pub fn head_branch() -> Result<Option<String>> {
+    read_symbolic_ref("HEAD")
+}
+
+fn write_ref_file(name: &str, content: &str) -> Result<()> {
+    let path = PathBuf::from(GIT_DIR).join(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_locked(&path, content.as_bytes())
+}
+
+/// writes a file through a <file>.lock file renamed into place, failing if the lock is taken
+pub fn write_locked(path: &PathBuf, content: &[u8]) -> Result<()> {
+    let lock = lock_path(path);
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|err| anyhow!("unable to create {}: {}", lock.display(), err))?;
+    let written = std::io::Write::write_all(&mut file, content);
+    if let Err(err) = written.and_then(|_| fs::rename(&lock, path)) {
+        let _ = fs::remove_file(&lock);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// moves the loose refs into the packed-refs file, along with the objects annotated tags
+/// peel to, and deletes them. symbolic refs stay loose.
+pub fn pack_refs() -> Result<()> {
+    let mut loose = Vec::new();
+    collect_loose_refs(&PathBuf::from(GIT_DIR).join("refs"), "refs", &mut loose)?;
+    let mut refs = read_packed_refs()?;
+    let mut packed = Vec::new();
+    for name in loose {
+        let content = fs::read_to_string(PathBuf::from(GIT_DIR).join(&name))?;
+        if content.starts_with("ref: ") {
+            continue;
+        }
+        let hash = Hash::try_from(content.trim_end().as_bytes())?;
+        refs.retain(|(packed_name, _)| *packed_name != name);
+        refs.push((name.clone(), hash));
+        packed.push((name, content));
+    }
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, hash) in &refs {
+        content.push_str(&format!("{:x} {}\n", hash, name));
+        let peeled = peel(hash.clone(), None)?;
+        if peeled != *hash {
+            content.push_str(&format!("^{:x}\n", peeled));
+        }
+    }
+    write_locked(&PathBuf::from(PACKED_REFS), content.as_bytes())?;
+
+    for (name, content) in packed {
+        // a ref updated since it was read keeps its loose file, which takes precedence
+        let path = PathBuf::from(GIT_DIR).join(&name);
+        if fs::read_to_string(&path)? != content {
+            continue;
+        }
+        fs::remove_file(&path)?;
+
+        // emptied directories go too, but not refs/heads, refs/tags and their like
+        let mut dir = path.parent();
+        while let Some(path) = dir {
+            if path.components().count() <= 3 || fs::remove_dir(path).is_err() {
+                break;
+            }
+            dir = path.parent();
+        }
+    }
+
+    Ok(())
+}
+
+/// reads the refs stored in the packed-refs file. peeled lines are skipped.
+fn read_packed_refs() -> Result<Vec<(String, Hash)>> {
+    let path = PathBuf::from(PACKED_REFS);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let mut refs = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or(anyhow!("invalid packed-refs line: {}", line))?;
+        refs.push((name.to_string(), Hash::try_from(hash.as_bytes())?));
+    }
+
+    Ok(refs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{
+        is_valid_ref_name, reflog::read_reflog, resolve_ref, write_symbolic_ref, RefTransaction,
+    };
+    use crate::test_repo::TestRepo;
+
+    #[test]
+    fn test_is_valid_ref_name() {
+        assert!(is_valid_ref_name("refs/heads/main"));
+        assert!(is_valid_ref_name("refs/heads/feature/x-1.2"));
+        assert!(!is_valid_ref_name("refs/heads/bad..name"));
+        assert!(!is_valid_ref_name("refs/heads/.hidden"));
+        assert!(!is_valid_ref_name("refs/heads/a.lock"));
+        assert!(!is_valid_ref_name("refs/heads/a b"));
+        assert!(!is_valid_ref_name("refs/heads/a@{1}"));
+        assert!(!is_valid_ref_name("refs/heads//a"));
+        assert!(!is_valid_ref_name("refs/heads/a/"));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let repo = TestRepo::new();
+        let tree = repo.tree(&[("a", "a")]);
+        let first = repo.commit(&tree, &[], 1, "first");
+        let second = repo.commit(&tree, &[&first], 2, "second");
+        repo.set_ref("refs/heads/a", &first);
+        repo.set_ref("refs/heads/b", &first);
+
+        // refs are locked until the transaction is dropped, and left unchanged
+        let mut transaction = RefTransaction::new();
+        let old = transaction
+            .lock("refs/heads/a", Some(&second), "update")
+            .unwrap();
+        assert_eq!(old, Some(first.clone()));
+        assert!(RefTransaction::new()
+            .lock("refs/heads/a", None, "delete")
+            .is_err());
+        drop(transaction);
+        assert!(!Path::new(".git/refs/heads/a.lock").exists());
+        assert_eq!(resolve_ref("refs/heads/a").unwrap(), Some(first.clone()));
+
+        // symbolic refs are updated through their target, and deleted themselves
+        write_symbolic_ref("HEAD", "refs/heads/a").unwrap();
+        let mut transaction = RefTransaction::new();
+        transaction.lock("HEAD", Some(&second), "update").unwrap();
+        transaction.lock("refs/heads/b", None, "delete").unwrap();
+        transaction
+            .lock("refs/heads/c", Some(&first), "create")
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(resolve_ref("refs/heads/a").unwrap(), Some(second.clone()));
+        assert_eq!(resolve_ref("refs/heads/b").unwrap(), None);
+        assert_eq!(resolve_ref("refs/heads/c").unwrap(), Some(first.clone()));
+        for name in ["HEAD", "refs/heads/a"] {
+            let reflog = read_reflog(name).unwrap();
+            assert_eq!(reflog.len(), 1);
+            assert_eq!((&reflog[0].old, &reflog[0].new), (&first, &second));
+        }
+        assert!(!Path::new(".git/refs/heads/a.lock").exists());
+        assert!(!Path::new(".git/refs/heads/b.lock").exists());
+
+        // the same ref can't be locked twice
+        let mut transaction = RefTransaction::new();
+        transaction.lock("refs/heads/a", None, "delete").unwrap();
+        assert!(transaction.lock("HEAD", Some(&first), "update").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    ident::reflog_identity,
+    objects::{commit::Author, hash::Hash},
+};
+
+use super::GIT_DIR;
+
+/// a single update of a ref, as recorded in .git/logs/<ref>
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: Hash,
+    pub new: Hash,
+    pub committer: Author,
+    pub message: String,
+}
+
+/// reads the reflog of a full ref name, oldest entry first.
+/// returns an empty list if the ref has no reflog.
+pub fn read_reflog(name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = PathBuf::from(GIT_DIR).join("logs").join(name);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(parse_entry)
+        .collect()
+}
+
+/// records an update of a ref in its reflog. like git's core.logAllRefUpdates default,
+/// reflogs are created for HEAD, branches, remote-tracking branches, notes and the stash,
+/// and other refs are only logged when they already have a reflog.
+pub fn append_reflog(name: &str, old: Option<&Hash>, new: &Hash, message: &str) -> Result<()> {
+    let path = PathBuf::from(GIT_DIR).join("logs").join(name);
+    let logged = name == "HEAD"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || name == "refs/stash";
+    if !logged && !path.is_file() {
+        return Ok(());
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let null = Hash(vec![0; 20]);
+    let committer = reflog_identity()?;
+    // messages are kept on a single line, with their whitespace collapsed
+    let message = message.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(
+        file,
+        "{:x} {:x} {}\t{}",
+        old.unwrap_or(&null),
+        new,
+        committer,
+        message
+    )?;
+
+    Ok(())
+}
+
+/// replaces the reflog of a ref with the given entries, oldest first
+pub fn write_reflog(name: &str, entries: &[ReflogEntry]) -> Result<()> {
+    let path = PathBuf::from(GIT_DIR).join("logs").join(name);
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&format!(
+            "{:x} {:x} {}\t{}\n",
+            entry.old, entry.new, entry.committer, entry.message
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// lists the refs that have a reflog, HEAD first
+pub fn list_reflogs() -> Result<Vec<String>> {
+    let logs = PathBuf::from(GIT_DIR).join("logs");
+    let mut names = Vec::new();
+    if logs.join("HEAD").is_file() {
+        names.push(String::from("HEAD"));
+    }
+    collect_reflogs(&logs.join("refs"), "refs", &mut names)?;
+    Ok(names)
+}
+
+fn collect_reflogs(dir: &PathBuf, prefix: &str, names: &mut Vec<String>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_reflogs(&entry.path(), &name, names)?;
+        } else {
+            names.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_entry(line: &str) -> Result<ReflogEntry> {
+    let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+    let mut fields = header.splitn(3, ' ');
+    let mut next_field = || {
+        fields
+            .next()
+            .ok_or(anyhow!("invalid reflog line: {}", line))
+    };
+
+    Ok(ReflogEntry {
+        old: Hash::try_from(next_field()?.as_bytes())?,
+        new: Hash::try_from(next_field()?.as_bytes())?,
+        committer: Author::try_from(next_field()?)?,
+        message: message.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_entry;
+
+    #[test]
+    fn test_parse_entry() {
+        let old = "0".repeat(40);
+        let new = "a".repeat(40);
+        let line = format!(
+            "{} {} A U Thor <author@example.com> 1112911993 -0700\tcommit (initial): first",
+            old, new
+        );
+
+        let entry = parse_entry(&line).unwrap();
+        assert_eq!(entry.old.to_hex(), old);
+        assert_eq!(entry.new.to_hex(), new);
+        assert_eq!(entry.committer.name, "A U Thor");
+        assert_eq!(entry.committer.time, 1112911993);
+        assert_eq!(entry.message, "commit (initial): first");
+    }
+}
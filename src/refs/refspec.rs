@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+
+use super::{is_valid_ref_name, DWIM_RULES};
+
+/// a refspec like +refs/heads/*:refs/remotes/origin/*, mapping refs of a remote to local
+/// refs. a pattern refspec has one '*' on each side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// the destination is updated even when the update isn't a fast-forward
+    pub force: bool,
+    pub src: String,
+    /// the local ref to store the source in, or None to only record it in FETCH_HEAD
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(refspec: &str) -> Result<Refspec> {
+        let (force, spec) = match refspec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, refspec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, "")) => (src, None),
+            Some((src, dst)) => (src, Some(dst)),
+            None => (spec, None),
+        };
+
+        let stars = |side: &str| side.matches('*').count();
+        let valid = match dst {
+            Some(dst) => {
+                stars(src) <= 1
+                    && stars(src) == stars(dst)
+                    && is_valid_ref_name(&src.replace('*', "a"))
+                    && is_valid_ref_name(&dst.replace('*', "a"))
+            }
+            None => stars(src) <= 1 && is_valid_ref_name(&src.replace('*', "a")),
+        };
+        if !valid {
+            bail!("invalid refspec '{}'", refspec);
+        }
+
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst: dst.map(|dst| dst.to_string()),
+        })
+    }
+
+    pub fn is_pattern(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// maps a ref matching the source to the destination
+    pub fn map(&self, name: &str) -> Option<String> {
+        map_side(&self.src, self.dst.as_deref()?, name)
+    }
+
+    /// maps a ref matching the destination back to the source
+    pub fn map_reverse(&self, name: &str) -> Option<String> {
+        map_side(self.dst.as_deref()?, &self.src, name)
+    }
+
+    /// whether a remote ref matches the source pattern, or is the full source ref
+    pub fn matches_src(&self, name: &str) -> bool {
+        match self.is_pattern() {
+            true => match_pattern(&self.src, name).is_some(),
+            false => self.src == name,
+        }
+    }
+
+    /// the prefixes of the remote refs the source can match, which a version 2 remote is
+    /// asked to list
+    pub fn ref_prefixes(&self) -> Vec<String> {
+        match self.src.split_once('*') {
+            Some((prefix, _)) => vec![prefix.to_string()],
+            None => DWIM_RULES
+                .iter()
+                .map(|rule| rule.replace("{}", &self.src))
+                .collect(),
+        }
+    }
+
+    /// finds the remote ref a non-pattern source names, trying its expansions in order
+    pub fn find_src<'a>(&self, remote_refs: &'a [String]) -> Option<&'a String> {
+        DWIM_RULES.iter().find_map(|rule| {
+            let full_name = rule.replace("{}", &self.src);
+            remote_refs.iter().find(|name| **name == full_name)
+        })
+    }
+}
+
+/// the local ref a destination names: refs/... is kept, heads/..., tags/... and
+/// remotes/... are put under refs/, and anything else is taken as a branch
+pub fn local_ref_name(dst: &str) -> String {
+    if dst.starts_with("refs/") {
+        dst.to_string()
+    } else if ["heads/", "tags/", "remotes/"]
+        .iter()
+        .any(|prefix| dst.starts_with(prefix))
+    {
+        format!("refs/{}", dst)
+    } else {
+        format!("refs/heads/{}", dst)
+    }
+}
+
+fn match_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let (prefix, suffix) = pattern.split_once('*')?;
+    name.strip_prefix(prefix)?.strip_suffix(suffix)
+}
+
+fn map_side(from: &str, to: &str, name: &str) -> Option<String> {
+    match to.split_once('*') {
+        Some((to_prefix, to_suffix)) => {
+            let matched = match_pattern(from, name)?;
+            Some(format!("{}{}{}", to_prefix, matched, to_suffix))
+        }
+        None if from == name => Some(to.to_string()),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{local_ref_name, Refspec};
+
+    #[test]
+    fn test_map_refspec() {
+        let refspec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(refspec.force);
+        assert_eq!(
+            refspec.map("refs/heads/main").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+        assert_eq!(
+            refspec.map_reverse("refs/remotes/origin/a/b").as_deref(),
+            Some("refs/heads/a/b")
+        );
+        assert_eq!(refspec.map("refs/tags/v1"), None);
+
+        let refspec = Refspec::parse("refs/heads/main:refs/remotes/origin/main").unwrap();
+        assert!(!refspec.force);
+        assert_eq!(
+            refspec.map("refs/heads/main").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+    }
+
+    #[test]
+    fn test_parse_refspec() {
+        assert_eq!(
+            Refspec::parse("main").unwrap(),
+            Refspec {
+                force: false,
+                src: "main".to_string(),
+                dst: None
+            }
+        );
+        assert!(Refspec::parse("refs/heads/*:refs/remotes/origin/main").is_err());
+        assert!(Refspec::parse("refs/heads/a..b").is_err());
+
+        let remote_refs = vec!["refs/heads/main".to_string(), "refs/tags/main".to_string()];
+        assert_eq!(
+            Refspec::parse("main").unwrap().find_src(&remote_refs),
+            Some(&"refs/tags/main".to_string())
+        );
+        assert_eq!(
+            Refspec::parse("+refs/heads/*:refs/remotes/origin/*")
+                .unwrap()
+                .ref_prefixes(),
+            vec!["refs/heads/"]
+        );
+        assert_eq!(Refspec::parse("main").unwrap().ref_prefixes().len(), 6);
+        assert_eq!(local_ref_name("topic"), "refs/heads/topic");
+        assert_eq!(local_ref_name("tags/v1"), "refs/tags/v1");
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+
+use crate::{config::Config, refs::refspec::Refspec};
+
+/// the command run on the remote side to serve fetches
+pub const DEFAULT_UPLOAD_PACK: &str = "git-upload-pack";
+
+/// the command run on the remote side to receive pushes
+pub const DEFAULT_RECEIVE_PACK: &str = "git-receive-pack";
+
+/// a repository to fetch from or push to, configured under remote.<name> or given as a url
+#[derive(Debug, Clone)]
+pub struct Remote {
+    /// the name of the remote, or the url when it isn't configured
+    pub name: String,
+    pub url: String,
+    /// the url pushes go to, when it isn't the url fetches come from
+    pub push_url: Option<String>,
+    /// the refspecs fetched when none are given
+    pub fetch: Vec<Refspec>,
+    /// the refspecs pushed when none are given
+    pub push: Vec<String>,
+    pub upload_pack: String,
+    pub receive_pack: String,
+    /// whether the remote is configured, as opposed to a bare url
+    pub configured: bool,
+}
+
+/// reads a remote from the config. a name that isn't a configured remote is taken as a url.
+pub fn remote(config: &Config, name: &str) -> Result<Remote> {
+    let url = match config.get(&format!("remote.{}.url", name)) {
+        Some(url) => url.to_string(),
+        None => {
+            return Ok(Remote {
+                name: name.to_string(),
+                url: name.to_string(),
+                push_url: None,
+                fetch: Vec::new(),
+                push: Vec::new(),
+                upload_pack: DEFAULT_UPLOAD_PACK.to_string(),
+                receive_pack: DEFAULT_RECEIVE_PACK.to_string(),
+                configured: false,
+            })
+        }
+    };
+
+    let fetch = config
+        .get_all(&format!("remote.{}.fetch", name))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<_>>>()?;
+    let upload_pack = config
+        .get(&format!("remote.{}.uploadpack", name))
+        .unwrap_or(DEFAULT_UPLOAD_PACK)
+        .to_string();
+    let receive_pack = config
+        .get(&format!("remote.{}.receivepack", name))
+        .unwrap_or(DEFAULT_RECEIVE_PACK)
+        .to_string();
+
+    Ok(Remote {
+        name: name.to_string(),
+        url,
+        push_url: config
+            .get(&format!("remote.{}.pushurl", name))
+            .map(|url| url.to_string()),
+        fetch,
+        push: config
+            .get_all(&format!("remote.{}.push", name))
+            .into_iter()
+            .map(|spec| spec.to_string())
+            .collect(),
+        upload_pack,
+        receive_pack,
+        configured: true,
+    })
+}
+
+/// the remote pushed to by default: the push remote of the current branch,
+/// remote.pushDefault, then the remote fetched from by default
+pub fn default_push_remote(config: &Config, branch: Option<&str>) -> String {
+    branch
+        .and_then(|branch| config.get(&format!("branch.{}.pushRemote", branch)))
+        .or_else(|| config.get("remote.pushDefault"))
+        .map(|remote| remote.to_string())
+        .unwrap_or_else(|| default_remote(config, branch))
+}
+
+/// the remote fetched from by default: the remote of the current branch, or origin
+pub fn default_remote(config: &Config, branch: Option<&str>) -> String {
+    branch
+        .and_then(|branch| config.get(&format!("branch.{}.remote", branch)))
+        .unwrap_or("origin")
+        .to_string()
+}
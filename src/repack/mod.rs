@@ -0,0 +1,249 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use anyhow::Result;
+
+use crate::{
+    index::read_index,
+    objects::{
+        hash::Hash,
+        pack::{has_packed, packs},
+        Object, ObjectKind,
+    },
+    pack_objects::write_pack_files,
+    pack_protocol::pack_writer::{PackWriter, PackWriterOptions},
+    refs::reflog::{list_reflogs, read_reflog},
+    revwalk::{RevWalk, WalkedObject},
+};
+
+const OBJECTS_DIR: &str = ".git/objects";
+const PACK_DIR: &str = ".git/objects/pack";
+
+pub struct RepackOptions {
+    /// pack all reachable objects, rather than only the loose ones
+    pub all: bool,
+    /// with all, the objects of the packs replaced that are no longer reachable are
+    /// written loose, so that they are only lost once pruned
+    pub loosen_unreachable: bool,
+    /// remove the packs and the loose objects made redundant by the new pack
+    pub delete: bool,
+    pub quiet: bool,
+    pub window: usize,
+    pub depth: usize,
+}
+
+/// packs the reachable objects that are loose, or all of them into a single pack. packs
+/// with a .keep file are left alone, and so are the objects they hold.
+pub fn repack(options: RepackOptions) -> Result<()> {
+    let (kept, packs): (Vec<_>, Vec<_>) = packs()?
+        .into_iter()
+        .partition(|(path, _)| path.with_extension("keep").is_file());
+
+    let mut writer = PackWriter::new(PackWriterOptions {
+        window: options.window,
+        depth: options.depth,
+        ..Default::default()
+    });
+    let mut packed = HashSet::new();
+    for object in reachable_objects()? {
+        if kept
+            .iter()
+            .any(|(_, index)| index.find(&object.hash).is_some())
+        {
+            continue;
+        }
+        if !options.all && has_packed(&object.hash)? {
+            continue;
+        }
+        let name = (object.kind != ObjectKind::Commit).then_some(object.path.as_str());
+        writer.add(Object::read_from_hash(object.hash.to_hex())?, name)?;
+        packed.insert(object.hash);
+    }
+
+    let pack_dir = PathBuf::from(PACK_DIR);
+    let written = match writer.object_count() {
+        0 => {
+            if !options.quiet {
+                println!("Nothing new to pack.");
+            }
+            None
+        }
+        _ => {
+            fs::create_dir_all(&pack_dir)?;
+            let base_name = pack_dir.join("pack");
+            Some(write_pack_files(writer, &base_name.to_string_lossy())?)
+        }
+    };
+
+    if !options.delete {
+        return Ok(());
+    }
+
+    if options.all {
+        // the new pack may have the content, and so the name, of an old one
+        let written = written.map(|checksum| pack_dir.join(format!("pack-{:x}.pack", checksum)));
+        for (path, index) in packs {
+            if Some(&path) == written.as_ref() {
+                continue;
+            }
+
+            if options.loosen_unreachable {
+                let mtime = fs::metadata(&path)?.modified()?;
+                for entry in index.entries() {
+                    if packed.contains(&entry.hash) {
+                        continue;
+                    }
+                    let (dir, file_name) = entry.hash.get_object_path();
+                    let loose = PathBuf::from(OBJECTS_DIR).join(dir).join(file_name);
+                    if loose.is_file() {
+                        continue;
+                    }
+                    Object::read_from_hash(entry.hash.to_hex())?.write()?;
+                    // loosened objects are as old as their pack, so that they are pruned
+                    // when it would have been
+                    fs::File::options()
+                        .write(true)
+                        .open(&loose)?
+                        .set_modified(mtime)?;
+                }
+            }
+
+            for extension in ["pack", "idx", "rev", "bitmap"] {
+                let path = path.with_extension(extension);
+                if path.is_file() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+    }
+
+    prune_packed()
+}
+
+/// removes the loose objects that are also packed
+fn prune_packed() -> Result<()> {
+    for (hash, path) in Object::list_loose()? {
+        if has_packed(&hash)? {
+            fs::remove_file(&path)?;
+            if let Some(dir) = path.parent() {
+                // the fan-out directory is only removed once empty
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// lists the objects reachable from HEAD, the refs, the entries of their reflogs and the
+/// index, commits first. objects are listed with the path they were found at.
+pub fn reachable_objects() -> Result<Vec<WalkedObject>> {
+    let mut walk = RevWalk::new();
+    walk.push_all(false)?;
+    let null = Hash(vec![0; 20]);
+    for name in list_reflogs()? {
+        for entry in read_reflog(&name)? {
+            for hash in [entry.old, entry.new] {
+                if hash != null && Object::exists(&hash)? {
+                    walk.push(hash)?;
+                }
+            }
+        }
+    }
+
+    let mut commits = Vec::new();
+    while let Some(commit) = walk.next_commit()? {
+        commits.push(commit.hash);
+    }
+    let mut objects: Vec<WalkedObject> = commits
+        .iter()
+        .map(|hash| WalkedObject {
+            hash: hash.clone(),
+            kind: ObjectKind::Commit,
+            path: String::new(),
+        })
+        .collect();
+    objects.extend(walk.objects(&commits)?);
+
+    // staged content is kept, though no commit has it yet
+    let mut seen: HashSet<Hash> = objects.iter().map(|object| object.hash.clone()).collect();
+    for entry in read_index()?.entries {
+        if !seen.contains(&entry.hash) && Object::exists(&entry.hash)? {
+            seen.insert(entry.hash.clone());
+            objects.push(WalkedObject {
+                hash: entry.hash,
+                kind: ObjectKind::Blob,
+                path: entry.path,
+            });
+        }
+    }
+
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{
+        objects::{hash::Hash, pack::has_packed, Object, ObjectKind},
+        test_repo::TestRepo,
+    };
+
+    use super::{reachable_objects, repack, RepackOptions};
+
+    #[test]
+    fn test_repack_tags_of_trees_and_blobs() {
+        let repo = TestRepo::new();
+        let commit_tree = repo.tree(&[("a", "a")]);
+        let commit = repo.commit(&commit_tree, &[], 1000, "commit");
+        repo.set_ref("refs/heads/main", &commit);
+
+        let tree = repo.tree(&[("tagged", "in a tagged tree")]);
+        let tree_tag = repo.tag(&tree, ObjectKind::Tree, "tree");
+        repo.set_ref("refs/tags/tree", &tree_tag);
+        let blob = repo.blob("tagged blob");
+        let blob_tag = repo.tag(&blob, ObjectKind::Blob, "blob");
+        repo.set_ref("refs/tags/blob", &blob_tag);
+        let light = repo.blob("lightweight");
+        repo.set_ref("refs/tags/light", &light);
+        let unreachable = repo.blob("unreachable");
+
+        let expected: HashSet<Hash> = [
+            commit,
+            commit_tree,
+            repo.blob("a"),
+            tree_tag,
+            tree,
+            repo.blob("in a tagged tree"),
+            blob_tag,
+            blob,
+            light,
+        ]
+        .into_iter()
+        .collect();
+        let reachable: HashSet<Hash> = reachable_objects()
+            .unwrap()
+            .into_iter()
+            .map(|object| object.hash)
+            .collect();
+        assert_eq!(reachable, expected);
+
+        repack(RepackOptions {
+            all: true,
+            loosen_unreachable: false,
+            delete: true,
+            quiet: true,
+            window: 10,
+            depth: 50,
+        })
+        .unwrap();
+        for hash in &expected {
+            assert!(has_packed(hash).unwrap());
+        }
+        let loose: Vec<Hash> = Object::list_loose()
+            .unwrap()
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        assert_eq!(loose, vec![unreachable]);
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    diff::matches_paths,
+    index::{index_mtime, read_index, write_index, Index, IndexEntry},
+    log::subject,
+    objects::{hash::Hash, ObjectKind},
+    refs::{reflog::append_reflog, resolve_ref, update_ref},
+    rev_parse::{abbreviate, peel, resolve_commit, resolve_revision},
+    sequencer::read_commit,
+    worktree::{checkout_file, is_clean, read_worktree_file, remove_file, reset_hard},
+};
+
+const ORIG_HEAD: &str = ".git/ORIG_HEAD";
+const MERGE_HEAD: &str = ".git/MERGE_HEAD";
+
+/// the files recording a merge, cherry-pick or revert in progress, forgotten by a reset
+const BRANCH_STATE: [&str; 7] = [
+    MERGE_HEAD,
+    ".git/MERGE_MSG",
+    ".git/MERGE_MODE",
+    ".git/CHERRY_PICK_HEAD",
+    ".git/REVERT_HEAD",
+    ".git/SQUASH_MSG",
+    ".git/AUTO_MERGE",
+];
+
+/// the index entries of a path, and its entries in HEAD and in the target
+type PathEntries<'a> = (
+    Vec<&'a IndexEntry>,
+    Option<&'a IndexEntry>,
+    Option<&'a IndexEntry>,
+);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResetMode {
+    /// only move HEAD
+    Soft,
+    /// move HEAD and reset the index
+    #[default]
+    Mixed,
+    /// move HEAD and reset the index and the working tree, discarding local changes
+    Hard,
+    /// like hard, but keep the unstaged changes to files that don't change
+    Merge,
+    /// like hard, but keep the local changes to files that don't change, and refuse to
+    /// reset files with local changes
+    Keep,
+}
+
+impl ResetMode {
+    fn name(&self) -> &str {
+        match self {
+            ResetMode::Soft => "soft",
+            ResetMode::Mixed => "mixed",
+            ResetMode::Hard => "hard",
+            ResetMode::Merge => "merge",
+            ResetMode::Keep => "keep",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResetOptions {
+    pub mode: ResetMode,
+    /// the commit to reset to (HEAD by default), or the tree-ish to take entries from
+    /// when paths are given
+    pub commit: Option<String>,
+    /// reset only the index entries of these paths, without moving HEAD
+    pub paths: Vec<String>,
+}
+
+/// moves the current branch to a commit, resetting the index and the working tree
+/// according to the mode, or resets the index entries of some paths.
+pub fn reset(options: ResetOptions) -> Result<()> {
+    let rev = options.commit.as_deref().unwrap_or("HEAD");
+    if !options.paths.is_empty() {
+        if options.mode != ResetMode::Mixed {
+            bail!("Cannot do {} reset with paths.", options.mode.name());
+        }
+        return reset_paths(rev, &options.paths);
+    }
+
+    let commit = resolve_commit(rev).map_err(|_| {
+        anyhow!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree.\nUse '--' to separate paths from revisions, like this:\n'mgit <command> [<revision>...] -- [<file>...]'",
+            rev
+        )
+    })?;
+    let tree = read_commit(&commit)?.tree;
+    let index = read_index()?;
+
+    let reset = match options.mode {
+        ResetMode::Soft => {
+            if Path::new(MERGE_HEAD).is_file() || index.has_conflicts() {
+                bail!("Cannot do a soft reset in the middle of a merge.");
+            }
+            None
+        }
+        ResetMode::Mixed => Some(reset_index(&index, &Index::from_tree(&tree)?)),
+        ResetMode::Hard => Some(reset_hard(&index, &tree)?),
+        ResetMode::Merge | ResetMode::Keep => {
+            let head = Index::from_tree(&read_commit(&resolve_commit("HEAD")?)?.tree)?;
+            let target = Index::from_tree(&tree)?;
+            let reset = merge_reset(&index, &head, &target, options.mode).map_err(|err| {
+                anyhow!("{}\nCould not reset index file to revision '{}'.", err, rev)
+            })?;
+            Some(reset)
+        }
+    };
+    if let Some(reset) = &reset {
+        write_index(reset)?;
+    }
+
+    move_head(&commit, &format!("reset: moving to {}", rev))?;
+    for file in BRANCH_STATE {
+        if Path::new(file).is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+
+    match options.mode {
+        ResetMode::Mixed => write_unstaged(&reset.expect("the index is reset"))?,
+        ResetMode::Hard => println!(
+            "HEAD is now at {} {}",
+            abbreviate(&commit)?,
+            subject(&read_commit(&commit)?.message)
+        ),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// points HEAD (and the branch it is on) at a commit, saving the old HEAD in ORIG_HEAD.
+/// the branch isn't logged when it doesn't move.
+pub fn move_head(commit: &Hash, message: &str) -> Result<()> {
+    let head = resolve_ref("HEAD")?;
+    if let Some(head) = &head {
+        fs::write(ORIG_HEAD, format!("{:x}\n", head))?;
+    }
+    match head.as_ref() == Some(commit) {
+        true => append_reflog("HEAD", head.as_ref(), commit, message),
+        false => update_ref("HEAD", commit, message),
+    }
+}
+
+/// resets the index entries of some paths to the ones of a tree-ish, leaving the
+/// working tree alone
+fn reset_paths(rev: &str, paths: &[String]) -> Result<()> {
+    let tree = resolve_revision(rev)
+        .and_then(|hash| peel(hash, Some(ObjectKind::Tree)))
+        .map_err(|_| anyhow!("Could not resolve '{}' to a tree.", rev))?;
+    let tree_index = Index::from_tree(&tree)?;
+    let index = read_index()?;
+
+    let mut reset = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    for entry in &index.entries {
+        if !matches_paths(&entry.path, false, paths) {
+            reset.entries.push(entry.clone());
+        }
+    }
+    for entry in &tree_index.entries {
+        if matches_paths(&entry.path, false, paths) {
+            reset.add(keep_stat(&index, entry));
+        }
+    }
+
+    write_index(&reset)?;
+    write_unstaged(&reset)
+}
+
+/// the index of a tree, with the stat information of the entries that didn't change
+fn reset_index(index: &Index, tree_index: &Index) -> Index {
+    let mut reset = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    reset.entries = tree_index
+        .entries
+        .iter()
+        .map(|entry| keep_stat(index, entry))
+        .collect();
+    reset
+}
+
+/// an entry of a tree, or the index entry of its path if it has the same content
+fn keep_stat(index: &Index, entry: &IndexEntry) -> IndexEntry {
+    match index.entry(&entry.path) {
+        Some(old) if old.mode == entry.mode && old.hash == entry.hash => {
+            let mut kept = old.clone();
+            kept.flags = entry.flags;
+            kept.extended_flags = entry.extended_flags;
+            kept
+        }
+        _ => entry.clone(),
+    }
+}
+
+/// resets the index and the working tree to the target for --merge and --keep, refusing
+/// to lose local changes. --merge resets the paths whose index entries differ from the
+/// target, which must not have unstaged changes, and keeps the unstaged changes of the
+/// others. --keep resets the paths that differ between HEAD and the target, which must
+/// not have any local changes, then resets the index like a mixed reset.
+fn merge_reset(index: &Index, head: &Index, target: &Index, mode: ResetMode) -> Result<Index> {
+    let index_mtime = index_mtime()?;
+    let mut paths: BTreeMap<&str, PathEntries> = BTreeMap::new();
+    for entry in &index.entries {
+        paths.entry(&entry.path).or_default().0.push(entry);
+    }
+    for entry in &head.entries {
+        paths.entry(&entry.path).or_default().1 = Some(entry);
+    }
+    for entry in &target.entries {
+        paths.entry(&entry.path).or_default().2 = Some(entry);
+    }
+
+    let same = |a: Option<&IndexEntry>, b: Option<&IndexEntry>| match (a, b) {
+        (Some(a), Some(b)) => a.mode == b.mode && a.hash == b.hash,
+        (None, None) => true,
+        _ => false,
+    };
+    // a missing file has no changes to lose
+    let uptodate = |entry: &IndexEntry| -> Result<bool> {
+        Ok(is_clean(entry, index_mtime)? || fs::symlink_metadata(&entry.path).is_err())
+    };
+    let untracked =
+        |path: &str| fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir());
+
+    let mut reset = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    // the paths whose files are replaced by the target's, or removed
+    let mut checkouts = Vec::new();
+    for (path, (entries, head, target)) in paths {
+        let unmerged = entries.iter().any(|entry| entry.stage() != 0);
+        let indexed = entries.first().copied().filter(|_| !unmerged);
+
+        let keep = match (mode, indexed) {
+            // conflicts are resolved to the target by --merge
+            (ResetMode::Merge, _) if unmerged => false,
+            (ResetMode::Merge, _) if same(indexed, target) => true,
+            (ResetMode::Merge, Some(indexed)) if !uptodate(indexed)? => {
+                bail!("Entry '{}' not uptodate. Cannot merge.", path)
+            }
+            (ResetMode::Merge, _) => false,
+            (_, _) if unmerged => {
+                bail!(
+                    "Entry '{}' would be overwritten by merge. Cannot merge.",
+                    path
+                )
+            }
+            (_, Some(_)) if head.is_none() && target.is_none() => true,
+            (_, Some(_)) if same(head, target) || same(indexed, target) => true,
+            (_, Some(indexed)) => {
+                if !same(Some(indexed), head) {
+                    bail!(
+                        "Entry '{}' would be overwritten by merge. Cannot merge.",
+                        path
+                    );
+                }
+                if !uptodate(indexed)? {
+                    bail!("Entry '{}' not uptodate. Cannot merge.", path);
+                }
+                false
+            }
+            // the deletion of the path is staged
+            (_, None) if head.is_some() && target.is_some() => match same(head, target) {
+                true => true,
+                false => bail!(
+                    "Entry '{}' would be overwritten by merge. Cannot merge.",
+                    path
+                ),
+            },
+            (_, None) if target.is_some() => false,
+            (_, None) => {
+                if head.is_some() && untracked(path) {
+                    bail!(
+                        "Untracked working tree file '{}' would be removed by merge.",
+                        path
+                    );
+                }
+                true
+            }
+        };
+
+        if keep {
+            reset.entries.extend(entries.into_iter().cloned());
+            continue;
+        }
+        if indexed.is_none() && !unmerged && target.is_some() && untracked(path) {
+            bail!(
+                "Untracked working tree file '{}' would be overwritten by merge.",
+                path
+            );
+        }
+        checkouts.push((path, indexed, target));
+    }
+
+    for (path, indexed, target) in checkouts {
+        match target {
+            Some(target) if same(indexed, Some(target)) => {
+                reset.entries.push(keep_stat(index, target))
+            }
+            Some(target) => {
+                let mode = target.mode.try_into()?;
+                reset
+                    .entries
+                    .push(checkout_file(path, mode, &target.hash, 0)?);
+            }
+            None => remove_file(path)?,
+        }
+    }
+    reset
+        .entries
+        .sort_by(|a, b| (a.path.as_bytes(), a.stage()).cmp(&(b.path.as_bytes(), b.stage())));
+
+    match mode {
+        ResetMode::Keep => Ok(reset_index(&reset, target)),
+        _ => Ok(reset),
+    }
+}
+
+/// lists the files whose working tree content differs from the reset index
+fn write_unstaged(index: &Index) -> Result<()> {
+    let index_mtime = index_mtime()?;
+    let mut unstaged = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        match read_worktree_file(entry, index_mtime)? {
+            None => unstaged.push(format!("D\t{}", entry.path)),
+            Some(file) if file.hash != entry.hash || file.mode as u32 != entry.mode => {
+                unstaged.push(format!("M\t{}", entry.path))
+            }
+            Some(_) => {}
+        }
+    }
+
+    if !unstaged.is_empty() {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "Unstaged changes after reset:")?;
+        for line in unstaged {
+            writeln!(stdout, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        index::{Index, IndexEntry},
+        objects::{hash::Hash, tree::EntryMode},
+    };
+
+    use super::reset_index;
+
+    #[test]
+    fn test_reset_index() {
+        let entry = |path: &str, byte: u8, stage: u8| {
+            IndexEntry::new(path, EntryMode::RegularFile, Hash(vec![byte; 20]), stage)
+        };
+        let mut same = entry("a", 1, 0);
+        same.size = 2;
+        let mut changed = entry("b", 2, 0);
+        changed.size = 2;
+        let index = Index {
+            entries: vec![same.clone(), changed, entry("c", 3, 1), entry("c", 4, 2)],
+            ..Default::default()
+        };
+        let tree_index = Index {
+            entries: vec![entry("a", 1, 0), entry("b", 5, 0), entry("c", 3, 0)],
+            ..Default::default()
+        };
+
+        let reset = reset_index(&index, &tree_index);
+        assert_eq!(
+            reset.entries,
+            vec![same, entry("b", 5, 0), entry("c", 3, 0)]
+        );
+    }
+}
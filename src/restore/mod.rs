@@ -0,0 +1,223 @@
+use std::fs;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    diff::matches_paths,
+    index::{read_index, write_index, Index, IndexEntry},
+    objects::{tree::EntryMode, ObjectKind},
+    rev_parse::{peel, resolve_revision},
+    worktree::{checkout_file, remove_file},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// the tree-ish to restore from. defaults to the index when only the working tree is
+    /// restored, and to HEAD otherwise.
+    pub source: Option<String>,
+    /// restore the index entries
+    pub staged: bool,
+    /// restore the working tree files, the default when --staged isn't given
+    pub worktree: bool,
+    pub paths: Vec<String>,
+}
+
+/// restores the files of some paths in the working tree and/or their index entries from
+/// a tree-ish or the index. paths missing from the source are removed.
+pub fn restore(options: RestoreOptions) -> Result<()> {
+    if options.paths.is_empty() {
+        bail!("you must specify path(s) to restore");
+    }
+    let worktree = options.worktree || !options.staged;
+    let mut index = read_index()?;
+
+    let source = match (&options.source, options.staged) {
+        (Some(rev), _) => Some(rev.as_str()),
+        (None, true) => Some("HEAD"),
+        (None, false) => None,
+    };
+    let source_entries: Vec<IndexEntry> = match source {
+        Some(rev) => {
+            let tree = resolve_revision(rev)
+                .and_then(|hash| peel(hash, Some(ObjectKind::Tree)))
+                .map_err(|_| anyhow!("could not resolve {}", rev))?;
+            Index::from_tree(&tree)?.entries
+        }
+        None => index.entries.clone(),
+    };
+
+    let matches = |path: &str| matches_paths(path, false, &options.paths);
+    for pathspec in &options.paths {
+        let pathspec = [pathspec.clone()];
+        let matched = source_entries
+            .iter()
+            .chain(index.entries.iter())
+            .any(|entry| matches_paths(&entry.path, false, &pathspec));
+        if !matched {
+            bail!(
+                "pathspec '{}' did not match any file(s) known to git",
+                pathspec[0]
+            );
+        }
+    }
+    if source.is_none() {
+        // the working tree can only be restored from merged entries
+        if let Some(entry) = index
+            .entries
+            .iter()
+            .find(|entry| entry.stage() != 0 && matches(&entry.path))
+        {
+            bail!("path '{}' is unmerged", entry.path);
+        }
+    }
+
+    // paths of the index that the source doesn't have
+    let removed: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| matches(&entry.path))
+        .filter(|entry| {
+            !source_entries
+                .iter()
+                .any(|source| source.path == entry.path)
+        })
+        .map(|entry| entry.path.clone())
+        .collect();
+
+    for entry in source_entries.iter().filter(|entry| matches(&entry.path)) {
+        let mode = EntryMode::try_from(entry.mode)?;
+        let restored = match worktree {
+            true => checkout_file(&entry.path, mode, &entry.hash, 0)?,
+            false => entry.clone(),
+        };
+
+        let indexed = index
+            .entry(&entry.path)
+            .is_some_and(|indexed| indexed.mode == entry.mode && indexed.hash == entry.hash);
+        if options.staged && !indexed {
+            index.add(IndexEntry::new(&entry.path, mode, entry.hash.clone(), 0));
+        }
+        // the stat information of an entry is refreshed along with its file
+        if worktree && (options.staged || indexed) {
+            if let Some(indexed) = index.entry(&entry.path).cloned() {
+                let mut refreshed = indexed;
+                refreshed.refresh(&fs::symlink_metadata(&restored.path)?);
+                index.add(refreshed);
+            }
+        }
+    }
+    for path in &removed {
+        if worktree {
+            remove_file(path)?;
+        }
+        if options.staged {
+            index.remove(path);
+        }
+    }
+
+    write_index(&index)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        index::{read_index, write_index, IndexEntry},
+        objects::{hash::Hash, tree::EntryMode},
+        test_repo::TestRepo,
+    };
+
+    use super::{restore, RestoreOptions};
+
+    /// main~1 has a = "one", main has a = "two". the index has a = "staged" and the
+    /// working tree a = "modified". c is only in the index and the working tree.
+    fn setup(repo: &TestRepo) {
+        let first = repo.commit(&repo.tree(&[("a", "one"), ("b", "b")]), &[], 1000, "first");
+        let tree = repo.tree(&[("a", "two"), ("b", "b")]);
+        let second = repo.commit(&tree, &[&first], 2000, "second");
+        repo.checkout(&second);
+
+        let mut index = read_index().unwrap();
+        for (path, content) in [("a", "staged"), ("c", "c")] {
+            index.add(IndexEntry::new(
+                path,
+                EntryMode::RegularFile,
+                repo.blob(content),
+                0,
+            ));
+            fs::write(path, content).unwrap();
+        }
+        write_index(&index).unwrap();
+        fs::write("a", "modified").unwrap();
+    }
+
+    fn indexed(path: &str) -> Option<Hash> {
+        read_index()
+            .unwrap()
+            .entry(path)
+            .map(|entry| entry.hash.clone())
+    }
+
+    fn restore_paths(source: Option<&str>, staged: bool, worktree: bool, paths: &[&str]) {
+        restore(RestoreOptions {
+            source: source.map(String::from),
+            staged,
+            worktree,
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_restore_worktree_from_index() {
+        let repo = TestRepo::new();
+        setup(&repo);
+
+        restore_paths(None, false, false, &["a"]);
+        assert_eq!(fs::read_to_string("a").unwrap(), "staged");
+        assert_eq!(indexed("a"), Some(repo.blob("staged")));
+    }
+
+    #[test]
+    fn test_restore_staged() {
+        let repo = TestRepo::new();
+        setup(&repo);
+
+        // the index is restored from HEAD, the working tree is left alone
+        restore_paths(None, true, false, &["a", "c"]);
+        assert_eq!(indexed("a"), Some(repo.blob("two")));
+        assert_eq!(indexed("c"), None);
+        assert_eq!(fs::read_to_string("a").unwrap(), "modified");
+        assert_eq!(fs::read_to_string("c").unwrap(), "c");
+    }
+
+    #[test]
+    fn test_restore_source() {
+        let repo = TestRepo::new();
+        setup(&repo);
+
+        // only the working tree is restored by default, even from another source
+        restore_paths(Some("main~1"), false, false, &["a"]);
+        assert_eq!(fs::read_to_string("a").unwrap(), "one");
+        assert_eq!(indexed("a"), Some(repo.blob("staged")));
+
+        // paths the source doesn't have are removed
+        restore_paths(Some("main~1"), true, true, &["a", "c"]);
+        assert_eq!(fs::read_to_string("a").unwrap(), "one");
+        assert_eq!(indexed("a"), Some(repo.blob("one")));
+        assert_eq!(indexed("c"), None);
+        assert!(!std::path::Path::new("c").exists());
+        assert_eq!(indexed("b"), Some(repo.blob("b")));
+    }
+
+    #[test]
+    fn test_restore_unknown_path() {
+        let _repo = TestRepo::new();
+        let result = restore(RestoreOptions {
+            paths: vec![String::from("missing")],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+}
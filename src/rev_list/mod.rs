@@ -0,0 +1,134 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::revwalk::{CommitOrder, RevWalk, Side};
+
+pub struct RevListOptions {
+    /// revision arguments in the order they were given. besides revisions and ranges,
+    /// `--not`, `--all`, `--branches`, `--tags` and `--remotes` are accepted, as well as
+    /// the options below when they come after a revision.
+    pub args: Vec<String>,
+    /// only limit the walk to commits that modify these paths
+    pub paths: Vec<String>,
+    /// print the number of commits instead of listing them
+    pub count: bool,
+    /// mark commits of symmetric ranges with the side they are reachable from
+    pub left_right: bool,
+    /// also list the trees and blobs used by the listed commits
+    pub objects: bool,
+    pub reverse: bool,
+    pub max_count: Option<usize>,
+    pub first_parent: bool,
+    pub order: CommitOrder,
+}
+
+/// lists commits reachable from the given revisions in reverse chronological order
+pub fn rev_list(mut options: RevListOptions) -> Result<()> {
+    let mut walk = RevWalk::new();
+    let mut not = false;
+    let mut revisions = 0;
+    let mut args = std::mem::take(&mut options.args).into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--not" => not = !not,
+            "--all" => {
+                walk.push_all(not)?;
+                revisions += 1;
+            }
+            "--branches" => {
+                walk.push_refs("refs/heads/", not)?;
+                revisions += 1;
+            }
+            "--tags" => {
+                walk.push_refs("refs/tags/", not)?;
+                revisions += 1;
+            }
+            "--remotes" => {
+                walk.push_refs("refs/remotes/", not)?;
+                revisions += 1;
+            }
+            "--count" => options.count = true,
+            "--left-right" => options.left_right = true,
+            "--objects" => options.objects = true,
+            "--reverse" => options.reverse = true,
+            "--first-parent" => options.first_parent = true,
+            "--topo-order" => options.order = CommitOrder::Topo,
+            "--date-order" => options.order = CommitOrder::Date,
+            "-n" | "--max-count" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("{} requires a value", arg))?;
+                options.max_count = Some(parse_max_count(&value)?);
+            }
+            arg if arg.starts_with("--max-count=") => {
+                options.max_count = Some(parse_max_count(&arg["--max-count=".len()..])?);
+            }
+            arg if arg.starts_with("-n") && arg.len() > 2 => {
+                options.max_count = Some(parse_max_count(&arg[2..])?);
+            }
+            arg if arg.starts_with('-') => bail!("unknown rev-list argument {}", arg),
+            arg => {
+                walk.push_arg(arg, not)?;
+                revisions += 1;
+            }
+        }
+    }
+
+    if revisions == 0 {
+        bail!("rev-list requires at least one revision");
+    }
+
+    walk.paths(options.paths);
+    walk.order(options.order);
+    walk.reverse(options.reverse);
+    walk.max_count(options.max_count);
+    walk.first_parent(options.first_parent);
+
+    let mut commits = Vec::new();
+    let (mut left, mut right) = (0, 0);
+    while let Some(entry) = walk.next_commit()? {
+        match entry.side {
+            Some(Side::Left) => left += 1,
+            Some(Side::Right) => right += 1,
+            None => {}
+        }
+
+        if options.count {
+            commits.push(entry.hash);
+            continue;
+        }
+
+        let marker = match (options.left_right, entry.side) {
+            (true, Some(Side::Left)) => "<",
+            (true, Some(Side::Right)) => ">",
+            _ => "",
+        };
+        println!("{}{:x}", marker, entry.hash);
+
+        if options.objects {
+            commits.push(entry.hash);
+        }
+    }
+
+    if options.count {
+        if options.left_right {
+            println!("{}\t{}", left, right);
+        } else {
+            println!("{}", commits.len());
+        }
+        return Ok(());
+    }
+
+    if options.objects {
+        for object in walk.objects(&commits)? {
+            println!("{:x} {}", object.hash, object.path);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_max_count(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid max count: {}", value))
+}
@@ -0,0 +1,212 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    index::read_index,
+    objects::{
+        commit::decode_commit, hash::Hash, tag::decode_tag, tree::lookup_path, Object, ObjectKind,
+    },
+    refs::{self, reflog},
+};
+
+/// default length of abbreviated hashes
+const DEFAULT_ABBREV: usize = 7;
+
+/// minimum length of a hex prefix accepted as an object name
+const MIN_ABBREV: usize = 4;
+
+/// resolves a revision (e.g. HEAD, main~2, v1.0^{commit}, a1b2c3d^2) to an object hash.
+/// blobs can be named by path, either in a tree-ish (HEAD:src/lib.rs) or in the index (:src/lib.rs, :2:src/lib.rs).
+pub fn resolve_revision(rev: &str) -> Result<Hash> {
+    if let Some(path) = rev.strip_prefix(':') {
+        return resolve_index_path(path);
+    }
+
+    if let Some((tree_ish, path)) = rev.split_once(':') {
+        let tree = peel(resolve_revision(tree_ish)?, Some(ObjectKind::Tree))?;
+        if path.is_empty() {
+            return Ok(tree);
+        }
+        return lookup_path(&tree, path)?
+            .map(|entry| entry.hash)
+            .ok_or(anyhow!("path '{}' does not exist in '{}'", path, tree_ish));
+    }
+
+    let base_end = rev.find(['^', '~']).unwrap_or(rev.len());
+    let (base, mut suffix) = rev.split_at(base_end);
+
+    let mut hash = resolve_name(base)?;
+
+    while !suffix.is_empty() {
+        let (op, rest) = suffix.split_at(1);
+        if op == "^" && rest.starts_with('{') {
+            let end = rest
+                .find('}')
+                .ok_or(anyhow!("invalid revision {}: missing '}}'", rev))?;
+            let kind = match &rest[1..end] {
+                "" => None,
+                kind => Some(ObjectKind::try_from(kind)?),
+            };
+            hash = peel(hash, kind)?;
+            suffix = &rest[end + 1..];
+            continue;
+        }
+
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n = match &rest[..digits_end] {
+            "" => 1,
+            digits => digits.parse::<usize>()?,
+        };
+        suffix = &rest[digits_end..];
+
+        hash = peel(hash, Some(ObjectKind::Commit))?;
+        if op == "~" {
+            for _ in 0..n {
+                hash = nth_parent(&hash, 1)
+                    .map_err(|_| anyhow!("invalid revision {}: not enough ancestors", rev))?;
+            }
+        } else if n != 0 {
+            hash = nth_parent(&hash, n)
+                .map_err(|_| anyhow!("invalid revision {}: no parent {}", rev, n))?;
+        }
+    }
+
+    Ok(hash)
+}
+
+/// resolves <ref>@{n}, the value of a ref n updates ago, from its reflog
+fn resolve_reflog_entry(name: &str, position: &str) -> Result<Hash> {
+    let n: usize = position
+        .parse()
+        .map_err(|_| anyhow!("invalid reflog position: @{{{}}}", position))?;
+    let full_name = match name {
+        "" | "@" => String::from("HEAD"),
+        name => refs::expand_ref(name)?.ok_or(anyhow!("unknown revision: {}", name))?,
+    };
+
+    let entries = reflog::read_reflog(&full_name)?;
+    entries
+        .iter()
+        .rev()
+        .nth(n)
+        .map(|entry| entry.new.clone())
+        .ok_or(anyhow!(
+            "log for '{}' only has {} entries",
+            name,
+            entries.len()
+        ))
+}
+
+/// resolves a revision and peels it to a commit
+pub fn resolve_commit(rev: &str) -> Result<Hash> {
+    peel(resolve_revision(rev)?, Some(ObjectKind::Commit))
+}
+
+/// follows tags (and commits, when a tree is requested) until an object of the requested kind is reached.
+/// if no kind is requested, tags are followed until a non-tag object is reached.
+pub fn peel(hash: Hash, kind: Option<ObjectKind>) -> Result<Hash> {
+    peel_kind(hash, kind).map(|(hash, _)| hash)
+}
+
+/// peels an object like `peel`, also returning the kind of the object reached
+pub fn peel_kind(mut hash: Hash, kind: Option<ObjectKind>) -> Result<(Hash, ObjectKind)> {
+    loop {
+        let object = Object::read_from_hash(hash.to_hex())?;
+        if Some(object.kind) == kind || (kind.is_none() && object.kind != ObjectKind::Tag) {
+            return Ok((hash, object.kind));
+        }
+
+        match (object.kind, kind) {
+            (ObjectKind::Tag, _) => hash = decode_tag(object.data)?.object,
+            (ObjectKind::Commit, Some(ObjectKind::Tree)) => hash = decode_commit(object.data)?.tree,
+            (found, Some(wanted)) => bail!("object {:x} is a {}, not a {}", hash, found, wanted),
+            (_, None) => unreachable!(),
+        }
+    }
+}
+
+/// returns the shortest unique abbreviation of the hash that is at least 7 characters long
+pub fn abbreviate(hash: &Hash) -> Result<String> {
+    let hex = hash.to_hex();
+    let candidates = Object::find_by_prefix(&hex[..DEFAULT_ABBREV])?;
+
+    let mut len = DEFAULT_ABBREV;
+    while len < hex.len()
+        && candidates
+            .iter()
+            .any(|candidate| *candidate != *hash && candidate.to_hex().starts_with(&hex[..len]))
+    {
+        len += 1;
+    }
+
+    Ok(hex[..len].to_string())
+}
+
+/// resolves "path" or "<stage>:path" to the blob of an index entry
+fn resolve_index_path(path: &str) -> Result<Hash> {
+    let (stage, path) = match path.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 && ("0"..="3").contains(&stage) => {
+            (stage.parse::<u8>()?, path)
+        }
+        _ => (0, path),
+    };
+
+    read_index()?
+        .entries
+        .into_iter()
+        .find(|entry| entry.path == path && entry.stage() == stage)
+        .map(|entry| entry.hash)
+        .ok_or(anyhow!(
+            "path '{}' is not in the index at stage {}",
+            path,
+            stage
+        ))
+}
+
+fn nth_parent(hash: &Hash, n: usize) -> Result<Hash> {
+    let object = Object::read_from_hash(hash.to_hex())?;
+    let commit = decode_commit(object.data)?;
+    commit
+        .parents
+        .into_iter()
+        .nth(n - 1)
+        .ok_or(anyhow!("commit {:x} has no parent {}", hash, n))
+}
+
+fn resolve_name(name: &str) -> Result<Hash> {
+    if let Some((name, position)) = name
+        .strip_suffix('}')
+        .and_then(|name| name.rsplit_once("@{"))
+    {
+        return resolve_reflog_entry(name, position);
+    }
+
+    let name = match name {
+        "" => bail!("empty revision"),
+        "@" => "HEAD",
+        name => name,
+    };
+
+    let is_hex = name.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && name.len() == 40 {
+        return Hash::try_from(name.to_ascii_lowercase().as_bytes());
+    }
+
+    if let Some(full_name) = refs::expand_ref(name)? {
+        if let Some(hash) = refs::resolve_ref(&full_name)? {
+            return Ok(hash);
+        }
+    }
+
+    if is_hex && name.len() >= MIN_ABBREV {
+        let mut matches = Object::find_by_prefix(&name.to_ascii_lowercase())?;
+        match matches.len() {
+            0 => {}
+            1 => return Ok(matches.remove(0)),
+            _ => bail!("short object id {} is ambiguous", name),
+        }
+    }
+
+    Err(anyhow!("unknown revision: {}", name))
+}
@@ -0,0 +1,1007 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    objects::{
+        commit::{decode_commit, Commit},
+        hash::Hash,
+        tag::decode_tag,
+        tree::{decode_tree, lookup_path, EntryMode},
+        Object, ObjectKind,
+    },
+    refs,
+    rev_parse::resolve_revision,
+};
+
+/// the commit was added to the queue
+const SEEN: u8 = 1 << 0;
+/// the commit is reachable from a hidden revision
+const UNINTERESTING: u8 = 1 << 1;
+/// the commit is reachable from the left side of a symmetric range
+const LEFT: u8 = 1 << 2;
+/// the commit is reachable from the right side of a symmetric range
+const RIGHT: u8 = 1 << 3;
+/// the commit doesn't change the requested paths
+const TREESAME: u8 = 1 << 4;
+/// the parents of the commit were processed
+const PROCESSED: u8 = 1 << 5;
+
+/// number of extra commits walked after everything left in the queue is uninteresting,
+/// to tolerate small clock skews
+const SLOP: usize = 5;
+
+/// order in which commits are returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitOrder {
+    /// reverse chronological order of committer dates
+    Default,
+    /// no parent is returned before all of its children, otherwise committer date order
+    Date,
+    /// no parent is returned before all of its children, and lines of history are not intermixed
+    Topo,
+}
+
+/// side of a symmetric range (A...B) a commit is reachable from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// a commit returned by the walk
+pub struct WalkedCommit {
+    pub hash: Hash,
+    pub commit: Commit,
+    /// parents of the commit. when parents are rewritten, these are the nearest returned ancestors.
+    pub parents: Vec<Hash>,
+    /// only set for commits of a symmetric range
+    pub side: Option<Side>,
+}
+
+/// an object found by [`RevWalk::objects`], with the path it was found at
+pub struct WalkedObject {
+    pub hash: Hash,
+    pub kind: ObjectKind,
+    pub path: String,
+}
+
+/// queue entry ordered by committer time, ties are broken by insertion order
+pub(crate) struct QueueItem {
+    pub time: u64,
+    pub seq: usize,
+    pub hash: Hash,
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// predicate deciding whether a walked commit is shown
+type CommitFilter = Box<dyn Fn(&Commit) -> bool>;
+
+struct Node {
+    flags: u8,
+    commit: Option<Commit>,
+    /// parents the walk continued to, after history simplification
+    parents: Vec<Hash>,
+}
+
+/// walks the commit graph from a set of tips, newest commits first.
+///
+/// commits reachable from hidden revisions are uninteresting: they are not returned, and
+/// the flag is propagated to their ancestors as the walk goes, like git's revision machinery.
+pub struct RevWalk {
+    queue: BinaryHeap<QueueItem>,
+    nodes: HashMap<Hash, Node>,
+    seq: usize,
+    limited: bool,
+    symmetric: bool,
+    first_parent: bool,
+    paths: Vec<String>,
+    order: CommitOrder,
+    reverse: bool,
+    rewrite_parents: bool,
+    max_count: Option<usize>,
+    filter: Option<CommitFilter>,
+    /// tags, trees and blobs found while resolving the tips, with the paths they are listed with.
+    /// they are listed by `objects` but not walked as commits.
+    pending: Vec<(Hash, ObjectKind, String)>,
+    /// uninteresting revisions given by the user
+    hidden: Vec<Hash>,
+    /// uninteresting trees and blobs given by the user
+    hidden_objects: Vec<Hash>,
+    /// commits whose parents are not walked, as a shallow clone ends its history at them
+    shallow: HashSet<Hash>,
+    prepared: Option<VecDeque<WalkedCommit>>,
+    returned: usize,
+}
+
+impl Default for RevWalk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RevWalk {
+    pub fn new() -> RevWalk {
+        RevWalk {
+            queue: BinaryHeap::new(),
+            nodes: HashMap::new(),
+            seq: 0,
+            limited: false,
+            symmetric: false,
+            first_parent: false,
+            paths: Vec::new(),
+            order: CommitOrder::Default,
+            reverse: false,
+            rewrite_parents: false,
+            max_count: None,
+            filter: None,
+            pending: Vec::new(),
+            hidden: Vec::new(),
+            hidden_objects: Vec::new(),
+            shallow: HashSet::new(),
+            prepared: None,
+            returned: 0,
+        }
+    }
+
+    /// only follow the first parent of merge commits
+    pub fn first_parent(&mut self, first_parent: bool) {
+        self.first_parent = first_parent;
+    }
+
+    /// only return commits that change the given paths, simplifying history like git's default mode
+    pub fn paths(&mut self, paths: Vec<String>) {
+        self.paths = paths;
+    }
+
+    pub fn order(&mut self, order: CommitOrder) {
+        self.order = order;
+    }
+
+    /// return commits oldest first
+    pub fn reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// rewrite the parents of returned commits to their nearest returned ancestors
+    pub fn rewrite_parents(&mut self, rewrite_parents: bool) {
+        self.rewrite_parents = rewrite_parents;
+    }
+
+    pub fn max_count(&mut self, max_count: Option<usize>) {
+        self.max_count = max_count;
+    }
+
+    /// walks these commits as if they had no parents, on both sides of the walk
+    pub fn shallow(&mut self, commits: HashSet<Hash>) {
+        self.shallow = commits;
+    }
+
+    /// only return commits accepted by the filter. the walk still goes through rejected commits.
+    pub fn filter(&mut self, filter: impl Fn(&Commit) -> bool + 'static) {
+        self.filter = Some(Box::new(filter));
+    }
+
+    /// adds a tip to start walking from
+    pub fn push(&mut self, hash: Hash) -> Result<()> {
+        self.add_tip(hash, 0)
+    }
+
+    /// hides a commit and all of its ancestors from the walk
+    pub fn hide(&mut self, hash: Hash) -> Result<()> {
+        self.add_tip(hash, UNINTERESTING)
+    }
+
+    /// adds a revision argument as understood by rev-list: `A`, `^A`, `A..B` or `A...B`.
+    /// `not` inverts the meaning of the argument, as if it was given after --not.
+    pub fn push_arg(&mut self, arg: &str, not: bool) -> Result<()> {
+        if let Some((left, right)) = arg.split_once("...") {
+            let left = if left.is_empty() { "HEAD" } else { left };
+            let right = if right.is_empty() { "HEAD" } else { right };
+            self.symmetric = true;
+            self.add_tip(resolve_revision(left)?, LEFT)?;
+            self.add_tip(resolve_revision(right)?, RIGHT)?;
+            return Ok(());
+        }
+
+        if let Some((left, right)) = arg.split_once("..") {
+            let left = if left.is_empty() { "HEAD" } else { left };
+            let right = if right.is_empty() { "HEAD" } else { right };
+            self.add_tip(resolve_revision(left)?, UNINTERESTING)?;
+            self.add_tip(resolve_revision(right)?, 0)?;
+            return Ok(());
+        }
+
+        let (name, mut flags) = match arg.strip_prefix('^') {
+            Some(name) => (name, UNINTERESTING),
+            None => (arg, 0),
+        };
+
+        if not {
+            flags ^= UNINTERESTING;
+        }
+
+        self.add_tip(resolve_revision(name)?, flags)
+    }
+
+    /// adds all refs whose names start with the prefix as tips (e.g. refs/heads/ for --branches)
+    pub fn push_refs(&mut self, prefix: &str, not: bool) -> Result<()> {
+        let flags = if not { UNINTERESTING } else { 0 };
+        for (_, hash) in refs::list_refs(prefix)? {
+            self.add_tip(hash, flags)?;
+        }
+
+        Ok(())
+    }
+
+    /// adds HEAD and all refs as tips, like --all
+    pub fn push_all(&mut self, not: bool) -> Result<()> {
+        let flags = if not { UNINTERESTING } else { 0 };
+        if let Some(head) = refs::resolve_ref("HEAD")? {
+            self.add_tip(head, flags)?;
+        }
+
+        self.push_refs("refs/", not)
+    }
+
+    fn add_tip(&mut self, hash: Hash, flags: u8) -> Result<()> {
+        let object = Object::read_from_hash(hash.to_hex())?;
+        match object.kind {
+            ObjectKind::Commit => {}
+            ObjectKind::Tag => {
+                let tag = decode_tag(object.data)?;
+                // like git, tags are listed with the name stored in them
+                if flags & UNINTERESTING == 0 {
+                    self.add_pending(hash, ObjectKind::Tag, tag.tag_name);
+                }
+                return self.add_tip(tag.object, flags);
+            }
+            // trees and blobs have no history, they only matter when listing objects
+            kind => {
+                if flags & UNINTERESTING != 0 {
+                    self.hidden_objects.push(hash);
+                } else {
+                    self.add_pending(hash, kind, String::new());
+                }
+                return Ok(());
+            }
+        }
+
+        if flags & UNINTERESTING != 0 {
+            self.limited = true;
+            self.hidden.push(hash.clone());
+        }
+
+        if flags & (LEFT | RIGHT) != 0 {
+            self.limited = true;
+        }
+
+        self.add_flags(&hash, flags)?;
+        Ok(())
+    }
+
+    fn add_pending(&mut self, hash: Hash, kind: ObjectKind, name: String) {
+        if !self.pending.iter().any(|(pending, _, _)| *pending == hash) {
+            self.pending.push((hash, kind, name));
+        }
+    }
+
+    /// adds flags to a commit, queueing it if it wasn't seen yet.
+    /// uninteresting flags are propagated to ancestors that were already processed.
+    fn add_flags(&mut self, hash: &Hash, flags: u8) -> Result<()> {
+        if !self.nodes.contains_key(hash) {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            if object.kind != ObjectKind::Commit {
+                bail!("object {:x} is a {}, not a commit", hash, object.kind);
+            }
+
+            let commit = decode_commit(object.data)?;
+            self.queue.push(QueueItem {
+                time: commit.committer.time,
+                seq: self.seq,
+                hash: hash.clone(),
+            });
+            self.seq += 1;
+            self.nodes.insert(
+                hash.clone(),
+                Node {
+                    flags: SEEN | flags,
+                    commit: Some(commit),
+                    parents: Vec::new(),
+                },
+            );
+            return Ok(());
+        }
+
+        let mut stack = vec![hash.clone()];
+        while let Some(current) = stack.pop() {
+            let node = match self.nodes.get_mut(&current) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            if node.flags & flags == flags {
+                continue;
+            }
+
+            node.flags |= flags;
+            if node.flags & PROCESSED != 0 {
+                stack.extend(node.parents.iter().cloned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// returns the next commit of the walk
+    pub fn next_commit(&mut self) -> Result<Option<WalkedCommit>> {
+        if self.max_count.is_some_and(|max| self.returned >= max) {
+            return Ok(None);
+        }
+
+        let needs_list = self.limited
+            || self.order != CommitOrder::Default
+            || self.reverse
+            || self.rewrite_parents;
+
+        if needs_list {
+            if self.prepared.is_none() {
+                let list = self.prepare()?;
+                self.prepared = Some(list);
+            }
+
+            let next = self.prepared.as_mut().unwrap().pop_front();
+            if next.is_some() {
+                self.returned += 1;
+            }
+            return Ok(next);
+        }
+
+        while let Some(item) = self.queue.pop() {
+            self.process_parents(&item.hash)?;
+            let flags = self.nodes[&item.hash].flags;
+            if !self.is_shown(flags) {
+                continue;
+            }
+
+            let node = self.nodes.get_mut(&item.hash).unwrap();
+
+            let commit = node.commit.take().unwrap();
+            if self.filter.as_ref().is_some_and(|filter| !filter(&commit)) {
+                continue;
+            }
+
+            self.returned += 1;
+            return Ok(Some(WalkedCommit {
+                hash: item.hash,
+                commit,
+                parents: node.parents.clone(),
+                side: None,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// walks the whole graph, returning the commits in their final order
+    fn prepare(&mut self) -> Result<VecDeque<WalkedCommit>> {
+        let mut walked = Vec::new();
+        let mut slop = SLOP;
+        while let Some(item) = self.queue.pop() {
+            self.process_parents(&item.hash)?;
+            walked.push(item.hash);
+
+            if self
+                .queue
+                .iter()
+                .all(|item| self.is_uninteresting(self.nodes[&item.hash].flags))
+            {
+                if slop == 0 {
+                    break;
+                }
+                slop -= 1;
+            } else {
+                slop = SLOP;
+            }
+        }
+
+        // flags may have changed after a commit was walked, so filtering happens at the end
+        let shown: HashSet<Hash> = walked
+            .iter()
+            .filter(|hash| {
+                let node = &self.nodes[*hash];
+                self.is_shown(node.flags)
+                    && self.filter.as_ref().is_none_or(|filter| {
+                        filter(node.commit.as_ref().expect("walked commits are loaded"))
+                    })
+            })
+            .cloned()
+            .collect();
+
+        let mut parents: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for hash in walked.iter().filter(|hash| shown.contains(*hash)) {
+            let node_parents = if self.rewrite_parents || self.order != CommitOrder::Default {
+                self.rewritten_parents(hash, &shown)
+            } else {
+                self.nodes[hash].parents.clone()
+            };
+            parents.insert(hash.clone(), node_parents);
+        }
+
+        let order: Vec<Hash> = walked
+            .into_iter()
+            .filter(|hash| shown.contains(hash))
+            .collect();
+        let mut order = match self.order {
+            CommitOrder::Default => order,
+            sorting => self.topo_sort(order, &parents, sorting),
+        };
+
+        if let Some(max) = self.max_count {
+            order.truncate(max);
+        }
+
+        if self.reverse {
+            order.reverse();
+        }
+
+        let mut list = VecDeque::new();
+        for hash in order {
+            let node = self.nodes.get_mut(&hash).unwrap();
+            let side = match (self.symmetric, node.flags & LEFT != 0) {
+                (false, _) => None,
+                (true, true) => Some(Side::Left),
+                (true, false) => Some(Side::Right),
+            };
+
+            list.push_back(WalkedCommit {
+                parents: parents.remove(&hash).unwrap_or_default(),
+                commit: node.commit.take().unwrap(),
+                hash,
+                side,
+            });
+        }
+
+        Ok(list)
+    }
+
+    fn is_uninteresting(&self, flags: u8) -> bool {
+        flags & UNINTERESTING != 0 || (self.symmetric && flags & (LEFT | RIGHT) == LEFT | RIGHT)
+    }
+
+    /// commits reachable from both sides of a symmetric range may only get both flags after they
+    /// were processed, so this is checked again when commits are returned
+    fn is_shown(&self, flags: u8) -> bool {
+        flags & TREESAME == 0 && !self.is_uninteresting(flags)
+    }
+
+    /// decides which parents of a commit the walk continues to, and queues them
+    fn process_parents(&mut self, hash: &Hash) -> Result<()> {
+        let node = self.nodes.get_mut(hash).unwrap();
+        if node.flags & PROCESSED != 0 {
+            return Ok(());
+        }
+        node.flags |= PROCESSED;
+
+        // commits reachable from both sides of a symmetric range are common
+        if self.symmetric && node.flags & (LEFT | RIGHT) == LEFT | RIGHT {
+            node.flags |= UNINTERESTING;
+        }
+
+        let flags = node.flags;
+        let commit = node.commit.as_ref().unwrap();
+        let tree = commit.tree.clone();
+        let mut parents = commit.parents.clone();
+        if self.first_parent {
+            parents.truncate(1);
+        }
+        if self.shallow.contains(hash) {
+            parents.clear();
+        }
+
+        if flags & UNINTERESTING == 0 && !self.paths.is_empty() {
+            let own = self.path_state(&tree)?;
+            if parents.is_empty() && own.iter().all(|entry| entry.is_none()) {
+                self.nodes.get_mut(hash).unwrap().flags |= TREESAME;
+            }
+
+            for parent in &parents {
+                let parent_tree = match self.nodes.get(parent).and_then(|n| n.commit.as_ref()) {
+                    Some(parent) => parent.tree.clone(),
+                    None => {
+                        let object = Object::read_from_hash(parent.to_hex())?;
+                        decode_commit(object.data)?.tree
+                    }
+                };
+
+                if self.path_state(&parent_tree)? == own {
+                    // follow only the parent the content came from
+                    self.nodes.get_mut(hash).unwrap().flags |= TREESAME;
+                    parents = vec![parent.clone()];
+                    break;
+                }
+            }
+        }
+
+        let propagated = flags & (UNINTERESTING | LEFT | RIGHT);
+        for parent in &parents {
+            self.add_flags(parent, propagated)?;
+        }
+        self.nodes.get_mut(hash).unwrap().parents = parents;
+
+        Ok(())
+    }
+
+    /// returns the objects found at the requested paths in a tree
+    fn path_state(&self, tree: &Hash) -> Result<Vec<Option<Hash>>> {
+        self.paths
+            .iter()
+            .map(|path| {
+                let path = path.trim_matches('/');
+                if path.is_empty() || path == "." {
+                    return Ok(Some(tree.clone()));
+                }
+                Ok(lookup_path(tree, path)?.map(|entry| entry.hash))
+            })
+            .collect()
+    }
+
+    /// finds the nearest shown ancestors of a commit through the parents the walk followed
+    fn rewritten_parents(&self, hash: &Hash, shown: &HashSet<Hash>) -> Vec<Hash> {
+        let mut result = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack: Vec<Hash> = self.nodes[hash].parents.iter().rev().cloned().collect();
+        while let Some(current) = stack.pop() {
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+
+            if shown.contains(&current) {
+                if !result.contains(&current) {
+                    result.push(current);
+                }
+                continue;
+            }
+
+            if let Some(node) = self.nodes.get(&current) {
+                if !self.is_uninteresting(node.flags) {
+                    stack.extend(node.parents.iter().rev().cloned());
+                }
+            }
+        }
+
+        result
+    }
+
+    fn topo_sort(
+        &self,
+        list: Vec<Hash>,
+        parents: &HashMap<Hash, Vec<Hash>>,
+        order: CommitOrder,
+    ) -> Vec<Hash> {
+        let mut children: HashMap<&Hash, usize> = list.iter().map(|hash| (hash, 0)).collect();
+        for hash in &list {
+            for parent in &parents[hash] {
+                if let Some(count) = children.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let tips: Vec<&Hash> = list.iter().filter(|hash| children[hash] == 0).collect();
+        let mut sorted = Vec::new();
+        if order == CommitOrder::Topo {
+            // a stack keeps following the line of history that was just returned.
+            // like git, the last parent of a merge comes first.
+            let mut stack: Vec<&Hash> = tips.into_iter().rev().collect();
+            while let Some(hash) = stack.pop() {
+                sorted.push(hash.clone());
+                for parent in &parents[hash] {
+                    if let Some(count) = children.get_mut(parent) {
+                        *count -= 1;
+                        if *count == 0 {
+                            stack.push(parent);
+                        }
+                    }
+                }
+            }
+        } else {
+            let positions: HashMap<&Hash, usize> =
+                list.iter().enumerate().map(|(i, hash)| (hash, i)).collect();
+            let item = |hash: &Hash| QueueItem {
+                time: self.nodes[hash].commit.as_ref().unwrap().committer.time,
+                seq: positions[hash],
+                hash: hash.clone(),
+            };
+
+            let mut queue: BinaryHeap<QueueItem> = tips.into_iter().map(item).collect();
+            while let Some(next) = queue.pop() {
+                for parent in &parents[&next.hash] {
+                    if let Some(count) = children.get_mut(parent) {
+                        *count -= 1;
+                        if *count == 0 {
+                            queue.push(item(parent));
+                        }
+                    }
+                }
+                sorted.push(next.hash);
+            }
+        }
+
+        sorted
+    }
+
+    /// lists the tags, trees and blobs that were pushed, followed by the trees and blobs reachable
+    /// from the given commits (usually the ones returned by the walk), leaving out everything
+    /// reachable from the hidden revisions. every object is listed once, with the path it was first found at.
+    pub fn objects(&self, commits: &[Hash]) -> Result<Vec<WalkedObject>> {
+        let mut seen = HashSet::new();
+
+        // trees of the uninteresting commits at the edge of the walk are excluded
+        let mut edges: Vec<Hash> = self.hidden.clone();
+        for hash in commits {
+            if let Some(node) = self.nodes.get(hash) {
+                edges.extend(
+                    node.parents
+                        .iter()
+                        .filter(|parent| {
+                            self.nodes
+                                .get(*parent)
+                                .is_some_and(|node| self.is_uninteresting(node.flags))
+                        })
+                        .cloned(),
+                );
+            }
+        }
+
+        for edge in edges {
+            let object = Object::read_from_hash(edge.to_hex())?;
+            let tree = decode_commit(object.data)?.tree;
+            mark_tree_seen(&tree, &mut seen)?;
+        }
+
+        for hash in &self.hidden_objects {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            match object.kind {
+                ObjectKind::Tree => mark_tree_seen(hash, &mut seen)?,
+                _ => {
+                    seen.insert(hash.clone());
+                }
+            }
+        }
+
+        // like git, pushed objects come before the trees of the commits
+        let mut objects = Vec::new();
+        for (hash, kind, name) in &self.pending {
+            match kind {
+                ObjectKind::Tree => {
+                    collect_tree_objects(hash, String::new(), &mut seen, &mut objects)?
+                }
+                kind => {
+                    if seen.insert(hash.clone()) {
+                        objects.push(WalkedObject {
+                            hash: hash.clone(),
+                            kind: *kind,
+                            path: name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        for hash in commits {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            let tree = decode_commit(object.data)?.tree;
+            collect_tree_objects(&tree, String::new(), &mut seen, &mut objects)?;
+        }
+
+        Ok(objects)
+    }
+}
+
+fn mark_tree_seen(tree: &Hash, seen: &mut HashSet<Hash>) -> Result<()> {
+    if !seen.insert(tree.clone()) {
+        return Ok(());
+    }
+
+    let object = Object::read_from_hash(tree.to_hex())?;
+    for entry in decode_tree(object.data)?.entries {
+        match entry.mode {
+            EntryMode::Directory => mark_tree_seen(&entry.hash, seen)?,
+            _ => {
+                seen.insert(entry.hash);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn collect_tree_objects(
+    tree: &Hash,
+    path: String,
+    seen: &mut HashSet<Hash>,
+    objects: &mut Vec<WalkedObject>,
+) -> Result<()> {
+    if !seen.insert(tree.clone()) {
+        return Ok(());
+    }
+
+    objects.push(WalkedObject {
+        hash: tree.clone(),
+        kind: ObjectKind::Tree,
+        path: path.clone(),
+    });
+
+    let object = Object::read_from_hash(tree.to_hex())?;
+    for entry in decode_tree(object.data)?.entries {
+        let entry_path = if path.is_empty() {
+            entry.name.clone()
+        } else {
+            format!("{}/{}", path, entry.name)
+        };
+
+        match entry.mode {
+            EntryMode::Directory => collect_tree_objects(&entry.hash, entry_path, seen, objects)?,
+            _ => {
+                if seen.insert(entry.hash.clone()) {
+                    objects.push(WalkedObject {
+                        hash: entry.hash,
+                        kind: ObjectKind::Blob,
+                        path: entry_path,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BinaryHeap;
+
+    use crate::{
+        objects::{hash::Hash, ObjectKind},
+        test_repo::TestRepo,
+    };
+
+    use super::{QueueItem, RevWalk, Side};
+
+    struct History {
+        base: Vec<Hash>,
+        left: Hash,
+        right: Vec<Hash>,
+    }
+
+    /// b1 - b2 - b3 - left
+    ///             \
+    ///              r1 - r2 - r3 (right, committed with a clock set far in the past)
+    fn history(repo: &TestRepo) -> History {
+        let mut base: Vec<Hash> = Vec::new();
+        for i in 1..=3 {
+            let tree = repo.tree(&[("file", &format!("b{}", i))]);
+            let parents: Vec<&Hash> = base.last().into_iter().collect();
+            base.push(repo.commit(&tree, &parents, 1000 * i, &format!("b{}", i)));
+        }
+
+        let tree = repo.tree(&[("file", "left")]);
+        let left = repo.commit(&tree, &[&base[2]], 100000, "left");
+
+        let mut right: Vec<Hash> = Vec::new();
+        for i in 1..=3 {
+            let tree = repo.tree(&[("file", "b3"), ("right", &format!("r{}", i))]);
+            let parent = right.last().unwrap_or(&base[2]);
+            right.push(repo.commit(&tree, &[parent], 10 * i, &format!("r{}", i)));
+        }
+
+        repo.set_ref("refs/heads/left", &left);
+        repo.set_ref("refs/heads/right", &right[2]);
+        History { base, left, right }
+    }
+
+    fn walk(walk: &mut RevWalk) -> Vec<(Hash, Option<Side>)> {
+        std::iter::from_fn(|| walk.next_commit().unwrap())
+            .map(|entry| (entry.hash, entry.side))
+            .collect()
+    }
+
+    fn newest_first(commits: &[Hash]) -> Vec<(Hash, Option<Side>)> {
+        commits
+            .iter()
+            .rev()
+            .map(|hash| (hash.clone(), None))
+            .collect()
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let hash =
+            |c: char| Hash::try_from((0..40).map(|_| c).collect::<String>().as_bytes()).unwrap();
+        let mut queue = BinaryHeap::new();
+        queue.push(QueueItem {
+            time: 10,
+            seq: 0,
+            hash: hash('a'),
+        });
+        queue.push(QueueItem {
+            time: 30,
+            seq: 1,
+            hash: hash('b'),
+        });
+        queue.push(QueueItem {
+            time: 10,
+            seq: 2,
+            hash: hash('c'),
+        });
+        queue.push(QueueItem {
+            time: 20,
+            seq: 3,
+            hash: hash('d'),
+        });
+
+        // newest first, ties are broken by insertion order
+        let order: Vec<Hash> = std::iter::from_fn(|| queue.pop().map(|item| item.hash)).collect();
+        assert_eq!(order, vec![hash('b'), hash('d'), hash('a'), hash('c')]);
+    }
+
+    #[test]
+    fn test_range() {
+        let repo = TestRepo::new();
+        let history = history(&repo);
+
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("left..right", false).unwrap();
+        assert_eq!(walk(&mut revwalk), newest_first(&history.right));
+
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("right", false).unwrap();
+        revwalk.push_arg("^left", false).unwrap();
+        assert_eq!(walk(&mut revwalk), newest_first(&history.right));
+    }
+
+    #[test]
+    fn test_not() {
+        let repo = TestRepo::new();
+        let history = history(&repo);
+
+        // like `rev-list left --not right`
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("left", false).unwrap();
+        revwalk.push_arg("right", true).unwrap();
+        assert_eq!(walk(&mut revwalk), vec![(history.left.clone(), None)]);
+
+        // --not also inverts a negated revision
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("^right", true).unwrap();
+        revwalk.push_arg("left", true).unwrap();
+        assert_eq!(walk(&mut revwalk), newest_first(&history.right));
+    }
+
+    #[test]
+    fn test_symmetric_range_with_clock_skew() {
+        let repo = TestRepo::new();
+        let history = history(&repo);
+
+        // the right side is older than the merge base, so the common commits are
+        // queued before they are known to be reachable from both sides
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("left...right", false).unwrap();
+        let mut expected = vec![(history.left.clone(), Some(Side::Left))];
+        expected.extend(
+            history
+                .right
+                .iter()
+                .rev()
+                .map(|hash| (hash.clone(), Some(Side::Right))),
+        );
+        assert_eq!(walk(&mut revwalk), expected);
+        assert!(expected
+            .iter()
+            .all(|(hash, _)| !history.base.contains(hash)));
+    }
+
+    #[test]
+    fn test_reverse() {
+        let repo = TestRepo::new();
+        let history = history(&repo);
+
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("right", false).unwrap();
+        revwalk.reverse(true);
+        let mut expected = history.base.clone();
+        expected.extend(history.right.iter().cloned());
+        let expected: Vec<(Hash, Option<Side>)> =
+            expected.into_iter().map(|hash| (hash, None)).collect();
+        assert_eq!(walk(&mut revwalk), expected);
+    }
+
+    #[test]
+    fn test_objects() {
+        let repo = TestRepo::new();
+        let history = history(&repo);
+        let tree = repo.tree(&[("tagged", "tagged content")]);
+        let tag = repo.tag(&tree, ObjectKind::Tree, "tree-tag");
+        repo.set_ref("refs/tags/tree-tag", &tag);
+        let blob = repo.blob("blob content");
+        repo.set_ref("refs/tags/blob", &blob);
+
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("left..right", false).unwrap();
+        revwalk.push_arg("tree-tag", false).unwrap();
+        revwalk.push_arg("blob", false).unwrap();
+        let commits: Vec<Hash> = walk(&mut revwalk)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        assert_eq!(
+            commits,
+            history.right.iter().rev().cloned().collect::<Vec<_>>()
+        );
+
+        let objects: Vec<(Hash, ObjectKind, String)> = revwalk
+            .objects(&commits)
+            .unwrap()
+            .into_iter()
+            .map(|object| (object.hash, object.kind, object.path))
+            .collect();
+
+        // pushed objects come first, then the objects of the commits that the hidden
+        // commit doesn't have. "file" is the same in all of them and in b3.
+        let mut expected = vec![
+            (tag, ObjectKind::Tag, String::from("tree-tag")),
+            (tree, ObjectKind::Tree, String::new()),
+            (
+                repo.blob("tagged content"),
+                ObjectKind::Blob,
+                String::from("tagged"),
+            ),
+            (blob.clone(), ObjectKind::Blob, String::new()),
+        ];
+        for i in (1..=3).rev() {
+            let content = format!("r{}", i);
+            expected.push((
+                repo.tree(&[("file", "b3"), ("right", &content)]),
+                ObjectKind::Tree,
+                String::new(),
+            ));
+            expected.push((repo.blob(&content), ObjectKind::Blob, String::from("right")));
+        }
+        assert_eq!(objects, expected);
+
+        // trees and blobs can be hidden too
+        let mut revwalk = RevWalk::new();
+        revwalk.push_arg("tree-tag", false).unwrap();
+        revwalk.push_arg("^blob", false).unwrap();
+        revwalk.push_arg("blob", false).unwrap();
+        assert!(walk(&mut revwalk).is_empty());
+        let objects = revwalk.objects(&[]).unwrap();
+        assert!(objects.iter().all(|object| object.hash != blob));
+        assert_eq!(objects.len(), 3);
+    }
+}
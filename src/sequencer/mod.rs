@@ -0,0 +1,200 @@
+mod replay;
+pub mod todo;
+
+use std::{fs, io, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+pub use self::replay::{cherry_pick, revert, ReplayAction, ReplayOptions};
+
+use crate::{
+    date::{format_date, DateFormat},
+    diff::write_tree_shortstat,
+    editor::{cleanup_message, editor, launch_editor},
+    ident::{identity, Role},
+    index::Index,
+    log::subject,
+    merge::{
+        checkout_merge, conflict_style,
+        tree::{merge_trees, TreeMergeOptions, TreeMergeResult},
+    },
+    objects::{
+        commit::{decode_commit, new_commit, Author, Commit},
+        hash::Hash,
+        Object,
+    },
+    refs::{head_branch, update_ref},
+    rev_parse::abbreviate,
+};
+
+const COMMIT_EDITMSG: &str = ".git/COMMIT_EDITMSG";
+
+const COMMIT_MESSAGE_HELP: &str = "
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+#
+";
+
+pub fn read_commit(hash: &Hash) -> Result<Commit> {
+    decode_commit(Object::read_from_hash(hash.to_hex())?.data)
+}
+
+/// names a commit in conflict markers and messages: "<abbrev> (<subject>)"
+pub fn describe_commit(hash: &Hash, commit: &Commit) -> Result<String> {
+    Ok(format!(
+        "{} ({})",
+        abbreviate(hash)?,
+        subject(&commit.message)
+    ))
+}
+
+/// the options of the merge applying a commit onto HEAD, with the commit and its parent
+/// named in conflict markers
+pub fn pick_options(hash: &Hash, commit: &Commit) -> Result<TreeMergeOptions> {
+    let description = describe_commit(hash, commit)?;
+    Ok(TreeMergeOptions {
+        ancestor_label: format!("parent of {}", description),
+        theirs_label: description,
+        style: conflict_style()?,
+        ..Default::default()
+    })
+}
+
+/// applies the changes from the base tree to their tree onto the tree of HEAD, updating
+/// the index and the working tree, and prints what happened to the merged paths. conflicts
+/// are left in the index and the working tree.
+pub fn apply_changes(
+    index: &Index,
+    base: Option<&Hash>,
+    head: &Hash,
+    theirs: &Hash,
+    options: &TreeMergeOptions,
+    operation: &str,
+) -> Result<TreeMergeResult> {
+    let result = merge_trees(base, head, theirs, options)?;
+    checkout_merge(index, &result, operation)?;
+    for message in &result.messages {
+        println!("{}", message);
+    }
+    Ok(result)
+}
+
+/// lets the user edit a commit message, and cleans it up
+pub fn edit_message(message: &str) -> Result<String> {
+    fs::write(
+        COMMIT_EDITMSG,
+        format!("{}\n{}", message.trim_end(), COMMIT_MESSAGE_HELP),
+    )?;
+    launch_editor(&editor()?, Path::new(COMMIT_EDITMSG))?;
+    let message = cleanup_message(&fs::read_to_string(COMMIT_EDITMSG)?, true);
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+    Ok(message)
+}
+
+/// writes a commit and moves HEAD to it. the committer is the current identity.
+pub fn write_commit(
+    tree: Hash,
+    parents: Vec<Hash>,
+    author: Author,
+    message: &str,
+    reflog_message: &str,
+) -> Result<Hash> {
+    let message = message.trim_end_matches('\n').to_string();
+    let commit = new_commit(
+        tree,
+        parents,
+        author,
+        Some(identity(Role::Committer)?),
+        None,
+        Some(message),
+    )?;
+    let hash = commit.write()?;
+    update_ref("HEAD", &hash, reflog_message)?;
+    Ok(hash)
+}
+
+/// prints the summary of a new commit: "[<branch> <abbrev>] <subject>", its author if not
+/// the committer, the date of its author if asked, as for the commits reusing an older
+/// author, followed by the totals of its changes and the created and deleted files
+pub fn write_commit_summary(hash: &Hash, show_author_date: bool) -> Result<()> {
+    let commit = read_commit(hash)?;
+    let mut branch = match head_branch()? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => String::from("detached HEAD"),
+    };
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(read_commit(parent)?.tree),
+        None => {
+            branch.push_str(" (root-commit)");
+            None
+        }
+    };
+
+    println!(
+        "[{} {}] {}",
+        branch,
+        abbreviate(hash)?,
+        subject(&commit.message)
+    );
+    let author = &commit.author;
+    if (&author.name, &author.email) != (&commit.committer.name, &commit.committer.email) {
+        println!(" Author: {} <{}>", author.name, author.email);
+    }
+    if show_author_date {
+        let date = format_date(author.time, &author.time_zone, &DateFormat::default())?;
+        println!(" Date: {}", date);
+    }
+    // the changes of merges aren't summed up
+    match commit.parents.len() > 1 {
+        true => Ok(()),
+        false => write_tree_shortstat(&mut io::stdout().lock(), parent_tree.as_ref(), &commit.tree),
+    }
+}
+
+/// saves the author of a commit the way git does, as shell variable assignments
+pub fn write_author_script(path: &Path, author: &Author) -> Result<()> {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+    let script = format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+        quote(&author.name),
+        quote(&author.email),
+        quote(&format!("@{} {}", author.time, author.time_zone))
+    );
+    fs::write(path, script)?;
+    Ok(())
+}
+
+/// reads the author saved by write_author_script
+pub fn read_author_script(path: &Path) -> Result<Author> {
+    let script = fs::read_to_string(path)?;
+    let mut values = Vec::new();
+    for line in script.lines() {
+        let (name, value) = line
+            .split_once('=')
+            .ok_or(anyhow!("invalid author script: {}", path.display()))?;
+        let value = value.replace("'\\''", "'").trim_matches('\'').to_string();
+        values.push((name.to_string(), value));
+    }
+
+    let value = |name: &str| {
+        values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .ok_or(anyhow!("{} missing from {}", name, path.display()))
+    };
+    let date = value("GIT_AUTHOR_DATE")?;
+    let (time, time_zone) = date
+        .trim_start_matches('@')
+        .split_once(' ')
+        .ok_or(anyhow!("invalid author date: {}", date))?;
+
+    Ok(Author {
+        name: value("GIT_AUTHOR_NAME")?,
+        email: value("GIT_AUTHOR_EMAIL")?,
+        time: time.parse()?,
+        time_zone: time_zone.to_string(),
+    })
+}
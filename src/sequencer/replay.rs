@@ -0,0 +1,672 @@
+use std::{
+    fs,
+    io::{stdin, IsTerminal},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::{parse_config, Config},
+    ident::{identity, Role},
+    index::{read_index, write_index, Index},
+    log::subject,
+    objects::{commit::Commit, hash::Hash},
+    refs::head_branch,
+    reset::move_head,
+    rev_parse::{abbreviate, resolve_commit},
+    revwalk::RevWalk,
+    worktree::{reset_hard, staged_paths},
+};
+
+use super::{
+    apply_changes, describe_commit, edit_message, pick_options, read_commit, write_commit,
+    write_commit_summary,
+};
+
+/// the state of a cherry-pick or revert of several commits, as kept by git
+const SEQUENCER_DIR: &str = ".git/sequencer";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+
+/// what to do with a cherry-pick or revert in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayAction {
+    /// commit the resolved conflicts, and go on with the commits left
+    Continue,
+    /// go on without the commit that stopped
+    Skip,
+    /// go back to the commit HEAD was at before starting
+    Abort,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    /// the commits to apply, or ranges of commits
+    pub commits: Vec<String>,
+    /// the parent of merges their changes are taken against, starting from 1
+    pub mainline: Option<usize>,
+    /// append "(cherry picked from commit <hash>)" to the messages
+    pub record_origin: bool,
+    /// only update the index and the working tree
+    pub no_commit: bool,
+    /// edit the messages. defaults to editing reverts made from a terminal
+    pub edit: Option<bool>,
+    pub action: Option<ReplayAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    CherryPick,
+    Revert,
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::CherryPick => "cherry-pick",
+            Operation::Revert => "revert",
+        }
+    }
+
+    /// the command of the operation in the todo list of the sequencer
+    fn command(&self) -> &'static str {
+        match self {
+            Operation::CherryPick => "pick",
+            Operation::Revert => "revert",
+        }
+    }
+
+    /// the file naming the commit that stopped the operation
+    fn head_file(&self) -> &'static str {
+        match self {
+            Operation::CherryPick => ".git/CHERRY_PICK_HEAD",
+            Operation::Revert => ".git/REVERT_HEAD",
+        }
+    }
+}
+
+/// applies the changes of commits onto HEAD, committing them with their original author.
+/// returns false if it stopped on conflicts.
+pub fn cherry_pick(options: ReplayOptions) -> Result<bool> {
+    replay(Operation::CherryPick, options)
+}
+
+/// commits the reverse of the changes of commits. returns false if it stopped on conflicts.
+pub fn revert(options: ReplayOptions) -> Result<bool> {
+    replay(Operation::Revert, options)
+}
+
+fn replay(operation: Operation, options: ReplayOptions) -> Result<bool> {
+    match options.action {
+        Some(ReplayAction::Continue) => continue_replay(operation),
+        Some(ReplayAction::Skip) => skip(),
+        Some(ReplayAction::Abort) => abort(),
+        None => start(operation, &options),
+    }
+}
+
+fn start(operation: Operation, options: &ReplayOptions) -> Result<bool> {
+    if Path::new(SEQUENCER_DIR).exists() || in_progress().is_some() {
+        bail!(
+            "a cherry-pick or revert is already in progress\n\
+            hint: try \"mgit {} (--continue | --skip | --abort)\"",
+            operation.name()
+        );
+    }
+
+    let index = read_index()?;
+    if index.has_conflicts() {
+        bail!(unmerged_error(operation));
+    }
+    if !options.no_commit {
+        let head_tree = read_commit(&resolve_commit("HEAD")?)?.tree;
+        if !staged_paths(&index, &Index::from_tree(&head_tree)?).is_empty() {
+            bail!(
+                "your local changes would be overwritten by {}.\n\
+                hint: commit your changes or stash them to proceed.",
+                operation.name()
+            );
+        }
+    }
+
+    let commits = list_commits(operation, &options.commits)?;
+    if commits.is_empty() {
+        bail!("empty commit set passed");
+    }
+
+    // a single commit is picked without keeping a todo list
+    let single = matches!(options.commits.as_slice(), [arg] if !is_range(arg));
+    if !single {
+        let head = resolve_commit("HEAD")?;
+        fs::create_dir_all(SEQUENCER_DIR)?;
+        fs::write(sequencer_path("head"), format!("{:x}\n", head))?;
+        fs::write(sequencer_path("abort-safety"), format!("{:x}\n", head))?;
+        write_options(options)?;
+    }
+    pick_commits(operation, options, &commits, !single)
+}
+
+/// the commits given one by one, in their order, or the commits of the ranges given: oldest
+/// first for cherry-picks and newest first for reverts
+fn list_commits(operation: Operation, args: &[String]) -> Result<Vec<Hash>> {
+    if !args.iter().any(|arg| is_range(arg)) {
+        return args.iter().map(|arg| resolve_commit(arg)).collect();
+    }
+
+    let mut walk = RevWalk::new();
+    walk.reverse(operation == Operation::CherryPick);
+    for arg in args {
+        walk.push_arg(arg, false)?;
+    }
+    let mut commits = Vec::new();
+    while let Some(entry) = walk.next_commit()? {
+        commits.push(entry.hash);
+    }
+    Ok(commits)
+}
+
+fn is_range(arg: &str) -> bool {
+    arg.contains("..") || arg.starts_with('^')
+}
+
+/// applies the commits one after the other. the commits left are kept in the todo list of
+/// the sequencer, so that the operation can go on once a conflict is resolved.
+fn pick_commits(
+    operation: Operation,
+    options: &ReplayOptions,
+    commits: &[Hash],
+    sequencer: bool,
+) -> Result<bool> {
+    for (i, hash) in commits.iter().enumerate() {
+        if sequencer {
+            write_todo(operation, &commits[i..])?;
+        }
+        if !pick_commit(operation, options, hash)? {
+            return Ok(false);
+        }
+        if sequencer {
+            let head = resolve_commit("HEAD")?;
+            fs::write(sequencer_path("abort-safety"), format!("{:x}\n", head))?;
+        }
+    }
+
+    if sequencer {
+        fs::remove_dir_all(SEQUENCER_DIR)?;
+    }
+    Ok(true)
+}
+
+/// applies the changes of a commit, or their reverse, and commits them unless asked not to.
+/// returns false if it stopped on conflicts, or because there was nothing to commit.
+fn pick_commit(operation: Operation, options: &ReplayOptions, hash: &Hash) -> Result<bool> {
+    let commit = read_commit(hash)?;
+    let parent = match (commit.parents.as_slice(), options.mainline) {
+        ([], None) => None,
+        ([parent], None) => Some(parent.clone()),
+        (_, None) => bail!("commit {:x} is a merge but no -m option was given.", hash),
+        (parents, Some(mainline)) if !parents.is_empty() => Some(
+            parents
+                .get(mainline.wrapping_sub(1))
+                .cloned()
+                .ok_or(anyhow!(
+                    "commit {:x} does not have parent {}",
+                    hash,
+                    mainline
+                ))?,
+        ),
+        (_, Some(_)) => bail!(
+            "mainline was specified but commit {:x} is not a merge.",
+            hash
+        ),
+    };
+    let parent_tree = match &parent {
+        Some(parent) => read_commit(parent)?.tree,
+        None => Index::default().write_tree()?,
+    };
+
+    let mut merge_options = pick_options(hash, &commit)?;
+    let (base, theirs, message, author) = match operation {
+        Operation::CherryPick => (
+            parent.as_ref().map(|_| parent_tree),
+            commit.tree.clone(),
+            cherry_pick_message(hash, &commit, options.record_origin),
+            commit.author.clone(),
+        ),
+        Operation::Revert => {
+            let description = describe_commit(hash, &commit)?;
+            merge_options.theirs_label = format!("parent of {}", description);
+            merge_options.ancestor_label = description;
+            let merge = commit.parents.len() > 1;
+            let mainline = parent.as_ref().filter(|_| merge);
+            (
+                Some(commit.tree.clone()),
+                parent_tree,
+                revert_message(hash, &commit, mainline),
+                identity(Role::Author)?,
+            )
+        }
+    };
+
+    let index = read_index()?;
+    let head = resolve_commit("HEAD")?;
+    let head_tree = read_commit(&head)?.tree;
+    let ours = match options.no_commit {
+        true => index.write_tree()?,
+        false => head_tree.clone(),
+    };
+    let result = apply_changes(
+        &index,
+        base.as_ref(),
+        &ours,
+        &theirs,
+        &merge_options,
+        "merge",
+    )?;
+
+    if !result.is_clean() {
+        if !options.no_commit {
+            fs::write(operation.head_file(), format!("{:x}\n", hash))?;
+        }
+        let mut merge_msg = format!("{}\n\n# Conflicts:\n", message);
+        for path in result.conflicted_paths() {
+            merge_msg.push_str(&format!("#\t{}\n", path));
+        }
+        fs::write(MERGE_MSG, merge_msg)?;
+
+        let verb = match operation {
+            Operation::CherryPick => "apply",
+            Operation::Revert => "revert",
+        };
+        let oneline = subject(&commit.message);
+        eprintln!(
+            "error: could not {} {}... {}",
+            verb,
+            abbreviate(hash)?,
+            oneline
+        );
+        print_conflict_hints(operation, options.no_commit);
+        return Ok(false);
+    }
+    if options.no_commit {
+        fs::write(MERGE_MSG, format!("{}\n", message))?;
+        return Ok(true);
+    }
+
+    let tree = result.index().write_tree()?;
+    if tree == head_tree {
+        // an empty cherry-pick is left for the user to skip, while a revert has nothing to do
+        fs::write(MERGE_MSG, format!("{}\n", message))?;
+        if operation == Operation::CherryPick {
+            fs::write(operation.head_file(), format!("{:x}\n", hash))?;
+            print_empty(operation);
+        } else {
+            match head_branch()? {
+                Some(branch) => println!("On branch {}", branch.trim_start_matches("refs/heads/")),
+                None => println!("HEAD detached at {}", abbreviate(&head)?),
+            }
+            println!("nothing to commit, working tree clean");
+        }
+        return Ok(false);
+    }
+
+    let edit = options
+        .edit
+        .unwrap_or(operation == Operation::Revert && stdin().is_terminal());
+    let message = match edit {
+        true => edit_message(&message)?,
+        false => message,
+    };
+    let reflog = format!("{}: {}", operation.name(), subject(&message));
+    let new = write_commit(tree, vec![head], author, &message, &reflog)?;
+    // only a cherry-pick reuses an older author date
+    write_commit_summary(&new, operation == Operation::CherryPick)?;
+    Ok(true)
+}
+
+/// the message of a cherry-picked commit, with the commit it comes from if asked
+fn cherry_pick_message(hash: &Hash, commit: &Commit, record_origin: bool) -> String {
+    let mut message = commit.message.trim_end().to_string();
+    if record_origin {
+        // the origin joins the trailers of the message, if it ends with some
+        let trailers = match message.rsplit_once("\n\n") {
+            Some((_, last)) => last.lines().all(is_trailer),
+            None => false,
+        };
+        message.push_str(if trailers { "\n" } else { "\n\n" });
+        message.push_str(&format!("(cherry picked from commit {:x})", hash));
+    }
+    message
+}
+
+/// returns true for lines like "Signed-off-by: A <a@x>"
+fn is_trailer(line: &str) -> bool {
+    if line.starts_with("(cherry picked from commit ") {
+        return true;
+    }
+    match line.split_once(": ") {
+        Some((key, _)) => {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }
+        None => false,
+    }
+}
+
+/// the message of a revert, naming the parent whose side is kept for merges
+fn revert_message(hash: &Hash, commit: &Commit, mainline: Option<&Hash>) -> String {
+    let mut message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {:x}",
+        subject(&commit.message),
+        hash
+    );
+    if let Some(parent) = mainline {
+        message.push_str(&format!(", reversing\nchanges made to {:x}", parent));
+    }
+    message.push('.');
+    message
+}
+
+fn print_conflict_hints(operation: Operation, no_commit: bool) {
+    if no_commit {
+        eprintln!(
+            "hint: after resolving the conflicts, mark the corrected paths\n\
+            hint: with 'git add <paths>' or 'git rm <paths>'"
+        );
+        return;
+    }
+    let name = operation.name();
+    eprintln!(
+        "hint: After resolving the conflicts, mark them with\n\
+        hint: \"git add/rm <pathspec>\", then run\n\
+        hint: \"mgit {name} --continue\".\n\
+        hint: You can instead skip this commit with \"mgit {name} --skip\".\n\
+        hint: To abort and get back to the state before \"mgit {name}\",\n\
+        hint: run \"mgit {name} --abort\"."
+    );
+}
+
+fn print_empty(operation: Operation) {
+    eprintln!(
+        "The previous {name} is now empty, possibly due to conflict resolution.\n\
+        Please use 'mgit {name} --skip' to skip it.",
+        name = operation.name()
+    );
+}
+
+fn unmerged_error(operation: Operation) -> String {
+    let doing = match operation {
+        Operation::CherryPick => "Cherry-picking",
+        Operation::Revert => "Reverting",
+    };
+    format!(
+        "{} is not possible because you have unmerged files.\n\
+        hint: Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+        hint: as appropriate to mark resolution and make a commit.",
+        doing
+    )
+}
+
+/// the operation that stopped on a commit, if any
+fn in_progress() -> Option<Operation> {
+    [Operation::CherryPick, Operation::Revert]
+        .into_iter()
+        .find(|operation| Path::new(operation.head_file()).is_file())
+}
+
+fn continue_replay(operation: Operation) -> Result<bool> {
+    let sequencer = Path::new(SEQUENCER_DIR).is_dir();
+    let stopped = in_progress();
+    if stopped.is_none() && !sequencer {
+        bail!("no cherry-pick or revert in progress");
+    }
+    let index = read_index()?;
+    if index.has_conflicts() {
+        bail!(unmerged_error(operation));
+    }
+
+    if let Some(stopped) = stopped {
+        if !commit_resolution(stopped, &index)? {
+            return Ok(false);
+        }
+    }
+    match sequencer {
+        true => pick_remaining(),
+        false => Ok(true),
+    }
+}
+
+/// commits the resolved conflicts of the commit that stopped the operation
+fn commit_resolution(operation: Operation, index: &Index) -> Result<bool> {
+    let hash = read_hash(operation.head_file())?;
+    let commit = read_commit(&hash)?;
+    let head = resolve_commit("HEAD")?;
+    let tree = index.write_tree()?;
+    if tree == read_commit(&head)?.tree {
+        print_empty(operation);
+        return Ok(false);
+    }
+
+    let message = match Path::new(MERGE_MSG).is_file() {
+        true => fs::read_to_string(MERGE_MSG)?,
+        false => commit.message.clone(),
+    };
+    let message = edit_message(&message)?;
+    // the author of a cherry-picked commit is kept, with its date
+    let (author, reflog) = match operation {
+        Operation::CherryPick => (
+            commit.author,
+            format!("commit (cherry-pick): {}", subject(&message)),
+        ),
+        Operation::Revert => (
+            identity(Role::Author)?,
+            format!("commit: {}", subject(&message)),
+        ),
+    };
+    let new = write_commit(tree, vec![head], author, &message, &reflog)?;
+    write_commit_summary(&new, operation == Operation::CherryPick)?;
+
+    clear_stop_state()?;
+    Ok(true)
+}
+
+fn skip() -> Result<bool> {
+    let sequencer = Path::new(SEQUENCER_DIR).is_dir();
+    if in_progress().is_none() && !sequencer {
+        bail!("no cherry-pick or revert in progress");
+    }
+    reset_merge(&resolve_commit("HEAD")?)?;
+    clear_stop_state()?;
+
+    match sequencer {
+        true => pick_remaining(),
+        false => Ok(true),
+    }
+}
+
+/// goes on with the todo list of the sequencer, without the commit that stopped it
+fn pick_remaining() -> Result<bool> {
+    let (operation, mut commits) = read_todo()?;
+    if !commits.is_empty() {
+        commits.remove(0);
+    }
+    let head = resolve_commit("HEAD")?;
+    fs::write(sequencer_path("abort-safety"), format!("{:x}\n", head))?;
+    pick_commits(operation, &read_options()?, &commits, true)
+}
+
+fn abort() -> Result<bool> {
+    let head = resolve_commit("HEAD")?;
+    if Path::new(SEQUENCER_DIR).is_dir() {
+        // HEAD is left alone if it was moved since the operation stopped
+        match head == read_hash(&sequencer_path("abort-safety"))? {
+            true => reset_merge(&read_hash(&sequencer_path("head"))?)?,
+            false => {
+                eprintln!("warning: You seem to have moved HEAD. Not rewinding, check your HEAD!")
+            }
+        }
+        fs::remove_dir_all(SEQUENCER_DIR)?;
+    } else if in_progress().is_some() {
+        reset_merge(&head)?;
+    } else {
+        bail!("no cherry-pick or revert in progress");
+    }
+
+    clear_stop_state()?;
+    Ok(true)
+}
+
+/// moves HEAD, the index and the working tree to a commit, the way git reset --merge does
+fn reset_merge(commit: &Hash) -> Result<()> {
+    let index = read_index()?;
+    write_index(&reset_hard(&index, &read_commit(commit)?.tree)?)?;
+    move_head(commit, &format!("reset: moving to {:x}", commit))
+}
+
+fn clear_stop_state() -> Result<()> {
+    let files = [
+        Operation::CherryPick.head_file(),
+        Operation::Revert.head_file(),
+        MERGE_MSG,
+    ];
+    for file in files {
+        if Path::new(file).is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn sequencer_path(name: &str) -> String {
+    format!("{}/{}", SEQUENCER_DIR, name)
+}
+
+fn read_hash(path: &str) -> Result<Hash> {
+    Hash::try_from(fs::read_to_string(path)?.trim().as_bytes())
+}
+
+/// writes the commits left, e.g. "pick 1a2b3c4 subject"
+fn write_todo(operation: Operation, commits: &[Hash]) -> Result<()> {
+    let mut todo = String::new();
+    for hash in commits {
+        let commit = read_commit(hash)?;
+        todo.push_str(&format!(
+            "{} {} {}\n",
+            operation.command(),
+            abbreviate(hash)?,
+            subject(&commit.message)
+        ));
+    }
+    fs::write(sequencer_path("todo"), todo)?;
+    Ok(())
+}
+
+fn read_todo() -> Result<(Operation, Vec<Hash>)> {
+    let todo = fs::read_to_string(sequencer_path("todo"))?;
+    let mut operation = Operation::CherryPick;
+    let mut commits = Vec::new();
+    for line in todo.lines().filter(|line| !line.trim().is_empty()) {
+        let mut words = line.split_whitespace();
+        operation = match words.next() {
+            Some("pick") | Some("p") => Operation::CherryPick,
+            Some("revert") => Operation::Revert,
+            _ => bail!("invalid line in {}: {}", sequencer_path("todo"), line),
+        };
+        let hash = words.next().ok_or(anyhow!(
+            "missing commit in {}: {}",
+            sequencer_path("todo"),
+            line
+        ))?;
+        commits.push(resolve_commit(hash)?);
+    }
+    Ok((operation, commits))
+}
+
+/// saves the options in the configuration format git uses, e.g. record-origin = true
+fn write_options(options: &ReplayOptions) -> Result<()> {
+    let mut opts = String::from("[options]\n");
+    if options.no_commit {
+        opts.push_str("\tno-commit = true\n");
+    }
+    if let Some(edit) = options.edit {
+        opts.push_str(&format!("\tedit = {}\n", edit));
+    }
+    if options.record_origin {
+        opts.push_str("\trecord-origin = true\n");
+    }
+    if let Some(mainline) = options.mainline {
+        opts.push_str(&format!("\tmainline = {}\n", mainline));
+    }
+    fs::write(sequencer_path("opts"), opts)?;
+    Ok(())
+}
+
+fn read_options() -> Result<ReplayOptions> {
+    let path = sequencer_path("opts");
+    let config = match Path::new(&path).is_file() {
+        true => Config {
+            entries: parse_config(&fs::read_to_string(&path)?)?,
+        },
+        false => Config::default(),
+    };
+
+    Ok(ReplayOptions {
+        mainline: config
+            .get("options.mainline")
+            .map(|mainline| mainline.parse())
+            .transpose()?,
+        record_origin: config.get_bool("options.record-origin")?.unwrap_or(false),
+        no_commit: config.get_bool("options.no-commit")?.unwrap_or(false),
+        edit: config.get_bool("options.edit")?,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cherry_pick_message, revert_message};
+    use crate::objects::{
+        commit::{Author, Commit},
+        hash::Hash,
+    };
+
+    fn commit(message: &str) -> Commit {
+        let author = Author {
+            name: String::from("A"),
+            email: String::from("a@x"),
+            time: 1700000000,
+            time_zone: String::from("+0000"),
+        };
+        Commit {
+            tree: Hash(vec![0; 20]),
+            parents: Vec::new(),
+            author: author.clone(),
+            committer: author,
+            additional_data: None,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let hash = Hash(vec![0xab; 20]);
+        let origin = format!("(cherry picked from commit {:x})", hash);
+
+        let message = cherry_pick_message(&hash, &commit("subject\n"), true);
+        assert_eq!(message, format!("subject\n\n{}", origin));
+        let signed = commit("subject\n\nbody\n\nSigned-off-by: A <a@x>");
+        let message = cherry_pick_message(&hash, &signed, true);
+        assert_eq!(
+            message,
+            format!("subject\n\nbody\n\nSigned-off-by: A <a@x>\n{}", origin)
+        );
+        assert_eq!(cherry_pick_message(&hash, &signed, false), signed.message);
+
+        let parent = Hash(vec![0xcd; 20]);
+        assert_eq!(
+            revert_message(&hash, &commit("subject\n\nbody"), Some(&parent)),
+            format!(
+                "Revert \"subject\"\n\nThis reverts commit {:x}, reversing\nchanges made to {:x}.",
+                hash, parent
+            )
+        );
+    }
+}
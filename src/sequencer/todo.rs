@@ -0,0 +1,251 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    objects::hash::Hash,
+    rev_parse::{abbreviate, resolve_commit},
+};
+
+/// a command of a todo list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoCommand {
+    /// use the commit
+    Pick,
+    /// use the commit, but edit its message
+    Reword,
+    /// use the commit, but stop for amending
+    Edit,
+    /// meld the commit into the previous one, combining their messages
+    Squash,
+    /// meld the commit into the previous one, keeping the previous message
+    Fixup,
+    /// run a shell command
+    Exec,
+    /// stop, to be continued later
+    Break,
+    /// remove the commit
+    Drop,
+    /// name the current HEAD
+    Label,
+    /// move HEAD to a label
+    Reset,
+    /// merge labels into HEAD
+    Merge,
+    /// do nothing
+    Noop,
+}
+
+impl TryFrom<&str> for TodoCommand {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        let command = match value {
+            "pick" | "p" => TodoCommand::Pick,
+            "reword" | "r" => TodoCommand::Reword,
+            "edit" | "e" => TodoCommand::Edit,
+            "squash" | "s" => TodoCommand::Squash,
+            "fixup" | "f" => TodoCommand::Fixup,
+            "exec" | "x" => TodoCommand::Exec,
+            "break" | "b" => TodoCommand::Break,
+            "drop" | "d" => TodoCommand::Drop,
+            "label" | "l" => TodoCommand::Label,
+            "reset" | "t" => TodoCommand::Reset,
+            "merge" | "m" => TodoCommand::Merge,
+            "noop" => TodoCommand::Noop,
+            _ => bail!("invalid command: {}", value),
+        };
+        Ok(command)
+    }
+}
+
+impl TodoCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TodoCommand::Pick => "pick",
+            TodoCommand::Reword => "reword",
+            TodoCommand::Edit => "edit",
+            TodoCommand::Squash => "squash",
+            TodoCommand::Fixup => "fixup",
+            TodoCommand::Exec => "exec",
+            TodoCommand::Break => "break",
+            TodoCommand::Drop => "drop",
+            TodoCommand::Label => "label",
+            TodoCommand::Reset => "reset",
+            TodoCommand::Merge => "merge",
+            TodoCommand::Noop => "noop",
+        }
+    }
+
+    /// returns true for the commands applying a commit
+    pub fn picks(&self) -> bool {
+        matches!(
+            self,
+            TodoCommand::Pick
+                | TodoCommand::Reword
+                | TodoCommand::Edit
+                | TodoCommand::Squash
+                | TodoCommand::Fixup
+                | TodoCommand::Drop
+        )
+    }
+
+    /// returns true for the commands melding a commit into the previous one
+    pub fn is_fixup(&self) -> bool {
+        matches!(self, TodoCommand::Squash | TodoCommand::Fixup)
+    }
+}
+
+/// a line of a todo list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoItem {
+    pub command: TodoCommand,
+    /// the commit to pick, or the commit whose message is used by fixup -C/-c and merge -C/-c
+    pub commit: Option<Hash>,
+    /// the -C or -c flag of fixup and merge: use the message of the commit, editing it with -c
+    pub flag: Option<char>,
+    /// the rest of the line: the subject of the commit, the shell command, the label, or
+    /// the labels to merge followed by "# <oneline>"
+    pub arg: String,
+}
+
+impl TodoItem {
+    pub fn new(command: TodoCommand, commit: Option<Hash>, arg: &str) -> TodoItem {
+        TodoItem {
+            command,
+            commit,
+            flag: None,
+            arg: arg.to_string(),
+        }
+    }
+
+    /// formats the item as a line of a todo list, with full or abbreviated hashes
+    pub fn format(&self, abbreviated: bool) -> Result<String> {
+        let mut line = String::from(self.command.name());
+        if let Some(flag) = self.flag {
+            line.push_str(&format!(" -{}", flag));
+        }
+        if let Some(commit) = &self.commit {
+            let hash = match abbreviated {
+                true => abbreviate(commit)?,
+                false => commit.to_hex(),
+            };
+            line.push(' ');
+            line.push_str(&hash);
+        }
+        if !self.arg.is_empty() {
+            line.push(' ');
+            line.push_str(&self.arg);
+        }
+        Ok(line)
+    }
+}
+
+/// parses a todo list, skipping empty lines and comments
+pub fn parse_todo(content: &str) -> Result<Vec<TodoItem>> {
+    let mut items = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let item = parse_todo_line(line)
+            .map_err(|err| anyhow!("invalid line {}: {}\n{}", number + 1, line, err))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// parses a line of a todo list that isn't empty or a comment
+pub fn parse_todo_line(line: &str) -> Result<TodoItem> {
+    let (command, rest) = split_word(line);
+    let command = TodoCommand::try_from(command)?;
+
+    let mut item = TodoItem::new(command, None, "");
+    match command {
+        TodoCommand::Break | TodoCommand::Noop => {
+            if !rest.is_empty() {
+                bail!("{} does not accept arguments", command.name());
+            }
+        }
+        TodoCommand::Exec | TodoCommand::Label | TodoCommand::Reset => {
+            if rest.is_empty() {
+                bail!("missing arguments for {}", command.name());
+            }
+            item.arg = rest.to_string();
+        }
+        TodoCommand::Merge => {
+            let mut rest = rest;
+            if let Some(flag) = parse_flag(rest) {
+                let (commit, after) = split_word(&rest[2..]);
+                item.flag = Some(flag);
+                item.commit = Some(resolve_commit(commit)?);
+                rest = after;
+            }
+            if rest.is_empty() || rest.starts_with('#') {
+                bail!("missing arguments for merge");
+            }
+            item.arg = rest.to_string();
+        }
+        _ => {
+            let mut rest = rest;
+            if command == TodoCommand::Fixup {
+                if let Some(flag) = parse_flag(rest) {
+                    item.flag = Some(flag);
+                    rest = rest[2..].trim_start();
+                }
+            }
+            let (commit, subject) = split_word(rest);
+            if commit.is_empty() {
+                bail!("missing commit for {}", command.name());
+            }
+            item.commit = Some(resolve_commit(commit)?);
+            item.arg = subject.to_string();
+        }
+    }
+
+    Ok(item)
+}
+
+/// returns the flag of "-C <commit>" or "-c <commit>"
+fn parse_flag(rest: &str) -> Option<char> {
+    match rest.split_whitespace().next() {
+        Some("-C") => Some('C'),
+        Some("-c") => Some('c'),
+        _ => None,
+    }
+}
+
+fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_todo, TodoCommand};
+
+    #[test]
+    fn test_parse_todo() {
+        let todo = "# comment\n\nx make test\nbreak\nlabel onto\nt onto\n  exec echo  hi  \n";
+        let items = parse_todo(todo).unwrap();
+        let commands: Vec<TodoCommand> = items.iter().map(|item| item.command).collect();
+        assert_eq!(
+            commands,
+            [
+                TodoCommand::Exec,
+                TodoCommand::Break,
+                TodoCommand::Label,
+                TodoCommand::Reset,
+                TodoCommand::Exec
+            ]
+        );
+        assert_eq!(items[0].arg, "make test");
+        assert_eq!(items[4].arg, "echo  hi");
+
+        assert!(parse_todo("break now").is_err());
+        assert!(parse_todo("label").is_err());
+        assert!(parse_todo("frobnicate abc").is_err());
+    }
+}
@@ -0,0 +1,592 @@
+use std::{
+    fs,
+    io::{self, IsTerminal},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    diff::{diff, matches_paths, DiffOptions, PatchOptions, DEFAULT_RENAME_SCORE},
+    ident::{identity, Role},
+    index::{index_mtime, read_index, write_index, Index, IndexEntry},
+    log::subject,
+    merge::{
+        checkout_merge, conflict_style,
+        tree::{merge_trees, TreeMergeOptions},
+    },
+    objects::{commit::new_commit, hash::Hash, tree::EntryMode, ObjectKind},
+    refs::{
+        delete_ref, head_branch,
+        reflog::{read_reflog, write_reflog},
+        resolve_ref, update_ref, write_ref, write_symbolic_ref,
+    },
+    reset::move_head,
+    rev_parse::{abbreviate, peel, resolve_revision},
+    sequencer::read_commit,
+    worktree::{
+        checkout_file, is_clean, remove_file, staged_paths, switch_trees, unstaged_paths,
+        untracked_paths, write_status, write_worktree_file,
+    },
+};
+
+const STASH_REF: &str = "refs/stash";
+
+#[derive(Debug, Clone)]
+pub enum StashAction {
+    /// saves the local changes and reverts them
+    Push(StashPushOptions),
+    /// applies the changes of a stash, restoring its index changes too with index
+    Apply {
+        stash: Option<String>,
+        index: bool,
+    },
+    /// applies a stash and drops it if it applied cleanly
+    Pop {
+        stash: Option<String>,
+        index: bool,
+    },
+    List,
+    /// shows the changes of a stash as a diffstat, or as a patch
+    Show {
+        stash: Option<String>,
+        patch: bool,
+        stat: bool,
+    },
+    Drop {
+        stash: Option<String>,
+    },
+    /// drops all the stashes
+    Clear,
+    /// creates a branch at the commit a stash was made on and applies the stash there
+    Branch {
+        branch: String,
+        stash: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StashPushOptions {
+    pub message: Option<String>,
+    /// stash the untracked files as well, and remove them
+    pub include_untracked: bool,
+    /// keep the changes added to the index
+    pub keep_index: bool,
+    /// stash only the changes to these paths
+    pub paths: Vec<String>,
+}
+
+/// the commits of a stash: the working tree commit, its parent HEAD, the index commit and
+/// the untracked files commit
+struct StashInfo {
+    /// the name the stash was given as, e.g. refs/stash@{0}
+    revision: String,
+    w_commit: Hash,
+    b_commit: Hash,
+    i_commit: Hash,
+    u_commit: Option<Hash>,
+}
+
+/// saves local changes in the stash, a stack kept in the reflog of refs/stash, and brings
+/// them back. stashes are commits recording the working tree, with HEAD, the index and
+/// the untracked files as parents, like git's. returns false if a stash didn't apply cleanly.
+pub fn stash(action: StashAction) -> Result<bool> {
+    match action {
+        StashAction::Push(options) => push(options),
+        StashAction::Apply { stash, index } => apply(&stash_info(stash.as_deref())?, index),
+        StashAction::Pop { stash, index } => {
+            let info = stash_info(stash.as_deref())?;
+            check_stash_ref(&info)?;
+            if !apply(&info, index)? {
+                println!("The stash entry is kept in case you need it again.");
+                return Ok(false);
+            }
+            drop_stash(&info)?;
+            Ok(true)
+        }
+        StashAction::List => {
+            let entries = read_reflog(STASH_REF)?;
+            for (i, entry) in entries.iter().rev().enumerate() {
+                println!("stash@{{{}}}: {}", i, entry.message);
+            }
+            Ok(true)
+        }
+        StashAction::Show { stash, patch, stat } => {
+            let info = stash_info(stash.as_deref())?;
+            let format = PatchOptions {
+                color: io::stdout().is_terminal(),
+                ..Default::default()
+            };
+            diff(DiffOptions {
+                args: vec![info.b_commit.to_hex(), info.w_commit.to_hex()],
+                stat: stat || !patch,
+                patch,
+                find_renames: true,
+                rename_score: DEFAULT_RENAME_SCORE,
+                format,
+                ..Default::default()
+            })?;
+            Ok(true)
+        }
+        StashAction::Drop { stash } => {
+            let info = stash_info(stash.as_deref())?;
+            check_stash_ref(&info)?;
+            drop_stash(&info)?;
+            Ok(true)
+        }
+        StashAction::Clear => {
+            delete_ref(STASH_REF)?;
+            Ok(true)
+        }
+        StashAction::Branch { branch, stash } => create_branch(&branch, stash.as_deref()),
+    }
+}
+
+fn push(options: StashPushOptions) -> Result<bool> {
+    let head = resolve_ref("HEAD")?.ok_or(anyhow!("You do not have the initial commit yet"))?;
+    let head_commit = read_commit(&head)?;
+    let index = read_index()?;
+    if index.has_conflicts() {
+        for path in unmerged_paths(&index) {
+            println!("{}: needs merge", path);
+        }
+        return Ok(false);
+    }
+    let head_index = Index::from_tree(&head_commit.tree)?;
+    let paths = &options.paths;
+    let matches = |path: &str| matches_paths(path, false, paths);
+
+    let untracked: Vec<String> = match options.include_untracked {
+        true => untracked_paths(&index)?
+            .into_iter()
+            .filter(|path| matches(path))
+            .collect(),
+        false => Vec::new(),
+    };
+    for pathspec in paths {
+        let pathspec = [pathspec.clone()];
+        let known = index
+            .entries
+            .iter()
+            .map(|entry| &entry.path)
+            .chain(untracked.iter())
+            .any(|path| matches_paths(path, false, &pathspec));
+        if !known {
+            bail!(
+                "pathspec '{}' did not match any file(s) known to git\nDid you forget to 'git add'?",
+                pathspec[0]
+            );
+        }
+    }
+
+    // the working tree versions of the tracked files that are stashed
+    let mut worktree_index = Index {
+        version: index.version,
+        entries: index.entries.clone(),
+        ..Default::default()
+    };
+    let index_mtime = index_mtime()?;
+    for entry in index.entries.iter().filter(|entry| matches(&entry.path)) {
+        if is_clean(entry, index_mtime)? {
+            continue;
+        }
+        match write_worktree_file(&entry.path)? {
+            Some(file) => worktree_index.add(file),
+            None => worktree_index.remove(&entry.path),
+        }
+    }
+
+    let staged = index
+        .entries
+        .iter()
+        .chain(head_index.entries.iter())
+        .filter(|entry| matches(&entry.path))
+        .any(|entry| {
+            let same = |other: Option<&IndexEntry>| {
+                other.is_some_and(|other| other.mode == entry.mode && other.hash == entry.hash)
+            };
+            !same(index.entry(&entry.path)) || !same(head_index.entry(&entry.path))
+        });
+    let w_tree = worktree_index.write_tree()?;
+    let i_tree = index.write_tree()?;
+    if !staged && w_tree == i_tree && untracked.is_empty() {
+        println!("No local changes to save");
+        return Ok(true);
+    }
+
+    let branch = match head_branch()? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => String::from("(no branch)"),
+    };
+    let description = format!(
+        "{}: {} {}",
+        branch,
+        abbreviate(&head)?,
+        subject(&head_commit.message)
+    );
+
+    let i_commit = write_stash_commit(
+        i_tree,
+        vec![head.clone()],
+        &format!("index on {}", description),
+        true,
+    )?;
+    let mut parents = vec![head.clone(), i_commit];
+    if !untracked.is_empty() {
+        let mut untracked_index = Index::default();
+        for path in &untracked {
+            if let Some(entry) = write_worktree_file(path)? {
+                untracked_index.add(entry);
+            }
+        }
+        let u_tree = untracked_index.write_tree()?;
+        let message = format!("untracked files on {}", description);
+        parents.push(write_stash_commit(u_tree, Vec::new(), &message, true)?);
+    }
+    let message = match &options.message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", description),
+    };
+    let w_commit = write_stash_commit(w_tree, parents, &message, false)?;
+    update_ref(STASH_REF, &w_commit, &message)?;
+    println!("Saved working directory and index state {}", message);
+
+    // the stashed paths go back to HEAD, or to the index with --keep-index. like git,
+    // stashing everything is recorded as a hard reset.
+    if paths.is_empty() {
+        move_head(&head, "reset: moving to HEAD")?;
+    }
+    for path in &untracked {
+        remove_file(path)?;
+    }
+    let target = match options.keep_index {
+        true => &index,
+        false => &head_index,
+    };
+    let mut reverted = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    for entry in &index.entries {
+        if !matches(&entry.path) {
+            reverted.entries.push(entry.clone());
+        } else if target.entry(&entry.path).is_none() {
+            remove_file(&entry.path)?;
+        }
+    }
+    for entry in target.entries.iter().filter(|entry| matches(&entry.path)) {
+        let unchanged = index
+            .entry(&entry.path)
+            .is_some_and(|old| old.mode == entry.mode && old.hash == entry.hash);
+        let old = index.entry(&entry.path).filter(|_| unchanged);
+        let reverted_entry = match old {
+            Some(old) if is_clean(old, index_mtime)? => old.clone(),
+            _ => {
+                let mode = EntryMode::try_from(entry.mode)?;
+                checkout_file(&entry.path, mode, &entry.hash, 0)?
+            }
+        };
+        reverted.add(reverted_entry);
+    }
+    write_index(&reverted)?;
+
+    Ok(true)
+}
+
+/// applies a stash onto the working tree: its changes are merged with the current
+/// index, and its untracked files restored. the changes are left unstaged except for new
+/// files, unless the index changes are restored too. prints the status afterwards.
+fn apply(info: &StashInfo, restore_index: bool) -> Result<bool> {
+    let index = read_index()?;
+    if index.has_conflicts() {
+        for path in unmerged_paths(&index) {
+            eprintln!("{}: needs merge", path);
+        }
+        return Ok(false);
+    }
+    let c_tree = index.write_tree()?;
+    let b_tree = read_commit(&info.b_commit)?.tree;
+    let w_tree = read_commit(&info.w_commit)?.tree;
+    let i_tree = read_commit(&info.i_commit)?.tree;
+
+    let options = TreeMergeOptions {
+        ours_label: match b_tree == c_tree {
+            true => String::from("Version stash was based on"),
+            false => String::from("Updated upstream"),
+        },
+        theirs_label: String::from("Stashed changes"),
+        ancestor_label: String::from("Stash base"),
+        style: conflict_style()?,
+        ..Default::default()
+    };
+
+    // the index changes are applied to the current index first
+    let mut index_tree = None;
+    if restore_index && i_tree != b_tree && i_tree != c_tree {
+        let result = merge_trees(Some(&b_tree), &c_tree, &i_tree, &options)?;
+        if !result.is_clean() {
+            bail!("conflicts in index. Try without --index.");
+        }
+        index_tree = Some(result.write_tree()?);
+        // git resets the index to HEAD at this point, which is recorded in the reflog
+        if let Some(head) = resolve_ref("HEAD")? {
+            move_head(&head, "reset: moving to HEAD")?;
+        }
+    }
+
+    let mut clean = true;
+    if w_tree == b_tree {
+        println!("Already up to date.");
+    } else {
+        let result = merge_trees(Some(&b_tree), &c_tree, &w_tree, &options)?;
+        let merged = checkout_merge(&index, &result, "merge")?;
+        for message in &result.messages {
+            println!("{}", message);
+        }
+
+        if !result.is_clean() {
+            clean = false;
+            if restore_index {
+                eprintln!("Index was not unstashed.");
+            }
+        } else {
+            // the merged index with only the new files staged, or the stash's index
+            let base = match &index_tree {
+                Some(tree) => Index::from_tree(tree)?,
+                None => {
+                    let mut base = Index::from_tree(&c_tree)?;
+                    for entry in &merged.entries {
+                        if base.entries.iter().all(|other| other.path != entry.path) {
+                            base.add(entry.clone());
+                        }
+                    }
+                    base
+                }
+            };
+            let mut unstaged = Index {
+                version: index.version,
+                ..Default::default()
+            };
+            for entry in base.entries {
+                let same = merged.entry(&entry.path).filter(|merged_entry| {
+                    merged_entry.mode == entry.mode && merged_entry.hash == entry.hash
+                });
+                unstaged.entries.push(same.cloned().unwrap_or(entry));
+            }
+            write_index(&unstaged)?;
+        }
+    }
+
+    if let Some(u_commit) = &info.u_commit {
+        if !restore_untracked(&read_commit(u_commit)?.tree)? {
+            eprintln!("error: could not restore untracked files from stash");
+            clean = false;
+        }
+    }
+
+    write_status(&mut io::stdout().lock())?;
+    Ok(clean)
+}
+
+/// writes the untracked files of a stash, except those that exist already. returns
+/// false if there were any.
+fn restore_untracked(tree: &Hash) -> Result<bool> {
+    let mut restored = true;
+    for entry in Index::from_tree(tree)?.entries {
+        if fs::symlink_metadata(&entry.path).is_ok() {
+            eprintln!("{} already exists, no checkout", entry.path);
+            restored = false;
+            continue;
+        }
+        let mode = EntryMode::try_from(entry.mode)?;
+        checkout_file(&entry.path, mode, &entry.hash, 0)?;
+    }
+    Ok(restored)
+}
+
+/// the paths of the index with conflicts, once each
+fn unmerged_paths(index: &Index) -> Vec<&str> {
+    let mut paths: Vec<&str> = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() != 0) {
+        if paths.last() != Some(&entry.path.as_str()) {
+            paths.push(&entry.path);
+        }
+    }
+    paths
+}
+
+/// removes a stash from the reflog of refs/stash, which is deleted along with the last stash
+fn drop_stash(info: &StashInfo) -> Result<()> {
+    let position = stash_position(&info.revision).expect("the stash is a stash reference");
+    let mut entries = read_reflog(STASH_REF)?;
+    let i = entries.len() - 1 - position;
+    entries.remove(i);
+    // the entries are chained, so the one after the dropped stash now follows the one before it
+    if i < entries.len() {
+        entries[i].old = match i {
+            0 => Hash(vec![0; 20]),
+            i => entries[i - 1].new.clone(),
+        };
+    }
+
+    match entries.last() {
+        Some(last) => {
+            write_reflog(STASH_REF, &entries)?;
+            write_ref(STASH_REF, &last.new)?;
+        }
+        None => delete_ref(STASH_REF)?,
+    }
+    println!("Dropped {} ({:x})", info.revision, info.w_commit);
+    Ok(())
+}
+
+/// checks out a new branch at the commit a stash was made on, and applies the stash
+/// with its index there. the stash is dropped if it applied cleanly.
+fn create_branch(branch: &str, stash: Option<&str>) -> Result<bool> {
+    let info = stash_info(stash)?;
+    let name = format!("refs/heads/{}", branch);
+    if resolve_ref(&name)?.is_some() {
+        bail!("a branch named '{}' already exists", branch);
+    }
+
+    let index = read_index()?;
+    let head = resolve_ref("HEAD")?;
+    let head_commit = head.as_ref().map(read_commit).transpose()?;
+    let b_tree = read_commit(&info.b_commit)?.tree;
+    let mut switched = switch_trees(
+        &index,
+        head_commit.as_ref().map(|commit| &commit.tree),
+        &b_tree,
+        "checkout",
+    )?;
+    switched.version = index.version;
+    write_index(&switched)?;
+    write_local_changes(&switched, &Index::from_tree(&b_tree)?)?;
+
+    let from = match head_branch()? {
+        Some(current) => current.trim_start_matches("refs/heads/").to_string(),
+        None => head.as_ref().map(|head| head.to_hex()).unwrap_or_default(),
+    };
+    update_ref(
+        &name,
+        &info.b_commit,
+        &format!("branch: Created from {:x}", info.b_commit),
+    )?;
+    write_symbolic_ref("HEAD", &name)?;
+    crate::refs::reflog::append_reflog(
+        "HEAD",
+        head.as_ref(),
+        &info.b_commit,
+        &format!("checkout: moving from {} to {}", from, branch),
+    )?;
+    eprintln!("Switched to a new branch '{}'", branch);
+
+    if !apply(&info, true)? {
+        return Ok(false);
+    }
+    if stash_position(&info.revision).is_some() {
+        drop_stash(&info)?;
+    }
+    Ok(true)
+}
+
+/// prints the paths with local changes kept when checking out a tree, like git checkout
+fn write_local_changes(index: &Index, tree_index: &Index) -> Result<()> {
+    let mut paths = staged_paths(index, tree_index);
+    paths.extend(unstaged_paths(index)?);
+    paths.sort();
+    paths.dedup();
+    for path in paths {
+        let status = match (tree_index.entry(path), fs::symlink_metadata(path)) {
+            (None, _) => 'A',
+            (_, Err(_)) => 'D',
+            _ => 'M',
+        };
+        println!("{}\t{}", status, path);
+    }
+    Ok(())
+}
+
+/// writes a commit of a stash, authored and committed by the current identity. like
+/// git, the message of the working tree commit isn't terminated by a newline.
+fn write_stash_commit(
+    tree: Hash,
+    parents: Vec<Hash>,
+    message: &str,
+    terminated: bool,
+) -> Result<Hash> {
+    let mut commit = new_commit(
+        tree,
+        parents,
+        identity(Role::Author)?,
+        Some(identity(Role::Committer)?),
+        None,
+        Some(message.to_string()),
+    )?;
+    if !terminated {
+        commit.data.pop();
+    }
+    commit.write()
+}
+
+/// resolves a stash given as stash@{n}, as n, or as any stash commit. the latest stash
+/// is the default.
+fn stash_info(stash: Option<&str>) -> Result<StashInfo> {
+    let revision = match stash {
+        None => {
+            if resolve_ref(STASH_REF)?.is_none() {
+                bail!("No stash entries found.");
+            }
+            format!("{}@{{0}}", STASH_REF)
+        }
+        Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => {
+            format!("{}@{{{}}}", STASH_REF, n)
+        }
+        Some(stash) => stash.to_string(),
+    };
+
+    let w_commit = peel(resolve_revision(&revision)?, Some(ObjectKind::Commit))?;
+    let commit = read_commit(&w_commit)?;
+    let (b_commit, i_commit, u_commit) = match commit.parents.as_slice() {
+        [b, i] => (b.clone(), i.clone(), None),
+        [b, i, u] => (b.clone(), i.clone(), Some(u.clone())),
+        _ => bail!("'{}' is not a stash-like commit", revision),
+    };
+
+    Ok(StashInfo {
+        revision,
+        w_commit,
+        b_commit,
+        i_commit,
+        u_commit,
+    })
+}
+
+/// the position in the stash stack of a stash given as stash@{n} or refs/stash@{n}
+fn stash_position(revision: &str) -> Option<usize> {
+    let position = revision
+        .strip_prefix("refs/stash@{")
+        .or_else(|| revision.strip_prefix("stash@{"))?;
+    position.strip_suffix('}')?.parse().ok()
+}
+
+/// only stashes of the stack can be dropped
+fn check_stash_ref(info: &StashInfo) -> Result<()> {
+    match stash_position(&info.revision) {
+        Some(_) => Ok(()),
+        None => bail!("'{}' is not a stash reference", info.revision),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::stash_position;
+
+    #[test]
+    fn test_stash_position() {
+        assert_eq!(stash_position("stash@{0}"), Some(0));
+        assert_eq!(stash_position("refs/stash@{12}"), Some(12));
+        assert_eq!(stash_position("stash"), None);
+        assert_eq!(stash_position("main@{1}"), None);
+        assert_eq!(stash_position("stash@{x}"), None);
+    }
+}
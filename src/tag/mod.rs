@@ -0,0 +1,380 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::read_config,
+    editor::{cleanup_message, editor, launch_editor},
+    ident::{identity, Role},
+    merge_base::CommitGraph,
+    objects::{
+        commit::decode_commit,
+        tag::{decode_tag, new_tag},
+        Object, ObjectKind,
+    },
+    ref_filter::{RefFormatter, RefItem},
+    refs::{delete_ref, is_valid_ref_name, list_refs, resolve_ref, write_ref},
+    rev_parse::{abbreviate, peel_kind, resolve_commit, resolve_revision},
+    worktree::wildmatch,
+};
+
+const TAG_EDITMSG: &str = ".git/TAG_EDITMSG";
+
+/// width tag names are padded to when their messages are shown
+const NAME_WIDTH: usize = 15;
+
+#[derive(Debug, Clone, Default)]
+pub struct TagListOptions {
+    /// globs the tag names must match, e.g. v1.*
+    pub patterns: Vec<String>,
+    /// keys to sort by, like refname or version:refname. the last one is the primary key.
+    /// tag.sort is used when none is given.
+    pub sort: Vec<String>,
+    /// only list the tags containing this commit
+    pub contains: Option<String>,
+    /// show this many lines of the message of each tag, or of the commit it points to
+    pub lines: Option<usize>,
+    /// a for-each-ref format to show the tags with, e.g. %(refname:short)
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TagAction {
+    List(TagListOptions),
+    /// tags an object, HEAD by default. the tag is annotated when annotate is set or a
+    /// message is given, and the message is asked for in an editor when none is given.
+    Create {
+        name: String,
+        object: Option<String>,
+        annotate: bool,
+        messages: Vec<String>,
+        file: Option<String>,
+        force: bool,
+    },
+    Delete {
+        names: Vec<String>,
+    },
+}
+
+/// lists, creates and deletes tags. returns false if a tag couldn't be deleted.
+pub fn tag(action: TagAction) -> Result<bool> {
+    match action {
+        TagAction::List(options) => list(options, &mut io::stdout()).map(|_| true),
+        TagAction::Create {
+            name,
+            object,
+            annotate,
+            messages,
+            file,
+            force,
+        } => {
+            let message = match (messages.is_empty(), file) {
+                (true, None) if !annotate => None,
+                (true, None) => Some(edit_tag_message(&name)?),
+                (true, Some(file)) => Some(cleanup_message(&read_message_file(&file)?, true)),
+                (false, _) => Some(cleanup_message(&messages.join("\n\n"), true)),
+            };
+            create(&name, object.as_deref().unwrap_or("HEAD"), message, force).map(|_| true)
+        }
+        TagAction::Delete { names } => delete(&names),
+    }
+}
+
+fn list<W: Write>(options: TagListOptions, out: &mut W) -> Result<()> {
+    let formatter = RefFormatter::new(false)?;
+    let mut items: Vec<RefItem> = list_refs("refs/tags/")?
+        .into_iter()
+        .filter(|(name, _)| {
+            let short = name.trim_start_matches("refs/tags/");
+            options.patterns.is_empty()
+                || options
+                    .patterns
+                    .iter()
+                    .any(|pattern| wildmatch(pattern.as_bytes(), short.as_bytes()))
+        })
+        .map(|(name, hash)| RefItem { name, hash })
+        .collect();
+
+    let sort = match options.sort.is_empty() {
+        true => read_config()?
+            .get_all("tag.sort")
+            .into_iter()
+            .map(|key| key.to_string())
+            .collect(),
+        false => options.sort,
+    };
+    formatter.sort(&mut items, &sort)?;
+
+    if let Some(rev) = &options.contains {
+        let commit = resolve_commit(rev).map_err(|_| anyhow!("malformed object name {}", rev))?;
+        let mut graph = CommitGraph::new();
+        let mut kept = Vec::new();
+        for item in items {
+            let (target, kind) = peel_kind(item.hash.clone(), None)?;
+            if kind == ObjectKind::Commit && graph.is_ancestor(&commit, &target)? {
+                kept.push(item);
+            }
+        }
+        items = kept;
+    }
+
+    for item in &items {
+        let name = item.name.trim_start_matches("refs/tags/");
+        match (&options.format, options.lines) {
+            (Some(format), _) => writeln!(out, "{}", formatter.format(format, item)?)?,
+            (None, Some(lines)) => writeln!(
+                out,
+                "{:width$} {}",
+                name,
+                message_lines(item, lines)?.join("\n    "),
+                width = NAME_WIDTH
+            )?,
+            (None, None) => writeln!(out, "{}", name)?,
+        }
+    }
+    Ok(())
+}
+
+/// the first lines of the message of a tag, or of the commit a lightweight tag points to
+fn message_lines(item: &RefItem, lines: usize) -> Result<Vec<String>> {
+    let object = Object::read_from_hash(item.hash.to_hex())?;
+    let message = match object.kind {
+        ObjectKind::Tag => decode_tag(object.data)?.commit_message,
+        ObjectKind::Commit => decode_commit(object.data)?.message,
+        _ => String::new(),
+    };
+    Ok(message
+        .lines()
+        .take(lines)
+        .map(|line| line.to_string())
+        .collect())
+}
+
+fn create(name: &str, object: &str, message: Option<String>, force: bool) -> Result<()> {
+    let ref_name = format!("refs/tags/{}", name);
+    if !is_valid_ref_name(&ref_name) {
+        bail!("'{}' is not a valid tag name.", name);
+    }
+    let target = resolve_revision(object)
+        .map_err(|_| anyhow!("Failed to resolve '{}' as a valid ref.", object))?;
+    let old = resolve_ref(&ref_name)?;
+    if old.is_some() && !force {
+        bail!("tag '{}' already exists", name);
+    }
+
+    let hash = match message {
+        Some(message) => {
+            let kind = Object::read_from_hash(target.to_hex())?.kind;
+            if kind == ObjectKind::Tag {
+                eprint!("{}", nested_tag_hint(name, object));
+            }
+            // the message keeps no final newline, the tag object adds it back
+            let message = message.strip_suffix('\n').unwrap_or(&message).to_string();
+            let tagger = identity(Role::Committer)?;
+            new_tag(target, kind, name.to_string(), tagger, Some(message), None).write()?
+        }
+        None => target,
+    };
+
+    write_ref(&ref_name, &hash)?;
+    if let Some(old) = old.filter(|old| *old != hash) {
+        println!("Updated tag '{}' (was {})", name, abbreviate(&old)?);
+    }
+    Ok(())
+}
+
+fn nested_tag_hint(name: &str, object: &str) -> String {
+    format!(
+        "hint: You have created a nested tag. The object referred to by your new tag is\n\
+         hint: already a tag. If you meant to tag the object that it points to, use:\n\
+         hint: \n\
+         hint: \tmgit tag -f {} {}^{{}}\n",
+        name, object
+    )
+}
+
+/// asks for the message of an annotated tag in an editor
+fn edit_tag_message(name: &str) -> Result<String> {
+    fs::write(
+        TAG_EDITMSG,
+        format!(
+            "\n#\n# Write a message for tag:\n#   {}\n# Lines starting with '#' will be ignored.\n",
+            name
+        ),
+    )?;
+    launch_editor(&editor()?, Path::new(TAG_EDITMSG))?;
+    let message = cleanup_message(&fs::read_to_string(TAG_EDITMSG)?, true);
+    if message.is_empty() {
+        bail!("no tag message?");
+    }
+    Ok(message)
+}
+
+/// reads a message from a file, or from stdin when the file is "-"
+fn read_message_file(file: &str) -> Result<String> {
+    match file {
+        "-" => Ok(io::read_to_string(io::stdin())?),
+        file => {
+            fs::read_to_string(file).map_err(|err| anyhow!("could not open '{}': {}", file, err))
+        }
+    }
+}
+
+fn delete(names: &[String]) -> Result<bool> {
+    let mut deleted_all = true;
+    for name in names {
+        let ref_name = format!("refs/tags/{}", name);
+        let hash = match resolve_ref(&ref_name)? {
+            Some(hash) => hash,
+            None => {
+                eprintln!("error: tag '{}' not found.", name);
+                deleted_all = false;
+                continue;
+            }
+        };
+        delete_ref(&ref_name)?;
+        println!("Deleted tag '{}' (was {})", name, abbreviate(&hash)?);
+    }
+    Ok(deleted_all)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        objects::{hash::Hash, tag::decode_tag, Object, ObjectKind},
+        refs::resolve_ref,
+        test_repo::TestRepo,
+    };
+
+    use super::{list, tag, TagAction, TagListOptions};
+
+    fn create(name: &str, object: Option<&str>, messages: &[&str], force: bool) -> bool {
+        tag(TagAction::Create {
+            name: name.to_string(),
+            object: object.map(String::from),
+            annotate: false,
+            messages: messages.iter().map(|message| message.to_string()).collect(),
+            file: None,
+            force,
+        })
+        .is_ok()
+    }
+
+    fn listed(options: TagListOptions) -> String {
+        let mut out = Vec::new();
+        list(options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// main has first and second, which is checked out
+    fn setup(repo: &TestRepo) -> (Hash, Hash) {
+        let first = repo.commit(&repo.tree(&[("a", "1")]), &[], 1000, "first");
+        let second = repo.commit(&repo.tree(&[("a", "2")]), &[&first], 2000, "second");
+        repo.checkout(&second);
+        (first, second)
+    }
+
+    #[test]
+    fn test_create() {
+        let repo = TestRepo::new();
+        let (first, second) = setup(&repo);
+
+        assert!(create("light", Some("main~1"), &[], false));
+        assert_eq!(resolve_ref("refs/tags/light").unwrap(), Some(first));
+
+        // messages are joined as paragraphs
+        assert!(create("v1.0", None, &["Release", "body  "], false));
+        let hash = resolve_ref("refs/tags/v1.0").unwrap().unwrap();
+        let object = Object::read_from_hash(hash.to_hex()).unwrap();
+        assert_eq!(object.kind, ObjectKind::Tag);
+        assert!(object.data.ends_with(b"\n\nRelease\n\nbody\n"));
+        let annotated = decode_tag(object.data).unwrap();
+        assert_eq!(annotated.object, second);
+        assert_eq!(annotated.object_type, ObjectKind::Commit);
+        assert_eq!(annotated.tag_name, "v1.0");
+        assert_eq!(annotated.tagger.name, "A U Thor");
+        assert_eq!(annotated.commit_message, "Release\n\nbody");
+
+        // existing tags are only replaced when forced
+        assert!(!create("v1.0", Some("light"), &[], false));
+        assert_eq!(resolve_ref("refs/tags/v1.0").unwrap(), Some(hash.clone()));
+        assert!(create("v1.0", Some("light"), &[], true));
+        assert_ne!(resolve_ref("refs/tags/v1.0").unwrap(), Some(hash));
+
+        assert!(!create("bad..name", None, &[], false));
+    }
+
+    #[test]
+    fn test_list_sort() {
+        let repo = TestRepo::new();
+        setup(&repo);
+        for name in ["v1.10", "v1.2", "v2.0", "v1.9"] {
+            assert!(create(name, None, &[], false));
+        }
+        let sorted = |sort: &[&str]| {
+            listed(TagListOptions {
+                sort: sort.iter().map(|key| key.to_string()).collect(),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(sorted(&[]), "v1.10\nv1.2\nv1.9\nv2.0\n");
+        assert_eq!(sorted(&["version:refname"]), "v1.2\nv1.9\nv1.10\nv2.0\n");
+        assert_eq!(sorted(&["-version:refname"]), "v2.0\nv1.10\nv1.9\nv1.2\n");
+
+        let matching = listed(TagListOptions {
+            patterns: vec![String::from("v1.*")],
+            sort: vec![String::from("v:refname")],
+            ..Default::default()
+        });
+        assert_eq!(matching, "v1.2\nv1.9\nv1.10\n");
+    }
+
+    #[test]
+    fn test_list_contains() {
+        let repo = TestRepo::new();
+        let (first, _) = setup(&repo);
+        assert!(create("old", Some("main~1"), &["old"], false));
+        assert!(create("new", None, &[], false));
+        // tags of other objects than commits contain nothing
+        let tree = repo.tree(&[("a", "1")]);
+        assert!(create("tree", Some(&format!("{:x}", tree)), &[], false));
+
+        let contains = |rev: String| {
+            listed(TagListOptions {
+                contains: Some(rev),
+                ..Default::default()
+            })
+        };
+        assert_eq!(contains(format!("{:x}", first)), "new\nold\n");
+        assert_eq!(contains(String::from("main")), "new\n");
+    }
+
+    #[test]
+    fn test_list_lines() {
+        let repo = TestRepo::new();
+        setup(&repo);
+        assert!(create("v1.0", None, &["Release", "body"], false));
+        assert!(create("light", None, &[], false));
+
+        let lines = |lines: usize| {
+            listed(TagListOptions {
+                lines: Some(lines),
+                ..Default::default()
+            })
+        };
+        // lightweight tags show the message of their commit
+        assert_eq!(
+            lines(1),
+            "light           second\nv1.0            Release\n"
+        );
+        assert_eq!(
+            lines(3),
+            "light           second\nv1.0            Release\n    \n    body\n"
+        );
+    }
+}
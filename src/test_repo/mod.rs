@@ -0,0 +1,156 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use crate::{
+    index::{read_index, write_index},
+    init::init,
+    objects::{
+        commit::{new_commit, Author},
+        hash::Hash,
+        tag::new_tag,
+        tree::{encode_tree, new_tree, Entry, EntryMode},
+        Object, ObjectKind,
+    },
+    refs,
+    rev_parse::peel,
+    worktree::reset_hard,
+};
+
+/// repositories are used through the current directory, which is shared by all test threads
+static LOCK: Mutex<()> = Mutex::new(());
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// an empty repository in a temporary directory, which is the current directory until it is dropped
+pub struct TestRepo {
+    dir: PathBuf,
+    previous_dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestRepo {
+    pub fn new() -> TestRepo {
+        // a failed test must not fail the ones waiting for the lock
+        let lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = env::temp_dir().join(format!(
+            "mgit-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let previous_dir = env::current_dir().unwrap();
+        env::set_current_dir(&dir).unwrap();
+        init().unwrap();
+        fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
+        // reflogs need an identity
+        fs::write(
+            ".git/config",
+            "[user]\n\tname = A U Thor\n\temail = author@example.com\n",
+        )
+        .unwrap();
+
+        TestRepo {
+            dir,
+            previous_dir,
+            _lock: lock,
+        }
+    }
+
+    pub fn blob(&self, content: &str) -> Hash {
+        Object {
+            data: content.as_bytes().to_vec(),
+            kind: ObjectKind::Blob,
+        }
+        .write()
+        .unwrap()
+    }
+
+    /// writes a tree of regular files with the given names and contents
+    pub fn tree(&self, files: &[(&str, &str)]) -> Hash {
+        let entries = files
+            .iter()
+            .map(|(name, content)| Entry {
+                mode: EntryMode::RegularFile,
+                name: name.to_string(),
+                hash: self.blob(content),
+            })
+            .collect();
+        self.tree_from_entries(entries)
+    }
+
+    pub fn tree_from_entries(&self, entries: Vec<Entry>) -> Hash {
+        Object {
+            data: encode_tree(new_tree(entries)),
+            kind: ObjectKind::Tree,
+        }
+        .write()
+        .unwrap()
+    }
+
+    /// writes a commit made at the given time, in seconds
+    pub fn commit(&self, tree: &Hash, parents: &[&Hash], time: u64, message: &str) -> Hash {
+        let author = Author {
+            name: String::from("A U Thor"),
+            email: String::from("author@example.com"),
+            time,
+            time_zone: String::from("+0000"),
+        };
+        let parents = parents.iter().map(|parent| (*parent).clone()).collect();
+
+        new_commit(
+            tree.clone(),
+            parents,
+            author,
+            None,
+            None,
+            Some(format!("{}\n", message)),
+        )
+        .unwrap()
+        .write()
+        .unwrap()
+    }
+
+    pub fn tag(&self, object: &Hash, kind: ObjectKind, name: &str) -> Hash {
+        let tagger = Author {
+            name: String::from("A U Thor"),
+            email: String::from("author@example.com"),
+            time: 0,
+            time_zone: String::from("+0000"),
+        };
+
+        new_tag(
+            object.clone(),
+            kind,
+            name.to_string(),
+            tagger,
+            Some(format!("{}\n", name)),
+            None,
+        )
+        .write()
+        .unwrap()
+    }
+
+    pub fn set_ref(&self, name: &str, hash: &Hash) {
+        refs::write_ref(name, hash).unwrap();
+    }
+
+    /// points the current branch at a commit, and resets the index and the working tree to it
+    pub fn checkout(&self, commit: &Hash) {
+        self.set_ref("refs/heads/main", commit);
+        let tree = peel(commit.clone(), Some(ObjectKind::Tree)).unwrap();
+        let index = reset_hard(&read_index().unwrap(), &tree).unwrap();
+        write_index(&index).unwrap();
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.previous_dir);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,1191 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    env,
+    io::{stdin, stdout, BufRead, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    config::{parse_size, read_config, Config},
+    merge_base::CommitGraph,
+    objects::{commit::decode_commit, hash::Hash, tag::decode_tag, Object, ObjectKind},
+    pack_protocol::{
+        pack_writer::{PackWriter, PackWriterOptions},
+        pkt_line::{
+            write_advertised_refs, write_flush, write_line, Capability, Packet, PktLineReader, Ref,
+            MAX_PKT_LINE_LENGTH,
+        },
+        sideband::{SidebandWriter, SIDEBAND_MAX_PACKET},
+        upload_pack_request::DepthRequest,
+    },
+    refs::{expand_ref, list_refs, read_symbolic_ref, resolve_ref},
+    rev_parse::{peel, peel_kind},
+    revwalk::{collect_tree_objects, RevWalk, WalkedObject},
+};
+
+/// the capabilities advertised to version 0 and 1 clients, along with symref, filter,
+/// object-format and agent
+const V0_CAPABILITIES: [&str; 13] = [
+    "multi_ack",
+    "thin-pack",
+    "side-band",
+    "side-band-64k",
+    "ofs-delta",
+    "shallow",
+    "deepen-since",
+    "deepen-not",
+    "deepen-relative",
+    "no-progress",
+    "include-tag",
+    "multi_ack_detailed",
+    "no-done",
+];
+
+/// how much of a session a server takes part in. smart HTTP splits a session into a
+/// request for the advertisement, and requests the server forgets between.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    /// the advertisement, then everything the client asks for
+    #[default]
+    Full,
+    /// only the advertisement
+    AdvertiseRefs,
+    /// a single request, without the advertisement
+    StatelessRpc,
+}
+
+pub struct UploadPackOptions {
+    /// the repository served: its work tree, or the .git directory in it. bare
+    /// repositories aren't supported.
+    pub dir: String,
+    pub session: Session,
+}
+
+/// serves a fetch or a clone over stdin and stdout, in the protocol version the client
+/// asks for through GIT_PROTOCOL
+pub fn upload_pack(options: UploadPackOptions) -> Result<()> {
+    enter_repository(&options.dir)?;
+    let version = env::var("GIT_PROTOCOL")
+        .map(|protocol| requested_version(&protocol))
+        .unwrap_or(0);
+    serve_upload_pack(
+        stdin().lock(),
+        BufWriter::new(stdout().lock()),
+        version,
+        options.session,
+    )
+}
+
+/// serves the repository of the working directory to a client
+pub(crate) fn serve_upload_pack<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    version: u8,
+    session: Session,
+) -> Result<()> {
+    let mut server = UploadPack::new(reader, writer, session)?;
+    server.serve(version)
+}
+
+/// moves into the work tree of a repository, given its work tree or its .git directory.
+/// like the rest of mgit, servers work from a work tree, so bare repositories can't be served.
+pub(crate) fn enter_repository(dir: &str) -> Result<()> {
+    let path = Path::new(dir);
+    let work_tree = match path.file_name() {
+        Some(name) if name == ".git" => path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new(".")),
+        _ => path,
+    };
+    if !work_tree.join(".git").is_dir() {
+        if ["HEAD", "objects", "refs"]
+            .iter()
+            .all(|name| path.join(name).exists())
+        {
+            bail!(
+                "'{}' is a bare repository, which mgit can't serve: it needs a work tree",
+                dir
+            );
+        }
+        bail!("'{}' does not appear to be a git repository", dir);
+    }
+    env::set_current_dir(work_tree).with_context(|| format!("cannot enter '{}'", dir))
+}
+
+/// the highest version among the "version=<n>" entries of GIT_PROTOCOL that is known
+pub(crate) fn requested_version(protocol: &str) -> u8 {
+    protocol
+        .split(':')
+        .filter_map(|entry| entry.strip_prefix("version="))
+        .filter_map(|version| version.parse::<u8>().ok())
+        .filter(|version| *version <= 2)
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum MultiAck {
+    /// a single ACK for the first common commit
+    #[default]
+    None,
+    /// "ACK <hash> continue" for every common commit
+    Basic,
+    /// "ACK <hash> common" for every common commit, and "ACK <hash> ready" once the
+    /// pack can be built
+    Detailed,
+}
+
+/// a filter-spec, leaving objects out of the pack of a partial clone
+#[derive(Debug, PartialEq, Eq)]
+enum Filter {
+    /// blob:none leaves all blobs out
+    BlobNone,
+    /// blob:limit=<n> leaves out the blobs of n bytes or more
+    BlobLimit(u64),
+    /// tree:<depth> leaves out the trees and blobs that deep from the root tree, or deeper
+    Tree(usize),
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Result<Filter> {
+        match spec.split_once(':') {
+            Some(("blob", "none")) => Ok(Filter::BlobNone),
+            Some(("blob", limit)) if limit.starts_with("limit=") => {
+                Ok(Filter::BlobLimit(parse_size(&limit["limit=".len()..])?))
+            }
+            Some(("tree", depth)) => depth
+                .parse()
+                .map(Filter::Tree)
+                .map_err(|_| anyhow!("expected 'tree:<depth>'")),
+            _ => bail!("invalid filter-spec '{}'", spec),
+        }
+    }
+
+    /// whether an object found by the walk goes into the pack
+    fn accepts(&self, object: &WalkedObject, size: usize) -> bool {
+        // a blob in the root tree is 1 deep, like its subtrees
+        let depth = match object.path.is_empty() {
+            true => 0,
+            false => object.path.split('/').count(),
+        };
+        match (self, object.kind) {
+            (Filter::BlobNone, ObjectKind::Blob) => false,
+            (Filter::BlobLimit(limit), ObjectKind::Blob) => (size as u64) < *limit,
+            (Filter::Tree(max_depth), ObjectKind::Tree | ObjectKind::Blob) => depth < *max_depth,
+            _ => true,
+        }
+    }
+}
+
+/// what a client asks for: the wants and the capabilities of a version 0 request, or
+/// the arguments of a version 2 fetch command
+#[derive(Default)]
+struct FetchRequest {
+    wants: Vec<Hash>,
+    /// only sent in version 2, where they come with the wants
+    haves: Vec<Hash>,
+    /// the commits the client has without their parents
+    shallows: Vec<Hash>,
+    deepen: Vec<DepthRequest>,
+    /// depths count from the shallow commits of the client rather than from the wants
+    deepen_relative: bool,
+    filter: Option<Filter>,
+    /// the largest packet of the side-band the pack is multiplexed on, if any
+    sideband: Option<usize>,
+    multi_ack: MultiAck,
+    /// the pack follows "ACK <hash> ready" without waiting for "done"
+    no_done: bool,
+    no_progress: bool,
+    include_tag: bool,
+    ofs_delta: bool,
+    done: bool,
+    wait_for_done: bool,
+}
+
+impl FetchRequest {
+    /// reads the capabilities of a version 0 request, sent after its first want
+    fn capabilities(&mut self, capabilities: &str) {
+        for capability in capabilities.split(' ') {
+            match capability {
+                "multi_ack" if self.multi_ack == MultiAck::None => self.multi_ack = MultiAck::Basic,
+                "multi_ack_detailed" => self.multi_ack = MultiAck::Detailed,
+                "no-done" => self.no_done = true,
+                "side-band" if self.sideband.is_none() => self.sideband = Some(SIDEBAND_MAX_PACKET),
+                "side-band-64k" => self.sideband = Some(MAX_PKT_LINE_LENGTH),
+                "no-progress" => self.no_progress = true,
+                "include-tag" => self.include_tag = true,
+                "ofs-delta" => self.ofs_delta = true,
+                "deepen-relative" => self.deepen_relative = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// reads a line both versions share. returns false if the line is not one of them.
+    fn parse_line(&mut self, line: &str, allow_filter: bool) -> Result<bool> {
+        let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+        match name {
+            "want" => self.wants.push(parse_hash(value)?),
+            "shallow" => self.shallows.push(parse_hash(value)?),
+            "deepen" => match value.parse::<u32>() {
+                Ok(depth) if depth > 0 => self.deepen.push(DepthRequest::Deepen(depth)),
+                _ => bail!("invalid deepen: {}", value),
+            },
+            "deepen-since" => match value.parse::<u64>() {
+                Ok(time) => self.deepen.push(DepthRequest::DeepenSince(time)),
+                Err(_) => bail!("invalid deepen-since: {}", value),
+            },
+            "deepen-not" => self.deepen.push(DepthRequest::DeepenNot(value.to_string())),
+            "deepen-relative" if value.is_empty() => self.deepen_relative = true,
+            "filter" => {
+                if !allow_filter {
+                    bail!("filtering capability not negotiated");
+                }
+                self.filter = Some(Filter::parse(value)?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn parse_hash(hex: &str) -> Result<Hash> {
+    match hex.len() {
+        40 => Hash::try_from(hex.as_bytes()),
+        _ => Err(anyhow!("expected object ID, got '{}'", hex)),
+    }
+}
+
+/// where the history the client gets ends
+#[derive(Default)]
+struct ShallowInfo {
+    /// commits the client gets without their parents, and didn't have so already
+    shallow: Vec<Hash>,
+    /// shallow commits of the client it now gets the parents of
+    unshallow: Vec<Hash>,
+    /// the commits the walk doesn't go past: the shallow commits of both sides
+    boundary: HashSet<Hash>,
+    /// the parents of the unshallowed commits, which are sent as if they were wanted
+    extra_wants: Vec<Hash>,
+}
+
+impl ShallowInfo {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for hash in &self.shallow {
+            write_line(writer, &format!("shallow {}", hash.to_hex()))?;
+        }
+        for hash in &self.unshallow {
+            write_line(writer, &format!("unshallow {}", hash.to_hex()))?;
+        }
+        Ok(())
+    }
+}
+
+/// what the client is known to have, from its haves
+#[derive(Default)]
+struct Negotiation {
+    graph: CommitGraph,
+    /// the objects the client has, in the order they were first sent
+    common: Vec<Hash>,
+    /// commits the client has, and their parents
+    they_have: HashSet<Hash>,
+    oldest_have: Option<u64>,
+    /// wants known to be reachable from a commit the client has
+    satisfied: HashSet<Hash>,
+}
+
+impl Negotiation {
+    /// records a have. returns false if the object isn't here.
+    fn have(&mut self, hash: &Hash) -> Result<bool> {
+        if !Object::exists(hash)? {
+            return Ok(false);
+        }
+
+        let mut known = self.common.contains(hash);
+        let object = Object::read_from_hash(hash.to_hex())?;
+        if object.kind == ObjectKind::Commit {
+            let commit = decode_commit(object.data)?;
+            known = !self.they_have.insert(hash.clone());
+            let time = commit.committer.time;
+            self.oldest_have = Some(self.oldest_have.map_or(time, |oldest| oldest.min(time)));
+            self.they_have.extend(commit.parents);
+        }
+        if !known {
+            self.common.push(hash.clone());
+        }
+        Ok(true)
+    }
+
+    /// whether every want is reachable from a commit the client has, so that the pack
+    /// would be small enough
+    fn ok_to_give_up(&mut self, wants: &[Hash]) -> Result<bool> {
+        if wants.is_empty() {
+            return Ok(false);
+        }
+
+        for want in wants {
+            if self.satisfied.contains(want) {
+                continue;
+            }
+            // only the history of commits tells what the client has
+            let (hash, kind) = peel_kind(want.clone(), None)?;
+            if kind != ObjectKind::Commit || self.reaches_have(&hash)? {
+                self.satisfied.insert(want.clone());
+                continue;
+            }
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// looks for a commit the client has among the ancestors of a commit, down to the
+    /// oldest commit it sent
+    fn reaches_have(&mut self, commit: &Hash) -> Result<bool> {
+        let oldest = match self.oldest_have {
+            Some(oldest) => oldest,
+            None => return Ok(false),
+        };
+
+        let mut seen = HashSet::new();
+        let mut stack = vec![commit.clone()];
+        while let Some(hash) = stack.pop() {
+            if self.they_have.contains(&hash) {
+                return Ok(true);
+            }
+            if !seen.insert(hash.clone()) || self.graph.time(&hash)? < oldest {
+                continue;
+            }
+            stack.extend(self.graph.parents(&hash)?);
+        }
+        Ok(false)
+    }
+}
+
+/// the server side of a fetch
+struct UploadPack<R: BufRead, W: Write> {
+    reader: PktLineReader<R>,
+    writer: W,
+    config: Config,
+    /// the refs served, HEAD first, with symbolic refs resolved
+    refs: Vec<(String, Hash)>,
+    allow_filter: bool,
+    session: Session,
+    /// once the pack is being sent, errors go to its side-band rather than to an ERR line
+    pack_started: bool,
+}
+
+impl<R: BufRead, W: Write> UploadPack<R, W> {
+    fn new(reader: R, writer: W, session: Session) -> Result<UploadPack<R, W>> {
+        let config = read_config()?;
+        let allow_filter = config.get_bool("uploadpack.allowFilter")?.unwrap_or(false);
+        let mut refs: Vec<(String, Hash)> = resolve_ref("HEAD")?
+            .map(|head| (String::from("HEAD"), head))
+            .into_iter()
+            .collect();
+        refs.extend(list_refs("refs/")?);
+
+        Ok(UploadPack {
+            reader: PktLineReader::new(reader),
+            writer,
+            config,
+            refs,
+            allow_filter,
+            session,
+            pack_started: false,
+        })
+    }
+
+    fn serve(&mut self, version: u8) -> Result<()> {
+        let result = match version {
+            2 => self.serve_v2(),
+            version => self.serve_v0(version),
+        };
+        if let Err(err) = &result {
+            // the client shows the error, if it still listens
+            if !self.pack_started {
+                let _ = write_line(&mut self.writer, &format!("ERR {}", err));
+                let _ = self.writer.flush();
+            }
+        }
+        result
+    }
+
+    /// reads the next packet, or None if the client hung up
+    fn read_packet(&mut self) -> Result<Option<Packet>> {
+        if self.reader.get_mut().fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.reader.read_packet().map(Some)
+    }
+
+    /// reads the next line, or None for a flush-pkt. hanging up is an error.
+    fn read_line(&mut self) -> Result<Option<String>> {
+        match self.read_packet()? {
+            Some(Packet::Data(data)) => text(data).map(Some),
+            Some(Packet::Flush) => Ok(None),
+            Some(packet) => bail!("protocol error: unexpected {:?}", packet),
+            None => bail!("the remote end hung up unexpectedly"),
+        }
+    }
+
+    /// advertises the refs and capabilities, then serves a single fetch
+    fn serve_v0(&mut self, version: u8) -> Result<()> {
+        if self.session != Session::StatelessRpc {
+            self.advertise_v0(version)?;
+        }
+        if self.session == Session::AdvertiseRefs {
+            return Ok(());
+        }
+
+        let request = match self.read_request_v0()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        for want in &request.wants {
+            if !self.refs.iter().any(|(_, hash)| hash == want) {
+                bail!("upload-pack: not our ref {}", want.to_hex());
+            }
+        }
+
+        let shallow = shallow_info(&request)?;
+        if !request.deepen.is_empty() {
+            shallow.write(&mut self.writer)?;
+            write_flush(&mut self.writer)?;
+            self.writer.flush()?;
+        }
+
+        match self.negotiate_v0(&request)? {
+            Some(common) => self.send_pack(&request, &common, &shallow),
+            None => Ok(()),
+        }
+    }
+
+    fn advertise_v0(&mut self, version: u8) -> Result<()> {
+        let mut refs = Vec::new();
+        for (name, hash) in &self.refs {
+            refs.push(Ref::Tip {
+                name: name.clone(),
+                object_id: hash.to_hex(),
+            });
+            if let Some(peeled) = peeled(hash)? {
+                refs.push(Ref::Peeled {
+                    name: name.clone(),
+                    object_id: peeled.to_hex(),
+                });
+            }
+        }
+
+        let mut capabilities: Vec<Capability> =
+            V0_CAPABILITIES.iter().map(|cap| cap.to_string()).collect();
+        if let (Some(target), Some(_)) = (read_symbolic_ref("HEAD")?, resolve_ref("HEAD")?) {
+            capabilities.push(format!("symref=HEAD:{}", target));
+        }
+        if self.allow_filter {
+            capabilities.push("filter".to_string());
+        }
+        capabilities.push("object-format=sha1".to_string());
+        capabilities.push(format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")));
+        write_advertised_refs(&mut self.writer, version, &refs, &capabilities)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// reads the wants, with the capabilities on the first one, and the shallow and
+    /// deepen lines. returns None if the client wants nothing.
+    fn read_request_v0(&mut self) -> Result<Option<FetchRequest>> {
+        let mut request = FetchRequest::default();
+        loop {
+            let line = match self.read_packet()? {
+                Some(Packet::Data(data)) => text(data)?,
+                Some(Packet::Flush) => break,
+                Some(packet) => bail!("protocol error: unexpected {:?}", packet),
+                // ls-remote hangs up once it has the refs
+                None => return Ok(None),
+            };
+
+            let line = match line
+                .strip_prefix("want ")
+                .and_then(|rest| rest.split_once(' '))
+            {
+                Some((want, capabilities)) if request.wants.is_empty() => {
+                    request.capabilities(capabilities);
+                    format!("want {}", want)
+                }
+                _ => line,
+            };
+            if line == "deepen-relative" || !request.parse_line(&line, self.allow_filter)? {
+                bail!("protocol error, expected to get object ID, not '{}'", line);
+            }
+        }
+
+        match request.wants.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(request)),
+        }
+    }
+
+    /// acknowledges the haves of the client, round by round, up to its "done". returns
+    /// the objects it has, or None if it hung up. a stateless session ends with the first
+    /// round, which the client sends again with more haves.
+    fn negotiate_v0(&mut self, request: &FetchRequest) -> Result<Option<Vec<Hash>>> {
+        let mut negotiation = Negotiation::default();
+        let mut got_common = false;
+        let mut got_other = false;
+        let mut sent_ready = false;
+        let mut last = None;
+        loop {
+            let line = match self.read_packet()? {
+                Some(Packet::Data(data)) => text(data)?,
+                // the end of a round
+                Some(Packet::Flush) => {
+                    if let Some(last) = last.as_ref().filter(|_| {
+                        request.multi_ack == MultiAck::Detailed && got_common && !got_other
+                    }) {
+                        if negotiation.ok_to_give_up(&request.wants)? {
+                            sent_ready = true;
+                            write_line(&mut self.writer, &format!("ACK {} ready", last))?;
+                        }
+                    }
+                    if negotiation.common.is_empty() || request.multi_ack != MultiAck::None {
+                        write_line(&mut self.writer, "NAK")?;
+                    }
+                    if let Some(last) = last.as_ref().filter(|_| request.no_done && sent_ready) {
+                        write_line(&mut self.writer, &format!("ACK {}", last))?;
+                        self.writer.flush()?;
+                        return Ok(Some(negotiation.common));
+                    }
+                    self.writer.flush()?;
+                    if self.session == Session::StatelessRpc {
+                        return Ok(None);
+                    }
+                    got_common = false;
+                    got_other = false;
+                    continue;
+                }
+                Some(packet) => bail!("protocol error: unexpected {:?}", packet),
+                None => return Ok(None),
+            };
+
+            if line == "done" {
+                match &last {
+                    Some(last) if !negotiation.common.is_empty() => {
+                        if request.multi_ack != MultiAck::None {
+                            write_line(&mut self.writer, &format!("ACK {}", last))?;
+                        }
+                    }
+                    _ => write_line(&mut self.writer, "NAK")?,
+                }
+                self.writer.flush()?;
+                return Ok(Some(negotiation.common));
+            }
+
+            let hash = match line.strip_prefix("have ") {
+                Some(hex) => parse_hash(hex)?,
+                None => bail!("expected SHA1 list, got '{}'", line),
+            };
+            let hex = hash.to_hex();
+            if negotiation.have(&hash)? {
+                got_common = true;
+                match request.multi_ack {
+                    MultiAck::Detailed => {
+                        write_line(&mut self.writer, &format!("ACK {} common", hex))?
+                    }
+                    MultiAck::Basic => {
+                        write_line(&mut self.writer, &format!("ACK {} continue", hex))?
+                    }
+                    MultiAck::None if negotiation.common.len() == 1 => {
+                        write_line(&mut self.writer, &format!("ACK {}", hex))?
+                    }
+                    MultiAck::None => {}
+                }
+                last = Some(hex);
+            } else {
+                got_other = true;
+                if request.multi_ack != MultiAck::None
+                    && negotiation.ok_to_give_up(&request.wants)?
+                {
+                    let status = match request.multi_ack {
+                        MultiAck::Detailed => {
+                            sent_ready = true;
+                            "ready"
+                        }
+                        _ => "continue",
+                    };
+                    write_line(&mut self.writer, &format!("ACK {} {}", hex, status))?;
+                }
+            }
+        }
+    }
+
+    fn advertise_v2(&mut self) -> Result<()> {
+        write_line(&mut self.writer, "version 2")?;
+        write_line(
+            &mut self.writer,
+            &format!("agent=mgit/{}", env!("CARGO_PKG_VERSION")),
+        )?;
+        write_line(&mut self.writer, "ls-refs=unborn")?;
+        match self.allow_filter {
+            true => write_line(&mut self.writer, "fetch=shallow wait-for-done filter")?,
+            false => write_line(&mut self.writer, "fetch=shallow wait-for-done")?,
+        }
+        write_line(&mut self.writer, "server-option")?;
+        write_line(&mut self.writer, "object-format=sha1")?;
+        write_flush(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// advertises the capabilities, then serves commands until the client is done
+    fn serve_v2(&mut self) -> Result<()> {
+        if self.session != Session::StatelessRpc {
+            self.advertise_v2()?;
+        }
+        if self.session == Session::AdvertiseRefs {
+            return Ok(());
+        }
+
+        loop {
+            // the command and its capabilities come before a delim-pkt, and its
+            // arguments after it
+            let mut command = None;
+            let mut args = Vec::new();
+            loop {
+                match self.read_packet()? {
+                    Some(Packet::Data(data)) => {
+                        let line = text(data)?;
+                        if let Some(name) = line.strip_prefix("command=") {
+                            command = Some(name.to_string());
+                        } else if let Some(format) = line.strip_prefix("object-format=") {
+                            if format != "sha1" {
+                                bail!("mismatched object format: server sha1; client {}", format);
+                            }
+                        }
+                    }
+                    Some(Packet::Delim) => {
+                        while let Some(arg) = self.read_line()? {
+                            args.push(arg);
+                        }
+                        break;
+                    }
+                    Some(Packet::Flush) => break,
+                    Some(Packet::ResponseEnd) => bail!("protocol error: unexpected response end"),
+                    None if command.is_none() => return Ok(()),
+                    None => bail!("the remote end hung up unexpectedly"),
+                }
+            }
+
+            // a lone flush-pkt ends the session
+            match command.as_deref() {
+                Some("ls-refs") => self.ls_refs(&args)?,
+                Some("fetch") => self.fetch_v2(&args)?,
+                Some(command) => bail!("invalid command '{}'", command),
+                None => return Ok(()),
+            }
+            self.writer.flush()?;
+        }
+    }
+
+    fn ls_refs(&mut self, args: &[String]) -> Result<()> {
+        let mut symrefs = false;
+        let mut peel = false;
+        let mut unborn = false;
+        let mut prefixes = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "symrefs" => symrefs = true,
+                "peel" => peel = true,
+                "unborn" => unborn = true,
+                arg => match arg.strip_prefix("ref-prefix ") {
+                    Some(prefix) => prefixes.push(prefix),
+                    None => bail!("unexpected line: '{}'", arg),
+                },
+            }
+        }
+        let listed = |name: &str| {
+            prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix))
+        };
+
+        let head = read_symbolic_ref("HEAD")?;
+        if let Some(target) = head.as_ref().filter(|_| unborn && listed("HEAD")) {
+            if resolve_ref("HEAD")?.is_none() {
+                let mut line = String::from("unborn HEAD");
+                if symrefs {
+                    line.push_str(&format!(" symref-target:{}", target));
+                }
+                write_line(&mut self.writer, &line)?;
+            }
+        }
+
+        for (name, hash) in &self.refs {
+            if !listed(name) {
+                continue;
+            }
+            let mut line = format!("{} {}", hash.to_hex(), name);
+            if symrefs {
+                if let Some(target) = read_symbolic_ref(name)? {
+                    line.push_str(&format!(" symref-target:{}", target));
+                }
+            }
+            if peel {
+                if let Some(peeled) = peeled(hash)? {
+                    line.push_str(&format!(" peeled:{}", peeled.to_hex()));
+                }
+            }
+            write_line(&mut self.writer, &line)?;
+        }
+        write_flush(&mut self.writer)
+    }
+
+    /// answers a version 2 fetch: the acknowledgments of its haves, and the pack once the
+    /// client is done or enough is common
+    fn fetch_v2(&mut self, args: &[String]) -> Result<()> {
+        let mut request = FetchRequest {
+            sideband: Some(MAX_PKT_LINE_LENGTH),
+            ..Default::default()
+        };
+        for arg in args {
+            if request.parse_line(arg, self.allow_filter)? {
+                continue;
+            }
+            match arg.as_str() {
+                "done" => request.done = true,
+                "thin-pack" => {}
+                "no-progress" => request.no_progress = true,
+                "include-tag" => request.include_tag = true,
+                "ofs-delta" => request.ofs_delta = true,
+                "wait-for-done" => request.wait_for_done = true,
+                arg => match arg.strip_prefix("have ") {
+                    Some(hex) => request.haves.push(parse_hash(hex)?),
+                    None => bail!("unexpected line: '{}'", arg),
+                },
+            }
+        }
+        for want in &request.wants {
+            if !Object::exists(want)? {
+                bail!("upload-pack: not our ref {}", want.to_hex());
+            }
+        }
+
+        let mut negotiation = Negotiation::default();
+        let mut acks = Vec::new();
+        for have in &request.haves {
+            if negotiation.have(have)? {
+                acks.push(have.to_hex());
+            }
+        }
+        if !request.done {
+            write_line(&mut self.writer, "acknowledgments")?;
+            if acks.is_empty() {
+                write_line(&mut self.writer, "NAK")?;
+            }
+            for ack in &acks {
+                write_line(&mut self.writer, &format!("ACK {}", ack))?;
+            }
+            if request.wait_for_done || !negotiation.ok_to_give_up(&request.wants)? {
+                return write_flush(&mut self.writer);
+            }
+            write_line(&mut self.writer, "ready")?;
+            self.writer.write_all(b"0001")?;
+        }
+
+        let shallow = shallow_info(&request)?;
+        if !request.deepen.is_empty() || !request.shallows.is_empty() {
+            write_line(&mut self.writer, "shallow-info")?;
+            shallow.write(&mut self.writer)?;
+            self.writer.write_all(b"0001")?;
+        }
+        write_line(&mut self.writer, "packfile")?;
+        self.send_pack(&request, &negotiation.common, &shallow)
+    }
+
+    /// sends the objects the client asked for, but for those reachable from what it has
+    fn send_pack(
+        &mut self,
+        request: &FetchRequest,
+        common: &[Hash],
+        shallow: &ShallowInfo,
+    ) -> Result<()> {
+        self.pack_started = true;
+        let mut writer = PackWriter::new(PackWriterOptions {
+            window: self.config.get_size("pack.window")?.unwrap_or(10) as usize,
+            depth: self.config.get_size("pack.depth")?.unwrap_or(50) as usize,
+            ofs_delta: request.ofs_delta,
+            reuse_deltas: true,
+        });
+
+        let max_packet = match request.sideband {
+            Some(max_packet) => max_packet,
+            None => {
+                add_objects(&mut writer, request, common, shallow)?;
+                writer.write(&mut self.writer)?;
+                self.writer.flush()?;
+                return Ok(());
+            }
+        };
+
+        let mut sideband = SidebandWriter::new(&mut self.writer, max_packet);
+        let progress = !request.no_progress;
+        let written = add_objects(&mut writer, request, common, shallow).and_then(|_| {
+            let count = writer.object_count();
+            if progress {
+                sideband.progress(&format!("Enumerating objects: {}, done.\n", count))?;
+            }
+            writer.write(&mut sideband)?;
+            Ok(count)
+        });
+        match written {
+            Ok(count) => {
+                if progress {
+                    sideband.progress(&format!("Total {}\n", count))?;
+                }
+                sideband.finish()?;
+                Ok(())
+            }
+            Err(err) => {
+                sideband.error(&format!("upload-pack: {}", err))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn text(data: Vec<u8>) -> Result<String> {
+    let line = String::from_utf8(data).map_err(|_| anyhow!("protocol error: line is not UTF-8"))?;
+    Ok(line.strip_suffix('\n').unwrap_or(&line).to_string())
+}
+
+/// the object an annotated tag points to at the end of its chain, or None if the object
+/// is not a tag
+fn peeled(hash: &Hash) -> Result<Option<Hash>> {
+    match Object::read_from_hash(hash.to_hex())?.kind {
+        ObjectKind::Tag => Ok(Some(peel(hash.clone(), None)?)),
+        _ => Ok(None),
+    }
+}
+
+/// the commits of the wants, tags peeled. wants of other objects have no history.
+fn want_commits(wants: &[Hash]) -> Result<Vec<Hash>> {
+    let mut commits = Vec::new();
+    for want in wants {
+        if let (hash, ObjectKind::Commit) = peel_kind(want.clone(), None)? {
+            commits.push(hash);
+        }
+    }
+    Ok(commits)
+}
+
+/// finds where the history sent ends, from the shallow commits of the client and what
+/// it asked to deepen by
+fn shallow_info(request: &FetchRequest) -> Result<ShallowInfo> {
+    let mut graph = CommitGraph::new();
+    let mut client = Vec::new();
+    for hash in &request.shallows {
+        // the client may be shallow at commits this repository doesn't know
+        if Object::exists(hash)? {
+            client.push(hash.clone());
+        }
+    }
+    let mut info = ShallowInfo {
+        boundary: client.iter().cloned().collect(),
+        ..Default::default()
+    };
+    if request.deepen.is_empty() {
+        return Ok(info);
+    }
+
+    // commits at the end of the history sent, and commits whose parents are sent too
+    let mut ends = Vec::new();
+    let mut expanded = HashSet::new();
+    let depth = request.deepen.iter().find_map(|deepen| match deepen {
+        DepthRequest::Deepen(depth) => Some(*depth),
+        _ => None,
+    });
+    match depth {
+        Some(_) if request.deepen.len() > 1 => {
+            bail!("deepen and deepen-since (or deepen-not) cannot be used together")
+        }
+        Some(depth) => {
+            let (starts, depth) = match request.deepen_relative {
+                true => (client.clone(), depth.saturating_add(1)),
+                false => (want_commits(&request.wants)?, depth),
+            };
+            let mut seen: HashSet<Hash> = starts.iter().cloned().collect();
+            let mut queue: VecDeque<(Hash, u32)> =
+                starts.into_iter().map(|hash| (hash, 0)).collect();
+            while let Some((hash, distance)) = queue.pop_front() {
+                let parents = graph.parents(&hash)?;
+                if distance + 1 >= depth {
+                    if !parents.is_empty() {
+                        ends.push(hash);
+                    }
+                    continue;
+                }
+                expanded.insert(hash);
+                for parent in parents {
+                    if seen.insert(parent.clone()) {
+                        queue.push_back((parent, distance + 1));
+                    }
+                }
+            }
+        }
+        None => {
+            let mut since = None;
+            let mut excluded = HashSet::new();
+            for deepen in &request.deepen {
+                match deepen {
+                    DepthRequest::DeepenSince(time) => since = Some(*time),
+                    DepthRequest::DeepenNot(name) => {
+                        let full_name =
+                            expand_ref(name)?.ok_or(anyhow!("ambiguous deepen-not: {}", name))?;
+                        let hash = resolve_ref(&full_name)?
+                            .ok_or(anyhow!("ambiguous deepen-not: {}", name))?;
+                        let mut stack = want_commits(&[hash])?;
+                        while let Some(hash) = stack.pop() {
+                            if excluded.insert(hash.clone()) {
+                                stack.extend(graph.parents(&hash)?);
+                            }
+                        }
+                    }
+                    DepthRequest::Deepen(_) => {}
+                }
+            }
+            let is_excluded = |graph: &mut CommitGraph, hash: &Hash| -> Result<bool> {
+                Ok(excluded.contains(hash)
+                    || match since {
+                        Some(since) => graph.time(hash)? < since,
+                        None => false,
+                    })
+            };
+
+            let mut seen = HashSet::new();
+            let mut stack = Vec::new();
+            for want in want_commits(&request.wants)? {
+                if !is_excluded(&mut graph, &want)? {
+                    stack.push(want);
+                }
+            }
+            while let Some(hash) = stack.pop() {
+                if !seen.insert(hash.clone()) {
+                    continue;
+                }
+                let parents = graph.parents(&hash)?;
+                let mut kept = Vec::new();
+                for parent in &parents {
+                    if !is_excluded(&mut graph, parent)? {
+                        kept.push(parent.clone());
+                    }
+                }
+                // as with rev-list, the history goes on through the parents kept,
+                // even past a commit that ends up shallow
+                match kept.len() == parents.len() {
+                    true => expanded.insert(hash),
+                    false => {
+                        ends.push(hash);
+                        true
+                    }
+                };
+                stack.extend(kept);
+            }
+            if seen.is_empty() {
+                bail!("no commits selected for shallow requests");
+            }
+        }
+    }
+
+    for hash in ends {
+        if !info.boundary.contains(&hash) {
+            info.shallow.push(hash.clone());
+            info.boundary.insert(hash);
+        }
+    }
+    // unshallowed commits stay at the boundary of the walk, which goes on from their
+    // parents, so that the haves don't hide the history under them
+    for hash in client {
+        if expanded.contains(&hash) {
+            info.extra_wants.extend(graph.parents(&hash)?);
+            info.unshallow.push(hash);
+        }
+    }
+    Ok(info)
+}
+
+/// lists the objects to send and adds them to the pack: those reachable from the wants
+/// and not from the objects the client has, within the shallow boundary. objects are
+/// left out as the filter says, unless they were wanted themselves.
+fn add_objects(
+    writer: &mut PackWriter,
+    request: &FetchRequest,
+    common: &[Hash],
+    shallow: &ShallowInfo,
+) -> Result<()> {
+    let mut walk = RevWalk::new();
+    walk.shallow(shallow.boundary.clone());
+    let mut seen = HashSet::new();
+    let mut others = Vec::new();
+    for want in request.wants.iter().chain(&shallow.extra_wants) {
+        let (target, kind) = peel_kind(want.clone(), None)?;
+        if kind == ObjectKind::Commit {
+            // the walk lists the tags that lead to commits
+            walk.push(want.clone())?;
+            continue;
+        }
+
+        let mut hash = want.clone();
+        while hash != target {
+            let object = Object::read_from_hash(hash.to_hex())?;
+            others.push(WalkedObject {
+                hash: hash.clone(),
+                kind: ObjectKind::Tag,
+                path: String::new(),
+            });
+            hash = decode_tag(object.data)?.object;
+        }
+        match kind {
+            ObjectKind::Tree => {
+                collect_tree_objects(&target, String::new(), &mut seen, &mut others)?
+            }
+            kind => others.push(WalkedObject {
+                hash: target,
+                kind,
+                path: String::new(),
+            }),
+        }
+    }
+    for have in common {
+        if peel_kind(have.clone(), None)?.1 == ObjectKind::Commit {
+            walk.hide(have.clone())?;
+        }
+    }
+
+    let mut commits = Vec::new();
+    while let Some(commit) = walk.next_commit()? {
+        commits.push(commit.hash);
+    }
+    let mut objects: Vec<WalkedObject> = commits
+        .iter()
+        .map(|hash| WalkedObject {
+            hash: hash.clone(),
+            kind: ObjectKind::Commit,
+            path: String::new(),
+        })
+        .collect();
+    objects.extend(walk.objects(&commits)?);
+    objects.extend(others);
+
+    let mut packed = HashSet::new();
+    for object in objects {
+        let data = Object::read_from_hash(object.hash.to_hex())?;
+        let wanted = request.wants.contains(&object.hash);
+        if !wanted
+            && request
+                .filter
+                .as_ref()
+                .is_some_and(|filter| !filter.accepts(&object, data.data.len()))
+        {
+            continue;
+        }
+        let name = (object.kind != ObjectKind::Commit).then_some(object.path.as_str());
+        writer.add(data, name)?;
+        packed.insert(object.hash);
+    }
+
+    // tags pointing to objects sent go along when the client follows tags
+    if request.include_tag {
+        for (name, hash) in list_refs("refs/tags/")? {
+            if packed.contains(&hash)
+                || peeled(&hash)?.is_none_or(|target| !packed.contains(&target))
+            {
+                continue;
+            }
+            let mut hash = hash;
+            loop {
+                let object = Object::read_from_hash(hash.to_hex())?;
+                if object.kind != ObjectKind::Tag || packed.contains(&hash) {
+                    break;
+                }
+                let next = decode_tag(object.data.clone())?.object;
+                writer.add(object, Some(&name))?;
+                packed.insert(hash);
+                hash = next;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{enter_repository, requested_version, Filter};
+    use crate::{
+        objects::{hash::Hash, ObjectKind},
+        revwalk::WalkedObject,
+        test_repo::TestRepo,
+    };
+
+    #[test]
+    fn test_enter_repository() {
+        let _repo = TestRepo::new();
+        let work_tree = env::current_dir().unwrap();
+        fs::create_dir_all("sub/dir").unwrap();
+        env::set_current_dir("sub").unwrap();
+
+        enter_repository("../.git").unwrap();
+        assert_eq!(env::current_dir().unwrap(), work_tree);
+        enter_repository("sub/..").unwrap();
+        assert_eq!(env::current_dir().unwrap(), work_tree);
+
+        let err = enter_repository("sub/dir").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("does not appear to be a git repository"));
+
+        // bare repositories have no work tree to work from
+        for dir in ["bare.git/objects", "bare.git/refs"] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write("bare.git/HEAD", "ref: refs/heads/main\n").unwrap();
+        let err = enter_repository("bare.git").unwrap_err();
+        assert!(err.to_string().contains("is a bare repository"));
+        assert_eq!(env::current_dir().unwrap(), work_tree);
+    }
+
+    #[test]
+    fn test_requested_version() {
+        assert_eq!(requested_version("version=2"), 2);
+        assert_eq!(requested_version("foo=bar:version=1"), 1);
+        assert_eq!(requested_version("version=3"), 0);
+        assert_eq!(requested_version(""), 0);
+    }
+
+    #[test]
+    fn test_filter() {
+        let object = |kind, path: &str| WalkedObject {
+            hash: Hash(vec![0; 20]),
+            kind,
+            path: path.to_string(),
+        };
+        let blob = object(ObjectKind::Blob, "src/main.rs");
+        let root = object(ObjectKind::Tree, "");
+
+        let filter = Filter::parse("blob:none").unwrap();
+        assert!(!filter.accepts(&blob, 1));
+        assert!(filter.accepts(&root, 1));
+
+        let filter = Filter::parse("blob:limit=1k").unwrap();
+        assert_eq!(filter, Filter::BlobLimit(1024));
+        assert!(filter.accepts(&blob, 1023));
+        assert!(!filter.accepts(&blob, 1024));
+
+        let filter = Filter::parse("tree:1").unwrap();
+        assert!(filter.accepts(&root, 1));
+        assert!(!filter.accepts(&object(ObjectKind::Blob, "README"), 1));
+        assert!(filter.accepts(&object(ObjectKind::Commit, ""), 1));
+
+        assert!(Filter::parse("sparse:oid=HEAD").is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+
+const INFO_EXCLUDE: &str = ".git/info/exclude";
+
+/// a pattern of a .gitignore file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    /// the glob, without its leading slash, "!" or trailing slash
+    glob: String,
+    /// the directory of the .gitignore file, with a trailing slash, or empty for the root
+    base: String,
+    /// matched against the whole path relative to the base rather than the file name
+    anchored: bool,
+    negated: bool,
+    directory_only: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &str) -> Option<Pattern> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // trailing spaces are ignored unless escaped
+        let mut line = line.to_string();
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line.pop();
+        }
+        let (negated, glob) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(&line)),
+        };
+        let (directory_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            anchored: glob.contains('/'),
+            glob: glob.strip_prefix('/').unwrap_or(glob).to_string(),
+            base: base.to_string(),
+            negated,
+            directory_only,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Some(relative) => relative,
+            None => return false,
+        };
+
+        match self.anchored {
+            true => wildmatch(self.glob.as_bytes(), relative.as_bytes()),
+            false => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                wildmatch(self.glob.as_bytes(), name.as_bytes())
+            }
+        }
+    }
+}
+
+/// the patterns deciding which untracked files are ignored: those of .git/info/exclude
+/// and of the .gitignore files of the directories walked so far
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// the rules of .git/info/exclude and the .gitignore of the root
+    pub fn new() -> Result<IgnoreRules> {
+        let mut rules = IgnoreRules::default();
+        if Path::new(INFO_EXCLUDE).is_file() {
+            rules.add_patterns(&fs::read_to_string(INFO_EXCLUDE)?, "");
+        }
+        rules.add_directory("")?;
+        Ok(rules)
+    }
+
+    /// adds the patterns of the .gitignore of a directory, given with a trailing slash
+    /// (or empty for the root). patterns of deeper directories take precedence.
+    pub fn add_directory(&mut self, dir: &str) -> Result<()> {
+        let path = format!("{}.gitignore", dir);
+        if let Ok(content) = fs::read(&path) {
+            self.add_patterns(&String::from_utf8_lossy(&content), dir);
+        }
+        Ok(())
+    }
+
+    fn add_patterns(&mut self, content: &str, base: &str) {
+        self.patterns.extend(
+            content
+                .lines()
+                .filter_map(|line| Pattern::parse(line, base)),
+        );
+    }
+
+    /// returns true if the last pattern matching the path excludes it
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// matches a path against a glob: "*" and "?" don't match slashes, "**" matches
+/// across directories, and "[...]" matches a set of characters
+pub fn wildmatch(glob: &[u8], text: &[u8]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some(b'*') if glob.get(1) == Some(&b'*') => {
+            let rest = &glob[2..];
+            // "**/" also matches no directory at all
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                if wildmatch(after_slash, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| wildmatch(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let rest = &glob[1..];
+            for i in 0..=text.len() {
+                if wildmatch(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => {
+            matches!(text.first(), Some(c) if *c != b'/') && wildmatch(&glob[1..], &text[1..])
+        }
+        Some(b'[') => match (match_class(&glob[1..], text.first().copied()), text.first()) {
+            (Some((true, rest)), Some(_)) => wildmatch(rest, &text[1..]),
+            // an unterminated class is matched literally
+            (None, Some(b'[')) => wildmatch(&glob[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if glob.len() > 1 => {
+            text.first() == Some(&glob[1]) && wildmatch(&glob[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && wildmatch(&glob[1..], &text[1..]),
+    }
+}
+
+/// matches a character against the class starting after "[", returning whether it
+/// matched and the rest of the glob, or None if the class isn't terminated
+fn match_class(glob: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut i) = match glob.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let c = c?;
+    let mut matched = false;
+    let start = i;
+    while i < glob.len() {
+        match glob[i] {
+            b']' if i > start => return Some((matched != negated && c != b'/', &glob[i + 1..])),
+            low if glob.get(i + 1) == Some(&b'-')
+                && glob.get(i + 2).is_some_and(|&high| high != b']') =>
+            {
+                matched |= (low..=glob[i + 2]).contains(&c);
+                i += 3;
+            }
+            other => {
+                matched |= other == c;
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{wildmatch, IgnoreRules, Pattern};
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch(b"*.o", b"main.o"));
+        assert!(!wildmatch(b"*.o", b"src/main.o"));
+        assert!(wildmatch(b"src/*.rs", b"src/lib.rs"));
+        assert!(wildmatch(b"**/target", b"target"));
+        assert!(wildmatch(b"**/target", b"a/b/target"));
+        assert!(wildmatch(b"doc/**", b"doc/a/b.md"));
+        assert!(wildmatch(b"a/**/b", b"a/b"));
+        assert!(wildmatch(b"a/**/b", b"a/x/y/b"));
+        assert!(wildmatch(b"file[0-9].txt", b"file7.txt"));
+        assert!(!wildmatch(b"file[!0-9].txt", b"file7.txt"));
+        assert!(wildmatch(b"?.c", b"a.c"));
+        assert!(!wildmatch(b"?.c", b"/.c"));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let mut rules = IgnoreRules::default();
+        for (line, base) in [
+            ("*.log", ""),
+            ("!keep.log", ""),
+            ("/target/", ""),
+            ("build", "sub/"),
+            ("# comment", ""),
+        ] {
+            rules.patterns.extend(Pattern::parse(line, base));
+        }
+
+        assert!(rules.is_ignored("a.log", false));
+        assert!(rules.is_ignored("dir/b.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored("target", false));
+        assert!(!rules.is_ignored("sub/target", true));
+        assert!(rules.is_ignored("sub/x/build", false));
+        assert!(!rules.is_ignored("build", false));
+        assert_eq!(rules.patterns.len(), 4);
+    }
+}
@@ -0,0 +1,476 @@
+mod ignore;
+mod status;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    index::{index_mtime, Index, IndexEntry},
+    objects::{hash::Hash, tree::EntryMode, Object, ObjectKind},
+};
+
+pub use self::{
+    ignore::{wildmatch, IgnoreRules},
+    status::{detached_head, write_status},
+};
+
+/// the state of a tracked file in the working tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorktreeFile {
+    pub mode: EntryMode,
+    pub hash: Hash,
+}
+
+/// reads the working tree file of an index entry, returning None if it is missing.
+/// a file that looks unchanged since it was staged gets the hash of the entry without being read.
+pub fn read_worktree_file(
+    entry: &IndexEntry,
+    index_mtime: Option<(u32, u32)>,
+) -> Result<Option<WorktreeFile>> {
+    let metadata = match fs::symlink_metadata(&entry.path) {
+        Ok(metadata) if !metadata.is_dir() => metadata,
+        _ => return Ok(None),
+    };
+
+    let mode = file_mode(&metadata);
+    if mode as u32 == entry.mode && entry.matches_stat(&metadata, index_mtime) {
+        return Ok(Some(WorktreeFile {
+            mode,
+            hash: entry.hash.clone(),
+        }));
+    }
+
+    Ok(Some(WorktreeFile {
+        mode,
+        hash: read_blob(&entry.path, mode)?.hash()?,
+    }))
+}
+
+/// writes the blob of a working tree file and returns its entry with its stat
+/// information, or None if the file is missing
+pub fn write_worktree_file(path: &str) -> Result<Option<IndexEntry>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => metadata,
+        _ => return Ok(None),
+    };
+    let mode = file_mode(&metadata);
+    let hash = read_blob(path, mode)?.write()?;
+
+    let mut entry = IndexEntry::new(path, mode, hash, 0);
+    entry.refresh(&metadata);
+    Ok(Some(entry))
+}
+
+fn file_mode(metadata: &fs::Metadata) -> EntryMode {
+    if metadata.file_type().is_symlink() {
+        EntryMode::SymbolicLink
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        EntryMode::ExecutableFile
+    } else {
+        EntryMode::RegularFile
+    }
+}
+
+/// the blob of a working tree file: its content, or the target of a symbolic link
+fn read_blob(path: &str, mode: EntryMode) -> Result<Object> {
+    let data = match mode {
+        EntryMode::SymbolicLink => {
+            use std::os::unix::ffi::OsStrExt;
+            fs::read_link(path)?.as_os_str().as_bytes().to_vec()
+        }
+        _ => fs::read(path)?,
+    };
+    Ok(Object {
+        data,
+        kind: ObjectKind::Blob,
+    })
+}
+
+/// returns true if the working tree file matches the entry
+pub fn is_clean(entry: &IndexEntry, index_mtime: Option<(u32, u32)>) -> Result<bool> {
+    Ok(read_worktree_file(entry, index_mtime)?
+        .is_some_and(|file| file.hash == entry.hash && file.mode as u32 == entry.mode))
+}
+
+/// writes a blob to the working tree, replacing what is in the way, and returns the
+/// entry of the written file with its stat information
+pub fn checkout_file(path: &str, mode: EntryMode, hash: &Hash, stage: u8) -> Result<IndexEntry> {
+    let object = Object::read_from_hash(hash.to_hex())?;
+    if object.kind != ObjectKind::Blob {
+        bail!("object {:x} is a {}, not a blob", hash, object.kind);
+    }
+
+    let target = Path::new(path);
+    if let Ok(metadata) = fs::symlink_metadata(target) {
+        match metadata.is_dir() {
+            true => fs::remove_dir_all(target)?,
+            false => fs::remove_file(target)?,
+        }
+    }
+    if let Some(dir) = target.parent() {
+        remove_files_in_the_way(dir)?;
+        fs::create_dir_all(dir)?;
+    }
+
+    match mode {
+        EntryMode::SymbolicLink => {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            symlink(OsStr::from_bytes(&object.data), target)?;
+        }
+        _ => {
+            fs::write(target, &object.data)?;
+            let permissions = match mode {
+                EntryMode::ExecutableFile => 0o755,
+                _ => 0o644,
+            };
+            fs::set_permissions(target, fs::Permissions::from_mode(permissions))?;
+        }
+    }
+
+    let mut entry = IndexEntry::new(path, mode, hash.clone(), stage);
+    entry.refresh(&fs::symlink_metadata(target)?);
+    Ok(entry)
+}
+
+/// removes a file from the working tree, along with the directories it leaves empty
+pub fn remove_file(path: &str) -> Result<()> {
+    let target = Path::new(path);
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(target)?,
+        _ => return Ok(()),
+    }
+
+    let mut dir = target.parent();
+    while let Some(current) = dir.filter(|dir| !dir.as_os_str().is_empty()) {
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+/// the paths whose entries differ between two indexes, with the entries of both
+fn changed_paths<'a>(
+    old: &'a Index,
+    new: &'a Index,
+) -> BTreeMap<&'a str, (Vec<&'a IndexEntry>, Vec<&'a IndexEntry>)> {
+    let mut paths: BTreeMap<&str, (Vec<&IndexEntry>, Vec<&IndexEntry>)> = BTreeMap::new();
+    for entry in &old.entries {
+        paths.entry(&entry.path).or_default().0.push(entry);
+    }
+    for entry in &new.entries {
+        paths.entry(&entry.path).or_default().1.push(entry);
+    }
+
+    let same = |a: &IndexEntry, b: &IndexEntry| {
+        a.stage() == b.stage() && a.mode == b.mode && a.hash == b.hash
+    };
+    paths.retain(|_, (old, new)| {
+        old.len() != new.len() || old.iter().zip(new.iter()).any(|(a, b)| !same(a, b))
+    });
+    paths
+}
+
+/// makes sure updating the working tree from the old index to the new one won't lose
+/// anything: the files that change must match the old index, and untracked files
+/// can't be overwritten. operation names what is being done in the error messages.
+pub fn check_overwrite(old: &Index, new: &Index, operation: &str) -> Result<()> {
+    let index_mtime = index_mtime()?;
+    let mut local_changes = Vec::new();
+    let mut untracked = Vec::new();
+    for (path, (old_entries, new_entries)) in changed_paths(old, new) {
+        match old_entries.as_slice() {
+            [entry]
+                if entry.stage() == 0
+                    && !is_clean(entry, index_mtime)?
+                    && fs::symlink_metadata(path).is_ok() =>
+            {
+                local_changes.push(path)
+            }
+            [] => {
+                let exists = fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir());
+                // a file identical to the one checked out is not lost
+                let identical = match new_entries.as_slice() {
+                    [entry] => is_clean(entry, None)?,
+                    _ => false,
+                };
+                if exists && !identical {
+                    untracked.push(path);
+                }
+            }
+            // clean files and unmerged paths are replaced
+            _ => {}
+        }
+    }
+
+    if !local_changes.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by {}:\n\t{}\nPlease commit your changes or stash them before you {}.\nAborting",
+            operation,
+            local_changes.join("\n\t"),
+            operation
+        );
+    }
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked working tree files would be overwritten by {}:\n\t{}\nPlease move or remove them before you {}.\nAborting",
+            operation,
+            untracked.join("\n\t"),
+            operation
+        );
+    }
+
+    Ok(())
+}
+
+/// updates the working tree from the old index to the new one: files of paths missing
+/// from the new index are removed, and merged entries that changed are checked out.
+/// unchanged entries keep their stat information. unmerged paths are left to the caller.
+pub fn update_worktree(old: &Index, new: &mut Index) -> Result<()> {
+    let changed: Vec<String> = changed_paths(old, new)
+        .into_keys()
+        .map(|path| path.to_string())
+        .collect();
+
+    // removals come first, so that files can replace directories and the other way around
+    for path in &changed {
+        if !new.entries.iter().any(|entry| entry.path == *path) {
+            remove_file(path)?;
+        }
+    }
+
+    for entry in new.entries.iter_mut() {
+        if entry.stage() != 0 {
+            continue;
+        }
+        if changed.binary_search(&entry.path).is_err() {
+            if let Some(old_entry) = old.entry(&entry.path) {
+                let (flags, extended_flags) = (entry.flags, entry.extended_flags);
+                *entry = old_entry.clone();
+                entry.flags = flags;
+                entry.extended_flags = extended_flags;
+            }
+            continue;
+        }
+
+        let mode = EntryMode::try_from(entry.mode)?;
+        let checked_out = checkout_file(&entry.path, mode, &entry.hash, 0)?;
+        entry.refresh(&fs::symlink_metadata(&checked_out.path)?);
+    }
+
+    Ok(())
+}
+
+/// computes the index after moving from one tree to another, given as the indexes of their
+/// files. staged changes to the paths that are the same in both trees are kept, while the
+/// paths that differ must not have any.
+pub fn switch_index(index: &Index, from: &Index, to: &Index, operation: &str) -> Result<Index> {
+    let mut paths: BTreeMap<&str, [Option<&IndexEntry>; 2]> = BTreeMap::new();
+    for entry in &from.entries {
+        paths.entry(&entry.path).or_default()[0] = Some(entry);
+    }
+    for entry in &to.entries {
+        paths.entry(&entry.path).or_default()[1] = Some(entry);
+    }
+
+    let same = |a: Option<&IndexEntry>, b: Option<&IndexEntry>| match (a, b) {
+        (Some(a), Some(b)) => a.mode == b.mode && a.hash == b.hash && b.stage() == 0,
+        (None, None) => true,
+        _ => false,
+    };
+
+    let mut switched = Index {
+        version: index.version,
+        ..Default::default()
+    };
+    let mut staged = Vec::new();
+    for entry in &index.entries {
+        match paths.get(entry.path.as_str()) {
+            Some([from, to]) if !same(*from, *to) => {
+                // the entry is replaced, which is only safe if it isn't changed from the old tree
+                if !same(*from, Some(entry)) && !same(*to, Some(entry)) {
+                    staged.push(entry.path.as_str());
+                }
+            }
+            _ => switched.entries.push(entry.clone()),
+        }
+    }
+    for [from, to] in paths.values() {
+        if let (false, Some(to)) = (same(*from, *to), to) {
+            switched.entries.push((*to).clone());
+        }
+    }
+
+    staged.dedup();
+    if !staged.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by {}:\n\t{}\nPlease commit your changes or stash them before you {}.\nAborting",
+            operation,
+            staged.join("\n\t"),
+            operation
+        );
+    }
+
+    switched
+        .entries
+        .sort_by(|a, b| (a.path.as_bytes(), a.stage()).cmp(&(b.path.as_bytes(), b.stage())));
+    Ok(switched)
+}
+
+/// moves the index and the working tree from one tree to another, keeping local changes
+/// to the paths that are the same in both. a missing tree is treated as empty.
+pub fn switch_trees(
+    index: &Index,
+    from: Option<&Hash>,
+    to: &Hash,
+    operation: &str,
+) -> Result<Index> {
+    let from = match from {
+        Some(tree) => Index::from_tree(tree)?,
+        None => Index::default(),
+    };
+    let mut switched = switch_index(index, &from, &Index::from_tree(to)?, operation)?;
+    check_overwrite(index, &switched, operation)?;
+    update_worktree(index, &mut switched)?;
+    Ok(switched)
+}
+
+/// resets the index and the working tree to a tree, discarding local changes to tracked
+/// files. untracked files are kept unless a file of the tree replaces them.
+pub fn reset_hard(index: &Index, tree: &Hash) -> Result<Index> {
+    let index_mtime = index_mtime()?;
+    let mut reset = Index::from_tree(tree)?;
+    reset.version = index.version;
+
+    for entry in &index.entries {
+        if reset.entry(&entry.path).is_none() {
+            remove_file(&entry.path)?;
+        }
+    }
+    for entry in reset.entries.iter_mut() {
+        let unchanged = index
+            .entry(&entry.path)
+            .is_some_and(|old| old.mode == entry.mode && old.hash == entry.hash);
+        if unchanged {
+            let old = index.entry(&entry.path).expect("the entry exists");
+            if is_clean(old, index_mtime)? {
+                let (flags, extended_flags) = (entry.flags, entry.extended_flags);
+                *entry = old.clone();
+                entry.flags = flags;
+                entry.extended_flags = extended_flags;
+                continue;
+            }
+        }
+
+        let mode = EntryMode::try_from(entry.mode)?;
+        *entry = checkout_file(&entry.path, mode, &entry.hash, 0)?;
+    }
+
+    Ok(reset)
+}
+
+/// the paths whose index entries differ from the ones of a tree, unmerged paths included
+pub fn staged_paths<'a>(index: &'a Index, tree_index: &'a Index) -> Vec<&'a str> {
+    let mut paths: Vec<&str> = Vec::new();
+    for entry in &index.entries {
+        let unchanged = entry.stage() == 0
+            && tree_index.entry(&entry.path).is_some_and(|tree_entry| {
+                tree_entry.mode == entry.mode && tree_entry.hash == entry.hash
+            });
+        if !unchanged && paths.last() != Some(&entry.path.as_str()) {
+            paths.push(&entry.path);
+        }
+    }
+    for entry in &tree_index.entries {
+        if !index
+            .entries
+            .iter()
+            .any(|indexed| indexed.path == entry.path)
+        {
+            paths.push(&entry.path);
+        }
+    }
+    paths.sort();
+    paths
+}
+
+/// the tracked paths whose working tree files differ from the index
+pub fn unstaged_paths(index: &Index) -> Result<Vec<&str>> {
+    let index_mtime = index_mtime()?;
+    let mut paths = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        if !entry.intent_to_add() && !is_clean(entry, index_mtime)? {
+            paths.push(entry.path.as_str());
+        }
+    }
+    Ok(paths)
+}
+
+/// the files of the working tree that aren't in the index nor ignored, sorted
+pub fn untracked_paths(index: &Index) -> Result<Vec<String>> {
+    let tracked: HashSet<&str> = index
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    let mut untracked = Vec::new();
+    collect_untracked("", &IgnoreRules::new()?, &tracked, &mut untracked)?;
+    untracked.sort();
+    Ok(untracked)
+}
+
+/// walks a directory, given with a trailing slash or empty for the root, reading the
+/// .gitignore files of its subdirectories on the way
+fn collect_untracked(
+    dir: &str,
+    rules: &IgnoreRules,
+    tracked: &HashSet<&str>,
+    untracked: &mut Vec<String>,
+) -> Result<()> {
+    let root = match dir {
+        "" => Path::new("."),
+        dir => Path::new(dir),
+    };
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+
+        let path = format!("{}{}", dir, name);
+        if entry.file_type()?.is_dir() {
+            if rules.is_ignored(&path, true) {
+                continue;
+            }
+            let dir = format!("{}/", path);
+            let mut rules = rules.clone();
+            rules.add_directory(&dir)?;
+            collect_untracked(&dir, &rules, tracked, untracked)?;
+        } else if !tracked.contains(path.as_str()) && !rules.is_ignored(&path, false) {
+            untracked.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// removes the files occupying the place of a directory to create, e.g. a file "a"
+/// when checking out "a/b"
+fn remove_files_in_the_way(dir: &Path) -> Result<()> {
+    for ancestor in dir.ancestors() {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        if fs::symlink_metadata(ancestor).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(ancestor)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    diff::{
+        diff_files, list_tree_files, ChangeStatus, ContentSource, DiffFile, TreeDiffOptions,
+        DEFAULT_RENAME_SCORE,
+    },
+    index::{index_mtime, read_index, Index},
+    objects::{hash::Hash, tree::EntryMode, ObjectKind},
+    refs::{expand_ref, head_branch, reflog::read_reflog, resolve_ref},
+    rev_parse::{abbreviate, peel},
+};
+
+use super::{read_worktree_file, untracked_paths};
+
+const MERGE_STATE: [&str; 3] = [
+    ".git/MERGE_HEAD",
+    ".git/CHERRY_PICK_HEAD",
+    ".git/REVERT_HEAD",
+];
+
+/// prints the state of the index and the working tree like git status: the staged
+/// changes, the unmerged paths, the unstaged changes and the untracked files
+pub fn write_status<W: Write>(out: &mut W) -> Result<()> {
+    let index = read_index()?;
+    let head = resolve_ref("HEAD")?;
+    match head_branch()? {
+        Some(branch) => writeln!(
+            out,
+            "On branch {}",
+            branch.trim_start_matches("refs/heads/")
+        )?,
+        None => match &head {
+            Some(head) => writeln!(out, "{}", detached_head(head)?)?,
+            None => writeln!(out, "Not currently on any branch.")?,
+        },
+    }
+    if head.is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+
+    let head_tree = head
+        .map(|head| peel(head, Some(ObjectKind::Tree)))
+        .transpose()?;
+    // the staged changes of a merge can't be unstaged one by one
+    let merging = MERGE_STATE.iter().any(|file| Path::new(file).is_file());
+    let staged = staged_changes(&index, head_tree.as_ref())?;
+    if !staged.is_empty() {
+        writeln!(out, "Changes to be committed:")?;
+        match head_tree {
+            _ if merging => {}
+            Some(_) => writeln!(
+                out,
+                "  (use \"mgit restore --staged <file>...\" to unstage)"
+            )?,
+            None => writeln!(out, "  (use \"git rm --cached <file>...\" to unstage)")?,
+        }
+        write_changes(out, &staged, 12)?;
+    }
+
+    let unmerged = unmerged_paths(&index);
+    if !unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        if !merging {
+            writeln!(
+                out,
+                "  (use \"mgit restore --staged <file>...\" to unstage)"
+            )?;
+        }
+        match unmerged.iter().any(|(label, _)| label.contains("deleted")) {
+            true => writeln!(
+                out,
+                "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+            )?,
+            false => writeln!(out, "  (use \"git add <file>...\" to mark resolution)")?,
+        }
+        write_changes(out, &unmerged, 17)?;
+    }
+
+    let unstaged = unstaged_changes(&index)?;
+    if !unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        match unstaged.iter().any(|(label, _)| *label == "deleted:") {
+            true => writeln!(
+                out,
+                "  (use \"git add/rm <file>...\" to update what will be committed)"
+            )?,
+            false => writeln!(
+                out,
+                "  (use \"git add <file>...\" to update what will be committed)"
+            )?,
+        }
+        writeln!(
+            out,
+            "  (use \"mgit restore <file>...\" to discard changes in working directory)"
+        )?;
+        write_changes(out, &unstaged, 12)?;
+    }
+
+    let untracked = collapse_untracked(&index, untracked_paths(&index)?);
+    if !untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        writeln!(
+            out,
+            "  (use \"git add <file>...\" to include in what will be committed)"
+        )?;
+        for path in &untracked {
+            writeln!(out, "\t{}", path)?;
+        }
+        writeln!(out)?;
+    }
+
+    // unmerged paths have changes in the working tree, but none staged
+    if staged.is_empty() {
+        if !unstaged.is_empty() || !unmerged.is_empty() {
+            writeln!(
+                out,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            )?;
+        } else if !untracked.is_empty() {
+            writeln!(
+                out,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?;
+        } else {
+            writeln!(out, "nothing to commit, working tree clean")?;
+        }
+    }
+    Ok(())
+}
+
+/// describes a detached HEAD from the last checkout recorded in its reflog: "HEAD detached
+/// at <name>" if HEAD is still where it was checked out, "HEAD detached from <name>" otherwise
+pub fn detached_head(head: &Hash) -> Result<String> {
+    let checkout = read_reflog("HEAD")?.into_iter().rev().find_map(|entry| {
+        let target = entry.message.strip_prefix("checkout: moving from ")?;
+        Some((target.rsplit_once(" to ")?.1.to_string(), entry.new))
+    });
+    let (target, checked_out) = match checkout {
+        Some(checkout) => checkout,
+        None => return Ok(format!("HEAD detached at {}", abbreviate(head)?)),
+    };
+
+    // a ref still pointing where it was checked out is shown by name
+    let mut name = abbreviate(&checked_out)?;
+    if let Some(full_name) = expand_ref(&target)? {
+        let hash = resolve_ref(&full_name)?;
+        let peeled = hash
+            .clone()
+            .map(|hash| peel(hash, Some(ObjectKind::Commit)))
+            .transpose()
+            .ok()
+            .flatten();
+        if hash.as_ref() == Some(&checked_out) || peeled.as_ref() == Some(&checked_out) {
+            name = full_name
+                .strip_prefix("refs/tags/")
+                .or_else(|| full_name.strip_prefix("refs/remotes/"))
+                .unwrap_or(&full_name)
+                .to_string();
+        }
+    }
+
+    match checked_out == *head {
+        true => Ok(format!("HEAD detached at {}", name)),
+        false => Ok(format!("HEAD detached from {}", name)),
+    }
+}
+
+/// prints a section's paths with their labels padded to a width, then a blank line
+fn write_changes<W: Write>(out: &mut W, changes: &[(&str, String)], width: usize) -> Result<()> {
+    for (label, path) in changes {
+        writeln!(out, "\t{:width$}{}", label, path, width = width)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// the merged entries of the index that differ from the tree of HEAD, with renames
+fn staged_changes(index: &Index, head_tree: Option<&Hash>) -> Result<Vec<(&'static str, String)>> {
+    let options = TreeDiffOptions {
+        recursive: true,
+        find_renames: true,
+        rename_score: DEFAULT_RENAME_SCORE,
+        ..Default::default()
+    };
+    let unmerged: HashSet<&str> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() != 0)
+        .map(|entry| entry.path.as_str())
+        .collect();
+    let old_files = list_tree_files(head_tree, &options)?
+        .into_iter()
+        .filter(|file| !unmerged.contains(file.path.as_str()))
+        .collect();
+    let mut new_files = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        new_files.push(DiffFile {
+            path: entry.path.clone(),
+            mode: EntryMode::try_from(entry.mode)?,
+            hash: entry.hash.clone(),
+        });
+    }
+
+    let mut changes = Vec::new();
+    for change in diff_files(old_files, new_files, &options, ContentSource::Objects)? {
+        let label = match change.status {
+            ChangeStatus::Added => "new file:",
+            ChangeStatus::Deleted => "deleted:",
+            ChangeStatus::Modified => "modified:",
+            ChangeStatus::TypeChanged => "typechange:",
+            ChangeStatus::Renamed => "renamed:",
+            ChangeStatus::Copied => "copied:",
+        };
+        let path = match (&change.status, &change.old, &change.new) {
+            (ChangeStatus::Renamed | ChangeStatus::Copied, Some(old), Some(new)) => {
+                format!("{} -> {}", old.path, new.path)
+            }
+            _ => change.path().to_string(),
+        };
+        changes.push((label, path));
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(changes)
+}
+
+/// the paths with conflicts, described by the stages they have
+fn unmerged_paths(index: &Index) -> Vec<(&'static str, String)> {
+    let mut stages: BTreeMap<&str, [bool; 3]> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() != 0) {
+        stages.entry(&entry.path).or_default()[entry.stage() as usize - 1] = true;
+    }
+
+    stages
+        .into_iter()
+        .map(|(path, stages)| {
+            let label = match stages {
+                [true, true, true] => "both modified:",
+                [false, true, true] => "both added:",
+                [true, true, false] => "deleted by them:",
+                [true, false, true] => "deleted by us:",
+                [false, true, false] => "added by us:",
+                [false, false, true] => "added by them:",
+                _ => "both deleted:",
+            };
+            (label, path.to_string())
+        })
+        .collect()
+}
+
+/// the merged entries whose working tree files are modified or deleted
+fn unstaged_changes(index: &Index) -> Result<Vec<(&'static str, String)>> {
+    let index_mtime = index_mtime()?;
+    let mut changes = Vec::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() == 0) {
+        let label = match read_worktree_file(entry, index_mtime)? {
+            None => "deleted:",
+            Some(file)
+                if (file.mode == EntryMode::SymbolicLink)
+                    != (entry.mode == EntryMode::SymbolicLink as u32) =>
+            {
+                "typechange:"
+            }
+            Some(file) if file.hash != entry.hash || file.mode as u32 != entry.mode => "modified:",
+            Some(_) => continue,
+        };
+        changes.push((label, entry.path.clone()));
+    }
+    Ok(changes)
+}
+
+/// shows the directories without tracked files as a whole, e.g. "dir/" for "dir/a/b"
+fn collapse_untracked(index: &Index, untracked: Vec<String>) -> Vec<String> {
+    let mut tracked_dirs = HashSet::new();
+    for entry in &index.entries {
+        let mut end = 0;
+        while let Some(slash) = entry.path[end..].find('/') {
+            end += slash + 1;
+            tracked_dirs.insert(&entry.path[..end]);
+        }
+    }
+
+    let mut collapsed: Vec<String> = Vec::new();
+    for path in untracked {
+        let mut shown = path.as_str();
+        let mut end = 0;
+        while let Some(slash) = path[end..].find('/') {
+            end += slash + 1;
+            if !tracked_dirs.contains(&path[..end]) {
+                shown = &path[..end];
+                break;
+            }
+        }
+        if collapsed.last().map(|last| last.as_str()) != Some(shown) {
+            collapsed.push(shown.to_string());
+        }
+    }
+    collapsed
+}